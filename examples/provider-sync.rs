@@ -9,8 +9,6 @@ use kitchen_fridge::Item;
 use kitchen_fridge::Task;
 use kitchen_fridge::task::CompletionStatus;
 use kitchen_fridge::CalDavProvider;
-use kitchen_fridge::traits::BaseCalendar;
-use kitchen_fridge::traits::CompleteCalendar;
 use kitchen_fridge::utils::pause;
 
 mod shared;
@@ -56,8 +54,9 @@ async fn add_items_and_sync_again(provider: &mut CalDavProvider) {
     // ...and add a task in it
     let new_name = "This is a new task in a new calendar";
     let new_task = Task::new(String::from(new_name), true, &new_calendar_url);
-    provider.local().get_calendar(&new_calendar_url).await.unwrap()
-        .lock().unwrap().add_item(Item::Task(new_task)).await.unwrap();
+    provider.local()
+        .with_calendar_mut(&new_calendar_url, |cal| cal.add_item_sync(Item::Task(new_task)))
+        .await.unwrap().unwrap();
 
 
     // Also create a task in a previously existing calendar
@@ -65,8 +64,9 @@ async fn add_items_and_sync_again(provider: &mut CalDavProvider) {
     let new_task_name = "This is a new task we're adding as an example, with ÜTF-8 characters";
     let new_task = Task::new(String::from(new_task_name), false, &changed_calendar_url);
     let new_url = new_task.url().clone();
-    provider.local().get_calendar(&changed_calendar_url).await.unwrap()
-        .lock().unwrap().add_item(Item::Task(new_task)).await.unwrap();
+    provider.local()
+        .with_calendar_mut(&changed_calendar_url, |cal| cal.add_item_sync(Item::Task(new_task)))
+        .await.unwrap().unwrap();
 
 
     if provider.sync().await == false {
@@ -88,10 +88,13 @@ async fn complete_item_and_sync_again(
     pause();
 
     let completion_status = CompletionStatus::Completed(Some(Utc::now()));
-    provider.local().get_calendar(changed_calendar_url).await.unwrap()
-        .lock().unwrap().get_item_by_url_mut(url_to_complete).await.unwrap()
-        .unwrap_task_mut()
-        .set_completion_status(completion_status);
+    provider.local()
+        .with_calendar_mut(changed_calendar_url, |cal| {
+            cal.get_item_by_url_mut_sync(url_to_complete).unwrap()
+                .unwrap_task_mut()
+                .set_completion_status(completion_status)
+        })
+        .await.unwrap();
 
     if provider.sync().await == false {
         log::warn!("Sync did not complete, see the previous log lines for more info. You can safely start a new sync. The new task may not have been synced.");
@@ -112,9 +115,9 @@ async fn remove_items_and_sync_again(
     pause();
 
     // Remove the task we had created
-    provider.local().get_calendar(changed_calendar_url).await.unwrap()
-        .lock().unwrap()
-        .mark_for_deletion(id_to_remove).await.unwrap();
+    provider.local()
+        .with_calendar_mut(changed_calendar_url, |cal| cal.mark_for_deletion_sync(id_to_remove))
+        .await.unwrap().unwrap();
 
     if provider.sync().await == false {
         log::warn!("Sync did not complete, see the previous log lines for more info. You can safely start a new sync. The new task may not have been synced.");
@@ -4,6 +4,7 @@ use kitchen_fridge::client::Client;
 use kitchen_fridge::traits::CalDavSource;
 use kitchen_fridge::CalDavProvider;
 use kitchen_fridge::cache::Cache;
+use kitchen_fridge::config::Config;
 
 
 // TODO: change these values with yours
@@ -14,24 +15,18 @@ pub const PASSWORD: &str = "secret_password";
 pub const EXAMPLE_EXISTING_CALENDAR_URL: &str = "https://my.server.com/remote.php/dav/calendars/john/a_calendar_name/";
 pub const EXAMPLE_CREATED_CALENDAR_URL: &str =  "https://my.server.com/remote.php/dav/calendars/john/a_calendar_that_we_have_created/";
 
-fn main() {
-    panic!("This file is not supposed to be executed");
-}
-
 
 /// Initializes a Provider, and run an initial sync from the server
 pub async fn initial_sync(cache_folder: &str) -> CalDavProvider {
     let cache_path = Path::new(cache_folder);
 
-    let client = Client::new(URL, USERNAME, PASSWORD).unwrap();
-    let cache = match Cache::from_folder(&cache_path) {
-        Ok(cache) => cache,
+    let mut provider = match CalDavProvider::connect(URL, USERNAME, PASSWORD, cache_path, Config::default()) {
+        Ok(provider) => provider,
         Err(err) => {
             log::warn!("Invalid cache file: {}. Using a default cache", err);
-            Cache::new(&cache_path)
+            CalDavProvider::new(Client::new(URL, USERNAME, PASSWORD).unwrap(), Cache::new(cache_path))
         }
     };
-    let mut provider = CalDavProvider::new(client, cache);
 
 
     let cals = provider.local().get_calendars().await.unwrap();
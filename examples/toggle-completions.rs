@@ -38,7 +38,7 @@ async fn toggle_all_tasks_and_sync_again(provider: &mut CalDavProvider) -> Resul
     let mut n_toggled = 0;
 
     for (_url, cal) in provider.local().get_calendars_sync()?.iter() {
-        for (_url, item) in cal.lock().unwrap().get_items_mut_sync()?.iter_mut() {
+        for (_url, item) in cal.write().unwrap().get_items_mut_sync()?.iter_mut() {
             match item {
                 Item::Task(task) => {
                     match task.completed() {
@@ -0,0 +1,304 @@
+//! Alarms of tasks (iCal `VALARM` components, see [RFC 5545](https://datatracker.ietf.org/doc/html/rfc5545#section-3.6.6)), and the times they fire at
+//!
+//! This can be used to build notification daemons on top of a [`Cache`](crate::cache::Cache): [`reminders`] tells every upcoming reminder of a set of items.
+//!
+//! ```
+//! use chrono::{TimeZone, Utc};
+//! use kitchen_fridge::alarm::reminders;
+//! use kitchen_fridge::ical::parse;
+//! use kitchen_fridge::item::SyncStatus;
+//!
+//! let ical = "BEGIN:VCALENDAR\r\n\
+//!     VERSION:2.0\r\n\
+//!     PRODID:-//Example Corp.//CalDAV Client//EN\r\n\
+//!     BEGIN:VTODO\r\n\
+//!     UID:pay-the-rent@example.com\r\n\
+//!     DTSTAMP:20210321T001600Z\r\n\
+//!     SUMMARY:Pay the rent\r\n\
+//!     DUE:20210401T090000Z\r\n\
+//!     BEGIN:VALARM\r\n\
+//!     ACTION:DISPLAY\r\n\
+//!     DESCRIPTION:The rent is due in one day\r\n\
+//!     TRIGGER;RELATED=END:-P1D\r\n\
+//!     END:VALARM\r\n\
+//!     END:VTODO\r\n\
+//!     END:VCALENDAR\r\n";
+//! let item = parse(ical, "https://my.server.com/rent.ics".parse().unwrap(), SyncStatus::NotSynced).unwrap();
+//!
+//! let upcoming = reminders(vec![&item], &Utc.ymd(2021, 3, 21).and_hms(0, 0, 0));
+//! assert_eq!(upcoming.len(), 1);
+//! assert_eq!(upcoming[0].time(), &Utc.ymd(2021, 3, 31).and_hms(9, 0, 0));
+//! assert_eq!(upcoming[0].alarm().description(), Some("The rent is due in one day"));
+//! ```
+
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+use ical::property::Property;
+use serde::{Deserialize, Serialize};
+
+use crate::Item;
+use crate::ical::param;
+use crate::Task;
+use crate::item::SyncStatus;
+
+/// The date an alarm is triggered at (the `TRIGGER` property)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trigger {
+    /// At a given date
+    Absolute(DateTime<Utc>),
+    /// At a given offset (usually negative) before the start or the end of the item
+    Relative(Duration, TriggerRelation),
+}
+
+/// What a [`Trigger::Relative`] is relative to (the `RELATED` parameter)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TriggerRelation {
+    /// The start of the item (its `DTSTART`). This is the default
+    Start,
+    /// The end of the item. For tasks, this is their due date (their `DUE`)
+    End,
+}
+
+/// An alarm of a task (an iCal `VALARM` component)
+///
+/// Its properties are kept as-is, so that it can be written back to an equivalent iCal file.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Alarm {
+    properties: Vec<Property>,
+}
+
+impl Alarm {
+    pub fn new(properties: Vec<Property>) -> Self {
+        Self { properties }
+    }
+
+    /// The properties of this alarm, as they have been parsed from the iCal file
+    pub fn properties(&self) -> &[Property] {
+        &self.properties
+    }
+
+    /// What this alarm should do (e.g. `DISPLAY`, `AUDIO` or `EMAIL`)
+    pub fn action(&self) -> Option<&str> {
+        self.property("ACTION").and_then(|prop| prop.value.as_deref())
+    }
+
+    /// The text to display, in case this alarm is a `DISPLAY` or an `EMAIL` one
+    pub fn description(&self) -> Option<&str> {
+        self.property("DESCRIPTION").and_then(|prop| prop.value.as_deref())
+    }
+
+    /// When this alarm is triggered. `None` in case it is missing or invalid
+    pub fn trigger(&self) -> Option<Trigger> {
+        let prop = self.property("TRIGGER")?;
+        let value = prop.value.as_deref()?.trim();
+
+        if param(prop, "VALUE") == Some("DATE-TIME") {
+            return crate::ical::parse_date_time(value).ok().map(Trigger::Absolute);
+        }
+        let offset = crate::ical::parse_signed_duration(value).ok()?;
+        let related = match param(prop, "RELATED") {
+            Some("END") => TriggerRelation::End,
+            _ => TriggerRelation::Start,
+        };
+        Some(Trigger::Relative(offset, related))
+    }
+
+    /// How many more times this alarm is triggered after the first time (the `REPEAT` property)
+    pub fn repeat(&self) -> u32 {
+        self.property("REPEAT")
+            .and_then(|prop| prop.value.as_deref())
+            .and_then(|value| value.trim().parse().ok())
+            .unwrap_or(0)
+    }
+
+    /// How long to wait between repetitions (the `DURATION` property)
+    pub fn repeat_interval(&self) -> Option<Duration> {
+        self.property("DURATION")
+            .and_then(|prop| prop.value.as_deref())
+            .and_then(|value| crate::ical::parse_signed_duration(value.trim()).ok())
+    }
+
+    /// Every date this alarm is triggered at, for a given task (including the repetitions), in chronological order.
+    ///
+    /// This is empty in case the trigger is relative to a date the task does not have. \
+    /// Floating dates of the task are considered in UTC, see [`Alarm::trigger_times_in`] to resolve them in the time zone of the user.
+    pub fn trigger_times(&self, task: &Task) -> Vec<DateTime<Utc>> {
+        self.trigger_times_in(task, &Tz::UTC)
+    }
+
+    /// Every date this alarm is triggered at, for a given task and a user in a given time zone (see [`Alarm::trigger_times`])
+    pub fn trigger_times_in(&self, task: &Task, local_zone: &Tz) -> Vec<DateTime<Utc>> {
+        let first = match self.trigger() {
+            None => return Vec::new(),
+            Some(Trigger::Absolute(date)) => date,
+            Some(Trigger::Relative(offset, relation)) => {
+                let reference = match relation {
+                    TriggerRelation::Start => task.start_in(local_zone),
+                    TriggerRelation::End => task.due_in(local_zone),
+                };
+                match reference {
+                    None => return Vec::new(),
+                    Some(reference) => reference + offset,
+                }
+            },
+        };
+
+        // RFC 5545 requires both REPEAT and DURATION, or none of them
+        match (self.repeat(), self.repeat_interval()) {
+            (repeat, Some(interval)) if repeat > 0 && interval > Duration::zero() => {
+                (0..=repeat as i32).map(|n| first + interval * n).collect()
+            },
+            _ => vec![first],
+        }
+    }
+
+    fn property(&self, name: &str) -> Option<&Property> {
+        self.properties.iter().find(|prop| prop.name == name)
+    }
+}
+
+
+/// A date an alarm of an item is triggered at
+#[derive(Clone, Copy, Debug)]
+pub struct Reminder<'a> {
+    time: DateTime<Utc>,
+    item: &'a Item,
+    alarm: &'a Alarm,
+}
+
+impl<'a> Reminder<'a> {
+    pub fn time(&self) -> &DateTime<Utc> { &self.time }
+    pub fn item(&self) -> &'a Item       { self.item }
+    pub fn alarm(&self) -> &'a Alarm     { self.alarm }
+}
+
+/// Every reminder of `items` that is due at `now` or later, in chronological order.
+///
+/// Completed tasks and locally deleted items have no reminders. \
+/// Only tasks are supported yet, events are skipped. \
+/// Floating dates are considered in UTC, see [`reminders_in`] to resolve them in the time zone of the user.
+pub fn reminders<'a, I>(items: I, now: &DateTime<Utc>) -> Vec<Reminder<'a>>
+where
+    I: IntoIterator<Item = &'a Item>,
+{
+    reminders_in(items, now, &Tz::UTC)
+}
+
+/// Every reminder of `items` that is due at `now` or later, for a user in a given time zone (see [`reminders`])
+pub fn reminders_in<'a, I>(items: I, now: &DateTime<Utc>, local_zone: &Tz) -> Vec<Reminder<'a>>
+where
+    I: IntoIterator<Item = &'a Item>,
+{
+    let mut reminders = Vec::new();
+    for item in items {
+        let task = match item {
+            Item::Task(task) => task,
+            Item::Event(_) => continue,
+        };
+        if task.completed() || matches!(task.sync_status(), SyncStatus::LocallyDeleted(_)) {
+            continue;
+        }
+
+        for alarm in task.alarms() {
+            reminders.extend(alarm.trigger_times_in(task, local_zone)
+                .into_iter()
+                .filter(|time| time >= now)
+                .map(|time| Reminder { time, item, alarm }));
+        }
+    }
+    reminders.sort_by_key(|reminder| reminder.time);
+    reminders
+}
+
+/// The first reminder of `items` that is due at `now` or later, if any (see [`reminders`])
+pub fn next_reminder<'a, I>(items: I, now: &DateTime<Utc>) -> Option<Reminder<'a>>
+where
+    I: IntoIterator<Item = &'a Item>,
+{
+    reminders(items, now).into_iter().next()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+    use url::Url;
+
+    use crate::ical::parse;
+
+    fn task(properties: &str, alarms: &[&str]) -> Item {
+        let alarms: String = alarms.iter()
+            .map(|alarm| format!("BEGIN:VALARM\r\n{}END:VALARM\r\n", alarm))
+            .collect();
+        let ical = format!("BEGIN:VCALENDAR\r\n\
+            VERSION:2.0\r\n\
+            PRODID:-//Example Corp.//CalDAV Client//EN\r\n\
+            BEGIN:VTODO\r\n\
+            UID:some-uid\r\n\
+            DTSTAMP:20210321T001600Z\r\n\
+            SUMMARY:Some task\r\n\
+            {}{}END:VTODO\r\n\
+            END:VCALENDAR\r\n", properties, alarms);
+        let url = Url::parse("https://some.calendar/some-task.ics").unwrap();
+        parse(&ical, url, SyncStatus::NotSynced).unwrap()
+    }
+
+    #[test]
+    fn test_triggers() {
+        let item = task("DTSTART:20210322T090000Z\r\nDUE:20210325T180000Z\r\n", &[
+            "ACTION:DISPLAY\r\nTRIGGER:-PT15M\r\n",
+            "ACTION:DISPLAY\r\nTRIGGER;RELATED=END:PT0S\r\n",
+            "ACTION:AUDIO\r\nTRIGGER;VALUE=DATE-TIME:20210323T120000Z\r\nREPEAT:2\r\nDURATION:PT30M\r\n",
+            "ACTION:AUDIO\r\nTRIGGER:not a duration\r\n",
+        ]);
+        let task = item.unwrap_task();
+        let alarms = task.alarms();
+        assert_eq!(alarms.len(), 4);
+
+        assert_eq!(alarms[0].trigger(), Some(Trigger::Relative(Duration::minutes(-15), TriggerRelation::Start)));
+        assert_eq!(alarms[0].trigger_times(task), vec![Utc.ymd(2021, 3, 22).and_hms(8, 45, 0)]);
+        assert_eq!(alarms[1].trigger_times(task), vec![Utc.ymd(2021, 3, 25).and_hms(18, 0, 0)]);
+        assert_eq!(alarms[2].action(), Some("AUDIO"));
+        assert_eq!(alarms[2].trigger_times(task), vec![
+            Utc.ymd(2021, 3, 23).and_hms(12, 0, 0),
+            Utc.ymd(2021, 3, 23).and_hms(12, 30, 0),
+            Utc.ymd(2021, 3, 23).and_hms(13, 0, 0),
+        ]);
+        assert_eq!(alarms[3].trigger(), None);
+        assert!(alarms[3].trigger_times(task).is_empty());
+
+        let upcoming = reminders(vec![&item], &Utc.ymd(2021, 3, 23).and_hms(12, 30, 0));
+        let times: Vec<_> = upcoming.iter().map(|reminder| *reminder.time()).collect();
+        assert_eq!(times, vec![
+            Utc.ymd(2021, 3, 23).and_hms(12, 30, 0),
+            Utc.ymd(2021, 3, 23).and_hms(13, 0, 0),
+            Utc.ymd(2021, 3, 25).and_hms(18, 0, 0),
+        ]);
+        assert_eq!(next_reminder(vec![&item], &Utc.ymd(2021, 3, 26).and_hms(0, 0, 0)).map(|reminder| *reminder.time()), None);
+    }
+
+    #[test]
+    fn test_no_reminders() {
+        // A trigger relative to a date the task does not have
+        let without_start = task("DUE:20210325T180000Z\r\n", &["ACTION:DISPLAY\r\nTRIGGER:-PT15M\r\n"]);
+        // A completed task
+        let completed = task("DUE:20210325T180000Z\r\nSTATUS:COMPLETED\r\n", &["ACTION:DISPLAY\r\nTRIGGER;RELATED=END:-PT15M\r\n"]);
+
+        let now = Utc.ymd(2021, 3, 21).and_hms(0, 0, 0);
+        assert!(reminders(vec![&without_start, &completed], &now).is_empty());
+    }
+
+    #[test]
+    fn test_floating_reminders() {
+        // 9:00 wherever the user is
+        let item = task("DUE:20210325T090000\r\n", &["ACTION:DISPLAY\r\nTRIGGER;RELATED=END:-PT15M\r\n"]);
+        let now = Utc.ymd(2021, 3, 21).and_hms(0, 0, 0);
+
+        let times = |zone: &str| reminders_in(vec![&item], &now, &zone.parse().unwrap()).iter().map(|reminder| *reminder.time()).collect::<Vec<_>>();
+        assert_eq!(times("UTC"), vec![Utc.ymd(2021, 3, 25).and_hms(8, 45, 0)]);
+        assert_eq!(times("Europe/Paris"), vec![Utc.ymd(2021, 3, 25).and_hms(7, 45, 0)]);
+        assert_eq!(times("America/Los_Angeles"), vec![Utc.ymd(2021, 3, 25).and_hms(15, 45, 0)]);
+    }
+}
@@ -0,0 +1,285 @@
+//! [proptest](https://docs.rs/proptest) generators for the data model, for property-based tests
+//!
+//! This module is only available with the `arbitrary` Cargo feature. \
+//! It implements [`Arbitrary`] for [`Task`], [`Item`], [`SyncStatus`], [`Alarm`] (and the types they are made of), so that apps can write tests over any possible task,
+//! and provides [`ical_input`], that generates iCal files to fuzz the [parser](crate::ical::parse).
+//!
+//! ```
+//! use proptest::prelude::*;
+//! use kitchen_fridge::Task;
+//! use kitchen_fridge::ical::{build_from, parse};
+//! use kitchen_fridge::item::Item;
+//!
+//! proptest!(|(task in any::<Task>())| {
+//!     let ical = build_from(&Item::Task(task.clone())).unwrap();
+//!     let parsed = parse(&ical, task.url().clone(), task.sync_status().clone()).unwrap();
+//!     prop_assert_eq!(parsed.name(), task.name());
+//! });
+//! ```
+#![cfg(feature = "arbitrary")]
+
+use chrono::{DateTime, TimeZone, Utc};
+use ical::property::Property;
+use proptest::prelude::*;
+use proptest::sample::Index;
+use url::Url;
+
+use crate::Task;
+use crate::alarm::Alarm;
+use crate::item::{Item, SyncStatus, VersionTag};
+use crate::task::CompletionStatus;
+
+impl Arbitrary for VersionTag {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_args: ()) -> Self::Strategy {
+        "[a-zA-Z0-9-]{1,32}".prop_map(VersionTag::from).boxed()
+    }
+}
+
+impl Arbitrary for SyncStatus {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_args: ()) -> Self::Strategy {
+        prop_oneof![
+            Just(SyncStatus::NotSynced),
+            any::<VersionTag>().prop_map(SyncStatus::Synced),
+            any::<VersionTag>().prop_map(SyncStatus::LocallyModified),
+            any::<VersionTag>().prop_map(SyncStatus::LocallyDeleted),
+        ].boxed()
+    }
+}
+
+impl Arbitrary for CompletionStatus {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_args: ()) -> Self::Strategy {
+        prop_oneof![
+            Just(CompletionStatus::Uncompleted),
+            proptest::option::of(date_time()).prop_map(CompletionStatus::Completed),
+        ].boxed()
+    }
+}
+
+impl Arbitrary for Task {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_args: ()) -> Self::Strategy {
+        (
+            name(),
+            "[a-zA-Z0-9@._-]{1,40}",
+            "[a-z0-9-]{1,20}",
+            any::<CompletionStatus>(),
+            any::<SyncStatus>(),
+            proptest::option::of(date_time()),
+            date_time(),
+            proptest::option::of(date_time()),
+            proptest::option::of(0u32..1000),
+            prod_id(),
+            (extra_parameters(), proptest::option::of(date_time())),
+            proptest::collection::vec(any::<Alarm>(), 0..3),
+        ).prop_map(|(name, uid, file_name, completion_status, sync_status, creation_date, last_modified, dtstamp, sequence, prod_id, (mut extra_parameters, due), alarms)| {
+            let url = Url::parse(&format!("https://caldav.example.com/calendars/arbitrary/{}.ics", file_name)).unwrap();
+            if let Some(due) = due {
+                extra_parameters.push(property("DUE", None, due.format("%Y%m%dT%H%M%SZ").to_string()));
+            }
+            Task::new_with_parameters(name, uid, url, completion_status, sync_status, creation_date, last_modified, prod_id, extra_parameters)
+                .with_dtstamp(dtstamp.unwrap_or(last_modified))
+                .with_sequence(sequence)
+                .with_alarms(alarms)
+        }).boxed()
+    }
+}
+
+impl Arbitrary for Alarm {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    /// Alarms that are triggered either at a given date, or some time before the start or the due date of their task
+    fn arbitrary_with(_args: ()) -> Self::Strategy {
+        let trigger = prop_oneof![
+            (0u32..10_000, any::<bool>()).prop_map(|(minutes, related_to_end)| {
+                let params = match related_to_end {
+                    true => Some(vec![(String::from("RELATED"), vec![String::from("END")])]),
+                    false => None,
+                };
+                property("TRIGGER", params, format!("-PT{}M", minutes))
+            }),
+            date_time().prop_map(|date| {
+                let params = Some(vec![(String::from("VALUE"), vec![String::from("DATE-TIME")])]);
+                property("TRIGGER", params, date.format("%Y%m%dT%H%M%SZ").to_string())
+            }),
+        ];
+        (
+            prop_oneof![Just("DISPLAY"), Just("AUDIO")],
+            trigger,
+            proptest::option::of("[a-zA-Z0-9]([a-zA-Z0-9 ]{0,30}[a-zA-Z0-9])?"),
+        ).prop_map(|(action, trigger, description)| {
+            let mut properties = vec![property("ACTION", None, action.to_string()), trigger];
+            if let Some(description) = description {
+                properties.push(property("DESCRIPTION", None, description));
+            }
+            Alarm::new(properties)
+        }).boxed()
+    }
+}
+
+impl Arbitrary for Item {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    /// Only tasks are generated, since events are not supported yet
+    fn arbitrary_with(_args: ()) -> Self::Strategy {
+        any::<Task>().prop_map(Item::Task).boxed()
+    }
+}
+
+/// A date between 1970 and 2100, with a precision of one second (that is the precision of iCal dates)
+pub fn date_time() -> impl Strategy<Value = DateTime<Utc>> {
+    (0i64..4_102_444_800).prop_map(|timestamp| Utc.timestamp_opt(timestamp, 0).unwrap())
+}
+
+/// A task name, that may contain iCal special characters (e.g. `,`, `;` or `:`).
+///
+/// The iCal parser this crate relies on trims trailing whitespace and a leading `:` from values, so that such names are not generated.
+fn name() -> impl Strategy<Value = String> {
+    "[a-zA-Z0-9 ,;:.!?'\"()&/+=\\-]{0,60}|\\PC{0,30}".prop_map(|name| {
+        name.trim_end().trim_start_matches(':').to_string()
+    })
+}
+
+fn prod_id() -> impl Strategy<Value = String> {
+    ("[a-zA-Z0-9 ]{1,20}", "[a-zA-Z0-9 .]{1,20}").prop_map(|(org, product)| format!("-//{}//{}//EN", org, product))
+}
+
+/// Properties that are kept as-is (the due date is generated separately, because a task has at most one)
+fn extra_parameters() -> impl Strategy<Value = Vec<Property>> {
+    let property = prop_oneof![
+        ("PRIORITY", "[0-9]"),
+        ("CATEGORIES", "[a-z]{1,8}(,[a-z]{1,8}){0,3}"),
+        ("X-KITCHEN-FRIDGE-ARBITRARY", "[a-zA-Z0-9]([a-zA-Z0-9 ]{0,18}[a-zA-Z0-9])?"),
+    ].prop_map(|(name, value)| property(&name, None, value));
+    proptest::collection::vec(property, 0..4)
+}
+
+fn property(name: &str, params: Option<Vec<(String, Vec<String>)>>, value: String) -> Property {
+    let mut prop = Property::new();
+    prop.name = name.to_string();
+    prop.params = params;
+    prop.value = Some(value);
+    prop
+}
+
+/// A change applied to the lines of a valid iCal file, to make it (probably) invalid
+#[derive(Clone, Debug)]
+enum Mutation {
+    Drop(Index),
+    Duplicate(Index),
+    Truncate(Index, Index),
+    Insert(Index, String),
+}
+
+fn mutation() -> impl Strategy<Value = Mutation> {
+    prop_oneof![
+        any::<Index>().prop_map(Mutation::Drop),
+        any::<Index>().prop_map(Mutation::Duplicate),
+        (any::<Index>(), any::<Index>()).prop_map(|(line, at)| Mutation::Truncate(line, at)),
+        (any::<Index>(), "[A-Z-]{0,10}[:;=]?\\PC{0,20}").prop_map(|(line, content)| Mutation::Insert(line, content)),
+    ]
+}
+
+fn apply_mutation(lines: &mut Vec<String>, mutation: &Mutation) {
+    if lines.is_empty() {
+        return;
+    }
+    match mutation {
+        Mutation::Drop(line) => {
+            let line = line.index(lines.len());
+            lines.remove(line);
+        },
+        Mutation::Duplicate(line) => {
+            let line = line.index(lines.len());
+            let duplicate = lines[line].clone();
+            lines.insert(line, duplicate);
+        },
+        Mutation::Truncate(line, at) => {
+            let len = lines.len();
+            let line = &mut lines[line.index(len)];
+            let boundaries: Vec<usize> = line.char_indices().map(|(i, _)| i).collect();
+            if boundaries.is_empty() == false {
+                line.truncate(boundaries[at.index(boundaries.len())]);
+            }
+        },
+        Mutation::Insert(line, content) => {
+            let line = line.index(lines.len());
+            lines.insert(line, content.clone());
+        },
+    }
+}
+
+/// iCal inputs to fuzz the parser with.
+///
+/// These are valid iCal files of arbitrary tasks, that have been randomly damaged (e.g. lines are removed, duplicated, truncated or made up). \
+/// Some of them are still valid, most of them are not. Parsing any of them must never panic.
+pub fn ical_input() -> impl Strategy<Value = String> {
+    (any::<Task>(), proptest::collection::vec(mutation(), 0..5)).prop_map(|(task, mutations)| {
+        let ical = crate::ical::build_from(&Item::Task(task)).unwrap();
+        let mut lines: Vec<String> = ical.split("\r\n").map(|line| line.to_string()).collect();
+        for mutation in &mutations {
+            apply_mutation(&mut lines, mutation);
+        }
+        lines.join("\r\n")
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ical::{build_from, parse};
+
+    /// `Property` does not implement `PartialEq`
+    fn debug_strings(properties: &[Property]) -> Vec<String> {
+        properties.iter().map(|prop| format!("{:?}", prop)).collect()
+    }
+
+    proptest! {
+        #[test]
+        fn test_round_trip(task in any::<Task>()) {
+            let ical = build_from(&Item::Task(task.clone())).unwrap();
+            let parsed = parse(&ical, task.url().clone(), task.sync_status().clone()).unwrap();
+            let parsed = parsed.unwrap_task();
+
+            prop_assert_eq!(parsed.url(), task.url());
+            prop_assert_eq!(parsed.uid(), task.uid());
+            prop_assert_eq!(parsed.name(), task.name());
+            prop_assert_eq!(parsed.completion_status(), task.completion_status());
+            prop_assert_eq!(parsed.sync_status(), task.sync_status());
+            prop_assert_eq!(parsed.creation_date(), task.creation_date());
+            prop_assert_eq!(parsed.last_modified(), task.last_modified());
+            prop_assert_eq!(parsed.dtstamp(), task.dtstamp());
+            prop_assert_eq!(parsed.sequence(), task.sequence());
+            prop_assert_eq!(parsed.due(), task.due());
+            prop_assert_eq!(parsed.ical_prod_id(), task.ical_prod_id());
+            prop_assert_eq!(debug_strings(parsed.extra_parameters()), debug_strings(task.extra_parameters()));
+            prop_assert_eq!(parsed.alarms().len(), task.alarms().len());
+            for (parsed_alarm, alarm) in parsed.alarms().iter().zip(task.alarms()) {
+                prop_assert_eq!(debug_strings(parsed_alarm.properties()), debug_strings(alarm.properties()));
+            }
+
+            // Building the parsed task must give the very same file
+            prop_assert_eq!(build_from(&Item::Task(parsed.clone())).unwrap(), ical);
+        }
+
+        #[test]
+        fn test_parser_does_not_panic(ical in ical_input()) {
+            let url = Url::parse("https://caldav.example.com/calendars/arbitrary/fuzzed.ics").unwrap();
+            let _ = parse(&ical, url, SyncStatus::NotSynced);
+        }
+    }
+}
@@ -0,0 +1,226 @@
+//! A blocking API, for apps that do not use async code (e.g. simple CLI tools, or GUI frameworks without async support)
+//!
+//! The types of this module wrap their async counterparts, and run their futures on a runtime that is dedicated to this module.
+//! The async types are still reachable (see e.g. [`Provider::inner`]), and their methods can be run with [`block_on`]. \
+//! Note that [`Cache`](crate::cache::Cache) and [`CachedCalendar`](crate::calendar::cached_calendar::CachedCalendar) already provide non-async versions of most of their methods (e.g. [`Cache::get_calendar_sync`](crate::cache::Cache::get_calendar_sync)).
+//!
+//! The functions of this module must not be called from an async context, because they block the current thread.
+//!
+//! ```no_run
+//! # #[cfg(all(feature = "client", feature = "cache"))] {
+//! use std::path::Path;
+//! use kitchen_fridge::{Cache, Client};
+//! use kitchen_fridge::blocking;
+//!
+//! let client = Client::new("https://my.server.com/remote.php/dav/files/john", "john", "secret").unwrap();
+//! let cache = Cache::from_folder(Path::new("/home/john/.cache/my-app")).unwrap();
+//! let mut provider = blocking::CalDavProvider::new(client, cache);
+//! if provider.sync() == false {
+//!     println!("The sync has not been fully successful");
+//! }
+//! # }
+//! ```
+
+#[cfg(feature = "client")]
+use std::collections::HashMap;
+#[cfg(feature = "client")]
+use std::error::Error;
+use std::future::Future;
+#[cfg(feature = "client")]
+use std::sync::{Arc, RwLock};
+
+#[cfg(feature = "client")]
+use csscolorparser::Color;
+use once_cell::sync::Lazy;
+use tokio::runtime::Runtime;
+use url::Url;
+
+use crate::traits::{CalDavSource, CompleteCalendar, DavCalendar};
+#[cfg(feature = "client")]
+use crate::calendar::SupportedComponents;
+#[cfg(feature = "client")]
+use crate::calendar::remote_calendar::RemoteCalendar;
+#[cfg(feature = "cache")]
+use crate::calendar::cached_calendar::CachedCalendar;
+use crate::provider::sync_progress::FeedbackSender;
+
+static RUNTIME: Lazy<Runtime> = Lazy::new(|| {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("kitchen-fridge-blocking")
+        .enable_all()
+        .build()
+        .expect("Unable to start the runtime of the blocking API")
+});
+
+/// Run a future to completion on the runtime of this module, blocking the current thread.
+///
+/// This can be used to call any async function of this crate that has no blocking counterpart in this module
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    RUNTIME.block_on(future)
+}
+
+
+/// A blocking version of [`crate::CalDavProvider`]
+#[cfg(all(feature = "client", feature = "cache"))]
+pub type CalDavProvider = Provider<crate::cache::Cache, CachedCalendar, crate::client::Client, RemoteCalendar>;
+
+/// A blocking version of [`crate::LocalProvider`]
+#[cfg(feature = "cache")]
+pub type LocalProvider = Provider<crate::cache::Cache, CachedCalendar, crate::cache::Cache, CachedCalendar>;
+
+/// A blocking wrapper around a [`Provider`](crate::provider::Provider)
+#[derive(Debug)]
+pub struct Provider<L, T, R, U>
+where
+    L: CalDavSource<T> + Sync,
+    T: CompleteCalendar + Sync + Send,
+    R: CalDavSource<U> + Sync,
+    U: DavCalendar + Sync + Send,
+{
+    inner: crate::provider::Provider<L, T, R, U>,
+}
+
+impl<L, T, R, U> Provider<L, T, R, U>
+where
+    L: CalDavSource<T> + Sync,
+    T: CompleteCalendar + Sync + Send,
+    R: CalDavSource<U> + Sync,
+    U: DavCalendar + Sync + Send,
+{
+    /// Create a provider. See [`crate::provider::Provider::new`]
+    pub fn new(remote: R, local: L) -> Self {
+        Self::from(crate::provider::Provider::new(remote, local))
+    }
+
+    /// Returns the data source described as `local`
+    pub fn local(&self)  -> &L { self.inner.local() }
+    /// Returns the data source described as `local`
+    pub fn local_mut(&mut self)  -> &mut L { self.inner.local_mut() }
+    /// Returns the data source described as `remote`. See [`crate::provider::Provider::remote`]
+    pub fn remote(&self) -> &R { self.inner.remote() }
+
+    /// Returns the wrapped async provider
+    pub fn inner(&self) -> &crate::provider::Provider<L, T, R, U> { &self.inner }
+    /// Returns the wrapped async provider
+    pub fn inner_mut(&mut self) -> &mut crate::provider::Provider<L, T, R, U> { &mut self.inner }
+    /// Returns the wrapped async provider
+    pub fn into_inner(self) -> crate::provider::Provider<L, T, R, U> { self.inner }
+
+    /// Performs a synchronisation between `local` and `remote`, and provide feeedback to the user about the progress.
+    ///
+    /// See [`crate::provider::Provider::sync_with_feedback`]
+    pub fn sync_with_feedback(&mut self, feedback_sender: FeedbackSender) -> bool {
+        block_on(self.inner.sync_with_feedback(feedback_sender))
+    }
+
+    /// Performs a synchronisation between `local` and `remote`, without giving any feedback.
+    ///
+    /// See [`crate::provider::Provider::sync`]
+    pub fn sync(&mut self) -> bool {
+        block_on(self.inner.sync())
+    }
+
+    /// Performs a synchronisation of a single calendar between `local` and `remote`, without giving any feedback.
+    ///
+    /// See [`crate::provider::Provider::sync_calendar`]
+    pub fn sync_calendar(&self, cal_url: &Url) -> bool {
+        block_on(self.inner.sync_calendar(cal_url))
+    }
+}
+
+impl<L, T, R, U> From<crate::provider::Provider<L, T, R, U>> for Provider<L, T, R, U>
+where
+    L: CalDavSource<T> + Sync,
+    T: CompleteCalendar + Sync + Send,
+    R: CalDavSource<U> + Sync,
+    U: DavCalendar + Sync + Send,
+{
+    fn from(inner: crate::provider::Provider<L, T, R, U>) -> Self {
+        Self { inner }
+    }
+}
+
+
+/// The calendars a [`Client`] has fetched, by URL
+#[cfg(feature = "client")]
+type RemoteCalendars = HashMap<Url, Arc<RwLock<RemoteCalendar>>>;
+
+/// A blocking wrapper around a [`Client`](crate::client::Client)
+#[cfg(feature = "client")]
+#[derive(Debug)]
+pub struct Client {
+    inner: crate::client::Client,
+}
+
+#[cfg(feature = "client")]
+impl Client {
+    /// Create a client. See [`crate::client::Client::new`]
+    pub fn new<S: AsRef<str>, T: ToString, U: ToString>(url: S, username: T, password: U) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::from(crate::client::Client::new(url, username, password)?))
+    }
+
+    /// Returns the wrapped async client
+    pub fn inner(&self) -> &crate::client::Client { &self.inner }
+    /// Returns the wrapped async client
+    pub fn inner_mut(&mut self) -> &mut crate::client::Client { &mut self.inner }
+    /// Returns the wrapped async client
+    pub fn into_inner(self) -> crate::client::Client { self.inner }
+
+    /// See [`CalDavSource::get_calendars`]
+    pub fn get_calendars(&self) -> Result<RemoteCalendars, Box<dyn Error>> {
+        block_on(self.inner.get_calendars())
+    }
+
+    /// See [`CalDavSource::get_calendar`]
+    pub fn get_calendar(&self, url: &Url) -> Option<Arc<RwLock<RemoteCalendar>>> {
+        block_on(self.inner.get_calendar(url))
+    }
+
+    /// See [`CalDavSource::create_calendar`]
+    pub fn create_calendar(&self, url: Url, name: String, supported_components: SupportedComponents, color: Option<Color>) -> Result<Arc<RwLock<RemoteCalendar>>, Box<dyn Error>> {
+        block_on(self.inner.create_calendar(url, name, supported_components, color))
+    }
+
+    /// See [`CalDavSource::delete_calendar`]
+    pub fn delete_calendar(&self, url: &Url) -> Result<(), Box<dyn Error>> {
+        block_on(self.inner.delete_calendar(url))
+    }
+}
+
+#[cfg(feature = "client")]
+impl From<crate::client::Client> for Client {
+    fn from(inner: crate::client::Client) -> Self {
+        Self { inner }
+    }
+}
+
+
+#[cfg(all(test, feature = "cache"))]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    use crate::cache::Cache;
+    use crate::calendar::SupportedComponents;
+    use crate::traits::BaseCalendar;
+    use crate::item::Item;
+    use crate::task::Task;
+
+    #[test]
+    fn test_blocking_sync() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let root = PathBuf::from(String::from("test_cache/blocking_test"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let cal_url = Url::parse("https://caldav.com/blocking").unwrap();
+        let mut provider = LocalProvider::from(crate::provider::Provider::new_folder_sync(Cache::new(&root.join("local")), &root.join("remote")).unwrap());
+        let calendar = block_on(provider.local_mut().create_calendar(cal_url.clone(), String::from("Blocking"), SupportedComponents::TODO, None)).unwrap();
+        block_on(calendar.write().unwrap().add_item(Item::Task(Task::new(String::from("Do not block"), false, &cal_url)))).unwrap();
+
+        assert!(provider.sync());
+        let remote_calendar = provider.remote().get_calendar_sync(&cal_url).unwrap();
+        assert_eq!(remote_calendar.read().unwrap().get_items_sync().unwrap().len(), 1);
+    }
+}
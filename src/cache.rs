@@ -1,28 +1,71 @@
 //! This module provides a local cache for CalDAV data
+//!
+//! This module requires the `cache` feature (enabled by default).
+#![cfg(feature = "cache")]
 
 use std::path::PathBuf;
 use std::path::Path;
 use std::error::Error;
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
 use std::ffi::OsStr;
+use std::io::BufWriter;
 
 use serde::{Deserialize, Serialize};
 use async_trait::async_trait;
 use csscolorparser::Color;
+use chrono::{DateTime, Utc};
 use url::Url;
 
+use crate::config::Config;
 use crate::traits::CalDavSource;
 use crate::traits::BaseCalendar;
 use crate::traits::CompleteCalendar;
-use crate::calendar::cached_calendar::CachedCalendar;
+use crate::traits::ItemObserver;
+use crate::calendar::cached_calendar::{CachedCalendar, ItemObservers};
 use crate::calendar::SupportedComponents;
+use crate::calendar::CalendarSyncStatus;
+use crate::item::Item;
+use crate::task::Task;
+use crate::provider::ConflictRecord;
+use crate::transaction::Transaction;
 
 #[cfg(feature = "local_calendar_mocks_remote_calendars")]
-use crate::mock_behaviour::MockBehaviour;
+use crate::mock_behaviour::{MockBehaviour, MockOperation, simulate_latency};
 
 const MAIN_FILE: &str = "data.json";
 
+/// The sub-folder that contains per-account caches, when several accounts share a single cache folder
+const ACCOUNTS_FOLDER: &str = "accounts";
+
+/// Write `value` as JSON to `path`. It is serialized through a buffer to a temporary file, that then replaces `path`.
+///
+/// This way, an interrupted save never leaves a truncated file behind, and other processes never read a partially written one. \
+/// Both the file and the rename are flushed to the disk before this returns. \
+/// `value` is streamed into the buffer as it is serialized (e.g. a calendar one item after another), so that its whole JSON is never built in memory.
+pub(crate) fn write_json_atomically<T: Serialize + ?Sized>(path: &Path, value: &T) -> Result<(), std::io::Error> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let mut writer = BufWriter::new(std::fs::File::create(&tmp_path)?);
+    serde_json::to_writer(&mut writer, value)?;
+    let file = writer.into_inner().map_err(|err| err.into_error())?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, path)?;
+
+    // The rename is only durable once the folder itself has been flushed
+    #[cfg(unix)]
+    {
+        let folder = match path.parent() {
+            Some(folder) if !folder.as_os_str().is_empty() => folder,
+            _ => Path::new("."),
+        };
+        std::fs::File::open(folder)?.sync_all()?;
+    }
+    Ok(())
+}
+
 /// A CalDAV source that stores its items in a local folder.
 ///
 /// It automatically updates the content of the folder when dropped (see its `Drop` implementation), but you can also manually call [`Cache::save_to_folder`]
@@ -33,16 +76,70 @@ const MAIN_FILE: &str = "data.json";
 pub struct Cache {
     backing_folder: PathBuf,
     data: CachedData,
+    /// Whether this cache is used as the remote source of a [`Provider`](crate::provider::Provider) (see [`Cache::new_as_remote`])
+    acts_as_remote: bool,
+    /// Whether this cache is saved to its backing folder (see [`Cache::new_in_memory_remote`])
+    persistent: bool,
+    /// The options of this cache (see [`Cache::with_config`])
+    config: Config,
 
     /// In tests, we may add forced errors to this object
     #[cfg(feature = "local_calendar_mocks_remote_calendars")]
     mock_behaviour: Option<Arc<Mutex<MockBehaviour>>>,
+    /// Behaviours that take precedence over `mock_behaviour` for some calendars
+    #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+    calendar_mock_behaviours: HashMap<Url, Arc<Mutex<MockBehaviour>>>,
+}
+
+/// Statistics about the content of a [`Cache`], see [`Cache::stats`]
+#[derive(Clone, Debug)]
+pub struct CacheStats {
+    /// Item counts, for every calendar of the cache
+    pub calendars: HashMap<Url, CalendarStats>,
+    /// The size (in bytes) of the backing folder on disk
+    pub on_disk_size: u64,
+    /// The last time the cache has been saved to its backing folder, or `None` if it has never been saved
+    pub last_save: Option<DateTime<Utc>>,
+}
+
+/// Item counts of a single calendar, see [`CacheStats`]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CalendarStats {
+    /// The total number of items (including events)
+    pub total: usize,
+    /// The number of completed tasks
+    pub completed: usize,
+    /// The number of tasks that are not completed yet
+    pub pending: usize,
+}
+
+/// Several items of a calendar that share a single UID, see [`Cache::duplicate_uids`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DuplicateUid {
+    /// The calendar these items belong to
+    pub calendar: Url,
+    /// The UID they share
+    pub uid: String,
+    /// The URLs of these items, sorted
+    pub items: Vec<Url>,
 }
 
 #[derive(Default, Debug, Serialize, Deserialize)]
 struct CachedData {
+    /// The account this cache belongs to, in case several accounts share a single cache folder
+    #[serde(default)]
+    account_id: Option<String>,
+    /// How many former versions of every item the calendars keep, see [`Cache::set_history_length`]
+    #[serde(default)]
+    history_length: usize,
+    /// How many days the calendars keep deleted items (`None` for the default), see [`Cache::set_trash_retention_days`]
+    #[serde(default)]
+    trash_retention_days: Option<u32>,
+    #[serde(skip)]
+    calendars: Mutex<HashMap<Url, Arc<RwLock<CachedCalendar>>>>,
+    /// The observers that are registered on every calendar, see [`Cache::add_observer`]
     #[serde(skip)]
-    calendars: HashMap<Url, Arc<Mutex<CachedCalendar>>>,
+    observers: Mutex<ItemObservers>,
 }
 
 impl Cache {
@@ -52,6 +149,26 @@ impl Cache {
         self.mock_behaviour = mock_behaviour;
     }
 
+    /// Set a mock behaviour for a single calendar, that takes precedence over the one of [`Cache::set_mock_behaviour`] (`None` to remove it).
+    ///
+    /// This is useful to model e.g. a healthy calendar next to a flaky one. The calendar does not need to exist yet.
+    #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+    pub fn set_calendar_mock_behaviour(&mut self, cal_url: &Url, mock_behaviour: Option<Arc<Mutex<MockBehaviour>>>) {
+        match mock_behaviour {
+            None => { self.calendar_mock_behaviours.remove(cal_url); },
+            Some(behaviour) => { self.calendar_mock_behaviours.insert(cal_url.clone(), behaviour); },
+        }
+        if let Some(cal) = self.get_calendar_sync(cal_url) {
+            cal.write().unwrap().set_mock_behaviour(self.mock_behaviour_for(cal_url).cloned());
+        }
+    }
+
+    /// The mock behaviour that applies to a given calendar
+    #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+    fn mock_behaviour_for(&self, cal_url: &Url) -> Option<&Arc<Mutex<MockBehaviour>>> {
+        self.calendar_mock_behaviours.get(cal_url).or(self.mock_behaviour.as_ref())
+    }
+
 
     /// Get the path to the cache folder
     pub fn cache_folder() -> PathBuf {
@@ -61,6 +178,8 @@ impl Cache {
     /// Initialize a cache from the content of a valid backing folder if it exists.
     /// Returns an error otherwise
     pub fn from_folder(folder: &Path) -> Result<Self, Box<dyn Error>> {
+        crate::transaction::recover(folder)?;
+
         // Load shared data...
         let main_file = folder.join(MAIN_FILE);
         let mut data: CachedData = match std::fs::File::open(&main_file) {
@@ -74,20 +193,23 @@ impl Cache {
         for entry in std::fs::read_dir(folder)? {
             match entry {
                 Err(err) => {
-                    log::error!("Unable to read dir: {:?}", err);
+                    log::error!(target: crate::logging::CACHE, "Unable to read dir: {:?}", err);
                     continue;
                 },
                 Ok(entry) => {
                     let cal_path = entry.path();
-                    log::debug!("Considering {:?}", cal_path);
+                    log::debug!(target: crate::logging::CACHE, "Considering {:?}", cal_path);
                     if cal_path.extension() == Some(OsStr::new("cal")) {
                         match Self::load_calendar(&cal_path) {
                             Err(err) => {
-                                log::error!("Unable to load calendar {:?} from cache: {:?}", cal_path, err);
+                                log::error!(target: crate::logging::CACHE, "Unable to load calendar {:?} from cache: {:?}", cal_path, err);
                                 continue;
                             },
-                            Ok(cal) =>
-                                data.calendars.insert(cal.url().clone(), Arc::new(Mutex::new(cal))),
+                            Ok(mut cal) => {
+                                cal.set_checkpoint_file(Some(cal_path.clone()));
+                                cal.purge_trash();
+                                data.calendars.get_mut().unwrap().insert(cal.url().clone(), Arc::new(RwLock::new(cal)))
+                            },
                         };
                     }
                 },
@@ -97,13 +219,88 @@ impl Cache {
         Ok(Self{
             backing_folder: PathBuf::from(folder),
             data,
+            acts_as_remote: false,
+            persistent: true,
+            config: Config::default(),
 
             #[cfg(feature = "local_calendar_mocks_remote_calendars")]
             mock_behaviour: None,
+            #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+            calendar_mock_behaviours: HashMap::new(),
         })
     }
 
-    fn load_calendar(path: &Path) -> Result<CachedCalendar, Box<dyn Error>> {
+    /// Initialize a cache for a given account, from a cache folder that can be shared by several accounts.
+    ///
+    /// See [`Cache::from_folder`] and [`Cache::accounts`]
+    pub fn from_folder_for_account(folder: &Path, account_id: &str) -> Result<Self, Box<dyn Error>> {
+        let cache = Self::from_folder(&Self::account_folder(folder, account_id))?;
+        match cache.account_id() {
+            Some(id) if id == account_id => Ok(cache),
+            other => Err(format!("Cache folder belongs to account {:?}, not to {:?}", other, account_id).into()),
+        }
+    }
+
+    /// Initialize an empty cache for a given account, in a cache folder that can be shared by several accounts
+    pub fn new_for_account(folder: &Path, account_id: &str) -> Self {
+        let mut cache = Self::new(&Self::account_folder(folder, account_id));
+        cache.data.account_id = Some(account_id.to_string());
+        cache
+    }
+
+    /// Returns the IDs of the accounts that have been saved in a shared cache folder
+    pub fn accounts(folder: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+        let accounts_folder = folder.join(ACCOUNTS_FOLDER);
+        if accounts_folder.exists() == false {
+            return Ok(Vec::new());
+        }
+
+        let mut accounts = Vec::new();
+        for entry in std::fs::read_dir(&accounts_folder)? {
+            let main_file = entry?.path().join(MAIN_FILE);
+            let data: CachedData = match std::fs::File::open(&main_file) {
+                Err(_) => continue,
+                Ok(file) => match serde_json::from_reader(file) {
+                    Err(err) => {
+                        log::warn!(target: crate::logging::CACHE, "Unable to read account data from {:?}: {}", main_file, err);
+                        continue;
+                    },
+                    Ok(data) => data,
+                },
+            };
+            if let Some(account_id) = data.account_id {
+                accounts.push(account_id);
+            }
+        }
+        accounts.sort();
+        Ok(accounts)
+    }
+
+    /// Returns the account this cache belongs to, in case it has been created by [`Cache::new_for_account`]
+    pub fn account_id(&self) -> Option<&str> {
+        self.data.account_id.as_deref()
+    }
+
+    fn account_folder(folder: &Path, account_id: &str) -> PathBuf {
+        folder.join(ACCOUNTS_FOLDER).join(sanitize_filename::sanitize(account_id))
+    }
+
+    /// Returns the folder this cache is saved to
+    pub fn backing_folder(&self) -> &Path {
+        &self.backing_folder
+    }
+
+    /// Whether this cache is saved to its backing folder (unlike the ones created by [`Cache::new_in_memory_remote`])
+    pub(crate) fn is_persistent(&self) -> bool {
+        self.persistent
+    }
+
+    /// Returns the name of the file a calendar is saved to, within the backing folder
+    pub(crate) fn calendar_file_name(cal_url: &Url) -> String {
+        sanitize_filename::sanitize(cal_url.as_str()) + ".cal"
+    }
+
+    pub(crate) fn load_calendar(path: &Path) -> Result<CachedCalendar, Box<dyn Error>> {
         let file = std::fs::File::open(&path)?;
         Ok(serde_json::from_reader(file)?)
     }
@@ -113,61 +310,308 @@ impl Cache {
         Self{
             backing_folder: PathBuf::from(folder_path),
             data: CachedData::default(),
+            acts_as_remote: false,
+            persistent: true,
+            config: Config::default(),
 
             #[cfg(feature = "local_calendar_mocks_remote_calendars")]
             mock_behaviour: None,
+            #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+            calendar_mock_behaviours: HashMap::new(),
+        }
+    }
+
+    /// Initialize a cache from the content of `folder` if it has been saved there, and with the default contents otherwise.
+    ///
+    /// Unlike a missing cache, an invalid cache is an error.
+    pub fn from_folder_or_new(folder: &Path) -> Result<Self, Box<dyn Error>> {
+        match folder.join(MAIN_FILE).exists() {
+            true => Self::from_folder(folder),
+            false => Ok(Self::new(folder)),
+        }
+    }
+
+    /// Initialize a cache that can be used as the remote source of a [`Provider`](crate::provider::Provider), instead of a CalDAV server.
+    ///
+    /// Its content is loaded from `folder` if it exists, and is initialized empty otherwise.
+    /// Just like a server would, it considers every item it stores as synced. \
+    /// Every change is written to `folder` at once, and `folder` is read again every time the calendars are listed (i.e. at the beginning of every sync), so that several processes can share it. \
+    /// This enables a file-based sync between devices: every device syncs its local cache with such a folder, and the folder itself is shared by other means (e.g. Syncthing). See [`LocalProvider`](crate::LocalProvider)
+    pub fn new_as_remote(folder: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut cache = Self::from_folder_or_new(folder)?;
+        cache.set_acts_as_remote();
+        Ok(cache)
+    }
+
+    /// Initialize a cache that can be used as the remote source of a [`Provider`](crate::provider::Provider), but that is never saved anywhere.
+    ///
+    /// See also [`Provider::new_local_only`](crate::provider::Provider::new_local_only), that is better suited for apps that work offline
+    pub fn new_in_memory_remote() -> Self {
+        let mut cache = Self::new(Path::new(""));
+        cache.persistent = false;
+        cache.set_acts_as_remote();
+        cache
+    }
+
+    /// Initialize an in-memory remote source that contains what `self` knows about its remote source, i.e. the content of `self` as it was when it was last synced
+    pub(crate) fn last_synced_copy(&self) -> Self {
+        let cache = Self::new_in_memory_remote();
+        {
+            let mut copies = cache.data.calendars.lock().unwrap();
+            for (url, cal) in self.data.calendars.lock().unwrap().iter() {
+                let copy = cal.read().unwrap().last_synced_copy();
+                copies.insert(url.clone(), Arc::new(RwLock::new(copy)));
+            }
+        }
+        cache
+    }
+
+    /// Set the options of this cache (e.g. the ProdID of the tasks created by [`Cache::new_task`]).
+    ///
+    /// Options are not saved along with the cache, they have to be set again every time it is loaded
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Returns the options of this cache
+    pub fn config(&self) -> &Config { &self.config }
+
+    /// Create a brand new task for one of the calendars of this cache, that advertises the ProdID of the [`Config`] of this cache. \
+    /// It is not added to the calendar: see e.g. [`CachedCalendar::add_item_sync`]
+    pub fn new_task(&self, name: String, completed: bool, cal_url: &Url) -> Task {
+        Task::new_with_config(name, completed, cal_url, &self.config)
+    }
+
+    /// Whether this cache is used as a remote source (see [`Cache::new_as_remote`])
+    pub fn acts_as_remote(&self) -> bool {
+        self.acts_as_remote
+    }
+
+    fn set_acts_as_remote(&mut self) {
+        self.acts_as_remote = true;
+        for cal in self.data.calendars.lock().unwrap().values() {
+            self.make_remote(&mut cal.write().unwrap());
+        }
+    }
+
+    /// Make a calendar of this cache behave like a remote calendar
+    fn make_remote(&self, cal: &mut CachedCalendar) {
+        cal.set_acts_as_remote(true);
+        cal.set_checkpoint_file(None);
+        if self.persistent {
+            cal.set_remote_backing_file(Some(self.backing_folder.join(Self::calendar_file_name(cal.url()))));
+        }
+    }
+
+    /// Register an observer on every calendar of this cache, including the ones that will be created later. See [`CachedCalendar::add_observer`]
+    pub fn add_observer(&self, observer: Arc<dyn ItemObserver>) {
+        for cal in self.data.calendars.lock().unwrap().values() {
+            cal.write().unwrap().add_observer(Arc::clone(&observer));
+        }
+        self.data.observers.lock().unwrap().0.push(observer);
+    }
+
+    /// Keep the last `length` versions of every item of every calendar of this cache, including the ones that will be created later (`0`, the default, keeps none).
+    ///
+    /// This is cheap insurance against accidental edits, that syncs would otherwise propagate everywhere. See [`CachedCalendar::set_history_length`]
+    pub fn set_history_length(&mut self, length: usize) {
+        self.data.history_length = length;
+        for cal in self.data.calendars.lock().unwrap().values() {
+            cal.write().unwrap().set_history_length(length);
+        }
+    }
+
+    /// Set how many days deleted items are kept in the trash of every calendar of this cache, including the ones that will be created later (`0` to delete items for good at once).
+    ///
+    /// See [`CachedCalendar::set_trash_retention_days`]
+    pub fn set_trash_retention_days(&mut self, days: u32) {
+        self.data.trash_retention_days = Some(days);
+        for cal in self.data.calendars.lock().unwrap().values() {
+            cal.write().unwrap().set_trash_retention_days(days);
+        }
+    }
+
+    /// Apply the settings of this cache that are saved along with every calendar (see e.g. [`Cache::set_history_length`]) to a calendar that is being created
+    pub(crate) fn apply_calendar_settings(&self, cal: &mut CachedCalendar) {
+        cal.set_history_length(self.data.history_length);
+        if let Some(days) = self.data.trash_retention_days {
+            cal.set_trash_retention_days(days);
+        }
+    }
+
+    /// Register the observers of this cache on a calendar that is being added to it
+    fn attach_observers(&self, cal: &mut CachedCalendar) {
+        for observer in &self.data.observers.lock().unwrap().0 {
+            cal.add_observer(Arc::clone(observer));
         }
     }
 
+    /// Read the calendars of a remote folder again, since other processes (or devices) may have changed them
+    fn reload_remote_folder(&self) {
+        if self.acts_as_remote == false || self.persistent == false {
+            return;
+        }
+        let entries = match std::fs::read_dir(&self.backing_folder) {
+            Err(_) => return, // Nothing has been written yet
+            Ok(entries) => entries,
+        };
+
+        let mut calendars = self.data.calendars.lock().unwrap();
+        for entry in entries.flatten() {
+            let cal_path = entry.path();
+            if cal_path.extension() != Some(OsStr::new("cal")) {
+                continue;
+            }
+            match Self::load_calendar(&cal_path) {
+                Err(err) => {
+                    // This file may be being written by another process. Let's keep what we had.
+                    log::warn!(target: crate::logging::CACHE, "Unable to reload calendar {:?}: {}", cal_path, err);
+                },
+                Ok(cal) => {
+                    match calendars.get(cal.url()) {
+                        Some(known) => known.write().unwrap().replace_content_with(cal),
+                        None => {
+                            let mut cal = cal;
+                            self.make_remote(&mut cal);
+                            self.attach_observers(&mut cal);
+                            calendars.insert(cal.url().clone(), Arc::new(RwLock::new(cal)));
+                        },
+                    }
+                },
+            }
+        }
+
+        // Calendars whose files have disappeared have been deleted by another process
+        calendars.retain(|url, _| self.backing_folder.join(Self::calendar_file_name(url)).exists());
+    }
+
     /// Store the current Cache to its backing folder
     ///
-    /// Note that this is automatically called when `self` is `drop`ped
+    /// Note that this is automatically called when `self` is `drop`ped. This does nothing for caches created by [`Cache::new_in_memory_remote`]
     pub fn save_to_folder(&self) -> Result<(), std::io::Error> {
+        if self.persistent == false {
+            return Ok(());
+        }
+
         let folder = &self.backing_folder;
         std::fs::create_dir_all(folder)?;
 
         // Save the general data
-        let main_file_path = folder.join(MAIN_FILE);
-        let file = std::fs::File::create(&main_file_path)?;
-        serde_json::to_writer(file, &self.data)?;
+        write_json_atomically(&folder.join(MAIN_FILE), &self.data)?;
 
         // Save each calendar
-        for (cal_url, cal_mutex) in &self.data.calendars {
-            let file_name = sanitize_filename::sanitize(cal_url.as_str()) + ".cal";
-            let cal_file = folder.join(file_name);
-            let file = std::fs::File::create(&cal_file)?;
-            let cal = cal_mutex.lock().unwrap();
-            serde_json::to_writer(file, &*cal)?;
+        for (cal_url, cal_mutex) in self.data.calendars.lock().unwrap().iter() {
+            let cal_file = folder.join(Self::calendar_file_name(cal_url));
+            let cal = cal_mutex.read().unwrap();
+            cal.notify_pending_changes();
+            write_json_atomically(&cal_file, &*cal)?;
         }
 
         Ok(())
     }
 
+    /// Returns statistics about this cache: item counts for every calendar, size on disk and the last time it has been saved.
+    ///
+    /// The on-disk values reflect the last call to [`Cache::save_to_folder`], not the current in-memory content
+    pub fn stats(&self) -> Result<CacheStats, Box<dyn Error>> {
+        let mut calendars = HashMap::new();
+        for (cal_url, cal_mutex) in self.data.calendars.lock().unwrap().iter() {
+            let cal = cal_mutex.read().unwrap();
+            let mut cal_stats = CalendarStats::default();
+            for item in cal.iter_items() {
+                cal_stats.total += 1;
+                if let Item::Task(task) = item {
+                    if task.completed() {
+                        cal_stats.completed += 1;
+                    } else {
+                        cal_stats.pending += 1;
+                    }
+                }
+            }
+            calendars.insert(cal_url.clone(), cal_stats);
+        }
+
+        let on_disk_size = match self.backing_folder.exists() {
+            true => folder_size(&self.backing_folder)?,
+            false => 0,
+        };
+        let last_save = std::fs::metadata(self.backing_folder.join(MAIN_FILE))
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .map(DateTime::<Utc>::from);
+
+        Ok(CacheStats { calendars, on_disk_size, last_save })
+    }
+
+    /// Returns the overdue tasks of every calendar, along with the URL of their calendars, sorted by due date. See [`CachedCalendar::get_overdue_tasks`]
+    pub fn get_overdue_tasks(&self) -> Vec<(Url, Task)> {
+        let mut tasks = self.collect_tasks(CachedCalendar::get_overdue_tasks);
+        tasks.sort_by_key(|(_, task)| task.due());
+        tasks
+    }
+
+    /// Returns the tasks of every calendar that are due today, along with the URL of their calendars, sorted by due date. See [`CachedCalendar::get_tasks_due_today`]
+    pub fn get_tasks_due_today(&self) -> Vec<(Url, Task)> {
+        let mut tasks = self.collect_tasks(CachedCalendar::get_tasks_due_today);
+        tasks.sort_by_key(|(_, task)| task.due());
+        tasks
+    }
+
+    /// Returns the tasks of every calendar that are due this week, along with the URL of their calendars, sorted by due date. See [`CachedCalendar::get_tasks_due_this_week`]
+    pub fn get_tasks_due_this_week(&self) -> Vec<(Url, Task)> {
+        let mut tasks = self.collect_tasks(CachedCalendar::get_tasks_due_this_week);
+        tasks.sort_by_key(|(_, task)| task.due());
+        tasks
+    }
+
+    /// Returns the tasks of every calendar that have been completed since a given date, along with the URL of their calendars, most recent first. See [`CachedCalendar::get_tasks_completed_since`]
+    pub fn get_tasks_completed_since(&self, since: DateTime<Utc>) -> Vec<(Url, Task)> {
+        let mut tasks = self.collect_tasks(|cal| cal.get_tasks_completed_since(since));
+        tasks.sort_by(|(_, a), (_, b)| b.completion_status().completion_date().cmp(&a.completion_status().completion_date()));
+        tasks
+    }
+
+    /// Run a query on every calendar, and return copies of the resulting tasks
+    fn collect_tasks<F>(&self, query: F) -> Vec<(Url, Task)>
+    where
+        F: Fn(&CachedCalendar) -> Vec<&Task>,
+    {
+        let mut tasks = Vec::new();
+        for (cal_url, cal_mutex) in self.data.calendars.lock().unwrap().iter() {
+            let cal = cal_mutex.read().unwrap();
+            tasks.extend(query(&cal).into_iter().map(|task| (cal_url.clone(), task.clone())));
+        }
+        tasks
+    }
+
 
     /// Compares two Caches to check they have the same current content
     ///
     /// This is not a complete equality test: some attributes (sync status...) may differ. This should mostly be used in tests
-    #[cfg(any(test, feature = "integration_tests"))]
+    #[cfg(any(test, feature = "testing"))]
     pub async fn has_same_observable_content_as(&self, other: &Self) -> Result<bool, Box<dyn Error>> {
         let calendars_l = self.get_calendars().await?;
         let calendars_r = other.get_calendars().await?;
 
         if crate::utils::keys_are_the_same(&calendars_l, &calendars_r) == false {
-            log::debug!("Different keys for calendars");
+            log::debug!(target: crate::logging::CACHE, "Different keys for calendars");
             return Ok(false);
         }
 
         for (calendar_url, cal_l) in calendars_l {
-            log::debug!("Comparing calendars {}", calendar_url);
-            let cal_l = cal_l.lock().unwrap();
+            log::debug!(target: crate::logging::CACHE, "Comparing calendars {}", calendar_url);
             let cal_r = match calendars_r.get(&calendar_url) {
-                Some(c) => c.lock().unwrap(),
+                Some(c) => c.read().unwrap(),
                 None => return Err("should not happen, we've just tested keys are the same".into()),
             };
 
             // TODO: check calendars have the same names/ID/whatever
-            if cal_l.has_same_observable_content_as(&cal_r).await? == false {
-                log::debug!("Different calendars");
+            let same = crate::utils::read_locked(&cal_l, &*cal_r, |cal_l, cal_r| Box::pin(async move {
+                cal_l.has_same_observable_content_as(cal_r).await
+            }))?;
+            if same == false {
+                log::debug!(target: crate::logging::CACHE, "Different calendars");
                 return Ok(false)
             }
 
@@ -176,60 +620,242 @@ impl Cache {
     }
 }
 
+/// Recursively computes the size of the files a folder contains
+fn folder_size(folder: &Path) -> Result<u64, std::io::Error> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(folder)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            size += folder_size(&entry.path())?;
+        } else {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
 impl Drop for Cache {
     fn drop(&mut self) {
+        if self.acts_as_remote {
+            // Every change has been saved already. Saving the whole cache again may overwrite changes made by other processes
+            return;
+        }
         if let Err(err) = self.save_to_folder() {
-            log::error!("Unable to automatically save the cache when it's no longer required: {}", err);
+            log::error!(target: crate::logging::CACHE, "Unable to automatically save the cache when it's no longer required: {}", err);
         }
     }
 }
 
 impl Cache {
     /// The non-async version of [`crate::traits::CalDavSource::get_calendars`]
-    pub fn get_calendars_sync(&self) -> Result<HashMap<Url, Arc<Mutex<CachedCalendar>>>, Box<dyn Error>> {
+    pub fn get_calendars_sync(&self) -> Result<HashMap<Url, Arc<RwLock<CachedCalendar>>>, Box<dyn Error>> {
         #[cfg(feature = "local_calendar_mocks_remote_calendars")]
         self.mock_behaviour.as_ref().map_or(Ok(()), |b| b.lock().unwrap().can_get_calendars())?;
 
-        Ok(self.data.calendars.iter()
+        self.reload_remote_folder();
+        Ok(self.data.calendars.lock().unwrap().iter()
             .map(|(url, cal)| (url.clone(), cal.clone()))
             .collect()
         )
     }
 
+    /// Run `f` on the calendars of this cache, without cloning the map that holds them (as [`Cache::get_calendars_sync`] does).
+    ///
+    /// This is meant for code that is called very often (e.g. a GUI that lists the calendars on every frame). \
+    /// The calendar map is locked while `f` runs, so `f` must not create or delete calendars of this cache, since that would deadlock.
+    pub fn with_calendars<F, R>(&self, f: F) -> R
+    where
+        F: for<'a> FnOnce(std::collections::hash_map::Iter<'a, Url, Arc<RwLock<CachedCalendar>>>) -> R,
+    {
+        self.reload_remote_folder();
+        f(self.data.calendars.lock().unwrap().iter())
+    }
+
+    /// The URLs of the calendars of this cache
+    pub fn calendar_urls(&self) -> Vec<Url> {
+        self.with_calendars(|cals| cals.map(|(url, _)| url.clone()).collect())
+    }
+
+    /// The number of calendars of this cache
+    pub fn calendar_count(&self) -> usize {
+        self.with_calendars(|cals| cals.len())
+    }
+
+    /// The conflicts that the syncs of every calendar of this cache have resolved, oldest first (see [`CompleteCalendar::conflict_log`]).
+    ///
+    /// `item_url` only keeps the conflicts of a single item, e.g. to find out why an edit has been lost.
+    pub fn conflict_log(&self, item_url: Option<&Url>) -> Vec<ConflictRecord> {
+        let mut log: Vec<ConflictRecord> = self.with_calendars(|cals| {
+            cals.flat_map(|(_, cal)| cal.read().unwrap().conflict_log().to_vec())
+                .filter(|record| item_url.is_none() || item_url == Some(record.item_url()))
+                .collect()
+        });
+        log.sort_by_key(|record| record.date());
+        log
+    }
+
+    /// The items that share a UID with other items of their calendars (see [`CachedCalendar::duplicate_uids`]), sorted by calendar then by UID.
+    ///
+    /// Apps may check this after a sync, and prompt the user about which items to keep.
+    pub fn duplicate_uids(&self) -> Vec<DuplicateUid> {
+        let mut duplicates: Vec<DuplicateUid> = self.with_calendars(|cals| {
+            cals.flat_map(|(cal_url, cal)| {
+                cal.read().unwrap().duplicate_uids().into_iter()
+                    .map(|(uid, items)| DuplicateUid { calendar: cal_url.clone(), uid, items })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+        });
+        duplicates.sort_by(|a, b| (&a.calendar, &a.uid).cmp(&(&b.calendar, &b.uid)));
+        duplicates
+    }
+
+    /// Change several items or calendars at once: either all the changes `f` makes are applied, or none of them (in case `f`, or saving them, fails).
+    ///
+    /// The changed calendars are written to the backing folder when the transaction is committed, in a way that survives a crash in the middle of the commit.
+    /// Observers are then notified once per changed calendar (see [`ItemObserver::items_changed`]). \
+    /// `f` works on copies of the calendars (see [`Transaction`]): in case other threads (e.g. a sync) change the same calendars while `f` is running, the transaction fails rather than overwriting their changes.
+    ///
+    /// ```
+    /// # use std::path::Path;
+    /// # use url::Url;
+    /// # use kitchen_fridge::{Cache, Item, Task};
+    /// # use kitchen_fridge::calendar::SupportedComponents;
+    /// let cache = Cache::new(Path::new("test_cache/doc_transaction"));
+    /// let cal_url = Url::parse("https://my.server.com/groceries/").unwrap();
+    /// cache.transaction(|txn| {
+    ///     let cal = txn.create_calendar(cal_url.clone(), String::from("Groceries"), SupportedComponents::TODO, None)?;
+    ///     cal.add_item_sync(Item::Task(Task::new(String::from("Milk"), false, &cal_url)))?;
+    ///     cal.add_item_sync(Item::Task(Task::new(String::from("Eggs"), false, &cal_url)))?;
+    ///     Ok(())
+    /// }).unwrap();
+    /// assert_eq!(cache.get_calendar_sync(&cal_url).unwrap().read().unwrap().counts().total, 2);
+    /// ```
+    pub fn transaction<F, R>(&self, f: F) -> Result<R, Box<dyn Error>>
+    where
+        F: FnOnce(&mut Transaction) -> Result<R, Box<dyn Error>>,
+    {
+        let mut transaction = Transaction::new(self);
+        let result = f(&mut transaction)?;
+        transaction.commit()?;
+        Ok(result)
+    }
+
+    /// An empty calendar with the settings of this cache (observers, backing file, etc.), that a calendar created by a [`Transaction`] is applied to before it is added to this cache
+    pub(crate) fn new_adopted_calendar(&self, cal: &CachedCalendar) -> CachedCalendar {
+        let url = cal.url().clone();
+        let mut adopted: CachedCalendar = CompleteCalendar::new(cal.name().to_string(), url.clone(), cal.supported_components(), cal.color().cloned());
+        self.attach_observers(&mut adopted);
+        if self.persistent {
+            adopted.set_checkpoint_file(Some(self.backing_folder.join(Self::calendar_file_name(&url))));
+        }
+        if self.acts_as_remote {
+            self.make_remote(&mut adopted);
+        }
+        #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+        adopted.set_mock_behaviour(self.mock_behaviour_for(&url).cloned());
+        adopted
+    }
+
+    /// Lock the calendars of this cache, e.g. so that a [`Transaction`] is committed without another thread adding a calendar in the meantime
+    pub(crate) fn lock_calendars(&self) -> MutexGuard<'_, HashMap<Url, Arc<RwLock<CachedCalendar>>>> {
+        self.data.calendars.lock().unwrap()
+    }
+
     /// The non-async version of [`crate::traits::CalDavSource::get_calendar`]
-    pub fn get_calendar_sync(&self, url: &Url) -> Option<Arc<Mutex<CachedCalendar>>> {
-        self.data.calendars.get(url).map(|arc| arc.clone())
+    pub fn get_calendar_sync(&self, url: &Url) -> Option<Arc<RwLock<CachedCalendar>>> {
+        self.data.calendars.lock().unwrap().get(url).map(|arc| arc.clone())
+    }
+
+    /// Mark a calendar for deletion, so that the upcoming sync deletes it from the remote source, then from this cache
+    pub fn mark_calendar_for_deletion(&self, url: &Url) -> Result<(), Box<dyn Error>> {
+        let cal = self.data.calendars.lock().unwrap().get(url).cloned().ok_or_else(|| format!("There is no calendar {}", url))?;
+        cal.write().unwrap().set_sync_status(CalendarSyncStatus::LocallyDeleted);
+        Ok(())
+    }
+
+    /// The non-async version of [`crate::traits::CalDavSource::delete_calendar`]
+    pub fn delete_calendar_sync(&self, url: &Url) -> Result<(), Box<dyn Error>> {
+        log::debug!(target: crate::logging::CACHE, "Deleting local calendar {}", url);
+        #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+        self.mock_behaviour_for(url).map_or(Ok(()), |b| b.lock().unwrap().can_delete_calendar())?;
+
+        let removed = self.data.calendars.lock().unwrap().remove(url);
+        if removed.is_none() {
+            return Err(format!("There is no calendar {}", url).into());
+        }
+
+        // Otherwise, it would be loaded again the next time this cache is read from its folder
+        let cal_file = self.backing_folder.join(Self::calendar_file_name(url));
+        if self.persistent && cal_file.exists() {
+            std::fs::remove_file(&cal_file)?;
+        }
+        Ok(())
     }
 }
 
 #[async_trait]
 impl CalDavSource<CachedCalendar> for Cache {
-    async fn get_calendars(&self) -> Result<HashMap<Url, Arc<Mutex<CachedCalendar>>>, Box<dyn Error>> {
+    async fn get_calendars(&self) -> Result<HashMap<Url, Arc<RwLock<CachedCalendar>>>, Box<dyn Error>> {
+        #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+        simulate_latency(&self.mock_behaviour, MockOperation::GetCalendars).await;
+
         self.get_calendars_sync()
     }
 
-    async fn get_calendar(&self, url: &Url) -> Option<Arc<Mutex<CachedCalendar>>> {
+    async fn get_calendar(&self, url: &Url) -> Option<Arc<RwLock<CachedCalendar>>> {
         self.get_calendar_sync(url)
     }
 
-    async fn create_calendar(&mut self, url: Url, name: String, supported_components: SupportedComponents, color: Option<Color>) -> Result<Arc<Mutex<CachedCalendar>>, Box<dyn Error>> {
-        log::debug!("Inserting local calendar {}", url);
+    async fn create_calendar(&self, url: Url, name: String, supported_components: SupportedComponents, color: Option<Color>) -> Result<Arc<RwLock<CachedCalendar>>, Box<dyn Error>> {
+        log::debug!(target: crate::logging::CACHE, "Inserting local calendar {}", url);
+        #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+        simulate_latency(&self.mock_behaviour_for(&url).cloned(), MockOperation::CreateCalendar).await;
         #[cfg(feature = "local_calendar_mocks_remote_calendars")]
-        self.mock_behaviour.as_ref().map_or(Ok(()), |b| b.lock().unwrap().can_create_calendar())?;
+        self.mock_behaviour_for(&url).map_or(Ok(()), |b| b.lock().unwrap().can_create_calendar())?;
 
-        let new_calendar = CachedCalendar::new(name, url.clone(), supported_components, color);
-        let arc = Arc::new(Mutex::new(new_calendar));
+        let mut new_calendar = CachedCalendar::new(name, url.clone(), supported_components, color);
+        self.apply_calendar_settings(&mut new_calendar);
+        self.attach_observers(&mut new_calendar);
+        if self.persistent {
+            new_calendar.set_checkpoint_file(Some(self.backing_folder.join(Self::calendar_file_name(&url))));
+        }
+        let arc = Arc::new(RwLock::new(new_calendar));
+        if self.acts_as_remote {
+            let mut cal = arc.write().unwrap();
+            self.make_remote(&mut cal);
+            if self.persistent {
+                std::fs::create_dir_all(&self.backing_folder)?;
+                if self.backing_folder.join(MAIN_FILE).exists() == false {
+                    write_json_atomically(&self.backing_folder.join(MAIN_FILE), &self.data)?;
+                }
+                cal.save_if_remote()?;
+            }
+        }
 
         #[cfg(feature = "local_calendar_mocks_remote_calendars")]
-        if let Some(behaviour) = &self.mock_behaviour {
-            arc.lock().unwrap().set_mock_behaviour(Some(Arc::clone(behaviour)));
+        if let Some(behaviour) = self.mock_behaviour_for(&url) {
+            arc.write().unwrap().set_mock_behaviour(Some(Arc::clone(behaviour)));
         };
 
-        match self.data.calendars.insert(url, arc.clone()) {
+        let previous = self.data.calendars.lock().unwrap().insert(url, arc.clone());
+        match previous {
             Some(_) => Err("Attempt to insert calendar failed: there is alredy such a calendar.".into()),
             None => Ok(arc),
         }
     }
+
+    async fn delete_calendar(&self, url: &Url) -> Result<(), Box<dyn Error>> {
+        #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+        simulate_latency(&self.mock_behaviour_for(url).cloned(), MockOperation::DeleteCalendar).await;
+
+        self.delete_calendar_sync(url)
+    }
+
+    fn persist(&self) -> Result<(), Box<dyn Error>> {
+        Ok(self.save_to_folder()?)
+    }
 }
 
 #[cfg(test)]
@@ -240,9 +866,10 @@ mod tests {
     use crate::calendar::SupportedComponents;
     use crate::item::Item;
     use crate::task::Task;
+    use crate::test_utils::{BulkRecorder, Recorder, TaskBuilder};
 
     async fn populate_cache(cache_path: &Path) -> Cache {
-        let mut cache = Cache::new(&cache_path);
+        let cache = Cache::new(&cache_path);
 
         let _shopping_list = cache.create_calendar(
             Url::parse("https://caldav.com/shopping").unwrap(),
@@ -259,15 +886,15 @@ mod tests {
         ).await.unwrap();
 
         {
-            let mut bucket_list = bucket_list.lock().unwrap();
+            let mut bucket_list = bucket_list.write().unwrap();
             let cal_url = bucket_list.url().clone();
-            bucket_list.add_item(Item::Task(Task::new(
+            bucket_list.add_item_sync(Item::Task(Task::new(
                 String::from("Attend a concert of JS Bach"), false, &cal_url
-            ))).await.unwrap();
+            ))).unwrap();
 
-            bucket_list.add_item(Item::Task(Task::new(
+            bucket_list.add_item_sync(Item::Task(Task::new(
                 String::from("Climb the Lighthouse of Alexandria"), true, &cal_url
-            ))).await.unwrap();
+            ))).unwrap();
         }
 
         cache
@@ -288,11 +915,60 @@ mod tests {
         assert_eq!(test.unwrap(), true);
     }
 
+    #[tokio::test]
+    async fn cache_stats() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let cache_path = PathBuf::from(String::from("test_cache/stats_test"));
+        let _ = std::fs::remove_dir_all(&cache_path);
+        let cache = populate_cache(&cache_path).await;
+
+        let stats = cache.stats().unwrap();
+        assert_eq!(stats.on_disk_size, 0);
+        assert!(stats.last_save.is_none());
+        let bucket_list_stats = &stats.calendars[&Url::parse("https://caldav.com/bucket-list").unwrap()];
+        assert_eq!(bucket_list_stats, &CalendarStats{ total: 2, completed: 1, pending: 1 });
+        let shopping_stats = &stats.calendars[&Url::parse("https://caldav.com/shopping").unwrap()];
+        assert_eq!(shopping_stats, &CalendarStats::default());
+
+        cache.save_to_folder().unwrap();
+        let stats = cache.stats().unwrap();
+        assert!(stats.on_disk_size > 0);
+        assert!(stats.last_save.is_some());
+    }
+
+    #[tokio::test]
+    async fn cache_multiple_accounts() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let shared_path = PathBuf::from(String::from("test_cache/multi_account_test"));
+        let _ = std::fs::remove_dir_all(&shared_path);
+
+        {
+            let work = Cache::new_for_account(&shared_path, "work@example.com");
+            work.create_calendar(
+                Url::parse("https://caldav.com/work").unwrap(),
+                "Work".to_string(),
+                SupportedComponents::TODO,
+                None,
+            ).await.unwrap();
+            let _personal = Cache::new_for_account(&shared_path, "me@example.com");
+            // Both are saved when dropped
+        }
+
+        assert_eq!(Cache::accounts(&shared_path).unwrap(), vec!["me@example.com".to_string(), "work@example.com".to_string()]);
+
+        let work = Cache::from_folder_for_account(&shared_path, "work@example.com").unwrap();
+        assert_eq!(work.account_id(), Some("work@example.com"));
+        assert_eq!(work.get_calendars_sync().unwrap().len(), 1);
+        let personal = Cache::from_folder_for_account(&shared_path, "me@example.com").unwrap();
+        assert_eq!(personal.get_calendars_sync().unwrap().len(), 0);
+        assert!(Cache::from_folder_for_account(&shared_path, "nobody@example.com").is_err());
+    }
+
     #[tokio::test]
     async fn cache_sanity_checks() {
         let _ = env_logger::builder().is_test(true).try_init();
         let cache_path = PathBuf::from(String::from("test_cache/sanity_tests"));
-        let mut cache = populate_cache(&cache_path).await;
+        let cache = populate_cache(&cache_path).await;
 
         // We should not be able to add a second calendar with the same URL
         let second_addition_same_calendar = cache.create_calendar(
@@ -303,4 +979,532 @@ mod tests {
         ).await;
         assert!(second_addition_same_calendar.is_err());
     }
+
+    #[tokio::test]
+    async fn folder_sync() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let root = PathBuf::from(String::from("test_cache/folder_sync_test"));
+        let _ = std::fs::remove_dir_all(&root);
+        let shared_path = root.join("shared");
+
+        {
+            let local = populate_cache(&root.join("first_device")).await;
+            let mut provider = crate::LocalProvider::new_folder_sync(local, &shared_path).unwrap();
+            assert!(provider.sync().await);
+        }
+
+        let local = Cache::new(&root.join("second_device"));
+        let mut provider = crate::LocalProvider::new_folder_sync(local, &shared_path).unwrap();
+        assert!(provider.remote().acts_as_remote());
+        assert!(provider.sync().await);
+
+        let bucket_list = provider.local().get_calendar_sync(&Url::parse("https://caldav.com/bucket-list").unwrap()).unwrap();
+        let bucket_list = bucket_list.read().unwrap();
+        let items = bucket_list.get_items_sync().unwrap();
+        assert_eq!(items.len(), 2);
+        assert!(items.values().all(|item| matches!(item.sync_status(), crate::item::SyncStatus::Synced(_))));
+    }
+
+    #[tokio::test]
+    async fn local_only_sync() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let cache_path = PathBuf::from(String::from("test_cache/local_only_test"));
+        let _ = std::fs::remove_dir_all(&cache_path);
+
+        {
+            let local = populate_cache(&cache_path).await;
+            let mut provider = crate::LocalProvider::new_local_only(local);
+            assert!(provider.sync().await);
+        }
+
+        // Items that have been synced already must not be considered as deleted on the (absent) remote
+        let local = Cache::from_folder(&cache_path).unwrap();
+        let mut provider = crate::LocalProvider::new_local_only(local);
+        assert!(provider.sync().await);
+
+        let bucket_list = provider.local().get_calendar_sync(&Url::parse("https://caldav.com/bucket-list").unwrap()).unwrap();
+        assert_eq!(bucket_list.read().unwrap().get_items_sync().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn calendar_pairs() {
+        let cache_path = PathBuf::from(String::from("test_cache/calendar_pairs_test"));
+        let _ = std::fs::remove_dir_all(&cache_path);
+        let bucket_list_url = Url::parse("https://caldav.com/bucket-list").unwrap();
+
+        let mut provider = crate::LocalProvider::new(Cache::new_in_memory_remote(), populate_cache(&cache_path).await);
+        assert!(provider.calendar_pair(&bucket_list_url).await.is_none());
+
+        assert!(provider.sync().await);
+        let (local, remote) = provider.calendar_pair(&bucket_list_url).await.unwrap();
+        assert_eq!(local.read().unwrap().get_items_sync().unwrap().len(), 2);
+        assert_eq!(remote.read().unwrap().get_items_sync().unwrap().len(), 2);
+        assert!(Arc::ptr_eq(&local, &provider.local().get_calendar_sync(&bucket_list_url).unwrap()));
+    }
+
+    #[tokio::test]
+    async fn folder_sync_between_live_providers() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let root = PathBuf::from(String::from("test_cache/live_folder_sync_test"));
+        let _ = std::fs::remove_dir_all(&root);
+        let shared_path = root.join("shared");
+        let bucket_list_url = Url::parse("https://caldav.com/bucket-list").unwrap();
+
+        let mut first = crate::LocalProvider::new_folder_sync(populate_cache(&root.join("first_device")).await, &shared_path).unwrap();
+        let mut second = crate::LocalProvider::new_folder_sync(Cache::new(&root.join("second_device")), &shared_path).unwrap();
+        assert!(first.sync().await);
+        assert!(second.sync().await);
+
+        let bucket_list = second.local().get_calendar_sync(&bucket_list_url).unwrap();
+        assert_eq!(bucket_list.read().unwrap().get_items_sync().unwrap().len(), 2);
+        bucket_list.write().unwrap().add_item_sync(Item::Task(Task::new(
+            String::from("Swim with dolphins"), false, &bucket_list_url
+        ))).unwrap();
+        assert!(second.sync().await);
+
+        assert!(first.sync().await);
+        let bucket_list = first.local().get_calendar_sync(&bucket_list_url).unwrap();
+        assert_eq!(bucket_list.read().unwrap().get_items_sync().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn calendar_deletion_sync() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let root = PathBuf::from(String::from("test_cache/calendar_deletion_test"));
+        let _ = std::fs::remove_dir_all(&root);
+        let shared_path = root.join("shared");
+        let shopping_url = Url::parse("https://caldav.com/shopping").unwrap();
+        let bucket_list_url = Url::parse("https://caldav.com/bucket-list").unwrap();
+
+        let mut first = crate::LocalProvider::new_folder_sync(populate_cache(&root.join("first_device")).await, &shared_path).unwrap();
+        assert!(first.sync().await);
+        let mut second = crate::LocalProvider::new_folder_sync(Cache::new(&root.join("second_device")), &shared_path).unwrap();
+        assert!(second.sync().await);
+
+        // A calendar deleted locally is deleted from the remote...
+        first.local().mark_calendar_for_deletion(&shopping_url).unwrap();
+        assert!(first.sync().await);
+        assert!(first.local().get_calendar_sync(&shopping_url).is_none());
+        assert!(first.remote().get_calendar_sync(&shopping_url).is_none());
+
+        // ...and then from other local caches
+        assert!(second.sync().await);
+        assert!(second.local().get_calendar_sync(&shopping_url).is_none());
+        assert!(second.local().get_calendar_sync(&bucket_list_url).is_some());
+
+        // Deleted calendars do not come back when the cache is loaded again
+        drop(first);
+        let reloaded = Cache::from_folder(&root.join("first_device")).unwrap();
+        assert!(reloaded.get_calendar_sync(&shopping_url).is_none());
+        assert!(reloaded.get_calendar_sync(&bucket_list_url).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_atomic_saves() {
+        let cache_path = PathBuf::from(String::from("test_cache/atomic_saves"));
+        let _ = std::fs::remove_dir_all(&cache_path);
+        let cache = populate_cache(&cache_path).await;
+        cache.save_to_folder().unwrap();
+        cache.save_to_folder().unwrap();
+
+        // No temporary file is left behind
+        let mut file_names: Vec<String> = std::fs::read_dir(&cache_path).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        file_names.sort();
+        assert_eq!(file_names, vec![
+            String::from(MAIN_FILE),
+            Cache::calendar_file_name(&Url::parse("https://caldav.com/bucket-list").unwrap()),
+            Cache::calendar_file_name(&Url::parse("https://caldav.com/shopping").unwrap()),
+        ]);
+
+        let retrieved_cache = Cache::from_folder(&cache_path).unwrap();
+        assert!(cache.has_same_observable_content_as(&retrieved_cache).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_calendar_handles() {
+        let cache_path = PathBuf::from(String::from("test_cache/calendar_handles"));
+        let cache = populate_cache(&cache_path).await;
+
+        let mut urls = cache.calendar_urls();
+        urls.sort();
+        assert_eq!(urls, vec![Url::parse("https://caldav.com/bucket-list").unwrap(), Url::parse("https://caldav.com/shopping").unwrap()]);
+        assert_eq!(cache.calendar_count(), 2);
+
+        let item_count: usize = cache.with_calendars(|cals| cals.map(|(_, cal)| cal.read().unwrap().get_items_sync().unwrap().len()).sum());
+        assert_eq!(item_count, 2);
+    }
+
+    #[tokio::test]
+    async fn test_sync_index() {
+        let cache_path = PathBuf::from(String::from("test_cache/sync_index"));
+        let cache = populate_cache(&cache_path).await;
+        let cal = cache.get_calendar_sync(&Url::parse("https://caldav.com/bucket-list").unwrap()).unwrap();
+        let mut cal = cal.write().unwrap();
+        let cal_url = cal.url().clone();
+        for i in 0..20 {
+            cal.add_item_sync(Item::Task(Task::new(format!("Task #{}", i), false, &cal_url))).unwrap();
+        }
+
+        let index: Vec<(&Url, &crate::item::SyncStatus)> = cal.sync_index().collect();
+        assert_eq!(index.len(), 22);
+        assert!(index.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(index.iter().all(|(url, status)| cal.get_item_by_url_sync(url).unwrap().sync_status() == *status));
+    }
+
+    #[tokio::test]
+    async fn test_shared_items() {
+        let cache_path = PathBuf::from(String::from("test_cache/shared_items"));
+        let cache = populate_cache(&cache_path).await;
+        let cal = cache.get_calendar_sync(&Url::parse("https://caldav.com/bucket-list").unwrap()).unwrap();
+        let item_url = cal.read().unwrap().get_item_urls_sync().unwrap().into_iter().next().unwrap();
+
+        // Handles are not copies
+        let first = cal.read().unwrap().get_item_arc(&item_url).unwrap();
+        let second = cal.read().unwrap().get_item_arc(&item_url).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+
+        // They are snapshots, that are not altered by later changes
+        let previous_name = first.name().to_string();
+        if let Some(Item::Task(task)) = cal.write().unwrap().get_item_by_url_mut_sync(&item_url) {
+            task.set_name(String::from("Renamed"));
+        }
+        assert_eq!(first.name(), previous_name);
+        let third = cal.read().unwrap().get_item_arc(&item_url).unwrap();
+        assert_eq!(third.name(), "Renamed");
+    }
+
+    #[tokio::test]
+    async fn test_item_history() {
+        let cache_path = PathBuf::from(String::from("test_cache/item_history"));
+        let _ = std::fs::remove_dir_all(&cache_path);
+        let mut cache = Cache::new(&cache_path);
+        cache.set_history_length(2);
+
+        // Calendars get the history length of their cache...
+        let cal_url = Url::parse("https://caldav.com/history").unwrap();
+        let cal = cache.create_calendar(cal_url.clone(), String::from("History"), SupportedComponents::TODO, None).await.unwrap();
+        assert_eq!(cal.read().unwrap().history_length(), 2);
+        let task = TaskBuilder::new("Version 1", &cal_url).item();
+        let task_url = task.url().clone();
+        cal.write().unwrap().add_item_sync(task).unwrap();
+        for name in ["Version 2", "Version 3"] {
+            let mut item = cal.read().unwrap().get_item_by_url_sync(&task_url).unwrap().clone();
+            item.unwrap_task_mut().set_name(String::from(name));
+            cal.write().unwrap().update_item_sync(item).unwrap();
+        }
+
+        // ...and their history is saved along with their items
+        cache.save_to_folder().unwrap();
+        let loaded = Cache::from_folder(&cache_path).unwrap();
+        let loaded_cal = loaded.get_calendar_sync(&cal_url).unwrap();
+        assert_eq!(loaded_cal.read().unwrap().history_length(), 2);
+        let names: Vec<String> = loaded_cal.read().unwrap().item_history(&task_url).iter().map(|item| item.name().to_string()).collect();
+        assert_eq!(names, vec!["Version 1", "Version 2"]);
+    }
+
+    #[tokio::test]
+    async fn test_trash() {
+        let cache_path = PathBuf::from(String::from("test_cache/trash"));
+        let _ = std::fs::remove_dir_all(&cache_path);
+        let cache = Cache::new(&cache_path);
+        let cal_url = Url::parse("https://caldav.com/trash").unwrap();
+        let cal = cache.create_calendar(cal_url.clone(), String::from("Trash"), SupportedComponents::TODO, None).await.unwrap();
+        let task = TaskBuilder::new("Synced", &cal_url).synced().item();
+        let task_url = task.url().clone();
+        cal.write().unwrap().add_item_sync(task).unwrap();
+        cal.write().unwrap().immediately_delete_item_sync(&task_url).unwrap();
+
+        // The trash is saved, and emptied after the retention period
+        cache.save_to_folder().unwrap();
+        let mut loaded = Cache::from_folder(&cache_path).unwrap();
+        let loaded_cal = loaded.get_calendar_sync(&cal_url).unwrap();
+        assert_eq!(loaded_cal.read().unwrap().trash().count(), 1);
+
+        // Items that have expired while the cache was not loaded are forgotten as soon as it is
+        let cal_file = cache_path.join(Cache::calendar_file_name(&cal_url));
+        let mut json: serde_json::Value = serde_json::from_reader(std::fs::File::open(&cal_file).unwrap()).unwrap();
+        for trashed in json["trash"].as_object_mut().unwrap().values_mut() {
+            trashed["deleted_on"] = serde_json::json!("2000-01-01T00:00:00Z");
+        }
+        serde_json::to_writer(std::fs::File::create(&cal_file).unwrap(), &json).unwrap();
+        let expired = Cache::from_folder(&cache_path).unwrap();
+        assert_eq!(expired.get_calendar_sync(&cal_url).unwrap().read().unwrap().trash().count(), 0);
+
+        // The retention period of a cache applies to its existing calendars
+        loaded.set_trash_retention_days(0);
+        assert_eq!(loaded_cal.read().unwrap().trash_retention_days(), 0);
+        assert_eq!(loaded_cal.read().unwrap().trash().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_uids() {
+        let cache = Cache::new(&PathBuf::from(String::from("test_cache/duplicate_uids")));
+        let cal_url = Url::parse("https://caldav.com/duplicates").unwrap();
+        let other_url = Url::parse("https://caldav.com/other").unwrap();
+        let cal = cache.create_calendar(cal_url.clone(), String::from("Duplicates"), SupportedComponents::TODO, None).await.unwrap();
+        let other = cache.create_calendar(other_url.clone(), String::from("Other"), SupportedComponents::TODO, None).await.unwrap();
+        let task = |name: &str, uid: &str, cal_url: &Url| TaskBuilder::new(name, cal_url).uid(uid).url(cal_url.join(&format!("{}.ics", name)).unwrap()).synced().build();
+
+        cal.write().unwrap().add_item_sync(Item::Task(task("first", "shared-uid", &cal_url))).unwrap();
+        cal.write().unwrap().add_item_sync(Item::Task(task("unique", "unique-uid", &cal_url))).unwrap();
+        other.write().unwrap().add_item_sync(Item::Task(task("elsewhere", "shared-uid", &other_url))).unwrap();
+        // UIDs only have to be unique within a calendar
+        assert!(cache.duplicate_uids().is_empty());
+
+        cal.write().unwrap().add_item_sync(Item::Task(task("second", "shared-uid", &cal_url))).unwrap();
+        let first_url = cal_url.join("first.ics").unwrap();
+        let second_url = cal_url.join("second.ics").unwrap();
+        assert_eq!(cache.duplicate_uids(), vec![DuplicateUid {
+            calendar: cal_url.clone(),
+            uid: String::from("shared-uid"),
+            items: vec![first_url, second_url],
+        }]);
+    }
+
+    #[tokio::test]
+    async fn test_floating_due_dates() {
+        use chrono::TimeZone;
+        let cache = Cache::new_in_memory_remote();
+        let cal_url = Url::parse("https://caldav.com/floating").unwrap();
+        let cal = cache.create_calendar(cal_url.clone(), String::from("Floating"), SupportedComponents::TODO, None).await.unwrap();
+        {
+            let mut cal = cal.write().unwrap();
+            cal.add_item_sync(TaskBuilder::new("At 12:00 UTC", &cal_url).due(Utc.ymd(2021, 3, 22).and_hms(12, 0, 0)).item()).unwrap();
+            // Due at 9:00, wherever the user is
+            let due = crate::utils::time_zone::IcalDateTime::Floating(chrono::NaiveDate::from_ymd(2021, 3, 22).and_hms(9, 0, 0));
+            cal.add_item_sync(TaskBuilder::new("At 9:00", &cal_url).raw_property(due.to_property("DUE")).item()).unwrap();
+        }
+
+        let cal = cal.read().unwrap();
+        let names = |tasks: Vec<&Task>| tasks.iter().map(|task| task.name().to_string()).collect::<Vec<_>>();
+        let morning = (Some(Utc.ymd(2021, 3, 22).and_hms(6, 0, 0)), Utc.ymd(2021, 3, 22).and_hms(10, 0, 0));
+        assert_eq!(names(cal.get_tasks_due_between(morning.0, morning.1)), vec!["At 9:00"]);
+        assert!(cal.get_tasks_due_between_in(morning.0, morning.1, &"America/New_York".parse().unwrap()).is_empty());
+        assert_eq!(names(cal.get_tasks_due_between_in(None, Utc.ymd(2021, 3, 23).and_hms(0, 0, 0), &"America/New_York".parse().unwrap())), vec!["At 12:00 UTC", "At 9:00"]);
+        assert_eq!(names(cal.get_tasks_due_between_in(None, Utc.ymd(2021, 3, 23).and_hms(0, 0, 0), &"Asia/Tokyo".parse().unwrap())), vec!["At 9:00", "At 12:00 UTC"]);
+    }
+
+    #[test]
+    fn test_config() {
+        let cal_url = Url::parse("https://caldav.com/configured").unwrap();
+        let config = Config { org_name: String::from("ABC Corporation"), product_name: String::from("My Product"), ..Config::default() };
+        let configured = Cache::new(&PathBuf::from(String::from("test_cache/configured"))).with_config(config);
+        let default = Cache::new(&PathBuf::from(String::from("test_cache/not_configured")));
+
+        let task = configured.new_task(String::from("Configured"), false, &cal_url);
+        assert_eq!(task.ical_prod_id(), "-//ABC Corporation//My Product//EN");
+        assert!(crate::ical::build_from(&Item::Task(task)).unwrap().contains("PRODID:-//ABC Corporation//My Product//EN\r\n"));
+        let task = default.new_task(String::from("Not configured"), false, &cal_url);
+        assert_eq!(task.ical_prod_id(), Config::default().prod_id());
+    }
+
+    #[tokio::test]
+    async fn test_observe_mutable_references() {
+        let cache = Cache::new(&PathBuf::from(String::from("test_cache/observe_mutable_references")));
+        let cal_url = Url::parse("https://caldav.com/mutable").unwrap();
+        let cal = cache.create_calendar(cal_url.clone(), String::from("Mutable"), SupportedComponents::TODO, None).await.unwrap();
+        let renamed = TaskBuilder::new("Before", &cal_url).item();
+        let renamed_url = renamed.url().clone();
+        cal.write().unwrap().add_item_sync(renamed).unwrap();
+        let recorder = Arc::new(Recorder::default());
+        cal.write().unwrap().add_observer(recorder.clone());
+
+        // Observers are told about the changes made through mutable references when the cache is saved
+        if let Some(Item::Task(task)) = cal.write().unwrap().get_item_by_url_mut_sync(&renamed_url) {
+            task.set_name(String::from("Saved"));
+        }
+        assert!(recorder.changes.lock().unwrap().is_empty());
+        cache.save_to_folder().unwrap();
+        assert_eq!(*recorder.changes.lock().unwrap(), vec![(Some(String::from("Before")), Some(String::from("Saved")))]);
+    }
+
+    #[tokio::test]
+    async fn test_add_items() {
+        let cache = Cache::new(&PathBuf::from(String::from("test_cache/add_items")));
+        let cal_url = Url::parse("https://caldav.com/imported").unwrap();
+        let cal = cache.create_calendar(cal_url.clone(), String::from("Imported"), SupportedComponents::TODO, None).await.unwrap();
+        let recorder = Arc::new(BulkRecorder::default());
+        cal.write().unwrap().add_observer(recorder.clone());
+        let existing = Task::new(String::from("Existing"), false, &cal_url);
+        cal.write().unwrap().add_item_sync(Item::Task(existing.clone())).unwrap();
+
+        let mut items: Vec<Item> = (0..100).map(|i| Item::Task(Task::new(format!("Imported #{}", i), false, &cal_url))).collect();
+        items.insert(50, Item::Task(existing));
+        let results = crate::utils::write_locked(&cal, &(), |cal, _| cal.add_items(items));
+        assert_eq!(results.len(), 101);
+        assert!(results[50].is_err());
+        assert!(results.iter().enumerate().all(|(i, result)| i == 50 || result.as_ref().unwrap() == &crate::item::SyncStatus::NotSynced));
+        assert_eq!(cal.read().unwrap().counts().total, 101);
+        assert_eq!(*recorder.notifications.lock().unwrap(), vec![1, 100]);
+    }
+
+    #[tokio::test]
+    async fn test_unsynced_items_as_remote_items() {
+        let cache = Cache::new(&PathBuf::from(String::from("test_cache/unsynced_as_remote")));
+        let cal_url = Url::parse("https://caldav.com/local").unwrap();
+        let cal = cache.create_calendar(cal_url.clone(), String::from("Local"), SupportedComponents::TODO, None).await.unwrap();
+        cal.write().unwrap().add_item_sync(Item::Task(Task::new(String::from("Not synced"), false, &cal_url))).unwrap();
+
+        // A calendar that does not behave like a remote one cannot tell the version tags of its items, but this must not panic
+        let cal = cal.read().unwrap().clone();
+        let tags = crate::traits::DavCalendar::get_item_version_tags(&cal).await;
+        assert!(tags.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_transactions() {
+        let cache_path = PathBuf::from(String::from("test_cache/transactions"));
+        let _ = std::fs::remove_dir_all(&cache_path);
+        let cache = Cache::new(&cache_path);
+        let recorder = Arc::new(BulkRecorder::default());
+        cache.add_observer(recorder.clone());
+        let cal_url = Url::parse("https://caldav.com/edited").unwrap();
+        let new_cal_url = Url::parse("https://caldav.com/created").unwrap();
+        let cal = cache.create_calendar(cal_url.clone(), String::from("Edited"), SupportedComponents::TODO, None).await.unwrap();
+        let task = Task::new(String::from("Existing"), false, &cal_url);
+        let task_url = task.url().clone();
+        cal.write().unwrap().add_item_sync(Item::Task(task)).unwrap();
+        cache.save_to_folder().unwrap();
+        recorder.notifications.lock().unwrap().clear();
+
+        // Nothing is applied in case the transaction fails
+        let result: Result<(), _> = cache.transaction(|txn| {
+            txn.calendar(&cal_url)?.add_item_sync(Item::Task(Task::new(String::from("Lost"), false, &cal_url)))?;
+            txn.create_calendar(new_cal_url.clone(), String::from("Lost"), SupportedComponents::TODO, None)?;
+            Err("The user has cancelled".into())
+        });
+        assert!(result.is_err());
+        assert_eq!(cal.read().unwrap().counts().total, 1);
+        assert!(cache.get_calendar_sync(&new_cal_url).is_none());
+        assert!(recorder.notifications.lock().unwrap().is_empty());
+
+        let cal_file = cache_path.join(Cache::calendar_file_name(&cal_url));
+        let former_content = std::fs::read(&cal_file).unwrap();
+        let added = cache.transaction(|txn| {
+            let edited = txn.calendar(&cal_url)?;
+            edited.set_completed_bulk(std::slice::from_ref(&task_url), crate::task::CompletionStatus::Completed(None))?;
+            edited.add_item_sync(Item::Task(Task::new(String::from("Added"), false, &cal_url)))?;
+            let created = txn.create_calendar(new_cal_url.clone(), String::from("Created"), SupportedComponents::TODO, None)?;
+            created.add_item_sync(Item::Task(Task::new(String::from("Created"), false, &new_cal_url)))?;
+            Ok(3)
+        }).unwrap();
+        assert_eq!(added, 3);
+        assert_eq!(cal.read().unwrap().counts().completed, 1);
+        assert_eq!(cal.read().unwrap().counts().total, 2);
+        assert_eq!(cache.get_calendar_sync(&new_cal_url).unwrap().read().unwrap().counts().total, 1);
+        // Observers are notified once per calendar...
+        let mut notifications = recorder.notifications.lock().unwrap().clone();
+        notifications.sort();
+        assert_eq!(notifications, vec![1, 2]);
+        // ...and the changes are on disk already
+        assert_eq!(Cache::from_folder(&cache_path).unwrap().get_calendar_sync(&new_cal_url).unwrap().read().unwrap().counts().total, 1);
+
+        // A commit that has been interrupted is completed when the cache is loaded...
+        let committed_content = std::fs::read(&cal_file).unwrap();
+        let staged_file = cache_path.join(format!("{}.txn", Cache::calendar_file_name(&cal_url)));
+        std::fs::write(&staged_file, &committed_content).unwrap();
+        std::fs::write(&cal_file, &former_content).unwrap();
+        std::fs::write(cache_path.join("transaction.json"), serde_json::to_string(&[Cache::calendar_file_name(&cal_url)]).unwrap()).unwrap();
+        let loaded = Cache::from_folder(&cache_path).unwrap();
+        assert_eq!(loaded.get_calendar_sync(&cal_url).unwrap().read().unwrap().counts().completed, 1);
+        assert!(cache_path.join("transaction.json").exists() == false);
+
+        // ...unlike a transaction that had not started being committed
+        std::fs::write(&staged_file, &former_content).unwrap();
+        let loaded = Cache::from_folder(&cache_path).unwrap();
+        assert_eq!(loaded.get_calendar_sync(&cal_url).unwrap().read().unwrap().counts().completed, 1);
+        assert!(staged_file.exists() == false);
+
+        // Calendars that another thread has created in the meantime are not replaced
+        let raced_url = Url::parse("https://caldav.com/raced").unwrap();
+        let result: Result<(), _> = cache.transaction(|txn| {
+            let created = txn.create_calendar(raced_url.clone(), String::from("Replaced"), SupportedComponents::TODO, None)?;
+            created.add_item_sync(Item::Task(Task::new(String::from("Replaced"), false, &raced_url)))?;
+            cache.transaction(|other| {
+                let created = other.create_calendar(raced_url.clone(), String::from("Kept"), SupportedComponents::TODO, None)?;
+                created.add_item_sync(Item::Task(Task::new(String::from("Kept"), false, &raced_url)))?;
+                Ok(())
+            })
+        });
+        assert!(result.is_err());
+        let raced = cache.get_calendar_sync(&raced_url).unwrap();
+        assert_eq!(raced.read().unwrap().name(), "Kept");
+        assert_eq!(raced.read().unwrap().counts().total, 1);
+        assert_eq!(Cache::from_folder(&cache_path).unwrap().get_calendar_sync(&raced_url).unwrap().read().unwrap().name(), "Kept");
+
+        // Changes that another thread makes to a calendar while a transaction edits it are not overwritten
+        let concurrent = Task::new(String::from("Concurrent"), false, &cal_url);
+        let concurrent_url = concurrent.url().clone();
+        let result: Result<(), _> = cache.transaction(|txn| {
+            txn.calendar(&cal_url)?.add_item_sync(Item::Task(Task::new(String::from("Stale"), false, &cal_url)))?;
+            let cal = cache.get_calendar_sync(&cal_url).unwrap();
+            std::thread::spawn(move || {
+                cal.write().unwrap().add_item_sync(Item::Task(concurrent)).unwrap();
+            }).join().unwrap();
+            Ok(())
+        });
+        assert!(result.is_err());
+        assert!(cal.read().unwrap().get_item_by_url_sync(&concurrent_url).is_some());
+        assert_eq!(cal.read().unwrap().counts().total, 3);
+    }
+
+    #[tokio::test]
+    async fn test_item_observers() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let root = PathBuf::from(String::from("test_cache/observers_test"));
+        let _ = std::fs::remove_dir_all(&root);
+        let shared_path = root.join("shared");
+        let cal_url = Url::parse("https://caldav.com/observed").unwrap();
+
+        let mut first = crate::LocalProvider::new_folder_sync(Cache::new(&root.join("first_device")), &shared_path).unwrap();
+        let second_local = Cache::new(&root.join("second_device"));
+        let recorder = Arc::new(Recorder::default());
+        second_local.add_observer(recorder.clone());
+        let mut second = crate::LocalProvider::new_folder_sync(second_local, &shared_path).unwrap();
+
+        // Changes made by a sync are notified...
+        let cal = first.local().create_calendar(cal_url.clone(), String::from("Observed"), SupportedComponents::TODO, None).await.unwrap();
+        let task = Item::Task(Task::new(String::from("Synced task"), false, &cal_url));
+        let task_url = task.url().clone();
+        cal.write().unwrap().add_item_sync(task).unwrap();
+        assert!(first.sync().await);
+        assert!(second.sync().await);
+        assert_eq!(*recorder.changes.lock().unwrap(), vec![(None, Some(String::from("Synced task")))]);
+
+        // ...as well as changes made by the app
+        let cal = second.local().get_calendar_sync(&cal_url).unwrap();
+        cal.write().unwrap().mark_for_deletion_sync(&task_url).unwrap();
+        assert!(second.sync().await);
+        assert_eq!(recorder.changes.lock().unwrap()[1..], [
+            (Some(String::from("Synced task")), Some(String::from("Synced task (deleted)"))),
+            (Some(String::from("Synced task (deleted)")), None),
+        ]);
+    }
+
+    #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+    #[tokio::test]
+    async fn test_calendar_mock_behaviours() {
+        let healthy_url = Url::parse("https://caldav.com/healthy").unwrap();
+        let flaky_url = Url::parse("https://caldav.com/flaky").unwrap();
+        let mut cache = Cache::new_in_memory_remote();
+        cache.set_mock_behaviour(Some(Arc::new(Mutex::new(MockBehaviour::new()))));
+        cache.set_calendar_mock_behaviour(&flaky_url, Some(Arc::new(Mutex::new(MockBehaviour::fail_now(1)))));
+
+        assert!(cache.create_calendar(healthy_url.clone(), String::from("Healthy"), SupportedComponents::TODO, None).await.is_ok());
+        assert!(cache.create_calendar(flaky_url.clone(), String::from("Flaky"), SupportedComponents::TODO, None).await.is_err());
+        let flaky = cache.create_calendar(flaky_url.clone(), String::from("Flaky"), SupportedComponents::TODO, None).await.unwrap();
+
+        let healthy = cache.get_calendar_sync(&healthy_url).unwrap();
+        assert!(healthy.write().unwrap().add_item_sync(Item::Task(Task::new(String::from("Fine"), false, &healthy_url))).is_ok());
+        assert!(flaky.write().unwrap().add_item_sync(Item::Task(Task::new(String::from("Lost"), false, &flaky_url))).is_err());
+        assert!(flaky.write().unwrap().add_item_sync(Item::Task(Task::new(String::from("Fine"), false, &flaky_url))).is_ok());
+
+        // Existing calendars get the new behaviour at once
+        cache.set_calendar_mock_behaviour(&healthy_url, Some(Arc::new(Mutex::new(MockBehaviour::fail_now(1)))));
+        assert!(healthy.write().unwrap().add_item_sync(Item::Task(Task::new(String::from("Lost"), false, &healthy_url))).is_err());
+        cache.set_calendar_mock_behaviour(&healthy_url, None);
+        assert!(healthy.write().unwrap().add_item_sync(Item::Task(Task::new(String::from("Fine"), false, &healthy_url))).is_ok());
+    }
 }
@@ -0,0 +1,176 @@
+//! This module watches the backing folder of a [`Cache`], so that changes made by other processes (e.g. a CLI companion tool) are reloaded
+#![cfg(feature = "watch")]
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use url::Url;
+
+use crate::cache::Cache;
+use crate::calendar::cached_calendar::CachedCalendar;
+
+/// A change that has been noticed in the backing folder of a watched [`Cache`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum CacheEvent {
+    /// A calendar has been modified by another process, and its new content has been loaded
+    CalendarReloaded(Url),
+    /// A calendar file that this cache did not know about has appeared in the backing folder.
+    /// Load a new `Cache` to take it into account
+    UnknownCalendarFile(PathBuf),
+    /// A calendar file has been removed from the backing folder.
+    /// The in-memory calendar is kept (and will be saved again at next save)
+    CalendarFileRemoved(PathBuf),
+    /// A calendar file has been modified, but it could not be reloaded (this may be a transient error, in case the file is still being written)
+    ReloadFailed{ path: PathBuf, error: String },
+}
+
+/// Watches the backing folder of a [`Cache`], see [`Cache::watch`]
+///
+/// The folder is watched as long as this object is alive.
+pub struct CacheWatcher {
+    _watcher: RecommendedWatcher,
+    receiver: UnboundedReceiver<CacheEvent>,
+}
+
+impl CacheWatcher {
+    /// Returns the channel [`CacheEvent`]s are sent to
+    pub fn receiver(&mut self) -> &mut UnboundedReceiver<CacheEvent> {
+        &mut self.receiver
+    }
+
+    /// Returns the next pending event, if any, without blocking
+    pub fn try_next_event(&mut self) -> Option<CacheEvent> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+impl std::fmt::Debug for CacheWatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CacheWatcher").finish()
+    }
+}
+
+impl Cache {
+    /// Start watching the backing folder of this cache.
+    ///
+    /// Whenever another process modifies the file of a calendar this cache knows about, the in-memory calendar is replaced by the content of this file, and a [`CacheEvent`] is emitted. \
+    /// Note that this overwrites in-memory changes that have not been saved yet. This is usually not a problem if every process saves its changes as soon as they are made.
+    pub fn watch(&self) -> Result<CacheWatcher, Box<dyn Error>> {
+        std::fs::create_dir_all(self.backing_folder())?;
+        // Paths in notify events are absolute
+        let folder = self.backing_folder().canonicalize()?;
+
+        let mut known_calendars = HashMap::new();
+        for (cal_url, cal) in self.get_calendars_sync()? {
+            known_calendars.insert(folder.join(Cache::calendar_file_name(&cal_url)), (cal_url, cal));
+        }
+
+        let (sender, receiver) = unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+            match res {
+                Err(err) => log::warn!(target: crate::logging::CACHE, "Error while watching the cache folder: {}", err),
+                Ok(event) => handle_event(event, &known_calendars, &sender),
+            }
+        })?;
+        watcher.watch(&folder, RecursiveMode::NonRecursive)?;
+        log::debug!(target: crate::logging::CACHE, "Watching cache folder {:?}", folder);
+
+        Ok(CacheWatcher { _watcher: watcher, receiver })
+    }
+}
+
+fn handle_event(event: notify::Event, known_calendars: &HashMap<PathBuf, (Url, Arc<RwLock<CachedCalendar>>)>, sender: &UnboundedSender<CacheEvent>) {
+    for path in event.paths {
+        if path.extension().map(|ext| ext == "cal") != Some(true) {
+            continue;
+        }
+
+        let cache_event = match event.kind {
+            EventKind::Create(_) | EventKind::Modify(_) => {
+                match known_calendars.get(&path) {
+                    None => Some(CacheEvent::UnknownCalendarFile(path)),
+                    Some((cal_url, cal)) => reload_calendar(&path, cal_url, cal),
+                }
+            },
+            EventKind::Remove(_) => Some(CacheEvent::CalendarFileRemoved(path)),
+            _ => None,
+        };
+
+        if let Some(cache_event) = cache_event {
+            // The receiver may have been dropped, there is nothing to do in this case
+            let _ = sender.send(cache_event);
+        }
+    }
+}
+
+/// Reload a calendar from its file, and return the event to emit (if any)
+fn reload_calendar(path: &Path, cal_url: &Url, cal: &Arc<RwLock<CachedCalendar>>) -> Option<CacheEvent> {
+    let content = match std::fs::read_to_string(path) {
+        Err(err) => return Some(CacheEvent::ReloadFailed{ path: path.to_path_buf(), error: err.to_string() }),
+        Ok(content) => content,
+    };
+
+    let mut cal = cal.write().unwrap();
+    // Our own saves trigger events as well. There is nothing to reload in this case
+    if serde_json::to_string(&*cal).ok().as_ref() == Some(&content) {
+        return None;
+    }
+
+    match serde_json::from_str::<CachedCalendar>(&content) {
+        Err(err) => Some(CacheEvent::ReloadFailed{ path: path.to_path_buf(), error: err.to_string() }),
+        Ok(new_cal) => {
+            log::info!(target: crate::logging::CACHE, "Calendar {} has been modified by another process, reloading it", cal_url);
+            cal.replace_content_with(new_cal);
+            Some(CacheEvent::CalendarReloaded(cal_url.clone()))
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use crate::calendar::SupportedComponents;
+    use crate::item::Item;
+    use crate::task::Task;
+    use crate::traits::CalDavSource;
+
+    #[tokio::test]
+    async fn reload_on_external_change() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let cache_path = PathBuf::from(String::from("test_cache/watch_test"));
+        let _ = std::fs::remove_dir_all(&cache_path);
+        let cal_url = Url::parse("https://caldav.com/shared").unwrap();
+
+        let cache = Cache::new(&cache_path);
+        cache.create_calendar(cal_url.clone(), "Shared".to_string(), SupportedComponents::TODO, None).await.unwrap();
+        cache.save_to_folder().unwrap();
+        let mut watcher = cache.watch().unwrap();
+
+        // Another process adds an item
+        {
+            let other_process = Cache::from_folder(&cache_path).unwrap();
+            let cal = other_process.get_calendar_sync(&cal_url).unwrap();
+            cal.write().unwrap().add_item_sync(Item::Task(Task::new("Added elsewhere".to_string(), false, &cal_url))).unwrap();
+        }
+
+        // Depending on the platform, a few transient events (e.g. the file being truncated before being written) may come first
+        loop {
+            let event = tokio::time::timeout(Duration::from_secs(5), watcher.receiver().recv()).await.unwrap();
+            match event {
+                Some(CacheEvent::CalendarReloaded(url)) => { assert_eq!(url, cal_url); break; },
+                Some(CacheEvent::ReloadFailed{ .. }) => continue,
+                other => panic!("Unexpected event {:?}", other),
+            }
+        }
+        let cal = cache.get_calendar_sync(&cal_url).unwrap();
+        assert_eq!(cal.read().unwrap().get_items_sync().unwrap().len(), 1);
+    }
+}
@@ -1,20 +1,152 @@
-use std::collections::{HashMap, HashSet};
+#![cfg(feature = "cache")]
+
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::error::Error;
+use std::ops::{Deref, DerefMut};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 
 use serde::{Deserialize, Serialize};
+use chrono::{Date, DateTime, Datelike, Duration, Local, TimeZone, Utc};
+use chrono_tz::Tz;
+use once_cell::sync::OnceCell;
 use async_trait::async_trait;
 use csscolorparser::Color;
 use url::Url;
 
 use crate::item::SyncStatus;
-use crate::traits::{BaseCalendar, CompleteCalendar};
+use crate::traits::{BaseCalendar, CompleteCalendar, ItemObserver};
 use crate::calendar::SupportedComponents;
+use crate::calendar::CalendarSyncStatus;
+use crate::calendar::CalendarKind;
+use crate::calendar::SearchFilter;
 use crate::Item;
+use crate::task::{CompletionStatus, Task};
+use crate::provider::{ConflictRecord, SyncJournal, SyncRollback};
 
 #[cfg(feature = "local_calendar_mocks_remote_calendars")]
-use std::sync::{Arc, Mutex};
-#[cfg(feature = "local_calendar_mocks_remote_calendars")]
-use crate::mock_behaviour::MockBehaviour;
+use crate::mock_behaviour::{MockBehaviour, MockOperation, simulate_latency};
+
+/// How many resolved conflicts a calendar remembers. Older ones are forgotten
+const MAX_CONFLICT_LOG_LEN: usize = 100;
+
+/// How long deleted items are kept in the trash, unless told otherwise (this is what Nextcloud does)
+const DEFAULT_TRASH_RETENTION_DAYS: u32 = 30;
+
+fn default_trash_retention_days() -> u32 {
+    DEFAULT_TRASH_RETENTION_DAYS
+}
+
+/// An item that has been deleted from a [`CachedCalendar`], and that can still be restored (see [`CachedCalendar::restore_from_trash`])
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TrashedItem {
+    item: Arc<Item>,
+    deleted_on: DateTime<Utc>,
+}
+
+impl TrashedItem {
+    /// The item, as it was when it has been deleted
+    pub fn item(&self) -> &Arc<Item> {
+        &self.item
+    }
+
+    /// When the item has been deleted
+    pub fn deleted_on(&self) -> DateTime<Utc> {
+        self.deleted_on
+    }
+}
+
+/// Item counts of a [`CachedCalendar`], see [`CachedCalendar::counts`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ItemCounts {
+    /// The total number of items (including events, and items that are marked for deletion)
+    pub total: usize,
+    /// The number of tasks that are not completed yet (not counting the ones that are marked for deletion)
+    pub pending: usize,
+    /// The number of completed tasks (not counting the ones that are marked for deletion)
+    pub completed: usize,
+    /// The number of items that have been modified locally since the last sync
+    pub locally_modified: usize,
+    /// The number of items that are marked for deletion, and will be deleted on the next sync
+    pub marked_for_deletion: usize,
+}
+
+
+/// An item of a [`CachedCalendar`], as handed out by [`CachedCalendar::iter_items_mut_sync`].
+///
+/// The item is only copied (in case it is shared) and reported to the observers of its calendar once it is mutably dereferenced
+pub struct ItemMut<'a> {
+    url: &'a Url,
+    item: &'a mut Arc<Item>,
+    /// `None` in case the calendar has no observers
+    pending_changes: Option<&'a PendingChanges>,
+    changed: bool,
+}
+
+impl<'a> ItemMut<'a> {
+    pub fn url(&self) -> &Url {
+        self.url
+    }
+}
+
+impl<'a> Deref for ItemMut<'a> {
+    type Target = Item;
+
+    fn deref(&self) -> &Item {
+        self.item
+    }
+}
+
+impl<'a> DerefMut for ItemMut<'a> {
+    fn deref_mut(&mut self) -> &mut Item {
+        if self.changed == false {
+            self.changed = true;
+            if let Some(pending_changes) = self.pending_changes {
+                pending_changes.add(self.url, self.item);
+            }
+        }
+        Arc::make_mut(self.item)
+    }
+}
+
+
+/// The observers of a [`CachedCalendar`]
+#[derive(Clone, Default)]
+pub(crate) struct ItemObservers(pub(crate) Vec<Arc<dyn ItemObserver>>);
+
+impl std::fmt::Debug for ItemObservers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} observers", self.0.len())
+    }
+}
+
+/// The items that have been handed out mutably (see [`CachedCalendar::get_item_by_url_mut_sync`]), along with their former states, whose observers have not been notified yet
+#[derive(Default)]
+struct PendingChanges(Mutex<Vec<(Option<Arc<Item>>, Url)>>);
+
+impl Clone for PendingChanges {
+    /// Pending notifications belong to the observers of the original calendar only
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl PendingChanges {
+    /// Remember the current state of an item, unless its observers still have to be told about an older one
+    fn add(&self, url: &Url, item: &Arc<Item>) {
+        let mut pending = self.0.lock().unwrap();
+        if pending.iter().any(|(_, pending_url)| pending_url == url) {
+            return;
+        }
+        pending.push((Some(Arc::clone(item)), url.clone()));
+    }
+}
+
+impl std::fmt::Debug for PendingChanges {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} pending changes", self.0.lock().unwrap().len())
+    }
+}
 
 
 /// A calendar used by the [`cache`](crate::cache) module
@@ -27,11 +159,68 @@ pub struct CachedCalendar {
     url: Url,
     supported_components: SupportedComponents,
     color: Option<Color>,
+    #[serde(default)]
+    kind: CalendarKind,
+    #[serde(default)]
+    sync_status: CalendarSyncStatus,
+    /// The progress of a sync that is in progress (or that has been interrupted)
+    #[serde(default)]
+    sync_journal: Option<SyncJournal>,
+    /// The version of the remote calendar as of the last complete sync
+    #[serde(default)]
+    last_remote_version: Option<VersionTag>,
+    /// The items whose changes the server has asked not to push again before a given date
+    #[serde(default)]
+    retry_not_before: HashMap<Url, DateTime<Utc>>,
+    /// The state of this calendar before the last sync that changed it
+    #[serde(default)]
+    rollback_point: Option<SyncRollback>,
+    /// The most recent conflicts the syncs of this calendar have resolved
+    #[serde(default)]
+    conflict_log: Vec<ConflictRecord>,
+    /// How many former versions of every item are kept (see [`CachedCalendar::set_history_length`])
+    #[serde(default)]
+    history_length: usize,
+    /// The former versions of the items, oldest first
+    #[serde(default)]
+    history: HashMap<Url, VecDeque<Arc<Item>>>,
+    /// How many days deleted items are kept in the trash (see [`CachedCalendar::set_trash_retention_days`])
+    #[serde(default = "default_trash_retention_days")]
+    trash_retention_days: u32,
+    /// The items that have been deleted
+    #[serde(default)]
+    trash: BTreeMap<Url, TrashedItem>,
     #[cfg(feature = "local_calendar_mocks_remote_calendars")]
     #[serde(skip)]
     mock_behaviour: Option<Arc<Mutex<MockBehaviour>>>,
+    /// Whether this calendar behaves like a calendar on a server (see [`Cache::new_as_remote`](crate::cache::Cache::new_as_remote))
+    #[serde(skip)]
+    acts_as_remote: bool,
+    /// The file this calendar is written to after every change, in case it is a remote calendar backed by a folder
+    #[serde(skip)]
+    remote_backing_file: Option<PathBuf>,
+    /// The file this calendar is written to whenever a sync records its progress (see [`CompleteCalendar::set_sync_journal`])
+    #[serde(skip)]
+    checkpoint_file: Option<PathBuf>,
+    /// The tasks that have a due date, sorted by due date.
+    /// It is built on demand, and reset whenever items are (or may be) modified
+    #[serde(skip)]
+    due_index: OnceCell<BTreeMap<DateTime<Utc>, Vec<Url>>>,
+    /// The item counts, built on demand as well
+    #[serde(skip)]
+    counts: OnceCell<ItemCounts>,
+    #[serde(skip)]
+    observers: ItemObservers,
+    #[serde(skip)]
+    pending_changes: PendingChanges,
+    /// How many times this calendar has been changed since it has been loaded, so that a [`Transaction`](crate::transaction::Transaction) can tell whether it has been changed while it was running
+    #[serde(skip)]
+    revision: u64,
 
-    items: HashMap<Url, Item>,
+    /// Items are shared, so that apps can keep them past the lock of this calendar without copying them (see [`CachedCalendar::get_item_arc`]). \
+    /// They are copied on write only in case they are still shared.
+    /// They are sorted by URL, so that this map is the index a sync compares with the remote calendar (see [`CompleteCalendar::sync_index`])
+    items: BTreeMap<Url, Arc<Item>>,
 }
 
 impl CachedCalendar {
@@ -42,21 +231,140 @@ impl CachedCalendar {
     }
 
 
-    #[cfg(feature = "local_calendar_mocks_remote_calendars")]
-    fn add_item_maybe_mocked(&mut self, item: Item) -> Result<SyncStatus, Box<dyn Error>> {
+    /// Make this calendar behave like a calendar on a server, i.e. every item it stores is considered synced
+    pub(crate) fn set_acts_as_remote(&mut self, acts_as_remote: bool) {
+        self.acts_as_remote = acts_as_remote;
+
+        if acts_as_remote {
+            self.invalidate_indexes();
+            // The folder may have been written by a regular cache. Servers do not know about pending changes
+            self.items.retain(|_url, item| matches!(item.sync_status(), SyncStatus::LocallyDeleted(_)) == false);
+            for item in self.items.values_mut() {
+                if let SyncStatus::Synced(_) = item.sync_status() {
+                    continue;
+                }
+                Arc::make_mut(item).set_sync_status(SyncStatus::random_synced());
+            }
+        }
+    }
+
+    /// Returns a copy of this calendar, in the state it had when it was last synced, that behaves like a calendar on a server
+    pub(crate) fn last_synced_copy(&self) -> CachedCalendar {
+        let mut copy: CachedCalendar = CompleteCalendar::new(self.name.clone(), self.url.clone(), self.supported_components, self.color.clone());
+        for (url, item) in &self.items {
+            let vt = match item.sync_status() {
+                SyncStatus::NotSynced => continue,
+                SyncStatus::Synced(vt) | SyncStatus::LocallyModified(vt) | SyncStatus::LocallyDeleted(vt) => vt.clone(),
+            };
+            let mut item = Item::clone(item);
+            item.set_sync_status(SyncStatus::Synced(vt));
+            copy.items.insert(url.clone(), Arc::new(item));
+        }
+        copy.set_acts_as_remote(true);
+        copy
+    }
+
+    /// Returns a copy of this calendar, that is not saved anywhere and has no observers, to be changed by a [`Transaction`](crate::transaction::Transaction)
+    pub(crate) fn detached_copy(&self) -> CachedCalendar {
+        let mut copy = self.clone();
+        copy.observers = ItemObservers::default();
+        copy.remote_backing_file = None;
+        copy.checkpoint_file = None;
+        copy
+    }
+
+    /// Replace the content of this calendar with a copy that a [`Transaction`](crate::transaction::Transaction) has changed, and notify the observers of every change at once
+    pub(crate) fn apply_transaction(&mut self, changed: CachedCalendar) {
+        let former_items = std::mem::take(&mut self.items);
+        self.replace_content_with(changed);
+
+        // Items that have not been changed are still shared with the former content
+        let mut changes: Vec<(Option<Arc<Item>>, Url)> = former_items.iter()
+            .filter(|(url, former)| match self.items.get(*url) {
+                Some(item) => Arc::ptr_eq(item, former) == false,
+                None => true,
+            })
+            .map(|(url, former)| (Some(Arc::clone(former)), url.clone()))
+            .collect();
+        changes.extend(self.items.keys()
+            .filter(|url| former_items.contains_key(*url) == false)
+            .map(|url| (None, url.clone())));
+        self.notify_observers_bulk(&changes);
+    }
+
+    /// Make this remote calendar write itself to a file after every change, so that other processes that share this file see the changes at once
+    pub(crate) fn set_remote_backing_file(&mut self, path: Option<PathBuf>) {
+        self.remote_backing_file = path;
+    }
+
+    /// Write this calendar to its backing file, in case it is a remote calendar backed by a folder
+    pub(crate) fn save_if_remote(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(path) = &self.remote_backing_file {
+            crate::cache::write_json_atomically(path, self)?;
+        }
+        Ok(())
+    }
+
+    /// Make this calendar write itself to a file whenever a sync records its progress, so that an interrupted sync can be resumed
+    pub(crate) fn set_checkpoint_file(&mut self, path: Option<PathBuf>) {
+        self.checkpoint_file = path;
+    }
+
+    /// Replace the content of this calendar with another instance (e.g. that has just been read from disk), but keep the settings that are not saved to disk
+    pub(crate) fn replace_content_with(&mut self, mut other: CachedCalendar) {
+        #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+        { other.mock_behaviour = self.mock_behaviour.take(); }
+        other.remote_backing_file = self.remote_backing_file.take();
+        other.checkpoint_file = self.checkpoint_file.take();
+        other.observers = std::mem::take(&mut self.observers);
+        other.set_acts_as_remote(self.acts_as_remote);
+        other.revision = self.revision + 1;
+        *self = other;
+    }
+
+    /// Whether this calendar has been asked to behave like a calendar on a server
+    pub fn acts_as_remote(&self) -> bool {
+        self.acts_as_remote
+    }
+
+    /// Whether this calendar behaves like a calendar on a server (either because it has been asked to, or because it mocks one)
+    fn is_remote(&self) -> bool {
+        #[cfg(feature = "local_calendar_mocks_remote_calendars")]
         if self.mock_behaviour.is_some() {
-            self.mock_behaviour.as_ref().map_or(Ok(()), |b| b.lock().unwrap().can_add_item())?;
+            return true;
+        }
+
+        self.acts_as_remote
+    }
+
+    fn add_item_maybe_remote(&mut self, item: Item) -> Result<SyncStatus, Box<dyn Error>> {
+        let sync_status = self.add_item_maybe_remote_unsaved(item)?;
+        if self.is_remote() {
+            self.save_if_remote()?;
+        }
+        Ok(sync_status)
+    }
+
+    /// Same as [`Self::add_item_maybe_remote`], but a calendar that behaves like a remote one is not saved yet
+    fn add_item_maybe_remote_unsaved(&mut self, item: Item) -> Result<SyncStatus, Box<dyn Error>> {
+        #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+        self.mock_behaviour.as_ref().map_or(Ok(()), |b| b.lock().unwrap().can_add_item())?;
+
+        if self.is_remote() {
             self.add_or_update_item_force_synced(item)
         } else {
             self.regular_add_or_update_item(item)
         }
     }
 
-    #[cfg(feature = "local_calendar_mocks_remote_calendars")]
-    fn update_item_maybe_mocked(&mut self, item: Item) -> Result<SyncStatus, Box<dyn Error>> {
-        if self.mock_behaviour.is_some() {
-            self.mock_behaviour.as_ref().map_or(Ok(()), |b| b.lock().unwrap().can_update_item())?;
-            self.add_or_update_item_force_synced(item)
+    fn update_item_maybe_remote(&mut self, item: Item) -> Result<SyncStatus, Box<dyn Error>> {
+        #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+        self.mock_behaviour.as_ref().map_or(Ok(()), |b| b.lock().unwrap().can_update_item())?;
+
+        if self.is_remote() {
+            let sync_status = self.add_or_update_item_force_synced(item)?;
+            self.save_if_remote()?;
+            Ok(sync_status)
         } else {
             self.regular_add_or_update_item(item)
         }
@@ -65,33 +373,93 @@ impl CachedCalendar {
     /// Add or update an item
     fn regular_add_or_update_item(&mut self, item: Item) -> Result<SyncStatus, Box<dyn Error>> {
         let ss_clone = item.sync_status().clone();
-        log::debug!("Adding or updating an item with {:?}", ss_clone);
-        self.items.insert(item.url().clone(), item);
+        log::debug!(target: crate::logging::CACHE, "Adding or updating an item with {:?}", ss_clone);
+        self.invalidate_indexes();
+        let url = item.url().clone();
+        if let Some(former) = self.items.insert(url.clone(), Arc::new(item)) {
+            self.push_to_history(url, former);
+        }
         Ok(ss_clone)
     }
 
+    /// Keep a former version of an item, in case this calendar keeps a history
+    fn push_to_history(&mut self, url: Url, former: Arc<Item>) {
+        if self.history_length == 0 {
+            return;
+        }
+        let versions = self.history.entry(url).or_default();
+        if versions.len() == self.history_length {
+            versions.pop_front();
+        }
+        versions.push_back(former);
+    }
+
+    /// Keep the last `length` versions of every item of this calendar (`0`, the default, keeps none).
+    ///
+    /// A version is kept whenever an item is replaced by [`Self::update_item_sync`] (or its async counterpart), be it by the user or by a sync. They are saved along with this calendar.
+    /// Former versions can be listed with [`Self::item_history`], and restored with [`Self::restore_item_version`]
+    pub fn set_history_length(&mut self, length: usize) {
+        self.history_length = length;
+        self.revision += 1;
+        for versions in self.history.values_mut() {
+            let excess = versions.len().saturating_sub(length);
+            versions.drain(..excess);
+        }
+        self.history.retain(|_, versions| versions.is_empty() == false);
+    }
+
+    /// How many former versions of every item are kept, see [`Self::set_history_length`]
+    pub fn history_length(&self) -> usize {
+        self.history_length
+    }
+
+    /// The former versions of an item, oldest first
+    pub fn item_history(&self, url: &Url) -> Vec<Arc<Item>> {
+        self.history.get(url).map(|versions| versions.iter().cloned().collect()).unwrap_or_default()
+    }
+
+    /// Replace an item with one of its former versions (`index` being its position in [`Self::item_history`]).
+    ///
+    /// This is a local change like any other: it will be pushed by the next sync, and the version it replaces is kept in the history.
+    pub fn restore_item_version(&mut self, url: &Url, index: usize) -> Result<SyncStatus, Box<dyn Error>> {
+        let current = Arc::clone(self.items.get(url).ok_or_else(|| format!("Item {} is absent from this calendar", url))?);
+        let mut restored = self.history.get(url)
+            .and_then(|versions| versions.get(index))
+            .map(|version| Item::clone(version))
+            .ok_or_else(|| format!("Item {} has no version #{}", url, index))?;
+        if let (Item::Task(restored), Item::Task(current)) = (&mut restored, &*current) {
+            restored.restore_over(current);
+        }
+        let new_status = match current.sync_status().clone() {
+            SyncStatus::NotSynced => SyncStatus::NotSynced,
+            SyncStatus::Synced(vt) | SyncStatus::LocallyModified(vt) | SyncStatus::LocallyDeleted(vt) => SyncStatus::LocallyModified(vt),
+        };
+        restored.set_sync_status(new_status);
+        self.update_item_sync(restored)
+    }
+
     /// Add or update an item, but force a "synced" SyncStatus. This is the normal behaviour that would happen on a server
-    #[cfg(feature = "local_calendar_mocks_remote_calendars")]
     fn add_or_update_item_force_synced(&mut self, mut item: Item) -> Result<SyncStatus, Box<dyn Error>> {
-        log::debug!("Adding or updating an item, but forces a synced SyncStatus");
+        log::debug!(target: crate::logging::CACHE, "Adding or updating an item, but forces a synced SyncStatus");
         match item.sync_status() {
             SyncStatus::Synced(_) => (),
             _ => item.set_sync_status(SyncStatus::random_synced()),
         };
         let ss_clone = item.sync_status().clone();
-        self.items.insert(item.url().clone(), item);
+        self.invalidate_indexes();
+        self.items.insert(item.url().clone(), Arc::new(item));
         Ok(ss_clone)
     }
 
     /// Some kind of equality check
-    #[cfg(any(test, feature = "integration_tests"))]
+    #[cfg(any(test, feature = "testing"))]
     pub async fn has_same_observable_content_as(&self, other: &CachedCalendar) -> Result<bool, Box<dyn Error>> {
         if self.name != other.name
         || self.url != other.url
         || self.supported_components != other.supported_components
         || self.color != other.color
         {
-            log::debug!("Calendar properties mismatch");
+            log::debug!(target: crate::logging::CACHE, "Calendar properties mismatch");
             return Ok(false);
         }
 
@@ -100,7 +468,7 @@ impl CachedCalendar {
         let items_r = other.get_items().await?;
 
         if crate::utils::keys_are_the_same(&items_l, &items_r) == false {
-            log::debug!("Different keys for items");
+            log::debug!(target: crate::logging::CACHE, "Different keys for items");
             return Ok(false);
         }
         for (url_l, item_l) in items_l {
@@ -109,9 +477,9 @@ impl CachedCalendar {
                 None => return Err("should not happen, we've just tested keys are the same".into()),
             };
             if item_l.has_same_observable_content_as(&item_r) == false {
-                log::debug!("Different items for URL {}:", url_l);
-                log::debug!("{:#?}", item_l);
-                log::debug!("{:#?}", item_r);
+                log::debug!(target: crate::logging::CACHE, "Different items for URL {}:", url_l);
+                log::debug!(target: crate::logging::CACHE, "{:#?}", item_l);
+                log::debug!(target: crate::logging::CACHE, "{:#?}", item_r);
                 return Ok(false);
             }
         }
@@ -130,27 +498,69 @@ impl CachedCalendar {
     /// The non-async version of [`Self::get_items`]
     pub fn get_items_sync(&self) -> Result<HashMap<Url, &Item>, Box<dyn Error>> {
         Ok(self.items.iter()
-            .map(|(url, item)| (url.clone(), item))
+            .map(|(url, item)| (url.clone(), item.as_ref()))
+            .collect()
+        )
+    }
+
+    /// The non-async version of [`Self::get_items_filtered`]
+    pub fn get_items_filtered_sync(&self, filter: &SearchFilter) -> Result<HashMap<Url, &Item>, Box<dyn Error>> {
+        Ok(self.items.iter()
+            .filter(|(_, item)| filter.matches(item))
+            .map(|(url, item)| (url.clone(), item.as_ref()))
             .collect()
         )
     }
 
     /// The non-async version of [`Self::get_items_mut`]
+    ///
+    /// Every item is considered changed: items that are shared (see [`Self::get_item_arc`]) are copied, and the observers are notified of the changes made through the returned references on the next change of this calendar, or on [`Self::notify_pending_changes`]. \
+    /// [`Self::iter_items_mut_sync`] only does so for the items that are actually changed, and [`Self::get_item_by_url_mut_sync`] is better suited to change a single item.
     pub fn get_items_mut_sync(&mut self) -> Result<HashMap<Url, &mut Item>, Box<dyn Error>> {
+        self.invalidate_indexes();
+        let urls: Vec<Url> = self.items.keys().cloned().collect();
+        for url in &urls {
+            self.add_pending_change(url);
+        }
         Ok(self.items.iter_mut()
-            .map(|(url, item)| (url.clone(), item))
+            .map(|(url, item)| (url.clone(), Arc::make_mut(item)))
             .collect()
         )
     }
 
+    /// Iterate over the items of this calendar, in order to change some of them.
+    ///
+    /// Items are only copied (in case they are shared, see [`Self::get_item_arc`]) and reported to the observers once they are changed through their [`ItemMut`]. \
+    /// The observers are notified of these changes on the next change of this calendar, or on [`Self::notify_pending_changes`]
+    pub fn iter_items_mut_sync(&mut self) -> impl Iterator<Item = ItemMut<'_>> {
+        self.invalidate_indexes();
+        let pending_changes = match self.observers.0.is_empty() {
+            true => None,
+            false => Some(&self.pending_changes),
+        };
+        self.items.iter_mut().map(move |(url, item)| ItemMut { url, item, pending_changes, changed: false })
+    }
+
     /// The non-async version of [`Self::get_item_by_url`]
     pub fn get_item_by_url_sync<'a>(&'a self, url: &Url) -> Option<&'a Item> {
-        self.items.get(url)
+        self.items.get(url).map(Arc::as_ref)
+    }
+
+    /// Returns a shared handle to an item.
+    ///
+    /// Unlike [`Self::get_item_by_url_sync`], the item can be kept after the lock of this calendar is released (e.g. by the view of an app), and this does not copy it. \
+    /// It is a snapshot: later changes of this calendar do not alter it.
+    pub fn get_item_arc(&self, url: &Url) -> Option<Arc<Item>> {
+        self.items.get(url).cloned()
     }
 
     /// The non-async version of [`Self::get_item_by_url_mut`]
+    ///
+    /// The observers are notified of the changes made through the returned reference on the next change of this calendar, or on [`Self::notify_pending_changes`]
     pub fn get_item_by_url_mut_sync<'a>(&'a mut self, url: &Url) -> Option<&'a mut Item> {
-        self.items.get_mut(url)
+        self.invalidate_indexes();
+        self.add_pending_change(url);
+        self.items.get_mut(url).map(Arc::make_mut)
     }
 
     /// The non-async version of [`Self::add_item`]
@@ -158,11 +568,40 @@ impl CachedCalendar {
         if self.items.contains_key(item.url()) {
             return Err(format!("Item {:?} cannot be added, it exists already", item.url()).into());
         }
-        #[cfg(not(feature = "local_calendar_mocks_remote_calendars"))]
-        return self.regular_add_or_update_item(item);
+        let url = item.url().clone();
+        let sync_status = self.add_item_maybe_remote(item)?;
+        self.notify_observers(None, &url);
+        Ok(sync_status)
+    }
 
-        #[cfg(feature = "local_calendar_mocks_remote_calendars")]
-        return self.add_item_maybe_mocked(item);
+    /// The non-async version of [`Self::add_items`].
+    ///
+    /// Observers are notified once (see [`ItemObserver::items_changed`]), and a calendar that behaves like a remote one is only saved once
+    pub fn add_items_sync(&mut self, items: Vec<Item>) -> Vec<Result<SyncStatus, Box<dyn Error>>> {
+        let mut changes = Vec::with_capacity(items.len());
+        let mut results: Vec<Result<SyncStatus, Box<dyn Error>>> = items.into_iter()
+            .map(|item| {
+                if self.items.contains_key(item.url()) {
+                    return Err(format!("Item {:?} cannot be added, it exists already", item.url()).into());
+                }
+                let url = item.url().clone();
+                let sync_status = self.add_item_maybe_remote_unsaved(item)?;
+                changes.push((None, url));
+                Ok(sync_status)
+            })
+            .collect();
+
+        if self.is_remote() && changes.is_empty() == false {
+            if let Err(err) = self.save_if_remote() {
+                // Just like for `add_item_sync`, items that could not be saved are reported as failed
+                let err = err.to_string();
+                for result in results.iter_mut().filter(|result| result.is_ok()) {
+                    *result = Err(format!("Unable to save the calendar: {}", err).into());
+                }
+            }
+        }
+        self.notify_observers_bulk(&changes);
+        results
     }
 
     /// The non-async version of [`Self::update_item`]
@@ -170,18 +609,77 @@ impl CachedCalendar {
         if self.items.contains_key(item.url()) == false {
             return Err(format!("Item {:?} cannot be updated, it does not already exist", item.url()).into());
         }
-        #[cfg(not(feature = "local_calendar_mocks_remote_calendars"))]
-        return self.regular_add_or_update_item(item);
-
-        #[cfg(feature = "local_calendar_mocks_remote_calendars")]
-        return self.update_item_maybe_mocked(item);
+        let url = item.url().clone();
+        let old = self.copy_for_observers(&url);
+        let sync_status = self.update_item_maybe_remote(item)?;
+        self.notify_observers(old.as_ref(), &url);
+        Ok(sync_status)
     }
 
     /// The non-async version of [`Self::mark_for_deletion`]
     pub fn mark_for_deletion_sync(&mut self, item_url: &Url) -> Result<(), Box<dyn Error>> {
+        let old = self.copy_for_observers(item_url);
+        let result = self.mark_for_deletion_unnotified(item_url);
+        if result.is_ok() {
+            self.notify_observers(old.as_ref(), item_url);
+        }
+        result
+    }
+
+    /// Mark several items for deletion at once (e.g. for a "select all → delete" action), then notify the observers once (see [`ItemObserver::items_changed`]).
+    ///
+    /// This fails (without changing anything) in case some of these items are not in this calendar
+    pub fn mark_for_deletion_bulk(&mut self, item_urls: &[Url]) -> Result<(), Box<dyn Error>> {
+        if let Some(missing) = item_urls.iter().find(|url| self.items.contains_key(*url) == false) {
+            return Err(format!("There is no item {} in calendar {}", missing, self.url).into());
+        }
+        let mut changes = Vec::with_capacity(item_urls.len());
+        for url in item_urls {
+            let old = self.copy_for_observers(url);
+            // This only fails for URLs that are given twice, and whose item has already been removed
+            if self.mark_for_deletion_unnotified(url).is_ok() {
+                changes.push((old, url.clone()));
+            }
+        }
+        self.notify_observers_bulk(&changes);
+        Ok(())
+    }
+
+    /// Set the completion status of several tasks at once (e.g. for a "select all → complete" action), then notify the observers once (see [`ItemObserver::items_changed`]).
+    ///
+    /// Tasks that already have this status are left unchanged. This fails (without changing anything) in case some of these URLs are not tasks of this calendar
+    pub fn set_completed_bulk(&mut self, task_urls: &[Url], status: CompletionStatus) -> Result<(), Box<dyn Error>> {
+        let mut tasks = Vec::with_capacity(task_urls.len());
+        for url in task_urls {
+            match self.items.get(url).map(Arc::as_ref) {
+                Some(Item::Task(task)) => tasks.push(task.clone()),
+                _ => return Err(format!("There is no task {} in calendar {}", url, self.url).into()),
+            }
+        }
+
+        let mut changes = Vec::with_capacity(tasks.len());
+        let mut result = Ok(());
+        for mut task in tasks.into_iter().filter(|task| task.completion_status() != &status) {
+            let url = task.url().clone();
+            let old = self.copy_for_observers(&url);
+            task.set_completion_status(status.clone());
+            if let Err(err) = self.update_item_maybe_remote(Item::Task(task)) {
+                result = Err(err);
+                break;
+            }
+            changes.push((old, url));
+        }
+        // Items that have been changed before an error are notified anyway
+        self.notify_observers_bulk(&changes);
+        result
+    }
+
+    fn mark_for_deletion_unnotified(&mut self, item_url: &Url) -> Result<(), Box<dyn Error>> {
+        self.invalidate_indexes();
         match self.items.get_mut(item_url) {
             None => Err("no item for this key".into()),
             Some(item) => {
+                let item = Arc::make_mut(item);
                 match item.sync_status() {
                     SyncStatus::Synced(prev_ss) => {
                         let prev_ss = prev_ss.clone();
@@ -197,7 +695,9 @@ impl CachedCalendar {
                     },
                     SyncStatus::NotSynced => {
                         // This was never synced to the server, we can safely delete it as soon as now
-                        self.items.remove(item_url);
+                        if let Some(removed) = self.items.remove(item_url) {
+                            self.move_to_trash(item_url, removed);
+                        }
                     },
                 };
                 Ok(())
@@ -205,14 +705,314 @@ impl CachedCalendar {
         }
     }
 
-    /// The non-async version of [`Self::immediately_delete_item`]
+    /// The non-async version of [`Self::immediately_delete_item`].
+    ///
+    /// Unless this calendar behaves like a calendar on a server, the item is moved to the trash, see [`Self::restore_from_trash`]
     pub fn immediately_delete_item_sync(&mut self, item_url: &Url) -> Result<(), Box<dyn Error>> {
+        self.invalidate_indexes();
         match self.items.remove(item_url) {
             None => Err(format!("Item {} is absent from this calendar", item_url).into()),
-            Some(_) => Ok(())
+            Some(old) => {
+                self.history.remove(item_url);
+                self.move_to_trash(item_url, Arc::clone(&old));
+                self.notify_observers(Some(&old), item_url);
+                Ok(())
+            },
+        }
+    }
+
+    /// Keep an item that has just been deleted in the trash, and forget the ones that have been there for too long
+    fn move_to_trash(&mut self, item_url: &Url, item: Arc<Item>) {
+        if self.is_remote() || self.trash_retention_days == 0 {
+            return;
+        }
+        self.trash.insert(item_url.clone(), TrashedItem { item, deleted_on: crate::deterministic::now() });
+        self.purge_trash();
+    }
+
+    /// How many days deleted items are kept in the trash (`0` to delete items for good at once). This is 30 days by default
+    pub fn set_trash_retention_days(&mut self, days: u32) {
+        self.trash_retention_days = days;
+        self.revision += 1;
+        self.purge_trash();
+    }
+
+    /// How many days deleted items are kept in the trash, see [`Self::set_trash_retention_days`]
+    pub fn trash_retention_days(&self) -> u32 {
+        self.trash_retention_days
+    }
+
+    /// The items that have been deleted from this calendar, and that can still be restored
+    pub fn trash(&self) -> impl Iterator<Item = &TrashedItem> + '_ {
+        self.trash.values()
+    }
+
+    /// Forget every item of the trash
+    pub fn empty_trash(&mut self) {
+        self.trash.clear();
+        self.revision += 1;
+    }
+
+    /// Put an item that has been deleted back into this calendar.
+    ///
+    /// It is considered a new item, that the next sync will add to the server again
+    pub fn restore_from_trash(&mut self, item_url: &Url) -> Result<SyncStatus, Box<dyn Error>> {
+        if self.items.contains_key(item_url) {
+            return Err(format!("Item {} cannot be restored, an item with the same URL exists already", item_url).into());
+        }
+        let trashed = self.trash.remove(item_url).ok_or_else(|| format!("Item {} is not in the trash", item_url))?;
+        let mut item = Item::clone(&trashed.item);
+        item.set_sync_status(SyncStatus::NotSynced);
+        self.add_item_sync(item)
+    }
+
+    /// Register an observer, that will be notified of every change of the items of this calendar
+    pub fn add_observer(&mut self, observer: Arc<dyn ItemObserver>) {
+        self.observers.0.push(observer);
+    }
+
+    /// Returns a handle to an item, to give its former state to the observers. This is `None` in case there are no observers, so that the item is not copied on the next write
+    fn copy_for_observers(&self, url: &Url) -> Option<Arc<Item>> {
+        if self.observers.0.is_empty() {
+            return None;
+        }
+        self.items.get(url).cloned()
+    }
+
+    /// Remember the current state of an item that is about to be handed out mutably, so that its observers can be notified later on
+    fn add_pending_change(&self, url: &Url) {
+        if self.observers.0.is_empty() {
+            return;
         }
+        if let Some(item) = self.items.get(url) {
+            self.pending_changes.add(url, item);
+        }
+    }
+
+    /// Notify the observers of the changes that have been made through the mutable references this calendar has handed out (see [`Self::get_item_by_url_mut_sync`]).
+    ///
+    /// Items whose content has not changed (e.g. only their sync status has) are not notified. \
+    /// This is done before any other change of this calendar is notified, and when the cache is saved, so that apps usually do not have to call this
+    pub fn notify_pending_changes(&self) {
+        let mut pending = std::mem::take(&mut *self.pending_changes.0.lock().unwrap());
+        pending.retain(|(old, url)| match (old, self.items.get(url)) {
+            (Some(old), Some(new)) => Arc::ptr_eq(old, new) == false && content_changed(old, new),
+            (None, None) => false,
+            _ => true,
+        });
+        self.send_to_observers(&pending);
+    }
+
+    fn notify_observers(&self, old: Option<&Arc<Item>>, url: &Url) {
+        self.notify_pending_changes();
+        let old = old.map(Arc::as_ref);
+        let new = self.items.get(url).map(Arc::as_ref);
+        for observer in &self.observers.0 {
+            observer.item_changed(&self.url, old, new);
+        }
+    }
+
+    /// Notify the observers of several changes at once. `changes` are the former states of the items (see [`Self::copy_for_observers`]) along with their URLs
+    fn notify_observers_bulk(&self, changes: &[(Option<Arc<Item>>, Url)]) {
+        self.notify_pending_changes();
+        self.send_to_observers(changes);
+    }
+
+    fn send_to_observers(&self, changes: &[(Option<Arc<Item>>, Url)]) {
+        if changes.is_empty() || self.observers.0.is_empty() {
+            return;
+        }
+        let changes: Vec<(Option<&Item>, Option<&Item>)> = changes.iter()
+            .map(|(old, url)| (old.as_deref(), self.items.get(url).map(Arc::as_ref)))
+            .collect();
+        for observer in &self.observers.0 {
+            observer.items_changed(&self.url, &changes);
+        }
+    }
+
+
+    /// Reset the data that is computed on demand from the items. This must be called whenever items are (or may be) modified
+    fn invalidate_indexes(&mut self) {
+        self.due_index.take();
+        self.counts.take();
+        self.revision += 1;
+    }
+
+    /// A number that changes whenever this calendar is (or may be) changed
+    pub(crate) fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Returns the number of items of this calendar, by status (e.g. to display badges in a UI).
+    ///
+    /// The counts are cached until the next change of this calendar, so this is cheap to call repeatedly
+    pub fn counts(&self) -> ItemCounts {
+        *self.counts.get_or_init(|| {
+            let mut counts = ItemCounts::default();
+            for item in self.items.values() {
+                counts.total += 1;
+                match item.sync_status() {
+                    SyncStatus::LocallyDeleted(_) => {
+                        counts.marked_for_deletion += 1;
+                        continue;
+                    },
+                    SyncStatus::LocallyModified(_) => counts.locally_modified += 1,
+                    SyncStatus::NotSynced | SyncStatus::Synced(_) => (),
+                }
+                if let Item::Task(task) = item.as_ref() {
+                    if task.completed() {
+                        counts.completed += 1;
+                    } else {
+                        counts.pending += 1;
+                    }
+                }
+            }
+            counts
+        })
+    }
+
+    /// Returns the UIDs that are shared by several items of this calendar (ignoring the items marked for deletion), along with the sorted URLs of these items.
+    ///
+    /// CalDAV forbids this, but some buggy clients create such items anyway. Syncs cannot tell which of them is the right one, so apps should rather ask the user which ones to keep
+    pub fn duplicate_uids(&self) -> BTreeMap<String, Vec<Url>> {
+        let mut by_uid: BTreeMap<String, Vec<Url>> = BTreeMap::new();
+        for (url, item) in &self.items {
+            if matches!(item.sync_status(), SyncStatus::LocallyDeleted(_)) == false {
+                by_uid.entry(item.uid().to_string()).or_default().push(url.clone());
+            }
+        }
+        by_uid.retain(|_, urls| urls.len() > 1);
+        for urls in by_uid.values_mut() {
+            urls.sort();
+        }
+        by_uid
+    }
+
+    /// Merge two tasks that share a UID (see [`Self::duplicate_uids`]): the task at `duplicate` is merged into the task at `keep` (see [`Task::merge`]), then marked for deletion.
+    ///
+    /// This returns the new sync status of the kept task
+    pub fn merge_duplicates(&mut self, keep: &Url, duplicate: &Url) -> Result<SyncStatus, Box<dyn Error>> {
+        if keep == duplicate {
+            return Err(format!("Cannot merge item {} with itself", keep).into());
+        }
+        let (mut kept, other) = match (self.items.get(keep).map(Arc::as_ref), self.items.get(duplicate).map(Arc::as_ref)) {
+            (Some(Item::Task(kept)), Some(Item::Task(other))) => (kept.clone(), other.clone()),
+            _ => return Err(format!("{} and {} are not both tasks of calendar {}", keep, duplicate, self.url).into()),
+        };
+        if kept.uid() != other.uid() {
+            return Err(format!("{} and {} do not share a UID", keep, duplicate).into());
+        }
+
+        kept.merge(&other);
+        let sync_status = self.update_item_sync(Item::Task(kept))?;
+        self.mark_for_deletion_sync(duplicate)?;
+        Ok(sync_status)
+    }
+
+    /// Returns the tasks that are not completed (nor marked for deletion), and whose due date is in `[start, end)` (or before `end` in case `start` is `None`), sorted by due date
+    ///
+    /// Floating due dates are considered in UTC. See [`CachedCalendar::get_tasks_due_between_in`] to resolve them in the time zone of the user.
+    pub fn get_tasks_due_between(&self, start: Option<DateTime<Utc>>, end: DateTime<Utc>) -> Vec<&Task> {
+        self.get_tasks_due_between_in(start, end, &Tz::UTC)
+    }
+
+    /// Returns the tasks that are not completed (nor marked for deletion), and whose due date is in `[start, end)` (or before `end` in case `start` is `None`), sorted by due date.
+    ///
+    /// Floating due dates (and due days) are considered in `local_zone` (see [`IcalDateTime::to_utc_in`](crate::utils::time_zone::IcalDateTime::to_utc_in))
+    pub fn get_tasks_due_between_in(&self, start: Option<DateTime<Utc>>, end: DateTime<Utc>, local_zone: &Tz) -> Vec<&Task> {
+        let due_index = self.due_index.get_or_init(|| {
+            let mut index: BTreeMap<DateTime<Utc>, Vec<Url>> = BTreeMap::new();
+            for item in self.items.values() {
+                if let Item::Task(task) = item.as_ref() {
+                    if let Some(due) = task.due() {
+                        index.entry(due).or_default().push(task.url().clone());
+                    }
+                }
+            }
+            index
+        });
+
+        // The index is built with floating dates in UTC. In any other time zone, they are at most 14 hours away
+        let margin = Duration::hours(14);
+        let range = match start {
+            Some(start) if start >= end => return Vec::new(),
+            Some(start) => due_index.range(start - margin..end + margin),
+            None => due_index.range(..end + margin),
+        };
+        let mut tasks: Vec<(DateTime<Utc>, &Task)> = range
+            .flat_map(|(_, urls)| urls)
+            .filter_map(|url| match self.items.get(url).map(Arc::as_ref) {
+                Some(Item::Task(task)) => task.due_in(local_zone).map(|due| (due, task)),
+                _ => None,
+            })
+            .filter(|(due, _)| start.map(|start| &start <= due).unwrap_or(true) && due < &end)
+            .filter(|(_, task)| task.completed() == false)
+            .filter(|(_, task)| matches!(task.sync_status(), SyncStatus::LocallyDeleted(_)) == false)
+            .collect();
+        // This sort is stable, so that tasks that are due at the same time keep the order of the index
+        tasks.sort_by_key(|(due, _)| *due);
+        tasks.into_iter().map(|(_, task)| task).collect()
+    }
+
+    /// Returns the tasks that are not completed, and whose due date has passed (according to [`crate::deterministic::now`]), sorted by due date
+    pub fn get_overdue_tasks(&self) -> Vec<&Task> {
+        self.get_tasks_due_between(None, crate::deterministic::now())
     }
 
+    /// Returns the tasks that are not completed, and that are due today (in local time), sorted by due date
+    pub fn get_tasks_due_today(&self) -> Vec<&Task> {
+        let (start, end) = local_days_range(0, 1);
+        self.get_tasks_due_between(Some(start), end)
+    }
+
+    /// Returns the tasks that are not completed, and that are due this week (from Monday to Sunday, in local time), sorted by due date
+    pub fn get_tasks_due_this_week(&self) -> Vec<&Task> {
+        let days_since_monday = local_today().weekday().num_days_from_monday() as i64;
+        let (start, end) = local_days_range(-days_since_monday, 7 - days_since_monday);
+        self.get_tasks_due_between(Some(start), end)
+    }
+
+    /// Returns the tasks that have been completed since a given date (and are not marked for deletion), most recent first
+    pub fn get_tasks_completed_since(&self, since: DateTime<Utc>) -> Vec<&Task> {
+        let mut tasks: Vec<(&DateTime<Utc>, &Task)> = self.items.values()
+            .filter_map(|item| match item.as_ref() {
+                Item::Task(task) => task.completion_status().completion_date().map(|date| (date, task)),
+                _ => None,
+            })
+            .filter(|(date, _)| **date >= since)
+            .filter(|(_, task)| matches!(task.sync_status(), SyncStatus::LocallyDeleted(_)) == false)
+            .collect();
+        tasks.sort_by(|(date_a, _), (date_b, _)| date_b.cmp(date_a));
+        tasks.into_iter().map(|(_, task)| task).collect()
+    }
+}
+
+/// Whether the content of `new` differs from the one of `old`, a former state of the same item.
+///
+/// Every change of the content of a task increments its `SEQUENCE` and updates its `LAST-MODIFIED` date (unlike a change of its sync status), so that comparing these is enough
+fn content_changed(old: &Item, new: &Item) -> bool {
+    match (old, new) {
+        (Item::Task(old), Item::Task(new)) => (old.last_modified(), old.sequence()) != (new.last_modified(), new.sequence()),
+        _ => true,
+    }
+}
+
+/// Today in local time, according to [`crate::deterministic::now`]
+fn local_today() -> Date<Local> {
+    crate::deterministic::now().with_timezone(&Local).date()
+}
+
+/// Returns the UTC instants of the local midnights that are `from` days and `to` days away from today
+fn local_days_range(from: i64, to: i64) -> (DateTime<Utc>, DateTime<Utc>) {
+    let today = local_today().naive_local();
+    let midnight = |days: i64| {
+        let naive = (today + Duration::days(days)).and_hms(0, 0, 0);
+        // Midnight may not exist (or be ambiguous) because of DST changes
+        Local.from_local_datetime(&naive).earliest()
+            .map(|local| local.with_timezone(&Utc))
+            .unwrap_or_else(|| DateTime::from_utc(naive, Utc))
+    };
+    (midnight(from), midnight(to))
 }
 
 
@@ -234,11 +1034,28 @@ impl BaseCalendar for CachedCalendar {
         self.color.as_ref()
     }
 
+    fn kind(&self) -> CalendarKind {
+        self.kind
+    }
+
     async fn add_item(&mut self, item: Item) -> Result<SyncStatus, Box<dyn Error>> {
+        #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+        simulate_latency(&self.mock_behaviour, MockOperation::AddItem).await;
+
         self.add_item_sync(item)
     }
 
+    async fn add_items(&mut self, items: Vec<Item>) -> Vec<Result<SyncStatus, Box<dyn Error>>> {
+        #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+        simulate_latency(&self.mock_behaviour, MockOperation::AddItem).await;
+
+        self.add_items_sync(items)
+    }
+
     async fn update_item(&mut self, item: Item) -> Result<SyncStatus, Box<dyn Error>> {
+        #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+        simulate_latency(&self.mock_behaviour, MockOperation::UpdateItem).await;
+
         self.update_item_sync(item)
     }
 }
@@ -248,9 +1065,28 @@ impl CompleteCalendar for CachedCalendar {
     fn new(name: String, url: Url, supported_components: SupportedComponents, color: Option<Color>) -> Self {
         Self {
             name, url, supported_components, color,
+            kind: CalendarKind::Owned,
+            sync_status: CalendarSyncStatus::NotSynced,
             #[cfg(feature = "local_calendar_mocks_remote_calendars")]
             mock_behaviour: None,
-            items: HashMap::new(),
+            acts_as_remote: false,
+            sync_journal: None,
+            last_remote_version: None,
+            retry_not_before: HashMap::new(),
+            rollback_point: None,
+            conflict_log: Vec::new(),
+            history_length: 0,
+            history: HashMap::new(),
+            trash_retention_days: DEFAULT_TRASH_RETENTION_DAYS,
+            trash: BTreeMap::new(),
+            remote_backing_file: None,
+            checkpoint_file: None,
+            due_index: OnceCell::new(),
+            counts: OnceCell::new(),
+            observers: ItemObservers::default(),
+            pending_changes: PendingChanges::default(),
+            revision: 0,
+            items: BTreeMap::new(),
         }
     }
 
@@ -266,6 +1102,18 @@ impl CompleteCalendar for CachedCalendar {
         self.get_items_mut_sync()
     }
 
+    fn iter_items(&self) -> Box<dyn Iterator<Item = &Item> + Send + '_> {
+        Box::new(self.items.values().map(Arc::as_ref))
+    }
+
+    fn sync_index(&self) -> Box<dyn Iterator<Item = (&Url, &SyncStatus)> + Send + '_> {
+        Box::new(self.items.iter().map(|(url, item)| (url, item.sync_status())))
+    }
+
+    async fn get_items_filtered(&self, filter: &SearchFilter) -> Result<HashMap<Url, &Item>, Box<dyn Error>> {
+        self.get_items_filtered_sync(filter)
+    }
+
     async fn get_item_by_url<'a>(&'a self, url: &Url) -> Option<&'a Item> {
         self.get_item_by_url_sync(url)
     }
@@ -281,25 +1129,134 @@ impl CompleteCalendar for CachedCalendar {
     async fn immediately_delete_item(&mut self, item_url: &Url) -> Result<(), Box<dyn Error>> {
         self.immediately_delete_item_sync(item_url)
     }
+
+    fn set_kind(&mut self, kind: CalendarKind) {
+        self.kind = kind;
+        self.revision += 1;
+    }
+
+    fn sync_status(&self) -> CalendarSyncStatus {
+        self.sync_status
+    }
+
+    fn set_sync_status(&mut self, status: CalendarSyncStatus) {
+        self.sync_status = status;
+        self.revision += 1;
+    }
+
+    fn sync_journal(&self) -> Option<&SyncJournal> {
+        self.sync_journal.as_ref()
+    }
+
+    fn set_sync_journal(&mut self, journal: Option<SyncJournal>) -> Result<(), Box<dyn Error>> {
+        self.sync_journal = journal;
+        self.revision += 1;
+        if let Some(path) = &self.checkpoint_file {
+            if let Some(folder) = path.parent() {
+                std::fs::create_dir_all(folder)?;
+            }
+            crate::cache::write_json_atomically(path, self)?;
+        }
+        Ok(())
+    }
+
+    fn last_remote_version(&self) -> Option<&VersionTag> {
+        self.last_remote_version.as_ref()
+    }
+
+    fn set_last_remote_version(&mut self, version: Option<VersionTag>) {
+        self.last_remote_version = version;
+        self.revision += 1;
+    }
+
+    fn retry_not_before(&self, url: &Url) -> Option<DateTime<Utc>> {
+        self.retry_not_before.get(url).copied()
+    }
+
+    fn set_retry_not_before(&mut self, url: &Url, not_before: Option<DateTime<Utc>>) {
+        self.revision += 1;
+        match not_before {
+            None => self.retry_not_before.remove(url),
+            Some(date) => self.retry_not_before.insert(url.clone(), date),
+        };
+    }
+
+    fn rollback_point(&self) -> Option<&SyncRollback> {
+        self.rollback_point.as_ref()
+    }
+
+    fn set_rollback_point(&mut self, rollback: Option<SyncRollback>) {
+        self.rollback_point = rollback;
+        self.revision += 1;
+    }
+
+    fn conflict_log(&self) -> &[ConflictRecord] {
+        &self.conflict_log
+    }
+
+    fn record_conflicts(&mut self, conflicts: Vec<ConflictRecord>) {
+        self.conflict_log.extend(conflicts);
+        self.revision += 1;
+        let excess = self.conflict_log.len().saturating_sub(MAX_CONFLICT_LOG_LEN);
+        self.conflict_log.drain(..excess);
+    }
+
+    fn purge_trash(&mut self) {
+        let oldest_kept = crate::deterministic::now() - Duration::days(self.trash_retention_days as i64);
+        self.revision += 1;
+        self.trash.retain(|_, trashed| trashed.deleted_on >= oldest_kept);
+    }
 }
 
 
 
-// This class can be used to mock a remote calendar for integration tests
+// This class can be used as a remote calendar, either to mock a server for integration tests, or to sync with another folder (see `Cache::new_as_remote`)
 
-#[cfg(feature = "local_calendar_mocks_remote_calendars")]
 use crate::{item::VersionTag,
             traits::DavCalendar,
             resource::Resource};
 
-#[cfg(feature = "local_calendar_mocks_remote_calendars")]
 #[async_trait]
 impl DavCalendar for CachedCalendar {
     fn new(name: String, resource: Resource, supported_components: SupportedComponents, color: Option<Color>) -> Self {
         crate::traits::CompleteCalendar::new(name, resource.url().clone(), supported_components, color)
     }
 
+    /// Copies only carry the items, and what makes this calendar behave like a server (they do not have its settings, its observers, nor its backing file)
+    fn detached(&self) -> Self {
+        let mut copy: Self = crate::traits::CompleteCalendar::new(self.name.clone(), self.url.clone(), self.supported_components, self.color.clone());
+        #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+        { copy.mock_behaviour = self.mock_behaviour.clone(); }
+        copy.acts_as_remote = self.acts_as_remote;
+        copy.items = self.items.clone();
+        copy
+    }
+
+    fn record_changes(&mut self, detached: Self, urls: &[Url]) -> Result<(), Box<dyn Error>> {
+        self.invalidate_indexes();
+        let mut changes = Vec::new();
+        for url in urls {
+            let new = detached.items.get(url);
+            let old = match new {
+                Some(new) => self.items.insert(url.clone(), Arc::clone(new)),
+                None => self.items.remove(url),
+            };
+            let unchanged = match (&old, new) {
+                (Some(old), Some(new)) => Arc::ptr_eq(old, new),
+                (None, None) => true,
+                _ => false,
+            };
+            if unchanged == false {
+                changes.push((old, url.clone()));
+            }
+        }
+        self.notify_observers_bulk(&changes);
+        self.save_if_remote()
+    }
+
     async fn get_item_version_tags(&self) -> Result<HashMap<Url, VersionTag>, Box<dyn Error>> {
+        #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+        simulate_latency(&self.mock_behaviour, MockOperation::GetItemVersionTags).await;
         #[cfg(feature = "local_calendar_mocks_remote_calendars")]
         self.mock_behaviour.as_ref().map_or(Ok(()), |b| b.lock().unwrap().can_get_item_version_tags())?;
 
@@ -308,23 +1265,62 @@ impl DavCalendar for CachedCalendar {
         let mut result = HashMap::new();
 
         for (url, item) in self.items.iter() {
+            // Caches used as remote sources may be folders that are shared with other apps, and their content cannot be trusted
             let vt = match item.sync_status() {
                 SyncStatus::Synced(vt) => vt.clone(),
-                _ => {
-                    panic!("Mock calendars must contain only SyncStatus::Synced. Got {:?}", item);
-                }
+                _ => return Err(format!("Remote calendars must contain only SyncStatus::Synced. Got {:?} for {}", item.sync_status(), url).into()),
             };
             result.insert(url.clone(), vt);
         }
 
+        #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+        if self.mock_behaviour.as_ref().map_or(false, |b| b.lock().unwrap().should_change_version_tags()) {
+            for vt in result.values_mut() {
+                *vt = VersionTag::from(format!("{}-{}", vt.as_str(), VersionTag::random().as_str()));
+            }
+        }
+
         Ok(result)
     }
 
+    async fn get_item_version_tags_modified_since(&self, since: &DateTime<Utc>) -> Result<HashMap<Url, VersionTag>, Box<dyn Error>> {
+        let mut result = DavCalendar::get_item_version_tags(self).await?;
+        result.retain(|url, _| self.items.get(url).filter(|item| item.last_modified() >= since).is_some());
+        Ok(result)
+    }
+
+    /// The version of this calendar is a hash of the version tags of its items
+    async fn get_current_version(&self) -> Result<Option<VersionTag>, Box<dyn Error>> {
+        use std::hash::{Hash, Hasher};
+
+        let mut tags: Vec<(&Url, &VersionTag)> = self.items.iter()
+            .filter_map(|(url, item)| match item.sync_status() {
+                SyncStatus::Synced(tag) => Some((url, tag)),
+                _ => None,
+            })
+            .collect();
+        tags.sort_unstable_by_key(|(url, _)| *url);
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        for (url, tag) in tags {
+            url.as_str().hash(&mut hasher);
+            tag.as_str().hash(&mut hasher);
+        }
+        Ok(Some(VersionTag::from(format!("{:016x}", hasher.finish()))))
+    }
+
     async fn get_item_by_url(&self, url: &Url) -> Result<Option<Item>, Box<dyn Error>> {
+        #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+        simulate_latency(&self.mock_behaviour, MockOperation::GetItemByUrl).await;
         #[cfg(feature = "local_calendar_mocks_remote_calendars")]
         self.mock_behaviour.as_ref().map_or(Ok(()), |b| b.lock().unwrap().can_get_item_by_url())?;
+        #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+        if self.items.contains_key(url) && self.mock_behaviour.as_ref().map_or(false, |b| b.lock().unwrap().should_return_unparsable_item()) {
+            // Let the actual parser fail, so that the error is the one a real server would trigger
+            let corrupted = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nBEGIN:VTODO\r\nSUMMARY:Truncated";
+            return crate::ical::parse(corrupted, url.clone(), SyncStatus::random_synced()).map(Some);
+        }
 
-        Ok(self.items.get(url).cloned())
+        Ok(self.items.get(url).map(|item| Item::clone(item)))
     }
 
     async fn get_items_by_url(&self, urls: &[Url]) -> Result<Vec<Option<Item>>, Box<dyn Error>> {
@@ -332,13 +1328,229 @@ impl DavCalendar for CachedCalendar {
         for url in urls {
             v.push(DavCalendar::get_item_by_url(self, url).await?);
         }
+
+        // Servers silently omit the items they do not send
+        #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+        if self.mock_behaviour.as_ref().map_or(false, |b| b.lock().unwrap().should_omit_from_multiget()) {
+            v.pop();
+        }
         Ok(v)
     }
 
     async fn delete_item(&mut self, item_url: &Url) -> Result<(), Box<dyn Error>> {
+        #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+        simulate_latency(&self.mock_behaviour, MockOperation::DeleteItem).await;
         #[cfg(feature = "local_calendar_mocks_remote_calendars")]
         self.mock_behaviour.as_ref().map_or(Ok(()), |b| b.lock().unwrap().can_delete_item())?;
 
-        self.immediately_delete_item(item_url).await
+        self.immediately_delete_item(item_url).await?;
+        self.save_if_remote()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_utils::{BulkRecorder, Recorder, TaskBuilder};
+
+    /// A calendar that contains the tasks `tasks` builds for its URL, along with the URLs of these tasks
+    fn calendar_with_tasks<F>(name: &str, tasks: F) -> (CachedCalendar, Vec<Url>)
+    where
+        F: FnOnce(&Url) -> Vec<TaskBuilder>,
+    {
+        let cal_url = Url::parse(&format!("https://caldav.com/{}", name)).unwrap();
+        let mut cal: CachedCalendar = CompleteCalendar::new(name.to_string(), cal_url.clone(), SupportedComponents::TODO, None);
+        let urls = tasks(&cal_url).into_iter()
+            .map(|task| {
+                let item = task.item();
+                let url = item.url().clone();
+                cal.add_item_sync(item).unwrap();
+                url
+            })
+            .collect();
+        (cal, urls)
+    }
+
+    #[test]
+    fn test_counts() {
+        let (mut cal, urls) = calendar_with_tasks("counted", |cal_url| vec![
+            TaskBuilder::new("Pending", cal_url).synced(),
+            TaskBuilder::new("Done", cal_url).completed(true).synced(),
+            TaskBuilder::new("To delete", cal_url).synced(),
+        ]);
+        assert_eq!(cal.counts(), ItemCounts { total: 3, pending: 2, completed: 1, locally_modified: 0, marked_for_deletion: 0 });
+
+        cal.mark_for_deletion_sync(&urls[2]).unwrap();
+        cal.get_item_by_url_mut_sync(&urls[0]).unwrap().unwrap_task_mut().set_name(String::from("Renamed"));
+        assert_eq!(cal.counts(), ItemCounts { total: 3, pending: 1, completed: 1, locally_modified: 1, marked_for_deletion: 1 });
+
+        cal.immediately_delete_item_sync(&urls[0]).unwrap();
+        assert_eq!(cal.counts(), ItemCounts { total: 2, pending: 0, completed: 1, locally_modified: 0, marked_for_deletion: 1 });
+    }
+
+    #[test]
+    fn test_item_history() {
+        let (mut cal, urls) = calendar_with_tasks("history", |cal_url| vec![TaskBuilder::new("Version 1", cal_url)]);
+        let task_url = &urls[0];
+        cal.set_history_length(2);
+        assert!(cal.item_history(task_url).is_empty());
+
+        for name in ["Version 2", "Version 3", "Version 4"] {
+            let mut item = cal.get_item_by_url_sync(task_url).unwrap().clone();
+            item.unwrap_task_mut().set_name(String::from(name));
+            cal.update_item_sync(item).unwrap();
+        }
+        let names = |cal: &CachedCalendar| cal.item_history(task_url).iter().map(|item| item.name().to_string()).collect::<Vec<_>>();
+        assert_eq!(names(&cal), vec!["Version 2", "Version 3"]);
+
+        // Restoring a version keeps the current one in the history
+        let current = cal.get_item_by_url_sync(task_url).unwrap().unwrap_task().clone();
+        cal.restore_item_version(task_url, 0).unwrap();
+        let restored = cal.get_item_by_url_sync(task_url).unwrap().unwrap_task().clone();
+        assert_eq!(restored.name(), "Version 2");
+        // It is a newer version than the current one for other clients as well
+        assert_eq!(restored.sequence(), current.sequence().map(|sequence| sequence + 1));
+        assert!(restored.last_modified() >= current.last_modified());
+        assert_eq!(names(&cal), vec!["Version 3", "Version 4"]);
+        assert!(cal.restore_item_version(task_url, 2).is_err());
+
+        // Deleted items have no history
+        cal.immediately_delete_item_sync(task_url).unwrap();
+        assert!(cal.item_history(task_url).is_empty());
+    }
+
+    #[test]
+    fn test_trash() {
+        let (mut cal, urls) = calendar_with_tasks("trash", |cal_url| vec![
+            TaskBuilder::new("Synced", cal_url).synced(),
+            TaskBuilder::new("Not synced", cal_url),
+        ]);
+        let (synced_url, unsynced_url) = (&urls[0], &urls[1]);
+        assert_eq!(cal.trash_retention_days(), 30);
+
+        // Items that are deleted (either by a sync, or because they were never synced) go to the trash
+        cal.immediately_delete_item_sync(synced_url).unwrap();
+        cal.mark_for_deletion_sync(unsynced_url).unwrap();
+        assert!(cal.get_item_by_url_sync(synced_url).is_none());
+        let mut trashed: Vec<String> = cal.trash().map(|trashed| trashed.item().name().to_string()).collect();
+        trashed.sort();
+        assert_eq!(trashed, vec!["Not synced", "Synced"]);
+
+        // Restored items are new items for the next sync
+        assert_eq!(cal.restore_from_trash(synced_url).unwrap(), SyncStatus::NotSynced);
+        assert_eq!(cal.get_item_by_url_sync(synced_url).unwrap().name(), "Synced");
+        assert!(cal.restore_from_trash(synced_url).is_err());
+
+        // Without a retention period, the trash is emptied, and deleted items do not go there anymore
+        cal.set_trash_retention_days(0);
+        assert_eq!(cal.trash().count(), 0);
+        cal.immediately_delete_item_sync(synced_url).unwrap();
+        assert_eq!(cal.trash().count(), 0);
+
+        // Remote calendars do not have a trash
+        let (mut remote_cal, urls) = calendar_with_tasks("remote-trash", |cal_url| vec![TaskBuilder::new("Remote", cal_url)]);
+        remote_cal.set_acts_as_remote(true);
+        remote_cal.immediately_delete_item_sync(&urls[0]).unwrap();
+        assert_eq!(remote_cal.trash().count(), 0);
+    }
+
+    #[test]
+    fn test_merge_duplicates() {
+        let (mut cal, urls) = calendar_with_tasks("duplicates", |cal_url| vec![
+            TaskBuilder::new("first", cal_url).uid("shared-uid").synced(),
+            TaskBuilder::new("unique", cal_url).uid("unique-uid").synced(),
+            TaskBuilder::new("second", cal_url).uid("shared-uid").synced(),
+        ]);
+        let (first_url, unique_url, second_url) = (&urls[0], &urls[1], &urls[2]);
+        let mut duplicates = vec![first_url.clone(), second_url.clone()];
+        duplicates.sort();
+        assert_eq!(cal.duplicate_uids(), BTreeMap::from([(String::from("shared-uid"), duplicates)]));
+
+        // Once merged, the duplicate is about to be deleted, and is not a duplicate anymore
+        assert!(cal.merge_duplicates(first_url, first_url).is_err());
+        assert!(cal.merge_duplicates(first_url, unique_url).is_err());
+        assert!(matches!(cal.merge_duplicates(second_url, first_url).unwrap(), SyncStatus::LocallyModified(_)));
+        assert!(cal.duplicate_uids().is_empty());
+        assert!(matches!(cal.get_item_by_url_sync(first_url).unwrap().sync_status(), SyncStatus::LocallyDeleted(_)));
+    }
+
+    #[test]
+    fn test_bulk_operations() {
+        let (mut cal, urls) = calendar_with_tasks("bulk", |cal_url| (0..3)
+            .map(|i| TaskBuilder::new(&format!("Task #{}", i), cal_url).synced())
+            .collect());
+        let recorder = Arc::new(BulkRecorder::default());
+        cal.add_observer(recorder.clone());
+
+        // Nothing is changed in case an URL is wrong
+        let missing = cal.url().join("missing.ics").unwrap();
+        let completed = CompletionStatus::Completed(None);
+        assert!(cal.set_completed_bulk(&[urls[0].clone(), missing.clone()], completed.clone()).is_err());
+        assert!(cal.mark_for_deletion_bulk(&[urls[0].clone(), missing]).is_err());
+        assert_eq!(cal.counts().completed, 0);
+
+        cal.set_completed_bulk(&urls[0..2], completed.clone()).unwrap();
+        assert_eq!(cal.counts().completed, 2);
+        assert_eq!(cal.counts().locally_modified, 2);
+        // Tasks that are completed already are not changed again
+        cal.set_completed_bulk(&urls, completed).unwrap();
+        assert_eq!(cal.counts().completed, 3);
+
+        cal.mark_for_deletion_bulk(&urls).unwrap();
+        assert_eq!(cal.counts().marked_for_deletion, 3);
+        assert_eq!(*recorder.notifications.lock().unwrap(), vec![2, 1, 3]);
+    }
+
+    #[test]
+    fn test_observe_mutable_references() {
+        let (mut cal, urls) = calendar_with_tasks("mutable", |cal_url| vec![TaskBuilder::new("Before", cal_url)]);
+        let renamed_url = &urls[0];
+        let recorder = Arc::new(Recorder::default());
+        cal.add_observer(recorder.clone());
+
+        if let Some(Item::Task(task)) = cal.get_item_by_url_mut_sync(renamed_url) {
+            task.set_name(String::from("Between"));
+        }
+        if let Some(Item::Task(task)) = cal.get_item_by_url_mut_sync(renamed_url) {
+            task.set_name(String::from("After"));
+        }
+        // Observers are told about the changes made through mutable references once the next change happens
+        assert!(recorder.changes.lock().unwrap().is_empty());
+        let added = TaskBuilder::new("Added", cal.url()).item();
+        cal.add_item_sync(added).unwrap();
+        assert_eq!(*recorder.changes.lock().unwrap(), vec![
+            (Some(String::from("Before")), Some(String::from("After"))),
+            (None, Some(String::from("Added"))),
+        ]);
+
+        // Items that have not changed, or whose sync status only has, are not notified
+        recorder.changes.lock().unwrap().clear();
+        cal.get_item_by_url_mut_sync(renamed_url).unwrap().set_sync_status(SyncStatus::random_synced());
+        cal.get_item_by_url_mut_sync(renamed_url);
+        cal.notify_pending_changes();
+        assert!(recorder.changes.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_lazy_mutable_items() {
+        let (mut cal, urls) = calendar_with_tasks("lazy", |cal_url| vec![
+            TaskBuilder::new("Untouched", cal_url),
+            TaskBuilder::new("Renamed", cal_url),
+        ]);
+        let recorder = Arc::new(Recorder::default());
+        cal.add_observer(recorder.clone());
+        let snapshot = cal.get_item_arc(&urls[0]).unwrap();
+
+        // Only the items that are actually changed are copied, and notified
+        for mut item in cal.iter_items_mut_sync() {
+            if item.name() == "Renamed" {
+                item.unwrap_task_mut().set_name(String::from("Has been renamed"));
+            }
+        }
+        cal.notify_pending_changes();
+        assert!(Arc::ptr_eq(&snapshot, &cal.get_item_arc(&urls[0]).unwrap()));
+        assert_eq!(*recorder.changes.lock().unwrap(), vec![(Some(String::from("Renamed")), Some(String::from("Has been renamed")))]);
     }
 }
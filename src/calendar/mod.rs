@@ -7,9 +7,12 @@ use std::convert::TryFrom;
 use std::error::Error;
 
 use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
 
 use bitflags::bitflags;
 
+use crate::item::Item;
+
 bitflags! {
     #[derive(Serialize, Deserialize)]
     pub struct SupportedComponents: u8 {
@@ -17,6 +20,10 @@ bitflags! {
         const EVENT = 1;
         /// A to-do item, such as a reminder
         const TODO = 2;
+        /// A journal entry, such as a diary note
+        const JOURNAL = 4;
+        /// Free/busy time information
+        const FREEBUSY = 8;
     }
 }
 
@@ -24,11 +31,13 @@ impl SupportedComponents {
     pub fn to_xml_string(&self) -> String {
         format!(r#"
             <B:supported-calendar-component-set>
-                {} {}
+                {} {} {} {}
             </B:supported-calendar-component-set>
             "#,
-            if self.contains(Self::EVENT) { "<B:comp name=\"VEVENT\"/>" } else { "" },
-            if self.contains(Self::TODO)  { "<B:comp name=\"VTODO\"/>"  } else { "" },
+            if self.contains(Self::EVENT)    { "<B:comp name=\"VEVENT\"/>"    } else { "" },
+            if self.contains(Self::TODO)     { "<B:comp name=\"VTODO\"/>"     } else { "" },
+            if self.contains(Self::JOURNAL)  { "<B:comp name=\"VJOURNAL\"/>"  } else { "" },
+            if self.contains(Self::FREEBUSY) { "<B:comp name=\"VFREEBUSY\"/>" } else { "" },
         )
     }
 }
@@ -48,6 +57,8 @@ impl TryFrom<minidom::Element> for SupportedComponents {
                 None => continue,
                 Some("VEVENT") => flags.insert(Self::EVENT),
                 Some("VTODO") => flags.insert(Self::TODO),
+                Some("VJOURNAL") => flags.insert(Self::JOURNAL),
+                Some("VFREEBUSY") => flags.insert(Self::FREEBUSY),
                 Some(other) => {
                     log::warn!("Unimplemented supported component type: {:?}. Ignoring it", other);
                     continue
@@ -60,16 +71,128 @@ impl TryFrom<minidom::Element> for SupportedComponents {
 }
 
 
+bitflags! {
+    /// The REPORTs a calendar supports (its `supported-report-set`, see RFC 3253, section 3.1.5)
+    pub struct SupportedReports: u8 {
+        /// `calendar-query` (RFC 4791, section 7.8)
+        const CALENDAR_QUERY = 1;
+        /// `calendar-multiget` (RFC 4791, section 7.9)
+        const CALENDAR_MULTIGET = 2;
+        /// `free-busy-query` (RFC 4791, section 7.10)
+        const FREE_BUSY_QUERY = 4;
+        /// `sync-collection` (RFC 6578)
+        const SYNC_COLLECTION = 8;
+    }
+}
+
+impl SupportedReports {
+    /// Read a `<supported-report-set>` element. Reports this crate does not know about are ignored
+    pub fn from_xml(element: &minidom::Element) -> Self {
+        let mut flags = Self::empty();
+        for report in crate::utils::find_elems(element, "report") {
+            for child in report.children() {
+                match child.name() {
+                    "calendar-query" => flags.insert(Self::CALENDAR_QUERY),
+                    "calendar-multiget" => flags.insert(Self::CALENDAR_MULTIGET),
+                    "free-busy-query" => flags.insert(Self::FREE_BUSY_QUERY),
+                    "sync-collection" => flags.insert(Self::SYNC_COLLECTION),
+                    _ => (),
+                }
+            }
+        }
+        flags
+    }
+}
+
+
+/// The sync status of a local calendar itself (the sync status of its items is tracked separately, see [`crate::item::SyncStatus`])
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CalendarSyncStatus {
+    /// This calendar has not been synced with the remote source yet
+    #[default]
+    NotSynced,
+    /// This calendar has been synced with the remote source at least once. If it disappears from the remote source, it will be removed locally as well (unless the [`RemoteRemovalPolicy`](crate::config::RemoteRemovalPolicy) tells otherwise)
+    Synced,
+    /// This calendar has been deleted locally, and will be deleted from the remote source on the next sync
+    LocallyDeleted,
+    /// This calendar has disappeared from the remote source, and is kept locally until the app decides what to do with it (see [`RemoteRemovalPolicy::Ask`](crate::config::RemoteRemovalPolicy::Ask)). It is not synced anymore
+    RemovedRemotely,
+    /// This calendar has disappeared from the remote source, and is kept locally as an archive (see [`RemoteRemovalPolicy::Archive`](crate::config::RemoteRemovalPolicy::Archive)). It is not synced anymore
+    Archived,
+}
+
+
+/// Who a calendar belongs to, as told by the server (e.g. so that apps can group calendars)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CalendarKind {
+    /// A calendar of the current user (that may be shared with other users)
+    #[default]
+    Owned,
+    /// A calendar that someone else has shared with the current user (or delegated to them), possibly without allowing them to change it
+    Shared{ read_only: bool },
+    /// A subscription to a calendar published elsewhere (e.g. a `webcal://` feed), that cannot be changed
+    Subscription,
+}
+
+impl CalendarKind {
+    /// Whether the current user is not allowed to change the items of this calendar.
+    ///
+    /// Unless told otherwise, a [`Provider`](crate::provider::Provider) only pulls the changes of such calendars (see [`CalendarOverrides::for_kind`](crate::provider::CalendarOverrides::for_kind))
+    pub fn is_read_only(&self) -> bool {
+        match self {
+            CalendarKind::Owned => false,
+            CalendarKind::Shared{ read_only } => *read_only,
+            CalendarKind::Subscription => true,
+        }
+    }
+}
+
+
+/// Optional properties of a calendar, in addition to its name, color and supported components
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CalendarProperties {
+    /// A human-readable description of the calendar
+    pub description: Option<String>,
+    /// The position of the calendar in the calendar list of the user
+    pub order: Option<u32>,
+    /// The time zone of the calendar, as an iCal `VCALENDAR` that contains a single `VTIMEZONE`
+    pub timezone: Option<String>,
+}
+
+
 /// Flags to tell which events should be retrieved
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum SearchFilter {
     /// Return all items
     All,
     /// Return only tasks
     Tasks,
-    // /// Return only completed tasks
-    // CompletedTasks,
-    // /// Return only calendar events
-    // Events,
+    /// Return only completed tasks
+    CompletedTasks,
+    /// Return only tasks that are not completed yet
+    PendingTasks,
+    /// Return only calendar events
+    Events,
+    /// Return only tasks that are due strictly before this date
+    DueBefore(DateTime<Utc>),
+    /// Return only tasks that belong to this category (see [`Task::categories`](crate::task::Task::categories))
+    Category(String),
+}
+
+impl SearchFilter {
+    /// Whether an item is retained by this filter
+    pub fn matches(&self, item: &Item) -> bool {
+        match (self, item) {
+            (SearchFilter::All, _) => true,
+            (SearchFilter::Events, item) => item.is_event(),
+            (_, Item::Event(_)) => false,
+            (SearchFilter::Tasks, Item::Task(_)) => true,
+            (SearchFilter::CompletedTasks, Item::Task(task)) => task.completed(),
+            (SearchFilter::PendingTasks, Item::Task(task)) => task.completed() == false,
+            (SearchFilter::DueBefore(date), Item::Task(task)) => matches!(task.due(), Some(due) if &due < date),
+            (SearchFilter::Category(name), Item::Task(task)) => task.categories().contains(&name.as_str()),
+        }
+    }
 }
 
 impl Default for SearchFilter {
@@ -77,3 +200,69 @@ impl Default for SearchFilter {
         SearchFilter::All
     }
 }
+
+
+#[cfg(all(test, feature = "cache"))]
+mod tests {
+    use super::*;
+
+    use url::Url;
+
+    use crate::calendar::cached_calendar::CachedCalendar;
+    use crate::test_utils::TaskBuilder;
+    use crate::traits::CompleteCalendar;
+
+    #[test]
+    fn test_supported_components() {
+        let xml = r#"<supported-calendar-component-set xmlns="urn:ietf:params:xml:ns:caldav"><comp name="VTODO"/><comp name="VJOURNAL"/><comp name="VFREEBUSY"/></supported-calendar-component-set>"#;
+        let element: minidom::Element = xml.parse().unwrap();
+        let components = SupportedComponents::try_from(element).unwrap();
+        assert_eq!(components, SupportedComponents::TODO | SupportedComponents::JOURNAL | SupportedComponents::FREEBUSY);
+
+        let xml = components.to_xml_string();
+        assert!(xml.contains(r#"<B:comp name="VTODO"/>"#));
+        assert!(xml.contains(r#"<B:comp name="VJOURNAL"/>"#));
+        assert!(xml.contains(r#"<B:comp name="VFREEBUSY"/>"#));
+        assert!(xml.contains("VEVENT") == false);
+    }
+
+    #[test]
+    fn test_supported_reports() {
+        let xml = r#"<d:supported-report-set xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+            <d:supported-report><d:report><d:expand-property/></d:report></d:supported-report>
+            <d:supported-report><d:report><d:sync-collection/></d:report></d:supported-report>
+            <d:supported-report><d:report><c:calendar-multiget/></d:report></d:supported-report>
+            <d:supported-report><d:report><c:calendar-query/></d:report></d:supported-report>
+        </d:supported-report-set>"#;
+        let element: minidom::Element = xml.parse().unwrap();
+        assert_eq!(SupportedReports::from_xml(&element), SupportedReports::SYNC_COLLECTION | SupportedReports::CALENDAR_MULTIGET | SupportedReports::CALENDAR_QUERY);
+    }
+
+    #[test]
+    fn test_search_filters() {
+        let cal_url: Url = "https://some.calendar/".parse().unwrap();
+        let mut cal = CachedCalendar::new(String::from("Filtered"), cal_url.clone(), SupportedComponents::TODO, None);
+        cal.add_item_sync(TaskBuilder::new("Buy milk", &cal_url).property("DUE", "20210322T090000Z").property("CATEGORIES", "Shopping,Food").item()).unwrap();
+        cal.add_item_sync(TaskBuilder::new("Buy eggs", &cal_url).completed(true).property("CATEGORIES", "Shopping").item()).unwrap();
+        cal.add_item_sync(TaskBuilder::new("Attend a concert", &cal_url).property("DUE", "20210401").item()).unwrap();
+
+        let names = |filter: SearchFilter| {
+            let mut names: Vec<String> = cal.get_items_filtered_sync(&filter).unwrap()
+                .values()
+                .map(|item| item.name().to_string())
+                .collect();
+            names.sort();
+            names
+        };
+
+        assert_eq!(names(SearchFilter::All).len(), 3);
+        assert_eq!(names(SearchFilter::Tasks).len(), 3);
+        assert!(names(SearchFilter::Events).is_empty());
+        assert_eq!(names(SearchFilter::CompletedTasks), vec!["Buy eggs"]);
+        assert_eq!(names(SearchFilter::PendingTasks), vec!["Attend a concert", "Buy milk"]);
+        let date = "2021-03-25T00:00:00Z".parse().unwrap();
+        assert_eq!(names(SearchFilter::DueBefore(date)), vec!["Buy milk"]);
+        assert_eq!(names(SearchFilter::Category(String::from("Shopping"))), vec!["Buy eggs", "Buy milk"]);
+        assert_eq!(names(SearchFilter::Category(String::from("Food"))), vec!["Buy milk"]);
+    }
+}
@@ -1,20 +1,27 @@
-use std::collections::HashMap;
+#![cfg(feature = "client")]
+
+use std::collections::{HashMap, VecDeque};
 use std::error::Error;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 
 use async_trait::async_trait;
-use reqwest::{header::CONTENT_TYPE, header::CONTENT_LENGTH};
+use chrono::{DateTime, Utc};
+use reqwest::{header::CONTENT_TYPE, header::CONTENT_LENGTH, header::ETAG, header::IF_NONE_MATCH, StatusCode};
 use csscolorparser::Color;
 use url::Url;
 
 use crate::traits::BaseCalendar;
 use crate::traits::DavCalendar;
-use crate::calendar::SupportedComponents;
+use crate::calendar::{CalendarKind, CalendarProperties, SupportedComponents, SupportedReports};
 use crate::item::Item;
+use crate::item::Occurrence;
+use crate::free_busy::FreeBusyPeriod;
 use crate::item::VersionTag;
 use crate::item::SyncStatus;
+use crate::config::RetryLaterError;
 use crate::resource::Resource;
 use crate::utils::find_elem;
+use crate::client::response_text;
 
 static TASKS_BODY: &str = r#"
     <c:calendar-query xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
@@ -29,17 +36,148 @@ static TASKS_BODY: &str = r#"
     </c:calendar-query>
 "#;
 
-static MULTIGET_BODY_PREFIX: &str = r#"
-    <c:calendar-multiget xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+static CURRENT_VERSION_BODY: &str = r#"
+    <d:propfind xmlns:d="DAV:" xmlns:cs="http://calendarserver.org/ns/">
         <d:prop>
-            <c:calendar-data />
+            <cs:getctag />
+            <d:sync-token />
         </d:prop>
+    </d:propfind>
 "#;
-static MULTIGET_BODY_SUFFIX: &str = r#"
-    </c:calendar-multiget>
-"#;
+
+/// Same as `TASKS_BODY`, but only for tasks that overlap a given time range (see RFC 4791, section 9.9)
+fn tasks_between_body(start: &DateTime<Utc>, end: &DateTime<Utc>) -> String {
+    format!(r#"
+    <c:calendar-query xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+        <d:prop>
+            <d:getetag />
+        </d:prop>
+        <c:filter>
+            <c:comp-filter name="VCALENDAR">
+                <c:comp-filter name="VTODO">
+                    <c:time-range start="{}" end="{}"/>
+                </c:comp-filter>
+            </c:comp-filter>
+        </c:filter>
+    </c:calendar-query>
+"#, format_time_range_date(start), format_time_range_date(end))
+}
+
+/// Same as `TASKS_BODY`, but only for tasks whose `LAST-MODIFIED` property is `since` or later (see RFC 4791, section 9.7.2)
+fn tasks_modified_since_body(since: &DateTime<Utc>) -> String {
+    format!(r#"
+    <c:calendar-query xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+        <d:prop>
+            <d:getetag />
+        </d:prop>
+        <c:filter>
+            <c:comp-filter name="VCALENDAR">
+                <c:comp-filter name="VTODO">
+                    <c:prop-filter name="LAST-MODIFIED">
+                        <c:time-range start="{}"/>
+                    </c:prop-filter>
+                </c:comp-filter>
+            </c:comp-filter>
+        </c:filter>
+    </c:calendar-query>
+"#, format_time_range_date(since))
+}
+
+/// A `calendar-query` that asks the server to expand the recurrent tasks that overlap a given time range (see RFC 4791, section 9.6.5)
+fn expanded_tasks_between_body(start: &DateTime<Utc>, end: &DateTime<Utc>) -> String {
+    let start = format_time_range_date(start);
+    let end = format_time_range_date(end);
+    format!(r#"
+    <c:calendar-query xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+        <d:prop>
+            <d:getetag />
+            <c:calendar-data>
+                <c:expand start="{start}" end="{end}"/>
+            </c:calendar-data>
+        </d:prop>
+        <c:filter>
+            <c:comp-filter name="VCALENDAR">
+                <c:comp-filter name="VTODO">
+                    <c:time-range start="{start}" end="{end}"/>
+                </c:comp-filter>
+            </c:comp-filter>
+        </c:filter>
+    </c:calendar-query>
+"#, start=start, end=end)
+}
+
+/// Format a date the way RFC 4791 expects it in `time-range` elements
+fn format_time_range_date(dt: &DateTime<Utc>) -> String {
+    dt.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// A `calendar-query` that returns the tasks whose summary contains a given text (see RFC 4791, section 9.7.5)
+fn search_body(query: &str) -> String {
+    format!(r#"
+    <c:calendar-query xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+        <d:prop>
+            <d:getetag />
+            <c:calendar-data />
+        </d:prop>
+        <c:filter>
+            <c:comp-filter name="VCALENDAR">
+                <c:comp-filter name="VTODO">
+                    <c:prop-filter name="SUMMARY">
+                        <c:text-match>{}</c:text-match>
+                    </c:prop-filter>
+                </c:comp-filter>
+            </c:comp-filter>
+        </c:filter>
+    </c:calendar-query>
+"#, crate::utils::escape_xml(query))
+}
+
+/// A `free-busy-query` for a given time range (see RFC 4791, section 7.10)
+fn free_busy_body(start: &DateTime<Utc>, end: &DateTime<Utc>) -> String {
+    format!(r#"
+    <c:free-busy-query xmlns:c="urn:ietf:params:xml:ns:caldav">
+        <c:time-range start="{}" end="{}"/>
+    </c:free-busy-query>
+"#, format_time_range_date(start), format_time_range_date(end))
+}
+
+/// Send a `free-busy-query` to a calendar collection
+pub(crate) async fn request_free_busy(resource: &Resource, start: &DateTime<Utc>, end: &DateTime<Utc>) -> Result<Vec<FreeBusyPeriod>, Box<dyn Error>> {
+    let text = crate::client::sub_request(resource, "REPORT", free_busy_body(start, end), 1).await?;
+    crate::ical::parse_free_busy(&text)
+}
+
+/// A `calendar-multiget` for the given items, `calendar_data` being the `<c:calendar-data>` element to request (see RFC 4791, section 7.9)
+fn multiget_body(urls: &[Url], calendar_data: &str) -> String {
+    let mut hrefs = String::new();
+    for url in urls {
+        hrefs.push_str(&format!("        <d:href>{}</d:href>\n", url.path()));
+    }
+    format!(r#"
+    <c:calendar-multiget xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+        <d:prop>
+            <d:getetag />
+            {}
+        </d:prop>
+{}    </c:calendar-multiget>
+"#, calendar_data, hrefs)
+}
+
+/// A `calendar-data` element that asks the server to only return the overridden instances that overlap a given time range (see RFC 4791, section 9.6.6)
+fn limit_recurrence_set_calendar_data(start: &DateTime<Utc>, end: &DateTime<Utc>) -> String {
+    format!(r#"<c:calendar-data>
+                <c:limit-recurrence-set start="{}" end="{}"/>
+            </c:calendar-data>"#, format_time_range_date(start), format_time_range_date(end))
+}
+
 
 
+/// The kind of access a user has been granted to a calendar that someone else delegated to them (see the `calendar-proxy` extension of Apple's CalendarServer)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Delegation {
+    ReadOnly,
+    ReadWrite,
+}
 
 /// A CalDAV calendar created by a [`Client`](crate::client::Client).
 #[derive(Debug)]
@@ -48,110 +186,206 @@ pub struct RemoteCalendar {
     resource: Resource,
     supported_components: SupportedComponents,
     color: Option<Color>,
+    /// The calendar home set this calendar has been discovered in
+    home_set: Option<Url>,
+    delegation: Option<Delegation>,
+    kind: CalendarKind,
+    properties: CalendarProperties,
+    /// `None` in case the server did not tell which reports this calendar supports
+    supported_reports: Option<SupportedReports>,
+
+    /// The version tags of the items, once they have been fetched. \
+    /// Like the other fields below, it is shared with the detached copies of this calendar (see [`DavCalendar::detached`])
+    cached_version_tags: Arc<Mutex<Option<HashMap<Url, VersionTag>>>>,
+    /// The version of the calendar (see [`DavCalendar::get_current_version`]) the last time it has been fetched. The cached version tags are dropped whenever it changes
+    known_version: Arc<Mutex<Option<VersionTag>>>,
+    /// Items that have been downloaded by [`DavCalendar::get_item_by_url`], along with their ETags, so that they are downloaded again only in case they have changed
+    fetched_items: Arc<Mutex<FetchedItems>>,
+}
+
+/// How many items downloaded by [`DavCalendar::get_item_by_url`] a [`RemoteCalendar`] keeps at most
+const MAX_FETCHED_ITEMS: usize = 128;
 
-    cached_version_tags: Mutex<Option<HashMap<Url, VersionTag>>>,
+/// Downloaded items, along with their ETags. Once full, the items that have been downloaded first are dropped
+#[derive(Debug)]
+struct FetchedItems {
+    capacity: usize,
+    items: HashMap<Url, (String, Item)>,
+    /// The URLs of `items`, in the order they have been downloaded
+    order: VecDeque<Url>,
 }
 
-#[async_trait]
-impl BaseCalendar for RemoteCalendar {
-    fn name(&self) -> &str { &self.name }
-    fn url(&self) -> &Url { &self.resource.url() }
-    fn supported_components(&self) -> crate::calendar::SupportedComponents {
-        self.supported_components
-    }
-    fn color(&self) -> Option<&Color> {
-        self.color.as_ref()
+impl FetchedItems {
+    fn new(capacity: usize) -> Self {
+        Self { capacity, items: HashMap::new(), order: VecDeque::new() }
     }
 
-    async fn add_item(&mut self, item: Item) -> Result<SyncStatus, Box<dyn Error>> {
-        let ical_text = crate::ical::build_from(&item)?;
+    fn get(&self, url: &Url) -> Option<&(String, Item)> {
+        self.items.get(url)
+    }
 
-        let response = reqwest::Client::new()
-            .put(item.url().clone())
-            .header("If-None-Match", "*")
-            .header(CONTENT_TYPE, "text/calendar")
-            .header(CONTENT_LENGTH, ical_text.len())
-            .basic_auth(self.resource.username(), Some(self.resource.password()))
-            .body(ical_text)
-            .send()
-            .await?;
-
-        if response.status().is_success() == false {
-            return Err(format!("Unexpected HTTP status code {:?}", response.status()).into());
-        }
-
-        let reply_hdrs = response.headers();
-        match reply_hdrs.get("ETag") {
-            None => Err(format!("No ETag in these response headers: {:?} (request was {:?})", reply_hdrs, item.url()).into()),
-            Some(etag) => {
-                let vtag_str = etag.to_str()?;
-                let vtag = VersionTag::from(String::from(vtag_str));
-                Ok(SyncStatus::Synced(vtag))
+    fn insert(&mut self, url: Url, etag: String, item: Item) {
+        self.remove(&url);
+        while self.items.len() >= self.capacity.max(1) {
+            match self.order.pop_front() {
+                None => break,
+                Some(oldest) => { self.items.remove(&oldest); },
             }
         }
+        self.order.push_back(url.clone());
+        self.items.insert(url, (etag, item));
     }
 
-    async fn update_item(&mut self, item: Item) -> Result<SyncStatus, Box<dyn Error>> {
-        let old_etag = match item.sync_status() {
-            SyncStatus::NotSynced => return Err("Cannot update an item that has not been synced already".into()),
-            SyncStatus::Synced(_) => return Err("Cannot update an item that has not changed".into()),
-            SyncStatus::LocallyModified(etag) => etag,
-            SyncStatus::LocallyDeleted(etag) => etag,
-        };
-        let ical_text = crate::ical::build_from(&item)?;
-
-        let request = reqwest::Client::new()
-            .put(item.url().clone())
-            .header("If-Match", old_etag.as_str())
-            .header(CONTENT_TYPE, "text/calendar")
-            .header(CONTENT_LENGTH, ical_text.len())
-            .basic_auth(self.resource.username(), Some(self.resource.password()))
-            .body(ical_text)
-            .send()
-            .await?;
-
-        if request.status().is_success() == false {
-            return Err(format!("Unexpected HTTP status code {:?}", request.status()).into());
-        }
-
-        let reply_hdrs = request.headers();
-        match reply_hdrs.get("ETag") {
-            None => Err(format!("No ETag in these response headers: {:?} (request was {:?})", reply_hdrs, item.url()).into()),
-            Some(etag) => {
-                let vtag_str = etag.to_str()?;
-                let vtag = VersionTag::from(String::from(vtag_str));
-                Ok(SyncStatus::Synced(vtag))
-            }
+    fn remove(&mut self, url: &Url) {
+        if self.items.remove(url).is_some() {
+            self.order.retain(|known| known != url);
         }
     }
 }
 
-#[async_trait]
-impl DavCalendar for RemoteCalendar {
-    fn new(name: String, resource: Resource, supported_components: SupportedComponents, color: Option<Color>) -> Self {
-        Self {
-            name, resource, supported_components, color,
-            cached_version_tags: Mutex::new(None),
+impl RemoteCalendar {
+    pub(crate) fn with_home_set(mut self, home_set: Url, delegation: Option<Delegation>) -> Self {
+        self.home_set = Some(home_set);
+        self.delegation = delegation;
+        self
+    }
+
+    /// The calendar home set this calendar has been discovered in (servers may expose several of them, e.g. for delegated calendars)
+    pub fn home_set(&self) -> Option<&Url> {
+        self.home_set.as_ref()
+    }
+
+    pub(crate) fn with_kind(mut self, kind: CalendarKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub(crate) fn with_properties(mut self, properties: CalendarProperties) -> Self {
+        self.properties = properties;
+        self
+    }
+
+    /// The description, order and time zone of this calendar
+    pub fn properties(&self) -> &CalendarProperties {
+        &self.properties
+    }
+
+    pub(crate) fn with_supported_reports(mut self, supported_reports: Option<SupportedReports>) -> Self {
+        self.supported_reports = supported_reports;
+        self
+    }
+
+    /// The REPORTs this calendar supports, or `None` in case the server did not tell
+    pub fn supported_reports(&self) -> Option<SupportedReports> {
+        self.supported_reports
+    }
+
+    /// Whether this calendar supports `sync-collection` REPORTs (RFC 6578). This is `false` in case the server did not tell
+    pub fn supports_sync_collection(&self) -> bool {
+        self.supports_report(SupportedReports::SYNC_COLLECTION, false)
+    }
+
+    /// Whether this calendar supports `calendar-multiget` REPORTs. Since they are mandatory for CalDAV servers, this is `true` in case the server did not tell
+    pub fn supports_multiget(&self) -> bool {
+        self.supports_report(SupportedReports::CALENDAR_MULTIGET, true)
+    }
+
+    /// Whether this calendar supports `calendar-query` REPORTs. Since they are mandatory for CalDAV servers, this is `true` in case the server did not tell
+    pub fn supports_calendar_query(&self) -> bool {
+        self.supports_report(SupportedReports::CALENDAR_QUERY, true)
+    }
+
+    /// Whether this calendar supports `free-busy-query` REPORTs. This is `true` in case the server did not tell
+    pub fn supports_free_busy_query(&self) -> bool {
+        self.supports_report(SupportedReports::FREE_BUSY_QUERY, true)
+    }
+
+    fn supports_report(&self, report: SupportedReports, default: bool) -> bool {
+        self.supported_reports.map_or(default, |reports| reports.contains(report))
+    }
+
+    /// Download items one by one, for calendars that do not support `calendar-multiget`
+    async fn get_items_one_by_one(&self, urls: &[Url]) -> Result<Vec<Option<Item>>, Box<dyn Error>> {
+        let mut items = Vec::with_capacity(urls.len());
+        for url in urls {
+            items.push(self.get_item(url, false).await?);
         }
+        Ok(items)
     }
 
+    /// Download an item with a GET request.
+    ///
+    /// In case `remember` is true, the item is kept along with its ETag, so that the next download sends an `If-None-Match` header, and does not download it again in case the server replies it is unchanged (`304 Not Modified`)
+    async fn get_item(&self, url: &Url, remember: bool) -> Result<Option<Item>, Box<dyn Error>> {
+        let known_etag = match remember {
+            true => self.fetched_items.lock().unwrap().get(url).map(|(etag, _)| etag.clone()),
+            false => None,
+        };
+        let mut request = self.resource.http_client()
+            .get(url.clone())
+            .header(CONTENT_TYPE, "text/calendar")
+            .basic_auth(self.resource.username(), Some(self.resource.password()));
+        if let Some(etag) = &known_etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        let res = self.resource.send(request).await?;
 
-    async fn get_item_version_tags(&self) -> Result<HashMap<Url, VersionTag>, Box<dyn Error>> {
-        if let Some(map) = &*self.cached_version_tags.lock().unwrap() {
-            log::debug!("Version tags are already cached.");
-            return Ok(map.clone());
+        if res.status() == StatusCode::NOT_MODIFIED && known_etag.is_some() {
+            if let Some((_, item)) = self.fetched_items.lock().unwrap().get(url) {
+                log::debug!(target: crate::logging::CLIENT, "{} has not changed since it was last downloaded", url);
+                return Ok(Some(item.clone()));
+            }
+        }
+        if res.status().is_success() == false {
+            return Err(format!("Unexpected HTTP status code {:?}", res.status()).into());
+        }
+
+        let etag = res.headers().get(ETAG).and_then(|etag| etag.to_str().ok()).map(|etag| etag.to_string());
+        let text = response_text(res).await?;
+
+        // This is supposed to be cached
+        let version_tags = self.get_item_version_tags().await?;
+        let vt = match version_tags.get(url) {
+            None => return Err(format!("Inconsistent data: {} has no version tag", url).into()),
+            Some(vt) => vt,
         };
 
-        let responses = crate::client::sub_request_and_extract_elems(&self.resource, "REPORT", TASKS_BODY.to_string(), "response").await?;
+        let item = self.parse_item(&text, url.clone(), SyncStatus::Synced(vt.clone()))?;
+        if let (true, Some(etag)) = (remember, etag) {
+            self.fetched_items.lock().unwrap().insert(url.clone(), etag, item.clone());
+        }
+        Ok(Some(item))
+    }
+
+    /// In case this calendar belongs to someone else, who delegated it to the current user, the kind of access the current user has been granted. \
+    /// This is `None` for calendars of the current user.
+    pub fn delegation(&self) -> Option<Delegation> {
+        self.delegation
+    }
+
+    /// Keep the cached version tags coherent after an item has been added, modified (`Some(new_tag)`) or deleted (`None`) on the server
+    fn update_cached_version_tag(&self, item_url: &Url, new_tag: Option<VersionTag>) {
+        self.fetched_items.lock().unwrap().remove(item_url);
+        if let Some(map) = &mut *self.cached_version_tags.lock().unwrap() {
+            match new_tag {
+                Some(tag) => map.insert(item_url.clone(), tag),
+                None => map.remove(item_url),
+            };
+        }
+    }
 
+    /// Send a `calendar-query` REPORT that requests `getetag`s, and parse its result
+    async fn request_version_tags(&self, body: String) -> Result<HashMap<Url, VersionTag>, Box<dyn Error>> {
         let mut items = HashMap::new();
-        for response in responses {
+
+        // Calendars may contain lots of items, let's process the responses while they are received
+        crate::client::sub_request_and_process_elems(&self.resource, "REPORT", body, "response", |response| {
             let item_url = crate::utils::find_elem(&response, "href")
                 .map(|elem| self.resource.combine(&elem.text()));
             let item_url = match item_url {
                 None => {
-                    log::warn!("Unable to extract HREF");
-                    continue;
+                    log::warn!(target: crate::logging::CLIENT, "Unable to extract HREF");
+                    return;
                 },
                 Some(resource) => {
                     resource.url().clone()
@@ -160,93 +394,423 @@ impl DavCalendar for RemoteCalendar {
 
             let version_tag = match crate::utils::find_elem(&response, "getetag") {
                 None => {
-                    log::warn!("Unable to extract ETAG for item {}, ignoring it", item_url);
-                    continue;
+                    log::warn!(target: crate::logging::CLIENT, "Unable to extract ETAG for item {}, ignoring it", item_url);
+                    return;
                 },
                 Some(etag) => {
                     VersionTag::from(etag.text())
                 }
             };
 
-            items.insert(item_url.clone(), version_tag);
-        }
+            items.insert(item_url, version_tag);
+        }).await?;
 
-        // Note: the mutex cannot be locked during this whole async function, but it can safely be re-entrant (this will just waste an unnecessary request)
-        *self.cached_version_tags.lock().unwrap() = Some(items.clone());
         Ok(items)
     }
 
-    async fn get_item_by_url(&self, url: &Url) -> Result<Option<Item>, Box<dyn Error>> {
-        let res = reqwest::Client::new()
-            .get(url.clone())
-            .header(CONTENT_TYPE, "text/calendar")
-            .basic_auth(self.resource.username(), Some(self.resource.password()))
-            .send()
-            .await?;
+    /// Ask the server which time slots of a given time range are busy in this calendar.
+    ///
+    /// This does not download the events themselves.
+    pub async fn get_free_busy(&self, start: &DateTime<Utc>, end: &DateTime<Utc>) -> Result<Vec<FreeBusyPeriod>, Box<dyn Error>> {
+        request_free_busy(&self.resource, start, end).await
+    }
 
-        if res.status().is_success() == false {
-            return Err(format!("Unexpected HTTP status code {:?}", res.status()).into());
+    pub(crate) fn resource(&self) -> &Resource {
+        &self.resource
+    }
+
+    /// Send a `calendar-multiget` REPORT, and parse the items it returns
+    async fn multiget(&self, body: String) -> Result<Vec<Option<Item>>, Box<dyn Error>> {
+        Ok(self.report_items(body).await?
+            .into_iter()
+            .map(Some)
+            .collect())
+    }
+
+    /// Send a REPORT that requests `calendar-data`, and parse the items it returns
+    async fn report_items(&self, body: String) -> Result<Vec<Item>, Box<dyn Error>> {
+        let mut results = Vec::new();
+        for (url, vt, ical_data) in self.report_calendar_data(body).await? {
+            let item = self.parse_item(&ical_data, url, SyncStatus::Synced(vt))?;
+            results.push(item);
         }
+        Ok(results)
+    }
 
-        let text = res.text().await?;
+    /// Send a REPORT that requests `calendar-data`, and return the URL, version tag and iCal data of every `<response>` element of the reply.
+    ///
+    /// These are extracted as soon as each `<response>` is received, so that the XML tree of the whole reply is never held in memory
+    async fn report_calendar_data(&self, body: String) -> Result<Vec<(Url, VersionTag, String)>, Box<dyn Error>> {
+        let mut replies = Vec::new();
+        // This is kept as a String: `Box<dyn Error>` is not `Send`, and the futures of `async_trait` methods must be `Send`, so that it cannot be held across the `.await` below
+        let mut failure: Option<String> = None;
+        crate::client::sub_request_and_process_elems(&self.resource, "REPORT", body, "response", |xml_reply| {
+            if failure.is_some() {
+                return;
+            }
+            match self.extract_calendar_data_reply(&xml_reply) {
+                Ok(reply) => replies.push(reply),
+                Err(err) => failure = Some(err.to_string()),
+            }
+        }).await?;
+        if let Some(err) = failure {
+            return Err(err.into());
+        }
 
-        // This is supposed to be cached
-        let version_tags = self.get_item_version_tags().await?;
-        let vt = match version_tags.get(url) {
-            None => return Err(format!("Inconsistent data: {} has no version tag", url).into()),
-            Some(vt) => vt,
+        let mut results = Vec::new();
+        for (url, vt, ical_data) in replies {
+            let vt = match vt {
+                Some(vt) => vt,
+                None => {
+                    // Some servers do not send the etag along with the data. This is supposed to be cached anyway
+                    let version_tags = self.get_item_version_tags().await?;
+                    match version_tags.get(&url) {
+                        None => return Err(format!("Inconsistent data: {} has no version tag", url).into()),
+                        Some(vt) => vt.clone(),
+                    }
+                },
+            };
+            results.push((url, vt, ical_data));
+        }
+
+        Ok(results)
+    }
+
+    /// Extract the URL, version tag (if any) and iCal data of a `<response>` element of a REPORT that requested `calendar-data`
+    fn extract_calendar_data_reply(&self, xml_reply: &minidom::Element) -> Result<(Url, Option<VersionTag>, String), Box<dyn Error>> {
+        let href = find_elem(xml_reply, "href").ok_or("Missing HREF")?.text();
+        let mut url = self.resource.url().clone();
+        url.set_path(&href);
+        let ical_data = find_elem(xml_reply, "calendar-data").ok_or("Missing calendar-data")?.text();
+        let vt = find_elem(xml_reply, "getetag").map(|etag| VersionTag::from(etag.text()));
+        Ok((url, vt, ical_data))
+    }
+
+    /// Parse an item downloaded from the server, once its common mistakes have been fixed (see [`crate::ical::repair`])
+    fn parse_item(&self, ical_data: &str, url: Url, sync_status: SyncStatus) -> Result<Item, Box<dyn Error>> {
+        let (ical_data, repairs) = crate::ical::repair(ical_data, &self.resource.config().repair);
+        if repairs.is_empty() == false {
+            log::info!(target: crate::logging::CLIENT, "Item {} has been repaired: {:?}", url, repairs);
+        }
+        crate::ical::parse(&ical_data, url, sync_status)
+    }
+
+    /// Forget the cached version tags. They will be fetched again from the server the next time they are needed
+    fn invalidate_cached_version_tags(&self) {
+        *self.cached_version_tags.lock().unwrap() = None;
+    }
+
+    /// Record the outcome of [`put_item`]
+    fn apply_put_result(&self, url: &Url, result: Result<Option<VersionTag>, Box<dyn Error + Send + Sync>>) -> Result<SyncStatus, Box<dyn Error>> {
+        match result.map_err(|err| err as Box<dyn Error>)? {
+            None => {
+                // We do not know the new version tag. Let's make sure we won't use a stale one
+                self.invalidate_cached_version_tags();
+                Err(format!("No ETag in the response headers (request was {:?})", url).into())
+            },
+            Some(vtag) => {
+                self.update_cached_version_tag(url, Some(vtag.clone()));
+                Ok(SyncStatus::Synced(vtag))
+            },
+        }
+    }
+
+    /// Upload several items, up to `concurrency` of them at the same time.
+    ///
+    /// Invalid items (see [`crate::ical::validate`]) are not uploaded
+    async fn put_items(&self, items: Vec<Item>, kind: PutKind, concurrency: usize) -> Vec<Result<SyncStatus, Box<dyn Error>>> {
+        let mut outcomes = Vec::with_capacity(items.len());
+        let mut items = items.into_iter().peekable();
+        while items.peek().is_some() {
+            let uploads: Vec<_> = items.by_ref()
+                .take(concurrency.max(1))
+                .map(|item| match crate::ical::validate(&item) {
+                    Err(err) => Err(err),
+                    Ok(()) => {
+                        // Spawned uploads still belong to the current sync
+                        let url = item.url().clone();
+                        let upload = crate::correlation::in_sync(crate::correlation::current_sync_id(), put_item(self.resource.clone(), item, kind));
+                        Ok((url, tokio::spawn(upload)))
+                    },
+                })
+                .collect();
+            for upload in uploads {
+                match upload {
+                    Err(err) => outcomes.push(Err(err)),
+                    Ok((url, upload)) => {
+                        let result = upload.await.unwrap_or_else(|err| Err(format!("Upload of {} failed: {}", url, err).into()));
+                        outcomes.push(Ok((url, result)));
+                    },
+                }
+            }
+        }
+
+        outcomes.into_iter()
+            .map(|outcome| match outcome {
+                Err(err) => Err(err.into()),
+                Ok((url, result)) => self.apply_put_result(&url, result),
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl BaseCalendar for RemoteCalendar {
+    fn name(&self) -> &str { &self.name }
+    fn url(&self) -> &Url { &self.resource.url() }
+    fn supported_components(&self) -> crate::calendar::SupportedComponents {
+        self.supported_components
+    }
+    fn color(&self) -> Option<&Color> {
+        self.color.as_ref()
+    }
+    fn kind(&self) -> CalendarKind {
+        self.kind
+    }
+
+    async fn add_item(&mut self, item: Item) -> Result<SyncStatus, Box<dyn Error>> {
+        crate::ical::validate(&item)?;
+        let url = item.url().clone();
+        let result = put_item(self.resource.clone(), item, PutKind::Addition).await;
+        self.apply_put_result(&url, result)
+    }
+
+    /// Items are uploaded [`Config::upload_concurrency`](crate::config::Config::upload_concurrency) at a time, according to the configuration of the client
+    async fn add_items(&mut self, items: Vec<Item>) -> Vec<Result<SyncStatus, Box<dyn Error>>> {
+        let concurrency = self.resource.config().upload_concurrency;
+        self.put_items(items, PutKind::Addition, concurrency).await
+    }
+
+    async fn update_item(&mut self, item: Item) -> Result<SyncStatus, Box<dyn Error>> {
+        crate::ical::validate(&item)?;
+        let url = item.url().clone();
+        let result = put_item(self.resource.clone(), item, PutKind::Update).await;
+        self.apply_put_result(&url, result)
+    }
+}
+
+/// Whether an uploaded item is new, or replaces a version that is already on the server
+#[derive(Clone, Copy, Debug)]
+enum PutKind {
+    Addition,
+    Update,
+}
+
+/// Upload an item, and return its new version tag (if the server has told it).
+///
+/// This does not borrow the calendar, so that several items can be uploaded concurrently. For the same reason, errors must be sent across threads
+async fn put_item(resource: Resource, item: Item, kind: PutKind) -> Result<Option<VersionTag>, Box<dyn Error + Send + Sync>> {
+    let precondition = match (kind, item.sync_status()) {
+        (PutKind::Addition, _) => ("If-None-Match", String::from("*")),
+        (PutKind::Update, SyncStatus::NotSynced) => return Err("Cannot update an item that has not been synced already".into()),
+        (PutKind::Update, SyncStatus::Synced(_)) => return Err("Cannot update an item that has not changed".into()),
+        (PutKind::Update, SyncStatus::LocallyModified(etag)) => ("If-Match", etag.as_str().to_string()),
+        (PutKind::Update, SyncStatus::LocallyDeleted(etag)) => ("If-Match", etag.as_str().to_string()),
+    };
+    let ical_text = crate::ical::build_from(&item).map_err(|err| err.to_string())?;
+
+    let request = resource.http_client()
+        .put(item.url().clone())
+        .header(precondition.0, precondition.1)
+        .header(CONTENT_TYPE, "text/calendar")
+        .header(CONTENT_LENGTH, ical_text.len())
+        .basic_auth(resource.username(), Some(resource.password()))
+        .body(ical_text);
+    let response = resource.send(request).await?;
+
+    if let Some(err) = RetryLaterError::from_response(&response, resource.server_clock()) {
+        return Err(err.into());
+    }
+    if response.status().is_success() == false {
+        return Err(format!("Unexpected HTTP status code {:?}", response.status()).into());
+    }
+
+    let reply_hdrs = response.headers();
+    match reply_hdrs.get("ETag") {
+        None => {
+            log::debug!(target: crate::logging::CLIENT, "No ETag in these response headers: {:?} (request was {:?})", reply_hdrs, item.url());
+            Ok(None)
+        },
+        Some(etag) => {
+            let vtag_str = etag.to_str()?;
+            Ok(Some(VersionTag::from(String::from(vtag_str))))
+        },
+    }
+}
+
+#[async_trait]
+impl DavCalendar for RemoteCalendar {
+    fn new(name: String, resource: Resource, supported_components: SupportedComponents, color: Option<Color>) -> Self {
+        Self {
+            name, resource, supported_components, color,
+            home_set: None,
+            delegation: None,
+            kind: CalendarKind::Owned,
+            properties: CalendarProperties::default(),
+            supported_reports: None,
+            cached_version_tags: Arc::new(Mutex::new(None)),
+            known_version: Arc::new(Mutex::new(None)),
+            fetched_items: Arc::new(Mutex::new(FetchedItems::new(MAX_FETCHED_ITEMS))),
+        }
+    }
+
+    /// Copies share the memoized version tags and items of this calendar, so that there is nothing to record once their requests are over
+    fn detached(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            resource: self.resource.clone(),
+            supported_components: self.supported_components,
+            color: self.color.clone(),
+            home_set: self.home_set.clone(),
+            delegation: self.delegation,
+            kind: self.kind,
+            properties: self.properties.clone(),
+            supported_reports: self.supported_reports,
+            cached_version_tags: Arc::clone(&self.cached_version_tags),
+            known_version: Arc::clone(&self.known_version),
+            fetched_items: Arc::clone(&self.fetched_items),
+        }
+    }
+
+    async fn add_items_concurrently(&mut self, items: Vec<Item>, concurrency: usize) -> Vec<Result<SyncStatus, Box<dyn Error>>> {
+        self.put_items(items, PutKind::Addition, concurrency).await
+    }
+
+    async fn update_items(&mut self, items: Vec<Item>, concurrency: usize) -> Vec<Result<SyncStatus, Box<dyn Error>>> {
+        self.put_items(items, PutKind::Update, concurrency).await
+    }
+
+    fn refresh(&self) {
+        log::debug!(target: crate::logging::CLIENT, "Dropping the cached version tags of {}", self.url());
+        self.invalidate_cached_version_tags();
+    }
+
+
+    async fn get_item_version_tags(&self) -> Result<HashMap<Url, VersionTag>, Box<dyn Error>> {
+        if let Some(map) = &*self.cached_version_tags.lock().unwrap() {
+            log::debug!(target: crate::logging::CLIENT, "Version tags are already cached.");
+            return Ok(map.clone());
         };
 
-        let item = crate::ical::parse(&text, url.clone(), SyncStatus::Synced(vt.clone()))?;
-        Ok(Some(item))
+        let items = self.request_version_tags(TASKS_BODY.to_string()).await?;
+
+        // Note: the mutex cannot be locked during this whole async function, but it can safely be re-entrant (this will just waste an unnecessary request)
+        *self.cached_version_tags.lock().unwrap() = Some(items.clone());
+        Ok(items)
     }
 
-    async fn get_items_by_url(&self, urls: &[Url]) -> Result<Vec<Option<Item>>, Box<dyn Error>> {
-        // Build the request body
-        let mut hrefs = String::new();
-        for url in urls {
-            hrefs.push_str(&format!("        <d:href>{}</d:href>\n", url.path()));
+    async fn get_item_version_tags_between(&self, start: &DateTime<Utc>, end: &DateTime<Utc>) -> Result<HashMap<Url, VersionTag>, Box<dyn Error>> {
+        // This is only a subset of the items, it must not be memoized
+        self.request_version_tags(tasks_between_body(start, end)).await
+    }
+
+    async fn get_item_version_tags_modified_since(&self, since: &DateTime<Utc>) -> Result<HashMap<Url, VersionTag>, Box<dyn Error>> {
+        // This is only a subset of the items, it must not be memoized
+        let since = *since + self.resource.server_clock().offset();
+        self.request_version_tags(tasks_modified_since_body(&since)).await
+    }
+
+    /// The `getctag` of the calendar (a widespread extension of CalDAV), or its `sync-token` (see [RFC 6578](https://datatracker.ietf.org/doc/html/rfc6578)) for servers that do not support it
+    async fn get_current_version(&self) -> Result<Option<VersionTag>, Box<dyn Error>> {
+        let text = crate::client::sub_request(&self.resource, "PROPFIND", CURRENT_VERSION_BODY.to_string(), 0).await?;
+        let reply: minidom::Element = text.parse()?;
+        // Properties the server does not support are listed as well, but empty
+        let version = ["getctag", "sync-token"].iter()
+            .filter_map(|name| find_elem(&reply, name))
+            .map(|elem| elem.text())
+            .find(|text| text.trim().is_empty() == false)
+            .map(VersionTag::from);
+
+        let mut known_version = self.known_version.lock().unwrap();
+        if version.is_some() && *known_version != version {
+            // Something has changed on the server, the version tags we may have cached are outdated
+            self.invalidate_cached_version_tags();
+            *known_version = version.clone();
         }
-        let body = format!("{}{}{}", MULTIGET_BODY_PREFIX, hrefs, MULTIGET_BODY_SUFFIX);
+        Ok(version)
+    }
 
-        // Send the request
-        let xml_replies = crate::client::sub_request_and_extract_elems(&self.resource, "REPORT", body, "response").await?;
+    /// Items that have already been downloaded by this function are requested with an `If-None-Match` header, so that they are not downloaded again in case they have not changed
+    async fn get_item_by_url(&self, url: &Url) -> Result<Option<Item>, Box<dyn Error>> {
+        self.get_item(url, true).await
+    }
 
-        // This is supposed to be cached
-        let version_tags = self.get_item_version_tags().await?;
+    async fn get_items_by_url(&self, urls: &[Url]) -> Result<Vec<Option<Item>>, Box<dyn Error>> {
+        if self.supports_multiget() == false {
+            return self.get_items_one_by_one(urls).await;
+        }
+        self.multiget(multiget_body(urls, "<c:calendar-data />")).await
+    }
 
-        // Parse the results
-        let mut results = Vec::new();
-        for xml_reply in xml_replies {
-            let href = find_elem(&xml_reply, "href").ok_or("Missing HREF")?.text();
-            let mut url = self.resource.url().clone();
-            url.set_path(&href);
-            let ical_data = find_elem(&xml_reply, "calendar-data").ok_or("Missing calendar-data")?.text();
-
-            let vt = match version_tags.get(&url) {
-                None => return Err(format!("Inconsistent data: {} has no version tag", url).into()),
-                Some(vt) => vt,
-            };
+    async fn get_items_by_url_limited_to(&self, urls: &[Url], start: &DateTime<Utc>, end: &DateTime<Utc>) -> Result<Vec<Option<Item>>, Box<dyn Error>> {
+        if self.supports_multiget() == false {
+            // Plain GETs cannot limit the recurrence set. The items are just bigger than needed
+            return self.get_items_one_by_one(urls).await;
+        }
+        self.multiget(multiget_body(urls, &limit_recurrence_set_calendar_data(start, end))).await
+    }
 
-            let item = crate::ical::parse(&ical_data, url.clone(), SyncStatus::Synced(vt.clone()))?;
-            results.push(Some(item));
+    async fn get_occurrences_between(&self, start: &DateTime<Utc>, end: &DateTime<Utc>) -> Result<Vec<Occurrence>, Box<dyn Error>> {
+        let mut occurrences = Vec::new();
+        for (url, vt, ical_data) in self.report_calendar_data(expanded_tasks_between_body(start, end)).await? {
+            occurrences.extend(crate::ical::parse_occurrences(&ical_data, url, SyncStatus::Synced(vt))?);
         }
+        Ok(occurrences)
+    }
 
-        Ok(results)
+    async fn search(&self, query: &str) -> Result<Vec<Item>, Box<dyn Error>> {
+        self.report_items(search_body(query)).await
     }
 
     async fn delete_item(&mut self, item_url: &Url) -> Result<(), Box<dyn Error>> {
-        let del_response = reqwest::Client::new()
+        let request = self.resource.http_client()
             .delete(item_url.clone())
-            .basic_auth(self.resource.username(), Some(self.resource.password()))
-            .send()
-            .await?;
+            .basic_auth(self.resource.username(), Some(self.resource.password()));
+        let del_response = self.resource.send(request).await?;
 
+        if let Some(err) = RetryLaterError::from_response(&del_response, self.resource.server_clock()) {
+            return Err(err.into());
+        }
         if del_response.status().is_success() == false {
             return Err(format!("Unexpected HTTP status code {:?}", del_response.status()).into());
         }
 
+        self.update_cached_version_tag(item_url, None);
         Ok(())
     }
 }
 
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::Task;
+
+    #[test]
+    fn test_fetched_items_are_bounded() {
+        let cal_url = Url::parse("https://caldav.com/fetched/").unwrap();
+        let tasks: Vec<Item> = (0..4).map(|i| Item::Task(Task::new(format!("Task #{}", i), false, &cal_url))).collect();
+        let mut fetched = FetchedItems::new(3);
+        for (i, task) in tasks.iter().enumerate() {
+            fetched.insert(task.url().clone(), format!("etag-{}", i), task.clone());
+        }
+
+        // The first downloaded item has been dropped
+        assert!(fetched.get(tasks[0].url()).is_none());
+        assert_eq!(fetched.get(tasks[3].url()).unwrap().0, "etag-3");
+        assert_eq!(fetched.items.len(), 3);
+
+        // Downloading an item again makes it the most recent one
+        fetched.insert(tasks[1].url().clone(), String::from("etag-1b"), tasks[1].clone());
+        fetched.insert(tasks[0].url().clone(), String::from("etag-0b"), tasks[0].clone());
+        assert!(fetched.get(tasks[2].url()).is_none());
+        assert_eq!(fetched.get(tasks[1].url()).unwrap().0, "etag-1b");
+        assert_eq!(fetched.order.len(), 3);
+
+        fetched.remove(tasks[1].url());
+        assert!(fetched.get(tasks[1].url()).is_none());
+        assert_eq!(fetched.order.len(), 2);
+    }
+}
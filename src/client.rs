@@ -1,9 +1,13 @@
 //! This module provides a client to connect to a CalDAV server
+//!
+//! This module requires the `client` feature (that is enabled by the TLS features, one of them being enabled by default).
+#![cfg(feature = "client")]
 
 use std::error::Error;
 use std::convert::TryFrom;
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
 use async_trait::async_trait;
 use reqwest::{Method, StatusCode};
@@ -11,11 +15,17 @@ use reqwest::header::CONTENT_TYPE;
 use minidom::Element;
 use url::Url;
 use csscolorparser::Color;
+use chrono::{DateTime, Utc};
 
 use crate::resource::Resource;
-use crate::utils::{find_elem, find_elems};
-use crate::calendar::remote_calendar::RemoteCalendar;
-use crate::calendar::SupportedComponents;
+use crate::config::{Config, HttpVersion, TlsBackend};
+use crate::utils::{escape_xml, find_elem, find_elems, MultistatusSplitter};
+use crate::calendar::remote_calendar::{Delegation, RemoteCalendar, request_free_busy};
+use crate::free_busy::FreeBusyPeriod;
+use crate::item::VersionTag;
+use crate::scheduling::{PartStat, SchedulingMessage};
+use crate::server_profile::ServerProfile;
+use crate::calendar::{CalendarKind, CalendarProperties, SupportedComponents, SupportedReports};
 use crate::traits::CalDavSource;
 use crate::traits::BaseCalendar;
 use crate::traits::DavCalendar;
@@ -38,13 +48,48 @@ static HOMESET_BODY: &str = r#"
     </d:propfind>
 "#;
 
+static PROXY_BODY: &str = r#"
+    <d:propfind xmlns:d="DAV:" xmlns:cs="http://calendarserver.org/ns/" >
+      <d:prop>
+        <cs:calendar-proxy-read-for />
+        <cs:calendar-proxy-write-for />
+      </d:prop>
+    </d:propfind>
+"#;
+
+static SCHEDULING_BODY: &str = r#"
+    <d:propfind xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav" >
+      <d:prop>
+        <c:schedule-inbox-URL />
+        <c:schedule-outbox-URL />
+      </d:prop>
+    </d:propfind>
+"#;
+
+static INBOX_BODY: &str = r#"
+    <c:calendar-query xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+        <d:prop>
+            <d:getetag />
+            <c:calendar-data />
+        </d:prop>
+        <c:filter>
+            <c:comp-filter name="VCALENDAR" />
+        </c:filter>
+    </c:calendar-query>
+"#;
+
 static CAL_BODY: &str = r#"
     <d:propfind xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav" >
        <d:prop>
          <d:displayname />
          <E:calendar-color xmlns:E="http://apple.com/ns/ical/"/>
+         <E:calendar-order xmlns:E="http://apple.com/ns/ical/"/>
+         <c:calendar-description />
+         <c:calendar-timezone />
          <d:resourcetype />
          <c:supported-calendar-component-set />
+         <d:supported-report-set />
+         <d:current-user-privilege-set />
        </d:prop>
     </d:propfind>
 "#;
@@ -55,23 +100,31 @@ pub(crate) async fn sub_request(resource: &Resource, method: &str, body: String,
     let method = method.parse()
         .expect("invalid method name");
 
-    let res = reqwest::Client::new()
+    let request = resource.http_client()
         .request(method, resource.url().clone())
         .header("Depth", depth)
         .header(CONTENT_TYPE, "application/xml")
         .basic_auth(resource.username(), Some(resource.password()))
-        .body(body)
-        .send()
-        .await?;
+        .body(body);
+    let res = resource.send(request).await?;
 
     if res.status().is_success() == false {
         return Err(format!("Unexpected HTTP status code {:?}", res.status()).into());
     }
 
-    let text = res.text().await?;
+    let text = response_text(res).await?;
     Ok(text)
 }
 
+/// Read the body of a response, taking its encoding into account
+pub(crate) async fn response_text(res: reqwest::Response) -> Result<String, Box<dyn Error>> {
+    let content_type = res.headers().get(CONTENT_TYPE)
+        .and_then(|ct| ct.to_str().ok())
+        .map(|ct| ct.to_string());
+    let bytes = res.bytes().await?;
+    crate::utils::decode_text(&bytes, content_type.as_deref())
+}
+
 pub(crate) async fn sub_request_and_extract_elem(resource: &Resource, body: String, items: &[&str]) -> Result<String, Box<dyn Error>> {
     let text = sub_request(resource, "PROPFIND", body, 0).await?;
 
@@ -86,14 +139,79 @@ pub(crate) async fn sub_request_and_extract_elem(resource: &Resource, body: Stri
 }
 
 pub(crate) async fn sub_request_and_extract_elems(resource: &Resource, method: &str, body: String, item: &str) -> Result<Vec<Element>, Box<dyn Error>> {
-    let text = sub_request(resource, method, body, 1).await?;
+    let mut elems = Vec::new();
+    sub_request_and_process_elems(resource, method, body, item, |elem| elems.push(elem)).await?;
+    Ok(elems)
+}
 
-    let element: &Element = &text.parse()?;
-    Ok(find_elems(&element, item)
-        .iter()
-        .map(|elem| (*elem).clone())
-        .collect()
-    )
+/// Same as [`sub_request_and_extract_elems`], but the matching elements are handed to `process` as soon as they are received.
+///
+/// This way, huge replies (e.g. the version tags of a calendar that contains tens of thousands of items) are never held in memory as a whole.
+pub(crate) async fn sub_request_and_process_elems<F: FnMut(Element)>(resource: &Resource, method: &str, body: String, item: &str, mut process: F) -> Result<(), Box<dyn Error>> {
+    let method = method.parse()
+        .expect("invalid method name");
+
+    let request = resource.http_client()
+        .request(method, resource.url().clone())
+        .header("Depth", 1)
+        .header(CONTENT_TYPE, "application/xml")
+        .basic_auth(resource.username(), Some(resource.password()))
+        .body(body);
+    let mut res = resource.send(request).await?;
+
+    if res.status().is_success() == false {
+        return Err(format!("Unexpected HTTP status code {:?}", res.status()).into());
+    }
+
+    let content_type = res.headers().get(CONTENT_TYPE)
+        .and_then(|ct| ct.to_str().ok())
+        .map(|ct| ct.to_string());
+
+    let mut splitter = None;
+    let mut non_streamable_body: Option<Vec<u8>> = None;
+    while let Some(chunk) = res.chunk().await? {
+        if let Some(body) = non_streamable_body.as_mut() {
+            body.extend_from_slice(&chunk);
+            continue;
+        }
+        let splitter = match splitter.as_mut() {
+            Some(splitter) => splitter,
+            None => {
+                // UTF-16 (and other non ASCII-compatible encodings) cannot be split on the fly
+                let (encoding, _) = crate::utils::detect_encoding(&chunk, content_type.as_deref());
+                if encoding.is_ascii_compatible() == false {
+                    non_streamable_body = Some(chunk.to_vec());
+                    continue;
+                }
+                splitter.insert(MultistatusSplitter::new(encoding))
+            },
+        };
+        for elem in splitter.feed(&chunk)? {
+            process_matching_elems(elem, item, &mut process);
+        }
+    }
+
+    match non_streamable_body {
+        Some(body) => {
+            let text = crate::utils::decode_text(&body, content_type.as_deref())?;
+            let element: Element = text.parse()?;
+            for elem in find_elems(&element, item) {
+                process(elem.clone());
+            }
+        },
+        None => splitter.unwrap_or_else(|| MultistatusSplitter::new(encoding_rs::UTF_8)).finish()?,
+    }
+    Ok(())
+}
+
+fn process_matching_elems<F: FnMut(Element)>(elem: Element, item: &str, process: &mut F) {
+    if elem.name() == item {
+        process(elem);
+    } else {
+        for child in find_elems(&elem, item) {
+            process(child.clone());
+        }
+    }
 }
 
 
@@ -111,21 +229,218 @@ pub struct Client {
 #[derive(Debug, Default)]
 struct CachedReplies {
     principal: Option<Resource>,
-    calendar_home_set: Option<Resource>,
-    calendars: Option<HashMap<Url, Arc<Mutex<RemoteCalendar>>>>,
+    calendar_home_sets: Option<Vec<HomeSet>>,
+    scheduling_urls: Option<SchedulingUrls>,
+    server_profile: Option<ServerProfile>,
+    /// The calendar listing. It is fetched once, and kept until [`Client::invalidate_calendars`] is called
+    calendars: Option<HashMap<Url, Arc<RwLock<RemoteCalendar>>>>,
+}
+
+/// A calendar home set, along with the way this user accesses it
+#[derive(Clone, Debug)]
+struct HomeSet {
+    resource: Resource,
+    /// `None` for the home sets of the current user, `Some` for the ones of principals that delegated their calendars to the current user
+    delegation: Option<Delegation>,
+}
+
+#[derive(Clone, Debug)]
+struct SchedulingUrls {
+    inbox: Resource,
+    outbox: Resource,
+}
+
+/// A builder for a [`Client`], for clients that do not use the default options
+///
+/// ```
+/// # #[cfg(feature = "rustls")] {
+/// use std::time::Duration;
+/// use kitchen_fridge::Client;
+/// use kitchen_fridge::config::{Config, TlsBackend};
+///
+/// let client = Client::builder("https://my.server.com/remote.php/dav/files/john", "john", "secret")
+///     .tls_backend(TlsBackend::Rustls)
+///     .config(Config { request_timeout: Some(Duration::from_secs(10)), ..Config::default() })
+///     .build()
+///     .unwrap();
+/// assert_eq!(client.config().tls_backend, Some(TlsBackend::Rustls));
+/// assert_eq!(client.config().request_timeout, Some(Duration::from_secs(10)));
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct ClientBuilder {
+    url: String,
+    username: String,
+    password: String,
+    principal: Option<String>,
+    config: Config,
+    /// The connection settings that have been set on their own. They take precedence over the ones of [`Self::config`], whatever the order they have been set in
+    tls_backend: Option<TlsBackend>,
+    http_version: Option<HttpVersion>,
+    pool_idle_timeout: Option<Option<Duration>>,
+    pool_max_idle_per_host: Option<usize>,
+}
+
+impl ClientBuilder {
+    /// Start building a client. See [`Client::new`]
+    pub fn new<S: ToString, T: ToString, U: ToString>(url: S, username: T, password: U) -> Self {
+        Self {
+            url: url.to_string(),
+            username: username.to_string(),
+            password: password.to_string(),
+            principal: None,
+            config: Config::default(),
+            tls_backend: None,
+            http_version: None,
+            pool_idle_timeout: None,
+            pool_max_idle_per_host: None,
+        }
+    }
+
+    /// Set the options of the client. Settings that are set on their own (e.g. with [`Self::tls_backend`]) take precedence over the ones of `config`
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Set the TLS library that is used to connect to the server
+    pub fn tls_backend(mut self, tls_backend: TlsBackend) -> Self {
+        self.tls_backend = Some(tls_backend);
+        self
+    }
+
+    /// Set the versions of HTTP that are used to talk to the server, see [`Config::http_version`]
+    pub fn http_version(mut self, http_version: HttpVersion) -> Self {
+        self.http_version = Some(http_version);
+        self
+    }
+
+    /// Set how long unused connections to the server are kept open (`None` to keep them open until the server closes them)
+    pub fn pool_idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.pool_idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Set how many unused connections to the server are kept open at most
+    pub fn pool_max_idle_per_host(mut self, max: usize) -> Self {
+        self.pool_max_idle_per_host = Some(max);
+        self
+    }
+
+    /// Work on the calendars of another principal than the one of the credentials (e.g. for an admin account, or for a user someone has delegated their calendars to), see [`Client::set_principal`]
+    pub fn principal<S: ToString>(mut self, principal_url: S) -> Self {
+        self.principal = Some(principal_url.to_string());
+        self
+    }
+
+    /// Create the client. This does not start a connection
+    pub fn build(self) -> Result<Client, Box<dyn Error>> {
+        let mut config = self.config;
+        if let Some(tls_backend) = self.tls_backend {
+            config.tls_backend = Some(tls_backend);
+        }
+        if let Some(http_version) = self.http_version {
+            config.http_version = http_version;
+        }
+        if let Some(timeout) = self.pool_idle_timeout {
+            config.pool_idle_timeout = timeout;
+        }
+        if let Some(max) = self.pool_max_idle_per_host {
+            config.pool_max_idle_per_host = max;
+        }
+        let client = Client::new_with_config(self.url, self.username, self.password, config)?;
+        if let Some(principal_url) = self.principal {
+            client.set_principal(&principal_url)?;
+        }
+        Ok(client)
+    }
 }
 
 impl Client {
     /// Create a client. This does not start a connection
     pub fn new<S: AsRef<str>, T: ToString, U: ToString>(url: S, username: T, password: U) -> Result<Self, Box<dyn Error>> {
+        Self::new_with_config(url, username, password, Config::default())
+    }
+
+    /// Start building a client, see [`ClientBuilder`]
+    pub fn builder<S: ToString, T: ToString, U: ToString>(url: S, username: T, password: U) -> ClientBuilder {
+        ClientBuilder::new(url, username, password)
+    }
+
+    /// Create a client that uses specific options (e.g. a request timeout). This does not start a connection
+    pub fn new_with_config<S: AsRef<str>, T: ToString, U: ToString>(url: S, username: T, password: U, config: Config) -> Result<Self, Box<dyn Error>> {
         let url = Url::parse(url.as_ref())?;
 
         Ok(Self{
-            resource: Resource::new(url, username.to_string(), password.to_string()),
+            resource: Resource::new_with_config(url, username.to_string(), password.to_string(), Arc::new(config)),
             cached_replies: Mutex::new(CachedReplies::default()),
         })
     }
 
+    /// The options this client has been created with
+    pub fn config(&self) -> &Config {
+        self.resource.config()
+    }
+
+    /// How far the clock of the server is from the local clock (positive when the server is ahead), as measured from the `Date` header of its last response.
+    ///
+    /// This is `None` until the server has sent a dated response. See also [`Config::clock_skew_tolerance`]
+    pub fn clock_skew(&self) -> Option<chrono::Duration> {
+        self.resource.server_clock().skew()
+    }
+
+    /// The current date, as the clock of the server tells it in case [`Config::compensate_clock_skew`] is set (otherwise, this is [`deterministic::now`](crate::deterministic::now))
+    pub fn server_now(&self) -> DateTime<Utc> {
+        self.resource.server_clock().now()
+    }
+
+    /// Return the implementation of the server, or detect it if not known yet (or set with [`Client::set_server_profile`])
+    pub async fn server_profile(&self) -> ServerProfile {
+        if let Some(profile) = self.cached_replies.lock().unwrap().server_profile {
+            return profile;
+        }
+
+        let profile = match self.detect_server_profile().await {
+            Ok(profile) => profile,
+            Err(err) => {
+                log::warn!(target: crate::logging::CLIENT, "Unable to detect the server implementation, assuming a generic one ({})", err);
+                ServerProfile::Generic
+            },
+        };
+        log::debug!(target: crate::logging::CLIENT, "Server profile is {:?}", profile);
+        self.cached_replies.lock().unwrap().server_profile = Some(profile);
+        profile
+    }
+
+    /// Override the automatic detection of the server implementation
+    pub fn set_server_profile(&self, profile: ServerProfile) {
+        self.cached_replies.lock().unwrap().server_profile = Some(profile);
+    }
+
+    async fn detect_server_profile(&self) -> Result<ServerProfile, Box<dyn Error>> {
+        let request = self.resource.http_client()
+            .request(Method::OPTIONS, self.resource.url().clone())
+            .basic_auth(self.resource.username(), Some(self.resource.password()));
+        let res = self.resource.send(request).await?;
+
+        Ok(ServerProfile::detect(self.resource.url(), res.headers()))
+    }
+
+    /// Work on the calendars of another principal, instead of the one the server tells for the credentials of this client (its `current-user-principal`).
+    ///
+    /// This is meant for admin accounts, or for users someone has delegated their calendars to: requests are still authenticated with the credentials of this client, so that the server must allow them to access the calendars of this principal.
+    /// `principal_url` is either a full URL, or a path on the server (e.g. `/principals/users/jane/`). The calendars that have been fetched already are forgotten.
+    pub fn set_principal(&self, principal_url: &str) -> Result<(), Box<dyn Error>> {
+        let url = self.resource.url().join(principal_url)?;
+        log::debug!(target: crate::logging::CLIENT, "Using principal {} instead of the current user principal", url);
+        let mut replies = self.cached_replies.lock().unwrap();
+        replies.principal = Some(self.resource.with_url(url));
+        replies.calendar_home_sets = None;
+        replies.scheduling_urls = None;
+        replies.calendars = None;
+        Ok(())
+    }
+
     /// Return the Principal URL, or fetch it from server if not known yet
     async fn get_principal(&self) -> Result<Resource, Box<dyn Error>> {
         if let Some(p) = &self.cached_replies.lock().unwrap().principal {
@@ -135,87 +450,223 @@ impl Client {
         let href = sub_request_and_extract_elem(&self.resource, DAVCLIENT_BODY.into(), &["current-user-principal", "href"]).await?;
         let principal_url = self.resource.combine(&href);
         self.cached_replies.lock().unwrap().principal = Some(principal_url.clone());
-        log::debug!("Principal URL is {}", href);
+        log::debug!(target: crate::logging::CLIENT, "Principal URL is {}", href);
 
         return Ok(principal_url);
     }
 
-    /// Return the Homeset URL, or fetch it from server if not known yet
-    async fn get_cal_home_set(&self) -> Result<Resource, Box<dyn Error>> {
-        if let Some(h) = &self.cached_replies.lock().unwrap().calendar_home_set {
+    /// Return the Homeset URLs, or fetch them from server if not known yet.
+    ///
+    /// Some servers return several home sets (e.g. the personal one, and the ones that are delegated to this user).
+    /// The home sets of the principals this user is a calendar proxy for are returned as well.
+    async fn get_cal_home_sets(&self) -> Result<Vec<HomeSet>, Box<dyn Error>> {
+        if let Some(h) = &self.cached_replies.lock().unwrap().calendar_home_sets {
             return Ok(h.clone());
         }
         let principal_url = self.get_principal().await?;
 
-        let href = sub_request_and_extract_elem(&principal_url, HOMESET_BODY.into(), &["calendar-home-set", "href"]).await?;
-        let chs_url = self.resource.combine(&href);
-        self.cached_replies.lock().unwrap().calendar_home_set = Some(chs_url.clone());
-        log::debug!("Calendar home set URL is {:?}", href);
+        let mut home_sets: Vec<HomeSet> = Vec::new();
+        let own_home_sets = self.get_cal_home_sets_of(&principal_url).await?;
+        let delegations = match self.server_profile().await.quirks().calendar_proxy {
+            false => Vec::new(),
+            true => self.get_proxied_principals_or_none(&principal_url).await,
+        };
 
-        Ok(chs_url)
+        for resource in own_home_sets {
+            home_sets.push(HomeSet{ resource, delegation: None });
+        }
+        for (proxied_principal, delegation) in delegations {
+            match self.get_cal_home_sets_of(&proxied_principal).await {
+                Err(err) => log::warn!(target: crate::logging::CLIENT, "Unable to fetch the calendar home set of {}: {}", proxied_principal.url(), err),
+                Ok(resources) => {
+                    for resource in resources {
+                        home_sets.push(HomeSet{ resource, delegation: Some(delegation) });
+                    }
+                },
+            }
+        }
+        // A home set may be both owned and delegated (in which case the owned one is kept)
+        let mut known_urls = std::collections::HashSet::new();
+        home_sets.retain(|home_set| known_urls.insert(home_set.resource.url().clone()));
+
+        self.cached_replies.lock().unwrap().calendar_home_sets = Some(home_sets.clone());
+        Ok(home_sets)
     }
 
-    async fn populate_calendars(&self) -> Result<(), Box<dyn Error>> {
-        let cal_home_set = self.get_cal_home_set().await?;
+    /// Fetch the calendar home sets of a principal
+    async fn get_cal_home_sets_of(&self, principal_url: &Resource) -> Result<Vec<Resource>, Box<dyn Error>> {
+        let text = sub_request(principal_url, "PROPFIND", HOMESET_BODY.into(), 0).await?;
+        let root: Element = text.parse()?;
+        let el_home_set = find_elem(&root, "calendar-home-set").ok_or("missing element calendar-home-set")?;
+        let hrefs: Vec<String> = find_elems(el_home_set, "href").iter().map(|href| href.text()).collect();
+        if hrefs.is_empty() {
+            return Err("missing element href".into());
+        }
 
-        let reps = sub_request_and_extract_elems(&cal_home_set, "PROPFIND", CAL_BODY.to_string(), "response").await?;
-        let mut calendars = HashMap::new();
-        for rep in reps {
-            let display_name = find_elem(&rep, "displayname").map(|e| e.text()).unwrap_or("<no name>".to_string());
-            log::debug!("Considering calendar {}", display_name);
+        let mut chs_urls: Vec<Resource> = Vec::new();
+        for href in &hrefs {
+            let chs_url = self.resource.combine(href);
+            if chs_urls.iter().any(|known| known.url() == chs_url.url()) == false {
+                chs_urls.push(chs_url);
+            }
+        }
+        log::debug!(target: crate::logging::CLIENT, "Calendar home set URLs of {} are {:?}", principal_url.url(), hrefs);
 
-            // We filter out non-calendar items
-            let resource_types = match find_elem(&rep, "resourcetype") {
-                None => continue,
-                Some(rt) => rt,
-            };
-            let mut found_calendar_type = false;
-            for resource_type in resource_types.children() {
-                if resource_type.name() == "calendar" {
-                    found_calendar_type = true;
-                    break;
+        Ok(chs_urls)
+    }
+
+    /// Same as `get_proxied_principals`, but failures are ignored (most servers do not support calendar proxies)
+    async fn get_proxied_principals_or_none(&self, principal_url: &Resource) -> Vec<(Resource, Delegation)> {
+        match self.get_proxied_principals(principal_url).await {
+            Ok(delegations) => delegations,
+            Err(err) => {
+                log::debug!(target: crate::logging::CLIENT, "Unable to fetch calendar proxies, ignoring them ({})", err);
+                Vec::new()
+            },
+        }
+    }
+
+    /// Fetch the principals that delegated their calendars to a principal (see the `calendar-proxy` extension of Apple's CalendarServer)
+    async fn get_proxied_principals(&self, principal_url: &Resource) -> Result<Vec<(Resource, Delegation)>, Box<dyn Error>> {
+        let text = sub_request(principal_url, "PROPFIND", PROXY_BODY.into(), 0).await?;
+        let root: Element = text.parse()?;
+
+        let mut principals = Vec::new();
+        for (prop_name, delegation) in [("calendar-proxy-write-for", Delegation::ReadWrite), ("calendar-proxy-read-for", Delegation::ReadOnly)] {
+            if let Some(el_proxy_for) = find_elem(&root, prop_name) {
+                for href in find_elems(el_proxy_for, "href") {
+                    log::debug!(target: crate::logging::CLIENT, "Current user is a {:?} calendar proxy for {}", delegation, href.text());
+                    principals.push((self.resource.combine(&href.text()), delegation));
                 }
             }
-            if found_calendar_type == false {
-                continue;
-            }
+        }
+        Ok(principals)
+    }
 
-            // We filter out the root calendar collection, that has an empty supported-calendar-component-set
-            let el_supported_comps = match find_elem(&rep, "supported-calendar-component-set") {
-                None => continue,
-                Some(comps) => comps,
-            };
-            if el_supported_comps.children().count() == 0 {
-                continue;
-            }
+    /// Return the scheduling inbox and outbox URLs, or fetch them from server if not known yet
+    async fn get_scheduling_urls(&self) -> Result<SchedulingUrls, Box<dyn Error>> {
+        if let Some(urls) = &self.cached_replies.lock().unwrap().scheduling_urls {
+            return Ok(urls.clone());
+        }
+        let principal_url = self.get_principal().await?;
 
-            let calendar_href = match find_elem(&rep, "href") {
-                None => {
-                    log::warn!("Calendar {} has no URL! Ignoring it.", display_name);
-                    continue;
-                },
-                Some(h) => h.text(),
-            };
+        let text = sub_request(&principal_url, "PROPFIND", SCHEDULING_BODY.into(), 0).await?;
+        let root: Element = text.parse()?;
+        let inbox_href = find_elem(&root, "schedule-inbox-URL")
+            .and_then(|elem| find_elem(elem, "href"))
+            .ok_or("This server does not support scheduling (no schedule-inbox-URL)")?
+            .text();
+        let outbox_href = find_elem(&root, "schedule-outbox-URL")
+            .and_then(|elem| find_elem(elem, "href"))
+            .ok_or("This server does not support scheduling (no schedule-outbox-URL)")?
+            .text();
+
+        let urls = SchedulingUrls {
+            inbox: self.resource.combine(&inbox_href),
+            outbox: self.resource.combine(&outbox_href),
+        };
+        self.cached_replies.lock().unwrap().scheduling_urls = Some(urls.clone());
+        log::debug!(target: crate::logging::CLIENT, "Scheduling inbox URL is {:?}, outbox URL is {:?}", inbox_href, outbox_href);
+
+        Ok(urls)
+    }
+
+    /// Return the URL of the scheduling inbox, where the server delivers incoming scheduling messages (e.g. invitations)
+    pub async fn scheduling_inbox_url(&self) -> Result<Url, Box<dyn Error>> {
+        Ok(self.get_scheduling_urls().await?.inbox.url().clone())
+    }
+
+    /// Return the URL of the scheduling outbox
+    pub async fn scheduling_outbox_url(&self) -> Result<Url, Box<dyn Error>> {
+        Ok(self.get_scheduling_urls().await?.outbox.url().clone())
+    }
+
+    /// List the scheduling messages (e.g. invitations, or replies to invitations) that are in the scheduling inbox
+    pub async fn get_scheduling_messages(&self) -> Result<Vec<SchedulingMessage>, Box<dyn Error>> {
+        let inbox = self.get_scheduling_urls().await?.inbox;
+        let replies = sub_request_and_extract_elems(&inbox, "REPORT", INBOX_BODY.into(), "response").await?;
+
+        let mut messages = Vec::new();
+        for reply in replies {
+            let href = find_elem(&reply, "href").ok_or("Missing url in response")?.text();
+            let version_tag = find_elem(&reply, "getetag").ok_or("Missing etag in response")?.text();
+            let ical = find_elem(&reply, "calendar-data").ok_or("Missing calendar-data")?.text();
+            messages.push(SchedulingMessage::new(inbox.combine(&href).url().clone(), VersionTag::from(version_tag), ical));
+        }
+        Ok(messages)
+    }
+
+    /// Remove a scheduling message from the scheduling inbox, e.g. once it has been processed
+    pub async fn delete_scheduling_message(&self, message: &SchedulingMessage) -> Result<(), Box<dyn Error>> {
+        let request = self.resource.http_client()
+            .delete(message.url().clone())
+            .header("If-Match", message.version_tag().as_str())
+            .basic_auth(self.resource.username(), Some(self.resource.password()));
+        let res = self.resource.send(request).await?;
+
+        if res.status().is_success() == false {
+            return Err(format!("Unexpected HTTP status code {:?}", res.status()).into());
+        }
+        Ok(())
+    }
+
+    /// Reply to an invitation, by updating the participation status of an attendee in the scheduled item.
+    ///
+    /// `item_url` is the URL of the scheduled item in a calendar of this user (that the server created when it delivered the invitation),
+    /// and `attendee` is the calendar user address of this user (e.g. `mailto:me@example.com`). \
+    /// The server is then asked to send the iTIP reply to the organizer.
+    /// Returns the new version tag of the item.
+    pub async fn reply_to_invitation(&self, item_url: &Url, attendee: &str, partstat: PartStat) -> Result<VersionTag, Box<dyn Error>> {
+        let request = self.resource.http_client()
+            .get(item_url.clone())
+            .basic_auth(self.resource.username(), Some(self.resource.password()));
+        let res = self.resource.send(request).await?;
+        if res.status().is_success() == false {
+            return Err(format!("Unexpected HTTP status code {:?}", res.status()).into());
+        }
+        let etag = res.headers().get("ETag")
+            .ok_or_else(|| format!("No ETag for {}", item_url))?
+            .to_str()?
+            .to_string();
+        let ical = response_text(res).await?;
+
+        let replied_ical = crate::scheduling::set_partstat(&ical, attendee, partstat)?;
+
+        let request = self.resource.http_client()
+            .put(item_url.clone())
+            .header("If-Match", etag)
+            .header("Schedule-Reply", "T")
+            .header(CONTENT_TYPE, "text/calendar")
+            .basic_auth(self.resource.username(), Some(self.resource.password()))
+            .body(replied_ical);
+        let res = self.resource.send(request).await?;
+        if res.status().is_success() == false {
+            return Err(format!("Unexpected HTTP status code {:?}", res.status()).into());
+        }
 
-            let this_calendar_url = self.resource.combine(&calendar_href);
+        match res.headers().get("ETag") {
+            None => Err(format!("No ETag in these response headers: {:?} (request was {:?})", res.headers(), item_url).into()),
+            Some(etag) => Ok(VersionTag::from(String::from(etag.to_str()?))),
+        }
+    }
 
-            let supported_components = match crate::calendar::SupportedComponents::try_from(el_supported_comps.clone()) {
-                Err(err) => {
-                    log::warn!("Calendar {} has invalid supported components ({})! Ignoring it.", display_name, err);
+    async fn populate_calendars(&self) -> Result<(), Box<dyn Error>> {
+        let cal_home_sets = self.get_cal_home_sets().await?;
+        let mut calendars = HashMap::new();
+        for cal_home_set in cal_home_sets {
+            let home_set_calendars = match (self.get_calendars_in_home_set(&cal_home_set).await, cal_home_set.delegation) {
+                (Ok(cals), _) => cals,
+                (Err(err), None) => return Err(err),
+                (Err(err), Some(_)) => {
+                    // A failure of delegated calendars should not prevent from using the calendars of the current user
+                    log::warn!(target: crate::logging::CLIENT, "Unable to fetch the delegated calendars of {}: {}", cal_home_set.resource.url(), err);
                     continue;
                 },
-                Ok(sc) => sc,
             };
-
-            let this_calendar_color = find_elem(&rep, "calendar-color")
-                .and_then(|col| {
-                    col.texts().next()
-                        .and_then(|t| csscolorparser::parse(t).ok())
-                });
-
-            let this_calendar = RemoteCalendar::new(display_name, this_calendar_url, supported_components, this_calendar_color);
-            log::info!("Found calendar {}", this_calendar.name());
-            calendars.insert(this_calendar.url().clone(), Arc::new(Mutex::new(this_calendar)));
+            for (url, cal) in home_set_calendars {
+                // A calendar may be reachable from several home sets. Let's keep the first one
+                calendars.entry(url).or_insert(cal);
+            }
         }
 
         let mut replies = self.cached_replies.lock().unwrap();
@@ -223,12 +674,223 @@ impl Client {
         Ok(())
     }
 
+    /// Fetch the calendar listing, unless it is known already
+    async fn populate_calendars_if_needed(&self) -> Result<(), Box<dyn Error>> {
+        if self.cached_replies.lock().unwrap().calendars.is_some() {
+            return Ok(());
+        }
+        self.populate_calendars().await
+    }
+
+    async fn get_calendars_in_home_set(&self, cal_home_set: &HomeSet) -> Result<HashMap<Url, Arc<RwLock<RemoteCalendar>>>, Box<dyn Error>> {
+        let reps = sub_request_and_extract_elems(&cal_home_set.resource, "PROPFIND", CAL_BODY.to_string(), "response").await?;
+        let mut calendars = HashMap::new();
+        for rep in reps {
+            if let Some(this_calendar) = self.parse_calendar_response(&rep) {
+                // Calendars that have been delegated to the current user are shared with them
+                let kind = match (this_calendar.kind(), cal_home_set.delegation) {
+                    (CalendarKind::Owned, Some(delegation)) => CalendarKind::Shared{ read_only: delegation == Delegation::ReadOnly },
+                    (kind, _) => kind,
+                };
+                let this_calendar = this_calendar.with_home_set(cal_home_set.resource.url().clone(), cal_home_set.delegation)
+                    .with_kind(kind);
+                log::info!(target: crate::logging::CLIENT, "Found calendar {}", this_calendar.name());
+                calendars.insert(this_calendar.url().clone(), Arc::new(RwLock::new(this_calendar)));
+            }
+        }
+
+        Ok(calendars)
+    }
+
+    /// Build a calendar from a `<response>` to a `CAL_BODY` PROPFIND, or return `None` if this response is not about a calendar
+    fn parse_calendar_response(&self, rep: &Element) -> Option<RemoteCalendar> {
+        let display_name = find_elem(rep, "displayname").map(|e| e.text()).unwrap_or("<no name>".to_string());
+        log::debug!(target: crate::logging::CLIENT, "Considering calendar {}", display_name);
+
+        // We filter out non-calendar items
+        let resource_types = find_elem(rep, "resourcetype")?;
+        let has_type = |name: &str| resource_types.children().any(|resource_type| resource_type.name() == name);
+        if has_type("calendar") == false {
+            return None;
+        }
+
+        // We filter out the root calendar collection, that has an empty supported-calendar-component-set
+        let el_supported_comps = find_elem(rep, "supported-calendar-component-set")?;
+        if el_supported_comps.children().count() == 0 {
+            return None;
+        }
+
+        let calendar_href = match find_elem(rep, "href") {
+            None => {
+                log::warn!(target: crate::logging::CLIENT, "Calendar {} has no URL! Ignoring it.", display_name);
+                return None;
+            },
+            Some(h) => h.text(),
+        };
+
+        let this_calendar_url = self.resource.combine(&calendar_href);
+
+        let supported_components = match crate::calendar::SupportedComponents::try_from(el_supported_comps.clone()) {
+            Err(err) => {
+                log::warn!(target: crate::logging::CLIENT, "Calendar {} has invalid supported components ({})! Ignoring it.", display_name, err);
+                return None;
+            },
+            Ok(sc) => sc,
+        };
+
+        let this_calendar_color = find_elem(rep, "calendar-color")
+            .and_then(|col| {
+                col.texts().next()
+                    .and_then(|t| csscolorparser::parse(t).ok())
+            });
+
+        let non_empty_text = |name: &str| find_elem(rep, name).map(|e| e.text()).filter(|text| text.trim().is_empty() == false);
+        let properties = CalendarProperties {
+            description: non_empty_text("calendar-description"),
+            order: non_empty_text("calendar-order").and_then(|order| order.trim().parse().ok()),
+            timezone: non_empty_text("calendar-timezone"),
+        };
+
+        // Servers that do not know this property reply with an empty element
+        let supported_reports = find_elem(rep, "supported-report-set")
+            .map(SupportedReports::from_xml)
+            .filter(|reports| reports.is_empty() == false);
+
+        // Servers that do not tell the privileges of the current user are trusted to let them write
+        let read_only = find_elem(rep, "current-user-privilege-set")
+            .filter(|privileges| privileges.children().count() != 0)
+            .map(|privileges| ["write", "write-content", "all"].iter().all(|privilege| find_elem(privileges, privilege).is_none()))
+            .unwrap_or(false);
+        // `shared` and `subscribed` are extensions of Apple's CalendarServer. Calendars the current user shares with others are `shared-owner`
+        let kind = if has_type("subscribed") {
+            CalendarKind::Subscription
+        } else if has_type("shared") || read_only {
+            CalendarKind::Shared{ read_only }
+        } else {
+            CalendarKind::Owned
+        };
+
+        Some(RemoteCalendar::new(display_name, this_calendar_url, supported_components, this_calendar_color)
+            .with_kind(kind)
+            .with_properties(properties)
+            .with_supported_reports(supported_reports))
+    }
+
+    /// Create a calendar on the server, with additional properties (see [`CalDavSource::create_calendar`]).
+    ///
+    /// The created calendar is then read back from the server, so that the returned calendar reflects the properties the server actually accepted.
+    pub async fn create_calendar_with_properties(&self, url: Url, name: String, supported_components: SupportedComponents, color: Option<Color>, properties: CalendarProperties) -> Result<Arc<RwLock<RemoteCalendar>>, Box<dyn Error>> {
+        let profile = self.server_profile().await;
+        if profile.quirks().calendar_creation == false {
+            return Err(format!("{:?} servers do not support creating calendars", profile).into());
+        }
+
+        self.populate_calendars_if_needed().await?;
+
+        match self.cached_replies.lock().unwrap().calendars.as_ref() {
+            None => return Err("No calendars have been fetched".into()),
+            Some(cals) => {
+                if cals.contains_key(&url) {
+                    return Err("This calendar already exists".into());
+                }
+            },
+        }
+
+        let creation_body = calendar_body(name.clone(), supported_components, color.clone(), &properties);
+
+        let status = self.send_creation_request(&url, "MKCALENDAR", creation_body).await?;
+        if status != StatusCode::CREATED {
+            // Some servers do not support MKCALENDAR, but support creating calendars with an extended MKCOL (see RFC 5689)
+            if [StatusCode::BAD_REQUEST, StatusCode::FORBIDDEN, StatusCode::METHOD_NOT_ALLOWED, StatusCode::NOT_IMPLEMENTED].contains(&status) == false {
+                return Err(format!("Unexpected HTTP status code. Expected CREATED, got {}", status.as_u16()).into());
+            }
+            log::info!(target: crate::logging::CLIENT, "Server rejected MKCALENDAR for {} ({}), trying an extended MKCOL instead", url, status.as_u16());
+
+            let fallback_body = extended_mkcol_body(name.clone(), supported_components, color.clone(), &properties);
+            let status = self.send_creation_request(&url, "MKCOL", fallback_body).await?;
+            if status != StatusCode::CREATED {
+                return Err(format!("Unexpected HTTP status code. Expected CREATED, got {}", status.as_u16()).into());
+            }
+        }
+
+        // Let's check what the server has accepted
+        let created_resource = self.resource.with_url(url.clone());
+        let text = sub_request(&created_resource, "PROPFIND", CAL_BODY.to_string(), 0).await?;
+        let root: Element = text.parse()?;
+        let created = find_elems(&root, "response").into_iter()
+            .find_map(|rep| self.parse_calendar_response(rep))
+            .ok_or_else(|| format!("Unable to read back created calendar {:?}", url))?;
+
+        if created.name() != name {
+            log::warn!(target: crate::logging::CLIENT, "Calendar {} has been created with name {:?} instead of {:?}", url, created.name(), name);
+        }
+        if created.supported_components() != supported_components {
+            log::warn!(target: crate::logging::CLIENT, "Calendar {} has been created with components {:?} instead of {:?}", url, created.supported_components(), supported_components);
+        }
+        if color.is_some() && created.color().is_none() {
+            log::warn!(target: crate::logging::CLIENT, "Server did not accept the color of calendar {}", url);
+        }
+        if created.properties() != &properties {
+            log::warn!(target: crate::logging::CLIENT, "Calendar {} has been created with properties {:?} instead of {:?}", url, created.properties(), properties);
+        }
+
+        // Tag the calendar with the home set that contains it
+        let home_set = self.get_cal_home_sets().await?
+            .into_iter()
+            .find(|home_set| url.path().starts_with(home_set.resource.url().path()));
+        let created = match home_set {
+            None => created,
+            Some(home_set) => created.with_home_set(home_set.resource.url().clone(), home_set.delegation),
+        };
+
+        let created = Arc::new(RwLock::new(created));
+        self.cached_replies.lock().unwrap()
+            .calendars
+            .get_or_insert_with(HashMap::new)
+            .insert(url, created.clone());
+        Ok(created)
+    }
+
+    /// Send a request that creates a collection, and return the HTTP status of the reply
+    async fn send_creation_request(&self, url: &Url, method: &str, body: String) -> Result<StatusCode, Box<dyn Error>> {
+        let request = self.resource.http_client()
+            .request(Method::from_bytes(method.as_bytes())?, url.clone())
+            .header(CONTENT_TYPE, "application/xml")
+            .basic_auth(self.resource.username(), Some(self.resource.password()))
+            .body(body);
+        let response = self.resource.send(request).await?;
+
+        Ok(response.status())
+    }
+
+    /// Ask the server which time slots of a given time range are busy, across every calendar that supports events.
+    ///
+    /// Applications can use [`crate::free_busy::is_free`] on the result to know whether a time slot is available, without syncing every event locally.
+    pub async fn get_free_busy(&self, start: &DateTime<Utc>, end: &DateTime<Utc>) -> Result<Vec<FreeBusyPeriod>, Box<dyn Error>> {
+        let resources: Vec<Resource> = self.get_calendars().await?
+            .values()
+            .filter_map(|cal| {
+                let cal = cal.read().unwrap();
+                match cal.supported_components().contains(SupportedComponents::EVENT) && cal.supports_free_busy_query() {
+                    true => Some(cal.resource().clone()),
+                    false => None,
+                }
+            })
+            .collect();
+
+        let mut periods = Vec::new();
+        for resource in resources {
+            periods.extend(request_free_busy(&resource, start, end).await?);
+        }
+        Ok(periods)
+    }
+
 }
 
 #[async_trait]
 impl CalDavSource<RemoteCalendar> for Client {
-    async fn get_calendars(&self) -> Result<HashMap<Url, Arc<Mutex<RemoteCalendar>>>, Box<dyn Error>> {
-        self.populate_calendars().await?;
+    async fn get_calendars(&self) -> Result<HashMap<Url, Arc<RwLock<RemoteCalendar>>>, Box<dyn Error>> {
+        self.populate_calendars_if_needed().await?;
 
         match &self.cached_replies.lock().unwrap().calendars {
             Some(cals) => {
@@ -238,9 +900,9 @@ impl CalDavSource<RemoteCalendar> for Client {
         };
     }
 
-    async fn get_calendar(&self, url: &Url) -> Option<Arc<Mutex<RemoteCalendar>>> {
-        if let Err(err) = self.populate_calendars().await {
-            log::warn!("Unable to fetch calendars: {}", err);
+    async fn get_calendar(&self, url: &Url) -> Option<Arc<RwLock<RemoteCalendar>>> {
+        if let Err(err) = self.populate_calendars_if_needed().await {
+            log::warn!(target: crate::logging::CLIENT, "Unable to fetch calendars: {}", err);
             return None;
         }
 
@@ -251,57 +913,112 @@ impl CalDavSource<RemoteCalendar> for Client {
             .map(|cal| cal.clone())
     }
 
-    async fn create_calendar(&mut self, url: Url, name: String, supported_components: SupportedComponents, color: Option<Color>) -> Result<Arc<Mutex<RemoteCalendar>>, Box<dyn Error>> {
-        self.populate_calendars().await?;
-
-        match self.cached_replies.lock().unwrap().calendars.as_ref() {
-            None => return Err("No calendars have been fetched".into()),
-            Some(cals) => {
-                if cals.contains_key(&url) {
-                    return Err("This calendar already exists".into());
-                }
-            },
-        }
+    async fn create_calendar(&self, url: Url, name: String, supported_components: SupportedComponents, color: Option<Color>) -> Result<Arc<RwLock<RemoteCalendar>>, Box<dyn Error>> {
+        self.create_calendar_with_properties(url, name, supported_components, color, CalendarProperties::default()).await
+    }
 
-        let creation_body = calendar_body(name, supported_components, color);
+    async fn delete_calendar(&self, url: &Url) -> Result<(), Box<dyn Error>> {
+        let request = self.resource.http_client()
+            .delete(url.clone())
+            .basic_auth(self.resource.username(), Some(self.resource.password()));
+        let response = self.resource.send(request).await?;
 
-        let response = reqwest::Client::new()
-            .request(Method::from_bytes(b"MKCALENDAR").unwrap(), url.clone())
-            .header(CONTENT_TYPE, "application/xml")
-            .basic_auth(self.resource.username(), Some(self.resource.password()))
-            .body(creation_body)
-            .send()
-            .await?;
+        if response.status().is_success() == false {
+            return Err(format!("Unexpected HTTP status code {:?}", response.status()).into());
+        }
 
-        let status = response.status();
-        if status != StatusCode::CREATED {
-            return Err(format!("Unexpected HTTP status code. Expected CREATED, got {}", status.as_u16()).into());
+        if let Some(cals) = self.cached_replies.lock().unwrap().calendars.as_mut() {
+            cals.remove(url);
         }
+        Ok(())
+    }
 
-        self.get_calendar(&url).await.ok_or(format!("Unable to insert calendar {:?}", url).into())
+    fn invalidate_calendars(&self) {
+        self.cached_replies.lock().unwrap().calendars = None;
     }
-}
 
-fn calendar_body(name: String, supported_components: SupportedComponents, color: Option<Color>) -> String {
-    let color_property = match color {
-        None => "".to_string(),
-        Some(color) => format!("<D:calendar-color xmlns:D=\"http://apple.com/ns/ical/\">{}FF</D:calendar-color>", color.to_hex_string().to_ascii_uppercase()),
-    };
+    async fn check_reachable(&self) -> Result<(), Box<dyn Error>> {
+        // Any answer (even an HTTP error) means the server is reachable
+        let request = self.resource.http_client()
+            .request(Method::OPTIONS, self.resource.url().clone())
+            .basic_auth(self.resource.username(), Some(self.resource.password()))
+            .timeout(self.resource.config().reachability_timeout);
+        self.resource.send_once(request).await?;
+        Ok(())
+    }
+}
 
+fn calendar_body(name: String, supported_components: SupportedComponents, color: Option<Color>, properties: &CalendarProperties) -> String {
     // This is taken from https://tools.ietf.org/html/rfc4791#page-24
     format!(r#"<?xml version="1.0" encoding="utf-8" ?>
         <B:mkcalendar xmlns:B="urn:ietf:params:xml:ns:caldav">
             <A:set xmlns:A="DAV:">
                 <A:prop>
-                    <A:displayname>{}</A:displayname>
-                    {}
                     {}
                 </A:prop>
             </A:set>
         </B:mkcalendar>
         "#,
-        name,
+        calendar_props(name, supported_components, color, properties),
+    )
+}
+
+/// Same as `calendar_body`, for servers that only support creating calendars with an extended MKCOL
+fn extended_mkcol_body(name: String, supported_components: SupportedComponents, color: Option<Color>, properties: &CalendarProperties) -> String {
+    // This is taken from https://tools.ietf.org/html/rfc5689#section-5.1
+    format!(r#"<?xml version="1.0" encoding="utf-8" ?>
+        <A:mkcol xmlns:A="DAV:" xmlns:B="urn:ietf:params:xml:ns:caldav">
+            <A:set>
+                <A:prop>
+                    <A:resourcetype>
+                        <A:collection/>
+                        <B:calendar/>
+                    </A:resourcetype>
+                    {}
+                </A:prop>
+            </A:set>
+        </A:mkcol>
+        "#,
+        calendar_props(name, supported_components, color, properties),
+    )
+}
+
+/// The value of a `calendar-color` property, that Apple clients write as `#RRGGBBAA`
+fn color_property_value(color: &Color) -> String {
+    let (r, g, b, a) = color.rgba_u8();
+    format!("#{:02X}{:02X}{:02X}{:02X}", r, g, b, a)
+}
+
+/// The properties of a calendar to create, with the `A` prefix for the `DAV:` namespace, and `B` for the CalDAV namespace
+fn calendar_props(name: String, supported_components: SupportedComponents, color: Option<Color>, properties: &CalendarProperties) -> String {
+    let color_property = match color {
+        None => "".to_string(),
+        Some(color) => format!("<D:calendar-color xmlns:D=\"http://apple.com/ns/ical/\">{}</D:calendar-color>", color_property_value(&color)),
+    };
+    let description_property = match &properties.description {
+        None => "".to_string(),
+        Some(description) => format!("<B:calendar-description>{}</B:calendar-description>", escape_xml(description)),
+    };
+    let order_property = match properties.order {
+        None => "".to_string(),
+        Some(order) => format!("<D:calendar-order xmlns:D=\"http://apple.com/ns/ical/\">{}</D:calendar-order>", order),
+    };
+    let timezone_property = match &properties.timezone {
+        None => "".to_string(),
+        Some(timezone) => format!("<B:calendar-timezone>{}</B:calendar-timezone>", escape_xml(timezone)),
+    };
+
+    format!(r#"<A:displayname>{}</A:displayname>
+                    {}
+                    {}
+                    {}
+                    {}
+                    {}"#,
+        escape_xml(&name),
         color_property,
+        description_property,
+        order_property,
+        timezone_property,
         supported_components.to_xml_string(),
     )
 }
@@ -1,12 +1,408 @@
 //! Support for library configuration options
+//!
+//! Options are stored in a [`Config`], that is given to every [`Client`](crate::client::Client) or [`Provider`](crate::provider::Provider) that should not use the default values.
+//! This way, several providers in a single process can use different settings. \
+//! Most values can be tuned depending on the server: e.g. a slow home server is better served with small batches, few concurrent uploads and a long retry backoff, while a hosted server can handle much more.
 
-use std::sync::{Arc, Mutex};
-use once_cell::sync::Lazy;
+use std::time::Duration;
 
-/// Part of the ProdID string that describes the organization (example of a ProdID string: `-//ABC Corporation//My Product//EN`).
-/// Feel free to override it when initing this library.
-pub static ORG_NAME: Lazy<Arc<Mutex<String>>> = Lazy::new(|| Arc::new(Mutex::new("My organization".to_string())));
+use chrono::{DateTime, Utc};
+#[cfg(feature = "client")]
+use reqwest::{RequestBuilder, Response};
 
-/// Part of the ProdID string that describes the product name (example of a ProdID string: `-//ABC Corporation//My Product//EN`).
-/// Feel free to override it when initing this library.
-pub static PRODUCT_NAME: Lazy<Arc<Mutex<String>>> = Lazy::new(|| Arc::new(Mutex::new("KitchenFridge".to_string())));
+use crate::ical::RepairOptions;
+use crate::metrics::SharedMetrics;
+#[cfg(feature = "client")]
+use crate::resource::ServerClock;
+
+/// The library used to establish TLS connections to the server.
+///
+/// Backends are enabled by Cargo features: `rustls` (enabled by default) and `native-tls` (that uses the TLS library of the system, e.g. OpenSSL)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TlsBackend {
+    /// A TLS library written in Rust, that does not depend on system libraries (useful e.g. for static builds)
+    #[cfg(feature = "rustls")]
+    Rustls,
+    /// The TLS library of the system (OpenSSL on Linux, Secure Transport on macOS, SChannel on Windows)
+    #[cfg(feature = "native-tls")]
+    NativeTls,
+}
+
+/// The versions of HTTP that are used to talk to the server
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HttpVersion {
+    /// HTTP/2 is used with HTTPS servers that offer it (it is negotiated during the TLS handshake), HTTP/1.1 otherwise
+    #[default]
+    Auto,
+    /// Only use HTTP/1.1 (e.g. for servers behind proxies that handle HTTP/2 badly)
+    Http1Only,
+    /// Only use HTTP/2, without negotiating it first. This is also the only way to use HTTP/2 with plain HTTP servers
+    Http2Only,
+}
+
+/// The kinds of HTTP requests, that can be retried differently (see [`Config::read_retries`] and [`Config::write_retries`])
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OperationClass {
+    /// Requests that do not change anything on the server (`GET`, `HEAD`, `OPTIONS`, `PROPFIND` and `REPORT`)
+    Read,
+    /// Every other request (e.g. `PUT`, `DELETE`, `MKCALENDAR` or `PROPPATCH`)
+    Write,
+}
+
+impl OperationClass {
+    /// The class of an HTTP method (e.g. `"PROPFIND"`)
+    pub fn from_method(method: &str) -> Self {
+        match method.to_ascii_uppercase().as_str() {
+            "GET" | "HEAD" | "OPTIONS" | "PROPFIND" | "REPORT" => OperationClass::Read,
+            _ => OperationClass::Write,
+        }
+    }
+}
+
+/// What a sync does with a local calendar that has been synced before, once it has disappeared from the remote source (e.g. because it has been deleted from another device)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RemoteRemovalPolicy {
+    /// The local calendar is deleted as well
+    #[default]
+    Delete,
+    /// The local calendar is kept, with its items, but it is not synced anymore (see [`CalendarSyncStatus::Archived`](crate::calendar::CalendarSyncStatus::Archived))
+    Archive,
+    /// The local calendar is kept until the app decides what to do with it, e.g. after asking the user (see [`Provider::resolve_remote_removal`](crate::provider::Provider::resolve_remote_removal))
+    Ask,
+}
+
+/// The error of a request the server has turned down for the time being (`503 Service Unavailable` or `429 Too Many Requests`),
+/// and that should not be sent again before the date its `Retry-After` header tells.
+///
+/// A sync does not try to push an item again before that date, in case it gets this error for this item (see [`CompleteCalendar::retry_not_before`](crate::traits::CompleteCalendar::retry_not_before))
+#[derive(Clone, Debug)]
+pub struct RetryLaterError {
+    status: u16,
+    not_before: DateTime<Utc>,
+}
+
+impl RetryLaterError {
+    pub fn new(status: u16, not_before: DateTime<Utc>) -> Self {
+        Self { status, not_before }
+    }
+
+    /// Read the `Retry-After` header of a response, in case its status tells to retry later
+    #[cfg(feature = "client")]
+    pub(crate) fn from_response(response: &Response, server_clock: &ServerClock) -> Option<Self> {
+        let status = response.status().as_u16();
+        if status != 503 && status != 429 {
+            return None;
+        }
+        // This is either a number of seconds, or a date
+        let retry_after = response.headers().get(reqwest::header::RETRY_AFTER)?.to_str().ok()?.trim();
+        let not_before = match retry_after.parse::<i64>() {
+            Ok(seconds) => crate::deterministic::now() + chrono::Duration::seconds(seconds),
+            Err(_) => DateTime::parse_from_rfc2822(retry_after).ok()?.with_timezone(&Utc) - server_clock.offset(),
+        };
+        Some(Self { status, not_before })
+    }
+
+    /// The HTTP status code of the response
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// The date before which the request should not be sent again
+    pub fn not_before(&self) -> DateTime<Utc> {
+        self.not_before
+    }
+}
+
+impl std::fmt::Display for RetryLaterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The server cannot handle this request for now (HTTP {}), it should not be sent again before {}", self.status, self.not_before)
+    }
+}
+
+impl std::error::Error for RetryLaterError {}
+
+/// How requests that failed because the server could not be reached (or timed out) are sent again
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// How many times a request is sent again
+    pub max_retries: u32,
+    /// How long to wait before sending a failed request again. This delay doubles after every retry
+    pub backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Requests are never sent again
+    pub fn never() -> Self {
+        Self { max_retries: 0, backoff: Duration::ZERO }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self { max_retries: 2, backoff: Duration::from_millis(500) }
+    }
+}
+
+/// The options of a [`Client`](crate::client::Client) or a [`Provider`](crate::provider::Provider)
+///
+/// ```
+/// use std::time::Duration;
+/// use kitchen_fridge::config::Config;
+///
+/// let config = Config {
+///     org_name: String::from("ABC Corporation"),
+///     product_name: String::from("My Product"),
+///     request_timeout: Some(Duration::from_secs(10)),
+///     ..Config::default()
+/// };
+/// assert_eq!(config.prod_id(), "-//ABC Corporation//My Product//EN");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// Part of the ProdID string that describes the organization (example of a ProdID string: `-//ABC Corporation//My Product//EN`).
+    pub org_name: String,
+    /// Part of the ProdID string that describes the product name (example of a ProdID string: `-//ABC Corporation//My Product//EN`).
+    pub product_name: String,
+    /// How long to wait for the server to answer an HTTP request (`None` waits forever)
+    pub request_timeout: Option<Duration>,
+    /// The TLS library to use (`None` to use the default one among the enabled ones)
+    pub tls_backend: Option<TlsBackend>,
+    /// The versions of HTTP to use. HTTP/2 sends every request over a single connection, which makes syncs that send many requests (e.g. many concurrent uploads) much faster
+    pub http_version: HttpVersion,
+    /// How long an unused connection to the server is kept open, so that the next requests do not have to connect again (`None` keeps it open until the server closes it)
+    pub pool_idle_timeout: Option<Duration>,
+    /// How many unused connections to the same server are kept open at most
+    pub pool_max_idle_per_host: usize,
+    /// How many items will be batched in a single HTTP request when downloading from the server
+    pub download_batch_size: usize,
+    /// How many items can be uploaded to the server at the same time during a sync
+    pub upload_concurrency: usize,
+    /// How many calendars have their version tags fetched at the same time, at the start of a sync. \
+    /// With `1`, the version tags of a calendar are only fetched when this calendar is synced.
+    pub tag_prefetch_concurrency: usize,
+    /// How requests that only read from the server (see [`OperationClass::Read`]) are sent again, in case they failed because the server could not be reached (or timed out)
+    pub read_retries: RetryPolicy,
+    /// How requests that change something on the server (see [`OperationClass::Write`]) are sent again, in case they failed because the server could not be reached (or timed out). \
+    /// Such a request may have reached the server even though it timed out: use [`RetryPolicy::never`] in case sending it twice (e.g. a `DELETE`) is not acceptable.
+    pub write_retries: RetryPolicy,
+    /// The mistakes of the iCal files downloaded from the server that are fixed before they are parsed (see [`crate::ical::repair`])
+    pub repair: RepairOptions,
+    /// Where to record the activity of the client and the provider (see the [`metrics`](crate::metrics) module), if anywhere
+    pub metrics: Option<SharedMetrics>,
+    /// Whether HTTP requests carry their identifiers in an `X-Request-Id` header (see the [`correlation`](crate::correlation) module)
+    pub request_id_header: bool,
+    /// How far the clock of the server may be from the local clock before a warning is logged (see [`Client::clock_skew`](crate::client::Client::clock_skew)). \
+    /// Some decisions compare dates set by the server or by other clients with local dates (e.g. the `LAST-MODIFIED` dates of the items, against [`Config::sync_window_days`]), and may be wrong beyond that.
+    pub clock_skew_tolerance: Duration,
+    /// Whether the dates a [`Client`](crate::client::Client) exchanges with its server (e.g. the start of the [sync window](Config::sync_window_days), or the dates of `Retry-After` headers) are shifted to match the clock of the server,
+    /// once it has been found too far from the local clock (see [`Config::clock_skew_tolerance`]). \
+    /// This only affects this client: the [`Clock`](crate::deterministic::Clock) of the process is left unchanged. Apps can date their own changes with [`Client::server_now`](crate::client::Client::server_now).
+    pub compensate_clock_skew: bool,
+    /// How long to wait for the server when checking whether it can be reached, before a sync (see [`CalDavSource::check_reachable`](crate::traits::CalDavSource::check_reachable))
+    pub reachability_timeout: Duration,
+    /// Whether items whose version tag has changed on the server are compared with their local copies, before they are written locally. \
+    /// Some servers change the version tags of items that have not changed. With this option, such items are not rewritten (and [observers](crate::traits::ItemObserver) are not notified); only their version tags are updated.
+    pub skip_identical_content: bool,
+    /// Whether the local source of a [`Provider`](crate::provider::Provider) is saved (see [`CalDavSource::persist`](crate::traits::CalDavSource::persist)) after every successful sync. \
+    /// Otherwise, the app has to save it (e.g. with [`Cache::save_to_folder`](crate::cache::Cache::save_to_folder)), or the changes of the sync are lost in case it crashes.
+    pub save_after_sync: bool,
+    /// Only sync the items that have been modified during this many last days (`None` to sync every item). \
+    /// This is meant for calendars that hold years of completed tasks, whose users only care about the recent activity. Older items are neither downloaded nor considered deleted in case they are missing from the server, but local changes are always pushed.
+    /// Servers that cannot filter items by modification time simply send more of them.
+    pub sync_window_days: Option<u32>,
+    /// What happens to the local calendars that have disappeared from the remote source
+    pub remote_removal: RemoteRemovalPolicy,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            org_name: "My organization".to_string(),
+            product_name: "KitchenFridge".to_string(),
+            request_timeout: None,
+            tls_backend: None,
+            http_version: HttpVersion::Auto,
+            pool_idle_timeout: Some(Duration::from_secs(90)),
+            pool_max_idle_per_host: usize::MAX,
+            download_batch_size: if cfg!(test) { 3 } else { 30 },
+            upload_concurrency: 1,
+            tag_prefetch_concurrency: 4,
+            read_retries: RetryPolicy::default(),
+            write_retries: RetryPolicy::default(),
+            repair: RepairOptions::default(),
+            metrics: None,
+            request_id_header: false,
+            clock_skew_tolerance: Duration::from_secs(120),
+            compensate_clock_skew: false,
+            reachability_timeout: Duration::from_secs(10),
+            skip_identical_content: false,
+            save_after_sync: false,
+            sync_window_days: None,
+            remote_removal: RemoteRemovalPolicy::default(),
+        }
+    }
+}
+
+impl Config {
+    /// The ProdID string of the items created with this configuration (e.g. `-//ABC Corporation//My Product//EN`)
+    pub fn prod_id(&self) -> String {
+        format!("-//{}//{}//EN", self.org_name, self.product_name)
+    }
+
+    /// The date before which items are not synced, see [`Config::sync_window_days`]
+    pub fn sync_window_start(&self) -> Option<DateTime<Utc>> {
+        self.sync_window_days.map(|days| crate::deterministic::now() - chrono::Duration::days(days as i64))
+    }
+
+    /// The retry policy of a class of requests
+    pub fn retry_policy(&self, class: OperationClass) -> &RetryPolicy {
+        match class {
+            OperationClass::Read => &self.read_retries,
+            OperationClass::Write => &self.write_retries,
+        }
+    }
+
+    /// Build an HTTP client that honours this configuration.
+    ///
+    /// Connections are kept open by the client, so that it should be reused for every request to the same server
+    #[cfg(feature = "client")]
+    pub(crate) fn http_client(&self) -> reqwest::Client {
+        let mut builder = reqwest::Client::builder()
+            .pool_idle_timeout(self.pool_idle_timeout)
+            .pool_max_idle_per_host(self.pool_max_idle_per_host);
+        if let Some(timeout) = self.request_timeout {
+            builder = builder.timeout(timeout);
+        }
+        match self.http_version {
+            HttpVersion::Auto => (),
+            HttpVersion::Http1Only => builder = builder.http1_only(),
+            HttpVersion::Http2Only => builder = builder.http2_prior_knowledge(),
+        }
+        if let Some(tls_backend) = self.tls_backend {
+            match tls_backend {
+                #[cfg(feature = "rustls")]
+                TlsBackend::Rustls => builder = builder.use_rustls_tls(),
+                #[cfg(feature = "native-tls")]
+                TlsBackend::NativeTls => builder = builder.use_native_tls(),
+            }
+        }
+        builder.build().unwrap_or_else(|err| {
+            log::warn!(target: crate::logging::CLIENT, "Unable to build a configured HTTP client ({}), using the default one", err);
+            reqwest::Client::new()
+        })
+    }
+
+    /// Send an HTTP request, and send it again (according to the [`retry_policy`](Self::retry_policy) of its method, waiting longer and longer) in case the server could not be reached.
+    ///
+    /// Requests that got an answer (even an HTTP error) are not sent again. Every attempt is recorded to the [`metrics`](Self::metrics), if any. \
+    /// Every request gets an identifier, that is logged (see the [`correlation`](crate::correlation) module). Retries keep the same identifier.
+    #[cfg(feature = "client")]
+    pub(crate) async fn send(&self, request: RequestBuilder) -> Result<Response, reqwest::Error> {
+        self.send_with_policy(request, None).await
+    }
+
+    /// Send an HTTP request only once, even in case the server could not be reached. See [`Self::send`]
+    #[cfg(feature = "client")]
+    pub(crate) async fn send_once(&self, request: RequestBuilder) -> Result<Response, reqwest::Error> {
+        self.send_with_policy(request, Some(RetryPolicy::never())).await
+    }
+
+    /// Send an HTTP request, and retry it according to `policy` (or to the retry policy of its method in case this is `None`)
+    #[cfg(feature = "client")]
+    async fn send_with_policy(&self, request: RequestBuilder, policy: Option<RetryPolicy>) -> Result<Response, reqwest::Error> {
+        let request_id = crate::correlation::new_request_id();
+        let mut request = match self.request_id_header {
+            true => request.header(crate::correlation::REQUEST_ID_HEADER, &request_id),
+            false => request,
+        };
+        // Building a copy of the request is the only way to know its method, URL and body
+        let (method, url, body_size) = match request.try_clone().and_then(|copy| copy.build().ok()) {
+            None => (String::new(), String::new(), 0),
+            Some(built) => (built.method().to_string(), built.url().to_string(), built.body().and_then(|body| body.as_bytes()).map(|bytes| bytes.len() as u64).unwrap_or(0)),
+        };
+        let policy = policy.unwrap_or_else(|| *self.retry_policy(OperationClass::from_method(&method)));
+        let mut retries_left = policy.max_retries;
+        let mut backoff = policy.backoff;
+        loop {
+            let retry = match retries_left {
+                0 => None,
+                _ => request.try_clone(),
+            };
+            log::debug!(target: crate::logging::CLIENT, "[request {}] {} {}", request_id, method, url);
+            let start = std::time::Instant::now();
+            let result = request.send().await;
+            match &result {
+                Ok(response) => log::debug!(target: crate::logging::CLIENT, "[request {}] HTTP {} after {:?}", request_id, response.status(), start.elapsed()),
+                Err(err) => log::debug!(target: crate::logging::CLIENT, "[request {}] Failed after {:?}: {}", request_id, start.elapsed(), err),
+            }
+            if let Some(metrics) = &self.metrics {
+                metrics.request(&method, start.elapsed(), result.as_ref().ok().map(|response| response.status().as_u16()));
+                let received = result.as_ref().ok().and_then(|response| response.content_length()).unwrap_or(0);
+                metrics.bytes_transferred(body_size, received);
+            }
+            match (result, retry) {
+                (Err(err), Some(retry)) if err.is_connect() || err.is_timeout() => {
+                    log::warn!(target: crate::logging::CLIENT, "HTTP request {} failed ({}), retrying in {:?}", request_id, err, backoff);
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    retries_left -= 1;
+                    request = retry;
+                },
+                (result, _) => return result,
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_operation_classes() {
+        for method in ["GET", "HEAD", "OPTIONS", "PROPFIND", "REPORT", "propfind"] {
+            assert_eq!(OperationClass::from_method(method), OperationClass::Read, "{}", method);
+        }
+        for method in ["PUT", "DELETE", "MKCALENDAR", "MKCOL", "PROPPATCH", "POST"] {
+            assert_eq!(OperationClass::from_method(method), OperationClass::Write, "{}", method);
+        }
+
+        let config = Config { write_retries: RetryPolicy::never(), ..Config::default() };
+        assert_eq!(config.retry_policy(OperationClass::Read), &RetryPolicy::default());
+        assert_eq!(config.retry_policy(OperationClass::Write).max_retries, 0);
+    }
+
+    #[cfg(feature = "client")]
+    #[tokio::test]
+    async fn test_retries_per_operation_class() {
+        use std::sync::{Arc, Mutex};
+        use crate::metrics::{Metrics, SharedMetrics};
+
+        #[derive(Debug, Default)]
+        struct Attempts(Mutex<Vec<String>>);
+        impl Metrics for Attempts {
+            fn request(&self, method: &str, _latency: Duration, _status: Option<u16>) {
+                self.0.lock().unwrap().push(method.to_string());
+            }
+        }
+
+        // Nothing listens on this port anymore, so that every request fails to connect
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/calendar/", listener.local_addr().unwrap());
+        drop(listener);
+
+        let attempts = Arc::new(Attempts::default());
+        let config = Config {
+            read_retries: RetryPolicy { max_retries: 3, backoff: Duration::from_millis(1) },
+            write_retries: RetryPolicy::never(),
+            metrics: Some(SharedMetrics::new(attempts.clone())),
+            ..Config::default()
+        };
+        let client = config.http_client();
+        assert!(config.send(client.request(reqwest::Method::from_bytes(b"PROPFIND").unwrap(), &url)).await.is_err());
+        assert!(config.send(client.delete(&url)).await.is_err());
+
+        assert_eq!(*attempts.0.lock().unwrap(), vec!["PROPFIND", "PROPFIND", "PROPFIND", "PROPFIND", "DELETE"]);
+    }
+}
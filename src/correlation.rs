@@ -0,0 +1,70 @@
+//! Identifiers that correlate the log messages of a sync with the HTTP requests it sends
+//!
+//! Every sync gets its own identifier, that prefixes its log messages (e.g. `[sync 3f2a9c01d4e7] Syncing calendar Tasks`). \
+//! Every HTTP request gets an identifier as well, that starts with the identifier of the sync it belongs to (e.g. `3f2a9c01d4e7-8b1e0f6a2c93`).
+//! It is logged along with the request, and it can be sent to the server as an `X-Request-Id` header (see [`Config::request_id_header`](crate::config::Config::request_id_header)),
+//! so that a failure seen in the logs of the server can be matched with the logs of this crate.
+
+use std::future::Future;
+
+/// The name of the HTTP header request identifiers are sent in
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+tokio::task_local! {
+    static SYNC_ID: String;
+}
+
+/// A new random identifier (12 hexadecimal digits).
+///
+/// These do not come from the [`UuidSource`](crate::deterministic::UuidSource) of the [`deterministic`](crate::deterministic) module:
+/// otherwise, the identifiers of the items a run creates would depend on how many HTTP requests it has sent
+pub fn new_id() -> String {
+    let uuid = uuid::Uuid::new_v4().to_simple().to_string();
+    uuid[uuid.len() - 12..].to_string()
+}
+
+/// The identifier of the sync the current task is running, if any
+pub fn current_sync_id() -> Option<String> {
+    SYNC_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Run `f` as part of the sync `sync_id`: the HTTP requests it sends get identifiers that start with `sync_id`.
+///
+/// Note that tasks spawned by `f` do not belong to the sync, unless they are run with this function as well
+pub async fn in_sync<F: Future>(sync_id: Option<String>, f: F) -> F::Output {
+    match sync_id {
+        Some(sync_id) => SYNC_ID.scope(sync_id, f).await,
+        None => f.await,
+    }
+}
+
+/// A new identifier for an HTTP request, that starts with the identifier of the current sync (if any)
+pub fn new_request_id() -> String {
+    match current_sync_id() {
+        Some(sync_id) => format!("{}-{}", sync_id, new_id()),
+        None => new_id(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_request_ids() {
+        assert_eq!(current_sync_id(), None);
+        assert_eq!(new_request_id().len(), 12);
+
+        let sync_id = new_id();
+        let (current, request_id) = in_sync(Some(sync_id.clone()), async { (current_sync_id(), new_request_id()) }).await;
+        assert_eq!(current, Some(sync_id.clone()));
+        assert!(request_id.starts_with(&format!("{}-", sync_id)));
+
+        // Spawned tasks keep the identifier of the sync, in case they are told to
+        let spawned = in_sync(Some(sync_id.clone()), async {
+            tokio::spawn(in_sync(current_sync_id(), async { current_sync_id() })).await.unwrap()
+        }).await;
+        assert_eq!(spawned, Some(sync_id));
+    }
+}
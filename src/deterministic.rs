@@ -0,0 +1,158 @@
+//! Sources of time and randomness, that can be made deterministic for reproducible runs
+//!
+//! Every date this crate generates by itself (e.g. the creation date of a [`Task`](crate::Task), or its last modification date) comes from a [`Clock`],
+//! and every random identifier (e.g. the URL or UID of a new task, or a [`VersionTag`](crate::item::VersionTag)) comes from a [`UuidSource`]. \
+//! By default, these are the system clock and random v4 UUIDs. Tests and debugging sessions can replace them, so that two runs generate exactly the same data.
+//!
+//! These settings are global to the process.
+//!
+//! ```
+//! use chrono::{TimeZone, Utc};
+//! use url::Url;
+//! use kitchen_fridge::{deterministic, Task};
+//!
+//! let cal_url = Url::parse("https://my.server.com/calendars/john/tasks/").unwrap();
+//!
+//! deterministic::enable(Utc.ymd(2021, 3, 21).and_hms(9, 0, 0));
+//! let first = Task::new(String::from("Buy milk"), false, &cal_url);
+//! deterministic::enable(Utc.ymd(2021, 3, 21).and_hms(9, 0, 0));
+//! let second = Task::new(String::from("Buy milk"), false, &cal_url);
+//! deterministic::disable();
+//!
+//! assert_eq!(first.uid(), second.uid());
+//! assert_eq!(first.url(), second.url());
+//! assert_eq!(first.creation_date(), second.creation_date());
+//! ```
+
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use chrono::{DateTime, Duration, Utc};
+use once_cell::sync::Lazy;
+use uuid::Uuid;
+
+/// Something that tells the current date
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// Something that generates unique identifiers
+pub trait UuidSource: Debug + Send + Sync {
+    fn new_uuid(&self) -> Uuid;
+}
+
+/// The system clock. This is the default [`Clock`]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// The system clock, shifted by a fixed offset (e.g. to match the clock of a server, see [`Client::clock_skew`](crate::client::Client::clock_skew))
+#[derive(Clone, Copy, Debug)]
+pub struct OffsetClock {
+    offset: Duration,
+}
+
+impl OffsetClock {
+    pub fn new(offset: Duration) -> Self {
+        Self { offset }
+    }
+}
+
+impl Clock for OffsetClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now() + self.offset
+    }
+}
+
+/// A clock that starts at a given date, and moves forward by a fixed step every time it is read.
+///
+/// Moving forward makes sure that successive changes still have increasing modification dates
+#[derive(Debug)]
+pub struct SteppingClock {
+    next: Mutex<DateTime<Utc>>,
+    step: Duration,
+}
+
+impl SteppingClock {
+    pub fn new(start: DateTime<Utc>, step: Duration) -> Self {
+        Self { next: Mutex::new(start), step }
+    }
+}
+
+impl Clock for SteppingClock {
+    fn now(&self) -> DateTime<Utc> {
+        let mut next = self.next.lock().unwrap();
+        let now = *next;
+        *next = now + self.step;
+        now
+    }
+}
+
+/// Random (v4) UUIDs. This is the default [`UuidSource`]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RandomUuids;
+
+impl UuidSource for RandomUuids {
+    fn new_uuid(&self) -> Uuid {
+        Uuid::new_v4()
+    }
+}
+
+/// UUIDs that are numbered in sequence, starting from 1 (`00000000-0000-0000-0000-000000000001`)
+#[derive(Debug, Default)]
+pub struct SequentialUuids {
+    last: AtomicU64,
+}
+
+impl UuidSource for SequentialUuids {
+    fn new_uuid(&self) -> Uuid {
+        let n = self.last.fetch_add(1, Ordering::SeqCst) + 1;
+        Uuid::from_u128(n as u128)
+    }
+}
+
+
+static CLOCK: Lazy<RwLock<Arc<dyn Clock>>> = Lazy::new(|| RwLock::new(Arc::new(SystemClock)));
+static UUID_SOURCE: Lazy<RwLock<Arc<dyn UuidSource>>> = Lazy::new(|| RwLock::new(Arc::new(RandomUuids)));
+
+/// Replace the clock used by this crate
+pub fn set_clock(clock: Arc<dyn Clock>) {
+    *CLOCK.write().unwrap() = clock;
+}
+
+/// Replace the source of identifiers used by this crate
+pub fn set_uuid_source(source: Arc<dyn UuidSource>) {
+    *UUID_SOURCE.write().unwrap() = source;
+}
+
+/// Make every generated date and identifier reproducible: the clock starts at `start` and moves forward by one second every time it is read, and UUIDs are numbered in sequence.
+///
+/// Calling this again starts over from the first identifier
+pub fn enable(start: DateTime<Utc>) {
+    set_clock(Arc::new(SteppingClock::new(start, Duration::seconds(1))));
+    set_uuid_source(Arc::new(SequentialUuids::default()));
+}
+
+/// Go back to the system clock and random UUIDs
+pub fn disable() {
+    set_clock(Arc::new(SystemClock));
+    set_uuid_source(Arc::new(RandomUuids));
+}
+
+/// The current date, according to the current [`Clock`]
+pub fn now() -> DateTime<Utc> {
+    let clock = CLOCK.read().unwrap().clone();
+    clock.now()
+}
+
+/// A new identifier, from the current [`UuidSource`]
+pub fn new_uuid() -> Uuid {
+    let source = UUID_SOURCE.read().unwrap().clone();
+    source.new_uuid()
+}
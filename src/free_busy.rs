@@ -0,0 +1,60 @@
+//! Free/busy information, as returned by a CalDAV server (see [RFC 4791](https://datatracker.ietf.org/doc/html/rfc4791#section-7.10))
+
+use chrono::{DateTime, Utc};
+
+/// The kind of a free/busy period (the `FBTYPE` parameter of RFC 5545)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FreeBusyType {
+    Free,
+    Busy,
+    BusyUnavailable,
+    BusyTentative,
+}
+
+impl FreeBusyType {
+    pub(crate) fn from_fb_type(fb_type: &str) -> Self {
+        match fb_type.to_uppercase().as_str() {
+            "FREE" => FreeBusyType::Free,
+            "BUSY-UNAVAILABLE" => FreeBusyType::BusyUnavailable,
+            "BUSY-TENTATIVE" => FreeBusyType::BusyTentative,
+            // RFC 5545 states that unknown values must be treated as BUSY
+            _ => FreeBusyType::Busy,
+        }
+    }
+}
+
+/// A time slot, and whether it is free or busy
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FreeBusyPeriod {
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    fb_type: FreeBusyType,
+}
+
+impl FreeBusyPeriod {
+    pub fn new(start: DateTime<Utc>, end: DateTime<Utc>, fb_type: FreeBusyType) -> Self {
+        Self { start, end, fb_type }
+    }
+
+    pub fn start(&self) -> &DateTime<Utc>   { &self.start }
+    pub fn end(&self) -> &DateTime<Utc>     { &self.end }
+    pub fn fb_type(&self) -> FreeBusyType   { self.fb_type }
+
+    /// Whether this period makes the time slot unavailable
+    pub fn is_busy(&self) -> bool {
+        self.fb_type != FreeBusyType::Free
+    }
+
+    /// Whether this period overlaps the given time range
+    pub fn overlaps(&self, start: &DateTime<Utc>, end: &DateTime<Utc>) -> bool {
+        &self.start < end && start < &self.end
+    }
+}
+
+/// Returns whether a time slot is free, given a list of free/busy periods
+pub fn is_free(periods: &[FreeBusyPeriod], start: &DateTime<Utc>, end: &DateTime<Utc>) -> bool {
+    periods.iter()
+        .filter(|period| period.is_busy())
+        .any(|period| period.overlaps(start, end))
+        == false
+}
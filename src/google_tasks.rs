@@ -0,0 +1,449 @@
+//! An adapter to the [Google Tasks REST API](https://developers.google.com/tasks/reference/rest), for Google accounts that do not expose their tasks over CalDAV
+//!
+//! [`GoogleTasksClient`] implements [`CalDavSource`] and [`GoogleTaskList`] implements [`DavCalendar`], so that they can be used as the remote end of a [`Provider`](crate::provider::Provider)
+//! (see [`GoogleTasksProvider`]). \
+//! This module requires the `google_tasks` feature. Getting an OAuth2 access token (with the `https://www.googleapis.com/auth/tasks` scope) is up to the application.
+//!
+//! Google assigns the IDs of the task lists and of the tasks, so that items (and calendars) created by this crate end up with a URL that differs from the one they have been created with.
+//! This adapter remembers these aliases, but only for its own lifetime: after a restart, the next sync will replace the local items with their counterparts that have Google URLs.
+#![cfg(feature = "google_tasks")]
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::sync::{Arc, Mutex, RwLock};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use csscolorparser::Color;
+use reqwest::Method;
+use reqwest::header::CONTENT_TYPE;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::Item;
+use crate::Task;
+use crate::calendar::SupportedComponents;
+use crate::item::{SyncStatus, VersionTag};
+use crate::resource::Resource;
+use crate::task::CompletionStatus;
+use crate::traits::{BaseCalendar, CalDavSource, DavCalendar};
+
+/// The root of the Google Tasks API
+pub static API_ROOT: &str = "https://tasks.googleapis.com/tasks/v1";
+
+/// A provider that syncs a local cache with Google Tasks
+pub type GoogleTasksProvider = crate::provider::Provider<crate::cache::Cache, crate::calendar::cached_calendar::CachedCalendar, GoogleTasksClient, GoogleTaskList>;
+
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Page<T> {
+    #[serde(default = "Vec::new")]
+    items: Vec<T>,
+    next_page_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GoogleTaskListData {
+    id: String,
+    title: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GoogleTaskData {
+    id: String,
+    etag: String,
+    title: Option<String>,
+    status: Option<String>,
+    updated: Option<DateTime<Utc>>,
+    completed: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GoogleTaskUpdate<'a> {
+    title: &'a str,
+    status: &'a str,
+    /// This must be explicitly `null` to mark a task as uncompleted
+    completed: Option<String>,
+}
+
+impl GoogleTaskData {
+    fn into_task(self, url: Url) -> Task {
+        let completion_status = match self.status.as_deref() {
+            Some("completed") => CompletionStatus::Completed(self.completed),
+            _ => CompletionStatus::Uncompleted,
+        };
+
+        Task::new_with_parameters(
+            self.title.unwrap_or_default(),
+            self.id,
+            url,
+            completion_status,
+            SyncStatus::Synced(VersionTag::from(self.etag)),
+            None,
+            self.updated.unwrap_or_else(Utc::now),
+            crate::ical::default_prod_id(),
+            Vec::new(),
+        )
+    }
+}
+
+fn update_body(item: &Item) -> Result<String, Box<dyn Error>> {
+    let task = match item {
+        Item::Task(task) => task,
+        Item::Event(_) => return Err("Google Tasks only supports tasks".into()),
+    };
+    let (status, completed) = match task.completion_status() {
+        CompletionStatus::Uncompleted => ("needsAction", None),
+        CompletionStatus::Completed(date) => ("completed", Some((*date).unwrap_or_else(Utc::now).to_rfc3339())),
+    };
+
+    Ok(serde_json::to_string(&GoogleTaskUpdate {
+        title: task.name(),
+        status,
+        completed,
+    })?)
+}
+
+/// Send a request to the Google Tasks API, and return the body of the reply
+async fn api_request(access_token: &str, method: Method, url: Url, body: Option<String>) -> Result<String, Box<dyn Error>> {
+    let mut request = reqwest::Client::new()
+        .request(method, url)
+        .bearer_auth(access_token);
+    if let Some(body) = body {
+        request = request
+            .header(CONTENT_TYPE, "application/json")
+            .body(body);
+    }
+
+    let res = request.send().await?;
+    if res.status().is_success() == false {
+        return Err(format!("Unexpected HTTP status code {:?}", res.status()).into());
+    }
+    Ok(res.text().await?)
+}
+
+/// Fetch every page of a paginated collection
+async fn api_list<T: for<'de> Deserialize<'de>>(access_token: &str, url: Url) -> Result<Vec<T>, Box<dyn Error>> {
+    let mut items = Vec::new();
+    let mut page_token: Option<String> = None;
+    loop {
+        let mut page_url = url.clone();
+        page_url.query_pairs_mut().append_pair("maxResults", "100");
+        if let Some(token) = &page_token {
+            page_url.query_pairs_mut().append_pair("pageToken", token);
+        }
+
+        let page: Page<T> = serde_json::from_str(&api_request(access_token, Method::GET, page_url, None).await?)?;
+        items.extend(page.items);
+        match page.next_page_token {
+            None => return Ok(items),
+            token => page_token = token,
+        }
+    }
+}
+
+fn list_url(list_id: &str) -> Result<Url, Box<dyn Error>> {
+    Ok(Url::parse(&format!("{}/lists/{}", API_ROOT, list_id))?)
+}
+
+
+
+/// A source of task lists, backed by the Google Tasks API
+#[derive(Debug)]
+pub struct GoogleTasksClient {
+    access_token: String,
+    calendars: Mutex<Option<HashMap<Url, Arc<RwLock<GoogleTaskList>>>>>,
+    /// Task lists that Google created with another URL than the requested one
+    calendar_aliases: Mutex<HashMap<Url, Url>>,
+}
+
+impl GoogleTasksClient {
+    /// Create a client. This does not start a connection
+    pub fn new<S: ToString>(access_token: S) -> Self {
+        Self {
+            access_token: access_token.to_string(),
+            calendars: Mutex::new(None),
+            calendar_aliases: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn populate_calendars(&self) -> Result<(), Box<dyn Error>> {
+        let url = Url::parse(&format!("{}/users/@me/lists", API_ROOT))?;
+        let lists: Vec<GoogleTaskListData> = api_list(&self.access_token, url).await?;
+
+        let mut known_calendars = self.calendars.lock().unwrap();
+        let mut calendars = HashMap::new();
+        for list in lists {
+            let url = list_url(&list.id)?;
+            // Keep the instances we already know of, since they hold the item aliases
+            let task_list = match known_calendars.as_ref().and_then(|cals| cals.get(&url)) {
+                Some(known) => known.clone(),
+                None => {
+                    log::info!("Found task list {}", list.title);
+                    Arc::new(RwLock::new(GoogleTaskList::new_with_token(list.title, url.clone(), self.access_token.clone())))
+                },
+            };
+            calendars.insert(url, task_list);
+        }
+
+        *known_calendars = Some(calendars);
+        Ok(())
+    }
+
+    fn resolve_calendar_url(&self, url: &Url) -> Url {
+        self.calendar_aliases.lock().unwrap().get(url).cloned().unwrap_or_else(|| url.clone())
+    }
+}
+
+#[async_trait]
+impl CalDavSource<GoogleTaskList> for GoogleTasksClient {
+    async fn get_calendars(&self) -> Result<HashMap<Url, Arc<RwLock<GoogleTaskList>>>, Box<dyn Error>> {
+        self.populate_calendars().await?;
+
+        let mut calendars = self.calendars.lock().unwrap().clone().ok_or("No calendars available")?;
+        // Make task lists reachable with the URLs they have been created with as well
+        for (alias, url) in self.calendar_aliases.lock().unwrap().iter() {
+            if let Some(cal) = calendars.get(url).cloned() {
+                calendars.insert(alias.clone(), cal);
+            }
+        }
+        Ok(calendars)
+    }
+
+    async fn get_calendar(&self, url: &Url) -> Option<Arc<RwLock<GoogleTaskList>>> {
+        if let Err(err) = self.populate_calendars().await {
+            log::warn!("Unable to fetch calendars: {}", err);
+            return None;
+        }
+
+        let url = self.resolve_calendar_url(url);
+        self.calendars.lock().unwrap()
+            .as_ref()
+            .and_then(|cals| cals.get(&url))
+            .cloned()
+    }
+
+    async fn create_calendar(&self, url: Url, name: String, supported_components: SupportedComponents, _color: Option<Color>) -> Result<Arc<RwLock<GoogleTaskList>>, Box<dyn Error>> {
+        if supported_components.intersects(SupportedComponents::TODO.complement()) {
+            return Err("Google Tasks only supports tasks".into());
+        }
+
+        let body = serde_json::json!({ "title": name }).to_string();
+        let created_url = Url::parse(&format!("{}/users/@me/lists", API_ROOT))?;
+        let reply = api_request(&self.access_token, Method::POST, created_url, Some(body)).await?;
+        let created: GoogleTaskListData = serde_json::from_str(&reply)?;
+
+        let google_url = list_url(&created.id)?;
+        if google_url != url {
+            log::info!("Task list {} has been created as {}", url, google_url);
+            self.calendar_aliases.lock().unwrap().insert(url.clone(), google_url.clone());
+        }
+
+        let task_list = Arc::new(RwLock::new(GoogleTaskList::new_with_token(created.title, google_url.clone(), self.access_token.clone())));
+        self.calendars.lock().unwrap()
+            .get_or_insert_with(HashMap::new)
+            .insert(google_url, task_list.clone());
+        Ok(task_list)
+    }
+
+    async fn delete_calendar(&self, url: &Url) -> Result<(), Box<dyn Error>> {
+        let google_url = self.resolve_calendar_url(url);
+        let list_id = google_url.path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .ok_or_else(|| format!("Invalid task list URL {}", google_url))?;
+        let delete_url = Url::parse(&format!("{}/users/@me/lists/{}", API_ROOT, list_id))?;
+        api_request(&self.access_token, Method::DELETE, delete_url, None).await?;
+
+        if let Some(cals) = self.calendars.lock().unwrap().as_mut() {
+            cals.remove(&google_url);
+        }
+        self.calendar_aliases.lock().unwrap().retain(|_alias, target| *target != google_url);
+        Ok(())
+    }
+
+    async fn check_reachable(&self) -> Result<(), Box<dyn Error>> {
+        // Any answer (even an HTTP error) means the API is reachable
+        reqwest::Client::new()
+            .head(API_ROOT)
+            .timeout(std::time::Duration::from_secs(10))
+            .send().await?;
+        Ok(())
+    }
+}
+
+
+
+/// A Google task list
+#[derive(Debug)]
+pub struct GoogleTaskList {
+    name: String,
+    url: Url,
+    access_token: String,
+
+    /// Items that Google created with another URL than the requested one (requested URL -> Google URL). \
+    /// It is shared with the detached copies of this list (see [`DavCalendar::detached`])
+    item_aliases: Arc<Mutex<HashMap<Url, Url>>>,
+}
+
+impl GoogleTaskList {
+    fn new_with_token(name: String, url: Url, access_token: String) -> Self {
+        Self { name, url, access_token, item_aliases: Arc::new(Mutex::new(HashMap::new())) }
+    }
+
+    fn tasks_url(&self) -> Result<Url, Box<dyn Error>> {
+        Ok(Url::parse(&format!("{}/tasks", self.url))?)
+    }
+
+    fn resolve_item_url(&self, url: &Url) -> Url {
+        self.item_aliases.lock().unwrap().get(url).cloned().unwrap_or_else(|| url.clone())
+    }
+
+    /// The URL this crate knows an item under
+    fn public_item_url(&self, google_url: Url) -> Url {
+        self.item_aliases.lock().unwrap()
+            .iter()
+            .find(|(_alias, url)| **url == google_url)
+            .map(|(alias, _url)| alias.clone())
+            .unwrap_or(google_url)
+    }
+
+    async fn get_google_tasks(&self) -> Result<Vec<GoogleTaskData>, Box<dyn Error>> {
+        let mut url = self.tasks_url()?;
+        url.query_pairs_mut()
+            .append_pair("showCompleted", "true")
+            .append_pair("showHidden", "true");
+        api_list(&self.access_token, url).await
+    }
+}
+
+#[async_trait]
+impl BaseCalendar for GoogleTaskList {
+    fn name(&self) -> &str { &self.name }
+    fn url(&self) -> &Url { &self.url }
+    fn supported_components(&self) -> SupportedComponents {
+        SupportedComponents::TODO
+    }
+    fn color(&self) -> Option<&Color> {
+        None
+    }
+
+    async fn add_item(&mut self, item: Item) -> Result<SyncStatus, Box<dyn Error>> {
+        let tasks_url = self.tasks_url()?;
+        let body = update_body(&item)?;
+        let reply = api_request(&self.access_token, Method::POST, tasks_url.clone(), Some(body)).await?;
+        let created: GoogleTaskData = serde_json::from_str(&reply)?;
+
+        let google_url = Url::parse(&format!("{}/{}", tasks_url, created.id))?;
+        if &google_url != item.url() {
+            self.item_aliases.lock().unwrap().insert(item.url().clone(), google_url);
+        }
+        Ok(SyncStatus::Synced(VersionTag::from(created.etag)))
+    }
+
+    async fn update_item(&mut self, item: Item) -> Result<SyncStatus, Box<dyn Error>> {
+        let url = self.resolve_item_url(item.url());
+        let body = update_body(&item)?;
+        let reply = api_request(&self.access_token, Method::PATCH, url, Some(body)).await?;
+        let updated: GoogleTaskData = serde_json::from_str(&reply)?;
+        Ok(SyncStatus::Synced(VersionTag::from(updated.etag)))
+    }
+}
+
+#[async_trait]
+impl DavCalendar for GoogleTaskList {
+    /// Create a task list. The OAuth2 access token must be given as the password of the `resource`
+    fn new(name: String, resource: Resource, _supported_components: SupportedComponents, _color: Option<Color>) -> Self {
+        Self::new_with_token(name, resource.url().clone(), resource.password().clone())
+    }
+
+    fn detached(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            url: self.url.clone(),
+            access_token: self.access_token.clone(),
+            item_aliases: Arc::clone(&self.item_aliases),
+        }
+    }
+
+    async fn get_item_version_tags(&self) -> Result<HashMap<Url, VersionTag>, Box<dyn Error>> {
+        let tasks_url = self.tasks_url()?;
+        let mut tags = HashMap::new();
+        for task in self.get_google_tasks().await? {
+            let url = self.public_item_url(Url::parse(&format!("{}/{}", tasks_url, task.id))?);
+            tags.insert(url, VersionTag::from(task.etag));
+        }
+        Ok(tags)
+    }
+
+    async fn get_item_by_url(&self, url: &Url) -> Result<Option<Item>, Box<dyn Error>> {
+        let reply = api_request(&self.access_token, Method::GET, self.resolve_item_url(url), None).await?;
+        let task: GoogleTaskData = serde_json::from_str(&reply)?;
+        Ok(Some(Item::Task(task.into_task(url.clone()))))
+    }
+
+    async fn get_items_by_url(&self, urls: &[Url]) -> Result<Vec<Option<Item>>, Box<dyn Error>> {
+        // Listing a whole task list is cheaper than fetching its tasks one by one
+        let tasks_url = self.tasks_url()?;
+        let mut tasks: HashMap<Url, GoogleTaskData> = HashMap::new();
+        for task in self.get_google_tasks().await? {
+            let url = self.public_item_url(Url::parse(&format!("{}/{}", tasks_url, task.id))?);
+            tasks.insert(url, task);
+        }
+
+        Ok(urls.iter()
+            .map(|url| tasks.remove(url).map(|task| Item::Task(task.into_task(url.clone()))))
+            .collect())
+    }
+
+    async fn delete_item(&mut self, item_url: &Url) -> Result<(), Box<dyn Error>> {
+        api_request(&self.access_token, Method::DELETE, self.resolve_item_url(item_url), None).await?;
+        self.item_aliases.lock().unwrap().remove(item_url);
+        Ok(())
+    }
+
+    async fn get_item_urls(&self) -> Result<HashSet<Url>, Box<dyn Error>> {
+        Ok(self.get_item_version_tags().await?.into_keys().collect())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_task_conversion() {
+        let reply = r#"{
+            "kind": "tasks#task",
+            "id": "dGFzay1pZA",
+            "etag": "\"LTEyMzQ1Njc4OQ\"",
+            "title": "Buy milk",
+            "updated": "2021-03-21T00:16:00.000Z",
+            "status": "completed",
+            "completed": "2021-03-22T09:00:00.000Z"
+        }"#;
+        let url: Url = "https://tasks.googleapis.com/tasks/v1/lists/bGlzdA/tasks/dGFzay1pZA".parse().unwrap();
+        let data: GoogleTaskData = serde_json::from_str(reply).unwrap();
+        let task = data.into_task(url.clone());
+
+        assert_eq!(task.name(), "Buy milk");
+        assert_eq!(task.uid(), "dGFzay1pZA");
+        assert_eq!(task.url(), &url);
+        assert!(task.completed());
+        assert_eq!(task.sync_status(), &SyncStatus::Synced(VersionTag::from(String::from("\"LTEyMzQ1Njc4OQ\""))));
+
+        let body: serde_json::Value = serde_json::from_str(&update_body(&Item::Task(task)).unwrap()).unwrap();
+        assert_eq!(body["title"], "Buy milk");
+        assert_eq!(body["status"], "completed");
+        assert_eq!(body["completed"], "2021-03-22T09:00:00+00:00");
+
+        let uncompleted = Item::Task(Task::new(String::from("Walk the dog"), false, &url));
+        let body: serde_json::Value = serde_json::from_str(&update_body(&uncompleted).unwrap()).unwrap();
+        assert_eq!(body["status"], "needsAction");
+        assert!(body["completed"].is_null());
+    }
+}
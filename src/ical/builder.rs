@@ -1,10 +1,11 @@
 //! A module to build ICal files
 
 use std::error::Error;
+use std::io::Write;
 
 use chrono::{DateTime, Utc};
-use ics::properties::{Completed, Created, LastModified, PercentComplete, Status, Summary};
-use ics::{ICalendar, ToDo};
+use ics::properties::{Action, Completed, Created, LastModified, PercentComplete, Sequence, Status, Summary, Trigger};
+use ics::{Alarm as IcsAlarm, ICalendar, ToDo};
 use ics::components::Parameter as IcsParameter;
 use ics::components::Property as IcsProperty;
 use ical::property::Property as IcalProperty;
@@ -12,6 +13,9 @@ use ical::property::Property as IcalProperty;
 use crate::Task;
 use crate::item::Item;
 use crate::task::CompletionStatus;
+use crate::alarm::Alarm;
+use crate::ical::param;
+use crate::utils::time_zone::IcalDateTime;
 
 
 /// Create an iCal item from a `crate::item::Item`
@@ -22,19 +26,71 @@ pub fn build_from(item: &Item) -> Result<String, Box<dyn Error>> {
     }
 }
 
+/// Write a single iCal calendar that contains several items into `writer` (e.g. to export a whole calendar). Items are written one at a time, so that large calendars are never built in memory.
+///
+/// `name` is advertised with the (widely supported) `X-WR-CALNAME` property.
+/// Dates that are expressed in a time zone are written in UTC, since the `VTIMEZONE` components that would define these time zones are not known. \
+/// Events are not supported yet, they are skipped.
+pub fn write_calendar<'a, W, I>(writer: &mut W, prod_id: &str, name: &str, items: I) -> Result<(), Box<dyn Error>>
+where
+    W: Write + ?Sized,
+    I: IntoIterator<Item = &'a Item>,
+{
+    write!(writer, "BEGIN:VCALENDAR\r\n")?;
+    write!(writer, "{}", IcsProperty::new("VERSION", "2.0"))?;
+    write!(writer, "{}", IcsProperty::new("PRODID", prod_id))?;
+    write!(writer, "{}", IcsProperty::new("X-WR-CALNAME", ics::escape_text(name)))?;
+
+    for item in items {
+        match item {
+            Item::Task(task) => {
+                write!(writer, "{}", build_todo_in_utc(task))?;
+                for overridden in task.overrides() {
+                    write!(writer, "{}", build_todo_in_utc(overridden))?;
+                }
+            },
+            Item::Event(_) => log::warn!("Exporting events is not supported yet. Skipping {}", item.url()),
+        }
+    }
+
+    write!(writer, "END:VCALENDAR\r\n")?;
+    Ok(())
+}
+
 pub fn build_from_task(task: &Task) -> Result<String, Box<dyn Error>> {
-    let s_last_modified = format_date_time(task.last_modified());
+    let mut calendar = ICalendar::new("2.0", task.ical_prod_id());
+    calendar.add_todo(build_todo(task));
+    for overridden in task.overrides() {
+        calendar.add_todo(build_todo(overridden));
+    }
+
+    Ok(calendar.to_string())
+}
 
+/// Build the `VTODO` component of a task
+fn build_todo(task: &Task) -> ToDo<'_> {
+    build_todo_with(task, |prop| prop.clone())
+}
+
+/// Same as [`build_todo`], for a file that has no `VTIMEZONE` component: its dates are written in UTC (see [`date_in_utc`])
+fn build_todo_in_utc(task: &Task) -> ToDo<'_> {
+    build_todo_with(task, date_in_utc)
+}
+
+fn build_todo_with(task: &Task, convert: fn(&IcalProperty) -> IcalProperty) -> ToDo<'_> {
     let mut todo = ToDo::new(
         task.uid(),
-        s_last_modified.clone(),
+        format_date_time(task.dtstamp()),
     );
 
     task.creation_date().map(|dt|
         todo.push(Created::new(format_date_time(dt)))
     );
-    todo.push(LastModified::new(s_last_modified));
-    todo.push(Summary::new(task.name()));
+    todo.push(LastModified::new(format_date_time(task.last_modified())));
+    if let Some(sequence) = task.sequence() {
+        todo.push(Sequence::new(sequence.to_string()));
+    }
+    todo.push(Summary::new(ics::escape_text(task.name())));
 
     match task.completion_status() {
         CompletionStatus::Uncompleted => {
@@ -51,14 +107,70 @@ pub fn build_from_task(task: &Task) -> Result<String, Box<dyn Error>> {
 
     // Also add fields that we have not handled
     for ical_property in task.extra_parameters() {
-        let ics_property = ical_to_ics_property(ical_property.clone());
+        let ics_property = ical_to_ics_property(convert(ical_property));
         todo.push(ics_property);
     }
 
-    let mut calendar = ICalendar::new("2.0", task.ical_prod_id());
-    calendar.add_todo(todo);
+    for alarm in task.alarms() {
+        match build_alarm(alarm) {
+            Some(ics_alarm) => todo.add_alarm(ics_alarm),
+            None => log::warn!("Skipping an alarm of task {} that has no ACTION or no TRIGGER", task.uid()),
+        }
+    }
 
-    Ok(calendar.to_string())
+    todo
+}
+
+/// Build a `VALARM` component. RFC5545 requires it to have an `ACTION` and a `TRIGGER`
+fn build_alarm(alarm: &Alarm) -> Option<IcsAlarm<'static>> {
+    let properties = alarm.properties();
+    let action = properties.iter().find(|prop| prop.name == "ACTION")?;
+    let trigger = properties.iter().find(|prop| prop.name == "TRIGGER")?;
+
+    let mut ics_trigger = Trigger::new(trigger.value.clone().unwrap_or_default());
+    if let Some(params) = &trigger.params {
+        for (key, values) in params {
+            ics_trigger.add(IcsParameter::new(key.clone(), param_values(values)));
+        }
+    }
+    let mut ics_alarm = IcsAlarm::new(Action::new(action.value.clone().unwrap_or_default()), ics_trigger);
+    for prop in properties {
+        if prop.name != "ACTION" && prop.name != "TRIGGER" {
+            ics_alarm.push(ical_to_ics_property(prop.clone()));
+        }
+    }
+    Some(ics_alarm)
+}
+
+/// A copy of a property whose dates are expressed in a time zone (i.e. that has a `TZID` parameter), with these dates in UTC.
+///
+/// Other properties are left as they are, and so are dates in a time zone this crate does not know about.
+fn date_in_utc(prop: &IcalProperty) -> IcalProperty {
+    if param(prop, "TZID").is_none() {
+        return prop.clone();
+    }
+    // Some properties (e.g. `EXDATE`) have several values
+    let values: Option<Vec<String>> = prop.value.as_deref().unwrap_or_default().split(',')
+        .map(|value| {
+            let mut single = prop.clone();
+            single.value = Some(value.to_string());
+            match IcalDateTime::from_property(&single)? {
+                IcalDateTime::Zoned(zoned) => Some(IcalDateTime::Utc(zoned.with_timezone(&Utc)).to_property(&prop.name).value?),
+                _ => None,
+            }
+        })
+        .collect();
+    match values {
+        None => prop.clone(),
+        Some(values) => {
+            let mut converted = prop.clone();
+            converted.params = prop.params.as_ref()
+                .map(|params| params.iter().filter(|(key, _)| key.eq_ignore_ascii_case("TZID") == false).cloned().collect::<Vec<_>>())
+                .filter(|params| params.is_empty() == false);
+            converted.value = Some(values.join(","));
+            converted
+        },
+    }
 }
 
 fn format_date_time(dt: &DateTime<Utc>) -> String {
@@ -66,6 +178,17 @@ fn format_date_time(dt: &DateTime<Utc>) -> String {
 }
 
 
+/// The values of a parameter, as they are written in an iCal file: separated by commas (RFC5545 section 3.2), and quoted in case they contain a character that would end them
+fn param_values(values: &[String]) -> String {
+    values.iter()
+        .map(|value| match value.contains([',', ';', ':']) {
+            true => format!("\"{}\"", value),
+            false => value.clone(),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
 fn ical_to_ics_property(prop: IcalProperty) -> IcsProperty<'static> {
     let mut ics_prop = match prop.value {
         Some(value) => IcsProperty::new(prop.name, value),
@@ -73,8 +196,7 @@ fn ical_to_ics_property(prop: IcalProperty) -> IcsProperty<'static> {
     };
     prop.params.map(|v| {
         for (key, vec_values) in v {
-            let values = vec_values.join(";");
-            ics_prop.add(IcsParameter::new(key, values));
+            ics_prop.add(IcsParameter::new(key, param_values(&vec_values)));
         }
     });
     ics_prop
@@ -85,11 +207,12 @@ fn ical_to_ics_property(prop: IcalProperty) -> IcsProperty<'static> {
 mod tests {
     use super::*;
     use crate::Task;
-    use crate::config::{ORG_NAME, PRODUCT_NAME};
+    use crate::config::Config;
 
     #[test]
     fn test_ical_from_completed_task() {
         let (s_now, uid, ical) = build_task(true);
+        let config = Config::default();
 
         let expected_ical = format!("BEGIN:VCALENDAR\r\n\
             VERSION:2.0\r\n\
@@ -104,7 +227,7 @@ mod tests {
             COMPLETED:{}\r\n\
             STATUS:COMPLETED\r\n\
             END:VTODO\r\n\
-            END:VCALENDAR\r\n", ORG_NAME.lock().unwrap(), PRODUCT_NAME.lock().unwrap(), uid, s_now, s_now, s_now, s_now);
+            END:VCALENDAR\r\n", config.org_name, config.product_name, uid, s_now, s_now, s_now, s_now);
 
         assert_eq!(ical, expected_ical);
     }
@@ -112,6 +235,7 @@ mod tests {
     #[test]
     fn test_ical_from_uncompleted_task() {
         let (s_now, uid, ical) = build_task(false);
+        let config = Config::default();
 
         let expected_ical = format!("BEGIN:VCALENDAR\r\n\
             VERSION:2.0\r\n\
@@ -124,11 +248,79 @@ mod tests {
             SUMMARY:This is a task with ÜTF-8 characters\r\n\
             STATUS:NEEDS-ACTION\r\n\
             END:VTODO\r\n\
-            END:VCALENDAR\r\n", ORG_NAME.lock().unwrap(), PRODUCT_NAME.lock().unwrap(), uid, s_now, s_now, s_now);
+            END:VCALENDAR\r\n", config.org_name, config.product_name, uid, s_now, s_now, s_now);
 
         assert_eq!(ical, expected_ical);
     }
 
+    #[test]
+    fn test_ical_from_several_tasks() {
+        let cal_url = "http://my.calend.ar/id".parse().unwrap();
+        let first = Item::Task(Task::new(String::from("First task"), false, &cal_url));
+        let second = Item::Task(Task::new(String::from("Second task"), true, &cal_url));
+
+        let mut ical = Vec::new();
+        write_calendar(&mut ical, "-//ABC Corporation//My Product//EN", "Groceries, chores; and more\n", vec![&first, &second]).unwrap();
+        let ical = String::from_utf8(ical).unwrap();
+        assert!(ical.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(ical.contains("PRODID:-//ABC Corporation//My Product//EN\r\n"));
+        assert!(ical.contains("X-WR-CALNAME:Groceries\\, chores\\; and more\\n\r\n"));
+        assert!(ical.ends_with("END:VCALENDAR\r\n"));
+
+        let parsed = ical::IcalParser::new(ical.as_bytes()).next().unwrap().unwrap();
+        assert_eq!(parsed.todos.len(), 2);
+    }
+
+    #[test]
+    fn test_exported_dates_are_in_utc() {
+        let zoned = |name: &str, tzid: &str, value: &str| {
+            let mut prop = IcalProperty::new();
+            prop.name = name.to_string();
+            prop.params = Some(vec![(String::from("TZID"), vec![tzid.to_string()])]);
+            prop.value = Some(value.to_string());
+            prop
+        };
+        let cal_url = "http://my.calend.ar/id".parse().unwrap();
+        let task = crate::test_utils::TaskBuilder::new("Meeting", &cal_url)
+            .raw_property(zoned("DUE", "Europe/Paris", "20210322T090000"))
+            .raw_property(zoned("EXDATE", "Europe/Paris", "20210329T090000,20210405T090000"))
+            .raw_property(zoned("DTSTART", "Somewhere/Nowhere", "20210322T080000"))
+            .item();
+
+        let mut ical = Vec::new();
+        write_calendar(&mut ical, &crate::ical::default_prod_id(), "Meetings", vec![&task]).unwrap();
+        let ical = String::from_utf8(ical).unwrap();
+        assert!(ical.contains("DUE:20210322T080000Z\r\n"));
+        // Clocks have gone forward in between
+        assert!(ical.contains("EXDATE:20210329T070000Z,20210405T070000Z\r\n"));
+        assert!(ical.contains("DTSTART;TZID=Somewhere/Nowhere:20210322T080000\r\n"));
+
+        // Uploaded items keep their time zones
+        assert!(build_from(&task).unwrap().contains("DUE;TZID=Europe/Paris:20210322T090000\r\n"));
+    }
+
+    #[test]
+    fn test_multi_valued_parameters() {
+        let mut prop = IcalProperty::new();
+        prop.name = String::from("ATTENDEE");
+        prop.params = Some(vec![
+            (String::from("MEMBER"), vec![String::from("mailto:projectA@example.com"), String::from("mailto:projectB@example.com")]),
+            (String::from("ROLE"), vec![String::from("CHAIR")]),
+        ]);
+        prop.value = Some(String::from("mailto:janedoe@example.com"));
+        let cal_url = "http://my.calend.ar/id".parse().unwrap();
+        let task = crate::test_utils::TaskBuilder::new("Meeting", &cal_url).raw_property(prop).item();
+
+        let ical = build_from(&task).unwrap();
+        assert!(ical.replace("\r\n ", "").contains("ATTENDEE;MEMBER=\"mailto:projectA@example.com\",\"mailto:projectB@example.com\";ROLE=CHAIR:mailto:janedoe@example.com\r\n"));
+
+        let parsed = crate::ical::parse(&ical, task.url().clone(), task.sync_status().clone()).unwrap();
+        let attendee = parsed.unwrap_task().extra_parameters().iter().find(|prop| prop.name == "ATTENDEE").unwrap().clone();
+        let members = &attendee.params.unwrap()[0].1;
+        assert_eq!(members.len(), 2);
+        assert_eq!(build_from(&parsed).unwrap(), ical);
+    }
+
     fn build_task(completed: bool) -> (String, String, String) {
         let cal_url = "http://my.calend.ar/id".parse().unwrap();
         let now = Utc::now();
@@ -4,13 +4,53 @@
 
 mod parser;
 pub use parser::parse;
+pub use parser::parse_occurrences;
+pub use parser::parse_free_busy;
+pub(crate) use parser::parse_date_time;
+pub(crate) use parser::parse_signed_duration;
 mod builder;
 pub use builder::build_from;
+pub use builder::write_calendar;
+mod validator;
+mod repair;
+pub use repair::{repair, Repair, RepairOptions};
+pub use validator::{validate, ValidationError, Violation};
 
-use crate::config::{ORG_NAME, PRODUCT_NAME};
+use ical::property::Property;
 
+use crate::config::Config;
+
+/// The ProdID of the default [`Config`], that is used for items that do not specify any
 pub fn default_prod_id() -> String {
-    format!("-//{}//{}//EN", ORG_NAME.lock().unwrap(), PRODUCT_NAME.lock().unwrap())
+    Config::default().prod_id()
+}
+
+/// Fold a content line, so that none of the lines it is split into is longer than 75 octets (see RFC 5545, section 3.1).
+///
+/// Lines are never split in the middle of a UTF-8 character
+pub(crate) fn fold_line(line: &str) -> String {
+    const MAX_OCTETS: usize = 75;
+
+    let mut folded = String::with_capacity(line.len() + 3 * (line.len() / MAX_OCTETS));
+    let mut current_len = 0;
+    for c in line.chars() {
+        if current_len + c.len_utf8() > MAX_OCTETS {
+            folded.push_str("\r\n ");
+            current_len = 1;
+        }
+        folded.push(c);
+        current_len += c.len_utf8();
+    }
+    folded
+}
+
+/// The (first) value of a parameter of a property (e.g. the `TZID` of `DTSTART;TZID=Europe/Paris:20210322T090000`)
+pub(crate) fn param<'a>(prop: &'a Property, name: &str) -> Option<&'a str> {
+    prop.params.as_ref()?
+        .iter()
+        .find(|(key, _)| key == name)
+        .and_then(|(_, values)| values.first())
+        .map(|value| value.as_str())
 }
 
 
@@ -22,6 +62,18 @@ mod tests {
     use std::collections::HashSet;
     use crate::item::SyncStatus;
 
+    #[test]
+    fn test_fold_line() {
+        assert_eq!(fold_line("SUMMARY:Short"), "SUMMARY:Short");
+
+        let long = format!("DESCRIPTION:{}", "é".repeat(60));
+        let folded = fold_line(&long);
+        for line in folded.split("\r\n") {
+            assert!(line.len() <= 75);
+        }
+        assert_eq!(folded.replace("\r\n ", ""), long);
+    }
+
     #[test]
     fn test_ical_round_trip_serde() {
         let ical_with_unknown_fields = std::fs::read_to_string("tests/assets/ical_with_unknown_fields.ics").unwrap();
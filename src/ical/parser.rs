@@ -3,14 +3,17 @@
 use std::error::Error;
 
 use ical::parser::ical::component::{IcalCalendar, IcalEvent, IcalTodo};
-use chrono::{DateTime, TimeZone, Utc};
+use chrono::{DateTime, Duration, TimeZone, Utc};
 use url::Url;
 
 use crate::Item;
-use crate::item::SyncStatus;
+use crate::item::{Occurrence, SyncStatus};
 use crate::Task;
 use crate::task::CompletionStatus;
 use crate::Event;
+use crate::free_busy::{FreeBusyPeriod, FreeBusyType};
+use crate::alarm::Alarm;
+use crate::utils::time_zone::IcalDateTime;
 
 
 /// Parse an iCal file into the internal representation [`crate::Item`]
@@ -33,83 +36,13 @@ pub fn parse(content: &str, item_url: Url, sync_status: SyncStatus) -> Result<It
             Item::Event(Event::new())
         },
 
-        CurrentType::Todo(todo) => {
-            let mut name = None;
-            let mut uid = None;
-            let mut completed = false;
-            let mut last_modified = None;
-            let mut completion_date = None;
-            let mut creation_date = None;
-            let mut extra_parameters = Vec::new();
-
-            for prop in &todo.properties {
-                match prop.name.as_str() {
-                    "SUMMARY" => { name = prop.value.clone() },
-                    "UID" => { uid = prop.value.clone() },
-                    "DTSTAMP" => {
-                        // The property can be specified once, but is not mandatory
-                        // "This property specifies the date and time that the information associated with
-                        //  the calendar component was last revised in the calendar store."
-                        // "In the case of an iCalendar object that doesn't specify a "METHOD"
-                        //  property [e.g.: VTODO and VEVENT], this property is equivalent to the "LAST-MODIFIED" property".
-                        last_modified = parse_date_time_from_property(&prop.value);
-                    },
-                    "LAST-MODIFIED" => {
-                        // The property can be specified once, but is not mandatory
-                        // "This property specifies the date and time that the information associated with
-                        //  the calendar component was last revised in the calendar store."
-                        // In practise, for VEVENT and VTODO, this is generally the same value as DTSTAMP.
-                        last_modified = parse_date_time_from_property(&prop.value);
-                    }
-                    "COMPLETED" => {
-                        // The property can be specified once, but is not mandatory
-                        // "This property defines the date and time that a to-do was
-                        //  actually completed."
-                        completion_date = parse_date_time_from_property(&prop.value)
-                    },
-                    "CREATED" => {
-                        // The property can be specified once, but is not mandatory
-                        creation_date = parse_date_time_from_property(&prop.value)
-                    },
-                    "STATUS" => {
-                        // Possible values:
-                        //   "NEEDS-ACTION" ;Indicates to-do needs action.
-                        //   "COMPLETED"    ;Indicates to-do completed.
-                        //   "IN-PROCESS"   ;Indicates to-do in process of.
-                        //   "CANCELLED"    ;Indicates to-do was cancelled.
-                        if prop.value.as_ref().map(|s| s.as_str()) == Some("COMPLETED") {
-                            completed = true;
-                        }
-                    }
-                    _ => {
-                        // This field is not supported. Let's store it anyway, so that we are able to re-create an identical iCal file
-                        extra_parameters.push(prop.clone());
-                    }
-                }
-            }
-            let name = match name {
-                Some(name) => name,
-                None => return Err(format!("Missing name for item {}", item_url).into()),
-            };
-            let uid = match uid {
-                Some(uid) => uid,
-                None => return Err(format!("Missing UID for item {}", item_url).into()),
-            };
-            let last_modified = match last_modified {
-                Some(dt) => dt,
-                None => return Err(format!("Missing DTSTAMP for item {}, but this is required by RFC5545", item_url).into()),
-            };
-            let completion_status = match completed {
-                false => {
-                    if completion_date.is_some() {
-                        log::warn!("Task {:?} has an inconsistent content: its STATUS is not completed, yet it has a COMPLETED timestamp at {:?}", uid, completion_date);
-                    }
-                    CompletionStatus::Uncompleted
-                },
-                true => CompletionStatus::Completed(completion_date),
-            };
-
-            Item::Task(Task::new_with_parameters(name, uid, item_url, completion_status, sync_status, creation_date, last_modified, ical_prod_id, extra_parameters))
+        CurrentType::Todo(todo, overrides) => {
+            let mut task = parse_task(todo, item_url.clone(), sync_status.clone(), ical_prod_id.clone())?;
+            let overrides = overrides.into_iter()
+                .map(|ov| parse_task(ov, item_url.clone(), sync_status.clone(), ical_prod_id.clone()))
+                .collect::<Result<Vec<Task>, Box<dyn Error>>>()?;
+            task.set_overrides(overrides);
+            Item::Task(task)
         },
     };
 
@@ -122,11 +55,257 @@ pub fn parse(content: &str, item_url: Url, sync_status: SyncStatus) -> Result<It
     Ok(item)
 }
 
-fn parse_date_time(dt: &str) -> Result<DateTime<Utc>, chrono::format::ParseError> {
+/// Parse the content of a `VTODO` component
+fn parse_task(todo: &IcalTodo, item_url: Url, sync_status: SyncStatus, ical_prod_id: String) -> Result<Task, Box<dyn Error>> {
+    let mut name = None;
+    let mut uid = None;
+    let mut completed = false;
+    let mut dtstamp = None;
+    let mut last_modified = None;
+    let mut sequence = None;
+    let mut completion_date = None;
+    let mut creation_date = None;
+    let mut percent_complete = None;
+    let mut extra_parameters = Vec::new();
+
+    for prop in &todo.properties {
+        match prop.name.as_str() {
+            // An empty SUMMARY is an empty name, not a missing one
+            "SUMMARY" => { name = Some(unescape_text(prop.value.as_deref().unwrap_or_default())) },
+            "UID" => { uid = prop.value.clone() },
+            "DTSTAMP" => {
+                // The property can be specified once, but is not mandatory
+                // "This property specifies the date and time that the information associated with
+                //  the calendar component was last revised in the calendar store."
+                // "In the case of an iCalendar object that doesn't specify a "METHOD"
+                //  property [e.g.: VTODO and VEVENT], this property is equivalent to the "LAST-MODIFIED" property".
+                // Some servers and clients still set them differently, so that both are kept.
+                dtstamp = parse_date_time_from_property(&prop.value);
+            },
+            "LAST-MODIFIED" => {
+                // The property can be specified once, but is not mandatory
+                // "This property specifies the date and time that the information associated with
+                //  the calendar component was last revised in the calendar store."
+                // In practise, for VEVENT and VTODO, this is generally the same value as DTSTAMP.
+                last_modified = parse_date_time_from_property(&prop.value);
+            }
+            "SEQUENCE" => {
+                // The property can be specified once, but is not mandatory
+                // "This property defines the revision sequence number of the calendar component within a sequence of revisions."
+                match prop.value.as_deref().and_then(|value| value.trim().parse::<u32>().ok()) {
+                    Some(value) => sequence = Some(value),
+                    None => extra_parameters.push(prop.clone()),
+                }
+            }
+            "COMPLETED" => {
+                // The property can be specified once, but is not mandatory
+                // "This property defines the date and time that a to-do was
+                //  actually completed."
+                completion_date = parse_date_time_from_property(&prop.value)
+            },
+            "CREATED" => {
+                // The property can be specified once, but is not mandatory
+                creation_date = parse_date_time_from_property(&prop.value)
+            },
+            "STATUS" => {
+                // Possible values:
+                //   "NEEDS-ACTION" ;Indicates to-do needs action.
+                //   "COMPLETED"    ;Indicates to-do completed.
+                //   "IN-PROCESS"   ;Indicates to-do in process of.
+                //   "CANCELLED"    ;Indicates to-do was cancelled.
+                if prop.value.as_ref().map(|s| s.as_str()) == Some("COMPLETED") {
+                    completed = true;
+                }
+            }
+            "PERCENT-COMPLETE" => {
+                // This is generated from the completion status when the task is completed, so that it must not be stored twice.
+                // Other values are kept as-is (see below)
+                percent_complete = Some(prop.clone());
+            }
+            _ => {
+                // This field is not supported. Let's store it anyway, so that we are able to re-create an identical iCal file
+                extra_parameters.push(prop.clone());
+            }
+        }
+    }
+    let name = match name {
+        Some(name) => name,
+        None => return Err(format!("Missing name for item {}", item_url).into()),
+    };
+    let uid = match uid {
+        Some(uid) => uid,
+        None => return Err(format!("Missing UID for item {}", item_url).into()),
+    };
+    // Each of these dates is used in place of the other one when it is missing
+    let (last_modified, dtstamp) = match (last_modified, dtstamp) {
+        (Some(last_modified), Some(dtstamp)) => (last_modified, dtstamp),
+        (Some(dt), None) | (None, Some(dt)) => (dt, dt),
+        (None, None) => return Err(format!("Missing DTSTAMP for item {}, but this is required by RFC5545", item_url).into()),
+    };
+    if let Some(prop) = percent_complete {
+        if completed == false || prop.value.as_deref() != Some("100") {
+            extra_parameters.push(prop);
+        }
+    }
+    let completion_status = match completed {
+        false => {
+            if completion_date.is_some() {
+                log::warn!("Task {:?} has an inconsistent content: its STATUS is not completed, yet it has a COMPLETED timestamp at {:?}", uid, completion_date);
+            }
+            CompletionStatus::Uncompleted
+        },
+        true => CompletionStatus::Completed(completion_date),
+    };
+
+    let alarms = todo.alarms.iter()
+        .map(|alarm| Alarm::new(alarm.properties.clone()))
+        .collect();
+
+    Ok(Task::new_with_parameters(name, uid, item_url, completion_status, sync_status, creation_date, last_modified, ical_prod_id, extra_parameters)
+        .with_dtstamp(dtstamp)
+        .with_sequence(sequence)
+        .with_alarms(alarms))
+}
+
+/// Parse an iCal file that may contain several instances of a single item.
+///
+/// This is what servers return when they are asked to expand recurrent items (see [`crate::traits::DavCalendar::get_occurrences_between`]). \
+/// Every returned occurrence shares the same URL and sync status.
+pub fn parse_occurrences(content: &str, item_url: Url, sync_status: SyncStatus) -> Result<Vec<Occurrence>, Box<dyn Error>> {
+    let reader = ical::IcalParser::new(content.as_bytes());
+    let mut occurrences = Vec::new();
+
+    for parsed_item in reader {
+        let parsed_item = match parsed_item {
+            Err(err) => return Err(format!("Unable to parse iCal data for item {}: {}", item_url, err).into()),
+            Ok(item) => item,
+        };
+        let ical_prod_id = extract_ical_prod_id(&parsed_item)
+            .map(|s| s.to_string())
+            .unwrap_or_else(super::default_prod_id);
+
+        if parsed_item.events.is_empty() == false {
+            log::warn!("Item {} contains events, that are not supported yet. Ignoring them", item_url);
+        }
+
+        for todo in &parsed_item.todos {
+            let recurrence_id = todo.properties.iter()
+                .find(|prop| prop.name == "RECURRENCE-ID")
+                // This may be a whole day (e.g. "RECURRENCE-ID;VALUE=DATE:20210329"), that is considered to start at midnight
+                .and_then(IcalDateTime::from_property)
+                .map(|recurrence_id| recurrence_id.to_utc());
+            let task = parse_task(todo, item_url.clone(), sync_status.clone(), ical_prod_id.clone())?;
+            occurrences.push(Occurrence::new(recurrence_id, Item::Task(task)));
+        }
+    }
+
+    if occurrences.is_empty() {
+        return Err(format!("Invalid iCal data to parse for item {}", item_url).into());
+    }
+    Ok(occurrences)
+}
+
+/// Parse the `VFREEBUSY` components of an iCal file (e.g. the reply to a `free-busy-query`) into a list of periods
+pub fn parse_free_busy(content: &str) -> Result<Vec<FreeBusyPeriod>, Box<dyn Error>> {
+    let mut periods = Vec::new();
+
+    for calendar in ical::IcalParser::new(content.as_bytes()) {
+        let calendar = calendar.map_err(|err| format!("Unable to parse free/busy data: {}", err))?;
+        for free_busy in &calendar.free_busys {
+            for prop in free_busy.properties.iter().filter(|prop| prop.name == "FREEBUSY") {
+                let fb_type = super::param(prop, "FBTYPE")
+                    .map(FreeBusyType::from_fb_type)
+                    .unwrap_or(FreeBusyType::Busy);
+
+                let value = match &prop.value {
+                    None => continue,
+                    Some(value) => value,
+                };
+                for period in value.split(',') {
+                    let (start, end) = parse_period(period)?;
+                    periods.push(FreeBusyPeriod::new(start, end, fb_type));
+                }
+            }
+        }
+    }
+
+    Ok(periods)
+}
+
+/// Parse a PERIOD value, that is either `start/end` or `start/duration` (see RFC 5545, section 3.3.9)
+fn parse_period(period: &str) -> Result<(DateTime<Utc>, DateTime<Utc>), Box<dyn Error>> {
+    let (start, end_or_duration) = period.split_once('/')
+        .ok_or_else(|| format!("Invalid period {}", period))?;
+    let start = parse_date_time(start)?;
+    let end = match end_or_duration.starts_with('P') || end_or_duration.starts_with("+P") {
+        true => start + parse_duration(end_or_duration)?,
+        false => parse_date_time(end_or_duration)?,
+    };
+    Ok((start, end))
+}
+
+/// Parse a positive DURATION value (see RFC 5545, section 3.3.6)
+fn parse_duration(duration: &str) -> Result<Duration, Box<dyn Error>> {
+    let invalid = || format!("Invalid duration {}", duration);
+
+    let mut total = Duration::zero();
+    let mut number = String::new();
+    let mut in_time_part = false;
+    for c in duration.trim_start_matches('+').strip_prefix('P').ok_or_else(invalid)?.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        if c == 'T' {
+            in_time_part = true;
+            continue;
+        }
+
+        let n: i64 = number.parse().map_err(|_| invalid())?;
+        number.clear();
+        total = total + match (c, in_time_part) {
+            ('W', false) => Duration::weeks(n),
+            ('D', false) => Duration::days(n),
+            ('H', true) => Duration::hours(n),
+            ('M', true) => Duration::minutes(n),
+            ('S', true) => Duration::seconds(n),
+            _ => return Err(invalid().into()),
+        };
+    }
+
+    if number.is_empty() == false {
+        return Err(invalid().into());
+    }
+    Ok(total)
+}
+
+/// Parse a DURATION value, that may be negative (e.g. `-PT15M`)
+pub(crate) fn parse_signed_duration(duration: &str) -> Result<Duration, Box<dyn Error>> {
+    match duration.strip_prefix('-') {
+        Some(positive) => parse_duration(positive).map(|duration| -duration),
+        None => parse_duration(duration),
+    }
+}
+
+pub(crate) fn parse_date_time(dt: &str) -> Result<DateTime<Utc>, chrono::format::ParseError> {
                     Utc.datetime_from_str(dt, "%Y%m%dT%H%M%SZ")
     .or_else(|_err| Utc.datetime_from_str(dt, "%Y%m%dT%H%M%S") )
 }
 
+/// Undo the escaping of a TEXT value (see RFC 5545, section 3.3.11)
+fn unescape_text(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) | ('\\', Some('N')) => { chars.next(); unescaped.push('\n'); },
+            ('\\', Some(escaped @ (',' | ';' | '\\'))) => { chars.next(); unescaped.push(escaped); },
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
 fn parse_date_time_from_property(value: &Option<String>) -> Option<DateTime<Utc>> {
     value.as_ref()
         .and_then(|s| {
@@ -152,7 +331,12 @@ fn extract_ical_prod_id(item: &IcalCalendar) -> Option<&str> {
 
 enum CurrentType<'a> {
     Event(&'a IcalEvent),
-    Todo(&'a IcalTodo),
+    /// A task, along with its overridden instances (in case it is recurrent)
+    Todo(&'a IcalTodo, Vec<&'a IcalTodo>),
+}
+
+fn has_recurrence_id(todo: &IcalTodo) -> bool {
+    todo.properties.iter().any(|prop| prop.name == "RECURRENCE-ID")
 }
 
 fn assert_single_type<'a>(item: &'a IcalCalendar) -> Result<CurrentType<'a>, Box<dyn Error>> {
@@ -172,7 +356,18 @@ fn assert_single_type<'a>(item: &'a IcalCalendar) -> Result<CurrentType<'a>, Box
         if n_events != 0 || n_journals != 0 {
             return Err("Only a single TODO or a single EVENT is supported".into());
         } else {
-            return Ok(CurrentType::Todo(&item.todos[0]));
+            return Ok(CurrentType::Todo(&item.todos[0], Vec::new()));
+        }
+    }
+
+    if n_todos > 1 && n_events == 0 && n_journals == 0 {
+        // This is supported in case this is a recurrent task, with a single "master" component and overridden instances (that have a RECURRENCE-ID)
+        let (overrides, masters): (Vec<&IcalTodo>, Vec<&IcalTodo>) = item.todos.iter().partition(|todo| has_recurrence_id(todo));
+        let uids: std::collections::HashSet<Option<&String>> = item.todos.iter()
+            .map(|todo| todo.properties.iter().find(|prop| prop.name == "UID").and_then(|prop| prop.value.as_ref()))
+            .collect();
+        if masters.len() == 1 && uids.len() == 1 {
+            return Ok(CurrentType::Todo(masters[0], overrides));
         }
     }
 
@@ -223,6 +418,20 @@ SUMMARY:Clean up your room or Mom will be angry
 STATUS:COMPLETED
 END:VTODO
 END:VCALENDAR
+"#;
+
+const EXAMPLE_ICAL_WITH_DTSTAMP_AND_SEQUENCE: &str = r#"BEGIN:VCALENDAR
+VERSION:2.0
+PRODID:-//Nextcloud Tasks v0.13.6
+BEGIN:VTODO
+UID:0633de27-8c32-42be-bcb8-63bc879c6185@some-domain.com
+CREATED:20210321T001600
+LAST-MODIFIED:20210322T101500
+DTSTAMP:20210323T080000
+SEQUENCE:4
+SUMMARY:Do not forget to do this
+END:VTODO
+END:VCALENDAR
 "#;
 
     const EXAMPLE_MULTIPLE_ICAL: &str = r#"BEGIN:VCALENDAR
@@ -245,6 +454,44 @@ DTSTAMP:20210321T001600
 SUMMARY:Buy a gift for Mom
 END:VTODO
 END:VCALENDAR
+"#;
+
+    const EXAMPLE_EXPANDED_ICAL: &str = r#"BEGIN:VCALENDAR
+VERSION:2.0
+PRODID:-//Example Corp.//CalDAV Server//EN
+BEGIN:VTODO
+UID:weekly-review@example.com
+DTSTAMP:20210321T001600Z
+RECURRENCE-ID:20210322T090000Z
+SUMMARY:Weekly review
+END:VTODO
+BEGIN:VTODO
+UID:weekly-review@example.com
+DTSTAMP:20210321T001600Z
+RECURRENCE-ID;VALUE=DATE:20210329
+SUMMARY:Weekly review
+STATUS:COMPLETED
+END:VTODO
+END:VCALENDAR
+"#;
+
+    const EXAMPLE_ICAL_WITH_OVERRIDE: &str = r#"BEGIN:VCALENDAR
+VERSION:2.0
+PRODID:-//Example Corp.//CalDAV Server//EN
+BEGIN:VTODO
+UID:weekly-review@example.com
+DTSTAMP:20210321T001600Z
+DTSTART:20210322T090000Z
+RRULE:FREQ=WEEKLY
+SUMMARY:Weekly review
+END:VTODO
+BEGIN:VTODO
+UID:weekly-review@example.com
+DTSTAMP:20210321T001600Z
+RECURRENCE-ID:20210329T090000Z
+SUMMARY:Weekly review (longer this time)
+END:VTODO
+END:VCALENDAR
 "#;
 
     use super::*;
@@ -294,6 +541,135 @@ END:VCALENDAR
         assert_eq!(task.completion_status(), &CompletionStatus::Completed(None));
     }
 
+    #[test]
+    fn test_empty_summary_parsing() {
+        let item_url: Url = "http://some.id/for/testing".parse().unwrap();
+        let ical = EXAMPLE_ICAL.replace("SUMMARY:Do not forget to do this", "SUMMARY:");
+
+        let item = parse(&ical, item_url.clone(), SyncStatus::NotSynced).unwrap();
+        assert_eq!(item.unwrap_task().name(), "");
+
+        // A missing SUMMARY is still an error
+        let ical = EXAMPLE_ICAL.replace("SUMMARY:Do not forget to do this\n", "");
+        assert!(parse(&ical, item_url, SyncStatus::NotSynced).is_err());
+    }
+
+    #[test]
+    fn test_percent_complete_parsing() {
+        let item_url: Url = "http://some.id/for/testing".parse().unwrap();
+
+        // The builder writes PERCENT-COMPLETE:100 for every completed task, so that it must not be written twice
+        let item = parse(EXAMPLE_ICAL_COMPLETED, item_url.clone(), SyncStatus::NotSynced).unwrap();
+        assert!(item.unwrap_task().extra_parameters().iter().all(|prop| prop.name != "PERCENT-COMPLETE"));
+        let built = crate::ical::build_from(&item).unwrap();
+        assert_eq!(built.matches("PERCENT-COMPLETE").count(), 1);
+
+        // Other values tell something the completion status does not
+        let ical = EXAMPLE_ICAL.replace("SUMMARY:", "PERCENT-COMPLETE:40\nSUMMARY:");
+        let item = parse(&ical, item_url, SyncStatus::NotSynced).unwrap();
+        assert!(item.unwrap_task().extra_parameters().iter().any(|prop| prop.name == "PERCENT-COMPLETE" && prop.value.as_deref() == Some("40")));
+    }
+
+    #[test]
+    fn test_expanded_occurrences_parsing() {
+        let sync_status = SyncStatus::Synced(VersionTag::from(String::from("test-tag")));
+        let item_url: Url = "http://some.id/for/testing".parse().unwrap();
+
+        let occurrences = parse_occurrences(EXAMPLE_EXPANDED_ICAL, item_url.clone(), sync_status).unwrap();
+        assert_eq!(occurrences.len(), 2);
+        assert_eq!(occurrences[0].recurrence_id(), Some(&Utc.ymd(2021, 3, 22).and_hms(9, 0, 0)));
+        assert!(occurrences[0].item().unwrap_task().completed() == false);
+        assert_eq!(occurrences[1].recurrence_id(), Some(&Utc.ymd(2021, 3, 29).and_hms(0, 0, 0)));
+        assert!(occurrences[1].item().unwrap_task().completed());
+        assert_eq!(occurrences[1].item().url(), &item_url);
+    }
+
+    #[test]
+    fn test_date_time_parsing() {
+        assert_eq!(parse_date_time("20210321T090000Z").unwrap(), Utc.ymd(2021, 3, 21).and_hms(9, 0, 0));
+        assert_eq!(parse_date_time("20210321T090000").unwrap(), Utc.ymd(2021, 3, 21).and_hms(9, 0, 0));
+        // Whole days are not DATE-TIME values
+        assert!(parse_date_time("20210321").is_err());
+    }
+
+    #[test]
+    fn test_recurrent_ical_with_override_parsing() {
+        let sync_status = SyncStatus::Synced(VersionTag::from(String::from("test-tag")));
+        let item_url: Url = "http://some.id/for/testing".parse().unwrap();
+
+        let item = parse(EXAMPLE_ICAL_WITH_OVERRIDE, item_url, sync_status).unwrap();
+        let task = item.unwrap_task();
+        assert_eq!(task.name(), "Weekly review");
+        assert_eq!(task.overrides().len(), 1);
+        assert_eq!(task.overrides()[0].name(), "Weekly review (longer this time)");
+    }
+
+    #[test]
+    fn test_recurrent_ical_with_override_roundtrip() {
+        let sync_status = SyncStatus::Synced(VersionTag::from(String::from("test-tag")));
+        let item_url: Url = "http://some.id/for/testing".parse().unwrap();
+
+        let item = parse(EXAMPLE_ICAL_WITH_OVERRIDE, item_url.clone(), sync_status.clone()).unwrap();
+        let ical = crate::ical::build_from(&item).unwrap();
+        assert!(ical.contains("RECURRENCE-ID:20210329T090000Z"));
+
+        let reparsed = parse(&ical, item_url, sync_status).unwrap();
+        let task = reparsed.unwrap_task();
+        assert_eq!(task.overrides().len(), 1);
+        assert_eq!(task.overrides()[0].name(), "Weekly review (longer this time)");
+    }
+
+    #[test]
+    fn test_free_busy_parsing() {
+        let reply = "BEGIN:VCALENDAR\r\n\
+            VERSION:2.0\r\n\
+            PRODID:-//Example Corp.//CalDAV Server//EN\r\n\
+            BEGIN:VFREEBUSY\r\n\
+            DTSTAMP:20210321T001600Z\r\n\
+            DTSTART:20210322T000000Z\r\n\
+            DTEND:20210323T000000Z\r\n\
+            FREEBUSY;FBTYPE=BUSY-TENTATIVE:20210322T090000Z/PT1H30M\r\n\
+            FREEBUSY:20210322T140000Z/20210322T150000Z,20210322T160000Z/PT15M\r\n\
+            END:VFREEBUSY\r\n\
+            END:VCALENDAR\r\n";
+
+        let periods = parse_free_busy(reply).unwrap();
+        assert_eq!(periods, vec![
+            FreeBusyPeriod::new(Utc.ymd(2021, 3, 22).and_hms(9, 0, 0),  Utc.ymd(2021, 3, 22).and_hms(10, 30, 0), FreeBusyType::BusyTentative),
+            FreeBusyPeriod::new(Utc.ymd(2021, 3, 22).and_hms(14, 0, 0), Utc.ymd(2021, 3, 22).and_hms(15, 0, 0),  FreeBusyType::Busy),
+            FreeBusyPeriod::new(Utc.ymd(2021, 3, 22).and_hms(16, 0, 0), Utc.ymd(2021, 3, 22).and_hms(16, 15, 0), FreeBusyType::Busy),
+        ]);
+
+        assert!(crate::free_busy::is_free(&periods, &Utc.ymd(2021, 3, 22).and_hms(10, 30, 0), &Utc.ymd(2021, 3, 22).and_hms(14, 0, 0)));
+        assert!(crate::free_busy::is_free(&periods, &Utc.ymd(2021, 3, 22).and_hms(10, 0, 0), &Utc.ymd(2021, 3, 22).and_hms(11, 0, 0)) == false);
+    }
+
+    #[test]
+    fn test_dtstamp_and_sequence_parsing() {
+        let item_url: Url = "http://some.id/for/testing".parse().unwrap();
+
+        let item = parse(EXAMPLE_ICAL_WITH_DTSTAMP_AND_SEQUENCE, item_url.clone(), SyncStatus::NotSynced).unwrap();
+        let task = item.unwrap_task();
+        assert_eq!(task.last_modified(), &Utc.ymd(2021, 3, 22).and_hms(10, 15, 0));
+        assert_eq!(task.dtstamp(), &Utc.ymd(2021, 3, 23).and_hms(8, 0, 0));
+        assert_eq!(task.sequence(), Some(4));
+        assert!(task.extra_parameters().is_empty());
+
+        // Both are kept on round trip
+        let ical = crate::ical::build_from(&item).unwrap();
+        assert!(ical.contains("\r\nLAST-MODIFIED:20210322T101500\r\n"));
+        assert!(ical.contains("\r\nDTSTAMP:20210323T080000\r\n"));
+        assert!(ical.contains("\r\nSEQUENCE:4\r\n"));
+
+        // Each one is used in place of the other one when it is missing
+        let without_dtstamp = EXAMPLE_ICAL_WITH_DTSTAMP_AND_SEQUENCE.replace("DTSTAMP:20210323T080000\n", "");
+        let task = parse(&without_dtstamp, item_url.clone(), SyncStatus::NotSynced).unwrap();
+        assert_eq!(task.unwrap_task().dtstamp(), &Utc.ymd(2021, 3, 22).and_hms(10, 15, 0));
+        let without_last_modified = EXAMPLE_ICAL_WITH_DTSTAMP_AND_SEQUENCE.replace("LAST-MODIFIED:20210322T101500\n", "");
+        let task = parse(&without_last_modified, item_url.clone(), SyncStatus::NotSynced).unwrap();
+        assert_eq!(task.unwrap_task().last_modified(), &Utc.ymd(2021, 3, 23).and_hms(8, 0, 0));
+    }
+
     #[test]
     fn test_multiple_items_in_ical() {
         let version_tag = VersionTag::from(String::from("test-tag"));
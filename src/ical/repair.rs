@@ -0,0 +1,310 @@
+//! A module to fix common mistakes of iCal files before they are parsed
+//!
+//! Some servers (or the clients that uploaded the data to them) produce iCal files that do not strictly follow RFC5545.
+//! Without repairs, such items would fail to parse (or be misread) on every sync.
+
+/// The fixes the repair pass is allowed to apply. See [`repair`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RepairOptions {
+    /// Escape the commas and semicolons of text values (e.g. `SUMMARY:Milk, eggs` becomes `SUMMARY:Milk\, eggs`)
+    pub unescaped_commas: bool,
+    /// Make property names uppercase (e.g. `summary:Milk` becomes `SUMMARY:Milk`)
+    pub property_name_case: bool,
+    /// Join lines that have been folded without the leading whitespace RFC5545 requires
+    pub line_folding: bool,
+    /// Keep the most recent component only, out of several ones that share the same `UID` (and `RECURRENCE-ID`)
+    pub duplicate_uids: bool,
+}
+
+impl RepairOptions {
+    /// Every fix is enabled. This is the default
+    pub fn all() -> Self {
+        Self { unescaped_commas: true, property_name_case: true, line_folding: true, duplicate_uids: true }
+    }
+
+    /// No fix is enabled: iCal files are parsed as-is
+    pub fn none() -> Self {
+        Self { unescaped_commas: false, property_name_case: false, line_folding: false, duplicate_uids: false }
+    }
+}
+
+impl Default for RepairOptions {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+/// A fix that has been applied by [`repair`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Repair {
+    /// The commas or semicolons of this property were not escaped
+    UnescapedText(String),
+    /// This property name was not in uppercase
+    LowercaseName(String),
+    /// This line was folded without a leading whitespace
+    BrokenFolding(String),
+    /// Several components had this UID, only the most recent one has been kept
+    DuplicateUid(String),
+}
+
+/// Properties whose value is a single TEXT (see RFC5545, section 3.3.11), where commas and semicolons must be escaped
+const TEXT_PROPERTIES: [&str; 4] = ["SUMMARY", "DESCRIPTION", "LOCATION", "COMMENT"];
+
+/// Fix the common mistakes of an iCal file, and tell which ones have been fixed.
+///
+/// Content that does not need any fix is returned unchanged.
+pub fn repair(content: &str, options: &RepairOptions) -> (String, Vec<Repair>) {
+    let mut repairs = Vec::new();
+
+    // Unfold the lines, so that every property is on its own line
+    let mut lines: Vec<String> = Vec::new();
+    for line in content.split('\n').map(|line| line.strip_suffix('\r').unwrap_or(line)) {
+        match lines.last_mut() {
+            Some(last) if line.starts_with(' ') || line.starts_with('\t') => last.push_str(&line[1..]),
+            Some(last) if options.line_folding && line.is_empty() == false && split_content_line(line).is_none() => {
+                repairs.push(Repair::BrokenFolding(line.to_string()));
+                last.push_str(line);
+            },
+            _ => {
+                if line.is_empty() == false {
+                    lines.push(line.to_string());
+                }
+            },
+        }
+    }
+
+    for line in lines.iter_mut() {
+        let (name_end, value_start) = match split_content_line(line) {
+            None => continue,
+            Some(split) => split,
+        };
+        let name = &line[..name_end];
+
+        if options.property_name_case && name.chars().any(|c| c.is_ascii_lowercase()) {
+            repairs.push(Repair::LowercaseName(name.to_string()));
+            let upper = name.to_ascii_uppercase();
+            let value = match upper.as_str() {
+                "BEGIN" | "END" => line[value_start..].to_ascii_uppercase(),
+                _ => line[value_start..].to_string(),
+            };
+            *line = format!("{}{}{}", upper, &line[name_end..value_start], value);
+        }
+
+        let name = &line[..name_end];
+        if options.unescaped_commas && TEXT_PROPERTIES.contains(&name) {
+            let value = &line[value_start..];
+            let escaped = escape_separators(value);
+            if escaped != value {
+                repairs.push(Repair::UnescapedText(name.to_string()));
+                *line = format!("{}{}", &line[..value_start], escaped);
+            }
+        }
+    }
+
+    if options.duplicate_uids {
+        lines = remove_duplicates(lines, &mut repairs);
+    }
+
+    match repairs.is_empty() {
+        true => (content.to_string(), repairs),
+        false => {
+            // Repaired lines may have become too long (e.g. escaped text), and they have been unfolded anyway
+            let mut repaired = lines.iter()
+                .map(|line| crate::ical::fold_line(line))
+                .collect::<Vec<_>>()
+                .join("\r\n");
+            repaired.push_str("\r\n");
+            (repaired, repairs)
+        },
+    }
+}
+
+/// Where the name of a content line ends, and where its value starts (after the parameters, that may contain quoted `:`).
+///
+/// `None` in case this is not a content line (e.g. the rest of a line that has been wrongly folded)
+fn split_content_line(line: &str) -> Option<(usize, usize)> {
+    let name_end = line.find([';', ':'])?;
+    if name_end == 0 || line[..name_end].chars().all(|c| c.is_ascii_alphanumeric() || c == '-') == false {
+        return None;
+    }
+
+    let mut in_quotes = false;
+    for (i, c) in line.char_indices().skip(name_end) {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ':' if in_quotes == false => return Some((name_end, i + 1)),
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Escape the commas and semicolons that are not escaped already
+fn escape_separators(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                escaped.push(c);
+                if let Some(next) = chars.next() {
+                    escaped.push(next);
+                }
+            },
+            ',' | ';' => {
+                escaped.push('\\');
+                escaped.push(c);
+            },
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A top-level component (e.g. a `VTODO`) of an iCal file
+struct Component {
+    /// The range of its lines (including its `BEGIN` and `END` lines)
+    lines: std::ops::Range<usize>,
+    uid: Option<String>,
+    recurrence_id: Option<String>,
+    sequence: u32,
+    /// `LAST-MODIFIED` (or `DTSTAMP`). Both are UTC dates, that are ordered the same way as their text
+    last_modified: Option<String>,
+}
+
+fn remove_duplicates(lines: Vec<String>, repairs: &mut Vec<Repair>) -> Vec<String> {
+    let mut components: Vec<Component> = Vec::new();
+    let mut depth = 0;
+    for (i, line) in lines.iter().enumerate() {
+        let (name, value) = match split_content_line(line) {
+            None => continue,
+            Some((name_end, value_start)) => (&line[..name_end], &line[value_start..]),
+        };
+        match (name, depth) {
+            ("BEGIN", _) => {
+                depth += 1;
+                if depth == 2 {
+                    components.push(Component { lines: i..i, uid: None, recurrence_id: None, sequence: 0, last_modified: None });
+                }
+            },
+            ("END", _) => {
+                if depth == 2 {
+                    if let Some(component) = components.last_mut() {
+                        component.lines.end = i + 1;
+                    }
+                }
+                depth -= 1;
+            },
+            (_, 2) => {
+                if let Some(component) = components.last_mut() {
+                    match name {
+                        "UID" => component.uid = Some(value.to_string()),
+                        "RECURRENCE-ID" => component.recurrence_id = Some(value.to_string()),
+                        "SEQUENCE" => component.sequence = value.trim().parse().unwrap_or(0),
+                        "LAST-MODIFIED" => component.last_modified = Some(value.to_string()),
+                        "DTSTAMP" if component.last_modified.is_none() => component.last_modified = Some(value.to_string()),
+                        _ => (),
+                    }
+                }
+            },
+            _ => (),
+        }
+    }
+
+    let mut dropped: Vec<std::ops::Range<usize>> = Vec::new();
+    for (i, component) in components.iter().enumerate() {
+        let uid = match &component.uid {
+            None => continue,
+            Some(uid) => uid,
+        };
+        // Keep the most recent component, or the last one in case they are equally recent
+        let superseded = components.iter().enumerate()
+            .filter(|(j, other)| *j != i && other.uid == component.uid && other.recurrence_id == component.recurrence_id)
+            .any(|(j, other)| {
+                let ordering = (other.sequence, &other.last_modified).cmp(&(component.sequence, &component.last_modified));
+                ordering == std::cmp::Ordering::Greater || (ordering == std::cmp::Ordering::Equal && j > i)
+            });
+        if superseded {
+            repairs.push(Repair::DuplicateUid(uid.clone()));
+            dropped.push(component.lines.clone());
+        }
+    }
+
+    lines.into_iter().enumerate()
+        .filter(|(i, _)| dropped.iter().any(|range| range.contains(i)) == false)
+        .map(|(_, line)| line)
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::item::SyncStatus;
+
+    fn repair_all(content: &str) -> (String, Vec<Repair>) {
+        repair(content, &RepairOptions::all())
+    }
+
+    fn todo(properties: &str) -> String {
+        format!("BEGIN:VTODO\r\nUID:some-uid\r\nDTSTAMP:20210321T001600Z\r\n{}END:VTODO\r\n", properties)
+    }
+
+    fn calendar(components: &str) -> String {
+        format!("BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//Example Corp.//CalDAV Client//EN\r\n{}END:VCALENDAR\r\n", components)
+    }
+
+    #[test]
+    fn test_valid_content_is_unchanged() {
+        let content = calendar(&todo("SUMMARY:Milk\\, eggs\r\nDESCRIPTION:Long\r\n  description\r\nCATEGORIES:shopping,food\r\n"));
+        assert_eq!(repair_all(&content), (content, vec![]));
+    }
+
+    #[test]
+    fn test_repairs() {
+        let content = calendar(&todo("summary:Milk, eggs; and butter\r\nDESCRIPTION:A description\r\nthat has been folded\r\n"));
+        let (repaired, repairs) = repair_all(&content);
+        assert_eq!(repairs, vec![
+            Repair::BrokenFolding(String::from("that has been folded")),
+            Repair::LowercaseName(String::from("summary")),
+            Repair::UnescapedText(String::from("SUMMARY")),
+        ]);
+        assert!(repaired.contains("\r\nSUMMARY:Milk\\, eggs\\; and butter\r\n"));
+        assert!(repaired.contains("\r\nDESCRIPTION:A descriptionthat has been folded\r\n"));
+
+        let url: url::Url = "https://some.calendar/some-task.ics".parse().unwrap();
+        assert!(crate::ical::parse(&content, url.clone(), SyncStatus::NotSynced).is_err());
+        let task = crate::ical::parse(&repaired, url, SyncStatus::NotSynced).unwrap();
+        assert_eq!(task.unwrap_task().name(), "Milk, eggs; and butter");
+
+        // Repairs can be disabled
+        assert_eq!(repair(&content, &RepairOptions::none()), (content, vec![]));
+    }
+
+    #[test]
+    fn test_repaired_lines_are_folded() {
+        let description = "a description, that is long enough to be folded once its commas are escaped";
+        let content = calendar(&todo(&format!("DESCRIPTION:{}\r\n , and already folded\r\n", description)));
+        let (repaired, repairs) = repair_all(&content);
+        assert_eq!(repairs, vec![Repair::UnescapedText(String::from("DESCRIPTION"))]);
+        assert!(repaired.lines().all(|line| line.len() <= 75));
+        assert!(repaired.replace("\r\n ", "").contains(&format!("DESCRIPTION:{}\\, and already folded\r\n", description.replace(',', "\\,"))));
+    }
+
+    #[test]
+    fn test_duplicate_uids() {
+        let older = todo("SUMMARY:Older\r\nSEQUENCE:1\r\n");
+        let newer = todo("SUMMARY:Newer\r\nSEQUENCE:2\r\nBEGIN:VALARM\r\nACTION:DISPLAY\r\nTRIGGER:-PT15M\r\nEND:VALARM\r\n");
+        let occurrence = todo("SUMMARY:Occurrence\r\nRECURRENCE-ID:20210322T090000Z\r\n");
+        let content = calendar(&format!("{}{}{}", newer, older, occurrence));
+
+        let (repaired, repairs) = repair_all(&content);
+        assert_eq!(repairs, vec![Repair::DuplicateUid(String::from("some-uid"))]);
+        assert_eq!(repaired, calendar(&format!("{}{}", newer, occurrence)));
+
+        // Equally recent components: the last one wins
+        let content = calendar(&format!("{}{}", todo("SUMMARY:First\r\n"), todo("SUMMARY:Second\r\n")));
+        assert_eq!(repair_all(&content).0, calendar(&todo("SUMMARY:Second\r\n")));
+    }
+}
@@ -0,0 +1,252 @@
+//! A module to check items before they are sent to a server
+//!
+//! Servers usually reject invalid items with a bare HTTP error (e.g. a 403), that does not tell what is wrong. This module tells it beforehand.
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use chrono::{DateTime, Utc};
+use ical::property::Property;
+
+use crate::Task;
+use crate::item::Item;
+use crate::utils::time_zone::IcalDateTime;
+
+/// A structural problem of an item, that a server would (or should) reject
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Violation {
+    /// The item has no `UID` (or an empty one)
+    MissingUid,
+    /// The `DUE` date of a task is earlier than its `DTSTART`
+    DueBeforeStart { start: DateTime<Utc>, due: DateTime<Utc> },
+    /// A date property (e.g. `DUE` or `DTSTART`) has a value that is not a valid date
+    InvalidDate { property: String, value: String },
+    /// A `RRULE` does not follow the syntax of RFC5545 (section 3.3.10)
+    MalformedRecurrenceRule { rule: String, reason: String },
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Violation::MissingUid => write!(f, "missing UID"),
+            Violation::DueBeforeStart { start, due } => write!(f, "DUE ({}) is before DTSTART ({})", due, start),
+            Violation::InvalidDate { property, value } => write!(f, "invalid {} date {:?}", property, value),
+            Violation::MalformedRecurrenceRule { rule, reason } => write!(f, "malformed RRULE {:?}: {}", rule, reason),
+        }
+    }
+}
+
+/// The error returned when an item is not valid. See [`validate`]
+#[derive(Clone, Debug)]
+pub struct ValidationError {
+    url: url::Url,
+    violations: Vec<Violation>,
+}
+
+impl ValidationError {
+    /// The URL of the invalid item
+    pub fn url(&self) -> &url::Url { &self.url }
+    /// Every problem that has been found (this is never empty)
+    pub fn violations(&self) -> &[Violation] { &self.violations }
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let violations: Vec<String> = self.violations.iter().map(|violation| violation.to_string()).collect();
+        write!(f, "Invalid item {}: {}", self.url, violations.join(", "))
+    }
+}
+
+impl Error for ValidationError {}
+
+
+/// Check an item for structural problems (e.g. a missing `UID`, a `DUE` date before its `DTSTART` or a malformed `RRULE`).
+///
+/// Overridden occurrences of recurrent tasks are checked as well. \
+/// [`RemoteCalendar`](crate::calendar::remote_calendar::RemoteCalendar)s run this before every upload, so that invalid items are not sent to the server.
+pub fn validate(item: &Item) -> Result<(), ValidationError> {
+    let violations = match item {
+        Item::Task(task) => {
+            let mut violations = task_violations(task);
+            for overridden in task.overrides() {
+                violations.extend(task_violations(overridden));
+            }
+            violations
+        },
+        Item::Event(event) => match event.uid().trim().is_empty() {
+            true => vec![Violation::MissingUid],
+            false => Vec::new(),
+        },
+    };
+
+    match violations.is_empty() {
+        true => Ok(()),
+        false => Err(ValidationError { url: item.url().clone(), violations }),
+    }
+}
+
+fn task_violations(task: &Task) -> Vec<Violation> {
+    let mut violations = Vec::new();
+
+    if task.uid().trim().is_empty() {
+        violations.push(Violation::MissingUid);
+    }
+
+    for prop in task.extra_parameters() {
+        match prop.name.as_str() {
+            "DTSTART" | "DUE" | "RECURRENCE-ID" if IcalDateTime::from_property(prop).is_none() => {
+                violations.push(Violation::InvalidDate {
+                    property: prop.name.clone(),
+                    value: prop.value.clone().unwrap_or_default(),
+                });
+            },
+            "RRULE" => {
+                let rule = prop.value.clone().unwrap_or_default();
+                if let Err(reason) = check_recurrence_rule(&rule) {
+                    violations.push(Violation::MalformedRecurrenceRule { rule, reason });
+                }
+            },
+            _ => (),
+        }
+    }
+
+    if let (Some(start), Some(due)) = (task.start(), task.due()) {
+        if due < start {
+            violations.push(Violation::DueBeforeStart { start, due });
+        }
+    }
+
+    violations
+}
+
+const FREQUENCIES: [&str; 7] = ["SECONDLY", "MINUTELY", "HOURLY", "DAILY", "WEEKLY", "MONTHLY", "YEARLY"];
+const WEEKDAYS: [&str; 7] = ["MO", "TU", "WE", "TH", "FR", "SA", "SU"];
+
+/// Check the value of a `RRULE` (e.g. `FREQ=WEEKLY;BYDAY=MO,WE;COUNT=10`)
+fn check_recurrence_rule(rule: &str) -> Result<(), String> {
+    let mut seen: Vec<&str> = Vec::new();
+
+    for part in rule.trim().split(';') {
+        let (name, value) = part.split_once('=').ok_or_else(|| format!("{:?} is not a NAME=VALUE pair", part))?;
+        if seen.contains(&name) {
+            return Err(format!("{} is specified more than once", name));
+        }
+        seen.push(name);
+
+        let valid = match name {
+            "FREQ" => FREQUENCIES.contains(&value),
+            "UNTIL" => is_date(value),
+            "COUNT" | "INTERVAL" => matches!(value.parse::<u32>(), Ok(n) if n > 0),
+            "BYSECOND" => is_number_list(value, 0, 60, false),
+            "BYMINUTE" => is_number_list(value, 0, 59, false),
+            "BYHOUR" => is_number_list(value, 0, 23, false),
+            "BYMONTHDAY" => is_number_list(value, 1, 31, true),
+            "BYYEARDAY" => is_number_list(value, 1, 366, true),
+            "BYWEEKNO" => is_number_list(value, 1, 53, true),
+            "BYMONTH" => is_number_list(value, 1, 12, false),
+            "BYSETPOS" => is_number_list(value, 1, 366, true),
+            "BYDAY" => value.split(',').all(is_weekday_number),
+            "WKST" => WEEKDAYS.contains(&value),
+            _ => return Err(format!("unknown rule part {}", name)),
+        };
+        if valid == false {
+            return Err(format!("invalid value {:?} for {}", value, name));
+        }
+    }
+
+    if seen.contains(&"FREQ") == false {
+        return Err(String::from("FREQ is missing"));
+    }
+    if seen.contains(&"UNTIL") && seen.contains(&"COUNT") {
+        return Err(String::from("UNTIL and COUNT must not be used together"));
+    }
+    Ok(())
+}
+
+fn is_date(value: &str) -> bool {
+    let mut prop = Property::new();
+    prop.value = Some(value.to_string());
+    IcalDateTime::from_property(&prop).is_some()
+}
+
+/// Whether `value` is a comma-separated list of numbers in `[min, max]` (or in `[-max, -min]` as well, in case `signed` is true)
+fn is_number_list(value: &str, min: u32, max: u32, signed: bool) -> bool {
+    value.split(',').all(|number| {
+        let unsigned = match signed {
+            true => number.strip_prefix(|c| c == '+' || c == '-').unwrap_or(number),
+            false => number,
+        };
+        unsigned.is_empty() == false
+            && unsigned.chars().all(|c| c.is_ascii_digit())
+            && matches!(unsigned.parse::<u32>(), Ok(n) if min <= n && n <= max)
+    })
+}
+
+/// Whether `value` is a weekday, optionally preceded by its (signed) position in the period (e.g. `MO`, `1MO` or `-1FR`)
+fn is_weekday_number(value: &str) -> bool {
+    if value.len() < 2 || value.is_char_boundary(value.len() - 2) == false {
+        return false;
+    }
+    let (position, weekday) = value.split_at(value.len() - 2);
+    WEEKDAYS.contains(&weekday) && (position.is_empty() || is_number_list(position, 1, 53, true))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    use crate::item::SyncStatus;
+    use crate::test_utils::TaskBuilder;
+
+    fn task(properties: &str) -> Item {
+        let ical = format!("BEGIN:VCALENDAR\r\n\
+            VERSION:2.0\r\n\
+            PRODID:-//Example Corp.//CalDAV Client//EN\r\n\
+            BEGIN:VTODO\r\n\
+            UID:some-uid\r\n\
+            DTSTAMP:20210321T001600Z\r\n\
+            SUMMARY:Some task\r\n\
+            {}END:VTODO\r\n\
+            END:VCALENDAR\r\n", properties);
+        crate::ical::parse(&ical, "https://some.calendar/some-task.ics".parse().unwrap(), SyncStatus::NotSynced).unwrap()
+    }
+
+    fn violations(properties: &str) -> Vec<Violation> {
+        match validate(&task(properties)) {
+            Ok(()) => Vec::new(),
+            Err(err) => err.violations().to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_valid_items() {
+        assert!(validate(&Item::Task(Task::new(String::from("Buy milk"), false, &"https://some.calendar/".parse().unwrap()))).is_ok());
+        assert_eq!(violations("DTSTART:20210322T090000Z\r\nDUE:20210322T090000Z\r\n"), vec![]);
+        assert_eq!(violations("DTSTART:20210322T090000Z\r\nRRULE:FREQ=MONTHLY;BYDAY=-1FR,2MO;INTERVAL=2;UNTIL=20211231\r\n"), vec![]);
+        assert_eq!(violations("RRULE:FREQ=YEARLY;BYMONTH=1,7;BYMONTHDAY=-1;COUNT=10;WKST=SU\r\n"), vec![]);
+    }
+
+    #[test]
+    fn test_violations() {
+        assert_eq!(violations("DTSTART:20210322T090000Z\r\nDUE:20210321T090000Z\r\n"), vec![Violation::DueBeforeStart {
+            start: Utc.ymd(2021, 3, 22).and_hms(9, 0, 0),
+            due: Utc.ymd(2021, 3, 21).and_hms(9, 0, 0),
+        }]);
+        assert_eq!(violations("DUE:tomorrow\r\n"), vec![Violation::InvalidDate { property: String::from("DUE"), value: String::from("tomorrow") }]);
+
+        for rule in ["BYDAY=MO", "FREQ=FORTNIGHTLY", "FREQ=DAILY;COUNT=0", "FREQ=DAILY;COUNT=3;UNTIL=20211231", "FREQ=DAILY;FREQ=WEEKLY",
+                     "FREQ=MONTHLY;BYMONTHDAY=32", "FREQ=WEEKLY;BYDAY=MONDAY", "FREQ=WEEKLY;BYDAY=+MO", "FREQ=DAILY;BYHOUR=-1", "FREQ=DAILY;ON=MO", "FREQ"] {
+            let found = violations(&format!("RRULE:{}\r\n", rule));
+            assert!(matches!(found.as_slice(), [Violation::MalformedRecurrenceRule { .. }]), "{} gave {:?}", rule, found);
+        }
+
+        let nameless = TaskBuilder::new("Nameless", &"https://some.calendar/".parse().unwrap())
+            .uid(" ").url("https://some.calendar/nameless.ics".parse().unwrap()).build();
+        let err = validate(&Item::Task(nameless)).unwrap_err();
+        assert_eq!(err.violations(), &[Violation::MissingUid]);
+        assert_eq!(err.to_string(), "Invalid item https://some.calendar/nameless.ics: missing UID");
+    }
+}
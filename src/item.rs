@@ -76,7 +76,7 @@ impl Item {
         }
     }
 
-    #[cfg(any(test, feature = "integration_tests"))]
+    #[cfg(any(test, feature = "testing"))]
     pub fn has_same_observable_content_as(&self, other: &Item) -> bool {
         match (self, other) {
             (Item::Event(s), Item::Event(o)) => s.has_same_observable_content_as(o),
@@ -89,6 +89,36 @@ impl Item {
 
 
 
+/// A single instance of an item.
+///
+/// Servers can expand recurrent items into their discrete instances (see [`crate::traits::DavCalendar::get_occurrences_between`]). Every instance is an `Occurrence`.
+#[derive(Clone, Debug)]
+pub struct Occurrence {
+    recurrence_id: Option<DateTime<Utc>>,
+    item: Item,
+}
+
+impl Occurrence {
+    pub fn new(recurrence_id: Option<DateTime<Utc>>, item: Item) -> Self {
+        Self { recurrence_id, item }
+    }
+
+    /// The `RECURRENCE-ID` of this instance, or `None` in case the item is not recurrent
+    pub fn recurrence_id(&self) -> Option<&DateTime<Utc>> {
+        self.recurrence_id.as_ref()
+    }
+
+    /// The content of this instance. Note that every instance of an item shares the same URL
+    pub fn item(&self) -> &Item {
+        &self.item
+    }
+
+    pub fn into_item(self) -> Item {
+        self.item
+    }
+}
+
+
 /// A VersionTag is basically a CalDAV `ctag` or `etag`. Whenever it changes, this means the data has changed.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct VersionTag {
@@ -108,9 +138,8 @@ impl VersionTag {
     }
 
     /// Generate a random VersionTag
-    #[cfg(feature = "local_calendar_mocks_remote_calendars")]
     pub fn random() -> Self {
-        let random = uuid::Uuid::new_v4().to_hyphenated().to_string();
+        let random = crate::deterministic::new_uuid().to_hyphenated().to_string();
         Self { tag: random }
     }
 }
@@ -132,7 +161,6 @@ pub enum SyncStatus {
 }
 impl SyncStatus {
     /// Generate a random SyncStatus::Synced
-    #[cfg(feature = "local_calendar_mocks_remote_calendars")]
     pub fn random_synced() -> Self {
         Self::Synced(VersionTag::random())
     }
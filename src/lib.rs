@@ -16,16 +16,56 @@
 //! A `CalDavProvider` abstracts these two sources by merging them together into one virtual source. \
 //! It also handles synchronisation between the local cache and the server, and robustly recovers from any network error (so that it never corrupts the local or remote source).
 //!
+//! Several servers (e.g. a personal server and a work server) can be merged into a single local cache with an [`AggregateProvider`](provider::AggregateProvider).
+//!
 //! Note that many methods are defined in common traits (see [`crate::traits`]).
 //!
+//! Apps that do not use async code can use the blocking wrappers of the [`blocking`] module instead.
+//!
 //! ## Examples
 //!
 //! See example usage in the `examples/` folder, that you can run using `cargo run --example <example-name>`. \
 //! You can also have a look at [`Voilà`](https://github.com/daladim/voila-tasks), a GUI app that uses `kitchen-fridge` under the hood.
 //!
+//! ## Working offline
+//!
+//! A [`LocalProvider`] has no server: it either only keeps data locally, or syncs it with another folder, that can in turn be shared between devices by any file-syncing tool.
+//!
+//! ## Sharing a cache between processes
+//!
+//! With the `watch` Cargo feature, a [`Cache`] can watch its backing folder and reload the calendars that have been modified by another process. See [`cache_watcher`].
+//!
+//! ## Google Tasks
+//!
+//! With the `google_tasks` Cargo feature, tasks can be synced with the Google Tasks API, for Google accounts that do not expose their tasks over CalDAV. See [`google_tasks`].
+//!
+//! ## Testing apps
+//!
+//! With the `mock_server` Cargo feature, the [`mock_server`] module runs a minimal CalDAV server in the current process, so that code that uses a [`Client`] can be tested over real HTTP. \
+//! With the `testing` Cargo feature, the [`testing`] module lets apps check how their own sync scenarii play out, against a mocked server. \
+//! With the `arbitrary` Cargo feature, the [`arbitrary`] module provides [proptest](https://docs.rs/proptest) generators of tasks and iCal inputs, for property-based tests. \
+//! Finally, the [`deterministic`] module makes the dates and identifiers generated by this crate reproducible from one run to another.
+//!
 //! ## Configuration options
 //!
-//! Have a look at the [`config`] module to see what default options can be overridden.
+//! Have a look at the [`config`] module to see what default options can be overridden. \
+//! Daemons can monitor the activity of the sync (e.g. requests, errors or synced items) with the [`metrics`] module,
+//! and match their logs with the logs of the server thanks to the [`correlation`] module.
+//! The targets of the log messages of this crate are listed in the [`logging`] module.
+//!
+//! ## TLS backends
+//!
+//! Connections to HTTPS servers use [rustls](https://docs.rs/rustls) by default. Disable the default features and enable the `native-tls` Cargo feature to use the TLS library of the system instead.
+//! When both features are enabled, the backend can be chosen at runtime, see [`client::ClientBuilder::tls_backend`].
+//!
+//! ## Smaller builds
+//!
+//! The crate is split into Cargo features, so that apps only build what they use:
+//! * `client`: the CalDAV [`client`] (enabled by any TLS feature). This is the only part that depends on `reqwest`
+//! * `cache`: the local [`cache`], that is stored on the filesystem. This is the only part that depends on `serde_json`
+//!
+//! Both are enabled by default. The data model (e.g. [`Item`], [`Task`]), the [`traits`] and the generic [`provider::Provider`] are always available.
+//! For instance, an app that only talks to a server can use `default-features = false, features = ["rustls"]`.
 
 #![doc(html_logo_url = "https://raw.githubusercontent.com/daladim/kitchen-fridge/master/resources/kitchen-fridge.svg")]
 
@@ -38,19 +78,53 @@ pub mod task;
 pub use task::Task;
 pub mod event;
 pub use event::Event;
+pub mod free_busy;
+pub mod alarm;
+pub mod scheduling;
+pub mod markdown;
 pub mod provider;
+pub mod blocking;
 pub mod mock_behaviour;
+pub mod mock_server;
+pub mod testing;
+pub mod arbitrary;
 
 pub mod client;
+#[cfg(feature = "client")]
 pub use client::Client;
+pub mod server_profile;
+pub mod google_tasks;
 pub mod cache;
+#[cfg(feature = "cache")]
 pub use cache::Cache;
+pub mod cache_watcher;
+pub mod transaction;
 pub mod ical;
 
 pub mod config;
+pub mod deterministic;
+pub mod metrics;
+pub mod correlation;
+pub mod logging;
 pub mod utils;
 pub mod resource;
+#[cfg(test)]
+mod test_utils;
+
+/// Items used by the macros of this crate. This is not part of the public API.
+#[doc(hidden)]
+pub mod __private {
+    pub use async_trait::async_trait;
+    pub use url::Url;
+    pub use csscolorparser::Color;
+}
 
 /// Unless you want another kind of Provider to write integration tests, you'll probably want this kind of Provider. \
 /// See alse the [`Provider` documentation](crate::provider::Provider)
+#[cfg(all(feature = "client", feature = "cache"))]
 pub type CalDavProvider = provider::Provider<cache::Cache, calendar::cached_calendar::CachedCalendar, Client, calendar::remote_calendar::RemoteCalendar>;
+
+/// A Provider that does not talk to any server: its remote source is either absent or another cache folder. \
+/// See [`Provider::new_local_only`](crate::provider::Provider::new_local_only) and [`Provider::new_folder_sync`](crate::provider::Provider::new_folder_sync)
+#[cfg(feature = "cache")]
+pub type LocalProvider = provider::Provider<cache::Cache, calendar::cached_calendar::CachedCalendar, cache::Cache, calendar::cached_calendar::CachedCalendar>;
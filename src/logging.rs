@@ -0,0 +1,19 @@
+//! The targets of the log messages of this crate
+//!
+//! Log messages are emitted with the [`log`](https://docs.rs/log) crate, under the following targets rather than under the paths of the modules they come from,
+//! so that apps can tune the verbosity of each part of the crate separately. \
+//! For instance, with [env_logger](https://docs.rs/env_logger), `RUST_LOG=warn,kitchen_fridge::provider::diff=debug` shows how the items of every calendar are synced, without showing every HTTP request.
+//!
+//! Messages from other parts of the crate (e.g. the iCal parser) keep their module paths as targets.
+
+/// The CalDAV client: discovery of the calendars, HTTP requests and their responses
+pub const CLIENT: &str = "kitchen_fridge::client";
+
+/// The outline of a sync: which calendars are synced, and the warnings and errors that happen
+pub const PROVIDER: &str = "kitchen_fridge::provider";
+
+/// The details of a sync: how every item has been found to differ between both sources, and how these differences are applied
+pub const PROVIDER_DIFF: &str = "kitchen_fridge::provider::diff";
+
+/// The local cache: loading and saving calendars, and watching the cache folder
+pub const CACHE: &str = "kitchen_fridge::cache";
@@ -0,0 +1,146 @@
+//! Export of tasks as Markdown checklists, e.g. to paste them into notes or issues
+
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+
+use crate::Task;
+
+/// How the tasks of a checklist are grouped into sections
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Grouping {
+    /// One section per calendar
+    Calendar,
+    /// One section per due date (tasks without a due date come last)
+    DueDate,
+}
+
+/// Render tasks as a Markdown checklist.
+///
+/// `tasks` are pairs of the name of the calendar a task belongs to, and the task itself. They can be any subset of the tasks of any calendars (e.g. the result of a filtered query). \
+/// Within a section, tasks are sorted by due date, then by name.
+pub fn build_checklist<'a, I>(tasks: I, grouping: Grouping) -> String
+where
+    I: IntoIterator<Item = (&'a str, &'a Task)>,
+{
+    let mut sections: BTreeMap<SectionKey, Vec<(&str, &Task)>> = BTreeMap::new();
+    for (cal_name, task) in tasks {
+        let key = match grouping {
+            Grouping::Calendar => SectionKey::Calendar(cal_name.to_string()),
+            Grouping::DueDate => {
+                let due = task.due().map(|due| due.naive_utc().date());
+                SectionKey::DueDate(due.is_none(), due)
+            },
+        };
+        sections.entry(key).or_default().push((cal_name, task));
+    }
+
+    let mut markdown = String::new();
+    for (key, mut tasks) in sections {
+        tasks.sort_by(|(_, a), (_, b)| {
+            // Tasks without a due date come last
+            (a.due().is_none(), a.due(), a.name()).cmp(&(b.due().is_none(), b.due(), b.name()))
+        });
+
+        if markdown.is_empty() == false {
+            markdown.push('\n');
+        }
+        markdown.push_str(&format!("## {}\n\n", key.title()));
+        for (cal_name, task) in tasks {
+            let checkbox = if task.completed() { "[x]" } else { "[ ]" };
+            markdown.push_str(&format!("- {} {}", checkbox, escape(task.name())));
+            match grouping {
+                Grouping::Calendar => {
+                    if let Some(due) = task.due() {
+                        markdown.push_str(&format!(" (due {})", due.naive_utc().date()));
+                    }
+                },
+                Grouping::DueDate => markdown.push_str(&format!(" _({})_", escape(cal_name))),
+            }
+            markdown.push('\n');
+        }
+    }
+    markdown
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum SectionKey {
+    Calendar(String),
+    /// `None` is the section of the tasks that have no due date.
+    /// It must be sorted last, hence the `bool` (that tells whether the date is `None`)
+    DueDate(bool, Option<NaiveDate>),
+}
+
+impl SectionKey {
+    fn title(&self) -> String {
+        match self {
+            SectionKey::Calendar(name) => escape(name),
+            SectionKey::DueDate(_, Some(date)) => date.to_string(),
+            SectionKey::DueDate(_, None) => String::from("No due date"),
+        }
+    }
+}
+
+/// Escape the characters that Markdown would interpret
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' | '|' => {
+                escaped.push('\\');
+                escaped.push(c);
+            },
+            '\n' | '\r' => escaped.push(' '),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use url::Url;
+
+    use crate::test_utils::TaskBuilder;
+
+    fn task(name: &str) -> TaskBuilder {
+        let cal_url: Url = "https://some.calendar/".parse().unwrap();
+        TaskBuilder::new(name, &cal_url)
+    }
+
+    #[test]
+    fn test_checklist() {
+        let milk = task("Buy milk").property("DUE", "20210322T090000Z").build();
+        let eggs = task("Buy *eggs*").completed(true).build();
+        let concert = task("Attend a concert").property("DUE", "20210321").build();
+        let tasks = vec![("Shopping", &milk), ("Shopping", &eggs), ("Bucket list", &concert)];
+
+        assert_eq!(build_checklist(tasks.clone(), Grouping::Calendar), "\
+## Bucket list
+
+- [ ] Attend a concert (due 2021-03-21)
+
+## Shopping
+
+- [ ] Buy milk (due 2021-03-22)
+- [x] Buy \\*eggs\\*
+");
+
+        assert_eq!(build_checklist(tasks, Grouping::DueDate), "\
+## 2021-03-21
+
+- [ ] Attend a concert _(Bucket list)_
+
+## 2021-03-22
+
+- [ ] Buy milk _(Shopping)_
+
+## No due date
+
+- [x] Buy \\*eggs\\* _(Shopping)_
+");
+    }
+}
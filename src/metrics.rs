@@ -0,0 +1,96 @@
+//! Hooks to monitor the activity of clients and providers (e.g. to export it to Prometheus or StatsD)
+//!
+//! Implement [`Metrics`] and set it in the [`Config`](crate::config::Config) of a [`Client`](crate::client::Client) and of a [`Provider`](crate::provider::Provider).
+//! Its methods are called for every HTTP request, and every time items are synced. \
+//! They are called from async code, and should return quickly (e.g. by only updating counters).
+//!
+//! ```
+//! use std::sync::Arc;
+//! use std::sync::atomic::{AtomicU64, Ordering};
+//! use std::time::Duration;
+//! use kitchen_fridge::config::Config;
+//! use kitchen_fridge::metrics::{Metrics, SharedMetrics};
+//!
+//! #[derive(Debug, Default)]
+//! struct RequestCounter(AtomicU64);
+//!
+//! impl Metrics for RequestCounter {
+//!     fn request(&self, _method: &str, _latency: Duration, _status: Option<u16>) {
+//!         self.0.fetch_add(1, Ordering::Relaxed);
+//!     }
+//! }
+//!
+//! let counter = Arc::new(RequestCounter::default());
+//! let config = Config {
+//!     metrics: Some(SharedMetrics::new(counter.clone())),
+//!     ..Config::default()
+//! };
+//! ```
+
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// What happened to items during a sync, see [`Metrics::items_synced`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ItemSync {
+    /// Items that have been added or changed on the server, and have been downloaded
+    Downloaded,
+    /// Items that have been added or changed locally, and have been uploaded
+    Uploaded,
+    /// Items that have been deleted from the server, and have been deleted locally as well
+    DeletedLocally,
+    /// Items that have been deleted locally, and have been deleted from the server as well
+    DeletedRemotely,
+}
+
+/// Something that records the activity of clients and providers.
+///
+/// Every method does nothing by default, so that implementors only record what they are interested in
+pub trait Metrics: Debug + Send + Sync {
+    /// An HTTP request has been answered with a given `status` code, or has failed (`None`), e.g. because the server could not be reached.
+    ///
+    /// `method` is the HTTP method of the request (e.g. `PROPFIND`, `REPORT` or `PUT`). Requests that are sent again after a failure are recorded every time.
+    fn request(&self, _method: &str, _latency: Duration, _status: Option<u16>) {}
+
+    /// Bytes have been sent to, or received from the server. These are sizes of HTTP bodies
+    fn bytes_transferred(&self, _sent: u64, _received: u64) {}
+
+    /// Items have been synced
+    fn items_synced(&self, _kind: ItemSync, _count: usize) {}
+
+    /// An error happened during a sync (i.e. an error that makes the sync unsuccessful)
+    fn sync_error(&self) {}
+
+    /// A sync is over
+    fn sync_finished(&self, _duration: Duration, _success: bool) {}
+}
+
+/// A [`Metrics`] implementation, as it is shared by [`Config`](crate::config::Config)s
+///
+/// Two `SharedMetrics` are equal in case they are the very same object
+#[derive(Clone, Debug)]
+pub struct SharedMetrics(Arc<dyn Metrics>);
+
+impl SharedMetrics {
+    pub fn new(metrics: Arc<dyn Metrics>) -> Self {
+        Self(metrics)
+    }
+}
+
+impl std::ops::Deref for SharedMetrics {
+    type Target = dyn Metrics;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.0
+    }
+}
+
+impl PartialEq for SharedMetrics {
+    fn eq(&self, other: &Self) -> bool {
+        // Only compare the data pointers, since vtables may be duplicated across codegen units
+        Arc::as_ptr(&self.0) as *const () == Arc::as_ptr(&other.0) as *const ()
+    }
+}
+
+impl Eq for SharedMetrics {}
@@ -1,11 +1,87 @@
-//! This module provides ways to tweak mocked calendars, so that they can return errors on some tests
+//! This module provides ways to tweak mocked calendars, so that they can return errors (or be slow) on some tests
 #![cfg(feature = "local_calendar_mocks_remote_calendars")]
 
 use std::error::Error;
+use std::collections::{HashMap, VecDeque};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// An artificial delay, that mocked calendars wait before performing an operation, to simulate a slow server
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Latency {
+    /// The operation is performed at once
+    #[default]
+    None,
+    /// The operation always takes the same time
+    Fixed(Duration),
+    /// The operation takes a random time between these two values (both included)
+    Random(Duration, Duration),
+}
+
+impl Latency {
+    /// Pick the duration of an operation
+    pub fn duration(&self) -> Duration {
+        match self {
+            Latency::None => Duration::ZERO,
+            Latency::Fixed(duration) => *duration,
+            Latency::Random(min, max) => {
+                if max <= min {
+                    return *min;
+                }
+                // No need for a proper random generator here, the hash keys of std are random enough
+                let random = RandomState::new().build_hasher().finish();
+                let span = (*max - *min).as_nanos() as u64;
+                *min + Duration::from_nanos(random % (span + 1))
+            },
+        }
+    }
+}
+
+/// An operation of a mocked source or calendar
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MockOperation {
+    GetCalendars,
+    CreateCalendar,
+    DeleteCalendar,
+    AddItem,
+    UpdateItem,
+    GetItemVersionTags,
+    GetItemByUrl,
+    DeleteItem,
+}
+
+/// The kind of error a scripted operation returns
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MockErrorKind {
+    /// The server could not be reached
+    Network,
+    /// The server answered with an HTTP 404
+    NotFound,
+    /// The server answered with an HTTP 412 (the item has been changed in the meantime)
+    PreconditionFailed,
+    /// The server answered with an HTTP 500
+    Server,
+}
+
+/// The outcome of a single scripted operation, see [`MockBehaviour::script`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MockOutcome {
+    Ok,
+    Err(MockErrorKind),
+    /// The server did not answer in time: the operation fails, after [`MockBehaviour::timeout_delay`]
+    Timeout,
+}
+
+/// How long a scripted [`MockOutcome::Timeout`] takes before it fails, unless [`MockBehaviour::timeout_delay`] tells otherwise
+pub const DEFAULT_TIMEOUT_DELAY: Duration = Duration::from_millis(100);
 
 /// This stores some behaviour tweaks, that describe how a mocked instance will behave during a given test
 ///
-/// So that a functions fails _n_ times after _m_ initial successes, set `(m, n)` for the suited parameter
+/// So that a functions fails _n_ times after _m_ initial successes, set `(m, n)` for the suited parameter. \
+/// The `(m, n)` counters of the corruption modes (e.g. `partial_multiget_behaviour`) work the same way: these calls succeed, but return wrong data _n_ times after _m_ correct answers. \
+/// For more precise orderings, a sequence of outcomes can be scripted for every operation (see [`MockBehaviour::script`]). Scripted outcomes take precedence over the `(m, n)` counters, that are used again once the script is over.
 #[derive(Default, Clone, Debug)]
 pub struct MockBehaviour {
     /// If this is true, every action will be allowed
@@ -15,6 +91,7 @@ pub struct MockBehaviour {
     pub get_calendars_behaviour: (u32, u32),
     //pub get_calendar_behaviour: (u32, u32),
     pub create_calendar_behaviour: (u32, u32),
+    pub delete_calendar_behaviour: (u32, u32),
 
     // From the BaseCalendar trait
     pub add_item_behaviour: (u32, u32),
@@ -24,6 +101,35 @@ pub struct MockBehaviour {
     pub get_item_version_tags_behaviour: (u32, u32),
     pub get_item_by_url_behaviour: (u32, u32),
     pub delete_item_behaviour: (u32, u32),
+
+    // Corruption modes, that return wrong data rather than errors
+    /// `get_items_by_url` omits the last item of the batch
+    pub partial_multiget_behaviour: (u32, u32),
+    /// `get_item_version_tags` returns version tags that change on every call
+    pub changing_version_tags_behaviour: (u32, u32),
+    /// `get_item_by_url` (and `get_items_by_url`) returns an item that cannot be parsed
+    pub unparsable_items_behaviour: (u32, u32),
+
+    // Artificial delays, that are waited for before the matching operation.
+    // They are not waited for while this behaviour is suspended
+    pub get_calendars_latency: Latency,
+    pub create_calendar_latency: Latency,
+    pub delete_calendar_latency: Latency,
+    pub add_item_latency: Latency,
+    pub update_item_latency: Latency,
+    pub get_item_version_tags_latency: Latency,
+    pub get_item_by_url_latency: Latency,
+    pub delete_item_latency: Latency,
+
+    /// The outcomes of the next calls of every operation
+    pub scripts: HashMap<MockOperation, VecDeque<MockOutcome>>,
+    /// How long a scripted [`MockOutcome::Timeout`] takes before it fails, on top of the latency of its operation (`None` for [`DEFAULT_TIMEOUT_DELAY`])
+    pub timeout_delay: Option<Duration>,
+
+    /// How many calls of every operation are waiting for their latency right now
+    pub in_flight: HashMap<MockOperation, usize>,
+    /// The most calls of every operation that have waited for their latency at the same time (e.g. to check how many requests a sync sends concurrently)
+    pub max_in_flight: HashMap<MockOperation, usize>,
 }
 
 impl MockBehaviour {
@@ -38,14 +144,48 @@ impl MockBehaviour {
             get_calendars_behaviour: (0, n_fails),
             //get_calendar_behaviour: (0, n_fails),
             create_calendar_behaviour: (0, n_fails),
+            delete_calendar_behaviour: (0, n_fails),
             add_item_behaviour: (0, n_fails),
             update_item_behaviour: (0, n_fails),
             get_item_version_tags_behaviour: (0, n_fails),
             get_item_by_url_behaviour: (0, n_fails),
             delete_item_behaviour: (0, n_fails),
+            ..Self::default()
+        }
+    }
+
+    /// Every operation will succeed, but only after a delay
+    pub fn slow(latency: Latency) -> Self {
+        Self {
+            get_calendars_latency: latency,
+            create_calendar_latency: latency,
+            delete_calendar_latency: latency,
+            add_item_latency: latency,
+            update_item_latency: latency,
+            get_item_version_tags_latency: latency,
+            get_item_by_url_latency: latency,
+            delete_item_latency: latency,
+            ..Self::default()
         }
     }
 
+    /// Script the outcomes of the next calls of an operation (they are appended to the outcomes that may already be scripted)
+    ///
+    /// ```
+    /// use kitchen_fridge::mock_behaviour::{MockBehaviour, MockOperation, MockOutcome, MockErrorKind};
+    ///
+    /// let mut behaviour = MockBehaviour::new()
+    ///     .script(MockOperation::AddItem, vec![MockOutcome::Ok, MockOutcome::Err(MockErrorKind::Server), MockOutcome::Timeout]);
+    /// assert!(behaviour.can_add_item().is_ok());
+    /// assert!(behaviour.can_add_item().is_err());
+    /// assert!(behaviour.can_add_item().is_err());
+    /// assert!(behaviour.can_add_item().is_ok());
+    /// ```
+    pub fn script(mut self, operation: MockOperation, outcomes: Vec<MockOutcome>) -> Self {
+        self.scripts.entry(operation).or_default().extend(outcomes);
+        self
+    }
+
     /// Suspend this mock behaviour until you call `resume`
     pub fn suspend(&mut self) {
         self.is_suspended = true;
@@ -58,10 +198,21 @@ impl MockBehaviour {
     pub fn copy_from(&mut self, other: &Self) {
         self.get_calendars_behaviour = other.get_calendars_behaviour;
         self.create_calendar_behaviour = other.create_calendar_behaviour;
+        self.delete_calendar_behaviour = other.delete_calendar_behaviour;
+        self.get_calendars_latency = other.get_calendars_latency;
+        self.create_calendar_latency = other.create_calendar_latency;
+        self.delete_calendar_latency = other.delete_calendar_latency;
+        for operation in [MockOperation::GetCalendars, MockOperation::CreateCalendar, MockOperation::DeleteCalendar] {
+            match other.scripts.get(&operation) {
+                None => { self.scripts.remove(&operation); },
+                Some(script) => { self.scripts.insert(operation, script.clone()); },
+            }
+        }
     }
 
     pub fn can_get_calendars(&mut self) -> Result<(), Box<dyn Error>> {
         if self.is_suspended { return Ok(()) }
+        if let Some(result) = play_script(&mut self.scripts, MockOperation::GetCalendars, "get_calendars") { return result }
         decrement(&mut self.get_calendars_behaviour, "get_calendars")
     }
     // pub fn can_get_calendar(&mut self) -> Result<(), Box<dyn Error>> {
@@ -70,30 +221,111 @@ impl MockBehaviour {
     // }
     pub fn can_create_calendar(&mut self) -> Result<(), Box<dyn Error>> {
         if self.is_suspended { return Ok(()) }
+        if let Some(result) = play_script(&mut self.scripts, MockOperation::CreateCalendar, "create_calendar") { return result }
         decrement(&mut self.create_calendar_behaviour, "create_calendar")
     }
+    pub fn can_delete_calendar(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.is_suspended { return Ok(()) }
+        if let Some(result) = play_script(&mut self.scripts, MockOperation::DeleteCalendar, "delete_calendar") { return result }
+        decrement(&mut self.delete_calendar_behaviour, "delete_calendar")
+    }
     pub fn can_add_item(&mut self) -> Result<(), Box<dyn Error>> {
         if self.is_suspended { return Ok(()) }
+        if let Some(result) = play_script(&mut self.scripts, MockOperation::AddItem, "add_item") { return result }
         decrement(&mut self.add_item_behaviour, "add_item")
     }
     pub fn can_update_item(&mut self) -> Result<(), Box<dyn Error>> {
         if self.is_suspended { return Ok(()) }
+        if let Some(result) = play_script(&mut self.scripts, MockOperation::UpdateItem, "update_item") { return result }
         decrement(&mut self.update_item_behaviour, "update_item")
     }
     pub fn can_get_item_version_tags(&mut self) -> Result<(), Box<dyn Error>> {
         if self.is_suspended { return Ok(()) }
+        if let Some(result) = play_script(&mut self.scripts, MockOperation::GetItemVersionTags, "get_item_version_tags") { return result }
         decrement(&mut self.get_item_version_tags_behaviour, "get_item_version_tags")
     }
     pub fn can_get_item_by_url(&mut self) -> Result<(), Box<dyn Error>> {
         if self.is_suspended { return Ok(()) }
+        if let Some(result) = play_script(&mut self.scripts, MockOperation::GetItemByUrl, "get_item_by_url") { return result }
         decrement(&mut self.get_item_by_url_behaviour, "get_item_by_url")
     }
     pub fn can_delete_item(&mut self) -> Result<(), Box<dyn Error>> {
         if self.is_suspended { return Ok(()) }
+        if let Some(result) = play_script(&mut self.scripts, MockOperation::DeleteItem, "delete_item") { return result }
         decrement(&mut self.delete_item_behaviour, "delete_item")
     }
+
+    /// The artificial delay of an operation
+    pub fn latency(&self, operation: MockOperation) -> Latency {
+        match operation {
+            MockOperation::GetCalendars => self.get_calendars_latency,
+            MockOperation::CreateCalendar => self.create_calendar_latency,
+            MockOperation::DeleteCalendar => self.delete_calendar_latency,
+            MockOperation::AddItem => self.add_item_latency,
+            MockOperation::UpdateItem => self.update_item_latency,
+            MockOperation::GetItemVersionTags => self.get_item_version_tags_latency,
+            MockOperation::GetItemByUrl => self.get_item_by_url_latency,
+            MockOperation::DeleteItem => self.delete_item_latency,
+        }
+    }
+
+    pub fn should_omit_from_multiget(&mut self) -> bool {
+        if self.is_suspended { return false }
+        decrement(&mut self.partial_multiget_behaviour, "multiget without its last item").is_err()
+    }
+    pub fn should_change_version_tags(&mut self) -> bool {
+        if self.is_suspended { return false }
+        decrement(&mut self.changing_version_tags_behaviour, "listing of changing version tags").is_err()
+    }
+    pub fn should_return_unparsable_item(&mut self) -> bool {
+        if self.is_suspended { return false }
+        decrement(&mut self.unparsable_items_behaviour, "unparsable item").is_err()
+    }
+}
+
+
+/// Wait for the latency of an operation, in case there is a mock behaviour. In case its next scripted outcome is a [`MockOutcome::Timeout`], wait for the timeout as well
+pub(crate) async fn simulate_latency(behaviour: &Option<Arc<Mutex<MockBehaviour>>>, operation: MockOperation) {
+    let duration = match behaviour {
+        None => return,
+        Some(behaviour) => {
+            let behaviour = behaviour.lock().unwrap();
+            if behaviour.is_suspended {
+                return;
+            }
+            let times_out = behaviour.scripts.get(&operation).and_then(|script| script.front()) == Some(&MockOutcome::Timeout);
+            match times_out {
+                true => behaviour.latency(operation).duration() + behaviour.timeout_delay.unwrap_or(DEFAULT_TIMEOUT_DELAY),
+                false => behaviour.latency(operation).duration(),
+            }
+        },
+    };
+    if duration.is_zero() == false {
+        let behaviour = behaviour.as_ref().unwrap();
+        {
+            let mut behaviour = behaviour.lock().unwrap();
+            let in_flight = behaviour.in_flight.entry(operation).or_default();
+            *in_flight += 1;
+            let in_flight = *in_flight;
+            let max_in_flight = behaviour.max_in_flight.entry(operation).or_default();
+            *max_in_flight = (*max_in_flight).max(in_flight);
+        }
+        log::debug!("Mock behaviour: waiting {:?}", duration);
+        tokio::time::sleep(duration).await;
+        *behaviour.lock().unwrap().in_flight.entry(operation).or_default() -= 1;
+    }
 }
 
+/// Play the next scripted outcome of an operation, or return `None` in case there is no (more) script for it
+fn play_script(scripts: &mut HashMap<MockOperation, VecDeque<MockOutcome>>, operation: MockOperation, descr: &str) -> Option<Result<(), Box<dyn Error>>> {
+    let outcome = scripts.get_mut(&operation)?.pop_front()?;
+    log::debug!("Mock behaviour: scripted {:?} for a {}", outcome, descr);
+    match outcome {
+        MockOutcome::Ok => Some(Ok(())),
+        MockOutcome::Err(kind) => Some(Err(format!("Mocked behaviour requires this {} to fail with a {:?} error", descr, kind).into())),
+        MockOutcome::Timeout => Some(Err(format!("Mocked behaviour requires this {} to time out", descr).into())),
+    }
+}
 
 /// Return Ok(()) in case the value is `(1+, _)` or `(_, 0)`, or return Err and decrement otherwise
 fn decrement(value: &mut (u32, u32), descr: &str) -> Result<(), Box<dyn Error>> {
@@ -101,12 +333,12 @@ fn decrement(value: &mut (u32, u32), descr: &str) -> Result<(), Box<dyn Error>>
     let remaining_failures = value.1;
 
     if remaining_successes > 0 {
-        value.0 = value.0 - 1;
+        value.0 -= 1;
         log::debug!("Mock behaviour: allowing a {} ({:?})", descr, value);
         Ok(())
     } else {
         if remaining_failures > 0 {
-            value.1 = value.1 - 1;
+            value.1 -= 1;
             log::debug!("Mock behaviour: failing a {} ({:?})", descr, value);
             Err(format!("Mocked behaviour requires this {} to fail this time. ({:?})", descr, value).into())
         } else {
@@ -159,4 +391,63 @@ mod test {
         assert!(custom.can_create_calendar().is_ok());
         assert!(custom.can_create_calendar().is_ok());
     }
+
+    #[test]
+    fn test_scripts() {
+        let mut scripted = MockBehaviour {
+            add_item_behaviour: (0, 1),
+            ..MockBehaviour::default()
+        }.script(MockOperation::AddItem, vec![MockOutcome::Timeout, MockOutcome::Ok])
+         .script(MockOperation::AddItem, vec![MockOutcome::Err(MockErrorKind::NotFound)]);
+
+        assert!(scripted.can_add_item().is_err());
+        scripted.suspend();
+        assert!(scripted.can_add_item().is_ok());
+        scripted.resume();
+        assert!(scripted.can_add_item().is_ok());
+        assert!(scripted.can_add_item().is_err());
+        // The script is over, the counters are used again
+        assert!(scripted.can_add_item().is_err());
+        assert!(scripted.can_add_item().is_ok());
+        // Other operations are not affected
+        assert!(scripted.can_update_item().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_latency() {
+        let min = Duration::from_millis(20);
+        let max = Duration::from_millis(40);
+        for _ in 0..20 {
+            let duration = Latency::Random(min, max).duration();
+            assert!(min <= duration && duration <= max);
+        }
+        assert_eq!(Latency::Fixed(min).duration(), min);
+        assert_eq!(Latency::None.duration(), Duration::ZERO);
+
+        let behaviour = Some(Arc::new(Mutex::new(MockBehaviour::slow(Latency::Fixed(min)))));
+        let start = std::time::Instant::now();
+        simulate_latency(&behaviour, MockOperation::AddItem).await;
+        assert!(start.elapsed() >= min);
+
+        behaviour.as_ref().unwrap().lock().unwrap().suspend();
+        let start = std::time::Instant::now();
+        simulate_latency(&behaviour, MockOperation::AddItem).await;
+        assert!(start.elapsed() < min);
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let delay = Duration::from_millis(30);
+        let behaviour = Some(Arc::new(Mutex::new(MockBehaviour {
+            timeout_delay: Some(delay),
+            ..MockBehaviour::default()
+        }.script(MockOperation::UpdateItem, vec![MockOutcome::Timeout]))));
+
+        let start = std::time::Instant::now();
+        simulate_latency(&behaviour, MockOperation::UpdateItem).await;
+        assert!(behaviour.as_ref().unwrap().lock().unwrap().can_update_item().is_err());
+        assert!(start.elapsed() >= delay);
+        // Other operations are not affected
+        assert!(behaviour.as_ref().unwrap().lock().unwrap().can_add_item().is_ok());
+    }
 }
@@ -0,0 +1,1335 @@
+//! A lightweight CalDAV server, that runs in the current process, so that a [`Client`](crate::client::Client) can be tested over real HTTP
+//!
+//! It only speaks the subset of CalDAV the [`Client`](crate::client::Client) of this crate uses (principal and home set discovery, `PROPFIND`, `MKCALENDAR`, `REPORT`, `GET`, `PUT` and `DELETE`),
+//! and stores its calendars in memory. Credentials are not checked, and `calendar-query` filters are only partially honoured (time ranges are ignored). \
+//! This module requires the `mock_server` feature.
+//!
+//! ```
+//! # #[tokio::main]
+//! # async fn main() {
+//! use kitchen_fridge::Client;
+//! use kitchen_fridge::calendar::SupportedComponents;
+//! use kitchen_fridge::mock_server::MockServer;
+//! use kitchen_fridge::traits::CalDavSource;
+//!
+//! let server = MockServer::start().await.unwrap();
+//! let cal_url = server.add_calendar("groceries", "Groceries", SupportedComponents::TODO);
+//!
+//! let client = Client::new(server.url(), "user", "password").unwrap();
+//! let calendars = client.get_calendars().await.unwrap();
+//! assert!(calendars.contains_key(&cal_url));
+//! # }
+//! ```
+#![cfg(feature = "mock_server")]
+
+use std::collections::{BTreeMap, HashSet};
+use std::convert::{Infallible, TryFrom};
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+use hyper::{Body, Request, Response, Server, StatusCode};
+use hyper::header::HeaderMap;
+use hyper::service::{make_service_fn, service_fn};
+use minidom::Element;
+use tokio::sync::oneshot;
+use url::Url;
+
+use crate::Item;
+use crate::calendar::{CalendarKind, SupportedComponents};
+use crate::utils::{escape_xml, find_elem, find_elems};
+
+const PRINCIPAL_PATH: &str = "/principal/";
+const HOME_SET_PATH: &str = "/calendars/";
+/// The parent of the principals of the other users (e.g. `/principals/jane/`)
+const OTHER_PRINCIPALS_PATH: &str = "/principals/";
+/// The parent of the home sets of the other users (e.g. `/homes/jane/`)
+const OTHER_HOME_SETS_PATH: &str = "/homes/";
+
+/// A CalDAV server that runs on the local host, until it is dropped.
+#[derive(Debug)]
+pub struct MockServer {
+    url: Url,
+    state: Arc<Mutex<ServerState>>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+#[derive(Debug, Default)]
+struct ServerState {
+    /// Calendars, by path
+    calendars: BTreeMap<String, MockCalendar>,
+    /// Items (as iCal data), by path
+    items: BTreeMap<String, MockItem>,
+    /// The `X-Request-Id` headers of the requests that have been received
+    request_ids: Vec<String>,
+    /// How many connections have been accepted
+    connections: usize,
+    /// How far the `Date` headers of the responses are from the actual time (see [`MockServer::set_clock_offset`])
+    clock_offset: Option<chrono::Duration>,
+    /// How many seconds clients are asked to wait before writing again (see [`MockServer::set_retry_after`])
+    retry_after: Option<u64>,
+    /// Whether multistatus replies are sent in ISO-8859-1 (see [`MockServer::set_latin1_replies`])
+    latin1_replies: bool,
+    /// How long every `PUT` takes (see [`MockServer::set_put_latency`])
+    put_latency: Option<std::time::Duration>,
+    /// How many `PUT`s are being handled right now
+    puts_in_flight: usize,
+    /// The most `PUT`s that have been handled at the same time
+    max_concurrent_puts: usize,
+    /// The paths of the items whose `PUT`s are rejected (see [`MockServer::reject_puts_to`])
+    rejected_puts: HashSet<String>,
+}
+
+#[derive(Clone, Debug)]
+struct MockCalendar {
+    name: String,
+    supported_components: SupportedComponents,
+    color: Option<String>,
+    description: Option<String>,
+    order: Option<String>,
+    timezone: Option<String>,
+    /// Who this calendar belongs to, as told by its `resourcetype` and `current-user-privilege-set`. It does not prevent from changing it
+    kind: CalendarKind,
+    /// Changes whenever an item of this calendar changes
+    ctag: u64,
+}
+
+#[derive(Clone, Debug)]
+struct MockItem {
+    etag: String,
+    ical: String,
+}
+
+/// The reply to an HTTP request
+struct Reply {
+    status: StatusCode,
+    etag: Option<String>,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Reply {
+    fn empty(status: StatusCode) -> Self {
+        Self { status, etag: None, content_type: "text/plain", body: String::new() }
+    }
+
+    fn multistatus(responses: Vec<String>) -> Self {
+        let body = format!(r#"<?xml version="1.0" encoding="utf-8"?>
+<d:multistatus xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav" xmlns:a="http://apple.com/ns/ical/" xmlns:cs="http://calendarserver.org/ns/">
+{}</d:multistatus>
+"#, responses.concat());
+        Self { status: StatusCode::MULTI_STATUS, etag: None, content_type: "application/xml; charset=utf-8", body }
+    }
+}
+
+impl MockServer {
+    /// Start a server on a random port of the local host. This must be called from within a Tokio runtime
+    pub async fn start() -> Result<Self, Box<dyn Error>> {
+        let state = Arc::new(Mutex::new(ServerState::default()));
+
+        let service_state = Arc::clone(&state);
+        let make_service = make_service_fn(move |_connection| {
+            let state = Arc::clone(&service_state);
+            state.lock().unwrap().connections += 1;
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| handle(Arc::clone(&state), request)))
+            }
+        });
+
+        let server = Server::try_bind(&([127, 0, 0, 1], 0).into())?.serve(make_service);
+        let url = Url::parse(&format!("http://{}/", server.local_addr()))?;
+        let (shutdown, shutdown_receiver) = oneshot::channel::<()>();
+        let server = server.with_graceful_shutdown(async {
+            shutdown_receiver.await.ok();
+        });
+        tokio::spawn(async move {
+            if let Err(err) = server.await {
+                log::error!("Mock CalDAV server error: {}", err);
+            }
+        });
+        log::info!("Mock CalDAV server listening on {}", url);
+
+        Ok(Self { url, state, shutdown: Some(shutdown) })
+    }
+
+    /// The URL a [`Client`](crate::client::Client) should be created with
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// The URL of a calendar of this server, given its (last) path segment
+    pub fn calendar_url(&self, path_segment: &str) -> Url {
+        self.url.join(&format!("{}{}/", HOME_SET_PATH, path_segment)).unwrap()
+    }
+
+    /// The URL of the principal of another user than the one clients log in as (see [`Client::set_principal`](crate::client::Client::set_principal))
+    pub fn principal_url(&self, user: &str) -> Url {
+        self.url.join(&format!("{}{}/", OTHER_PRINCIPALS_PATH, user)).unwrap()
+    }
+
+    /// Create a calendar on the server side (as if it had been created by another client), and return its URL
+    pub fn add_calendar(&self, path_segment: &str, name: &str, supported_components: SupportedComponents) -> Url {
+        self.insert_calendar(self.calendar_url(path_segment), name, supported_components)
+    }
+
+    /// Create a calendar of another user on the server side (see [`Self::principal_url`]), and return its URL
+    pub fn add_calendar_of(&self, user: &str, path_segment: &str, name: &str, supported_components: SupportedComponents) -> Url {
+        let url = self.url.join(&format!("{}{}/{}/", OTHER_HOME_SETS_PATH, user, path_segment)).unwrap();
+        self.insert_calendar(url, name, supported_components)
+    }
+
+    fn insert_calendar(&self, url: Url, name: &str, supported_components: SupportedComponents) -> Url {
+        self.state.lock().unwrap().calendars.insert(url.path().to_string(), MockCalendar {
+            name: name.to_string(),
+            supported_components,
+            color: None,
+            description: None,
+            order: None,
+            timezone: None,
+            kind: CalendarKind::Owned,
+            ctag: 0,
+        });
+        url
+    }
+
+    /// Set the color of a calendar on the server side (as if it had been changed by another client), as a `calendar-color` value (e.g. `#FF8000FF`)
+    pub fn set_calendar_color(&self, cal_url: &Url, color: &str) {
+        if let Some(calendar) = self.state.lock().unwrap().calendars.get_mut(cal_url.path()) {
+            calendar.color = Some(color.to_string());
+        }
+    }
+
+    /// Make a calendar look like it belongs to someone else (or like a subscription), to the clients that list the calendars
+    pub fn set_calendar_kind(&self, cal_url: &Url, kind: CalendarKind) {
+        if let Some(calendar) = self.state.lock().unwrap().calendars.get_mut(cal_url.path()) {
+            calendar.kind = kind;
+        }
+    }
+
+    /// The `calendar-color` of a calendar on the server side, if any
+    pub fn calendar_color(&self, cal_url: &Url) -> Option<String> {
+        self.state.lock().unwrap().calendars.get(cal_url.path()).and_then(|calendar| calendar.color.clone())
+    }
+
+    /// Add or replace an item on the server side (as if it had been changed by another client). The calendar must exist already
+    pub fn put_item(&self, item: &Item) -> Result<(), Box<dyn Error>> {
+        let ical = crate::ical::build_from(item)?;
+        let mut state = self.state.lock().unwrap();
+        let path = item.url().path().to_string();
+        if state.calendars.contains_key(parent_path(&path)) == false {
+            return Err(format!("There is no calendar for {}", item.url()).into());
+        }
+        state.touch(&path);
+        state.items.insert(path, MockItem { etag: new_etag(), ical });
+        Ok(())
+    }
+
+    /// Delete an item on the server side (as if it had been deleted by another client)
+    pub fn delete_item(&self, url: &Url) -> Result<(), Box<dyn Error>> {
+        let mut state = self.state.lock().unwrap();
+        match state.items.remove(url.path()) {
+            None => Err(format!("There is no item {}", url).into()),
+            Some(_) => {
+                state.touch(url.path());
+                Ok(())
+            },
+        }
+    }
+
+    /// The URLs of the items of a calendar
+    pub fn item_urls(&self, cal_url: &Url) -> Vec<Url> {
+        let state = self.state.lock().unwrap();
+        state.items.keys()
+            .filter(|path| parent_path(path) == cal_url.path())
+            .filter_map(|path| self.url.join(path).ok())
+            .collect()
+    }
+
+    /// The iCal data of an item, as it is stored on the server
+    pub fn item_ical(&self, url: &Url) -> Option<String> {
+        self.state.lock().unwrap().items.get(url.path()).map(|item| item.ical.clone())
+    }
+
+    /// The request identifiers (see [`crate::correlation`]) the server has received so far
+    pub fn request_ids(&self) -> Vec<String> {
+        self.state.lock().unwrap().request_ids.clone()
+    }
+
+    /// How many connections clients have opened to the server so far
+    pub fn connection_count(&self) -> usize {
+        self.state.lock().unwrap().connections
+    }
+
+    /// Make the server date its responses as if its clock was `offset` ahead of the actual time (or behind it, for negative offsets)
+    pub fn set_clock_offset(&self, offset: chrono::Duration) {
+        self.state.lock().unwrap().clock_offset = Some(offset);
+    }
+
+    /// Make the server reject every `PUT` and `DELETE` with a `503 Service Unavailable`, that asks to retry after `seconds` (or accept them again, for `None`)
+    pub fn set_retry_after(&self, seconds: Option<u64>) {
+        self.state.lock().unwrap().retry_after = seconds;
+    }
+
+    /// Send multistatus replies in ISO-8859-1 (as some servers do) instead of UTF-8
+    pub fn set_latin1_replies(&self, latin1: bool) {
+        self.state.lock().unwrap().latin1_replies = latin1;
+    }
+
+    /// Make every `PUT` take (at least) `latency` before it is answered, so that concurrent uploads overlap (or answer them at once, for `None`)
+    pub fn set_put_latency(&self, latency: Option<std::time::Duration>) {
+        self.state.lock().unwrap().put_latency = latency;
+    }
+
+    /// The most `PUT`s the server has handled at the same time so far
+    pub fn max_concurrent_puts(&self) -> usize {
+        self.state.lock().unwrap().max_concurrent_puts
+    }
+
+    /// Make the server reject every `PUT` of an item with a `403 Forbidden`
+    pub fn reject_puts_to(&self, item_url: &Url) {
+        self.state.lock().unwrap().rejected_puts.insert(item_url.path().to_string());
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+
+async fn handle(state: Arc<Mutex<ServerState>>, request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let method = request.method().as_str().to_string();
+    let path = request.uri().path().to_string();
+    let headers = request.headers().clone();
+    let body = match hyper::body::to_bytes(request.into_body()).await {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+        Err(err) => {
+            log::warn!("Mock CalDAV server: unable to read a request body: {}", err);
+            String::new()
+        },
+    };
+    log::debug!("Mock CalDAV server: {} {}", method, path);
+
+    if method == "PUT" {
+        let latency = {
+            let mut state = state.lock().unwrap();
+            state.puts_in_flight += 1;
+            state.max_concurrent_puts = state.max_concurrent_puts.max(state.puts_in_flight);
+            state.put_latency
+        };
+        if let Some(latency) = latency {
+            tokio::time::sleep(latency).await;
+        }
+        state.lock().unwrap().puts_in_flight -= 1;
+    }
+
+    let (mut reply, clock_offset, retry_after, latin1) = {
+        let mut state = state.lock().unwrap();
+        (state.reply(&method, &path, &headers, &body), state.clock_offset, state.retry_after, state.latin1_replies)
+    };
+    let body = match latin1 && reply.status == StatusCode::MULTI_STATUS {
+        false => reply.body.into_bytes(),
+        true => {
+            reply.content_type = "application/xml; charset=iso-8859-1";
+            let text = reply.body.replacen(r#"encoding="utf-8""#, r#"encoding="iso-8859-1""#, 1);
+            encoding_rs::WINDOWS_1252.encode(&text).0.into_owned()
+        },
+    };
+
+    let mut response = Response::builder()
+        .status(reply.status)
+        .header("DAV", "1, 2, calendar-access")
+        .header("Server", "kitchen-fridge mock server")
+        .header("Content-Type", reply.content_type);
+    if let Some(etag) = reply.etag {
+        response = response.header("ETag", etag);
+    }
+    if let (StatusCode::SERVICE_UNAVAILABLE, Some(seconds)) = (reply.status, retry_after) {
+        response = response.header("Retry-After", seconds.to_string());
+    }
+    // Otherwise, the date is set by hyper
+    if let Some(offset) = clock_offset {
+        response = response.header("Date", (chrono::Utc::now() + offset).format("%a, %d %b %Y %H:%M:%S GMT").to_string());
+    }
+    Ok(response.body(Body::from(body)).unwrap())
+}
+
+impl ServerState {
+    fn reply(&mut self, method: &str, path: &str, headers: &HeaderMap, body: &str) -> Reply {
+        if let Some(request_id) = headers.get(crate::correlation::REQUEST_ID_HEADER).and_then(|id| id.to_str().ok()) {
+            self.request_ids.push(request_id.to_string());
+        }
+        match method {
+            "OPTIONS" => Reply::empty(StatusCode::OK),
+            "PROPFIND" => self.propfind(path, headers, body),
+            "MKCALENDAR" | "MKCOL" => self.create_calendar(path, body),
+            "REPORT" => self.report(path, body),
+            "GET" => match self.items.get(path) {
+                None => Reply::empty(StatusCode::NOT_FOUND),
+                Some(item) if headers.get("If-None-Match").and_then(|etag| etag.to_str().ok()) == Some(item.etag.as_str()) => Reply {
+                    status: StatusCode::NOT_MODIFIED,
+                    etag: Some(item.etag.clone()),
+                    content_type: "text/calendar; charset=utf-8",
+                    body: String::new(),
+                },
+                Some(item) => Reply {
+                    status: StatusCode::OK,
+                    etag: Some(item.etag.clone()),
+                    content_type: "text/calendar; charset=utf-8",
+                    body: item.ical.clone(),
+                },
+            },
+            "PUT" | "DELETE" if self.retry_after.is_some() => Reply::empty(StatusCode::SERVICE_UNAVAILABLE),
+            "PUT" if self.rejected_puts.contains(path) => Reply::empty(StatusCode::FORBIDDEN),
+            "PUT" => self.put(path, headers, body),
+            "DELETE" => self.delete(path),
+            _ => Reply::empty(StatusCode::METHOD_NOT_ALLOWED),
+        }
+    }
+
+    fn propfind(&self, path: &str, headers: &HeaderMap, body: &str) -> Reply {
+        let depth = headers.get("Depth").and_then(|depth| depth.to_str().ok()).unwrap_or("0");
+
+        if body.contains("current-user-principal") {
+            return Reply::multistatus(vec![response(path, &format!("<d:current-user-principal><d:href>{}</d:href></d:current-user-principal>", PRINCIPAL_PATH))]);
+        }
+        let home_set = match path.strip_prefix(OTHER_PRINCIPALS_PATH) {
+            _ if path == PRINCIPAL_PATH => Some(HOME_SET_PATH.to_string()),
+            Some(user) if parent_path(path) == OTHER_PRINCIPALS_PATH => Some(format!("{}{}", OTHER_HOME_SETS_PATH, user)),
+            _ => None,
+        };
+        if let Some(home_set) = home_set {
+            if body.contains("calendar-home-set") {
+                return Reply::multistatus(vec![response(path, &format!("<c:calendar-home-set><d:href>{}</d:href></c:calendar-home-set>", home_set))]);
+            }
+            // Calendar proxies and scheduling are not supported
+            return Reply::empty(StatusCode::NOT_FOUND);
+        }
+
+        if is_home_set(path) {
+            let mut responses = vec![response(path, "<d:displayname>Calendars</d:displayname><d:resourcetype><d:collection/></d:resourcetype>")];
+            if depth != "0" {
+                for (cal_path, calendar) in &self.calendars {
+                    if parent_path(cal_path) == path {
+                        responses.push(response(cal_path, &calendar.props()));
+                    }
+                }
+            }
+            return Reply::multistatus(responses);
+        }
+
+        match self.calendars.get(path) {
+            None => Reply::empty(StatusCode::NOT_FOUND),
+            Some(calendar) => Reply::multistatus(vec![response(path, &calendar.props())]),
+        }
+    }
+
+    fn create_calendar(&mut self, path: &str, body: &str) -> Reply {
+        if path.ends_with('/') == false || is_home_set(parent_path(path)) == false {
+            return Reply::empty(StatusCode::FORBIDDEN);
+        }
+        if self.calendars.contains_key(path) {
+            return Reply::empty(StatusCode::METHOD_NOT_ALLOWED);
+        }
+        let root: Element = match body.parse() {
+            Ok(root) => root,
+            Err(_) => return Reply::empty(StatusCode::BAD_REQUEST),
+        };
+
+        let text_of = |name: &str| find_elem(&root, name).map(|elem| elem.text()).filter(|text| text.trim().is_empty() == false);
+        let supported_components = find_elem(&root, "supported-calendar-component-set")
+            .and_then(|elem| SupportedComponents::try_from(elem.clone()).ok())
+            .unwrap_or(SupportedComponents::EVENT | SupportedComponents::TODO);
+        self.calendars.insert(path.to_string(), MockCalendar {
+            name: text_of("displayname").unwrap_or_default(),
+            supported_components,
+            color: text_of("calendar-color"),
+            description: text_of("calendar-description"),
+            order: text_of("calendar-order"),
+            timezone: text_of("calendar-timezone"),
+            kind: CalendarKind::Owned,
+            ctag: 0,
+        });
+        Reply::empty(StatusCode::CREATED)
+    }
+
+    fn report(&self, path: &str, body: &str) -> Reply {
+        if self.calendars.contains_key(path) == false {
+            return Reply::empty(StatusCode::NOT_FOUND);
+        }
+        let root: Element = match body.parse() {
+            Ok(root) => root,
+            Err(_) => return Reply::empty(StatusCode::BAD_REQUEST),
+        };
+        let with_data = find_elem(&root, "calendar-data").is_some();
+
+        let item_paths: Vec<&String> = match root.name() {
+            "calendar-multiget" => {
+                // Items that do not exist are omitted
+                find_elems(&root, "href").iter()
+                    .filter_map(|href| self.items.get_key_value(&href.text()).map(|(path, _)| path))
+                    .collect()
+            },
+            "calendar-query" => {
+                let wanted_component = find_elems(&root, "comp-filter").iter()
+                    .filter_map(|filter| filter.attr("name"))
+                    .find(|name| *name != "VCALENDAR")
+                    .map(|name| format!("BEGIN:{}", name));
+                let text_match = find_elem(&root, "text-match").map(|elem| elem.text().to_lowercase());
+                self.items.iter()
+                    .filter(|(item_path, _)| parent_path(item_path) == path)
+                    .filter(|(_, item)| wanted_component.iter().all(|component| item.ical.contains(component.as_str())))
+                    .filter(|(_, item)| text_match.iter().all(|text| summary_of(&item.ical).to_lowercase().contains(text.as_str())))
+                    .map(|(item_path, _)| item_path)
+                    .collect()
+            },
+            _ => return Reply::empty(StatusCode::NOT_IMPLEMENTED),
+        };
+
+        let responses = item_paths.into_iter()
+            .map(|item_path| {
+                let item = &self.items[item_path];
+                let data = match with_data {
+                    true => format!("<c:calendar-data>{}</c:calendar-data>", escape_xml(&item.ical)),
+                    false => String::new(),
+                };
+                response(item_path, &format!("<d:getetag>{}</d:getetag>{}", escape_xml(&item.etag), data))
+            })
+            .collect();
+        Reply::multistatus(responses)
+    }
+
+    fn put(&mut self, path: &str, headers: &HeaderMap, body: &str) -> Reply {
+        if self.calendars.contains_key(parent_path(path)) == false {
+            return Reply::empty(StatusCode::CONFLICT);
+        }
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+        let existing = self.items.get(path);
+        let precondition_failed = match (header("If-None-Match"), header("If-Match")) {
+            (Some("*"), _) => existing.is_some(),
+            (_, Some(etag)) => existing.iter().all(|item| item.etag != etag),
+            _ => false,
+        };
+        if precondition_failed {
+            return Reply::empty(StatusCode::PRECONDITION_FAILED);
+        }
+
+        let status = if existing.is_some() { StatusCode::NO_CONTENT } else { StatusCode::CREATED };
+        let etag = new_etag();
+        self.touch(path);
+        self.items.insert(path.to_string(), MockItem { etag: etag.clone(), ical: body.to_string() });
+        Reply { etag: Some(etag), ..Reply::empty(status) }
+    }
+
+    fn delete(&mut self, path: &str) -> Reply {
+        if self.calendars.remove(path).is_some() {
+            self.items.retain(|item_path, _| parent_path(item_path) != path);
+            return Reply::empty(StatusCode::NO_CONTENT);
+        }
+        match self.items.remove(path) {
+            None => Reply::empty(StatusCode::NOT_FOUND),
+            Some(_) => {
+                self.touch(path);
+                Reply::empty(StatusCode::NO_CONTENT)
+            },
+        }
+    }
+
+    /// Change the ctag of the calendar an item belongs to, after this item has changed
+    fn touch(&mut self, item_path: &str) {
+        if let Some(calendar) = self.calendars.get_mut(parent_path(item_path)) {
+            calendar.ctag += 1;
+        }
+    }
+}
+
+impl MockCalendar {
+    /// The properties of this calendar, as requested by [`Client`](crate::client::Client)
+    fn props(&self) -> String {
+        let optional = |tag: &str, value: &Option<String>| match value {
+            None => String::new(),
+            Some(value) => format!("<{tag}>{}</{tag}>", escape_xml(value), tag = tag),
+        };
+        let components: String = [
+            (SupportedComponents::EVENT, "VEVENT"),
+            (SupportedComponents::TODO, "VTODO"),
+            (SupportedComponents::JOURNAL, "VJOURNAL"),
+            (SupportedComponents::FREEBUSY, "VFREEBUSY"),
+        ].iter()
+            .filter(|(flag, _)| self.supported_components.contains(*flag))
+            .map(|(_, name)| format!(r#"<c:comp name="{}"/>"#, name))
+            .collect();
+        let (extra_type, privileges) = match self.kind {
+            CalendarKind::Owned => ("", "<d:privilege><d:all/></d:privilege>"),
+            CalendarKind::Shared{ read_only: false } => ("<cs:shared/>", "<d:privilege><d:read/></d:privilege><d:privilege><d:write/></d:privilege>"),
+            CalendarKind::Shared{ read_only: true } => ("<cs:shared/>", "<d:privilege><d:read/></d:privilege>"),
+            CalendarKind::Subscription => ("<cs:subscribed/>", "<d:privilege><d:read/></d:privilege>"),
+        };
+
+        format!("<d:displayname>{}</d:displayname>\
+            <d:resourcetype><d:collection/><c:calendar/>{}</d:resourcetype>\
+            <d:current-user-privilege-set>{}</d:current-user-privilege-set>\
+            <c:supported-calendar-component-set>{}</c:supported-calendar-component-set>\
+            <d:supported-report-set>\
+                <d:supported-report><d:report><c:calendar-multiget/></d:report></d:supported-report>\
+                <d:supported-report><d:report><c:calendar-query/></d:report></d:supported-report>\
+            </d:supported-report-set>\
+            <cs:getctag>{}</cs:getctag>{}{}{}{}",
+            escape_xml(&self.name),
+            extra_type,
+            privileges,
+            components,
+            self.ctag,
+            optional("a:calendar-color", &self.color),
+            optional("c:calendar-description", &self.description),
+            optional("a:calendar-order", &self.order),
+            optional("c:calendar-timezone", &self.timezone),
+        )
+    }
+}
+
+/// A `<response>` element of a multistatus
+fn response(href: &str, props: &str) -> String {
+    format!("<d:response><d:href>{}</d:href><d:propstat><d:prop>{}</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>\n",
+        escape_xml(href), props)
+}
+
+/// Whether a path is the home set of the current user, or the one of another user
+fn is_home_set(path: &str) -> bool {
+    path == HOME_SET_PATH || parent_path(path) == OTHER_HOME_SETS_PATH
+}
+
+/// The path of the collection that contains a resource (including the trailing slash)
+fn parent_path(path: &str) -> &str {
+    let trimmed = path.strip_suffix('/').unwrap_or(path);
+    match trimmed.rfind('/') {
+        None => "/",
+        Some(index) => &path[..=index],
+    }
+}
+
+/// The value of the SUMMARY property of some iCal data
+fn summary_of(ical: &str) -> &str {
+    ical.lines()
+        .find_map(|line| line.strip_prefix("SUMMARY:"))
+        .unwrap_or_default()
+}
+
+fn new_etag() -> String {
+    format!("\"{}\"", crate::deterministic::new_uuid().to_simple())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    use crate::{Cache, CalDavProvider, Client, Task};
+    use crate::config::{Config, HttpVersion};
+    use crate::item::SyncStatus;
+    use crate::metrics::{ItemSync, Metrics, SharedMetrics};
+    use crate::provider::CalendarOverrides;
+    use crate::provider::sync_progress::{IssueSeverity, SyncEvent};
+    use crate::traits::{BaseCalendar, CalDavSource, CompleteCalendar, DavCalendar};
+    use crate::calendar::remote_calendar::RemoteCalendar;
+    use crate::test_utils::TaskBuilder;
+
+    /// A copy of a remote calendar to make requests with, like a sync does (see [`DavCalendar::detached`])
+    fn detached(cal: &std::sync::RwLock<RemoteCalendar>) -> RemoteCalendar {
+        cal.read().unwrap().detached()
+    }
+
+    #[test]
+    fn test_parent_path() {
+        assert_eq!(parent_path("/calendars/work/task.ics"), "/calendars/work/");
+        assert_eq!(parent_path("/calendars/work/"), "/calendars/");
+        assert_eq!(parent_path("/"), "/");
+    }
+
+    #[tokio::test]
+    async fn test_sync_over_http() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let root = PathBuf::from(String::from("test_cache/mock_server_test"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let server = MockServer::start().await.unwrap();
+        let remote_url = server.add_calendar("remote", "Created on the server", SupportedComponents::TODO);
+        let remote_task = Task::new(String::from("Remote task"), false, &remote_url);
+        server.put_item(&Item::Task(remote_task.clone())).unwrap();
+
+        let client = Client::new(server.url(), "user", "password").unwrap();
+        let mut provider = CalDavProvider::new(client, Cache::new(&root));
+
+        // Calendars are created on both sides
+        let local_url = server.calendar_url("local");
+        let local_cal = provider.local().create_calendar(local_url.clone(), String::from("Created locally"), SupportedComponents::TODO, None).await.unwrap();
+        let local_task = Task::new(String::from("Local task"), false, &local_url);
+        local_cal.write().unwrap().add_item_sync(Item::Task(local_task.clone())).unwrap();
+        assert!(provider.sync().await);
+
+        assert_eq!(server.item_urls(&local_url), vec![local_task.url().clone()]);
+        assert!(server.item_ical(local_task.url()).unwrap().contains("SUMMARY:Local task"));
+        let synced_remote_cal = provider.local().get_calendar_sync(&remote_url).unwrap();
+        assert_eq!(synced_remote_cal.read().unwrap().get_item_urls_sync().unwrap().len(), 1);
+        assert_eq!(synced_remote_cal.read().unwrap().name(), "Created on the server");
+
+        // Changes on the server are pulled, local deletions are pushed
+        let mut renamed = remote_task.clone();
+        renamed.set_name(String::from("Renamed on the server"));
+        server.put_item(&Item::Task(renamed)).unwrap();
+        local_cal.write().unwrap().mark_for_deletion_sync(local_task.url()).unwrap();
+        assert!(provider.sync().await);
+
+        assert!(server.item_urls(&local_url).is_empty());
+        let synced_name = synced_remote_cal.read().unwrap().get_item_by_url_sync(remote_task.url()).unwrap().name().to_string();
+        assert_eq!(synced_name, "Renamed on the server");
+
+        // The sync is stable
+        assert!(provider.sync().await);
+    }
+
+    #[tokio::test]
+    async fn test_latin1_replies() {
+        let root = PathBuf::from(String::from("test_cache/mock_server_latin1"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let server = MockServer::start().await.unwrap();
+        server.set_latin1_replies(true);
+        let cal_url = server.add_calendar("latin1", "Tâches", SupportedComponents::TODO);
+        let task = Task::new(String::from("Réparer le vélo"), false, &cal_url);
+        server.put_item(&Item::Task(task.clone())).unwrap();
+
+        let client = Client::new(server.url(), "user", "password").unwrap();
+        let mut provider = CalDavProvider::new(client, Cache::new(&root));
+        assert!(provider.sync().await);
+        let local_cal = provider.local().get_calendar_sync(&cal_url).unwrap();
+        assert_eq!(local_cal.read().unwrap().name(), "Tâches");
+        assert_eq!(local_cal.read().unwrap().get_item_by_url_sync(task.url()).unwrap().name(), "Réparer le vélo");
+    }
+
+    #[tokio::test]
+    async fn test_calendar_colors() {
+        let root = PathBuf::from(String::from("test_cache/mock_server_colors"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let server = MockServer::start().await.unwrap();
+        let remote_url = server.add_calendar("remote", "Colored on the server", SupportedComponents::TODO);
+        server.set_calendar_color(&remote_url, "#FF2968FF");
+        let client = Client::new(server.url(), "user", "password").unwrap();
+        let mut provider = CalDavProvider::new(client, Cache::new(&root));
+        let local_url = server.calendar_url("local");
+        let translucent = csscolorparser::Color::from_rgba_u8(255, 128, 0, 128);
+        provider.local().create_calendar(local_url.clone(), String::from("Colored locally"), SupportedComponents::TODO, Some(translucent.clone())).await.unwrap();
+        assert!(provider.sync().await);
+
+        // Colors are created on the server...
+        assert_eq!(server.calendar_color(&local_url).as_deref(), Some("#FF800080"));
+        let remote_cal = provider.remote().get_calendar(&local_url).await.unwrap();
+        assert_eq!(remote_cal.read().unwrap().color().unwrap().rgba_u8(), translucent.rgba_u8());
+        // ...and fetched from it
+        let local_cal = provider.local().get_calendar_sync(&remote_url).unwrap();
+        assert_eq!(local_cal.read().unwrap().color().unwrap().rgba_u8(), (0xFF, 0x29, 0x68, 0xFF));
+    }
+
+    #[tokio::test]
+    async fn test_calendar_kinds() {
+        let root = PathBuf::from(String::from("test_cache/mock_server_kinds"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let server = MockServer::start().await.unwrap();
+        let kinds = [
+            ("owned", CalendarKind::Owned),
+            ("shared", CalendarKind::Shared{ read_only: false }),
+            ("read-only", CalendarKind::Shared{ read_only: true }),
+            ("subscription", CalendarKind::Subscription),
+        ];
+        let mut tasks = Vec::new();
+        for (path_segment, kind) in kinds {
+            let url = server.add_calendar(path_segment, path_segment, SupportedComponents::TODO);
+            server.set_calendar_kind(&url, kind);
+            let task = Task::new(String::from("Original name"), false, &url);
+            server.put_item(&Item::Task(task.clone())).unwrap();
+            tasks.push((url, kind, task));
+        }
+        let client = Client::new(server.url(), "user", "password").unwrap();
+        let mut provider = CalDavProvider::new(client, Cache::new(&root));
+        assert!(provider.sync().await);
+
+        // Kinds are known on both sides...
+        for (url, kind, task) in &tasks {
+            assert_eq!(provider.remote().get_calendar(url).await.unwrap().read().unwrap().kind(), *kind);
+            let local_cal = provider.local().get_calendar_sync(url).unwrap();
+            assert_eq!(local_cal.read().unwrap().kind(), *kind);
+            let mut renamed = local_cal.read().unwrap().get_item_by_url_sync(task.url()).unwrap().clone();
+            renamed.unwrap_task_mut().set_name(String::from("Renamed locally"));
+            local_cal.write().unwrap().update_item_sync(renamed).unwrap();
+        }
+
+        // ...and read-only calendars are pull-only, unless told otherwise
+        provider.set_calendar_overrides(tasks[3].0.clone(), CalendarOverrides::default());
+        assert!(provider.sync().await);
+        let pushed: Vec<bool> = tasks.iter()
+            .map(|(_, _, task)| server.item_ical(task.url()).unwrap().contains("SUMMARY:Renamed locally"))
+            .collect();
+        assert_eq!(pushed, vec![true, true, false, true]);
+    }
+
+    #[tokio::test]
+    async fn test_other_principal() {
+        let root = PathBuf::from(String::from("test_cache/mock_server_other_principal"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let server = MockServer::start().await.unwrap();
+        let own_url = server.add_calendar("mine", "Mine", SupportedComponents::TODO);
+        let other_url = server.add_calendar_of("jane", "work", "Jane's work", SupportedComponents::TODO);
+        let task = Task::new(String::from("Jane's task"), false, &other_url);
+        server.put_item(&Item::Task(task.clone())).unwrap();
+
+        let client = Client::builder(server.url(), "admin", "password")
+            .principal("/principals/jane/")
+            .build().unwrap();
+        let mut provider = CalDavProvider::new(client, Cache::new(&root));
+        assert!(provider.sync().await);
+        let calendars = provider.local().get_calendars_sync().unwrap();
+        assert_eq!(calendars.keys().collect::<Vec<_>>(), vec![&other_url]);
+        let local_cal = provider.local().get_calendar_sync(&other_url).unwrap();
+        assert!(local_cal.read().unwrap().get_item_by_url_sync(task.url()).is_some());
+
+        // Calendars of the other principal are created in its home set
+        let created_url = server.url().join("/homes/jane/home/").unwrap();
+        provider.local().create_calendar(created_url.clone(), String::from("Jane's home"), SupportedComponents::TODO, None).await.unwrap();
+        assert!(provider.sync().await);
+        provider.remote().invalidate_calendars();
+        assert_eq!(provider.remote().get_calendars().await.unwrap().len(), 2);
+
+        // Switching back to the current user principal
+        let client = Client::new(server.url(), "admin", "password").unwrap();
+        let own_calendars = client.get_calendars().await.unwrap();
+        assert_eq!(own_calendars.keys().collect::<Vec<_>>(), vec![&own_url]);
+        client.set_principal(server.principal_url("jane").as_str()).unwrap();
+        assert_eq!(client.get_calendars().await.unwrap().len(), 2);
+    }
+
+    #[derive(Debug, Default)]
+    struct Recorder {
+        requests: Mutex<Vec<(String, Option<u16>)>>,
+        bytes: Mutex<(u64, u64)>,
+        items: Mutex<HashMap<ItemSync, usize>>,
+        syncs: Mutex<Vec<bool>>,
+    }
+
+    impl Metrics for Recorder {
+        fn request(&self, method: &str, _latency: std::time::Duration, status: Option<u16>) {
+            self.requests.lock().unwrap().push((method.to_string(), status));
+        }
+        fn bytes_transferred(&self, sent: u64, received: u64) {
+            let mut bytes = self.bytes.lock().unwrap();
+            bytes.0 += sent;
+            bytes.1 += received;
+        }
+        fn items_synced(&self, kind: ItemSync, count: usize) {
+            *self.items.lock().unwrap().entry(kind).or_default() += count;
+        }
+        fn sync_finished(&self, _duration: std::time::Duration, success: bool) {
+            self.syncs.lock().unwrap().push(success);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let root = PathBuf::from(String::from("test_cache/mock_server_metrics"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let server = MockServer::start().await.unwrap();
+        let cal_url = server.add_calendar("metrics", "Monitored", SupportedComponents::TODO);
+        server.put_item(&Item::Task(Task::new(String::from("Remote task"), false, &cal_url))).unwrap();
+
+        let recorder = Arc::new(Recorder::default());
+        let config = Config { metrics: Some(SharedMetrics::new(recorder.clone())), ..Config::default() };
+        let client = Client::new_with_config(server.url(), "user", "password", config.clone()).unwrap();
+        let mut provider = CalDavProvider::new(client, Cache::new(&root)).with_config(config);
+        assert!(provider.sync().await);
+
+        let local_cal = provider.local().get_calendar_sync(&cal_url).unwrap();
+        local_cal.write().unwrap().add_item_sync(Item::Task(Task::new(String::from("Local task"), false, &cal_url))).unwrap();
+        assert!(provider.sync().await);
+
+        let requests = recorder.requests.lock().unwrap();
+        assert!(requests.iter().any(|(method, status)| method == "PROPFIND" && *status == Some(207)));
+        assert!(requests.iter().any(|(method, status)| method == "PUT" && *status == Some(201)));
+        let bytes = *recorder.bytes.lock().unwrap();
+        assert!(bytes.0 > 0 && bytes.1 > 0);
+        assert_eq!(*recorder.items.lock().unwrap(), HashMap::from([(ItemSync::Downloaded, 1), (ItemSync::Uploaded, 1)]));
+        assert_eq!(*recorder.syncs.lock().unwrap(), vec![true, true]);
+    }
+
+    #[tokio::test]
+    async fn test_request_ids() {
+        let root = PathBuf::from(String::from("test_cache/mock_server_request_ids"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let server = MockServer::start().await.unwrap();
+        let cal_url = server.add_calendar("correlated", "Correlated", SupportedComponents::TODO);
+        let config = Config { request_id_header: true, ..Config::default() };
+        let client = Client::new_with_config(server.url(), "user", "password", config.clone()).unwrap();
+        let mut provider = CalDavProvider::new(client, Cache::new(&root)).with_config(config);
+        let local_cal = provider.local().create_calendar(cal_url.clone(), String::from("Correlated"), SupportedComponents::TODO, None).await.unwrap();
+        local_cal.write().unwrap().add_item_sync(Item::Task(Task::new(String::from("Local task"), false, &cal_url))).unwrap();
+        assert!(provider.sync().await);
+
+        // Every request of the sync (including the concurrent uploads) carries the identifier of the sync
+        let request_ids = server.request_ids();
+        assert!(request_ids.len() > 1);
+        let sync_id = request_ids[0].split('-').next().unwrap().to_string();
+        assert_eq!(sync_id.len(), 12);
+        assert!(request_ids.iter().all(|id| id.starts_with(&format!("{}-", sync_id))));
+        let unique: std::collections::HashSet<_> = request_ids.iter().collect();
+        assert_eq!(unique.len(), request_ids.len());
+
+        // Identifiers are not sent by default
+        let server = MockServer::start().await.unwrap();
+        let client = Client::new(server.url(), "user", "password").unwrap();
+        client.get_calendars().await.unwrap();
+        assert!(server.request_ids().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_connection_reuse() {
+        let root = PathBuf::from(String::from("test_cache/mock_server_connection_reuse"));
+
+        for (http_version, max_idle, reused) in [(HttpVersion::Auto, usize::MAX, true), (HttpVersion::Http2Only, usize::MAX, true), (HttpVersion::Auto, 0, false)] {
+            let _ = std::fs::remove_dir_all(&root);
+            let server = MockServer::start().await.unwrap();
+            let cal_url = server.add_calendar("pooled", "Pooled", SupportedComponents::TODO);
+            let client = Client::builder(server.url(), "user", "password")
+                .config(Config { request_id_header: true, ..Config::default() })
+                .http_version(http_version)
+                .pool_max_idle_per_host(max_idle)
+                .build().unwrap();
+            let mut provider = CalDavProvider::new(client, Cache::new(&root));
+            let local_cal = provider.local().create_calendar(cal_url.clone(), String::from("Pooled"), SupportedComponents::TODO, None).await.unwrap();
+            for i in 0..3 {
+                local_cal.write().unwrap().add_item_sync(Item::Task(Task::new(format!("Task #{}", i), false, &cal_url))).unwrap();
+            }
+            assert!(provider.sync().await);
+
+            let requests = server.request_ids().len();
+            assert!(requests > 3);
+            match (http_version, reused) {
+                (HttpVersion::Http2Only, _) => assert_eq!(server.connection_count(), 1),
+                (_, true) => assert!(server.connection_count() < requests),
+                (_, false) => assert_eq!(server.connection_count(), requests),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_clock_skew() {
+        let server = MockServer::start().await.unwrap();
+        let client = Client::new(server.url(), "user", "password").unwrap();
+        assert!(client.clock_skew().is_none());
+        client.get_calendars().await.unwrap();
+        assert!(client.clock_skew().unwrap().num_seconds().abs() <= 1);
+
+        // Dates are only shifted on request...
+        let close_enough = |a: chrono::DateTime<chrono::Utc>, b: chrono::DateTime<chrono::Utc>| (a - b).num_seconds().abs() <= 2;
+        server.set_clock_offset(chrono::Duration::seconds(-30));
+        let config = Config { clock_skew_tolerance: std::time::Duration::from_secs(5), ..Config::default() };
+        let client = Client::new_with_config(server.url(), "user", "password", config.clone()).unwrap();
+        client.get_calendars().await.unwrap();
+        assert!((client.clock_skew().unwrap() + chrono::Duration::seconds(30)).num_seconds().abs() <= 1);
+        assert!(close_enough(client.server_now(), chrono::Utc::now()));
+
+        // ...and only for the client that has been asked to, not for the whole process
+        let compensating = Client::new_with_config(server.url(), "user", "password", Config { compensate_clock_skew: true, ..config }).unwrap();
+        compensating.get_calendars().await.unwrap();
+        assert!(close_enough(compensating.server_now(), chrono::Utc::now() - chrono::Duration::seconds(30)));
+        assert!(close_enough(client.server_now(), chrono::Utc::now()));
+        assert!(close_enough(crate::deterministic::now(), chrono::Utc::now()));
+    }
+
+    #[tokio::test]
+    async fn test_add_items() {
+        let server = MockServer::start().await.unwrap();
+        let cal_url = server.add_calendar("imported", "Imported", SupportedComponents::TODO);
+        let config = Config { upload_concurrency: 4, ..Config::default() };
+        let client = Client::new_with_config(server.url(), "user", "password", config).unwrap();
+        let cal = client.get_calendar(&cal_url).await.unwrap();
+
+        let items: Vec<Item> = (0..10).map(|i| Item::Task(Task::new(format!("Imported #{}", i), false, &cal_url))).collect();
+        let mut urls: Vec<Url> = items.iter().map(|item| item.url().clone()).collect();
+        let results = detached(&cal).add_items(items).await;
+        assert!(results.iter().all(|result| matches!(result, Ok(SyncStatus::Synced(_)))));
+        let mut on_server = server.item_urls(&cal_url);
+        on_server.sort();
+        urls.sort();
+        assert_eq!(on_server, urls);
+    }
+
+    #[tokio::test]
+    async fn test_retry_after() {
+        let root = PathBuf::from(String::from("test_cache/mock_server_retry_after"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let server = MockServer::start().await.unwrap();
+        let cal_url = server.add_calendar("busy", "Busy", SupportedComponents::TODO);
+        let recorder = Arc::new(Recorder::default());
+        let config = Config { metrics: Some(SharedMetrics::new(recorder.clone())), ..Config::default() };
+        let client = Client::new_with_config(server.url(), "user", "password", config.clone()).unwrap();
+        let mut provider = CalDavProvider::new(client, Cache::new(&root)).with_config(config);
+        let local_cal = provider.local().create_calendar(cal_url.clone(), String::from("Busy"), SupportedComponents::TODO, None).await.unwrap();
+        let task = Task::new(String::from("Deferred"), false, &cal_url);
+        let task_url = task.url().clone();
+        local_cal.write().unwrap().add_item_sync(Item::Task(task)).unwrap();
+
+        // The date the server asked for is recorded...
+        server.set_retry_after(Some(3600));
+        assert!(provider.sync().await == false);
+        let puts = || recorder.requests.lock().unwrap().iter().filter(|(method, _)| method == "PUT").count();
+        assert_eq!(puts(), 1);
+        let not_before = local_cal.read().unwrap().retry_not_before(&task_url).unwrap();
+        assert!(not_before > crate::deterministic::now() + chrono::Duration::minutes(50));
+
+        // ...and the item is not pushed again before it
+        server.set_retry_after(None);
+        assert!(provider.sync().await);
+        assert_eq!(puts(), 1);
+        assert!(server.item_urls(&cal_url).is_empty());
+
+        // It is pushed again once this date has passed
+        local_cal.write().unwrap().set_retry_not_before(&task_url, Some(crate::deterministic::now() - chrono::Duration::hours(1)));
+        assert!(provider.sync().await);
+        assert_eq!(puts(), 2);
+        assert_eq!(server.item_urls(&cal_url), vec![task_url.clone()]);
+        assert!(local_cal.read().unwrap().retry_not_before(&task_url).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_uploads() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let root = PathBuf::from(String::from("test_cache/mock_server_concurrent_uploads"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let server = MockServer::start().await.unwrap();
+        server.set_put_latency(Some(std::time::Duration::from_millis(100)));
+        let cal_url = server.add_calendar("busy", "Busy", SupportedComponents::TODO);
+        let config = Config { upload_concurrency: 4, ..Config::default() };
+        let client = Client::new(server.url(), "user", "password").unwrap();
+        let mut provider = CalDavProvider::new(client, Cache::new(&root)).with_config(config);
+        let local_cal = provider.local().create_calendar(cal_url.clone(), String::from("Busy"), SupportedComponents::TODO, None).await.unwrap();
+        let mut urls = Vec::new();
+        for i in 0..10 {
+            let task = Task::new(format!("Task #{}", i), false, &cal_url);
+            urls.push(task.url().clone());
+            local_cal.write().unwrap().add_item_sync(Item::Task(task)).unwrap();
+        }
+        let rejected = vec![urls[1].clone(), urls[6].clone()];
+        for url in &rejected {
+            server.reject_puts_to(url);
+        }
+
+        let (sender, receiver) = crate::provider::sync_progress::feedback_channel();
+        assert!(!provider.sync_with_feedback(sender).await);
+        assert_eq!(server.max_concurrent_puts(), 4);
+
+        // Every failed upload is reported on its own, and does not prevent the others from succeeding
+        match &*receiver.borrow() {
+            SyncEvent::Finished{ success: false, issues } => {
+                let mut failed: Vec<Url> = issues.iter().filter_map(|issue| issue.item.clone()).collect();
+                failed.sort();
+                let mut expected = rejected.clone();
+                expected.sort();
+                assert_eq!(issues.len(), 2);
+                assert!(issues.iter().all(|issue| issue.severity == IssueSeverity::Error));
+                assert_eq!(failed, expected);
+            },
+            other => panic!("Unexpected last event {}", other),
+        }
+        assert_eq!(server.item_urls(&cal_url).len(), 8);
+        for url in &urls {
+            let synced = matches!(local_cal.read().unwrap().get_item_by_url_sync(url).unwrap().sync_status(), SyncStatus::Synced(_));
+            assert_eq!(synced, !rejected.contains(url));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_offline_sync() {
+        let root = PathBuf::from(String::from("test_cache/mock_server_offline"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let server = MockServer::start().await.unwrap();
+        let cal_url = server.add_calendar("offline", "Offline", SupportedComponents::TODO);
+        let recorder = Arc::new(Recorder::default());
+        let config = Config { metrics: Some(SharedMetrics::new(recorder.clone())), ..Config::default() };
+
+        // Nothing listens on this port anymore, as if the network was down
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let offline_url = format!("http://{}/", listener.local_addr().unwrap());
+        drop(listener);
+        let client = Client::new_with_config(&offline_url, "user", "password", config.clone()).unwrap();
+        let mut provider = CalDavProvider::new(client, Cache::new(&root)).with_config(config.clone());
+        let local_cal = provider.local().create_calendar(cal_url.clone(), String::from("Offline"), SupportedComponents::TODO, None).await.unwrap();
+        let task = Task::new(String::from("Written offline"), false, &cal_url);
+        local_cal.write().unwrap().add_item_sync(Item::Task(task.clone())).unwrap();
+
+        // A single request is sent, and the local change is kept for the next sync
+        assert!(provider.sync().await == false);
+        assert!(provider.is_offline());
+        assert_eq!(recorder.requests.lock().unwrap().len(), 1);
+        assert_eq!(recorder.syncs.lock().unwrap().as_slice(), &[false]);
+        assert_eq!(local_cal.read().unwrap().get_item_by_url_sync(task.url()).unwrap().sync_status(), &SyncStatus::NotSynced);
+        assert!(provider.sync_calendar(&cal_url).await == false);
+        assert!(provider.is_offline());
+
+        // Once the server can be reached, the change is pushed
+        provider.local().save_to_folder().unwrap();
+        let client = Client::new_with_config(server.url(), "user", "password", config.clone()).unwrap();
+        let mut provider = CalDavProvider::new(client, Cache::from_folder(&root).unwrap()).with_config(config);
+        assert!(provider.sync().await);
+        assert!(provider.is_offline() == false);
+        assert_eq!(server.item_urls(&cal_url), vec![task.url().clone()]);
+    }
+
+    #[tokio::test]
+    async fn test_conditional_get() {
+        let server = MockServer::start().await.unwrap();
+        let cal_url = server.add_calendar("conditional", "Conditional", SupportedComponents::TODO);
+        let task = Task::new(String::from("Unchanged"), false, &cal_url);
+        server.put_item(&Item::Task(task.clone())).unwrap();
+
+        let recorder = Arc::new(Recorder::default());
+        let config = Config { metrics: Some(SharedMetrics::new(recorder.clone())), ..Config::default() };
+        let client = Client::new_with_config(server.url(), "user", "password", config).unwrap();
+        let cal = client.get_calendar(&cal_url).await.unwrap();
+        let get_statuses = || recorder.requests.lock().unwrap().iter()
+            .filter(|(method, _)| method == "GET")
+            .map(|(_, status)| *status)
+            .collect::<Vec<_>>();
+
+        // An item that has not changed is not downloaded again
+        let first = detached(&cal).get_item_by_url(task.url()).await.unwrap().unwrap();
+        let second = detached(&cal).get_item_by_url(task.url()).await.unwrap().unwrap();
+        assert_eq!(first.name(), "Unchanged");
+        assert_eq!(second.name(), "Unchanged");
+        assert_eq!(get_statuses(), vec![Some(200), Some(304)]);
+
+        // A changed item is
+        let mut renamed = task.clone();
+        renamed.set_name(String::from("Changed"));
+        server.put_item(&Item::Task(renamed)).unwrap();
+        cal.read().unwrap().refresh();
+        let third = detached(&cal).get_item_by_url(task.url()).await.unwrap().unwrap();
+        assert_eq!(third.name(), "Changed");
+        assert_eq!(get_statuses(), vec![Some(200), Some(304), Some(200)]);
+    }
+
+    #[tokio::test]
+    async fn test_unchanged_calendars_are_skipped() {
+        let root = PathBuf::from(String::from("test_cache/mock_server_unchanged_calendars"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let server = MockServer::start().await.unwrap();
+        let cal_url = server.add_calendar("quiet", "Quiet", SupportedComponents::TODO);
+        server.put_item(&Item::Task(Task::new(String::from("Remote task"), false, &cal_url))).unwrap();
+        let recorder = Arc::new(Recorder::default());
+        let config = Config { metrics: Some(SharedMetrics::new(recorder.clone())), ..Config::default() };
+        let client = Client::new_with_config(server.url(), "user", "password", config.clone()).unwrap();
+        let mut provider = CalDavProvider::new(client, Cache::new(&root)).with_config(config);
+        let reports = || recorder.requests.lock().unwrap().iter().filter(|(method, _)| method == "REPORT").count();
+
+        // The version of a calendar changes along with its items
+        let remote_cal = provider.remote().get_calendar(&cal_url).await.unwrap();
+        let version = detached(&remote_cal).get_current_version().await.unwrap();
+        assert!(version.is_some());
+        assert!(provider.sync().await);
+        let local_cal = provider.local().get_calendar_sync(&cal_url).unwrap();
+        assert_eq!(local_cal.read().unwrap().last_remote_version(), version.as_ref());
+
+        // Calendars that have not changed are not listed again
+        let listed = reports();
+        assert!(provider.sync().await);
+        assert_eq!(reports(), listed);
+
+        // Calendars that have changed are
+        server.put_item(&Item::Task(Task::new(String::from("Another remote task"), false, &cal_url))).unwrap();
+        assert_ne!(detached(&remote_cal).get_current_version().await.unwrap(), version);
+        assert!(provider.sync().await);
+        assert!(reports() > listed);
+        assert_eq!(local_cal.read().unwrap().get_items_sync().unwrap().len(), 2);
+
+        // So are calendars that have changed locally, and the version they end up with is not known until the next sync
+        local_cal.write().unwrap().add_item_sync(Item::Task(Task::new(String::from("Local task"), false, &cal_url))).unwrap();
+        assert!(provider.sync().await);
+        assert_eq!(server.item_urls(&cal_url).len(), 3);
+        assert!(local_cal.read().unwrap().last_remote_version().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_version_tags_are_memoized_until_the_calendar_changes() {
+        let server = MockServer::start().await.unwrap();
+        let cal_url = server.add_calendar("memo", "Memo", SupportedComponents::TODO);
+        server.put_item(&Item::Task(Task::new(String::from("Remote task"), false, &cal_url))).unwrap();
+        let recorder = Arc::new(Recorder::default());
+        let config = Config { metrics: Some(SharedMetrics::new(recorder.clone())), ..Config::default() };
+        let client = Client::new_with_config(server.url(), "user", "password", config).unwrap();
+        let reports = || recorder.requests.lock().unwrap().iter().filter(|(method, _)| method == "REPORT").count();
+
+        let remote_cal = client.get_calendar(&cal_url).await.unwrap();
+        detached(&remote_cal).get_current_version().await.unwrap();
+        assert_eq!(detached(&remote_cal).get_item_version_tags().await.unwrap().len(), 1);
+        assert_eq!(reports(), 1);
+
+        // As long as the calendar has not changed, the version tags are not requested again
+        detached(&remote_cal).get_current_version().await.unwrap();
+        assert_eq!(detached(&remote_cal).get_item_version_tags().await.unwrap().len(), 1);
+        assert_eq!(reports(), 1);
+
+        server.put_item(&Item::Task(Task::new(String::from("Another remote task"), false, &cal_url))).unwrap();
+        detached(&remote_cal).get_current_version().await.unwrap();
+        assert_eq!(detached(&remote_cal).get_item_version_tags().await.unwrap().len(), 2);
+        assert_eq!(reports(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_calendar_listing_is_cached() {
+        let root = PathBuf::from(String::from("test_cache/mock_server_calendar_listing"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let server = MockServer::start().await.unwrap();
+        let first_url = server.add_calendar("first", "First", SupportedComponents::TODO);
+        let recorder = Arc::new(Recorder::default());
+        let config = Config { metrics: Some(SharedMetrics::new(recorder.clone())), ..Config::default() };
+        let client = Client::new_with_config(server.url(), "user", "password", config.clone()).unwrap();
+        let request_count = || recorder.requests.lock().unwrap().len();
+
+        assert_eq!(client.get_calendars().await.unwrap().len(), 1);
+        let after_listing = request_count();
+        assert!(client.get_calendar(&first_url).await.is_some());
+        assert_eq!(client.get_calendars().await.unwrap().len(), 1);
+        assert_eq!(request_count(), after_listing);
+
+        // Calendars created by other clients are only seen once the listing is invalidated...
+        let second_url = server.add_calendar("second", "Second", SupportedComponents::TODO);
+        assert!(client.get_calendar(&second_url).await.is_none());
+        client.invalidate_calendars();
+        assert!(client.get_calendar(&second_url).await.is_some());
+        assert!(request_count() > after_listing);
+
+        // ...which every sync does
+        let third_url = server.add_calendar("third", "Third", SupportedComponents::TODO);
+        let mut provider = CalDavProvider::new(client, Cache::new(&root)).with_config(config);
+        assert!(provider.sync().await);
+        assert_eq!(provider.remote().get_calendars().await.unwrap().len(), 3);
+        assert!(provider.local().get_calendar_sync(&third_url).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_provider_connect() {
+        let root = PathBuf::from(String::from("test_cache/mock_server_connect"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let server = MockServer::start().await.unwrap();
+        let cal_url = server.add_calendar("tasks", "Tasks", SupportedComponents::TODO);
+
+        let mut provider = CalDavProvider::connect(server.url(), "user", "password", &root, Config::default()).unwrap();
+        assert_eq!(provider.local().calendar_count(), 0);
+        assert!(provider.sync().await);
+        provider.local().save_to_folder().unwrap();
+
+        // The second time, the cache is loaded from the folder
+        let provider = CalDavProvider::connect(server.url(), "user", "password", &root, Config::default()).unwrap();
+        assert!(provider.local().get_calendar_sync(&cal_url).is_some());
+
+        // An invalid cache is not silently replaced
+        std::fs::write(root.join("data.json"), "not json").unwrap();
+        assert!(CalDavProvider::connect(server.url(), "user", "password", &root, Config::default()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_save_after_sync() {
+        let root = PathBuf::from(String::from("test_cache/mock_server_save_after_sync"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let server = MockServer::start().await.unwrap();
+        let cal_url = server.add_calendar("tasks", "Tasks", SupportedComponents::TODO);
+        let first = Task::new(String::from("First"), false, &cal_url);
+        server.put_item(&Item::Task(first.clone())).unwrap();
+
+        // `provider` is kept alive, so that the cache is not saved when it is dropped
+        let config = Config { save_after_sync: true, ..Config::default() };
+        let mut provider = CalDavProvider::connect(server.url(), "user", "password", &root, config).unwrap();
+        assert!(provider.sync().await);
+        let saved = Cache::from_folder(&root).unwrap();
+        assert!(saved.get_calendar_sync(&cal_url).unwrap().read().unwrap().get_item_arc(first.url()).is_some());
+
+        let second = Task::new(String::from("Second"), false, &cal_url);
+        server.put_item(&Item::Task(second.clone())).unwrap();
+        assert!(provider.sync_calendar(&cal_url).await);
+        let saved = Cache::from_folder(&root).unwrap();
+        assert!(saved.get_calendar_sync(&cal_url).unwrap().read().unwrap().get_item_arc(second.url()).is_some());
+    }
+
+    #[derive(Debug, Default)]
+    struct ChangeCounter(std::sync::Mutex<usize>);
+
+    impl crate::traits::ItemObserver for ChangeCounter {
+        fn item_changed(&self, _calendar_url: &Url, _old: Option<&Item>, _new: Option<&Item>) {
+            *self.0.lock().unwrap() += 1;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_skip_identical_content() {
+        let root = PathBuf::from(String::from("test_cache/mock_server_identical_content"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let server = MockServer::start().await.unwrap();
+        let cal_url = server.add_calendar("rotating", "Rotating ETags", SupportedComponents::TODO);
+        let task = Item::Task(Task::new(String::from("Unchanged"), false, &cal_url));
+        server.put_item(&task).unwrap();
+
+        let config = Config { skip_identical_content: true, ..Config::default() };
+        let client = Client::new(server.url(), "user", "password").unwrap();
+        let mut provider = CalDavProvider::new(client, Cache::new(&root)).with_config(config);
+        assert!(provider.sync().await);
+        let changes = Arc::new(ChangeCounter::default());
+        provider.local().add_observer(changes.clone());
+        let local_cal = provider.local().get_calendar_sync(&cal_url).unwrap();
+        let local_tag = || local_cal.read().unwrap().get_item_by_url_sync(task.url()).unwrap().sync_status().clone();
+        let first_tag = local_tag();
+
+        // The server changes the ETag of an item whose content has not changed
+        server.put_item(&task).unwrap();
+        assert!(provider.sync().await);
+        assert_eq!(*changes.0.lock().unwrap(), 0);
+        let second_tag = local_tag();
+        assert!(second_tag != first_tag);
+        assert!(matches!(second_tag, SyncStatus::Synced(_)));
+
+        // Without the option, the item is written again
+        let mut provider = provider.with_config(Config::default());
+        server.put_item(&task).unwrap();
+        assert!(provider.sync().await);
+        assert_eq!(*changes.0.lock().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_items_are_not_uploaded() {
+        let server = MockServer::start().await.unwrap();
+        let cal_url = server.add_calendar("invalid", "Invalid items", SupportedComponents::TODO);
+        let client = Client::new(server.url(), "user", "password").unwrap();
+        let cal = client.get_calendar(&cal_url).await.unwrap();
+
+        let invalid = TaskBuilder::new("Every other week", &cal_url).property("RRULE", "FREQ=FORTNIGHTLY").item();
+
+        let err = detached(&cal).add_item(invalid).await.unwrap_err();
+        let err = err.downcast_ref::<crate::ical::ValidationError>().unwrap();
+        assert!(matches!(err.violations(), [crate::ical::Violation::MalformedRecurrenceRule { .. }]));
+        assert!(server.item_urls(&cal_url).is_empty());
+    }
+}
@@ -0,0 +1,228 @@
+//! A provider that syncs several remote sources with a single local source
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::marker::PhantomData;
+
+use url::Url;
+
+use crate::traits::{CalDavSource, CompleteCalendar, DavCalendar};
+use crate::config::Config;
+use super::{ConflictAuthority, Provider};
+use super::sync_progress::{FeedbackSender, IssueKind, SyncEvent, SyncProgress};
+
+/// A data source that merges several remote `CalDavSource`s (e.g. a personal server and a work server) into a single local source, and is able to sync them.
+///
+/// The app only has to deal with the local source, that contains the calendars of every remote source. \
+/// Every remote source is registered with a root URL (see [`AggregateProvider::add_remote`]): calendars that are created locally are pushed to the remote source whose root URL is a prefix of their URLs.
+#[derive(Debug)]
+pub struct AggregateProvider<L, T, R, U>
+where
+    L: CalDavSource<T> + Sync,
+    T: CompleteCalendar + Sync + Send,
+    R: CalDavSource<U> + Sync,
+    U: DavCalendar + Sync + Send,
+{
+    local: L,
+    /// The remote sources, along with their root URLs
+    remotes: Vec<(Url, R)>,
+    /// The options used during syncs
+    config: Config,
+    /// Whether some remote sources could not be reached during the last sync
+    offline: bool,
+
+    phantom_t: PhantomData<T>,
+    phantom_u: PhantomData<U>,
+}
+
+impl<L, T, R, U> AggregateProvider<L, T, R, U>
+where
+    L: CalDavSource<T> + Sync,
+    T: CompleteCalendar + Sync + Send,
+    R: CalDavSource<U> + Sync,
+    U: DavCalendar + Sync + Send,
+{
+    /// Create a provider that has no remote sources yet
+    pub fn new(local: L) -> Self {
+        Self { local, remotes: Vec::new(), config: Config::default(), offline: false,
+            phantom_t: PhantomData, phantom_u: PhantomData,
+        }
+    }
+
+    /// Set the options used during syncs, see [`Provider::with_config`]
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Add a remote source. `root` is a URL that is a prefix of the URLs of the calendars of this source (e.g. the URL of the server)
+    pub fn add_remote(&mut self, root: Url, remote: R) {
+        self.remotes.push((root, remote));
+    }
+
+    /// Returns the local source, that contains the calendars of every remote source
+    pub fn local(&self)  -> &L { &self.local }
+    /// Returns the local source, that contains the calendars of every remote source
+    pub fn local_mut(&mut self)  -> &mut L { &mut self.local }
+    /// Returns the remote sources, along with their root URLs
+    pub fn remotes(&self) -> impl Iterator<Item = (&Url, &R)> {
+        self.remotes.iter().map(|(root, remote)| (root, remote))
+    }
+    /// Returns the remote source a calendar would be created in, i.e. the one with the longest root URL that is a prefix of `cal_url`
+    pub fn remote_for(&self, cal_url: &Url) -> Option<&R> {
+        root_index_for(self.remotes.iter().map(|(root, _)| root), cal_url)
+            .map(|index| &self.remotes[index].1)
+    }
+    /// Whether some remote sources could not be reached during the last sync, see [`Provider::is_offline`]
+    pub fn is_offline(&self) -> bool { self.offline }
+
+    /// Performs a synchronisation between `local` and every remote source, and provide feeedback to the user about the progress.
+    ///
+    /// See [`Provider::sync_with_feedback`]. A remote source that cannot be reached does not prevent the other ones from being synced
+    pub async fn sync_with_feedback(&mut self, feedback_sender: FeedbackSender) -> bool {
+        let mut progress = SyncProgress::new_with_feedback_channel(feedback_sender).with_metrics(self.config.metrics.clone());
+        self.run_sync(&mut progress).await
+    }
+
+    /// Performs a synchronisation between `local` and every remote source, without giving any feedback.
+    ///
+    /// See [`Self::sync_with_feedback`]
+    pub async fn sync(&mut self) -> bool {
+        let mut progress = SyncProgress::new().with_metrics(self.config.metrics.clone());
+        self.run_sync(&mut progress).await
+    }
+
+    async fn run_sync(&mut self, progress: &mut SyncProgress) -> bool {
+        let sync_id = Some(progress.sync_id().to_string());
+        if let Err(err) = crate::correlation::in_sync(sync_id, self.run_sync_inner(progress)).await {
+            progress.error(IssueKind::SyncAborted{ error: err.to_string() });
+        }
+        self.offline = progress.is_offline();
+        progress.feedback(SyncEvent::Finished{ success: progress.is_success(), issues: progress.issues().to_vec() });
+        progress.is_success()
+    }
+
+    async fn run_sync_inner(&mut self, progress: &mut SyncProgress) -> Result<(), Box<dyn Error>> {
+        progress.info("Starting a sync of several remote sources.");
+        progress.feedback(SyncEvent::Started);
+
+        // A calendar that is known by a remote source belongs to it, even if its URL does not start with the root of this source
+        let mut remote_calendars = Vec::new();
+        for (root, remote) in &self.remotes {
+            if Provider::<L, T, R, U>::is_remote_offline(remote, progress).await {
+                progress.info(&format!("Skipping remote source {} this time", root));
+                remote_calendars.push(None);
+                continue;
+            }
+            remote.invalidate_calendars();
+            match remote.get_calendars().await {
+                Ok(cals) => remote_calendars.push(Some(cals.into_keys().collect::<HashSet<Url>>())),
+                Err(err) => {
+                    progress.error(IssueKind::RemoteSourceUnavailable{ source: root.clone(), error: err.to_string() });
+                    remote_calendars.push(None);
+                },
+            }
+        }
+
+        let roots: Vec<&Url> = self.remotes.iter().map(|(root, _)| root).collect();
+        for (index, (root, remote)) in self.remotes.iter().enumerate() {
+            let own_calendars = match &remote_calendars[index] {
+                None => continue,
+                Some(cals) => cals,
+            };
+            let owns_local_calendar = |cal_url: &Url| {
+                if own_calendars.contains(cal_url) {
+                    return true;
+                }
+                let known_elsewhere = remote_calendars.iter().flatten().any(|cals| cals.contains(cal_url));
+                known_elsewhere == false && root_index_for(roots.iter().copied(), cal_url) == Some(index)
+            };
+
+            progress.info(&format!("Syncing remote source {}", root));
+            if let Err(err) = Provider::<L, T, R, U>::sync_sources(&self.local, remote, &owns_local_calendar, ConflictAuthority::Remote, &self.config, &HashMap::new(), progress).await {
+                progress.error(IssueKind::RemoteSourceSyncFailed{ source: root.clone(), error: err.to_string() });
+            }
+        }
+
+        for cal_url in self.local.get_calendars().await?.keys() {
+            let known = remote_calendars.iter().flatten().any(|cals| cals.contains(cal_url));
+            if known == false && root_index_for(roots.iter().copied(), cal_url).is_none() {
+                progress.calendar_warn(cal_url, IssueKind::CalendarWithoutSource);
+            }
+        }
+
+        progress.info("Sync ended");
+        Ok(())
+    }
+}
+
+/// Returns the index of the longest root that is a prefix of a calendar URL
+fn root_index_for<'a, I: Iterator<Item = &'a Url>>(roots: I, cal_url: &Url) -> Option<usize> {
+    roots.enumerate()
+        .filter(|(_, root)| cal_url.as_str().starts_with(root.as_str()))
+        .max_by_key(|(_, root)| root.as_str().len())
+        .map(|(index, _)| index)
+}
+
+
+#[cfg(all(test, feature = "cache"))]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    use crate::cache::Cache;
+    use crate::calendar::SupportedComponents;
+    use crate::calendar::cached_calendar::CachedCalendar;
+    use crate::item::Item;
+    use crate::task::Task;
+
+    async fn add_calendar_with_task(source: &Cache, cal_url: &Url, task_name: &str) {
+        let cal = source.create_calendar(cal_url.clone(), task_name.to_string(), SupportedComponents::TODO, None).await.unwrap();
+        cal.write().unwrap().add_item_sync(Item::Task(Task::new(task_name.to_string(), false, cal_url))).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_sync() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let root = PathBuf::from(String::from("test_cache/aggregate_test"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let personal_root = Url::parse("https://personal.example.com/dav/").unwrap();
+        let work_root = Url::parse("https://work.example.com/dav/").unwrap();
+        let personal = Cache::new_in_memory_remote();
+        add_calendar_with_task(&personal, &personal_root.join("groceries/").unwrap(), "Buy milk").await;
+        let work = Cache::new_in_memory_remote();
+        add_calendar_with_task(&work, &work_root.join("projects/").unwrap(), "Write report").await;
+
+        let mut provider: AggregateProvider<Cache, CachedCalendar, Cache, CachedCalendar> = AggregateProvider::new(Cache::new(&root));
+        provider.add_remote(personal_root.clone(), personal);
+        provider.add_remote(work_root.clone(), work);
+        assert!(provider.sync().await);
+
+        // The local source contains the calendars of both remote sources
+        let local_calendars = provider.local().get_calendars_sync().unwrap();
+        assert_eq!(local_calendars.len(), 2);
+        assert!(local_calendars.contains_key(&personal_root.join("groceries/").unwrap()));
+        assert!(local_calendars.contains_key(&work_root.join("projects/").unwrap()));
+
+        // Calendars created locally go to the right remote source
+        let meetings_url = work_root.join("meetings/").unwrap();
+        add_calendar_with_task(provider.local(), &meetings_url, "Prepare slides").await;
+        assert!(provider.sync().await);
+        let remotes: Vec<&Cache> = provider.remotes().map(|(_, remote)| remote).collect();
+        assert!(remotes[0].get_calendar_sync(&meetings_url).is_none());
+        assert_eq!(remotes[1].get_calendar_sync(&meetings_url).unwrap().read().unwrap().get_items_sync().unwrap().len(), 1);
+
+        // Calendars of one source are not deleted when syncing another source
+        assert!(provider.sync().await);
+        assert_eq!(provider.local().get_calendars_sync().unwrap().len(), 3);
+
+        // Calendars that belong to no source are left alone (but the sync reports they have not been synced)
+        let orphan_url = Url::parse("https://elsewhere.example.com/orphan/").unwrap();
+        add_calendar_with_task(provider.local(), &orphan_url, "Orphan").await;
+        assert!(provider.sync().await == false);
+        assert!(provider.local().get_calendar_sync(&orphan_url).is_some());
+        assert!(provider.remote_for(&orphan_url).is_none());
+    }
+}
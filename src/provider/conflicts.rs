@@ -0,0 +1,67 @@
+//! A log of the sync conflicts a provider has resolved, so that users can find out why an edit "disappeared"
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::provider::ConflictAuthority;
+
+/// The ways an item can have changed on both sides since the last sync
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ConflictKind {
+    /// The item has been modified in both sources
+    ModifiedOnBothSides,
+    /// The item has been deleted locally, and modified in the remote source
+    LocallyDeletedRemotelyModified,
+    /// The item has been modified locally, and deleted from the remote source
+    LocallyModifiedRemotelyDeleted,
+}
+
+/// A conflict that has been resolved by a sync.
+///
+/// A [`Provider`](crate::provider::Provider) stores these records in the local calendar of the item (see [`CompleteCalendar::conflict_log`](crate::traits::CompleteCalendar::conflict_log)).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConflictRecord {
+    item_url: Url,
+    kind: ConflictKind,
+    local_summary: Option<String>,
+    remote_summary: Option<String>,
+    winner: ConflictAuthority,
+    date: DateTime<Utc>,
+}
+
+impl ConflictRecord {
+    pub fn new(item_url: Url, kind: ConflictKind, local_summary: Option<String>, remote_summary: Option<String>, winner: ConflictAuthority) -> Self {
+        Self { item_url, kind, local_summary, remote_summary, winner, date: crate::deterministic::now() }
+    }
+
+    /// The URL of the conflicting item
+    pub fn item_url(&self) -> &Url {
+        &self.item_url
+    }
+
+    /// How the item has changed on both sides
+    pub fn kind(&self) -> ConflictKind {
+        self.kind
+    }
+
+    /// The summary of the local version of the item
+    pub fn local_summary(&self) -> Option<&str> {
+        self.local_summary.as_deref()
+    }
+
+    /// The summary of the remote version of the item (`None` in case it has been deleted from the remote source, or in case the local version has been kept, since the remote version is not downloaded then)
+    pub fn remote_summary(&self) -> Option<&str> {
+        self.remote_summary.as_deref()
+    }
+
+    /// The source whose version has been kept
+    pub fn winner(&self) -> ConflictAuthority {
+        self.winner
+    }
+
+    /// When the conflict has been resolved
+    pub fn date(&self) -> DateTime<Utc> {
+        self.date
+    }
+}
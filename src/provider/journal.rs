@@ -0,0 +1,134 @@
+//! A journal of the sync of a calendar, that makes it possible to resume an interrupted sync, or to undo it
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::item::{Item, VersionTag};
+
+/// How old a journal can be to be resumed. Older journals are discarded, and both calendars are compared again
+const MAX_JOURNAL_AGE_HOURS: i64 = 24;
+
+/// A change that the sync of a calendar has planned to apply to an item
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PlannedChange {
+    /// The item has been deleted locally, and must be deleted from the remote source
+    LocalDeletion,
+    /// The item has been deleted from the remote source, and must be deleted locally
+    RemoteDeletion,
+    /// The item has been added to the remote source, and must be downloaded
+    RemoteAddition,
+    /// The item has been changed in the remote source, and must be downloaded
+    RemoteChange,
+    /// The item has been added locally, and must be uploaded
+    LocalAddition,
+    /// The item has been changed locally, and must be uploaded
+    LocalChange,
+}
+
+/// The plan of the sync of a calendar, along with the items that have been synced already.
+///
+/// A [`Provider`](crate::provider::Provider) stores it in the local calendar (see [`CompleteCalendar::set_sync_journal`](crate::traits::CompleteCalendar::set_sync_journal)) before applying any change, and updates it after every batch. \
+/// In case the sync is interrupted (e.g. because the app crashed, or the device ran out of battery), the next sync resumes it where it left off, instead of comparing both calendars and downloading the same items again.
+/// Changes that happened in the meantime are synced by the sync after it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SyncJournal {
+    /// When the sync started
+    started: DateTime<Utc>,
+    plan: HashMap<Url, PlannedChange>,
+    /// The remote version tags local changes must be pushed against, for items that have been modified on both sides
+    overwritten_tags: HashMap<Url, VersionTag>,
+    /// The items that have been synced already
+    done: HashSet<Url>,
+}
+
+impl SyncJournal {
+    pub fn new(plan: HashMap<Url, PlannedChange>, overwritten_tags: HashMap<Url, VersionTag>) -> Self {
+        Self { started: crate::deterministic::now(), plan, overwritten_tags, done: HashSet::new() }
+    }
+
+    /// When the sync of this journal started
+    pub fn started(&self) -> DateTime<Utc> {
+        self.started
+    }
+
+    /// Whether this journal is too old to be resumed (both calendars have likely changed too much since then)
+    pub fn is_stale(&self) -> bool {
+        crate::deterministic::now() - self.started > Duration::hours(MAX_JOURNAL_AGE_HOURS)
+    }
+
+    /// The items that have a given change planned, and that have not been synced yet
+    pub fn pending(&self, change: PlannedChange) -> HashSet<Url> {
+        self.plan.iter()
+            .filter(|(url, planned)| **planned == change && self.done.contains(*url) == false)
+            .map(|(url, _)| url.clone())
+            .collect()
+    }
+
+    /// The change planned for an item, if any
+    pub fn planned_change(&self, url: &Url) -> Option<PlannedChange> {
+        self.plan.get(url).copied()
+    }
+
+    /// The remote version tag a local change must be pushed against, in case it overwrites a remote change
+    pub fn overwritten_tag(&self, url: &Url) -> Option<&VersionTag> {
+        self.overwritten_tags.get(url)
+    }
+
+    /// Record that an item has been synced (or that it failed to, and that it is left to the next sync)
+    pub fn mark_done(&mut self, url: &Url) {
+        self.done.insert(url.clone());
+    }
+
+    /// How many items have been synced already
+    pub fn done_count(&self) -> usize {
+        self.done.len()
+    }
+
+    /// How many items this sync handles
+    pub fn len(&self) -> usize {
+        self.plan.len()
+    }
+
+    /// Whether this sync has nothing to do
+    pub fn is_empty(&self) -> bool {
+        self.plan.is_empty()
+    }
+}
+
+/// What a sync of a calendar has planned, along with the local items as they were before the sync changed them.
+///
+/// A [`Provider`](crate::provider::Provider) stores it in the local calendar (see [`CompleteCalendar::set_rollback_point`](crate::traits::CompleteCalendar::set_rollback_point)) when a sync starts changing it,
+/// so that this sync can be undone later (see [`Provider::rollback_last_sync`](crate::provider::Provider::rollback_last_sync)).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SyncRollback {
+    /// When the sync started
+    started: DateTime<Utc>,
+    plan: HashMap<Url, PlannedChange>,
+    /// The local items the plan touches, as they were before the sync. Items that did not exist locally are missing
+    previous: HashMap<Url, Item>,
+}
+
+impl SyncRollback {
+    /// Create a rollback point for the sync of `journal`, given the local items it is about to change
+    pub fn new(journal: &SyncJournal, previous: HashMap<Url, Item>) -> Self {
+        Self { started: journal.started, plan: journal.plan.clone(), previous }
+    }
+
+    /// When the sync started
+    pub fn started(&self) -> DateTime<Utc> {
+        self.started
+    }
+
+    /// The changes the sync has planned
+    pub fn plan(&self) -> &HashMap<Url, PlannedChange> {
+        &self.plan
+    }
+
+    /// The local version of an item before the sync (`None` in case it did not exist locally)
+    pub fn previous_item(&self, url: &Url) -> Option<&Item> {
+        self.previous.get(url)
+    }
+}
@@ -3,28 +3,46 @@
 //! It is also responsible for syncing them together
 
 use std::error::Error;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::marker::PhantomData;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::fmt::{Display, Formatter};
+#[cfg(feature = "cache")]
+use std::path::Path;
 
 use url::Url;
-use itertools::Itertools;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use itertools::{EitherOrBoth, Itertools};
 
 use crate::traits::{BaseCalendar, CalDavSource, DavCalendar};
 use crate::traits::CompleteCalendar;
-use crate::item::SyncStatus;
+use crate::item::{Item, SyncStatus, VersionTag};
+use crate::calendar::CalendarSyncStatus;
+#[cfg(feature = "cache")]
+use crate::cache::Cache;
+use crate::config::{Config, RemoteRemovalPolicy, RetryLaterError};
+use crate::metrics::ItemSync;
+use crate::utils::{read_locked, write_locked};
+#[cfg(feature = "cache")]
+use crate::calendar::cached_calendar::CachedCalendar;
+#[cfg(all(feature = "client", feature = "cache"))]
+use crate::client::Client;
+#[cfg(all(feature = "client", feature = "cache"))]
+use crate::calendar::remote_calendar::RemoteCalendar;
 
 pub mod sync_progress;
+pub mod aggregate;
+pub use aggregate::AggregateProvider;
+pub mod overrides;
+pub use overrides::{CalendarOverrides, SyncDirection};
+pub mod journal;
+pub use journal::{PlannedChange, SyncJournal, SyncRollback};
+pub mod conflicts;
+pub use conflicts::{ConflictKind, ConflictRecord};
 use sync_progress::SyncProgress;
-use sync_progress::{FeedbackSender, SyncEvent};
-
-/// How many items will be batched in a single HTTP request when downloading from the server
-#[cfg(not(test))]
-const DOWNLOAD_BATCH_SIZE: usize = 30;
-/// How many items will be batched in a single HTTP request when downloading from the server
-#[cfg(test)]
-const DOWNLOAD_BATCH_SIZE: usize = 3;
+use sync_progress::{CalendarListChange, FeedbackSender, IssueKind, SyncEvent, SyncStep};
 
 // I am too lazy to actually make `fetch_and_apply` generic over an async closure.
 // Let's work around by passing an enum, so that `fetch_and_apply` will know what to do
@@ -33,6 +51,20 @@ enum BatchDownloadType {
     RemoteChanges,
 }
 
+enum BatchUploadType {
+    LocalAdditions,
+    LocalChanges,
+}
+
+impl Display for BatchUploadType {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Self::LocalAdditions => write!(f, "local addition"),
+            Self::LocalChanges => write!(f, "local change"),
+        }
+    }
+}
+
 impl Display for BatchDownloadType {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         match self {
@@ -42,6 +74,25 @@ impl Display for BatchDownloadType {
     }
 }
 
+/// What a sync needs to know about a remote calendar before comparing it with its local counterpart
+struct RemoteSnapshot {
+    /// The version of the whole calendar (see [`DavCalendar::get_current_version`]), that is fetched before the version tags so that changes that happen in the meantime are not missed
+    version: Option<VersionTag>,
+    /// The version tags of the items
+    tags: HashMap<Url, VersionTag>,
+}
+
+/// Which source wins when an item has been modified on both sides since the last sync
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConflictAuthority {
+    /// The remote version is kept, local changes are discarded. This is the default, and what you want when `local` is a cache of a server
+    #[default]
+    Remote,
+    /// The local version is pushed to the remote, remote changes are overwritten.
+    /// This is what you want when `local` is the primary store, and `remote` is merely a mirror (e.g. a backup target)
+    Local,
+}
+
 
 /// A data source that combines two `CalDavSource`s, which is able to sync both sources.
 ///
@@ -50,15 +101,23 @@ impl Display for BatchDownloadType {
 #[derive(Debug)]
 pub struct Provider<L, T, R, U>
 where
-    L: CalDavSource<T>,
+    L: CalDavSource<T> + Sync,
     T: CompleteCalendar + Sync + Send,
-    R: CalDavSource<U>,
+    R: CalDavSource<U> + Sync,
     U: DavCalendar + Sync + Send,
 {
     /// The remote source (usually a server)
     remote: R,
     /// The local cache
     local: L,
+    /// The source that wins sync conflicts
+    conflict_authority: ConflictAuthority,
+    /// The options used during syncs
+    config: Config,
+    /// The calendars that are not synced with the default settings
+    calendar_overrides: HashMap<Url, CalendarOverrides>,
+    /// Whether `remote` could not be reached during the last sync
+    offline: AtomicBool,
 
     phantom_t: PhantomData<T>,
     phantom_u: PhantomData<U>,
@@ -66,21 +125,58 @@ where
 
 impl<L, T, R, U> Provider<L, T, R, U>
 where
-    L: CalDavSource<T>,
+    L: CalDavSource<T> + Sync,
     T: CompleteCalendar + Sync + Send,
-    R: CalDavSource<U>,
+    R: CalDavSource<U> + Sync,
     U: DavCalendar + Sync + Send,
 {
     /// Create a provider.
     ///
     /// `remote` is usually a [`Client`](crate::client::Client), `local` is usually a [`Cache`](crate::cache::Cache).
-    /// However, both can be interchangeable. The only difference is that `remote` always wins in case of a sync conflict
+    /// However, both can be interchangeable. The only difference is that `remote` wins in case of a sync conflict (see [`Self::with_conflict_authority`] to change this)
     pub fn new(remote: R, local: L) -> Self {
         Self { remote, local,
+            conflict_authority: ConflictAuthority::default(),
+            config: Config::default(),
+            calendar_overrides: HashMap::new(),
+            offline: AtomicBool::new(false),
             phantom_t: PhantomData, phantom_u: PhantomData,
         }
     }
 
+    /// Set which source wins in case of a sync conflict
+    pub fn with_conflict_authority(mut self, authority: ConflictAuthority) -> Self {
+        self.conflict_authority = authority;
+        self
+    }
+
+    /// Returns the source that wins in case of a sync conflict
+    pub fn conflict_authority(&self) -> ConflictAuthority { self.conflict_authority }
+
+    /// Set the options used during syncs (e.g. the download batch size)
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Returns the options used during syncs
+    pub fn config(&self) -> &Config { &self.config }
+
+    /// Sync a calendar with specific settings (e.g. only pull the changes of a shared read-only calendar), that take precedence over the ones of this provider
+    pub fn set_calendar_overrides(&mut self, cal_url: Url, overrides: CalendarOverrides) {
+        self.calendar_overrides.insert(cal_url, overrides);
+    }
+
+    /// Returns the specific settings of a calendar, if any (see [`Self::set_calendar_overrides`])
+    pub fn calendar_overrides(&self, cal_url: &Url) -> Option<&CalendarOverrides> {
+        self.calendar_overrides.get(cal_url)
+    }
+
+    /// Sync a calendar with the default settings again
+    pub fn remove_calendar_overrides(&mut self, cal_url: &Url) -> Option<CalendarOverrides> {
+        self.calendar_overrides.remove(cal_url)
+    }
+
     /// Returns the data source described as `local`
     pub fn local(&self)  -> &L { &self.local }
     /// Returns the data source described as `local`
@@ -92,16 +188,57 @@ where
     /// To be sure `local` accurately mirrors the `remote` source, you can run [`Provider::sync`]
     pub fn remote(&self) -> &R { &self.remote }
 
+    /// Whether the `remote` source could not be reached during the last sync (e.g. because the network is down).
+    ///
+    /// In this case, the sync has been postponed after a single request (see [`CalDavSource::check_reachable`]). Local changes are not lost: they keep their [`SyncStatus`], and are pushed by the next sync.
+    pub fn is_offline(&self) -> bool { self.offline.load(Ordering::Relaxed) }
+
+    /// The local calendars that have disappeared from the remote source, and that wait for the app to decide what to do with them (see [`RemoteRemovalPolicy::Ask`])
+    pub async fn pending_remote_removals(&self) -> Result<Vec<Url>, Box<dyn Error>> {
+        let mut pending = Vec::new();
+        for (cal_url, cal_local) in self.local.get_calendars().await? {
+            if cal_local.read().unwrap().sync_status() == CalendarSyncStatus::RemovedRemotely {
+                pending.push(cal_url);
+            }
+        }
+        pending.sort();
+        Ok(pending)
+    }
+
+    /// Decide what to do with a local calendar that has disappeared from the remote source (see [`Self::pending_remote_removals`]).
+    ///
+    /// With [`RemoteRemovalPolicy::Delete`], it is deleted from `local`. With [`RemoteRemovalPolicy::Archive`], it is kept as an archive. [`RemoteRemovalPolicy::Ask`] leaves it pending.
+    pub async fn resolve_remote_removal(&self, cal_url: &Url, decision: RemoteRemovalPolicy) -> Result<(), Box<dyn Error>> {
+        let cal_local = self.local.get_calendar(cal_url).await.ok_or_else(|| format!("There is no local calendar {}", cal_url))?;
+        if cal_local.read().unwrap().sync_status() != CalendarSyncStatus::RemovedRemotely {
+            return Err(format!("Calendar {} is not waiting for a decision about its removal from the remote source", cal_url).into());
+        }
+        log::info!(target: crate::logging::PROVIDER, "Resolving the removal of calendar {} from the remote source: {:?}", cal_url, decision);
+        match decision {
+            RemoteRemovalPolicy::Delete => self.local.delete_calendar(cal_url).await?,
+            RemoteRemovalPolicy::Archive => cal_local.write().unwrap().set_sync_status(CalendarSyncStatus::Archived),
+            RemoteRemovalPolicy::Ask => (),
+        }
+        Ok(())
+    }
+
+    /// Returns the local calendar and its remote counterpart (i.e. the calendars that [`Provider::sync`] syncs together), in case both exist
+    pub async fn calendar_pair(&self, cal_url: &Url) -> Option<(Arc<RwLock<T>>, Arc<RwLock<U>>)> {
+        let local = self.local.get_calendar(cal_url).await?;
+        let remote = self.remote.get_calendar(cal_url).await?;
+        Some((local, remote))
+    }
+
     /// Performs a synchronisation between `local` and `remote`, and provide feeedback to the user about the progress.
     ///
     /// This bidirectional sync applies additions/deletions made on a source to the other source.
-    /// In case of conflicts (the same item has been modified on both ends since the last sync, `remote` always wins).
+    /// In case of conflicts (the same item has been modified on both ends since the last sync), the [`ConflictAuthority`] of this provider wins (`remote`, unless told otherwise).
     ///
     /// It returns whether the sync was totally successful (details about errors are logged using the `log::*` macros).
     /// In case errors happened, the sync might have been partially executed but your data will never be correupted (either locally nor in the server).
     /// Simply run this function again, it will re-start a sync, picking up where it failed.
     pub async fn sync_with_feedback(&mut self, feedback_sender: FeedbackSender) -> bool {
-        let mut progress = SyncProgress::new_with_feedback_channel(feedback_sender);
+        let mut progress = SyncProgress::new_with_feedback_channel(feedback_sender).with_metrics(self.config.metrics.clone());
         self.run_sync(&mut progress).await
     }
 
@@ -109,15 +246,132 @@ where
     ///
     /// See [`Self::sync_with_feedback`]
     pub async fn sync(&mut self) -> bool {
-        let mut progress = SyncProgress::new();
+        let mut progress = SyncProgress::new().with_metrics(self.config.metrics.clone());
         self.run_sync(&mut progress).await
     }
 
+    /// Performs a synchronisation of a single calendar between `local` and `remote`, and provide feeedback to the user about the progress.
+    ///
+    /// This is much faster than a full sync, and can be used to refresh only the calendar the user is currently looking at (e.g. when it is opened). \
+    /// The calendar must already exist in both sources (e.g. because a full sync has already been run), otherwise this returns `false` and nothing is synced.
+    /// Conflicts are handled just like in [`Self::sync_with_feedback`]
+    pub async fn sync_calendar_with_feedback(&self, cal_url: &Url, feedback_sender: FeedbackSender) -> bool {
+        let mut progress = SyncProgress::new_with_feedback_channel(feedback_sender).with_metrics(self.config.metrics.clone());
+        self.run_calendar_sync(cal_url, &mut progress).await
+    }
+
+    /// Performs a synchronisation of a single calendar between `local` and `remote`, without giving any feedback.
+    ///
+    /// See [`Self::sync_calendar_with_feedback`]
+    pub async fn sync_calendar(&self, cal_url: &Url) -> bool {
+        let mut progress = SyncProgress::new().with_metrics(self.config.metrics.clone());
+        self.run_calendar_sync(cal_url, &mut progress).await
+    }
+
+    /// Undo the last sync that changed each local calendar, e.g. after a conflict has been resolved the wrong way.
+    ///
+    /// The local items that sync has changed or deleted are restored as they were before it, with their former sync statuses: the next sync makes the same decisions again, unless the settings have changed in the meantime (e.g. with [`Self::with_conflict_authority`]). \
+    /// Local changes made to these items since that sync are lost.
+    ///
+    /// With `revert_remote`, the items that sync has pushed to `remote` are reverted as well: local additions are deleted from `remote`, and local deletions are added back.
+    /// Local changes cannot be reverted, since their former remote versions are not known; their local copies are left as they are.
+    pub async fn rollback_last_sync(&self, revert_remote: bool) -> Result<(), Box<dyn Error>> {
+        for (cal_url, cal_local) in self.local.get_calendars().await? {
+            let rollback = match cal_local.read().unwrap().rollback_point().cloned() {
+                None => continue,
+                Some(rollback) => rollback,
+            };
+            log::info!(target: crate::logging::PROVIDER, "Rolling back the sync of calendar {} that started on {}", cal_url, rollback.started());
+            let cal_remote = match revert_remote {
+                true => self.remote.get_calendar(&cal_url).await,
+                false => None,
+            };
+
+            for (url, change) in rollback.plan() {
+                let previous = rollback.previous_item(url).cloned();
+                match (change, &cal_remote) {
+                    (PlannedChange::LocalAddition, Some(cal_remote)) => {
+                        // Unless it has failed, the upload has made the local copy synced
+                        let status = read_locked(&cal_local, url, |cal, url| Box::pin(async move {
+                            cal.get_item_by_url(url).await.map(|item| item.sync_status().clone())
+                        }));
+                        if let Some(SyncStatus::Synced(_)) = status {
+                            let mut detached_remote = cal_remote.read().unwrap().detached();
+                            detached_remote.delete_item(url).await?;
+                            cal_remote.write().unwrap().record_changes(detached_remote, std::slice::from_ref(url))?;
+                            Self::restore_item(&cal_local, url, previous)?;
+                        }
+                    },
+                    (PlannedChange::LocalDeletion, Some(cal_remote)) => {
+                        let exists = read_locked(&cal_local, url, |cal, url| Box::pin(async move { cal.get_item_by_url(url).await.is_some() }));
+                        if let (Some(mut item), false) = (previous, exists) {
+                            item.set_sync_status(SyncStatus::NotSynced);
+                            let mut detached_remote = cal_remote.read().unwrap().detached();
+                            let new_status = detached_remote.add_item(item.clone()).await?;
+                            cal_remote.write().unwrap().record_changes(detached_remote, std::slice::from_ref(item.url()))?;
+                            item.set_sync_status(new_status);
+                            write_locked(&cal_local, &(), |cal, _| cal.add_item(item))?;
+                        }
+                    },
+                    (PlannedChange::LocalChange, Some(_)) => log::warn!(target: crate::logging::PROVIDER, "Local change {} cannot be reverted in the remote source", url),
+                    (PlannedChange::LocalAddition, None) | (PlannedChange::LocalChange, None) => {
+                        // These are in the remote source now, their local copies must stay consistent with it
+                    },
+                    _ => Self::restore_item(&cal_local, url, previous)?,
+                }
+            }
+            cal_local.write().unwrap().set_rollback_point(None);
+        }
+        Ok(())
+    }
+
+    /// Give a local item the state it had before a sync (`None` in case it did not exist)
+    fn restore_item(cal_local: &RwLock<T>, url: &Url, previous: Option<Item>) -> Result<(), Box<dyn Error>> {
+        write_locked(cal_local, url, |cal_local, url| Box::pin(async move {
+            let exists = cal_local.get_item_by_url(url).await.is_some();
+            match (previous, exists) {
+                (None, true) => cal_local.immediately_delete_item(url).await,
+                (None, false) => Ok(()),
+                (Some(item), true) => cal_local.update_item(item).await.map(|_| ()),
+                (Some(item), false) => cal_local.add_item(item).await.map(|_| ()),
+            }
+        }))
+    }
+
+    async fn run_calendar_sync(&self, cal_url: &Url, progress: &mut SyncProgress) -> bool {
+        progress.feedback(SyncEvent::Started);
+        let overrides = self.calendar_overrides.get(cal_url).cloned().unwrap_or_default();
+        let sync_id = Some(progress.sync_id().to_string());
+        let offline = overrides.enabled && crate::correlation::in_sync(sync_id.clone(), Self::is_remote_offline(&self.remote, progress)).await;
+        match crate::correlation::in_sync(sync_id.clone(), self.calendar_pair(cal_url)).await {
+            _ if overrides.enabled == false => progress.info(&format!("Calendar {} is not synced, its sync has been disabled", cal_url)),
+            _ if offline => (),
+            None => progress.calendar_error(cal_url, IssueKind::CalendarNotInBothSources),
+            Some((cal_local, cal_remote)) => {
+                let kind = cal_remote.read().unwrap().kind();
+                cal_local.write().unwrap().set_kind(kind);
+                let overrides = overrides::overrides_of(&self.calendar_overrides, cal_url, kind);
+                let sync = Self::sync_calendar_pair(cal_local.clone(), cal_remote, self.conflict_authority, &self.config, &overrides, progress);
+                match crate::correlation::in_sync(sync_id, sync).await {
+                    Err(err) => progress.calendar_error(cal_url, IssueKind::CalendarSyncFailed{ error: err.to_string() }),
+                    Ok(()) => cal_local.write().unwrap().set_sync_status(CalendarSyncStatus::Synced),
+                }
+            },
+        }
+        self.save_local_after_sync(progress);
+        self.offline.store(progress.is_offline(), Ordering::Relaxed);
+        progress.feedback(SyncEvent::Finished{ success: progress.is_success(), issues: progress.issues().to_vec() });
+        progress.is_success()
+    }
+
     async fn run_sync(&mut self, progress: &mut SyncProgress) -> bool {
-        if let Err(err) = self.run_sync_inner(progress).await {
-            progress.error(&format!("Sync terminated because of an error: {}", err));
+        let sync_id = Some(progress.sync_id().to_string());
+        if let Err(err) = crate::correlation::in_sync(sync_id, self.run_sync_inner(progress)).await {
+            progress.error(IssueKind::SyncAborted{ error: err.to_string() });
         }
-        progress.feedback(SyncEvent::Finished{ success: progress.is_success() });
+        self.save_local_after_sync(progress);
+        self.offline.store(progress.is_offline(), Ordering::Relaxed);
+        progress.feedback(SyncEvent::Finished{ success: progress.is_success(), issues: progress.issues().to_vec() });
         progress.is_success()
     }
 
@@ -125,367 +379,983 @@ where
         progress.info("Starting a sync.");
         progress.feedback(SyncEvent::Started);
 
+        if Self::is_remote_offline(&self.remote, progress).await {
+            return Ok(());
+        }
+
+        // Calendars may have been created or deleted by other clients since the last time they were listed
+        self.remote.invalidate_calendars();
+        Self::sync_sources(&self.local, &self.remote, &|_| true, self.conflict_authority, &self.config, &self.calendar_overrides, progress).await?;
+
+        progress.info("Sync ended");
+        Ok(())
+    }
+
+    /// Save `local` in case the sync has succeeded and [`Config::save_after_sync`] is set. Failing to save makes the sync fail
+    fn save_local_after_sync(&self, progress: &mut SyncProgress) {
+        if self.config.save_after_sync == false || progress.is_success() == false || progress.is_offline() {
+            return;
+        }
+        if let Err(err) = self.local.persist() {
+            progress.error(IssueKind::SaveFailed{ error: err.to_string() });
+        }
+    }
+
+    /// Check whether `remote` can be reached, and mark the sync as offline (see [`SyncProgress::is_offline`]) in case it cannot
+    pub(crate) async fn is_remote_offline(remote: &R, progress: &mut SyncProgress) -> bool {
+        match remote.check_reachable().await {
+            Ok(()) => false,
+            Err(err) => {
+                progress.set_offline(err.to_string());
+                true
+            },
+        }
+    }
+
+    /// Sync the calendars of `remote` with their counterparts in `local`.
+    ///
+    /// Local calendars that are not in `remote` are only considered when `owns_local_calendar` returns `true` for them (they are then either created in `remote`, or deleted locally in case they have been deleted from `remote`). \
+    /// Calendars that have an entry in `overrides` are synced with these settings.
+    /// In case `remote` turns out to be unreachable after a calendar failed to sync, the remaining calendars are not synced (see [`SyncProgress::is_offline`])
+    pub(crate) async fn sync_sources(local: &L, remote: &R, owns_local_calendar: &(dyn Fn(&Url) -> bool + Sync), authority: ConflictAuthority, config: &Config, overrides: &HashMap<Url, CalendarOverrides>, progress: &mut SyncProgress) -> Result<(), Box<dyn Error>> {
         let mut handled_calendars = HashSet::new();
 
         // Sync every remote calendar
-        let cals_remote = self.remote.get_calendars().await?;
+        let cals_remote = remote.get_calendars().await?;
+        let remote_calendars: HashSet<Url> = cals_remote.keys().cloned().collect();
+        let mut prefetched = Self::prefetch_version_tags(local, &cals_remote, config, overrides, progress).await;
         for (cal_url, cal_remote) in cals_remote {
-            let counterpart = match self.get_or_insert_local_counterpart_calendar(&cal_url, cal_remote.clone()).await {
+            let kind = cal_remote.read().unwrap().kind();
+            let cal_overrides = overrides::overrides_of(overrides, &cal_url, kind);
+            if cal_overrides.enabled == false {
+                progress.debug(&format!("> Not syncing calendar {}, its sync has been disabled", cal_url));
+                handled_calendars.insert(cal_url);
+                continue;
+            }
+
+            let local_status = local.get_calendar(&cal_url).await
+                .map(|cal| cal.read().unwrap().sync_status());
+            if local_status == Some(CalendarSyncStatus::LocallyDeleted) {
+                if cal_overrides.pushes() == false {
+                    progress.debug(&format!("> Not deleting calendar {} from the remote source, it is pull-only", cal_url));
+                    handled_calendars.insert(cal_url);
+                    continue;
+                }
+                progress.debug(&format!("> Deleting calendar {}, that has been deleted locally", cal_url));
+                if let Err(err) = remote.delete_calendar(&cal_url).await {
+                    // It is still marked for deletion, the next sync will try again
+                    progress.calendar_warn(&cal_url, IssueKind::RemoteCalendarDeletionFailed{ error: err.to_string() });
+                    handled_calendars.insert(cal_url);
+                    continue;
+                }
+                if let Err(err) = local.delete_calendar(&cal_url).await {
+                    progress.calendar_warn(&cal_url, IssueKind::LocalCalendarDeletionFailed{ error: err.to_string() });
+                }
+                handled_calendars.insert(cal_url);
+                continue;
+            }
+
+            let counterpart = match get_or_insert_counterpart_calendar("local", local, &cal_url, cal_remote.clone()).await {
                 Err(err) => {
-                    progress.warn(&format!("Unable to get or insert local counterpart calendar for {} ({}). Skipping this time", cal_url, err));
+                    progress.calendar_warn(&cal_url, IssueKind::LocalCounterpartFailed{ error: err.to_string() });
                     continue;
                 },
                 Ok(arc) => arc,
             };
+            counterpart.write().unwrap().set_kind(kind);
+            if local_status.is_none() {
+                let name = counterpart.read().unwrap().name().to_string();
+                progress.calendar_list_changed(CalendarListChange::Added{ url: cal_url.clone(), name });
+            }
 
-            if let Err(err) = Self::sync_calendar_pair(counterpart, cal_remote, progress).await {
-                progress.warn(&format!("Unable to sync calendar {}: {}, skipping this time.", cal_url, err));
+            let snapshot = prefetched.remove(&cal_url);
+            if let Err(err) = Self::sync_calendar_pair_with_tags(counterpart.clone(), cal_remote, authority, config, &cal_overrides, snapshot, progress).await {
+                progress.calendar_warn(&cal_url, IssueKind::CalendarSyncFailed{ error: err.to_string() });
+                // The network may have gone down during the sync. In this case, the other calendars would fail as well
+                if Self::is_remote_offline(remote, progress).await {
+                    return Ok(());
+                }
                 continue;
             }
+            counterpart.write().unwrap().set_sync_status(CalendarSyncStatus::Synced);
             handled_calendars.insert(cal_url);
         }
 
         // Sync every local calendar that would not be in the remote yet
-        let cals_local = self.local.get_calendars().await?;
+        let cals_local = local.get_calendars().await?;
         for (cal_url, cal_local) in cals_local {
-            if handled_calendars.contains(&cal_url) {
+            if handled_calendars.contains(&cal_url) || owns_local_calendar(&cal_url) == false {
+                continue;
+            }
+            let kind = cal_local.read().unwrap().kind();
+            let cal_overrides = overrides::overrides_of(overrides, &cal_url, kind);
+            if cal_overrides.enabled == false {
+                progress.debug(&format!("> Not syncing calendar {}, its sync has been disabled", cal_url));
                 continue;
             }
 
-            let counterpart = match self.get_or_insert_remote_counterpart_calendar(&cal_url, cal_local.clone()).await {
+            if remote_calendars.contains(&cal_url) == false {
+                let local_status = cal_local.read().unwrap().sync_status();
+                match local_status {
+                    CalendarSyncStatus::NotSynced if cal_overrides.pushes() == false => {
+                        progress.debug(&format!("> Not creating calendar {} in the remote source, it is pull-only", cal_url));
+                        continue;
+                    },
+                    CalendarSyncStatus::NotSynced => (),
+                    CalendarSyncStatus::RemovedRemotely | CalendarSyncStatus::Archived => {
+                        progress.debug(&format!("> Not syncing calendar {}, it has been removed from the remote source", cal_url));
+                        continue;
+                    },
+                    CalendarSyncStatus::LocallyDeleted => {
+                        // It has been deleted on both sides
+                        progress.debug(&format!("> Deleting local calendar {}, that is not in the remote source anymore", cal_url));
+                        if let Err(err) = local.delete_calendar(&cal_url).await {
+                            progress.calendar_warn(&cal_url, IssueKind::LocalCalendarDeletionFailed{ error: err.to_string() });
+                        }
+                        continue;
+                    },
+                    CalendarSyncStatus::Synced => {
+                        Self::handle_remote_removal(local, &cal_url, cal_local, config.remote_removal, progress).await;
+                        continue;
+                    },
+                }
+            }
+
+            let counterpart = match get_or_insert_counterpart_calendar("remote", remote, &cal_url, cal_local.clone()).await {
                 Err(err) => {
-                    progress.warn(&format!("Unable to get or insert remote counterpart calendar for {} ({}). Skipping this time", cal_url, err));
+                    progress.calendar_warn(&cal_url, IssueKind::RemoteCounterpartFailed{ error: err.to_string() });
                     continue;
                 },
                 Ok(arc) => arc,
             };
 
-            if let Err(err) = Self::sync_calendar_pair(cal_local, counterpart, progress).await {
-                progress.warn(&format!("Unable to sync calendar {}: {}, skipping this time.", cal_url, err));
+            if let Err(err) = Self::sync_calendar_pair(cal_local.clone(), counterpart, authority, config, &cal_overrides, progress).await {
+                progress.calendar_warn(&cal_url, IssueKind::CalendarSyncFailed{ error: err.to_string() });
+                if Self::is_remote_offline(remote, progress).await {
+                    return Ok(());
+                }
                 continue;
             }
+            cal_local.write().unwrap().set_sync_status(CalendarSyncStatus::Synced);
         }
 
-        progress.info("Sync ended");
-
         Ok(())
     }
 
+    /// Apply a [`RemoteRemovalPolicy`] to a local calendar that has been synced before, and that has disappeared from the remote source
+    async fn handle_remote_removal(local: &L, cal_url: &Url, cal_local: Arc<RwLock<T>>, policy: RemoteRemovalPolicy, progress: &mut SyncProgress) {
+        let name = cal_local.read().unwrap().name().to_string();
+        let url = cal_url.clone();
+        match policy {
+            RemoteRemovalPolicy::Delete => {
+                progress.debug(&format!("> Deleting local calendar {}, that is not in the remote source anymore", cal_url));
+                match local.delete_calendar(cal_url).await {
+                    Err(err) => progress.calendar_warn(cal_url, IssueKind::LocalCalendarDeletionFailed{ error: err.to_string() }),
+                    Ok(()) => progress.calendar_list_changed(CalendarListChange::Removed{ url, name }),
+                }
+            },
+            RemoteRemovalPolicy::Archive => {
+                progress.debug(&format!("> Archiving local calendar {}, that is not in the remote source anymore", cal_url));
+                cal_local.write().unwrap().set_sync_status(CalendarSyncStatus::Archived);
+                progress.calendar_list_changed(CalendarListChange::Archived{ url, name });
+            },
+            RemoteRemovalPolicy::Ask => {
+                progress.debug(&format!("> Keeping local calendar {}, that is not in the remote source anymore, until the app decides what to do with it", cal_url));
+                cal_local.write().unwrap().set_sync_status(CalendarSyncStatus::RemovedRemotely);
+                progress.calendar_list_changed(CalendarListChange::RemovalPending{ url, name });
+            },
+        }
+    }
 
-    async fn get_or_insert_local_counterpart_calendar(&mut self, cal_url: &Url, needle: Arc<Mutex<U>>) -> Result<Arc<Mutex<T>>, Box<dyn Error>> {
-        get_or_insert_counterpart_calendar("local", &mut self.local, cal_url, needle).await
+    /// Sync a local calendar with its remote counterpart, reporting the progress and errors to `progress`.
+    ///
+    /// This is the building block of [`Self::sync`]. It can be used to drive custom sync flows, for calendars that are not (or not yet) known to a `Provider`. \
+    /// Items that have been modified on both sides are resolved according to `authority`, and downloads are batched according to `config`, unless `overrides` tell otherwise.
+    /// This does not change the [`CalendarSyncStatus`] of `cal_local`, nor does it emit the `Started` and `Finished` [`SyncEvent`]s, this is left to the caller.
+    ///
+    /// An `Err` is returned in case the calendars could not be compared. Failures that happen on single items are only reported to `progress` (see [`SyncProgress::is_success`])
+    pub async fn sync_calendar_pair(cal_local: Arc<RwLock<T>>, cal_remote: Arc<RwLock<U>>, authority: ConflictAuthority, config: &Config, overrides: &CalendarOverrides, progress: &mut SyncProgress) -> Result<(), Box<dyn Error>> {
+        Self::sync_calendar_pair_with_tags(cal_local, cal_remote, authority, config, overrides, None, progress).await
     }
-    async fn get_or_insert_remote_counterpart_calendar(&mut self, cal_url: &Url, needle: Arc<Mutex<T>>) -> Result<Arc<Mutex<U>>, Box<dyn Error>> {
-        get_or_insert_counterpart_calendar("remote", &mut self.remote, cal_url, needle).await
+
+    /// Fetch the version tags of the remote calendars that are about to be synced, several calendars at a time (see [`Config::tag_prefetch_concurrency`]),
+    /// so that waiting for the server overlaps for all of them.
+    ///
+    /// Calendars whose version tags could not be fetched are left out. Their sync fetches them again (and reports the error)
+    async fn prefetch_version_tags(local: &L, cals_remote: &HashMap<Url, Arc<RwLock<U>>>, config: &Config, overrides: &HashMap<Url, CalendarOverrides>, progress: &mut SyncProgress)
+        -> HashMap<Url, RemoteSnapshot>
+    {
+        let mut prefetched = HashMap::new();
+        if config.tag_prefetch_concurrency <= 1 {
+            return prefetched;
+        }
+
+        let mut to_fetch = Vec::new();
+        for (cal_url, cal_remote) in cals_remote {
+            if overrides.get(cal_url).cloned().unwrap_or_default().enabled == false {
+                continue;
+            }
+            // Calendars that are about to be deleted, or whose interrupted sync is resumed, do not need them.
+            // Neither do calendars that may not have changed since their last sync, unless their sync finds out they have
+            if let Some(cal_local) = local.get_calendar(cal_url).await {
+                let cal_local = cal_local.read().unwrap();
+                if cal_local.sync_status() == CalendarSyncStatus::LocallyDeleted
+                || cal_local.sync_journal().filter(|journal| journal.is_stale() == false).is_some()
+                || Self::may_be_unchanged(&*cal_local) {
+                    continue;
+                }
+            }
+            to_fetch.push((cal_url, cal_remote));
+        }
+
+        progress.debug(&format!("Fetching the version tags of {} calendars...", to_fetch.len()));
+        let window_start = config.sync_window_start();
+        for round in to_fetch.chunks(config.tag_prefetch_concurrency) {
+            let fetches = round.iter()
+                .map(|(_, cal)| Self::remote_version_tags(cal, window_start))
+                .collect();
+            let results = crate::utils::join_all(fetches).await;
+            for ((cal_url, _), result) in round.iter().zip(results) {
+                match result {
+                    Ok(snapshot) => { prefetched.insert((*cal_url).clone(), snapshot); },
+                    Err(err) => progress.debug(&format!("> Unable to fetch the version tags of {} in advance: {}", cal_url, err)),
+                }
+            }
+        }
+        prefetched
     }
 
+    /// The version of `cal_remote` and the version tags of the items a sync considers, i.e. only the recent ones in case a sync window is set (see [`Config::sync_window_days`])
+    async fn remote_version_tags(cal_remote: &RwLock<U>, window_start: Option<DateTime<Utc>>) -> Result<RemoteSnapshot, Box<dyn Error>> {
+        let cal_remote = cal_remote.read().unwrap().detached();
+        // Servers that fail to tell their version can still be synced, they are just never skipped
+        let version = cal_remote.get_current_version().await.ok().flatten();
+        if version.is_none() {
+            // We cannot tell whether the version tags we may have cached are outdated. Let's not use them
+            cal_remote.refresh();
+        }
+        let tags = match window_start {
+            None => cal_remote.get_item_version_tags().await?,
+            Some(start) => cal_remote.get_item_version_tags_modified_since(&start).await?,
+        };
+        Ok(RemoteSnapshot { version, tags })
+    }
 
-    async fn sync_calendar_pair(cal_local: Arc<Mutex<T>>, cal_remote: Arc<Mutex<U>>, progress: &mut SyncProgress) -> Result<(), Box<dyn Error>> {
-        let mut cal_remote = cal_remote.lock().unwrap();
-        let mut cal_local = cal_local.lock().unwrap();
-        let cal_name = cal_local.name().to_string();
+    /// Whether the sync of `cal_local` may be skipped, i.e. nothing has changed locally since a sync that has recorded the version of the remote calendar (see [`CompleteCalendar::last_remote_version`]).
+    ///
+    /// The remote calendar must then be checked for changes
+    fn may_be_unchanged(cal_local: &T) -> bool {
+        cal_local.last_remote_version().is_some()
+            && cal_local.sync_index().all(|(_, status)| matches!(status, SyncStatus::Synced(_)))
+    }
+
+    /// Same as [`Self::sync_calendar_pair`], with the version tags of `cal_remote` in case they have been fetched already
+    async fn sync_calendar_pair_with_tags(cal_local: Arc<RwLock<T>>, cal_remote: Arc<RwLock<U>>, authority: ConflictAuthority, config: &Config, overrides: &CalendarOverrides, remote: Option<RemoteSnapshot>, progress: &mut SyncProgress) -> Result<(), Box<dyn Error>> {
+        // The warnings and errors of this sync are about this calendar
+        let cal_url = cal_local.read().unwrap().url().clone();
+        progress.set_calendar(Some(cal_url));
+        let result = Self::diff_and_apply(Arc::clone(&cal_local), cal_remote, authority, config, overrides, remote, progress).await;
+        progress.set_calendar(None);
+        cal_local.write().unwrap().purge_trash();
+        result
+    }
+
+    /// Find the differences between two calendars, and apply them. See [`Self::sync_calendar_pair`]
+    async fn diff_and_apply(cal_local: Arc<RwLock<T>>, cal_remote: Arc<RwLock<U>>, authority: ConflictAuthority, config: &Config, overrides: &CalendarOverrides, remote: Option<RemoteSnapshot>, progress: &mut SyncProgress) -> Result<(), Box<dyn Error>> {
+        let cal_name = cal_local.read().unwrap().name().to_string();
+        let authority = overrides.conflict_authority.unwrap_or(authority);
+        let download_batch_size = overrides.download_batch_size.unwrap_or(config.download_batch_size);
 
         progress.info(&format!("Syncing calendar {}", cal_name));
         progress.reset_counter();
+        progress.set_total_operations(None);
         progress.feedback(SyncEvent::InProgress{
             calendar: cal_name.clone(),
             items_done_already: 0,
-            details: "started".to_string()
+            total_operations: None,
+            step: SyncStep::Started,
         });
 
+        // An interrupted sync is resumed from its journal, rather than comparing both calendars again
+        let resumed = cal_local.read().unwrap().sync_journal().filter(|journal| journal.is_stale() == false).cloned();
+        let issues_before = progress.issues().len();
+
+        // Calendars that have changed on neither side since their last sync do not have to be compared
+        if resumed.is_none() && remote.is_none() && Self::may_be_unchanged(&*cal_local.read().unwrap()) {
+            let last_version = cal_local.read().unwrap().last_remote_version().cloned();
+            let detached_remote = cal_remote.read().unwrap().detached();
+            let current_version = detached_remote.get_current_version().await.ok().flatten();
+            if current_version.is_some() && current_version == last_version {
+                progress.debug(&format!("> Calendar {} has not changed since the last sync", cal_name));
+                progress.set_total_operations(Some(0));
+                progress.feedback(SyncEvent::CalendarPlanned{
+                    calendar: cal_name.clone(),
+                    total_operations: 0,
+                });
+                return Ok(());
+            }
+        }
+
         // Step 1 - find the differences
-        progress.debug("Finding the differences to sync...");
+        if resumed.is_none() {
+            progress.debug("Finding the differences to sync...");
+        }
         let mut local_del = HashSet::new();
         let mut remote_del = HashSet::new();
         let mut local_changes = HashSet::new();
         let mut remote_changes = HashSet::new();
         let mut local_additions = HashSet::new();
         let mut remote_additions = HashSet::new();
+        // Local changes that overwrite a remote change. They must be pushed against the current remote version tag
+        let mut overwritten_tags: HashMap<Url, VersionTag> = HashMap::new();
+        // Items that are neither local nor remote changes, but whose local copy must be discarded
+        let mut deleted_on_both_sides = HashSet::new();
+        // Conflicts are logged in the local calendar, along with the summaries of both versions
+        let mut conflicts = Vec::new();
 
-        let remote_items = cal_remote.get_item_version_tags().await?;
-        progress.feedback(SyncEvent::InProgress{
-            calendar: cal_name.clone(),
-            items_done_already: 0,
-            details: format!("{} remote items", remote_items.len()),
-        });
+        // The version of the remote calendar these differences are computed against
+        let mut remote_version = None;
 
-        let mut local_items_to_handle = cal_local.get_item_urls().await?;
-        for (url, remote_tag) in remote_items {
-            progress.trace(&format!("***** Considering remote item {}...", url));
-            match cal_local.get_item_by_url(&url).await {
-                None => {
-                    // This was created on the remote
-                    progress.debug(&format!("*   {} is a remote addition", url));
-                    remote_additions.insert(url);
-                },
-                Some(local_item) => {
-                    if local_items_to_handle.remove(&url) == false {
-                        progress.error(&format!("Inconsistent state: missing task {} from the local tasks", url));
-                    }
+        // Differences are computed while only holding read locks, and without holding any lock while waiting for the server, so that other threads can still browse the calendars
+        if resumed.is_none() {
+            let window_start = config.sync_window_start();
+            let snapshot = match remote {
+                Some(snapshot) => snapshot,
+                None => Self::remote_version_tags(&cal_remote, window_start).await?,
+            };
+            remote_version = snapshot.version;
+            let mut remote_items = snapshot.tags;
 
-                    match local_item.sync_status() {
-                        SyncStatus::NotSynced => {
-                            progress.error(&format!("URL reuse between remote and local sources ({}). Ignoring this item in the sync", url));
-                            continue;
-                        },
-                        SyncStatus::Synced(local_tag) => {
-                            if &remote_tag != local_tag {
-                                // This has been modified on the remote
-                                progress.debug(&format!("*   {} is a remote change", url));
-                                remote_changes.insert(url);
-                            }
-                        },
-                        SyncStatus::LocallyModified(local_tag) => {
-                            if &remote_tag == local_tag {
-                                // This has been changed locally
-                                progress.debug(&format!("*   {} is a local change", url));
-                                local_changes.insert(url);
-                            } else {
-                                progress.info(&format!("Conflict: task {} has been modified in both sources. Using the remote version.", url));
-                                progress.debug(&format!("*   {} is considered a remote change", url));
-                                remote_changes.insert(url);
-                            }
-                        },
-                        SyncStatus::LocallyDeleted(local_tag) => {
-                            if &remote_tag == local_tag {
-                                // This has been locally deleted
-                                progress.debug(&format!("*   {} is a local deletion", url));
-                                local_del.insert(url);
-                            } else {
-                                progress.info(&format!("Conflict: task {} has been locally deleted and remotely modified. Reverting to the remote version.", url));
-                                progress.debug(&format!("*   {} is a considered a remote change", url));
-                                remote_changes.insert(url);
-                            }
-                        },
+            // Local items that are out of the sync window are not compared with the remote source...
+            let mut out_of_window = HashSet::new();
+            if let Some(start) = window_start {
+                let unlisted_changes: Vec<Url> = {
+                    let cal_local = cal_local.read().unwrap();
+                    out_of_window = cal_local.iter_items()
+                        .filter(|item| item.last_modified() < &start)
+                        .map(|item| item.url().clone())
+                        .collect();
+
+                    // ...except local changes, that must be pushed against the current remote version tags
+                    cal_local.sync_index()
+                        .filter(|(url, status)| matches!(status, SyncStatus::LocallyModified(_) | SyncStatus::LocallyDeleted(_)) && remote_items.contains_key(*url) == false)
+                        .map(|(url, _)| url.clone())
+                        .collect()
+                };
+                if unlisted_changes.is_empty() == false {
+                    let detached_remote = cal_remote.read().unwrap().detached();
+                    let unlisted_items = detached_remote.get_items_by_url(&unlisted_changes).await?;
+                    for item in unlisted_items.into_iter().flatten() {
+                        if let SyncStatus::Synced(tag) = item.sync_status() {
+                            remote_items.insert(item.url().clone(), tag.clone());
+                        }
                     }
                 }
             }
+            progress.feedback(SyncEvent::InProgress{
+                calendar: cal_name.clone(),
+                items_done_already: 0,
+                total_operations: None,
+                step: SyncStep::RemoteItemsListed{ count: remote_items.len() },
+            });
+
+            // Both indexes are sorted by URL, so that they can be compared in a single pass
+            let mut remote_items: Vec<(Url, VersionTag)> = remote_items.into_iter().collect();
+            remote_items.sort_unstable_by(|(url_a, _), (url_b, _)| url_a.cmp(url_b));
+            let cal_local = cal_local.read().unwrap();
+            let items = remote_items.into_iter().merge_join_by(cal_local.sync_index(), |(remote_url, _), (local_url, _)| remote_url.cmp(local_url));
+            for item in items {
+                match item {
+                    EitherOrBoth::Left((url, _)) => {
+                        // This was created on the remote
+                        progress.trace(&format!("***** Considering remote item {}...", url));
+                        progress.debug(&format!("*   {} is a remote addition", url));
+                        remote_additions.insert(url);
+                    },
+                    EitherOrBoth::Both((url, remote_tag), (_, local_status)) => {
+                        progress.trace(&format!("***** Considering remote item {}...", url));
+                        match local_status {
+                            SyncStatus::NotSynced => {
+                                progress.item_error(&url, IssueKind::UrlReuse);
+                                continue;
+                            },
+                            SyncStatus::Synced(local_tag) => {
+                                if &remote_tag != local_tag {
+                                    // This has been modified on the remote
+                                    progress.debug(&format!("*   {} is a remote change", url));
+                                    remote_changes.insert(url);
+                                }
+                            },
+                            SyncStatus::LocallyModified(local_tag) => {
+                                if &remote_tag == local_tag {
+                                    // This has been changed locally
+                                    progress.debug(&format!("*   {} is a local change", url));
+                                    local_changes.insert(url);
+                                } else if authority == ConflictAuthority::Local {
+                                    progress.info(&format!("Conflict: task {} has been modified in both sources. Using the local version.", url));
+                                    progress.debug(&format!("*   {} is considered a local change", url));
+                                    conflicts.push((url.clone(), ConflictKind::ModifiedOnBothSides));
+                                    overwritten_tags.insert(url.clone(), remote_tag);
+                                    local_changes.insert(url);
+                                } else {
+                                    progress.info(&format!("Conflict: task {} has been modified in both sources. Using the remote version.", url));
+                                    progress.debug(&format!("*   {} is considered a remote change", url));
+                                    conflicts.push((url.clone(), ConflictKind::ModifiedOnBothSides));
+                                    remote_changes.insert(url);
+                                }
+                            },
+                            SyncStatus::LocallyDeleted(local_tag) => {
+                                if &remote_tag == local_tag {
+                                    // This has been locally deleted
+                                    progress.debug(&format!("*   {} is a local deletion", url));
+                                    local_del.insert(url);
+                                } else if authority == ConflictAuthority::Local {
+                                    progress.info(&format!("Conflict: task {} has been locally deleted and remotely modified. Deleting it from the remote source.", url));
+                                    progress.debug(&format!("*   {} is considered a local deletion", url));
+                                    conflicts.push((url.clone(), ConflictKind::LocallyDeletedRemotelyModified));
+                                    local_del.insert(url);
+                                } else {
+                                    progress.info(&format!("Conflict: task {} has been locally deleted and remotely modified. Reverting to the remote version.", url));
+                                    progress.debug(&format!("*   {} is a considered a remote change", url));
+                                    conflicts.push((url.clone(), ConflictKind::LocallyDeletedRemotelyModified));
+                                    remote_changes.insert(url);
+                                }
+                            },
+                        }
+                    },
+                    EitherOrBoth::Right((url, local_status)) => {
+                        // This local item is not on the remote
+                        progress.trace(&format!("##### Considering local item {}...", url));
+                        let url = url.clone();
+                        match local_status {
+                            SyncStatus::Synced(_) if out_of_window.contains(&url) => {
+                                progress.trace(&format!("#   {} is out of the sync window", url));
+                            },
+                            SyncStatus::Synced(_) => {
+                                // This item has been removed from the remote
+                                progress.debug(&format!("#   {} is a deletion from the server", url));
+                                remote_del.insert(url);
+                            },
+                            SyncStatus::NotSynced => {
+                                // This item has just been locally created
+                                progress.debug(&format!("#   {} has been locally created", url));
+                                local_additions.insert(url);
+                            },
+                            SyncStatus::LocallyDeleted(_) => {
+                                // This item has been deleted from both sources
+                                progress.debug(&format!("#   {} has been deleted from both sources", url));
+                                deleted_on_both_sides.insert(url.clone());
+                                remote_del.insert(url);
+                            },
+                            SyncStatus::LocallyModified(_) => {
+                                conflicts.push((url.clone(), ConflictKind::LocallyModifiedRemotelyDeleted));
+                                if authority == ConflictAuthority::Local {
+                                    progress.info(&format!("Conflict: item {} has been deleted from the server and locally modified. Adding it back to the server", url));
+                                    local_additions.insert(url);
+                                } else {
+                                    progress.info(&format!("Conflict: item {} has been deleted from the server and locally modified. Deleting the local copy", url));
+                                    remote_del.insert(url);
+                                }
+                            },
+                        }
+                    },
+                }
+            }
         }
 
-        // Also iterate on the local tasks that are not on the remote
-        for url in local_items_to_handle {
-            progress.trace(&format!("##### Considering local item {}...", url));
-            let local_item = match cal_local.get_item_by_url(&url).await {
-                None => {
-                    progress.error(&format!("Inconsistent state: missing task {} from the local tasks", url));
-                    continue;
-                },
-                Some(item) => item,
-            };
+        // The local versions of the conflicting items, before this sync changes them
+        let conflicts: Vec<(Url, ConflictKind, Option<String>)> = match conflicts.is_empty() {
+            true => Vec::new(),
+            false => {
+                let conflicting_urls: HashSet<&Url> = conflicts.iter().map(|(url, _)| url).collect();
+                let mut local_summaries: HashMap<Url, String> = cal_local.read().unwrap().iter_items()
+                    .filter(|item| conflicting_urls.contains(item.url()))
+                    .map(|item| (item.url().clone(), item.name().to_string()))
+                    .collect();
+                conflicts.into_iter()
+                    .map(|(url, kind)| {
+                        let local_summary = local_summaries.remove(&url);
+                        (url, kind, local_summary)
+                    })
+                    .collect()
+            },
+        };
 
-            match local_item.sync_status() {
-                SyncStatus::Synced(_) => {
-                    // This item has been removed from the remote
-                    progress.debug(&format!("#   {} is a deletion from the server", url));
-                    remote_del.insert(url);
-                },
-                SyncStatus::NotSynced => {
-                    // This item has just been locally created
-                    progress.debug(&format!("#   {} has been locally created", url));
-                    local_additions.insert(url);
-                },
-                SyncStatus::LocallyDeleted(_) => {
-                    // This item has been deleted from both sources
-                    progress.debug(&format!("#   {} has been deleted from both sources", url));
-                    remote_del.insert(url);
-                },
-                SyncStatus::LocallyModified(_) => {
-                    progress.info(&format!("Conflict: item {} has been deleted from the server and locally modified. Deleting the local copy", url));
-                    remote_del.insert(url);
-                },
+        // Only keep the changes the sync direction of this calendar allows
+        if overrides.pushes() == false {
+            for url in local_del.drain().chain(local_changes.drain()).chain(local_additions.drain()) {
+                progress.debug(&format!("*   Not pushing {}, calendar {} is pull-only", url, cal_name));
+            }
+        }
+        if overrides.pulls() == false {
+            for url in remote_additions.drain().chain(remote_changes.drain()) {
+                progress.debug(&format!("*   Not pulling {}, calendar {} is push-only", url, cal_name));
+            }
+            remote_del.retain(|url| deleted_on_both_sides.contains(url));
+        }
+
+        // Items the server has asked not to push again yet are left for a later sync
+        {
+            let now = crate::deterministic::now();
+            let cal_local = cal_local.read().unwrap();
+            for pushes in [&mut local_del, &mut local_changes, &mut local_additions] {
+                pushes.retain(|url| match cal_local.retry_not_before(url).filter(|date| *date > now) {
+                    None => true,
+                    Some(date) => {
+                        progress.debug(&format!("*   Not pushing {} before {}, as requested by the server", url, date));
+                        false
+                    },
+                });
             }
         }
 
+        let new_plan = resumed.is_none();
+        let mut journal = match resumed {
+            Some(journal) => {
+                progress.info(&format!("Resuming the interrupted sync of calendar {} ({}/{} items done already)", cal_name, journal.done_count(), journal.len()));
+                journal
+            },
+            None => {
+                let plan = local_del.into_iter().map(|url| (url, PlannedChange::LocalDeletion))
+                    .chain(remote_del.into_iter().map(|url| (url, PlannedChange::RemoteDeletion)))
+                    .chain(remote_additions.into_iter().map(|url| (url, PlannedChange::RemoteAddition)))
+                    .chain(remote_changes.into_iter().map(|url| (url, PlannedChange::RemoteChange)))
+                    .chain(local_additions.into_iter().map(|url| (url, PlannedChange::LocalAddition)))
+                    .chain(local_changes.into_iter().map(|url| (url, PlannedChange::LocalChange)))
+                    .collect();
+                SyncJournal::new(plan, overwritten_tags)
+            },
+        };
+
+        progress.set_total_operations(Some(journal.len() - journal.done_count()));
+        progress.feedback(SyncEvent::CalendarPlanned{
+            calendar: cal_name.clone(),
+            total_operations: journal.len() - journal.done_count(),
+        });
+
+        // Pushing changes changes the version of the remote calendar, that is only known until then
+        let pushes_changes = [PlannedChange::LocalDeletion, PlannedChange::LocalAddition, PlannedChange::LocalChange].iter()
+            .any(|change| journal.pending(*change).is_empty() == false);
 
         // Step 2 - commit changes
         progress.trace("Committing changes...");
-        for url_del in local_del {
+        // Calendars are only locked for one operation at a time, so that other threads can still use them while waiting for the server
+        if journal.is_empty() == false {
+            if new_plan {
+                // Keep the local items this sync is about to change, so that it can be undone
+                let mut cal_local = cal_local.write().unwrap();
+                let previous = cal_local.iter_items()
+                    .filter(|item| journal.planned_change(item.url()).is_some())
+                    .map(|item| (item.url().clone(), item.clone()))
+                    .collect();
+                cal_local.set_rollback_point(Some(SyncRollback::new(&journal, previous)));
+            }
+            Self::checkpoint(&cal_local, Some(&journal), progress);
+        }
+
+        for url_del in journal.pending(PlannedChange::LocalDeletion) {
             progress.debug(&format!("> Pushing local deletion {} to the server", url_del));
             progress.increment_counter(1);
             progress.feedback(SyncEvent::InProgress{
                 calendar: cal_name.clone(),
                 items_done_already: progress.counter(),
-                details: Self::item_name(&cal_local, &url_del).await,
+                total_operations: progress.total_operations(),
+                step: SyncStep::DeletingRemoteItem{ item_name: Self::item_name(&cal_local, &url_del) },
             });
 
-            match cal_remote.delete_item(&url_del).await {
+            let mut detached_remote = cal_remote.read().unwrap().detached();
+            let result = detached_remote.delete_item(&url_del).await
+                .and_then(|()| cal_remote.write().unwrap().record_changes(detached_remote, std::slice::from_ref(&url_del)));
+            match result {
                 Err(err) => {
-                    progress.warn(&format!("Unable to delete remote item {}: {}", url_del, err));
+                    progress.item_warn(&url_del, IssueKind::RemoteItemDeletionFailed{ error: err.to_string() });
+                    cal_local.write().unwrap().set_retry_not_before(&url_del, Self::retry_not_before(&*err));
                 },
                 Ok(()) => {
+                    progress.items_synced(ItemSync::DeletedRemotely, 1);
+                    cal_local.write().unwrap().set_retry_not_before(&url_del, None);
                     // Change the local copy from "marked to deletion" to "actually deleted"
-                    if let Err(err) = cal_local.immediately_delete_item(&url_del).await {
-                        progress.error(&format!("Unable to permanently delete local item {}: {}", url_del, err));
+                    if let Err(err) = write_locked(&cal_local, &url_del, |cal, url| cal.immediately_delete_item(url)) {
+                        progress.item_error(&url_del, IssueKind::LocalItemDeletionFailed{ error: err.to_string() });
                     }
                 },
             }
+            journal.mark_done(&url_del);
         }
 
+        let remote_del = journal.pending(PlannedChange::RemoteDeletion);
+        if remote_del.is_empty() == false {
+            Self::checkpoint(&cal_local, Some(&journal), progress);
+        }
         for url_del in remote_del {
             progress.debug(&format!("> Applying remote deletion {} locally", url_del));
             progress.increment_counter(1);
             progress.feedback(SyncEvent::InProgress{
                 calendar: cal_name.clone(),
                 items_done_already: progress.counter(),
-                details: Self::item_name(&cal_local, &url_del).await,
+                total_operations: progress.total_operations(),
+                step: SyncStep::DeletingLocalItem{ item_name: Self::item_name(&cal_local, &url_del) },
             });
-            if let Err(err) = cal_local.immediately_delete_item(&url_del).await {
-                progress.warn(&format!("Unable to delete local item {}: {}", url_del, err));
+            match write_locked(&cal_local, &url_del, |cal, url| cal.immediately_delete_item(url)) {
+                Err(err) => progress.item_warn(&url_del, IssueKind::LocalItemDeletionFailed{ error: err.to_string() }),
+                Ok(()) => progress.items_synced(ItemSync::DeletedLocally, 1),
             }
+            journal.mark_done(&url_del);
         }
 
         Self::apply_remote_additions(
-            remote_additions,
-            &mut *cal_local,
-            &mut *cal_remote,
+            journal.pending(PlannedChange::RemoteAddition),
+            download_batch_size,
+            &cal_local,
+            &cal_remote,
+            &mut journal,
             progress,
-            &cal_name
         ).await;
 
         Self::apply_remote_changes(
-            remote_changes,
-            &mut *cal_local,
-            &mut *cal_remote,
+            journal.pending(PlannedChange::RemoteChange),
+            download_batch_size,
+            config.skip_identical_content,
+            &cal_local,
+            &cal_remote,
+            &mut journal,
             progress,
-            &cal_name
         ).await;
 
 
-        for url_add in local_additions {
+        let mut additions = Vec::new();
+        for url_add in journal.pending(PlannedChange::LocalAddition) {
             progress.debug(&format!("> Pushing local addition {} to the server", url_add));
-            progress.increment_counter(1);
-            progress.feedback(SyncEvent::InProgress{
-                calendar: cal_name.clone(),
-                items_done_already: progress.counter(),
-                details: Self::item_name(&cal_local, &url_add).await,
-            });
-            match cal_local.get_item_by_url_mut(&url_add).await {
+            let item = read_locked(&cal_local, &url_add, |cal, url| Box::pin(async move { cal.get_item_by_url(url).await.cloned() }));
+            match item {
                 None => {
-                    progress.error(&format!("Inconsistency: created item {} has been marked for upload but is locally missing", url_add));
+                    progress.item_error(&url_add, IssueKind::LocalItemMissing);
                     continue;
                 },
-                Some(item) => {
-                    match cal_remote.add_item(item.clone()).await {
-                        Err(err) => progress.error(&format!("Unable to add item {} to remote calendar: {}", url_add, err)),
-                        Ok(new_ss) => {
-                            // Update local sync status
-                            item.set_sync_status(new_ss);
-                        },
-                    }
-                },
+                Some(item) => additions.push(item),
             };
         }
+        Self::push_local_items(BatchUploadType::LocalAdditions, additions, &cal_local, &cal_remote, config.upload_concurrency, &mut journal, progress).await;
 
-        for url_change in local_changes {
+        let mut changes = Vec::new();
+        for url_change in journal.pending(PlannedChange::LocalChange) {
             progress.debug(&format!("> Pushing local change {} to the server", url_change));
-            progress.increment_counter(1);
-            progress.feedback(SyncEvent::InProgress{
-                calendar: cal_name.clone(),
-                items_done_already: progress.counter(),
-                details: Self::item_name(&cal_local, &url_change).await,
-            });
-            match cal_local.get_item_by_url_mut(&url_change).await {
+            let overwritten_tag = journal.overwritten_tag(&url_change).cloned();
+            let item = write_locked(&cal_local, &url_change, |cal, url| Box::pin(async move {
+                let item = cal.get_item_by_url_mut(url).await?;
+                if let Some(remote_tag) = overwritten_tag {
+                    item.set_sync_status(SyncStatus::LocallyModified(remote_tag));
+                }
+                Some(item.clone())
+            }));
+            match item {
                 None => {
-                    progress.error(&format!("Inconsistency: modified item {} has been marked for upload but is locally missing", url_change));
+                    progress.item_error(&url_change, IssueKind::LocalItemMissing);
                     continue;
                 },
-                Some(item) => {
-                    match cal_remote.update_item(item.clone()).await {
-                        Err(err) => progress.error(&format!("Unable to update item {} in remote calendar: {}", url_change, err)),
-                        Ok(new_ss) => {
-                            // Update local sync status
-                            item.set_sync_status(new_ss);
-                        },
-                    };
-                }
+                Some(item) => changes.push(item),
             };
         }
+        Self::push_local_items(BatchUploadType::LocalChanges, changes, &cal_local, &cal_remote, config.upload_concurrency, &mut journal, progress).await;
 
+        let records = read_locked(&cal_local, &journal, |cal, journal| Box::pin(async move { Self::conflict_records(cal, conflicts, journal).await }));
+        if !records.is_empty() {
+            cal_local.write().unwrap().record_conflicts(records);
+        }
+
+        // The sync of this calendar is over, there is nothing left to resume
+        if cal_local.read().unwrap().sync_journal().is_some() {
+            Self::checkpoint(&cal_local, None, progress);
+        }
+
+        // Both calendars are now identical, unless something went wrong (or only part of the remote calendar has been considered)
+        let complete = pushes_changes == false && progress.issues().len() == issues_before && config.sync_window_days.is_none();
+        cal_local.write().unwrap().set_last_remote_version(if complete { remote_version } else { None });
         Ok(())
     }
 
+    /// Save the progress of the sync of a local calendar (see [`SyncJournal`]), or forget about it once the sync is over (`None`)
+    fn checkpoint(cal_local: &RwLock<T>, journal: Option<&SyncJournal>, progress: &mut SyncProgress) {
+        let result = cal_local.write().unwrap().set_sync_journal(journal.cloned());
+        if let Err(err) = result {
+            progress.warn(IssueKind::JournalSaveFailed{ error: err.to_string() });
+        }
+    }
+
 
-    async fn item_name(cal: &T, url: &Url) -> String {
-        cal.get_item_by_url(url).await.map(|item| item.name()).unwrap_or_default().to_string()
+    /// Upload local items to the remote calendar (several of them at the same time if `concurrency` allows it), and update their local sync statuses.
+    ///
+    /// The progress is saved to `journal` after every round of concurrent uploads
+    async fn push_local_items(
+        upload_type: BatchUploadType,
+        items: Vec<Item>,
+        cal_local: &RwLock<T>,
+        cal_remote: &RwLock<U>,
+        concurrency: usize,
+        journal: &mut SyncJournal,
+        progress: &mut SyncProgress,
+    ) {
+        let cal_name = cal_local.read().unwrap().name().to_string();
+        let mut items = items.into_iter().peekable();
+        while items.peek().is_some() {
+            let round: Vec<Item> = items.by_ref().take(concurrency.max(1)).collect();
+            for item in &round {
+                progress.increment_counter(1);
+                progress.feedback(SyncEvent::InProgress{
+                    calendar: cal_name.clone(),
+                    items_done_already: progress.counter(),
+                    total_operations: progress.total_operations(),
+                    step: match upload_type {
+                        BatchUploadType::LocalAdditions => SyncStep::UploadingNewItem{ item_name: item.name().to_string() },
+                        BatchUploadType::LocalChanges => SyncStep::UploadingChangedItem{ item_name: item.name().to_string() },
+                    },
+                });
+            }
+
+            let uploaded = round.clone();
+            let mut detached_remote = cal_remote.read().unwrap().detached();
+            let mut results: Vec<_> = match upload_type {
+                BatchUploadType::LocalAdditions => detached_remote.add_items_concurrently(round, concurrency).await,
+                BatchUploadType::LocalChanges => detached_remote.update_items(round, concurrency).await,
+            }.into_iter().map(|result| result.map_err(|err| (err.to_string(), Self::retry_not_before(&*err)))).collect();
+
+            // The remote calendar is only locked once the uploads are over, to record them
+            let urls: Vec<Url> = uploaded.iter().map(|item| item.url().clone()).collect();
+            if let Err(err) = cal_remote.write().unwrap().record_changes(detached_remote, &urls) {
+                let err = err.to_string();
+                results = results.into_iter().map(|result| result.and_then(|_| Err((err.clone(), None)))).collect();
+            }
+
+            for (uploaded_item, result) in uploaded.iter().zip(results) {
+                let url = uploaded_item.url();
+                match result {
+                    Err((err, None)) => progress.item_error(url, IssueKind::UploadFailed{ new_item: matches!(upload_type, BatchUploadType::LocalAdditions), error: err }),
+                    Err((err, Some(not_before))) => {
+                        progress.item_warn(url, IssueKind::UploadFailed{ new_item: matches!(upload_type, BatchUploadType::LocalAdditions), error: err });
+                        cal_local.write().unwrap().set_retry_not_before(url, Some(not_before));
+                    },
+                    Ok(new_ss) => {
+                        progress.items_synced(ItemSync::Uploaded, 1);
+                        cal_local.write().unwrap().set_retry_not_before(url, None);
+                        // Update local sync status
+                        let updated = write_locked(cal_local, uploaded_item, |cal, uploaded_item| Box::pin(async move {
+                            let item = cal.get_item_by_url_mut(uploaded_item.url()).await?;
+                            let new_ss = status_after_upload(uploaded_item, item, new_ss);
+                            item.set_sync_status(new_ss);
+                            Some(())
+                        }));
+                        if updated.is_none() {
+                            progress.item_error(url, IssueKind::LocalItemMissing);
+                        }
+                    },
+                }
+                journal.mark_done(url);
+            }
+            Self::checkpoint(cal_local, Some(journal), progress);
+        }
+    }
+
+    /// The date before which the server has asked not to send a request again, in case it has failed with a [`RetryLaterError`]
+    fn retry_not_before(err: &(dyn Error + 'static)) -> Option<DateTime<Utc>> {
+        err.downcast_ref::<RetryLaterError>().map(|err| err.not_before())
+    }
+
+    /// Build the log records of the conflicts of a calendar, once this sync has applied its changes.
+    ///
+    /// Only the conflicts that have actually been resolved are logged, with the source whose version has been kept. \
+    /// Conflicts this sync has left alone (because of the sync direction of the calendar, a failure, or a server that asked to retry later) are found again by the next sync.
+    async fn conflict_records(cal_local: &T, conflicts: Vec<(Url, ConflictKind, Option<String>)>, journal: &SyncJournal) -> Vec<ConflictRecord> {
+        let mut records = Vec::new();
+        for (url, kind, local_summary) in conflicts {
+            let winner = match journal.planned_change(&url) {
+                None => continue,
+                Some(PlannedChange::LocalDeletion) | Some(PlannedChange::LocalAddition) | Some(PlannedChange::LocalChange) => ConflictAuthority::Local,
+                Some(PlannedChange::RemoteDeletion) | Some(PlannedChange::RemoteAddition) | Some(PlannedChange::RemoteChange) => ConflictAuthority::Remote,
+            };
+            // A resolved conflict leaves an item that is either gone, or identical in both sources
+            let remote_summary = match cal_local.get_item_by_url(&url).await {
+                None => None,
+                Some(item) => match item.sync_status() {
+                    SyncStatus::Synced(_) if winner == ConflictAuthority::Remote => Some(item.name().to_string()),
+                    SyncStatus::Synced(_) => None,
+                    _ => continue,
+                },
+            };
+            records.push(ConflictRecord::new(url, kind, local_summary, remote_summary, winner));
+        }
+        records
+    }
+
+    /// In case the local copy of `new_item` has the very same content, give it the sync status of `new_item` and return `true`
+    async fn mark_if_identical(cal_local: &mut T, new_item: &Item) -> bool {
+        let local_item = match cal_local.get_item_by_url_mut(new_item.url()).await {
+            None => return false,
+            Some(item) => item,
+        };
+        match (ical_content(local_item), ical_content(new_item)) {
+            (Some(local_content), Some(new_content)) if local_content == new_content => {
+                local_item.set_sync_status(new_item.sync_status().clone());
+                true
+            },
+            _ => false,
+        }
+    }
+
+    fn item_name(cal: &RwLock<T>, url: &Url) -> String {
+        read_locked(cal, url, |cal, url| Box::pin(async move {
+            cal.get_item_by_url(url).await.map(|item| item.name()).unwrap_or_default().to_string()
+        }))
     }
 
     async fn apply_remote_additions(
         mut remote_additions: HashSet<Url>,
-        cal_local: &mut T,
-        cal_remote: &mut U,
+        batch_size: usize,
+        cal_local: &RwLock<T>,
+        cal_remote: &RwLock<U>,
+        journal: &mut SyncJournal,
         progress: &mut SyncProgress,
-        cal_name: &str
     ) {
-        for batch in remote_additions.drain().chunks(DOWNLOAD_BATCH_SIZE).into_iter() {
-            Self::fetch_batch_and_apply(BatchDownloadType::RemoteAdditions, batch, cal_local, cal_remote, progress, cal_name).await;
+        for batch in remote_additions.drain().chunks(batch_size.max(1)).into_iter() {
+            Self::fetch_batch_and_apply(BatchDownloadType::RemoteAdditions, batch, false, cal_local, cal_remote, journal, progress).await;
         }
     }
 
     async fn apply_remote_changes(
         mut remote_changes: HashSet<Url>,
-        cal_local: &mut T,
-        cal_remote: &mut U,
+        batch_size: usize,
+        skip_identical_content: bool,
+        cal_local: &RwLock<T>,
+        cal_remote: &RwLock<U>,
+        journal: &mut SyncJournal,
         progress: &mut SyncProgress,
-        cal_name: &str
     ) {
-        for batch in remote_changes.drain().chunks(DOWNLOAD_BATCH_SIZE).into_iter() {
-            Self::fetch_batch_and_apply(BatchDownloadType::RemoteChanges, batch, cal_local, cal_remote, progress, cal_name).await;
+        for batch in remote_changes.drain().chunks(batch_size.max(1)).into_iter() {
+            Self::fetch_batch_and_apply(BatchDownloadType::RemoteChanges, batch, skip_identical_content, cal_local, cal_remote, journal, progress).await;
         }
     }
 
+    /// Download a batch of items, and apply them locally. The progress is saved to `journal` afterwards.
+    ///
+    /// With `skip_identical_content`, items that are identical to their local copies only get their sync statuses updated
     async fn fetch_batch_and_apply<I: Iterator<Item = Url>>(
         batch_type: BatchDownloadType,
         remote_additions: I,
-        cal_local: &mut T,
-        cal_remote: &mut U,
+        skip_identical_content: bool,
+        cal_local: &RwLock<T>,
+        cal_remote: &RwLock<U>,
+        journal: &mut SyncJournal,
         progress: &mut SyncProgress,
-        cal_name: &str
     ) {
         progress.debug(&format!("> Applying a batch of {} locally", batch_type) /* too bad Chunks does not implement ExactSizeIterator, that could provide useful debug info. See https://github.com/rust-itertools/itertools/issues/171 */);
 
-        let list_of_additions: Vec<Url> = remote_additions.map(|url| url.clone()).collect();
-        match cal_remote.get_items_by_url(&list_of_additions).await {
+        let list_of_additions: Vec<Url> = remote_additions.collect();
+        let detached_remote = cal_remote.read().unwrap().detached();
+        match detached_remote.get_items_by_url(&list_of_additions).await {
             Err(err) => {
-                progress.warn(&format!("Unable to get the batch of {} {:?}: {}. Skipping them.", batch_type, list_of_additions, err));
+                progress.warn(IssueKind::DownloadFailed{ count: list_of_additions.len(), error: err.to_string() });
             },
             Ok(items) => {
+                if items.len() < list_of_additions.len() {
+                    // These items will be requested again on the next sync
+                    progress.warn(IssueKind::IncompleteDownload{ received: items.len(), requested: list_of_additions.len() });
+                }
                 for item in items {
                     match item {
                         None => {
-                            progress.error(&format!("Inconsistency: an item from the batch has vanished from the remote end"));
+                            progress.error(IssueKind::DownloadedItemMissing);
                             continue;
                         },
                         Some(new_item) => {
+                            if skip_identical_content && write_locked(cal_local, &new_item, |cal, new_item| Box::pin(async move { Self::mark_if_identical(cal, new_item).await })) {
+                                progress.debug(&format!("> Item {} has not actually changed, only its version tag has been updated", new_item.url()));
+                                progress.items_synced(ItemSync::Downloaded, 1);
+                                continue;
+                            }
                             let local_update_result = match batch_type {
-                                BatchDownloadType::RemoteAdditions => cal_local.add_item(new_item.clone()).await,
-                                BatchDownloadType::RemoteChanges => cal_local.update_item(new_item.clone()).await,
+                                BatchDownloadType::RemoteAdditions => write_locked(cal_local, &(), |cal, _| cal.add_item(new_item.clone())),
+                                BatchDownloadType::RemoteChanges => write_locked(cal_local, &(), |cal, _| cal.update_item(new_item.clone())),
                             };
-                            if let Err(err) = local_update_result {
-                                progress.error(&format!("Not able to add item {} to local calendar: {}", new_item.url(), err));
+                            match local_update_result {
+                                Err(err) => progress.item_error(new_item.url(), IssueKind::LocalItemWriteFailed{ error: err.to_string() }),
+                                Ok(_) => progress.items_synced(ItemSync::Downloaded, 1),
                             }
                         },
                     }
                 }
 
                 // Notifying every item at the same time would not make sense. Let's notify only one of them
-                let one_item_name = match list_of_additions.get(0) {
-                    Some(url) => Self::item_name(&cal_local, &url).await,
-                    None => String::from("<unable to get the name of the first batched item>"),
+                let first_item_name = match list_of_additions.first() {
+                    Some(url) => Some(Self::item_name(cal_local, url)),
+                    None => None,
                 };
                 progress.increment_counter(list_of_additions.len());
                 progress.feedback(SyncEvent::InProgress{
-                    calendar: cal_name.to_string(),
+                    calendar: cal_local.read().unwrap().name().to_string(),
                     items_done_already: progress.counter(),
-                    details: one_item_name,
+                    total_operations: progress.total_operations(),
+                    step: SyncStep::ItemsDownloaded{ first_item_name, count: list_of_additions.len() },
                 });
             },
         }
+
+        // Items that could not be downloaded are left to the next sync
+        for url in &list_of_additions {
+            journal.mark_done(url);
+        }
+        Self::checkpoint(cal_local, Some(journal), progress);
     }
 }
 
+#[cfg(feature = "cache")]
+impl Provider<Cache, CachedCalendar, Cache, CachedCalendar> {
+    /// Create a provider that has no remote source, for apps that work offline (or until the user has configured an account).
+    ///
+    /// The data only lives in `local`. Syncing this provider only marks the local changes as synced.
+    pub fn new_local_only(local: Cache) -> Self {
+        Self::new(local.last_synced_copy(), local)
+    }
 
-async fn get_or_insert_counterpart_calendar<H, N, I>(haystack_descr: &str, haystack: &mut H, cal_url: &Url, needle: Arc<Mutex<N>>)
-    -> Result<Arc<Mutex<I>>, Box<dyn Error>>
+    /// Create a provider whose remote source is a folder rather than a CalDAV server.
+    ///
+    /// Several devices (or apps) that sync their own local caches with the same folder will see each other's changes.
+    /// Sharing the folder itself between devices is up to the user (e.g. using Syncthing or a network share). See [`Cache::new_as_remote`]
+    pub fn new_folder_sync(local: Cache, remote_folder: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::new(Cache::new_as_remote(remote_folder)?, local))
+    }
+}
+
+#[cfg(all(feature = "client", feature = "cache"))]
+impl Provider<Cache, CachedCalendar, Client, RemoteCalendar> {
+    /// Create a provider that syncs a CalDAV server with a cache saved in `cache_folder`.
+    ///
+    /// The cache is loaded from `cache_folder` if it has been saved there, and is initialized empty otherwise (see [`Cache::from_folder_or_new`]). \
+    /// `config` is used both by the [`Client`] and by the syncs of this provider.
+    pub fn connect<S: AsRef<str>, T: ToString, U: ToString>(url: S, username: T, password: U, cache_folder: &Path, config: Config) -> Result<Self, Box<dyn Error>> {
+        let cache = Cache::from_folder_or_new(cache_folder)?;
+        let client = Client::new_with_config(url, username, password, config.clone())?;
+        Ok(Self::new(client, cache).with_config(config))
+    }
+}
+
+
+/// The iCal representation of an item (which does not depend on its sync status)
+fn ical_content(item: &Item) -> Option<String> {
+    crate::ical::build_from(item).ok()
+}
+
+/// The sync status `local_item` gets once `uploaded_item` has been uploaded, and the server has returned `new_status`.
+///
+/// The local item may have been changed (or deleted) while it was being uploaded. It must then stay a local change, against the version tag of what has been uploaded
+fn status_after_upload(uploaded_item: &Item, local_item: &Item, new_status: SyncStatus) -> SyncStatus {
+    match (new_status, local_item.sync_status()) {
+        (SyncStatus::Synced(tag), SyncStatus::LocallyDeleted(_)) => SyncStatus::LocallyDeleted(tag),
+        (SyncStatus::Synced(tag), _) if ical_content(local_item) != ical_content(uploaded_item) => SyncStatus::LocallyModified(tag),
+        (new_status, _) => new_status,
+    }
+}
+
+async fn get_or_insert_counterpart_calendar<H, N, I>(haystack_descr: &str, haystack: &H, cal_url: &Url, needle: Arc<RwLock<N>>)
+    -> Result<Arc<RwLock<I>>, Box<dyn Error>>
 where
     H: CalDavSource<I>,
     I: BaseCalendar,
@@ -497,19 +1367,705 @@ where
         }
 
         // This calendar does not exist locally yet, let's add it
-        log::debug!("Adding a {} calendar {}", haystack_descr, cal_url);
-        let src = needle.lock().unwrap();
-        let name = src.name().to_string();
-        let supported_comps = src.supported_components();
-        let color = src.color();
+        log::debug!(target: crate::logging::PROVIDER, "Adding a {} calendar {}", haystack_descr, cal_url);
+        let (name, supported_comps, color) = {
+            let src = needle.read().unwrap();
+            (src.name().to_string(), src.supported_components(), src.color().cloned())
+        };
         if let Err(err) = haystack.create_calendar(
             cal_url.clone(),
             name,
             supported_comps,
-            color.cloned(),
+            color,
         ).await{
             return Err(err);
         }
     }
 }
 
+
+
+#[cfg(all(test, feature = "cache"))]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    use crate::calendar::SupportedComponents;
+    use crate::item::Item;
+    use crate::task::Task;
+    use crate::test_utils::TaskBuilder;
+
+    fn rename(cal: &mut CachedCalendar, url: &Url, new_name: &str) {
+        let mut item = cal.get_item_by_url_sync(url).unwrap().clone();
+        item.unwrap_task_mut().set_name(new_name.to_string());
+        cal.update_item_sync(item).unwrap();
+    }
+
+    fn name_of(cal: &Arc<RwLock<CachedCalendar>>, url: &Url) -> Option<String> {
+        cal.read().unwrap().get_item_by_url_sync(url).map(|item| item.name().to_string())
+    }
+
+    #[tokio::test]
+    async fn test_local_conflict_authority() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let root = PathBuf::from(String::from("test_cache/local_authority_test"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let cal_url = Url::parse("https://caldav.com/backup/").unwrap();
+        let mut provider = crate::LocalProvider::new(Cache::new_in_memory_remote(), Cache::new(&root))
+            .with_conflict_authority(ConflictAuthority::Local);
+        assert_eq!(provider.conflict_authority(), ConflictAuthority::Local);
+
+        let local_cal = provider.local().create_calendar(cal_url.clone(), String::from("Backup"), SupportedComponents::TODO, None).await.unwrap();
+        let modified = Task::new(String::from("Modified"), false, &cal_url);
+        let deleted = Task::new(String::from("Deleted"), false, &cal_url);
+        let (modified_url, deleted_url) = (modified.url().clone(), deleted.url().clone());
+        local_cal.write().unwrap().add_item_sync(Item::Task(modified)).unwrap();
+        local_cal.write().unwrap().add_item_sync(Item::Task(deleted)).unwrap();
+        assert!(provider.sync().await);
+        let remote_cal = provider.remote().get_calendar_sync(&cal_url).unwrap();
+
+        // Conflicting changes on both sides
+        rename(&mut local_cal.write().unwrap(), &modified_url, "Local name");
+        rename(&mut remote_cal.write().unwrap(), &modified_url, "Remote name");
+        rename(&mut local_cal.write().unwrap(), &deleted_url, "Still needed");
+        remote_cal.write().unwrap().immediately_delete_item_sync(&deleted_url).unwrap();
+
+        assert!(provider.sync().await);
+        assert_eq!(name_of(&remote_cal, &modified_url).as_deref(), Some("Local name"));
+        assert_eq!(name_of(&local_cal, &modified_url).as_deref(), Some("Local name"));
+        assert_eq!(name_of(&remote_cal, &deleted_url).as_deref(), Some("Still needed"));
+
+        // Local deletions win over remote changes
+        local_cal.write().unwrap().mark_for_deletion_sync(&modified_url).unwrap();
+        rename(&mut remote_cal.write().unwrap(), &modified_url, "Remote name");
+        assert!(provider.sync().await);
+        assert!(name_of(&remote_cal, &modified_url).is_none());
+        assert!(name_of(&local_cal, &modified_url).is_none());
+
+        // The sync is stable
+        assert!(provider.sync().await);
+        assert_eq!(local_cal.read().unwrap().get_item_urls_sync().unwrap(), remote_cal.read().unwrap().get_item_urls_sync().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_conflict_log() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let root = PathBuf::from(String::from("test_cache/conflict_log_test"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let cal_url = Url::parse("https://caldav.com/conflicts/").unwrap();
+        let mut provider = crate::LocalProvider::new(Cache::new_in_memory_remote(), Cache::new(&root));
+        let local_cal = provider.local().create_calendar(cal_url.clone(), String::from("Conflicts"), SupportedComponents::TODO, None).await.unwrap();
+        let modified = Task::new(String::from("Modified"), false, &cal_url);
+        let deleted = Task::new(String::from("Deleted"), false, &cal_url);
+        let (modified_url, deleted_url) = (modified.url().clone(), deleted.url().clone());
+        local_cal.write().unwrap().add_item_sync(Item::Task(modified)).unwrap();
+        local_cal.write().unwrap().add_item_sync(Item::Task(deleted)).unwrap();
+        assert!(provider.sync().await);
+        assert!(provider.local().conflict_log(None).is_empty());
+        let remote_cal = provider.remote().get_calendar_sync(&cal_url).unwrap();
+
+        rename(&mut local_cal.write().unwrap(), &modified_url, "Local name");
+        rename(&mut remote_cal.write().unwrap(), &modified_url, "Remote name");
+        rename(&mut local_cal.write().unwrap(), &deleted_url, "Still needed");
+        remote_cal.write().unwrap().immediately_delete_item_sync(&deleted_url).unwrap();
+        assert!(provider.sync().await);
+
+        let log = provider.local().conflict_log(Some(&modified_url));
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].kind(), ConflictKind::ModifiedOnBothSides);
+        assert_eq!(log[0].local_summary(), Some("Local name"));
+        assert_eq!(log[0].remote_summary(), Some("Remote name"));
+        assert_eq!(log[0].winner(), ConflictAuthority::Remote);
+
+        let log = provider.local().conflict_log(Some(&deleted_url));
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].kind(), ConflictKind::LocallyModifiedRemotelyDeleted);
+        assert_eq!(log[0].local_summary(), Some("Still needed"));
+        assert!(log[0].remote_summary().is_none());
+
+        // The log is saved along with the calendar, and syncs without conflicts leave it untouched
+        assert!(provider.sync().await);
+        provider.local().save_to_folder().unwrap();
+        assert_eq!(Cache::from_folder(&root).unwrap().conflict_log(None), provider.local().conflict_log(None));
+        assert_eq!(provider.local().conflict_log(None).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_conflict_log_skipped_changes() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let root = PathBuf::from(String::from("test_cache/conflict_log_skipped_test"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let cal_url = Url::parse("https://caldav.com/backup/").unwrap();
+        let mut provider = crate::LocalProvider::new(Cache::new_in_memory_remote(), Cache::new(&root));
+        let local_cal = provider.local().create_calendar(cal_url.clone(), String::from("Backup"), SupportedComponents::TODO, None).await.unwrap();
+        let task = Task::new(String::from("Task"), false, &cal_url);
+        let task_url = task.url().clone();
+        local_cal.write().unwrap().add_item_sync(Item::Task(task)).unwrap();
+        assert!(provider.sync().await);
+        let remote_cal = provider.remote().get_calendar_sync(&cal_url).unwrap();
+
+        // The remote version wins, but a push-only calendar does not download it: this conflict is not resolved yet
+        provider.set_calendar_overrides(cal_url.clone(), CalendarOverrides::push_only());
+        rename(&mut local_cal.write().unwrap(), &task_url, "Local name");
+        rename(&mut remote_cal.write().unwrap(), &task_url, "Remote name");
+        assert!(provider.sync().await);
+        assert!(provider.local().conflict_log(None).is_empty());
+
+        // It is logged once a sync actually resolves it
+        provider.remove_calendar_overrides(&cal_url);
+        assert!(provider.sync().await);
+        let log = provider.local().conflict_log(Some(&task_url));
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].local_summary(), Some("Local name"));
+        assert_eq!(log[0].remote_summary(), Some("Remote name"));
+        assert_eq!(log[0].winner(), ConflictAuthority::Remote);
+    }
+
+    #[tokio::test]
+    async fn test_sync_window() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let root = PathBuf::from(String::from("test_cache/sync_window_test"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let cal_url = Url::parse("https://caldav.com/archive/").unwrap();
+        let windowed = Config { sync_window_days: Some(30), ..Config::default() };
+        let mut provider = crate::LocalProvider::new(Cache::new_in_memory_remote(), Cache::new(&root)).with_config(windowed.clone());
+        let remote_cal = provider.remote().create_calendar(cal_url.clone(), String::from("Archive"), SupportedComponents::TODO, None).await.unwrap();
+        let long_ago = crate::deterministic::now() - chrono::Duration::days(400);
+        let old = TaskBuilder::new("Old", &cal_url).completed_on(long_ago).synced().created(long_ago).last_modified(long_ago).build();
+        let recent = Task::new(String::from("Recent"), false, &cal_url);
+        let (old_url, recent_url) = (old.url().clone(), recent.url().clone());
+        remote_cal.write().unwrap().add_item_sync(Item::Task(old)).unwrap();
+        remote_cal.write().unwrap().add_item_sync(Item::Task(recent)).unwrap();
+
+        // Old items are not downloaded...
+        assert!(provider.sync().await);
+        let local_cal = provider.local().get_calendar_sync(&cal_url).unwrap();
+        assert!(name_of(&local_cal, &old_url).is_none());
+        assert_eq!(name_of(&local_cal, &recent_url).as_deref(), Some("Recent"));
+
+        // ...and the ones that have been synced already are not considered deleted
+        let mut provider = provider.with_config(Config::default());
+        assert!(provider.sync().await);
+        assert_eq!(name_of(&local_cal, &old_url).as_deref(), Some("Old"));
+        let mut provider = provider.with_config(windowed);
+        rename(&mut remote_cal.write().unwrap(), &recent_url, "Recent, renamed");
+        assert!(provider.sync().await);
+        assert_eq!(name_of(&local_cal, &old_url).as_deref(), Some("Old"));
+        assert_eq!(name_of(&local_cal, &recent_url).as_deref(), Some("Recent, renamed"));
+
+        // Local changes of old items are pushed, even though the remote source does not list them
+        rename(&mut local_cal.write().unwrap(), &old_url, "Old, renamed");
+        assert!(provider.sync().await);
+        assert_eq!(name_of(&remote_cal, &old_url).as_deref(), Some("Old, renamed"));
+        assert_eq!(local_cal.read().unwrap().get_item_by_url_sync(&old_url).unwrap().sync_status(), remote_cal.read().unwrap().get_item_by_url_sync(&old_url).unwrap().sync_status());
+    }
+
+    #[tokio::test]
+    async fn test_rollback_last_sync() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let root = PathBuf::from(String::from("test_cache/rollback_test"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let cal_url = Url::parse("https://caldav.com/rollback/").unwrap();
+        let mut provider = crate::LocalProvider::new(Cache::new_in_memory_remote(), Cache::new(&root));
+        let local_cal = provider.local().create_calendar(cal_url.clone(), String::from("Rollback"), SupportedComponents::TODO, None).await.unwrap();
+        let conflicting = Task::new(String::from("Conflicting"), false, &cal_url);
+        let conflicting_url = conflicting.url().clone();
+        local_cal.write().unwrap().add_item_sync(Item::Task(conflicting)).unwrap();
+        assert!(provider.sync().await);
+        let remote_cal = provider.remote().get_calendar_sync(&cal_url).unwrap();
+
+        // The remote version wins the conflict, and the local edit is lost...
+        rename(&mut local_cal.write().unwrap(), &conflicting_url, "Local edit");
+        rename(&mut remote_cal.write().unwrap(), &conflicting_url, "Remote edit");
+        let remote_addition = Task::new(String::from("Remote addition"), false, &cal_url);
+        let remote_addition_url = remote_addition.url().clone();
+        remote_cal.write().unwrap().add_item_sync(Item::Task(remote_addition)).unwrap();
+        assert!(provider.sync().await);
+        assert_eq!(name_of(&local_cal, &conflicting_url).as_deref(), Some("Remote edit"));
+        assert!(local_cal.read().unwrap().rollback_point().is_some());
+
+        // ...until the sync is rolled back
+        provider.rollback_last_sync(false).await.unwrap();
+        assert_eq!(name_of(&local_cal, &conflicting_url).as_deref(), Some("Local edit"));
+        assert!(name_of(&local_cal, &remote_addition_url).is_none());
+        assert!(local_cal.read().unwrap().rollback_point().is_none());
+        let mut provider = provider.with_conflict_authority(ConflictAuthority::Local);
+        assert!(provider.sync().await);
+        assert_eq!(name_of(&remote_cal, &conflicting_url).as_deref(), Some("Local edit"));
+        assert_eq!(name_of(&local_cal, &remote_addition_url).as_deref(), Some("Remote addition"));
+
+        // Pushed additions and deletions can be reverted in the remote source as well
+        let local_addition = Task::new(String::from("Local addition"), false, &cal_url);
+        let local_addition_url = local_addition.url().clone();
+        local_cal.write().unwrap().add_item_sync(Item::Task(local_addition)).unwrap();
+        local_cal.write().unwrap().mark_for_deletion_sync(&remote_addition_url).unwrap();
+        assert!(provider.sync().await);
+        assert!(name_of(&remote_cal, &remote_addition_url).is_none());
+        assert!(name_of(&remote_cal, &local_addition_url).is_some());
+
+        provider.rollback_last_sync(true).await.unwrap();
+        assert!(name_of(&remote_cal, &local_addition_url).is_none());
+        assert_eq!(local_cal.read().unwrap().get_item_by_url_sync(&local_addition_url).unwrap().sync_status(), &SyncStatus::NotSynced);
+        assert_eq!(name_of(&remote_cal, &remote_addition_url).as_deref(), Some("Remote addition"));
+        assert_eq!(local_cal.read().unwrap().get_item_by_url_sync(&remote_addition_url).unwrap().sync_status(), remote_cal.read().unwrap().get_item_by_url_sync(&remote_addition_url).unwrap().sync_status());
+
+        // The sources are consistent again
+        assert!(provider.sync().await);
+        assert_eq!(local_cal.read().unwrap().get_item_urls_sync().unwrap(), remote_cal.read().unwrap().get_item_urls_sync().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_sync_single_calendar() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let root = PathBuf::from(String::from("test_cache/single_calendar_sync_test"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let opened_url = Url::parse("https://caldav.com/opened/").unwrap();
+        let other_url = Url::parse("https://caldav.com/other/").unwrap();
+        let provider = crate::LocalProvider::new(Cache::new_in_memory_remote(), Cache::new(&root));
+        for url in &[&opened_url, &other_url] {
+            provider.remote().create_calendar((*url).clone(), String::from("Calendar"), SupportedComponents::TODO, None).await.unwrap();
+        }
+        // Unknown calendars cannot be synced on their own
+        assert!(provider.sync_calendar(&opened_url).await == false);
+        let (sender, receiver) = crate::provider::sync_progress::feedback_channel();
+        assert!(provider.sync_calendar_with_feedback(&opened_url, sender).await == false);
+        match &*receiver.borrow() {
+            SyncEvent::Finished{ success: false, issues } => {
+                assert_eq!(issues.len(), 1);
+                assert_eq!(issues[0].severity, crate::provider::sync_progress::IssueSeverity::Error);
+                assert_eq!(issues[0].calendar.as_ref(), Some(&opened_url));
+                assert!(issues[0].item.is_none());
+            },
+            other => panic!("Unexpected last event {}", other),
+        }
+
+        for url in &[&opened_url, &other_url] {
+            provider.local().create_calendar((*url).clone(), String::from("Calendar"), SupportedComponents::TODO, None).await.unwrap();
+            let remote_cal = provider.remote().get_calendar_sync(url).unwrap();
+            remote_cal.write().unwrap().add_item_sync(Item::Task(Task::new(String::from("New task"), false, url))).unwrap();
+        }
+
+        let (sender, receiver) = crate::provider::sync_progress::feedback_channel();
+        assert!(provider.sync_calendar_with_feedback(&opened_url, sender).await);
+        assert!(matches!(*receiver.borrow(), SyncEvent::Finished{ success: true, .. }));
+
+        let opened_cal = provider.local().get_calendar_sync(&opened_url).unwrap();
+        assert_eq!(opened_cal.read().unwrap().get_items_sync().unwrap().len(), 1);
+        assert_eq!(opened_cal.read().unwrap().sync_status(), CalendarSyncStatus::Synced);
+        let other_cal = provider.local().get_calendar_sync(&other_url).unwrap();
+        assert!(other_cal.read().unwrap().get_items_sync().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_status_after_upload() {
+        let cal_url = Url::parse("https://caldav.com/shopping").unwrap();
+        let uploaded = Item::Task(Task::new(String::from("Milk"), false, &cal_url));
+        let new_tag = VersionTag::from(String::from("new-tag"));
+        let synced = SyncStatus::Synced(new_tag.clone());
+
+        // Items that have not changed while they were uploaded are synced
+        assert_eq!(status_after_upload(&uploaded, &uploaded, synced.clone()), synced);
+
+        // Others stay local changes, against the version that has been uploaded
+        let mut renamed = uploaded.clone();
+        renamed.unwrap_task_mut().set_name(String::from("Oat milk"));
+        assert_eq!(status_after_upload(&uploaded, &renamed, synced.clone()), SyncStatus::LocallyModified(new_tag.clone()));
+        let mut deleted = uploaded.clone();
+        deleted.set_sync_status(SyncStatus::LocallyDeleted(VersionTag::from(String::from("old-tag"))));
+        assert_eq!(status_after_upload(&uploaded, &deleted, synced), SyncStatus::LocallyDeleted(new_tag));
+    }
+
+    #[tokio::test]
+    async fn test_progress_total_operations() {
+        use crate::provider::sync_progress::FeedbackReceiver;
+        use crate::traits::ItemObserver;
+
+        /// Records the latest sync event every time a local item is changed
+        struct EventRecorder {
+            receiver: FeedbackReceiver,
+            events: std::sync::Mutex<Vec<SyncEvent>>,
+        }
+        impl ItemObserver for EventRecorder {
+            fn item_changed(&self, _calendar_url: &Url, _old: Option<&Item>, _new: Option<&Item>) {
+                self.events.lock().unwrap().push(self.receiver.borrow().clone());
+            }
+        }
+
+        let _ = env_logger::builder().is_test(true).try_init();
+        let root = PathBuf::from(String::from("test_cache/progress_total_operations_test"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let cal_url = Url::parse("https://caldav.com/progress/").unwrap();
+        let mut provider = crate::LocalProvider::new(Cache::new_in_memory_remote(), Cache::new(&root));
+        let remote_cal = provider.remote().create_calendar(cal_url.clone(), String::from("Progress"), SupportedComponents::TODO, None).await.unwrap();
+        let mut urls = Vec::new();
+        for i in 0..2 {
+            let task = Task::new(format!("Task #{}", i), false, &cal_url);
+            urls.push(task.url().clone());
+            remote_cal.write().unwrap().add_item_sync(Item::Task(task)).unwrap();
+        }
+        assert!(provider.sync().await);
+
+        // Two remote deletions and a remote addition
+        for url in &urls {
+            remote_cal.write().unwrap().immediately_delete_item_sync(url).unwrap();
+        }
+        remote_cal.write().unwrap().add_item_sync(Item::Task(Task::new(String::from("New task"), false, &cal_url))).unwrap();
+
+        let (sender, receiver) = crate::provider::sync_progress::feedback_channel();
+        let recorder = Arc::new(EventRecorder { receiver, events: std::sync::Mutex::new(Vec::new()) });
+        let local_cal = provider.local().get_calendar_sync(&cal_url).unwrap();
+        local_cal.write().unwrap().add_observer(recorder.clone());
+        assert!(provider.sync_with_feedback(sender).await);
+
+        // Local items are deleted right after the event that announces it
+        {
+            let events = recorder.events.lock().unwrap();
+            let mut deletions: Vec<(usize, Option<usize>)> = events.iter()
+                .filter_map(|event| match event {
+                    SyncEvent::InProgress{ items_done_already, total_operations, step: SyncStep::DeletingLocalItem{ .. }, .. } => Some((*items_done_already, *total_operations)),
+                    _ => None,
+                })
+                .collect();
+            deletions.dedup();
+            assert_eq!(deletions, vec![(1, Some(3)), (2, Some(3))]);
+            let first_deletion = events.iter().find(|event| matches!(event, SyncEvent::InProgress{ step: SyncStep::DeletingLocalItem{ .. }, .. })).unwrap();
+            assert!(first_deletion.to_string().starts_with("Progress [1/3] "));
+        }
+
+        // Calendars that have not changed since their last sync are planned as well, with no operation
+        let (sender, receiver) = crate::provider::sync_progress::feedback_channel();
+        let mut progress = SyncProgress::new_with_feedback_channel(sender);
+        crate::LocalProvider::sync_calendar_pair(local_cal, remote_cal, ConflictAuthority::Remote, &Config::default(), &CalendarOverrides::default(), &mut progress).await.unwrap();
+        assert!(matches!(&*receiver.borrow(), SyncEvent::CalendarPlanned{ total_operations: 0, .. }));
+        assert_eq!(progress.total_operations(), Some(0));
+    }
+
+    #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+    #[tokio::test]
+    async fn test_version_tags_prefetch() {
+        use crate::mock_behaviour::{Latency, MockBehaviour, MockOperation};
+
+        let _ = env_logger::builder().is_test(true).try_init();
+        let root = PathBuf::from(String::from("test_cache/version_tags_prefetch_test"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let mock_behaviour = Arc::new(std::sync::Mutex::new(MockBehaviour {
+            get_item_version_tags_latency: Latency::Fixed(std::time::Duration::from_millis(50)),
+            ..MockBehaviour::default()
+        }));
+        let mut remote = Cache::new_in_memory_remote();
+        remote.set_mock_behaviour(Some(Arc::clone(&mock_behaviour)));
+        for i in 0..4 {
+            let cal_url = Url::parse(&format!("https://caldav.com/slow-{}/", i)).unwrap();
+            let cal = remote.create_calendar(cal_url.clone(), format!("Slow #{}", i), SupportedComponents::TODO, None).await.unwrap();
+            cal.write().unwrap().add_item_sync(Item::Task(Task::new(String::from("Remote task"), false, &cal_url))).unwrap();
+        }
+        let mut provider = crate::LocalProvider::new(remote, Cache::new(&root));
+
+        // The version tags of every calendar are fetched at the same time
+        assert!(provider.sync().await);
+        assert_eq!(mock_behaviour.lock().unwrap().max_in_flight.get(&MockOperation::GetItemVersionTags), Some(&4));
+        assert_eq!(provider.local().get_calendars_sync().unwrap().len(), 4);
+        assert!(provider.local().get_calendars_sync().unwrap().values().all(|cal| cal.read().unwrap().get_items_sync().unwrap().len() == 1));
+
+        // ...unless told otherwise (calendars that have not changed are not even listed, so let's change them)
+        for (cal_url, cal) in provider.remote().get_calendars_sync().unwrap() {
+            cal.write().unwrap().add_item_sync(Item::Task(Task::new(String::from("Another remote task"), false, &cal_url))).unwrap();
+        }
+        let mut provider = provider.with_config(Config { tag_prefetch_concurrency: 1, ..Config::default() });
+        mock_behaviour.lock().unwrap().max_in_flight.clear();
+        assert!(provider.sync().await);
+        assert_eq!(mock_behaviour.lock().unwrap().max_in_flight.get(&MockOperation::GetItemVersionTags), Some(&1));
+    }
+
+    #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+    #[tokio::test]
+    async fn test_remote_calendar_unlocked_during_requests() {
+        use crate::mock_behaviour::{Latency, MockBehaviour, MockOperation};
+
+        let _ = env_logger::builder().is_test(true).try_init();
+        let root = PathBuf::from(String::from("test_cache/unlocked_during_requests_test"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let mock_behaviour = Arc::new(std::sync::Mutex::new(MockBehaviour {
+            add_item_latency: Latency::Fixed(std::time::Duration::from_millis(100)),
+            ..MockBehaviour::default()
+        }));
+        let mut remote = Cache::new_in_memory_remote();
+        remote.set_mock_behaviour(Some(Arc::clone(&mock_behaviour)));
+        let cal_url = Url::parse("https://caldav.com/slow/").unwrap();
+        let remote_cal = remote.create_calendar(cal_url.clone(), String::from("Slow"), SupportedComponents::TODO, None).await.unwrap();
+        let local = Cache::new(&root);
+        let local_cal = local.create_calendar(cal_url.clone(), String::from("Slow"), SupportedComponents::TODO, None).await.unwrap();
+        local_cal.write().unwrap().add_item_sync(Item::Task(Task::new(String::from("Local task"), false, &cal_url))).unwrap();
+        let mut provider = crate::LocalProvider::new(remote, local);
+
+        // Other tasks can use the remote calendar while an upload is in flight
+        let check = async {
+            loop {
+                let in_flight = mock_behaviour.lock().unwrap().in_flight.get(&MockOperation::AddItem).copied();
+                if in_flight == Some(1) {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            }
+            remote_cal.try_write().is_ok()
+        };
+        let (synced, unlocked) = tokio::join!(provider.sync(), check);
+        assert!(synced);
+        assert!(unlocked);
+        assert_eq!(remote_cal.read().unwrap().get_items_sync().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_resume_interrupted_sync() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let root = PathBuf::from(String::from("test_cache/resumed_sync_test"));
+        let _ = std::fs::remove_dir_all(&root);
+        let (local_root, remote_root) = (root.join("local"), root.join("remote"));
+
+        let cal_url = Url::parse("https://caldav.com/resumed/").unwrap();
+        let tasks: Vec<Item> = (0..4).map(|i| Item::Task(Task::new(format!("Task #{}", i), false, &cal_url))).collect();
+        let remote = Cache::new_as_remote(&remote_root).unwrap();
+        let remote_cal = remote.create_calendar(cal_url.clone(), String::from("Resumed"), SupportedComponents::TODO, None).await.unwrap();
+        remote_cal.write().unwrap().add_item_sync(tasks[0].clone()).unwrap();
+        let mut provider = crate::LocalProvider::new(remote, Cache::new(&local_root));
+        assert!(provider.sync().await);
+        provider.local().save_to_folder().unwrap();
+        let local_cal = provider.local().get_calendar_sync(&cal_url).unwrap();
+        assert!(local_cal.read().unwrap().sync_journal().is_none());
+
+        // A sync that has planned to download two items, and that has been interrupted after the first one
+        for task in &tasks[1..] {
+            remote_cal.write().unwrap().add_item_sync(task.clone()).unwrap();
+        }
+        let plan = tasks[1..3].iter().map(|task| (task.url().clone(), PlannedChange::RemoteAddition)).collect();
+        let mut journal = SyncJournal::new(plan, HashMap::new());
+        journal.mark_done(tasks[1].url());
+        local_cal.write().unwrap().set_sync_journal(Some(journal.clone())).unwrap();
+        // The journal is written to disk at once
+        let saved = Cache::load_calendar(&local_root.join(Cache::calendar_file_name(&cal_url))).unwrap();
+        assert_eq!(saved.sync_journal(), Some(&journal));
+        drop(provider);
+
+        // The next sync only handles what was left to do
+        let mut provider = crate::LocalProvider::new(Cache::new_as_remote(&remote_root).unwrap(), Cache::from_folder(&local_root).unwrap());
+        assert!(provider.sync().await);
+        let local_cal = provider.local().get_calendar_sync(&cal_url).unwrap();
+        let expected: HashSet<Url> = vec![tasks[0].url().clone(), tasks[2].url().clone()].into_iter().collect();
+        assert_eq!(local_cal.read().unwrap().get_item_urls_sync().unwrap(), expected);
+        assert!(local_cal.read().unwrap().sync_journal().is_none());
+
+        // The sync after it catches up with the changes of the meantime
+        assert!(provider.sync().await);
+        assert_eq!(local_cal.read().unwrap().get_item_urls_sync().unwrap().len(), 4);
+    }
+
+    #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+    #[tokio::test]
+    async fn test_failed_calendar_deletion() {
+        use std::sync::Mutex;
+        use crate::mock_behaviour::MockBehaviour;
+
+        let _ = env_logger::builder().is_test(true).try_init();
+        let root = PathBuf::from(String::from("test_cache/failed_calendar_deletion_test"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let cal_url = Url::parse("https://caldav.com/doomed/").unwrap();
+        let behaviour = Arc::new(Mutex::new(MockBehaviour::new()));
+        let mut remote = Cache::new_in_memory_remote();
+        remote.set_mock_behaviour(Some(Arc::clone(&behaviour)));
+        let mut provider = crate::LocalProvider::new(remote, Cache::new(&root));
+        let remote_cal = provider.remote().create_calendar(cal_url.clone(), String::from("Doomed"), SupportedComponents::TODO, None).await.unwrap();
+        remote_cal.write().unwrap().add_item_sync(Item::Task(Task::new(String::from("Remote task"), false, &cal_url))).unwrap();
+        assert!(provider.sync().await);
+
+        // A calendar the server has failed to delete is still marked for deletion...
+        provider.local().mark_calendar_for_deletion(&cal_url).unwrap();
+        behaviour.lock().unwrap().delete_calendar_behaviour = (0, 1);
+        assert!(provider.sync().await == false);
+        let local_cal = provider.local().get_calendar_sync(&cal_url).unwrap();
+        assert_eq!(local_cal.read().unwrap().sync_status(), CalendarSyncStatus::LocallyDeleted);
+        assert!(provider.remote().get_calendar_sync(&cal_url).is_some());
+
+        // ...so that the next sync deletes it
+        assert!(provider.sync().await);
+        assert!(provider.local().get_calendar_sync(&cal_url).is_none());
+        assert!(provider.remote().get_calendar_sync(&cal_url).is_none());
+    }
+
+    #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+    #[tokio::test]
+    async fn test_corrupted_responses() {
+        use std::sync::Mutex;
+        use crate::mock_behaviour::MockBehaviour;
+
+        let _ = env_logger::builder().is_test(true).try_init();
+        let root = PathBuf::from(String::from("test_cache/corrupted_responses_test"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let cal_url = Url::parse("https://caldav.com/corrupted/").unwrap();
+        let behaviour = Arc::new(Mutex::new(MockBehaviour::new()));
+        let mut remote = Cache::new_in_memory_remote();
+        remote.set_mock_behaviour(Some(Arc::clone(&behaviour)));
+        let mut provider = crate::LocalProvider::new(remote, Cache::new(&root));
+        let remote_cal = provider.remote().create_calendar(cal_url.clone(), String::from("Corrupted"), SupportedComponents::TODO, None).await.unwrap();
+        for i in 0..4 {
+            remote_cal.write().unwrap().add_item_sync(Item::Task(Task::new(format!("Task #{}", i), false, &cal_url))).unwrap();
+        }
+        let item_count = |provider: &crate::LocalProvider| provider.local().get_calendar_sync(&cal_url)
+            .map_or(0, |cal| cal.read().unwrap().get_items_sync().unwrap().len());
+
+        // Items missing from a multiget are noticed, and downloaded on the next sync
+        behaviour.lock().unwrap().partial_multiget_behaviour = (0, 1);
+        assert!(provider.sync().await == false);
+        assert_eq!(item_count(&provider), 3);
+        assert!(provider.sync().await);
+        assert_eq!(item_count(&provider), 4);
+
+        // Ever-changing version tags only trigger useless downloads
+        behaviour.lock().unwrap().changing_version_tags_behaviour = (0, 2);
+        assert!(provider.sync().await);
+        assert!(provider.sync().await);
+        assert_eq!(item_count(&provider), 4);
+
+        // An unparsable item does not end up in the local calendar
+        behaviour.lock().unwrap().unparsable_items_behaviour = (0, 1);
+        remote_cal.write().unwrap().add_item_sync(Item::Task(Task::new(String::from("Corrupted"), false, &cal_url))).unwrap();
+        assert!(provider.sync().await == false);
+        assert_eq!(item_count(&provider), 4);
+        assert!(provider.sync().await);
+        assert_eq!(item_count(&provider), 5);
+        let local_cal = provider.local().get_calendar_sync(&cal_url).unwrap();
+        assert_eq!(local_cal.read().unwrap().get_item_urls_sync().unwrap(), remote_cal.read().unwrap().get_item_urls_sync().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_calendar_overrides() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let root = PathBuf::from(String::from("test_cache/calendar_overrides_test"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let shared_url = Url::parse("https://caldav.com/shared/").unwrap();
+        let backup_url = Url::parse("https://caldav.com/backup/").unwrap();
+        let private_url = Url::parse("https://caldav.com/private/").unwrap();
+        let mut provider = crate::LocalProvider::new(Cache::new_in_memory_remote(), Cache::new(&root));
+        provider.set_calendar_overrides(shared_url.clone(), CalendarOverrides::pull_only());
+        provider.set_calendar_overrides(backup_url.clone(), CalendarOverrides::push_only());
+        provider.set_calendar_overrides(private_url.clone(), CalendarOverrides::disabled());
+        assert_eq!(provider.calendar_overrides(&shared_url), Some(&CalendarOverrides::pull_only()));
+
+        for url in &[&shared_url, &backup_url] {
+            provider.remote().create_calendar((*url).clone(), String::from("Calendar"), SupportedComponents::TODO, None).await.unwrap();
+            let remote_cal = provider.remote().get_calendar_sync(url).unwrap();
+            remote_cal.write().unwrap().add_item_sync(Item::Task(Task::new(String::from("Remote task"), false, url))).unwrap();
+        }
+        let private_cal = provider.local().create_calendar(private_url.clone(), String::from("Private"), SupportedComponents::TODO, None).await.unwrap();
+        private_cal.write().unwrap().add_item_sync(Item::Task(Task::new(String::from("Secret"), false, &private_url))).unwrap();
+        assert!(provider.sync().await);
+
+        // Disabled calendars are left alone
+        assert!(provider.remote().get_calendar_sync(&private_url).is_none());
+        // Pull-only calendars get the remote changes, push-only calendars do not
+        let shared_cal = provider.local().get_calendar_sync(&shared_url).unwrap();
+        assert_eq!(shared_cal.read().unwrap().get_items_sync().unwrap().len(), 1);
+        let backup_cal = provider.local().get_calendar_sync(&backup_url).unwrap();
+        assert!(backup_cal.read().unwrap().get_items_sync().unwrap().is_empty());
+
+        // Local changes are only pushed for push-only calendars
+        for (url, cal) in &[(&shared_url, &shared_cal), (&backup_url, &backup_cal)] {
+            cal.write().unwrap().add_item_sync(Item::Task(Task::new(String::from("Local task"), false, url))).unwrap();
+        }
+        assert!(provider.sync().await);
+        let remote_shared = provider.remote().get_calendar_sync(&shared_url).unwrap();
+        assert_eq!(remote_shared.read().unwrap().get_items_sync().unwrap().len(), 1);
+        assert_eq!(shared_cal.read().unwrap().get_items_sync().unwrap().len(), 2);
+        let remote_backup = provider.remote().get_calendar_sync(&backup_url).unwrap();
+        assert_eq!(remote_backup.read().unwrap().get_items_sync().unwrap().len(), 2);
+
+        // Single calendar syncs honour the overrides as well
+        assert!(provider.sync_calendar(&private_url).await);
+        assert!(provider.remote().get_calendar_sync(&private_url).is_none());
+
+        provider.remove_calendar_overrides(&private_url);
+        assert!(provider.sync().await);
+        assert!(provider.remote().get_calendar_sync(&private_url).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_calendar_list_changes() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let root = PathBuf::from(String::from("test_cache/calendar_list_changes_test"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let cal_url = Url::parse("https://caldav.com/appearing/").unwrap();
+        let mut provider = crate::LocalProvider::new(Cache::new_in_memory_remote(), Cache::new(&root));
+        provider.remote().create_calendar(cal_url.clone(), String::from("Appearing"), SupportedComponents::TODO, None).await.unwrap();
+
+        let mut progress = SyncProgress::new();
+        assert!(provider.run_sync(&mut progress).await);
+        assert_eq!(progress.calendar_changes(), &[CalendarListChange::Added{ url: cal_url.clone(), name: String::from("Appearing") }]);
+
+        // Nothing changes the next time
+        let mut progress = SyncProgress::new();
+        assert!(provider.run_sync(&mut progress).await);
+        assert!(progress.calendar_changes().is_empty());
+
+        provider.remote().delete_calendar_sync(&cal_url).unwrap();
+        let mut progress = SyncProgress::new();
+        assert!(provider.run_sync(&mut progress).await);
+        assert_eq!(progress.calendar_changes(), &[CalendarListChange::Removed{ url: cal_url.clone(), name: String::from("Appearing") }]);
+        assert!(provider.local().get_calendar_sync(&cal_url).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_remote_removal_policies() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let root = PathBuf::from(String::from("test_cache/remote_removal_test"));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let archived_url = Url::parse("https://caldav.com/archived/").unwrap();
+        let asked_url = Url::parse("https://caldav.com/asked/").unwrap();
+        let mut provider = crate::LocalProvider::new(Cache::new_in_memory_remote(), Cache::new(&root))
+            .with_config(Config { remote_removal: RemoteRemovalPolicy::Archive, ..Config::default() });
+        for url in [&archived_url, &asked_url] {
+            let cal = provider.remote().create_calendar(url.clone(), String::from("Removed"), SupportedComponents::TODO, None).await.unwrap();
+            cal.write().unwrap().add_item_sync(Item::Task(Task::new(String::from("Kept"), false, url))).unwrap();
+        }
+        assert!(provider.sync().await);
+
+        // Archived calendars are kept, and are not pushed back to the remote source
+        provider.remote().delete_calendar_sync(&archived_url).unwrap();
+        let mut progress = SyncProgress::new();
+        assert!(provider.run_sync(&mut progress).await);
+        assert_eq!(progress.calendar_changes(), &[CalendarListChange::Archived{ url: archived_url.clone(), name: String::from("Removed") }]);
+        let archived = provider.local().get_calendar_sync(&archived_url).unwrap();
+        assert_eq!(archived.read().unwrap().sync_status(), CalendarSyncStatus::Archived);
+        assert_eq!(archived.read().unwrap().get_item_urls_sync().unwrap().len(), 1);
+        assert!(provider.sync().await);
+        assert!(provider.remote().get_calendar_sync(&archived_url).is_none());
+
+        // Pending calendars wait for a decision
+        let mut provider = provider.with_config(Config { remote_removal: RemoteRemovalPolicy::Ask, ..Config::default() });
+        provider.remote().delete_calendar_sync(&asked_url).unwrap();
+        let mut progress = SyncProgress::new();
+        assert!(provider.run_sync(&mut progress).await);
+        assert_eq!(progress.calendar_changes(), &[CalendarListChange::RemovalPending{ url: asked_url.clone(), name: String::from("Removed") }]);
+        assert!(provider.sync().await);
+        assert_eq!(provider.pending_remote_removals().await.unwrap(), vec![asked_url.clone()]);
+        assert!(provider.resolve_remote_removal(&archived_url, RemoteRemovalPolicy::Delete).await.is_err());
+
+        provider.resolve_remote_removal(&asked_url, RemoteRemovalPolicy::Delete).await.unwrap();
+        assert!(provider.local().get_calendar_sync(&asked_url).is_none());
+        assert!(provider.pending_remote_removals().await.unwrap().is_empty());
+        assert!(provider.local().get_calendar_sync(&archived_url).is_some());
+    }
+}
@@ -0,0 +1,92 @@
+//! Settings that change the way a single calendar is synced (see [`Provider::set_calendar_overrides`](crate::provider::Provider::set_calendar_overrides))
+
+use std::collections::HashMap;
+
+use url::Url;
+
+use super::ConflictAuthority;
+use crate::calendar::CalendarKind;
+
+/// Which changes are applied during the sync of a calendar
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SyncDirection {
+    /// Remote changes are applied locally, and local changes are pushed to the remote source
+    #[default]
+    Bidirectional,
+    /// Only remote changes are applied locally (e.g. for a shared read-only calendar). Local changes are kept locally, but they are never pushed
+    PullOnly,
+    /// Only local changes are pushed to the remote source (e.g. for a backup target). Remote changes are never applied locally
+    PushOnly,
+}
+
+/// Settings of a calendar that take precedence over the ones of its [`Provider`](crate::provider::Provider)
+///
+/// ```
+/// use kitchen_fridge::provider::{CalendarOverrides, SyncDirection};
+///
+/// let shared_calendar = CalendarOverrides::pull_only();
+/// assert_eq!(shared_calendar.direction, SyncDirection::PullOnly);
+/// assert!(shared_calendar.enabled);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CalendarOverrides {
+    /// Whether this calendar is synced at all
+    pub enabled: bool,
+    /// Which changes are applied during the sync
+    pub direction: SyncDirection,
+    /// The source that wins sync conflicts (`None` to use the one of the provider)
+    pub conflict_authority: Option<ConflictAuthority>,
+    /// How many items are batched in a single HTTP request when downloading from the server (`None` to use the one of the provider)
+    pub download_batch_size: Option<usize>,
+}
+
+impl Default for CalendarOverrides {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            direction: SyncDirection::default(),
+            conflict_authority: None,
+            download_batch_size: None,
+        }
+    }
+}
+
+impl CalendarOverrides {
+    /// Settings for a calendar that is not synced
+    pub fn disabled() -> Self {
+        Self { enabled: false, ..Self::default() }
+    }
+
+    /// Settings for a calendar whose local changes must never be pushed
+    pub fn pull_only() -> Self {
+        Self { direction: SyncDirection::PullOnly, ..Self::default() }
+    }
+
+    /// Settings for a calendar whose remote changes must never be applied locally
+    pub fn push_only() -> Self {
+        Self { direction: SyncDirection::PushOnly, ..Self::default() }
+    }
+
+    /// The default settings of a calendar, for its [`CalendarKind`]: read-only calendars (see [`CalendarKind::is_read_only`]) are pull-only, the others use the settings of the provider
+    pub fn for_kind(kind: CalendarKind) -> Self {
+        match kind.is_read_only() {
+            true => Self::pull_only(),
+            false => Self::default(),
+        }
+    }
+
+    /// Whether local changes are pushed to the remote source
+    pub fn pushes(&self) -> bool {
+        self.direction != SyncDirection::PullOnly
+    }
+
+    /// Whether remote changes are applied locally
+    pub fn pulls(&self) -> bool {
+        self.direction != SyncDirection::PushOnly
+    }
+}
+
+/// The settings of a calendar: the ones it has been given, or the default ones for its kind
+pub(crate) fn overrides_of(overrides: &HashMap<Url, CalendarOverrides>, cal_url: &Url, kind: CalendarKind) -> CalendarOverrides {
+    overrides.get(cal_url).cloned().unwrap_or_else(|| CalendarOverrides::for_kind(kind))
+}
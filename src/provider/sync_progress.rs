@@ -1,6 +1,187 @@
 //! Utilities to track the progression of a sync
 
 use std::fmt::{Display, Error, Formatter};
+use std::time::Instant;
+
+use url::Url;
+
+use crate::metrics::{ItemSync, SharedMetrics};
+
+/// How serious a [`SyncIssue`] is
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IssueSeverity {
+    /// Something has not been synced this time, and will be retried by the next sync
+    Warning,
+    /// Something went wrong, and may need the attention of the user
+    Error,
+}
+
+/// What went wrong in a [`SyncIssue`].
+///
+/// These are not formatted, so that applications can translate and style them as they wish (see [`SyncIssue::message`] for a plain English version). \
+/// `error` fields are the errors the sources have returned, as they have formatted them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IssueKind {
+    /// The sync has been interrupted before its end
+    SyncAborted{ error: String },
+    /// The remote source could not be reached. The sync is postponed, and local changes are kept for the next one
+    RemoteUnreachable{ error: String },
+    /// The calendars of a remote source of an [`AggregateProvider`](crate::provider::aggregate::AggregateProvider) could not be listed. This source has been skipped
+    RemoteSourceUnavailable{ source: Url, error: String },
+    /// A remote source of an [`AggregateProvider`](crate::provider::aggregate::AggregateProvider) could not be synced
+    RemoteSourceSyncFailed{ source: Url, error: String },
+    /// A local calendar belongs to none of the remote sources of an [`AggregateProvider`](crate::provider::aggregate::AggregateProvider). It has not been synced
+    CalendarWithoutSource,
+    /// A calendar does not exist in both sources, so that it cannot be synced on its own
+    CalendarNotInBothSources,
+    /// A calendar could not be synced
+    CalendarSyncFailed{ error: String },
+    /// A calendar could not be deleted from the remote source
+    RemoteCalendarDeletionFailed{ error: String },
+    /// A calendar could not be deleted from the local source
+    LocalCalendarDeletionFailed{ error: String },
+    /// The local counterpart of a remote calendar could not be found or created
+    LocalCounterpartFailed{ error: String },
+    /// The remote counterpart of a local calendar could not be found or created
+    RemoteCounterpartFailed{ error: String },
+    /// The local source could not be saved after the sync
+    SaveFailed{ error: String },
+    /// The progress of the sync of a calendar could not be saved, so that it cannot be resumed in case it is interrupted
+    JournalSaveFailed{ error: String },
+    /// An item that has never been synced has the same URL as a remote item. It has been ignored
+    UrlReuse,
+    /// A local deletion could not be pushed to the server
+    RemoteItemDeletionFailed{ error: String },
+    /// An item could not be deleted from the local calendar
+    LocalItemDeletionFailed{ error: String },
+    /// An item that is being synced is missing from the local calendar
+    LocalItemMissing,
+    /// A local item could not be uploaded. `new_item` tells whether it was a new item or a changed one
+    UploadFailed{ new_item: bool, error: String },
+    /// A batch of `count` remote items could not be downloaded. They will be downloaded by the next sync
+    DownloadFailed{ count: usize, error: String },
+    /// The server has sent only `received` items of a batch of `requested` ones. The other ones will be downloaded by the next sync
+    IncompleteDownload{ received: usize, requested: usize },
+    /// An item of a downloaded batch is missing from the reply of the server
+    DownloadedItemMissing,
+    /// A downloaded item could not be written to the local calendar
+    LocalItemWriteFailed{ error: String },
+}
+
+/// A warning or an error that happened during a sync, with the calendar and the item it is about (if any)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyncIssue {
+    pub severity: IssueSeverity,
+    /// The calendar this issue is about
+    pub calendar: Option<Url>,
+    /// The item this issue is about
+    pub item: Option<Url>,
+    /// What went wrong
+    pub kind: IssueKind,
+}
+
+impl SyncIssue {
+    /// A plain English description of this issue, as it has been logged
+    pub fn message(&self) -> String {
+        let calendar = self.calendar.as_ref().map(Url::as_str).unwrap_or("?");
+        let item = self.item.as_ref().map(Url::as_str).unwrap_or("?");
+        match &self.kind {
+            IssueKind::SyncAborted{error} => format!("Sync terminated because of an error: {}", error),
+            IssueKind::RemoteUnreachable{error} => format!("The remote source cannot be reached ({}). The sync is postponed, local changes are kept for the next one", error),
+            IssueKind::RemoteSourceUnavailable{source, error} => format!("Unable to get the calendars of remote source {}: {}. Skipping it this time", source, error),
+            IssueKind::RemoteSourceSyncFailed{source, error} => format!("Unable to sync remote source {}: {}", source, error),
+            IssueKind::CalendarWithoutSource => format!("Local calendar {} belongs to no remote source. It has not been synced", calendar),
+            IssueKind::CalendarNotInBothSources => format!("Calendar {} does not exist in both sources, it cannot be synced on its own", calendar),
+            IssueKind::CalendarSyncFailed{error} => format!("Unable to sync calendar {}: {}", calendar, error),
+            IssueKind::RemoteCalendarDeletionFailed{error} => format!("Unable to delete remote calendar {}: {}. Skipping this time", calendar, error),
+            IssueKind::LocalCalendarDeletionFailed{error} => format!("Unable to delete local calendar {}: {}", calendar, error),
+            IssueKind::LocalCounterpartFailed{error} => format!("Unable to get or insert local counterpart calendar for {} ({}). Skipping this time", calendar, error),
+            IssueKind::RemoteCounterpartFailed{error} => format!("Unable to get or insert remote counterpart calendar for {} ({}). Skipping this time", calendar, error),
+            IssueKind::SaveFailed{error} => format!("Unable to save the local source after the sync: {}", error),
+            IssueKind::JournalSaveFailed{error} => format!("Unable to save the progress of the sync of {}: {}", calendar, error),
+            IssueKind::UrlReuse => format!("URL reuse between remote and local sources ({}). Ignoring this item in the sync", item),
+            IssueKind::RemoteItemDeletionFailed{error} => format!("Unable to delete remote item {}: {}", item, error),
+            IssueKind::LocalItemDeletionFailed{error} => format!("Unable to delete local item {}: {}", item, error),
+            IssueKind::LocalItemMissing => format!("Inconsistency: item {} is being synced but is locally missing", item),
+            IssueKind::UploadFailed{new_item: true, error} => format!("Unable to push local addition {} to remote calendar: {}", item, error),
+            IssueKind::UploadFailed{new_item: false, error} => format!("Unable to push local change {} to remote calendar: {}", item, error),
+            IssueKind::DownloadFailed{count, error} => format!("Unable to get a batch of {} items from {}: {}. Skipping them", count, calendar, error),
+            IssueKind::IncompleteDownload{received, requested} => format!("The server has sent only {} items of a batch of {} from {}", received, requested, calendar),
+            IssueKind::DownloadedItemMissing => format!("Inconsistency: an item from a batch of {} has vanished from the remote end", calendar),
+            IssueKind::LocalItemWriteFailed{error} => format!("Not able to add item {} to local calendar: {}", item, error),
+        }
+    }
+}
+
+impl Display for SyncIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        match self.severity {
+            IssueSeverity::Warning => write!(f, "Warning: {}", self.message()),
+            IssueSeverity::Error => write!(f, "Error: {}", self.message()),
+        }
+    }
+}
+
+/// What the sync of a calendar is doing, as told by a [`SyncEvent::InProgress`].
+///
+/// These are not formatted, so that applications can translate and style them as they wish. Their `Display` implementation is a plain English version of them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SyncStep {
+    /// The sync of the calendar has just started
+    Started,
+    /// The items of the remote calendar have been listed
+    RemoteItemsListed{ count: usize },
+    /// A local deletion is being pushed to the server
+    DeletingRemoteItem{ item_name: String },
+    /// A remote deletion is being applied locally
+    DeletingLocalItem{ item_name: String },
+    /// A new local item is being uploaded
+    UploadingNewItem{ item_name: String },
+    /// A changed local item is being uploaded
+    UploadingChangedItem{ item_name: String },
+    /// A batch of `count` remote items has been downloaded. `first_item_name` is the name of one of them, if it is known
+    ItemsDownloaded{ first_item_name: Option<String>, count: usize },
+}
+
+impl Display for SyncStep {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        match self {
+            SyncStep::Started => write!(f, "started"),
+            SyncStep::RemoteItemsListed{count} => write!(f, "{} remote items", count),
+            SyncStep::DeletingRemoteItem{item_name} => write!(f, "deleting remote item {}", item_name),
+            SyncStep::DeletingLocalItem{item_name} => write!(f, "deleting local item {}", item_name),
+            SyncStep::UploadingNewItem{item_name} => write!(f, "uploading new item {}", item_name),
+            SyncStep::UploadingChangedItem{item_name} => write!(f, "uploading changed item {}", item_name),
+            SyncStep::ItemsDownloaded{first_item_name: Some(name), count: 1} => write!(f, "downloaded {}", name),
+            SyncStep::ItemsDownloaded{first_item_name: Some(name), count} => write!(f, "downloaded {} and {} other items", name, count.saturating_sub(1)),
+            SyncStep::ItemsDownloaded{first_item_name: None, count} => write!(f, "downloaded {} items", count),
+        }
+    }
+}
+
+/// A change in the list of calendars, that a sync has applied to the local source, as told by a [`SyncEvent::CalendarListChanged`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CalendarListChange {
+    /// A calendar has been found in the remote source, and has been added locally
+    Added{ url: Url, name: String },
+    /// A calendar that had been synced is not in the remote source anymore, and has been removed locally
+    Removed{ url: Url, name: String },
+    /// A calendar that had been synced is not in the remote source anymore, and has been kept locally as an archive (see [`RemoteRemovalPolicy::Archive`](crate::config::RemoteRemovalPolicy::Archive))
+    Archived{ url: Url, name: String },
+    /// A calendar that had been synced is not in the remote source anymore, and the app should decide what to do with it (see [`RemoteRemovalPolicy::Ask`](crate::config::RemoteRemovalPolicy::Ask))
+    RemovalPending{ url: Url, name: String },
+}
+
+impl Display for CalendarListChange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        match self {
+            CalendarListChange::Added{name, ..} => write!(f, "new calendar {}", name),
+            CalendarListChange::Removed{name, ..} => write!(f, "calendar {} has been removed", name),
+            CalendarListChange::Archived{name, ..} => write!(f, "calendar {} has been removed from the server, and archived", name),
+            CalendarListChange::RemovalPending{name, ..} => write!(f, "calendar {} has been removed from the server", name),
+        }
+    }
+}
 
 /// An event that happens during a sync
 #[derive(Clone, Debug)]
@@ -9,10 +190,20 @@ pub enum SyncEvent {
     NotStarted,
     /// Sync has just started but no calendar is handled yet
     Started,
+    /// The differences of a calendar have been found, and `total_operations` items are about to be synced (e.g. to size a progress bar before the first item of this calendar is handled).
+    ///
+    /// This is sent (with `total_operations: 0`) for calendars that have not changed since their last sync as well
+    CalendarPlanned{ calendar: String, total_operations: usize },
     /// Sync is in progress.
-    InProgress{ calendar: String, items_done_already: usize, details: String},
-    /// Sync is finished
-    Finished{ success: bool },
+    ///
+    /// `total_operations` is how many items the sync of this calendar is about to handle, just like in [`SyncEvent::CalendarPlanned`] (since the feedback channel only keeps the latest event). It is `None` until its differences have been found
+    InProgress{ calendar: String, items_done_already: usize, total_operations: Option<usize>, step: SyncStep },
+    /// A calendar has appeared in (or disappeared from) the local source, e.g. for apps to update their list of calendars.
+    ///
+    /// Since the feedback channel only keeps the latest event, these changes are also available from [`SyncProgress::calendar_changes`]
+    CalendarListChanged(CalendarListChange),
+    /// Sync is finished. `issues` are the warnings and errors that happened during the sync, so that they can be shown to the user
+    Finished{ success: bool, issues: Vec<SyncIssue> },
 }
 
 impl Display for SyncEvent {
@@ -20,8 +211,13 @@ impl Display for SyncEvent {
         match self {
             SyncEvent::NotStarted => write!(f, "Not started"),
             SyncEvent::Started => write!(f, "Sync has started..."),
-            SyncEvent::InProgress{calendar, items_done_already, details} => write!(f, "{} [{}/?] {}...", calendar, items_done_already, details),
-            SyncEvent::Finished{success} => match success {
+            SyncEvent::CalendarPlanned{calendar, total_operations} => write!(f, "{} [0/{}] planned", calendar, total_operations),
+            SyncEvent::InProgress{calendar, items_done_already, total_operations, step} => match total_operations {
+                Some(total) => write!(f, "{} [{}/{}] {}...", calendar, items_done_already, total, step),
+                None => write!(f, "{} [{}/?] {}...", calendar, items_done_already, step),
+            },
+            SyncEvent::CalendarListChanged(change) => write!(f, "{}", change),
+            SyncEvent::Finished{success, ..} => match success {
                 true => write!(f, "Sync successfully finished"),
                 false => write!(f, "Sync finished with errors"),
             }
@@ -53,15 +249,35 @@ pub fn feedback_channel() -> (FeedbackSender, FeedbackReceiver) {
 /// A structure that tracks the progression and the errors that happen during a sync
 pub struct SyncProgress {
     n_errors: u32,
+    issues: Vec<SyncIssue>,
+    calendar_changes: Vec<CalendarListChange>,
+    /// The calendar that is being synced, which warnings and errors are about unless told otherwise
+    calendar: Option<Url>,
     feedback_channel: Option<FeedbackSender>,
     counter: usize,
+    total_operations: Option<usize>,
+    metrics: Option<SharedMetrics>,
+    start: Instant,
+    sync_id: String,
+    offline: bool,
 }
 impl SyncProgress {
     pub fn new() -> Self {
-        Self { n_errors: 0, feedback_channel: None, counter: 0 }
+        Self { n_errors: 0, issues: Vec::new(), calendar_changes: Vec::new(), calendar: None, feedback_channel: None, counter: 0, total_operations: None, metrics: None, start: Instant::now(), sync_id: crate::correlation::new_id(), offline: false }
     }
     pub fn new_with_feedback_channel(channel: FeedbackSender) -> Self {
-        Self { n_errors: 0, feedback_channel: Some(channel), counter: 0 }
+        Self { feedback_channel: Some(channel), ..Self::new() }
+    }
+
+    /// The identifier of this sync, that prefixes its log messages (see the [`correlation`](crate::correlation) module)
+    pub fn sync_id(&self) -> &str {
+        &self.sync_id
+    }
+
+    /// Record the errors and the synced items to `metrics` as well (see the [`metrics`](crate::metrics) module)
+    pub fn with_metrics(mut self, metrics: Option<SharedMetrics>) -> Self {
+        self.metrics = metrics;
+        self
     }
 
     /// Reset the user-info counter
@@ -78,6 +294,14 @@ impl SyncProgress {
     pub fn counter(&self) -> usize {
         self.counter
     }
+    /// Set how many items the sync of the current calendar is about to handle (`None` while it is not known yet), see [`SyncEvent::InProgress`]
+    pub fn set_total_operations(&mut self, total: Option<usize>) {
+        self.total_operations = total;
+    }
+    /// Retrieves how many items the sync of the current calendar is about to handle, see [`Self::set_total_operations`]
+    pub fn total_operations(&self) -> Option<usize> {
+        self.total_operations
+    }
 
 
 
@@ -85,30 +309,99 @@ impl SyncProgress {
         self.n_errors == 0
     }
 
+    /// Whether a remote source could not be reached (see [`CalDavSource::check_reachable`](crate::traits::CalDavSource::check_reachable)), so that (part of) the sync has been postponed
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
+    /// Record that a remote source could not be reached, because of `error`. This is logged as a warning
+    pub fn set_offline(&mut self, error: String) {
+        self.offline = true;
+        self.warn(IssueKind::RemoteUnreachable{ error });
+    }
+
+    /// Set the calendar that is being synced (`None` once it is over), that the next warnings and errors are about
+    pub fn set_calendar(&mut self, calendar: Option<Url>) {
+        self.calendar = calendar;
+    }
+
+    /// The warnings and errors that have happened so far
+    pub fn issues(&self) -> &[SyncIssue] {
+        &self.issues
+    }
+
+    /// The calendars that have been added to (or removed from) the local source so far
+    pub fn calendar_changes(&self) -> &[CalendarListChange] {
+        &self.calendar_changes
+    }
+
+    /// Record a change in the list of local calendars, and send it as a feedback (see [`SyncEvent::CalendarListChanged`])
+    pub fn calendar_list_changed(&mut self, change: CalendarListChange) {
+        log::info!(target: crate::logging::PROVIDER, "[sync {}] {}", self.sync_id, change);
+        self.calendar_changes.push(change.clone());
+        self.feedback(SyncEvent::CalendarListChanged(change));
+    }
+
     /// Log an error
-    pub fn error(&mut self, text: &str) {
-        log::error!("{}", text);
-        self.n_errors += 1;
+    pub fn error(&mut self, kind: IssueKind) {
+        self.record_issue(IssueSeverity::Error, self.calendar.clone(), None, kind);
     }
     /// Log a warning
-    pub fn warn(&mut self, text: &str) {
-        log::warn!("{}", text);
+    pub fn warn(&mut self, kind: IssueKind) {
+        self.record_issue(IssueSeverity::Warning, self.calendar.clone(), None, kind);
+    }
+    /// Log an error about an item of the calendar that is being synced
+    pub fn item_error(&mut self, item: &Url, kind: IssueKind) {
+        self.record_issue(IssueSeverity::Error, self.calendar.clone(), Some(item.clone()), kind);
+    }
+    /// Log a warning about an item of the calendar that is being synced
+    pub fn item_warn(&mut self, item: &Url, kind: IssueKind) {
+        self.record_issue(IssueSeverity::Warning, self.calendar.clone(), Some(item.clone()), kind);
+    }
+    /// Log an error about a calendar
+    pub fn calendar_error(&mut self, calendar: &Url, kind: IssueKind) {
+        self.record_issue(IssueSeverity::Error, Some(calendar.clone()), None, kind);
+    }
+    /// Log a warning about a calendar
+    pub fn calendar_warn(&mut self, calendar: &Url, kind: IssueKind) {
+        self.record_issue(IssueSeverity::Warning, Some(calendar.clone()), None, kind);
+    }
+    fn record_issue(&mut self, severity: IssueSeverity, calendar: Option<Url>, item: Option<Url>, kind: IssueKind) {
+        let issue = SyncIssue { severity, calendar, item, kind };
+        match severity {
+            IssueSeverity::Error => log::error!(target: crate::logging::PROVIDER, "[sync {}] {}", self.sync_id, issue.message()),
+            IssueSeverity::Warning => log::warn!(target: crate::logging::PROVIDER, "[sync {}] {}", self.sync_id, issue.message()),
+        }
+        self.issues.push(issue);
         self.n_errors += 1;
+        if let Some(metrics) = &self.metrics {
+            metrics.sync_error();
+        }
+    }
+    /// Record that items have been synced
+    pub fn items_synced(&mut self, kind: ItemSync, count: usize) {
+        if let (Some(metrics), true) = (&self.metrics, count > 0) {
+            metrics.items_synced(kind, count);
+        }
     }
     /// Log an info
     pub fn info(&mut self, text: &str) {
-        log::info!("{}", text);
+        log::info!(target: crate::logging::PROVIDER, "[sync {}] {}", self.sync_id, text);
     }
     /// Log a debug message
     pub fn debug(&mut self, text: &str) {
-        log::debug!("{}", text);
+        log::debug!(target: crate::logging::PROVIDER_DIFF, "[sync {}] {}", self.sync_id, text);
     }
     /// Log a trace message
     pub fn trace(&mut self, text: &str) {
-        log::trace!("{}", text);
+        log::trace!(target: crate::logging::PROVIDER_DIFF, "[sync {}] {}", self.sync_id, text);
     }
     /// Send an event as a feedback to the listener (if any).
+    ///
+    /// A [`SyncEvent::Finished`] event is recorded to the metrics as well (if any)
     pub fn feedback(&mut self, event: SyncEvent) {
+        if let (Some(metrics), SyncEvent::Finished{ success, .. }) = (&self.metrics, &event) {
+            metrics.sync_finished(self.start.elapsed(), *success);
+        }
         self.feedback_channel
             .as_ref()
             .map(|sender| {
@@ -116,3 +409,35 @@ impl SyncProgress {
             });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_issue_messages() {
+        let calendar = Url::parse("https://caldav.com/cal/").unwrap();
+        let item = Url::parse("https://caldav.com/cal/task.ics").unwrap();
+        let mut progress = SyncProgress::new();
+        progress.set_calendar(Some(calendar.clone()));
+        progress.item_warn(&item, IssueKind::UploadFailed{ new_item: true, error: String::from("403 Forbidden") });
+        progress.warn(IssueKind::IncompleteDownload{ received: 2, requested: 3 });
+
+        // Issues keep what went wrong, and only format it on demand
+        let issues = progress.issues();
+        assert_eq!(issues[0].kind, IssueKind::UploadFailed{ new_item: true, error: String::from("403 Forbidden") });
+        assert_eq!(issues[0].item.as_ref(), Some(&item));
+        assert_eq!(issues[0].to_string(), "Warning: Unable to push local addition https://caldav.com/cal/task.ics to remote calendar: 403 Forbidden");
+        assert_eq!(issues[1].calendar.as_ref(), Some(&calendar));
+        assert_eq!(issues[1].message(), "The server has sent only 2 items of a batch of 3 from https://caldav.com/cal/");
+        assert!(!progress.is_success());
+    }
+
+    #[test]
+    fn test_step_messages() {
+        let step = |first_item_name: Option<&str>, count| SyncStep::ItemsDownloaded{ first_item_name: first_item_name.map(String::from), count }.to_string();
+        assert_eq!(step(Some("Task"), 1), "downloaded Task");
+        assert_eq!(step(Some("Task"), 3), "downloaded Task and 2 other items");
+        assert_eq!(step(None, 3), "downloaded 3 items");
+    }
+}
@@ -1,21 +1,116 @@
+use std::sync::Arc;
+
+#[cfg(feature = "client")]
+use std::sync::Mutex;
+#[cfg(feature = "client")]
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(feature = "client")]
+use chrono::{DateTime, Duration, Utc};
+#[cfg(feature = "client")]
+use once_cell::sync::OnceCell;
 use url::Url;
 
+use crate::config::Config;
+
 /// Just a wrapper around a URL and credentials
 #[derive(Clone, Debug)]
 pub struct Resource {
     url: Url,
     username: String,
     password: String,
+    /// The configuration HTTP requests to this resource are sent with
+    config: Arc<Config>,
+    /// The HTTP client requests are sent with. It is shared by the resources derived from this one, so that they share their connections to the server as well
+    #[cfg(feature = "client")]
+    http_client: Arc<OnceCell<reqwest::Client>>,
+    /// What is known about the clock of the server. It is shared the same way
+    #[cfg(feature = "client")]
+    server_clock: Arc<ServerClock>,
+}
+
+/// What is known about the clock of a server, from the `Date` headers of its responses
+#[cfg(feature = "client")]
+#[derive(Debug, Default)]
+pub(crate) struct ServerClock {
+    /// The last measured difference between the clock of the server and the local clock (positive when the server is ahead)
+    skew: Mutex<Option<Duration>>,
+    /// Whether the skew has been found too large already, and a warning has been logged
+    warned: AtomicBool,
+    /// The offset the dates this client sends to the server are shifted by, if they are (see [`Config::compensate_clock_skew`])
+    compensated: Mutex<Option<Duration>>,
+}
+
+#[cfg(feature = "client")]
+impl ServerClock {
+    /// See [`Client::clock_skew`](crate::client::Client::clock_skew)
+    pub(crate) fn skew(&self) -> Option<Duration> {
+        *self.skew.lock().unwrap()
+    }
+
+    /// How far the dates this client exchanges with the server are shifted (zero unless [`Config::compensate_clock_skew`] is set, and the skew is too large)
+    pub(crate) fn offset(&self) -> Duration {
+        self.compensated.lock().unwrap().unwrap_or_else(Duration::zero)
+    }
+
+    /// See [`Client::server_now`](crate::client::Client::server_now)
+    pub(crate) fn now(&self) -> DateTime<Utc> {
+        crate::deterministic::now() + self.offset()
+    }
+
+    /// Measure the skew from a response, that has been received at `received` for a request that has been sent at `sent`
+    fn observe(&self, response: &reqwest::Response, sent: DateTime<Utc>, received: DateTime<Utc>, config: &Config) {
+        let server_date = match response.headers().get(reqwest::header::DATE)
+            .and_then(|date| date.to_str().ok())
+            .and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+        {
+            None => return,
+            Some(date) => date.with_timezone(&Utc),
+        };
+        // The server has dated its response somewhere between these two instants
+        let skew = server_date - (sent + (received - sent) / 2);
+        *self.skew.lock().unwrap() = Some(skew);
+
+        let too_large = match Duration::from_std(config.clock_skew_tolerance) {
+            Err(_) => false,
+            Ok(tolerance) => skew > tolerance || -skew > tolerance,
+        };
+        if too_large && self.warned.swap(true, Ordering::Relaxed) == false {
+            log::warn!(target: crate::logging::CLIENT, "The clock of the server is {} seconds {} the local clock. Dates that are compared across both clocks may be wrong",
+                skew.num_seconds().abs(), if skew > Duration::zero() { "ahead of" } else { "behind" });
+        }
+
+        if config.compensate_clock_skew {
+            let wanted = if too_large { skew } else { Duration::zero() };
+            let mut compensated = self.compensated.lock().unwrap();
+            // The `Date` header is only precise to the second
+            if (wanted - compensated.unwrap_or_else(Duration::zero)).num_seconds().abs() >= 2 {
+                log::info!(target: crate::logging::CLIENT, "Shifting the dates sent to the server by {} seconds to match its clock", wanted.num_seconds());
+                *compensated = Some(wanted);
+            }
+        }
+    }
 }
 
 impl Resource {
     pub fn new(url: Url, username: String, password: String) -> Self {
-        Self { url, username, password }
+        Self::new_with_config(url, username, password, Arc::new(Config::default()))
+    }
+
+    pub(crate) fn new_with_config(url: Url, username: String, password: String, config: Arc<Config>) -> Self {
+        Self {
+            url, username, password, config,
+            #[cfg(feature = "client")]
+            http_client: Arc::new(OnceCell::new()),
+            #[cfg(feature = "client")]
+            server_clock: Arc::new(ServerClock::default()),
+        }
     }
 
     pub fn url(&self) -> &Url { &self.url }
     pub fn username(&self) -> &String { &self.username }
     pub fn password(&self) -> &String { &self.password }
+    pub fn config(&self) -> &Config { &self.config }
 
     /// Build a new Resource by keeping the same credentials, scheme and server from `base` but changing the path part
     pub fn combine(&self, new_path: &str) -> Resource {
@@ -23,4 +118,42 @@ impl Resource {
         built.url.set_path(&new_path);
         built
     }
+
+    /// Build a new Resource by keeping the same credentials and configuration, but changing the URL
+    #[cfg(feature = "client")]
+    pub(crate) fn with_url(&self, url: Url) -> Resource {
+        let mut built = (*self).clone();
+        built.url = url;
+        built
+    }
+
+    /// The HTTP client to send requests with (built on first use, see [`Config::http_client`])
+    #[cfg(feature = "client")]
+    pub(crate) fn http_client(&self) -> reqwest::Client {
+        self.http_client.get_or_init(|| self.config.http_client()).clone()
+    }
+
+    /// What is known about the clock of the server
+    #[cfg(feature = "client")]
+    pub(crate) fn server_clock(&self) -> &ServerClock {
+        &self.server_clock
+    }
+
+    /// Send an HTTP request, see [`Config::send`]. The clock of the server is measured from its response
+    #[cfg(feature = "client")]
+    pub(crate) async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response, reqwest::Error> {
+        let sent = Utc::now();
+        let response = self.config.send(request).await?;
+        self.server_clock.observe(&response, sent, Utc::now(), &self.config);
+        Ok(response)
+    }
+
+    /// Send an HTTP request only once, see [`Config::send_once`]. The clock of the server is measured from its response
+    #[cfg(feature = "client")]
+    pub(crate) async fn send_once(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response, reqwest::Error> {
+        let sent = Utc::now();
+        let response = self.config.send_once(request).await?;
+        self.server_clock.observe(&response, sent, Utc::now(), &self.config);
+        Ok(response)
+    }
 }
@@ -0,0 +1,192 @@
+//! CalDAV scheduling (iTIP messages exchanged through the scheduling inbox and outbox, see [RFC 6638](https://datatracker.ietf.org/doc/html/rfc6638))
+
+use std::error::Error;
+
+use url::Url;
+
+use crate::item::VersionTag;
+
+/// The participation status of an attendee (the `PARTSTAT` parameter of RFC 5545)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PartStat {
+    NeedsAction,
+    Accepted,
+    Declined,
+    Tentative,
+}
+
+impl PartStat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PartStat::NeedsAction => "NEEDS-ACTION",
+            PartStat::Accepted => "ACCEPTED",
+            PartStat::Declined => "DECLINED",
+            PartStat::Tentative => "TENTATIVE",
+        }
+    }
+}
+
+/// A scheduling message (e.g. an invitation) that has been delivered to the scheduling inbox
+#[derive(Clone, Debug)]
+pub struct SchedulingMessage {
+    url: Url,
+    version_tag: VersionTag,
+    ical: String,
+}
+
+impl SchedulingMessage {
+    pub fn new(url: Url, version_tag: VersionTag, ical: String) -> Self {
+        Self { url, version_tag, ical }
+    }
+
+    pub fn url(&self) -> &Url                   { &self.url }
+    pub fn version_tag(&self) -> &VersionTag    { &self.version_tag }
+    /// The raw iCal content of this message
+    pub fn ical(&self) -> &str                  { &self.ical }
+
+    /// The iTIP method of this message (e.g. `REQUEST`, `REPLY` or `CANCEL`)
+    pub fn method(&self) -> Option<&str> {
+        self.property("METHOD")
+    }
+
+    /// The UID of the scheduled item this message is about
+    pub fn uid(&self) -> Option<&str> {
+        self.property("UID")
+    }
+
+    fn property(&self, name: &str) -> Option<&str> {
+        self.ical.lines()
+            .find_map(|line| line.strip_prefix(name).and_then(|rest| rest.strip_prefix(':')))
+            .map(|value| value.trim_end())
+    }
+}
+
+/// Set the participation status of an attendee in a scheduling object (e.g. to accept an invitation).
+///
+/// The attendee is identified by their calendar user address (e.g. `mailto:someone@example.com`).
+/// Any `RSVP` request for this attendee is removed, since this is the answer.
+pub fn set_partstat(ical: &str, attendee: &str, partstat: PartStat) -> Result<String, Box<dyn Error>> {
+    let mut found = false;
+    let mut lines = Vec::new();
+
+    for line in unfold(ical) {
+        match split_attendee(&line) {
+            Some((params, address)) if address.eq_ignore_ascii_case(attendee) => {
+                found = true;
+                let mut new_line = String::from("ATTENDEE");
+                for param in params.iter().filter(|p| {
+                    let upper = p.to_uppercase();
+                    upper.starts_with("PARTSTAT=") == false && upper.starts_with("RSVP=") == false
+                }) {
+                    new_line.push(';');
+                    new_line.push_str(param);
+                }
+                new_line.push_str(";PARTSTAT=");
+                new_line.push_str(partstat.as_str());
+                new_line.push(':');
+                new_line.push_str(address);
+                lines.push(new_line);
+            },
+            _ => lines.push(line),
+        }
+    }
+
+    if found == false {
+        return Err(format!("{} is not an attendee of this item", attendee).into());
+    }
+
+    // The attendee line may now be too long to fit on a single line
+    let mut result = lines.iter()
+        .map(|line| crate::ical::fold_line(line))
+        .collect::<Vec<_>>()
+        .join("\r\n");
+    result.push_str("\r\n");
+    Ok(result)
+}
+
+/// Unfold the content lines of an iCal file (see RFC 5545, section 3.1)
+fn unfold(ical: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in ical.lines() {
+        match (line.strip_prefix(' ').or_else(|| line.strip_prefix('\t')), lines.last_mut()) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+/// Split an `ATTENDEE` line into its parameters and its value
+fn split_attendee(line: &str) -> Option<(Vec<&str>, &str)> {
+    let rest = line.strip_prefix("ATTENDEE")?;
+    if rest.starts_with(';') == false && rest.starts_with(':') == false {
+        return None;
+    }
+
+    // Parameter values may contain colons and semicolons, as long as they are quoted
+    let mut in_quotes = false;
+    let mut params = Vec::new();
+    let mut param_start = None;
+    for (i, c) in rest.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ';' | ':' if in_quotes == false => {
+                if let Some(start) = param_start {
+                    params.push(&rest[start..i]);
+                }
+                if c == ':' {
+                    return Some((params, &rest[i+1..]));
+                }
+                param_start = Some(i + 1);
+            },
+            _ => (),
+        }
+    }
+    None
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INVITATION: &str = "BEGIN:VCALENDAR\r\n\
+        VERSION:2.0\r\n\
+        PRODID:-//Example Corp.//CalDAV Server//EN\r\n\
+        METHOD:REQUEST\r\n\
+        BEGIN:VEVENT\r\n\
+        UID:meeting@example.com\r\n\
+        DTSTAMP:20210321T001600Z\r\n\
+        DTSTART:20210322T090000Z\r\n\
+        SUMMARY:Team meeting\r\n\
+        ORGANIZER;CN=\"Boss: the one\":mailto:boss@example.com\r\n\
+        ATTENDEE;CN=Me;PARTSTAT=NEEDS-ACTION;RSVP=TRUE:mailto:me@exa\r\n mple.com\r\n\
+        ATTENDEE;PARTSTAT=ACCEPTED:mailto:colleague@example.com\r\n\
+        END:VEVENT\r\n\
+        END:VCALENDAR\r\n";
+
+    #[test]
+    fn test_message_properties() {
+        let message = SchedulingMessage::new("https://some.server/inbox/1.ics".parse().unwrap(), VersionTag::from(String::from("tag")), INVITATION.to_string());
+        assert_eq!(message.method(), Some("REQUEST"));
+        assert_eq!(message.uid(), Some("meeting@example.com"));
+    }
+
+    #[test]
+    fn test_set_partstat() {
+        let replied = set_partstat(INVITATION, "MAILTO:me@example.com", PartStat::Accepted).unwrap();
+        assert!(replied.contains("ATTENDEE;CN=Me;PARTSTAT=ACCEPTED:mailto:me@example.com\r\n"));
+        assert!(replied.contains("ATTENDEE;PARTSTAT=ACCEPTED:mailto:colleague@example.com\r\n"));
+        assert!(replied.contains("ORGANIZER;CN=\"Boss: the one\":mailto:boss@example.com\r\n"));
+
+        assert!(set_partstat(INVITATION, "mailto:someone-else@example.com", PartStat::Declined).is_err());
+    }
+
+    #[test]
+    fn test_set_partstat_folds_long_lines() {
+        let invitation = INVITATION.replace("ATTENDEE;CN=Me;", "ATTENDEE;CN=\"Me, with a rather long common name\";ROLE=REQ-PARTICIPANT;");
+        let replied = set_partstat(&invitation, "mailto:me@example.com", PartStat::Tentative).unwrap();
+        assert!(replied.lines().all(|line| line.len() <= 75));
+        assert!(replied.replace("\r\n ", "").contains(";PARTSTAT=TENTATIVE:mailto:me@example.com\r\n"));
+    }
+}
@@ -0,0 +1,103 @@
+//! Detection of the server implementation, in order to work around its quirks
+#![cfg(feature = "client")]
+
+use reqwest::header::HeaderMap;
+use url::Url;
+
+/// The known CalDAV server implementations
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ServerProfile {
+    /// Nextcloud (and ownCloud), that are based on SabreDAV
+    Nextcloud,
+    /// Another SabreDAV-based server
+    SabreDav,
+    Radicale,
+    Baikal,
+    Xandikos,
+    ICloud,
+    Google,
+    /// An unknown server, that is expected to follow the RFCs
+    Generic,
+}
+
+/// The behaviours that differ from a server implementation to another
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerQuirks {
+    /// Whether the server supports the `calendar-proxy` extension (to discover delegated calendars)
+    pub calendar_proxy: bool,
+    /// Whether the server lets clients create calendars
+    pub calendar_creation: bool,
+}
+
+impl ServerProfile {
+    /// Guess the server implementation from its URL and the headers of a reply (typically to an `OPTIONS` request)
+    pub fn detect(url: &Url, headers: &HeaderMap) -> Self {
+        let header = |name: &str| headers.get_all(name).iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect::<Vec<_>>()
+            .join(", ")
+            .to_lowercase();
+        let server = header("server");
+        let dav = header("dav");
+        let host = url.host_str().unwrap_or_default().to_lowercase();
+        let path = url.path();
+
+        if host.ends_with("icloud.com") {
+            ServerProfile::ICloud
+        } else if host.ends_with("google.com") || host.ends_with("googleusercontent.com") {
+            ServerProfile::Google
+        } else if server.contains("radicale") {
+            ServerProfile::Radicale
+        } else if server.contains("xandikos") {
+            ServerProfile::Xandikos
+        } else if dav.contains("nextcloud") || dav.contains("oc-resource-sharing") || path.contains("remote.php/") {
+            ServerProfile::Nextcloud
+        } else if path.contains("dav.php") || path.contains("cal.php") {
+            ServerProfile::Baikal
+        } else if headers.contains_key("x-sabre-version") || dav.contains("calendarserver-principal-property-search") {
+            ServerProfile::SabreDav
+        } else {
+            ServerProfile::Generic
+        }
+    }
+
+    pub fn quirks(&self) -> ServerQuirks {
+        let calendar_proxy = matches!(self, ServerProfile::Nextcloud | ServerProfile::SabreDav | ServerProfile::Baikal | ServerProfile::ICloud | ServerProfile::Generic);
+
+        ServerQuirks {
+            calendar_proxy,
+            // Google calendars can only be created with the Google Calendar API
+            calendar_creation: *self != ServerProfile::Google,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.append(*name, HeaderValue::from_static(value));
+        }
+        map
+    }
+
+    #[test]
+    fn test_detect() {
+        let url = |s: &str| Url::parse(s).unwrap();
+
+        assert_eq!(ServerProfile::detect(&url("https://cloud.example.com/remote.php/dav/"), &headers(&[])), ServerProfile::Nextcloud);
+        assert_eq!(ServerProfile::detect(&url("https://dav.example.com/"), &headers(&[("DAV", "1, 3, extended-mkcol, nextcloud-checksum-update")])), ServerProfile::Nextcloud);
+        assert_eq!(ServerProfile::detect(&url("https://example.com/baikal/html/dav.php/"), &headers(&[])), ServerProfile::Baikal);
+        assert_eq!(ServerProfile::detect(&url("https://example.com:5232/"), &headers(&[("Server", "WSGIServer/0.2 CPython/3.9.2"), ("Server", "Radicale/3.1.8")])), ServerProfile::Radicale);
+        assert_eq!(ServerProfile::detect(&url("https://example.com/"), &headers(&[("Server", "Xandikos/0.2.8")])), ServerProfile::Xandikos);
+        assert_eq!(ServerProfile::detect(&url("https://caldav.icloud.com/"), &headers(&[])), ServerProfile::ICloud);
+        assert_eq!(ServerProfile::detect(&url("https://apidata.googleusercontent.com/caldav/v2/"), &headers(&[])), ServerProfile::Google);
+        assert_eq!(ServerProfile::detect(&url("https://example.com/dav/"), &headers(&[("X-Sabre-Version", "4.1.5")])), ServerProfile::SabreDav);
+        assert_eq!(ServerProfile::detect(&url("https://example.com/dav/"), &headers(&[("DAV", "1, 2, access-control, calendar-access")])), ServerProfile::Generic);
+    }
+}
@@ -1,13 +1,21 @@
 //! To-do tasks (iCal `VTODO` item)
 
+use std::error::Error;
+use std::sync::Arc;
+
 use serde::{Deserialize, Serialize};
-use uuid::Uuid;
 use chrono::{DateTime, Utc};
 use ical::property::Property;
 use url::Url;
 
 use crate::item::SyncStatus;
 use crate::utils::random_url;
+use crate::utils::intern::intern;
+use crate::config::Config;
+use crate::deterministic;
+use crate::alarm::Alarm;
+use crate::utils::time_zone::IcalDateTime;
+use chrono_tz::Tz;
 
 /// RFC5545 defines the completion as several optional fields, yet some combinations make no sense.
 /// This enum provides an API that forbids such impossible combinations.
@@ -27,6 +35,14 @@ impl CompletionStatus {
             _ => false,
         }
     }
+
+    /// The date this task has been completed, if it is completed and this date is known
+    pub fn completion_date(&self) -> Option<&DateTime<Utc>> {
+        match self {
+            CompletionStatus::Completed(date) => date.as_ref(),
+            _ => None,
+        }
+    }
 }
 
 /// A to-do task
@@ -45,8 +61,15 @@ pub struct Task {
     /// The time this item was created.
     /// This is not required by RFC5545. This will be populated in tasks created by this crate, but can be None for tasks coming from a server
     creation_date: Option<DateTime<Utc>>,
-    /// The last time this item was modified
+    /// The last time this item was modified (the `LAST-MODIFIED` property)
     last_modified: DateTime<Utc>,
+    /// The `DTSTAMP` property, in case it differs from `last_modified`.
+    /// For items that are stored in a calendar, RFC5545 makes it equivalent to `LAST-MODIFIED`, but some servers and clients set it differently, and it must be kept as-is
+    #[serde(default)]
+    dtstamp: Option<DateTime<Utc>>,
+    /// The revision number of this task (the `SEQUENCE` property), if any
+    #[serde(default)]
+    sequence: Option<u32>,
     /// The completion status of this task
     completion_status: CompletionStatus,
 
@@ -54,12 +77,23 @@ pub struct Task {
     name: String,
 
 
-    /// The PRODID, as defined in iCal files
-    ical_prod_id: String,
+    /// The PRODID, as defined in iCal files.
+    /// It is usually the same for every task, so it is interned
+    #[serde(deserialize_with = "crate::utils::intern::deserialize")]
+    ical_prod_id: Arc<str>,
 
     /// Extra parameters that have not been parsed from the iCal file (because they're not supported (yet) by this crate).
     /// They are needed to serialize this item into an equivalent iCal file
     extra_parameters: Vec<Property>,
+
+    /// In case this task is recurrent, the instances that differ from what the recurrence rule defines.
+    /// They share the UID of this task, and have a `RECURRENCE-ID`
+    #[serde(default)]
+    overrides: Vec<Task>,
+
+    /// The alarms of this task (its `VALARM` components)
+    #[serde(default)]
+    alarms: Vec<Alarm>,
 }
 
 
@@ -67,15 +101,22 @@ impl Task {
     /// Create a brand new Task that is not on a server yet.
     /// This will pick a new (random) task ID.
     pub fn new(name: String, completed: bool, parent_calendar_url: &Url) -> Self {
+        Self::new_with_config(name, completed, parent_calendar_url, &Config::default())
+    }
+
+    /// Create a brand new Task that is not on a server yet, advertising the ProdID of `config`.
+    /// This will pick a new (random) task ID.
+    pub fn new_with_config(name: String, completed: bool, parent_calendar_url: &Url, config: &Config) -> Self {
         let new_url = random_url(parent_calendar_url);
         let new_sync_status = SyncStatus::NotSynced;
-        let new_uid = Uuid::new_v4().to_hyphenated().to_string();
-        let new_creation_date = Some(Utc::now());
-        let new_last_modified = Utc::now();
+        let new_uid = deterministic::new_uuid().to_hyphenated().to_string();
+        let now = deterministic::now();
+        let new_creation_date = Some(now);
+        let new_last_modified = now;
         let new_completion_status = if completed {
-                CompletionStatus::Completed(Some(Utc::now()))
+                CompletionStatus::Completed(Some(now))
             } else { CompletionStatus::Uncompleted };
-        let ical_prod_id = crate::ical::default_prod_id();
+        let ical_prod_id = config.prod_id();
         let extra_parameters = Vec::new();
         Self::new_with_parameters(name, new_uid, new_url, new_completion_status, new_sync_status, new_creation_date, new_last_modified, ical_prod_id, extra_parameters)
     }
@@ -95,8 +136,12 @@ impl Task {
             sync_status,
             creation_date,
             last_modified,
-            ical_prod_id,
+            dtstamp: None,
+            sequence: None,
+            ical_prod_id: intern(&ical_prod_id),
             extra_parameters,
+            overrides: Vec::new(),
+            alarms: Vec::new(),
         }
     }
 
@@ -107,11 +152,223 @@ impl Task {
     pub fn ical_prod_id(&self) -> &str            { &self.ical_prod_id }
     pub fn sync_status(&self) -> &SyncStatus      { &self.sync_status  }
     pub fn last_modified(&self) -> &DateTime<Utc> { &self.last_modified }
+    /// The `DTSTAMP` of this task. It is usually the same as its [`last_modified`](Self::last_modified) date
+    pub fn dtstamp(&self) -> &DateTime<Utc>       { self.dtstamp.as_ref().unwrap_or(&self.last_modified) }
+    /// The revision number of this task (the `SEQUENCE` property), if any
+    pub fn sequence(&self) -> Option<u32>         { self.sequence }
     pub fn creation_date(&self) -> Option<&DateTime<Utc>>   { self.creation_date.as_ref() }
     pub fn completion_status(&self) -> &CompletionStatus    { &self.completion_status }
     pub fn extra_parameters(&self) -> &[Property]           { &self.extra_parameters }
+    /// The overridden instances of this task, in case it is recurrent
+    pub fn overrides(&self) -> &[Task]                      { &self.overrides }
+    /// The alarms of this task. See the [`alarm`](crate::alarm) module to know when they are triggered
+    pub fn alarms(&self) -> &[Alarm]                        { &self.alarms }
+
+    /// The start date of this task (the `DTSTART` property), if any
+    pub fn start(&self) -> Option<DateTime<Utc>> {
+        self.zoned_start().map(|start| start.to_utc())
+    }
+
+    /// The start date of this task, for a user in a given time zone (see [`IcalDateTime::to_utc_in`])
+    pub fn start_in(&self, local_zone: &Tz) -> Option<DateTime<Utc>> {
+        self.zoned_start().map(|start| start.to_utc_in(local_zone))
+    }
+
+    /// The start date of this task, as it is stored (e.g. in a given time zone)
+    pub fn zoned_start(&self) -> Option<IcalDateTime> {
+        self.date_property("DTSTART")
+    }
+
+    /// The due date of this task (the `DUE` property), if any
+    pub fn due(&self) -> Option<DateTime<Utc>> {
+        self.zoned_due().map(|due| due.to_utc())
+    }
+
+    /// The due date of this task, for a user in a given time zone (see [`IcalDateTime::to_utc_in`])
+    pub fn due_in(&self, local_zone: &Tz) -> Option<DateTime<Utc>> {
+        self.zoned_due().map(|due| due.to_utc_in(local_zone))
+    }
+
+    /// The due date of this task, as it is stored (e.g. in a given time zone)
+    pub fn zoned_due(&self) -> Option<IcalDateTime> {
+        self.date_property("DUE")
+    }
+
+    /// The `RECURRENCE-ID` of this task, in case it is an overridden instance of a recurrent task (see [`Task::overrides`])
+    pub fn recurrence_id(&self) -> Option<DateTime<Utc>> {
+        self.date_property("RECURRENCE-ID").map(|recurrence_id| recurrence_id.to_utc())
+    }
+
+    /// Whether this task is recurrent (i.e. it has a `RRULE` or a `RDATE`)
+    pub fn is_recurrent(&self) -> bool {
+        self.extra_parameters.iter().any(|prop| prop.name == "RRULE" || prop.name == "RDATE")
+    }
+
+    /// The occurrences of this recurrent task that have been deleted (the `EXDATE` properties)
+    pub fn excluded_dates(&self) -> Vec<DateTime<Utc>> {
+        self.extra_parameters.iter()
+            .filter(|prop| prop.name == "EXDATE")
+            .flat_map(|prop| {
+                // A single EXDATE may contain several dates, that share the same parameters (e.g. the same TZID)
+                prop.value.as_deref().unwrap_or_default().split(',').filter_map(move |value| {
+                    let mut single = prop.clone();
+                    single.value = Some(value.to_string());
+                    IcalDateTime::from_property(&single)
+                })
+            })
+            .map(|date| date.to_utc())
+            .collect()
+    }
+
+    /// The overridden instance of this recurrent task for a given occurrence, if any
+    pub fn overridden_occurrence(&self, recurrence_id: &DateTime<Utc>) -> Option<&Task> {
+        self.overrides.iter().find(|ov| ov.recurrence_id().as_ref() == Some(recurrence_id))
+    }
+
+    /// The priority of this task (the `PRIORITY` property), from 1 (highest) to 9 (lowest). `None` in case it is undefined
+    pub fn priority(&self) -> Option<u8> {
+        self.extra_parameters.iter()
+            .find(|prop| prop.name == "PRIORITY")
+            .and_then(|prop| prop.value.as_deref())
+            .and_then(|value| value.trim().parse().ok())
+            .filter(|priority| *priority != 0)
+    }
 
-    #[cfg(any(test, feature = "integration_tests"))]
+    /// The categories of this task (the `CATEGORIES` properties)
+    pub fn categories(&self) -> Vec<&str> {
+        self.extra_parameters.iter()
+            .filter(|prop| prop.name == "CATEGORIES")
+            .filter_map(|prop| prop.value.as_deref())
+            .flat_map(|value| value.split(','))
+            .map(|category| category.trim())
+            .filter(|category| category.is_empty() == false)
+            .collect()
+    }
+
+    /// Set the `DTSTAMP` of this task, in case it differs from its last modification date (e.g. for a task that comes from a server)
+    pub fn with_dtstamp(mut self, dtstamp: DateTime<Utc>) -> Self {
+        self.dtstamp = match dtstamp == self.last_modified {
+            true => None,
+            false => Some(dtstamp),
+        };
+        self
+    }
+
+    /// Set the revision number of this task (e.g. for a task that comes from a server)
+    pub fn with_sequence(mut self, sequence: Option<u32>) -> Self {
+        self.sequence = sequence;
+        self
+    }
+
+    /// Set the alarms of this task
+    pub fn with_alarms(mut self, alarms: Vec<Alarm>) -> Self {
+        self.alarms = alarms;
+        self
+    }
+
+    pub(crate) fn set_overrides(&mut self, overrides: Vec<Task>) {
+        self.overrides = overrides;
+    }
+
+    /// Modify a single occurrence of this recurrent task.
+    ///
+    /// `recurrence_id` is the original start date of this occurrence. In case it has not been overridden yet, a new overridden instance is created (see [`Task::overrides`]),
+    /// as a copy of this task that starts at `recurrence_id`. `modify` is then called on this instance. \
+    /// This fails in case this task is not recurrent, or in case this occurrence has been deleted.
+    ///
+    /// ```
+    /// # use chrono::{TimeZone, Utc};
+    /// # use kitchen_fridge::ical::parse;
+    /// # use kitchen_fridge::item::SyncStatus;
+    /// # let ical = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//Example Corp.//CalDAV Client//EN\r\nBEGIN:VTODO\r\nUID:weekly-review@example.com\r\n\
+    /// #     DTSTAMP:20210321T001600Z\r\nDTSTART:20210322T090000Z\r\nRRULE:FREQ=WEEKLY\r\nSUMMARY:Weekly review\r\nEND:VTODO\r\nEND:VCALENDAR\r\n";
+    /// # let mut item = parse(ical, "https://my.server.com/review.ics".parse().unwrap(), SyncStatus::NotSynced).unwrap();
+    /// let task = item.unwrap_task_mut();
+    /// let second_week = Utc.ymd(2021, 3, 29).and_hms(9, 0, 0);
+    /// task.modify_occurrence(second_week, |occurrence| occurrence.set_name(String::from("Weekly review (longer this time)"))).unwrap();
+    /// assert_eq!(task.overridden_occurrence(&second_week).unwrap().name(), "Weekly review (longer this time)");
+    ///
+    /// let third_week = Utc.ymd(2021, 4, 5).and_hms(9, 0, 0);
+    /// task.delete_occurrence(third_week).unwrap();
+    /// assert_eq!(task.excluded_dates(), vec![third_week]);
+    /// ```
+    pub fn modify_occurrence<F: FnOnce(&mut Task)>(&mut self, recurrence_id: DateTime<Utc>, modify: F) -> Result<(), Box<dyn Error>> {
+        if self.is_recurrent() == false {
+            return Err(format!("Task {} is not recurrent", self.uid).into());
+        }
+        if self.excluded_dates().contains(&recurrence_id) {
+            return Err(format!("Occurrence {} of task {} has been deleted", recurrence_id, self.uid).into());
+        }
+
+        let index = match self.overrides.iter().position(|ov| ov.recurrence_id() == Some(recurrence_id)) {
+            Some(index) => index,
+            None => {
+                let new_override = self.new_override(recurrence_id);
+                self.overrides.push(new_override);
+                self.overrides.len() - 1
+            },
+        };
+        modify(&mut self.overrides[index]);
+
+        // The overridden instance is part of this item, that must now be pushed to the server
+        self.update_sync_status();
+        self.update_last_modified();
+        Ok(())
+    }
+
+    /// Delete a single occurrence of this recurrent task.
+    ///
+    /// `recurrence_id` is the original start date of this occurrence. It is added as an `EXDATE`, and its overridden instance (if any) is removed.
+    /// This fails in case this task is not recurrent.
+    pub fn delete_occurrence(&mut self, recurrence_id: DateTime<Utc>) -> Result<(), Box<dyn Error>> {
+        if self.is_recurrent() == false {
+            return Err(format!("Task {} is not recurrent", self.uid).into());
+        }
+        self.overrides.retain(|ov| ov.recurrence_id() != Some(recurrence_id));
+        if self.excluded_dates().contains(&recurrence_id) == false {
+            let exdate = self.recurrence_date_property("EXDATE", &recurrence_id);
+            self.extra_parameters.push(exdate);
+        }
+
+        self.update_sync_status();
+        self.update_last_modified();
+        Ok(())
+    }
+
+    /// Create an overridden instance for a given occurrence, with the same content as this task
+    fn new_override(&self, recurrence_id: DateTime<Utc>) -> Task {
+        let mut new_override = self.clone();
+        new_override.overrides = Vec::new();
+
+        // This instance starts at the date of the occurrence, and its due date (if any) moves along, on the wall clock (and it stays floating, if it is)
+        let shift = self.zoned_start().map(|start| start.same_kind(&recurrence_id).naive_local() - start.naive_local());
+        new_override.extra_parameters = self.extra_parameters.iter()
+            .filter(|prop| ["RRULE", "RDATE", "EXDATE", "RECURRENCE-ID"].contains(&prop.name.as_str()) == false)
+            .map(|prop| match (prop.name.as_str(), shift, self.zoned_due()) {
+                ("DTSTART", _, _) => self.recurrence_date_property("DTSTART", &recurrence_id),
+                ("DUE", Some(shift), Some(due)) => due.add_wall_clock(shift).to_property("DUE"),
+                _ => prop.clone(),
+            })
+            .collect();
+        new_override.extra_parameters.push(self.recurrence_date_property("RECURRENCE-ID", &recurrence_id));
+        new_override
+    }
+
+    /// RFC5545 requires `RECURRENCE-ID` and `EXDATE` to have the same value type as `DTSTART` (either a `DATE` or a `DATE-TIME`, in the same time zone)
+    fn recurrence_date_property(&self, name: &str, date: &DateTime<Utc>) -> Property {
+        match self.zoned_start() {
+            Some(start) => start.same_kind(date).to_property(name),
+            None => IcalDateTime::Utc(*date).to_property(name),
+        }
+    }
+
+    fn date_property(&self, name: &str) -> Option<IcalDateTime> {
+        self.extra_parameters.iter()
+            .find(|prop| prop.name == name)
+            .and_then(IcalDateTime::from_property)
+    }
+
+    #[cfg(any(test, feature = "testing"))]
     pub fn has_same_observable_content_as(&self, other: &Task) -> bool {
            self.url == other.url
         && self.uid == other.uid
@@ -141,14 +398,28 @@ impl Task {
         }
     }
 
+    /// Record that the content of this task has changed: this updates its `LAST-MODIFIED` and `DTSTAMP` dates, and increments its `SEQUENCE`
     fn update_last_modified(&mut self) {
-        self.last_modified = Utc::now();
+        self.last_modified = deterministic::now();
+        self.dtstamp = None;
+
+        // Caches written by older versions of this crate store the SEQUENCE among the extra parameters
+        if self.sequence.is_none() {
+            if let Some(index) = self.extra_parameters.iter().position(|prop| prop.name == "SEQUENCE") {
+                let prop = self.extra_parameters.remove(index);
+                self.sequence = prop.value.and_then(|value| value.trim().parse().ok());
+            }
+        }
+        self.sequence = Some(self.sequence.unwrap_or(0) + 1);
     }
 
 
     /// Rename a task.
-    /// This updates its "last modified" field
+    /// This updates its "last modified" field, unless the name is unchanged
     pub fn set_name(&mut self, new_name: String) {
+        if new_name == self.name {
+            return;
+        }
         self.update_sync_status();
         self.update_last_modified();
         self.name = new_name;
@@ -161,12 +432,79 @@ impl Task {
         self.name = new_name;
     }
 
-    /// Set the completion status
+    /// Set the completion status.
+    /// This updates its "last modified" field, unless the completion status is unchanged
     pub fn set_completion_status(&mut self, new_completion_status: CompletionStatus) {
+        if new_completion_status == self.completion_status {
+            return;
+        }
         self.update_sync_status();
         self.update_last_modified();
         self.completion_status = new_completion_status;
     }
+
+    /// Merge `other`, a duplicate of this task (see [`Cache::duplicate_uids`](crate::cache::Cache::duplicate_uids)), into this task.
+    ///
+    /// This task keeps its URL and UID. The content of the most recently modified of both tasks wins, the properties only the other task has are added,
+    /// and the categories of both are kept. \
+    /// The other task is left unchanged, see [`CachedCalendar::merge_duplicates`](crate::calendar::cached_calendar::CachedCalendar::merge_duplicates) to also delete it
+    pub fn merge(&mut self, other: &Task) {
+        let (latest, oldest) = match other.last_modified > self.last_modified {
+            true => (other.clone(), self.clone()),
+            false => (self.clone(), other.clone()),
+        };
+
+        let mut categories: Vec<String> = Vec::new();
+        for category in latest.categories().into_iter().chain(oldest.categories()) {
+            if categories.iter().any(|known| known == category) == false {
+                categories.push(category.to_string());
+            }
+        }
+        let mut extra_parameters: Vec<Property> = latest.extra_parameters.iter()
+            .filter(|prop| prop.name != "CATEGORIES")
+            .cloned()
+            .collect();
+        for prop in &oldest.extra_parameters {
+            if prop.name != "CATEGORIES" && latest.extra_parameters.iter().any(|known| known.name == prop.name) == false {
+                extra_parameters.push(prop.clone());
+            }
+        }
+        if categories.is_empty() == false {
+            let mut prop = Property::new();
+            prop.name = String::from("CATEGORIES");
+            prop.value = Some(categories.join(","));
+            extra_parameters.push(prop);
+        }
+
+        self.update_sync_status();
+        self.sequence = self.sequence.max(other.sequence);
+        self.update_last_modified();
+        self.name = latest.name;
+        self.completion_status = latest.completion_status;
+        self.creation_date = match (self.creation_date, other.creation_date) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        self.extra_parameters = extra_parameters;
+        self.overrides = match latest.overrides.is_empty() {
+            true => oldest.overrides,
+            false => latest.overrides,
+        };
+        self.alarms = match latest.alarms.is_empty() {
+            true => oldest.alarms,
+            false => latest.alarms,
+        };
+    }
+
+    /// Turn this former version of a task into the latest version of `current` (see [`CachedCalendar::restore_item_version`](crate::calendar::cached_calendar::CachedCalendar::restore_item_version)).
+    ///
+    /// Its `SEQUENCE` goes past the one of `current` and its `LAST-MODIFIED` date is updated, so that other clients do not consider it outdated
+    #[cfg(feature = "cache")]
+    pub(crate) fn restore_over(&mut self, current: &Task) {
+        self.sequence = self.sequence.max(current.sequence);
+        self.update_last_modified();
+    }
+
     #[cfg(feature = "local_calendar_mocks_remote_calendars")]
     /// Set the completion status, but forces a "master" SyncStatus, just like CalDAV servers are always "masters"
     pub fn mock_remote_calendar_set_completion_status(&mut self, new_completion_status: CompletionStatus) {
@@ -174,3 +512,214 @@ impl Task {
         self.completion_status = new_completion_status;
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+    use crate::item::VersionTag;
+    use crate::test_utils::TaskBuilder;
+
+    #[test]
+    fn test_content_changes() {
+        let cal_url: Url = "https://some.calendar/".parse().unwrap();
+        let last_modified = Utc.ymd(2021, 3, 21).and_hms(9, 0, 0);
+        let dtstamp = Utc.ymd(2021, 3, 22).and_hms(9, 0, 0);
+        let synced = SyncStatus::Synced(VersionTag::from(String::from("some-tag")));
+        let mut task = TaskBuilder::new("Buy milk", &cal_url).sync_status(synced.clone()).last_modified(last_modified).build()
+            .with_dtstamp(dtstamp)
+            .with_sequence(Some(2));
+
+        // Setting the same content is not a change
+        task.set_name(String::from("Buy milk"));
+        task.set_completion_status(CompletionStatus::Uncompleted);
+        assert_eq!(task.last_modified(), &last_modified);
+        assert_eq!(task.dtstamp(), &dtstamp);
+        assert_eq!(task.sequence(), Some(2));
+        assert_eq!(task.sync_status(), &synced);
+
+        task.set_name(String::from("Buy oat milk"));
+        assert!(task.last_modified() > &dtstamp);
+        assert_eq!(task.dtstamp(), task.last_modified());
+        assert_eq!(task.sequence(), Some(3));
+        assert!(matches!(task.sync_status(), SyncStatus::LocallyModified(_)));
+
+        task.set_completion_status(CompletionStatus::Completed(None));
+        assert_eq!(task.sequence(), Some(4));
+    }
+
+    #[test]
+    fn test_merge() {
+        let cal_url: Url = "https://some.calendar/".parse().unwrap();
+        let synced = SyncStatus::Synced(VersionTag::from(String::from("some-tag")));
+        let mut kept = TaskBuilder::new("Buy milk", &cal_url).uid("some-uid").url(cal_url.join("kept").unwrap())
+            .sync_status(synced).created(Utc.ymd(2021, 3, 20).and_hms(9, 0, 0)).last_modified(Utc.ymd(2021, 3, 21).and_hms(9, 0, 0))
+            .property("CATEGORIES", "Shopping,Food").property("PRIORITY", "1").property("LOCATION", "Home")
+            .build()
+            .with_sequence(Some(1));
+        let duplicate = TaskBuilder::new("Buy oat milk", &cal_url).uid("some-uid").url(cal_url.join("duplicate").unwrap()).completed(true)
+            .created(Utc.ymd(2021, 3, 21).and_hms(9, 0, 0)).last_modified(Utc.ymd(2021, 3, 22).and_hms(9, 0, 0))
+            .property("CATEGORIES", "Groceries,Food").property("PRIORITY", "5")
+            .build()
+            .with_sequence(Some(3));
+
+        kept.merge(&duplicate);
+        assert_eq!(kept.url(), &cal_url.join("kept").unwrap());
+        assert!(matches!(kept.sync_status(), SyncStatus::LocallyModified(_)));
+        assert_eq!(kept.sequence(), Some(4));
+        assert_eq!(kept.creation_date(), Some(&Utc.ymd(2021, 3, 20).and_hms(9, 0, 0)));
+        // The duplicate has been modified more recently
+        assert_eq!(kept.name(), "Buy oat milk");
+        assert!(kept.completed());
+        assert_eq!(kept.priority(), Some(5));
+        // Properties that only one task has are kept, and so are every categories
+        assert!(kept.extra_parameters().iter().any(|prop| prop.name == "LOCATION"));
+        assert_eq!(kept.categories(), vec!["Groceries", "Food", "Shopping"]);
+    }
+
+    #[test]
+    fn test_legacy_sequence() {
+        let cal_url: Url = "https://some.calendar/".parse().unwrap();
+        let mut task = TaskBuilder::new("Buy milk", &cal_url).property("SEQUENCE", "7").build();
+
+        task.set_name(String::from("Buy oat milk"));
+        assert_eq!(task.sequence(), Some(8));
+        assert!(task.extra_parameters().is_empty());
+    }
+
+    #[test]
+    fn test_shared_prod_id() {
+        let first = recurrent_task("DTSTART:20210315T100000Z");
+        let second = recurrent_task("DTSTART:20210316T100000Z");
+        assert_eq!(first.ical_prod_id(), "-//Example Corp.//CalDAV Client//EN");
+        assert!(Arc::ptr_eq(&first.ical_prod_id, &second.ical_prod_id));
+    }
+
+    fn recurrent_task(dtstart: &str) -> Task {
+        let ical = format!("BEGIN:VCALENDAR\r\n\
+            VERSION:2.0\r\n\
+            PRODID:-//Example Corp.//CalDAV Client//EN\r\n\
+            BEGIN:VTODO\r\n\
+            UID:weekly-review@example.com\r\n\
+            DTSTAMP:20210321T001600Z\r\n\
+            {}\r\n\
+            DUE:20210322T100000Z\r\n\
+            RRULE:FREQ=WEEKLY\r\n\
+            SUMMARY:Weekly review\r\n\
+            END:VTODO\r\n\
+            END:VCALENDAR\r\n", dtstart);
+        let synced = SyncStatus::Synced(VersionTag::from(String::from("some-tag")));
+        let item = crate::ical::parse(&ical, "https://some.calendar/review.ics".parse().unwrap(), synced).unwrap();
+        item.unwrap_task().clone()
+    }
+
+    #[test]
+    fn test_occurrence_editing() {
+        let mut task = recurrent_task("DTSTART:20210322T090000Z");
+        let second_week = Utc.ymd(2021, 3, 29).and_hms(9, 0, 0);
+        let third_week = Utc.ymd(2021, 4, 5).and_hms(9, 0, 0);
+
+        let (sequence, last_modified) = (task.sequence().unwrap_or(0), *task.last_modified());
+        task.modify_occurrence(second_week, |occurrence| occurrence.set_name(String::from("Weekly review (longer this time)"))).unwrap();
+        assert!(matches!(task.sync_status(), SyncStatus::LocallyModified(_)));
+        assert_eq!(task.sequence(), Some(sequence + 1));
+        assert!(task.last_modified() > &last_modified);
+        assert_eq!(task.name(), "Weekly review");
+        let occurrence = task.overridden_occurrence(&second_week).unwrap();
+        assert_eq!(occurrence.name(), "Weekly review (longer this time)");
+        assert_eq!(occurrence.start(), Some(second_week));
+        assert_eq!(occurrence.due(), Some(Utc.ymd(2021, 3, 29).and_hms(10, 0, 0)));
+        assert!(occurrence.is_recurrent() == false);
+
+        // Modifying it again does not create another instance
+        task.modify_occurrence(second_week, |occurrence| occurrence.set_completion_status(CompletionStatus::Completed(None))).unwrap();
+        assert_eq!(task.overrides().len(), 1);
+        assert!(task.overrides()[0].completed());
+
+        task.delete_occurrence(third_week).unwrap();
+        task.delete_occurrence(second_week).unwrap();
+        assert!(task.overrides().is_empty());
+        assert_eq!(task.excluded_dates(), vec![third_week, second_week]);
+        assert!(task.modify_occurrence(second_week, |_| ()).is_err());
+
+        let mut not_recurrent = Task::new(String::from("Buy milk"), false, &"https://some.calendar/".parse().unwrap());
+        assert!(not_recurrent.modify_occurrence(second_week, |_| ()).is_err());
+        assert!(not_recurrent.delete_occurrence(second_week).is_err());
+    }
+
+    #[test]
+    fn test_occurrence_serialization() {
+        let url: Url = "https://some.calendar/review.ics".parse().unwrap();
+        for (dtstart, recurrence_id, exdate) in [
+            ("DTSTART:20210322T090000Z", "RECURRENCE-ID:20210329T090000Z", "EXDATE:20210405T090000Z"),
+            ("DTSTART:20210322T090000", "RECURRENCE-ID:20210329T090000", "EXDATE:20210405T090000"),
+            ("DTSTART;VALUE=DATE:20210322", "RECURRENCE-ID;VALUE=DATE:20210329", "EXDATE;VALUE=DATE:20210405"),
+            // Daylight saving time starts in between, but occurrences are still at 9:00 local time
+            ("DTSTART;TZID=Europe/Paris:20210322T090000", "RECURRENCE-ID;TZID=Europe/Paris:20210329T090000", "EXDATE;TZID=Europe/Paris:20210405T090000"),
+        ] {
+            let mut task = recurrent_task(dtstart);
+            let start = task.zoned_start().unwrap();
+            let second_week = start.add_wall_clock(chrono::Duration::weeks(1)).to_utc();
+            let third_week = start.add_wall_clock(chrono::Duration::weeks(2)).to_utc();
+            task.modify_occurrence(second_week, |occurrence| occurrence.set_name(String::from("Moved"))).unwrap();
+            task.delete_occurrence(third_week).unwrap();
+
+            let ical = crate::ical::build_from(&crate::Item::Task(task)).unwrap();
+            assert!(ical.contains(&format!("\r\n{}\r\n", recurrence_id)), "{} not in {}", recurrence_id, ical);
+            assert!(ical.contains(&format!("\r\n{}\r\n", exdate)), "{} not in {}", exdate, ical);
+
+            let reparsed = crate::ical::parse(&ical, url.clone(), SyncStatus::NotSynced).unwrap();
+            let reparsed = reparsed.unwrap_task();
+            assert_eq!(reparsed.overrides().len(), 1);
+            assert_eq!(reparsed.overrides()[0].name(), "Moved");
+            assert_eq!(reparsed.overrides()[0].recurrence_id(), Some(second_week));
+            assert_eq!(reparsed.excluded_dates(), vec![third_week]);
+        }
+    }
+
+    #[test]
+    fn test_zoned_dates() {
+        let mut due = Property::new();
+        due.name = String::from("DUE");
+        due.params = Some(vec![(String::from("TZID"), vec![String::from("America/New_York")])]);
+        due.value = Some(String::from("20211107T090000"));
+        let task = TaskBuilder::new("Buy milk", &"https://some.calendar/".parse().unwrap()).raw_property(due).build();
+
+        let new_york: chrono_tz::Tz = "America/New_York".parse().unwrap();
+        assert_eq!(task.zoned_due(), Some(IcalDateTime::Zoned(new_york.ymd(2021, 11, 7).and_hms(9, 0, 0))));
+        // Daylight saving time ended earlier this day
+        assert_eq!(task.due(), Some(Utc.ymd(2021, 11, 7).and_hms(14, 0, 0)));
+        assert_eq!(task.zoned_start(), None);
+    }
+
+    #[test]
+    fn test_floating_dates() {
+        let url: Url = "https://some.calendar/review.ics".parse().unwrap();
+        let mut task = recurrent_task("DTSTART:20210322T090000");
+        let floating_due = IcalDateTime::Floating(chrono::NaiveDate::from_ymd(2021, 3, 22).and_hms(18, 0, 0));
+        for prop in task.extra_parameters.iter_mut().filter(|prop| prop.name == "DUE") {
+            *prop = floating_due.to_property("DUE");
+        }
+
+        // Floating dates are resolved only when they are queried
+        let paris: chrono_tz::Tz = "Europe/Paris".parse().unwrap();
+        let tokyo: chrono_tz::Tz = "Asia/Tokyo".parse().unwrap();
+        assert_eq!(task.due(), Some(Utc.ymd(2021, 3, 22).and_hms(18, 0, 0)));
+        assert_eq!(task.due_in(&paris), Some(Utc.ymd(2021, 3, 22).and_hms(17, 0, 0)));
+        assert_eq!(task.due_in(&tokyo), Some(Utc.ymd(2021, 3, 22).and_hms(9, 0, 0)));
+        assert_eq!(task.start_in(&tokyo), Some(Utc.ymd(2021, 3, 22).and_hms(0, 0, 0)));
+
+        // ...and they stay floating, even in the occurrences of a recurrent task
+        task.modify_occurrence(Utc.ymd(2021, 3, 29).and_hms(9, 0, 0), |occurrence| occurrence.set_name(String::from("Moved"))).unwrap();
+        let ical = crate::ical::build_from(&crate::Item::Task(task)).unwrap();
+        assert!(ical.contains("\r\nDTSTART:20210322T090000\r\n"), "{}", ical);
+        assert!(ical.contains("\r\nDUE:20210322T180000\r\n"), "{}", ical);
+        assert!(ical.contains("\r\nDUE:20210329T180000\r\n"), "{}", ical);
+
+        let reparsed = crate::ical::parse(&ical, url, SyncStatus::NotSynced).unwrap();
+        assert_eq!(crate::ical::build_from(&reparsed).unwrap(), ical);
+        assert_eq!(reparsed.unwrap_task().overrides()[0].due_in(&paris), Some(Utc.ymd(2021, 3, 29).and_hms(16, 0, 0)));
+    }
+}
@@ -0,0 +1,149 @@
+//! Helpers that are shared by the unit tests of this crate
+
+// Most of these helpers are only used by the tests of the cache
+#![cfg_attr(not(feature = "cache"), allow(dead_code))]
+
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use ical::property::Property;
+use url::Url;
+
+use crate::item::{Item, SyncStatus};
+use crate::task::{CompletionStatus, Task};
+use crate::traits::ItemObserver;
+
+/// Builds the tasks tests need, so that each test only has to set the fields it cares about.
+///
+/// Unless told otherwise, tasks are not completed, not synced, and have a random UID and URL in their calendar
+pub(crate) struct TaskBuilder {
+    name: String,
+    uid: String,
+    url: Url,
+    completion_status: CompletionStatus,
+    sync_status: SyncStatus,
+    creation_date: Option<DateTime<Utc>>,
+    last_modified: DateTime<Utc>,
+    properties: Vec<Property>,
+}
+
+impl TaskBuilder {
+    pub(crate) fn new(name: &str, cal_url: &Url) -> Self {
+        let task = Task::new(name.to_string(), false, cal_url);
+        Self {
+            name: name.to_string(),
+            uid: task.uid().to_string(),
+            url: task.url().clone(),
+            completion_status: CompletionStatus::Uncompleted,
+            sync_status: SyncStatus::NotSynced,
+            creation_date: None,
+            last_modified: *task.last_modified(),
+            properties: Vec::new(),
+        }
+    }
+
+    pub(crate) fn uid(mut self, uid: &str) -> Self {
+        self.uid = uid.to_string();
+        self
+    }
+
+    /// Store the task at `url`, instead of a random URL
+    pub(crate) fn url(mut self, url: Url) -> Self {
+        self.url = url;
+        self
+    }
+
+    /// Completed at an unknown date
+    pub(crate) fn completed(mut self, completed: bool) -> Self {
+        self.completion_status = match completed {
+            true => CompletionStatus::Completed(None),
+            false => CompletionStatus::Uncompleted,
+        };
+        self
+    }
+
+    pub(crate) fn completed_on(mut self, date: DateTime<Utc>) -> Self {
+        self.completion_status = CompletionStatus::Completed(Some(date));
+        self
+    }
+
+    pub(crate) fn sync_status(mut self, sync_status: SyncStatus) -> Self {
+        self.sync_status = sync_status;
+        self
+    }
+
+    /// Synced, with a random version tag
+    pub(crate) fn synced(self) -> Self {
+        self.sync_status(SyncStatus::random_synced())
+    }
+
+    pub(crate) fn created(mut self, date: DateTime<Utc>) -> Self {
+        self.creation_date = Some(date);
+        self
+    }
+
+    pub(crate) fn last_modified(mut self, date: DateTime<Utc>) -> Self {
+        self.last_modified = date;
+        self
+    }
+
+    /// Add a property that has no parameter (e.g. `PRIORITY:1`)
+    pub(crate) fn property(self, name: &str, value: &str) -> Self {
+        let mut prop = Property::new();
+        prop.name = name.to_string();
+        prop.value = Some(value.to_string());
+        self.raw_property(prop)
+    }
+
+    pub(crate) fn raw_property(mut self, prop: Property) -> Self {
+        self.properties.push(prop);
+        self
+    }
+
+    /// Due at a given instant (e.g. `DUE:20210322T090000Z`)
+    pub(crate) fn due(self, date: DateTime<Utc>) -> Self {
+        self.property("DUE", &date.format("%Y%m%dT%H%M%SZ").to_string())
+    }
+
+    pub(crate) fn build(self) -> Task {
+        Task::new_with_parameters(self.name, self.uid, self.url, self.completion_status,
+            self.sync_status, self.creation_date, self.last_modified, String::from("-//Test//EN"), self.properties)
+    }
+
+    pub(crate) fn item(self) -> Item {
+        Item::Task(self.build())
+    }
+}
+
+
+/// An observer that records every change, as the names of the former and new versions of the items (deleted items are suffixed with `(deleted)`)
+#[derive(Default)]
+pub(crate) struct Recorder {
+    pub(crate) changes: Mutex<Vec<(Option<String>, Option<String>)>>,
+}
+
+impl ItemObserver for Recorder {
+    fn item_changed(&self, _calendar_url: &Url, old: Option<&Item>, new: Option<&Item>) {
+        let describe = |item: Option<&Item>| item.map(|item| match item.sync_status() {
+            SyncStatus::LocallyDeleted(_) => format!("{} (deleted)", item.name()),
+            _ => item.name().to_string(),
+        });
+        self.changes.lock().unwrap().push((describe(old), describe(new)));
+    }
+}
+
+/// An observer that records how many changes each of its notifications carries
+#[derive(Default)]
+pub(crate) struct BulkRecorder {
+    pub(crate) notifications: Mutex<Vec<usize>>,
+}
+
+impl ItemObserver for BulkRecorder {
+    fn item_changed(&self, _calendar_url: &Url, _old: Option<&Item>, _new: Option<&Item>) {
+        self.notifications.lock().unwrap().push(1);
+    }
+
+    fn items_changed(&self, _calendar_url: &Url, changes: &[(Option<&Item>, Option<&Item>)]) {
+        self.notifications.lock().unwrap().push(changes.len());
+    }
+}
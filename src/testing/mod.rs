@@ -0,0 +1,381 @@
+//! A test kit to check how a [`Provider`] syncs, so that apps can write their own sync scenarios
+//!
+//! To do so, "scenarii" are defined. A scenario contains an inital state before sync, changes made either on the local or remote side, then the expected final state that should be present in both sources after sync.
+//!
+//! This module builds actual CalDAV sources (actually [`Cache`]s, that can also mock what would be [`Client`](crate::client::Client)s in a real program, see [`MockBehaviour`]) and [`Provider`]s that contain this data.
+//! It can also check the sources after a sync contain the actual data we expect (see [`run_scenarii`]). \
+//! Some ready-made scenarii are available in the [`scenarii`] module.
+//!
+//! This module requires the `testing` Cargo feature. The sources are stored in the `test_cache/` folder of the current directory.
+//!
+//! ```
+//! # #[tokio::main]
+//! # async fn main() {
+//! use std::sync::{Arc, Mutex};
+//! use kitchen_fridge::mock_behaviour::MockBehaviour;
+//! use kitchen_fridge::testing::{run_scenarii, scenarii};
+//!
+//! // Make every operation of the mocked server fail twice, and check the sync eventually succeeds
+//! let mock_behaviour = Arc::new(Mutex::new(MockBehaviour::fail_now(2)));
+//! run_scenarii(&scenarii::scenarii_basic(), mock_behaviour, 100).await;
+//! # }
+//! ```
+#![cfg(feature = "testing")]
+
+pub mod scenarii;
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::error::Error;
+use url::Url;
+
+use chrono::Utc;
+
+use crate::calendar::SupportedComponents;
+use crate::traits::CalDavSource;
+use crate::traits::BaseCalendar;
+use crate::traits::CompleteCalendar;
+use crate::traits::DavCalendar;
+use crate::cache::Cache;
+use crate::Item;
+use crate::item::SyncStatus;
+use crate::Task;
+use crate::task::CompletionStatus;
+use crate::calendar::cached_calendar::CachedCalendar;
+use crate::provider::Provider;
+use crate::mock_behaviour::MockBehaviour;
+use crate::utils::write_locked;
+
+/// Where an item is, at a given step of a scenario
+pub enum LocatedState {
+    /// Item does not exist yet or does not exist anymore
+    None,
+    /// Item is only in the local source
+    Local(ItemState),
+    /// Item is only in the remote source
+    Remote(ItemState),
+    /// Item is synced at both locations,
+    BothSynced(ItemState),
+}
+
+/// The content of an item, at a given step of a scenario
+pub struct ItemState {
+    // TODO: if/when this crate supports Events as well, we could add such events here
+    /// The calendar it is in
+    pub calendar: Url,
+    /// Its name
+    pub name: String,
+    /// Its completion status
+    pub completed: bool,
+}
+
+/// A change made to an item, on either side, between two syncs
+pub enum ChangeToApply {
+    Rename(String),
+    SetCompletion(bool),
+    Create(Url, Item),
+    /// "remove" means "mark for deletion" in the local calendar, or "immediately delete" on the remote calendar
+    Remove,
+    // ChangeCalendar(Url) is useless, as long as changing a calendar is implemented as "delete in one calendar and re-create it in another one"
+}
+
+
+/// What happens to a single item, from the last sync to the expected result of the next one
+pub struct ItemScenario {
+    /// The URL of the item (see [`crate::utils::random_url`])
+    pub url: Url,
+    /// Where the item is, as of the last sync
+    pub initial_state: LocatedState,
+    pub local_changes_to_apply:  Vec<ChangeToApply>,
+    pub remote_changes_to_apply: Vec<ChangeToApply>,
+    /// Where the item should be after the next sync
+    pub after_sync: LocatedState,
+}
+
+
+/// Populate a provider with the given scenarii, sync it (up to `max_attempts` times, in case `mock_behaviour` makes the sync fail), then check both its sources contain the expected data.
+///
+/// `mock_behaviour` is suspended while the provider is populated, and while its content is checked.
+/// This panics in case the content of the sources is not the expected one.
+pub async fn run_scenarii(scenarii: &[ItemScenario], mock_behaviour: Arc<Mutex<MockBehaviour>>, max_attempts: u32) {
+    mock_behaviour.lock().unwrap().suspend();
+
+    let mut provider = populate_test_provider_before_sync(scenarii, Arc::clone(&mock_behaviour)).await;
+    print_provider(&provider, "before sync").await;
+
+    mock_behaviour.lock().unwrap().resume();
+    for attempt in 0..max_attempts {
+        log::info!("Syncing...");
+        if provider.sync().await == true {
+            log::info!("Sync complete after {} attempts (multiple attempts are due to forced errors in mocked behaviour)", attempt+1);
+            break
+        }
+    }
+    mock_behaviour.lock().unwrap().suspend();
+
+    print_provider(&provider, "after sync").await;
+
+    // Check the contents of both sources are the same after sync
+    assert!(provider.remote().has_same_observable_content_as(provider.local()).await.unwrap());
+
+    // But also explicitely check that every item is expected
+    let expected_provider = populate_test_provider_after_sync(scenarii, Arc::clone(&mock_behaviour)).await;
+
+    assert!(provider.local() .has_same_observable_content_as(expected_provider.local() ).await.unwrap());
+    assert!(provider.remote().has_same_observable_content_as(expected_provider.remote()).await.unwrap());
+
+    // Perform a second sync, even if no change has happened, just to check
+    log::info!("Syncing again");
+    provider.sync().await;
+    assert!(provider.local() .has_same_observable_content_as(expected_provider.local() ).await.unwrap());
+    assert!(provider.remote().has_same_observable_content_as(expected_provider.remote()).await.unwrap());
+}
+
+/// Log the contents of the provider (at the `debug` level). This is usually used for debugging
+pub async fn print_provider(provider: &Provider<Cache, CachedCalendar, Cache, CachedCalendar>, title: &str) {
+    if !log::log_enabled!(log::Level::Debug) {
+        return;
+    }
+    let cals_server = provider.remote().get_calendars().await.unwrap();
+    log::debug!("----Server, {}-------\n{}", title, describe_calendars(&cals_server));
+    let cals_local = provider.local().get_calendars().await.unwrap();
+    log::debug!("-----Local, {}-------\n{}", title, describe_calendars(&cals_local));
+}
+
+/// A description of the calendars and their items, as [`crate::utils::print_calendar_list`] would print them
+fn describe_calendars(cals: &HashMap<Url, Arc<RwLock<CachedCalendar>>>) -> String {
+    let mut description = String::new();
+    for (url, cal) in cals {
+        let cal = cal.read().unwrap();
+        description.push_str(&format!("CAL {} ({})\n", cal.name(), url));
+        for item in cal.get_items_sync().unwrap_or_default().values() {
+            if let Item::Task(task) = item {
+                let completion = if task.completed() { "✓" } else { " " };
+                let sync = match task.sync_status() {
+                    SyncStatus::NotSynced => ".",
+                    SyncStatus::Synced(_) => "=",
+                    SyncStatus::LocallyModified(_) => "~",
+                    SyncStatus::LocallyDeleted(_) =>  "x",
+                };
+                description.push_str(&format!("    {}{} {}\t{}\n", completion, sync, task.name(), task.url()));
+            }
+        }
+    }
+    description
+}
+
+/// Build a `Provider` that contains the data (defined in the given scenarii) before sync
+pub async fn populate_test_provider_before_sync(scenarii: &[ItemScenario], mock_behaviour: Arc<Mutex<MockBehaviour>>) -> Provider<Cache, CachedCalendar, Cache, CachedCalendar> {
+    let mut provider = populate_test_provider(scenarii, mock_behaviour, false).await;
+    apply_changes_on_provider(&mut provider, scenarii).await;
+    provider
+}
+
+/// Build a `Provider` that contains the data (defined in the given scenarii) after sync
+pub async fn populate_test_provider_after_sync(scenarii: &[ItemScenario], mock_behaviour: Arc<Mutex<MockBehaviour>>) -> Provider<Cache, CachedCalendar, Cache, CachedCalendar> {
+    populate_test_provider(scenarii, mock_behaviour, true).await
+}
+
+async fn populate_test_provider(scenarii: &[ItemScenario], mock_behaviour: Arc<Mutex<MockBehaviour>>, populate_for_final_state: bool) -> Provider<Cache, CachedCalendar, Cache, CachedCalendar> {
+    let mut local = Cache::new(&PathBuf::from(String::from("test_cache/local/")));
+    let mut remote = Cache::new(&PathBuf::from(String::from("test_cache/remote/")));
+    remote.set_mock_behaviour(Some(mock_behaviour));
+
+    // Create the initial state, as if we synced both sources in a given state
+    for item in scenarii {
+        let required_state = if populate_for_final_state { &item.after_sync } else { &item.initial_state };
+        let (state, sync_status) = match required_state {
+            LocatedState::None => continue,
+            LocatedState::Local(s) => {
+                assert!(populate_for_final_state == false, "You are not supposed to expect an item in this state after sync");
+                (s, SyncStatus::NotSynced)
+            },
+            LocatedState::Remote(s) => {
+                assert!(populate_for_final_state == false, "You are not supposed to expect an item in this state after sync");
+                (s, SyncStatus::random_synced())
+            }
+            LocatedState::BothSynced(s) => (s, SyncStatus::random_synced()),
+        };
+
+        let now = Utc::now();
+        let completion_status = match state.completed {
+            false => CompletionStatus::Uncompleted,
+            true => CompletionStatus::Completed(Some(now)),
+        };
+
+        let new_item = Item::Task(
+            Task::new_with_parameters(
+                state.name.clone(),
+                item.url.to_string(),
+                item.url.clone(),
+                completion_status,
+                sync_status,
+                Some(now),
+                now,
+                "prod_id".to_string(), Vec::new(),
+            ));
+
+        match required_state {
+            LocatedState::None => panic!("Should not happen, we've continued already"),
+            LocatedState::Local(s) => {
+                let cal = get_or_insert_calendar(&mut local, &s.calendar).await.unwrap();
+                add_item(&cal, new_item, false).await;
+            },
+            LocatedState::Remote(s) => {
+                let cal = get_or_insert_calendar(&mut remote, &s.calendar).await.unwrap();
+                add_item(&cal, new_item, true).await;
+            },
+            LocatedState::BothSynced(s) => {
+                let cal = get_or_insert_calendar(&mut local, &s.calendar).await.unwrap();
+                add_item(&cal, new_item.clone(), false).await;
+                let cal = get_or_insert_calendar(&mut remote, &s.calendar).await.unwrap();
+                add_item(&cal, new_item, true).await;
+            },
+        }
+    }
+    Provider::new(remote, local)
+}
+
+/// Apply `local_changes_to_apply` and `remote_changes_to_apply` to a provider that contains data before sync
+async fn apply_changes_on_provider(provider: &mut Provider<Cache, CachedCalendar, Cache, CachedCalendar>, scenarii: &[ItemScenario]) {
+    // Apply changes to each item
+    for item in scenarii {
+        let initial_calendar_url = match &item.initial_state {
+            LocatedState::None => None,
+            LocatedState::Local(state) => Some(state.calendar.clone()),
+            LocatedState::Remote(state) => Some(state.calendar.clone()),
+            LocatedState::BothSynced(state) => Some(state.calendar.clone()),
+        };
+
+        let mut calendar_url = initial_calendar_url.clone();
+        for local_change in &item.local_changes_to_apply {
+            calendar_url = Some(apply_change(provider.local(), calendar_url, &item.url, local_change, false).await);
+        }
+
+        let mut calendar_url = initial_calendar_url;
+        for remote_change in &item.remote_changes_to_apply {
+            calendar_url = Some(apply_change(provider.remote(), calendar_url, &item.url, remote_change, true).await);
+        }
+    }
+}
+
+async fn get_or_insert_calendar(source: &mut Cache, url: &Url)
+    -> Result<Arc<RwLock<CachedCalendar>>, Box<dyn Error>>
+{
+    match source.get_calendar(url).await {
+        Some(cal) => Ok(cal),
+        None => {
+            let new_name = format!("Test calendar for URL {}", url);
+            let supported_components = SupportedComponents::TODO;
+            let color = csscolorparser::parse("#ff8000").unwrap(); // TODO: we should rather have specific colors, depending on the calendars
+
+            source.create_calendar(
+                url.clone(),
+                new_name.to_string(),
+                supported_components,
+                Some(color),
+            ).await
+        }
+    }
+}
+
+/// Apply a single change on a given source, and returns the calendar URL that was modified
+async fn apply_change<S, C>(source: &S, calendar_url: Option<Url>, item_url: &Url, change: &ChangeToApply, is_remote: bool) -> Url
+where
+    S: CalDavSource<C>,
+    C: CompleteCalendar + DavCalendar + Send, // in this test, we're using a calendar that mocks both kinds
+{
+    match calendar_url {
+        Some(cal) => {
+            apply_changes_on_an_existing_item(source, &cal, item_url, change, is_remote).await;
+            cal
+        },
+        None => {
+            create_test_item(source, change, is_remote).await
+        },
+    }
+}
+
+async fn apply_changes_on_an_existing_item<S, C>(source: &S, calendar_url: &Url, item_url: &Url, change: &ChangeToApply, is_remote: bool)
+where
+    S: CalDavSource<C>,
+    C: CompleteCalendar + DavCalendar + Send, // in this test, we're using a calendar that mocks both kinds
+{
+    let cal = source.get_calendar(calendar_url).await.unwrap();
+    if let (ChangeToApply::Remove, true) = (change, is_remote) {
+        // Like a sync does, requests to the remote calendar do not keep it locked
+        let mut detached = cal.read().unwrap().detached();
+        detached.delete_item(item_url).await.unwrap();
+        cal.write().unwrap().record_changes(detached, std::slice::from_ref(item_url)).unwrap();
+        return;
+    }
+
+    write_locked(&cal, &(item_url, change), |cal, (item_url, change)| Box::pin(async move {
+        let task = cal.get_item_by_url_mut(item_url).await.unwrap().unwrap_task_mut();
+
+        match change {
+            ChangeToApply::Rename(new_name) => {
+                if is_remote {
+                    task.mock_remote_calendar_set_name(new_name.clone());
+                } else {
+                    task.set_name(new_name.clone());
+                }
+            },
+            ChangeToApply::SetCompletion(new_status) => {
+                let completion_status = match new_status {
+                    false => CompletionStatus::Uncompleted,
+                    true => CompletionStatus::Completed(Some(Utc::now())),
+                };
+                if is_remote {
+                    task.mock_remote_calendar_set_completion_status(completion_status);
+                } else {
+                    task.set_completion_status(completion_status);
+                }
+            },
+            ChangeToApply::Remove => {
+                // Remote deletions have been applied above
+                cal.mark_for_deletion(item_url).await.unwrap();
+            },
+            ChangeToApply::Create(_calendar_url, _item) => {
+                panic!("This function only handles already existing items");
+            },
+        }
+    }))
+}
+
+/// Create an item, and returns the URL of the calendar it was inserted in
+async fn create_test_item<S, C>(source: &S, change: &ChangeToApply, is_remote: bool) -> Url
+where
+    S: CalDavSource<C>,
+    C: CompleteCalendar + DavCalendar + Send, // in this test, we're using a calendar that mocks both kinds
+{
+    match change {
+        ChangeToApply::Rename(_) |
+        ChangeToApply::SetCompletion(_) |
+        ChangeToApply::Remove => {
+            panic!("This function only creates items that do not exist yet");
+        }
+        ChangeToApply::Create(calendar_url, item) => {
+            let cal = source.get_calendar(calendar_url).await.unwrap();
+            add_item(&cal, item.clone(), is_remote).await;
+            calendar_url.clone()
+        },
+    }
+}
+
+/// Add an item to a calendar. Like a sync does, requests to a remote calendar are made with a detached copy of it, so that it is not locked while they are in flight
+async fn add_item<C>(cal: &RwLock<C>, item: Item, is_remote: bool)
+where
+    C: CompleteCalendar + DavCalendar + Send,
+{
+    match is_remote {
+        false => { write_locked(cal, &(), |cal, _| cal.add_item(item)).unwrap(); },
+        true => {
+            let url = item.url().clone();
+            let mut detached = cal.read().unwrap().detached();
+            detached.add_item(item).await.unwrap();
+            cal.write().unwrap().record_changes(detached, &[url]).unwrap();
+        },
+    }
+}
@@ -2,35 +2,202 @@
 
 use std::error::Error;
 use std::collections::{HashMap, HashSet};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, RwLock};
 
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use csscolorparser::Color;
 use url::Url;
 
 use crate::item::SyncStatus;
 use crate::item::Item;
+use crate::task::Task;
+use crate::item::Occurrence;
 use crate::item::VersionTag;
 use crate::calendar::SupportedComponents;
+use crate::calendar::CalendarSyncStatus;
+use crate::calendar::SearchFilter;
+use crate::utils::comparison::{compare_with_keys, SortKey};
 use crate::resource::Resource;
+use crate::provider::{ConflictRecord, SyncJournal, SyncRollback};
+use crate::config::Config;
 
 /// This trait must be implemented by data sources (either local caches or remote CalDAV clients)
 ///
+/// Its functions only take `&self`, so that a source can be shared (e.g. to create a calendar while other parts of an app are reading this source). Implementors use interior mutability for that purpose. \
+/// Calendars are shared behind [`RwLock`]s, so that many readers (e.g. UI threads) can access them concurrently, while writers (e.g. a sync) get exclusive access. \
+/// Requests to remote calendars are made with [detached](DavCalendar::detached) copies of them, so that these locks are never held while waiting for a server. \
 /// Note that some concrete types (e.g. [`crate::cache::Cache`]) can also provide non-async versions of these functions
 #[async_trait]
 pub trait CalDavSource<T: BaseCalendar> {
     /// Returns the current calendars that this source contains
     /// This function may trigger an update (that can be a long process, or that can even fail, e.g. in case of a remote server)
-    async fn get_calendars(&self) -> Result<HashMap<Url, Arc<Mutex<T>>>, Box<dyn Error>>;
+    async fn get_calendars(&self) -> Result<HashMap<Url, Arc<RwLock<T>>>, Box<dyn Error>>;
     /// Returns the calendar matching the URL
-    async fn get_calendar(&self, url: &Url) -> Option<Arc<Mutex<T>>>;
+    async fn get_calendar(&self, url: &Url) -> Option<Arc<RwLock<T>>>;
     /// Create a calendar if it did not exist, and return it
-    async fn create_calendar(&mut self, url: Url, name: String, supported_components: SupportedComponents, color: Option<Color>)
-        -> Result<Arc<Mutex<T>>, Box<dyn Error>>;
+    async fn create_calendar(&self, url: Url, name: String, supported_components: SupportedComponents, color: Option<Color>)
+        -> Result<Arc<RwLock<T>>, Box<dyn Error>>;
+    /// Immediately delete a calendar and all its items.
+    ///
+    /// To delete a calendar that is in sync with another source, you probably want to mark it for deletion instead (see [`CompleteCalendar::set_sync_status`]), so that the upcoming sync deletes it from both sources. \
+    /// The default implementation returns an error, for sources that cannot delete calendars.
+    async fn delete_calendar(&self, url: &Url) -> Result<(), Box<dyn Error>>
+    where
+        Self: Sync,
+    {
+        Err(format!("Unable to delete calendar {}: this source does not support deleting calendars", url).into())
+    }
+
+    /// Check whether this source can be reached (e.g. whether the network is up), with a single quick request.
+    ///
+    /// An `Err` means that this source is offline. Providers run this before a sync, so that an offline source is not sent dozens of requests that would all time out. \
+    /// The default implementation returns `Ok(())`, for sources that are always available (e.g. local caches).
+    async fn check_reachable(&self) -> Result<(), Box<dyn Error>>
+    where
+        Self: Sync,
+    {
+        Ok(())
+    }
+
+    /// Forget the calendar listing this source may have cached, so that the next call to [`CalDavSource::get_calendars`] fetches it again.
+    ///
+    /// Remote sources usually list their calendars once, rather than on every call (which can be very frequent, e.g. in GUIs that redraw their calendar list). \
+    /// Providers call this before every sync, so that calendars that have been created or deleted by other clients are noticed. Sources that do not cache their listing do not need to implement it.
+    fn invalidate_calendars(&self) {}
 
-    // Removing a calendar is not supported yet
+    /// Write the content of this source to its storage, if it has one.
+    ///
+    /// Providers call this after successful syncs when [`Config::save_after_sync`](crate::config::Config::save_after_sync) is set. Sources that do not need to be saved (e.g. servers, that store every change at once) do not need to implement it.
+    fn persist(&self) -> Result<(), Box<dyn Error>> { Ok(()) }
+
+    /// Run `f` on the calendar matching the URL, and return its result (or `None` in case there is no such calendar).
+    ///
+    /// The calendar is read-locked while `f` runs, and only then. Since `f` is not async, it cannot keep the lock across an `.await` point. \
+    /// Note that `f` must not try to lock this calendar again (e.g. by calling [`CalDavSource::with_calendar_mut`]), since that would deadlock.
+    async fn with_calendar<F, R>(&self, url: &Url, f: F) -> Option<R>
+    where
+        Self: Sync,
+        T: Send + Sync,
+        F: FnOnce(&T) -> R + Send,
+    {
+        let calendar = self.get_calendar(url).await?;
+        let calendar = calendar.read().unwrap();
+        Some(f(&calendar))
+    }
+
+    /// Same as [`CalDavSource::with_calendar`], but `f` gets a mutable access to the calendar (that is write-locked while `f` runs)
+    async fn with_calendar_mut<F, R>(&self, url: &Url, f: F) -> Option<R>
+    where
+        Self: Sync,
+        T: Send + Sync,
+        F: FnOnce(&mut T) -> R + Send,
+    {
+        let calendar = self.get_calendar(url).await?;
+        let mut calendar = calendar.write().unwrap();
+        Some(f(&mut calendar))
+    }
 }
 
+/// An object-safe version of [`CalDavSource`], whose calendars are trait objects.
+///
+/// Every source of this crate implements it, so that applications can hold a `Box<dyn DynCalDavSource>` and switch between backends (e.g. a [`Cache`](crate::cache::Cache) or a [`Client`](crate::client::Client)) at runtime. \
+/// Custom backends that implement [`CalDavSource`] can implement it as well with the [`impl_dyn_caldav_source`](crate::impl_dyn_caldav_source) macro.
+#[async_trait]
+pub trait DynCalDavSource: Send + Sync {
+    /// See [`CalDavSource::get_calendars`]
+    async fn get_calendars(&self) -> Result<HashMap<Url, DynCalendar>, Box<dyn Error>>;
+    /// See [`CalDavSource::get_calendar`]
+    async fn get_calendar(&self, url: &Url) -> Option<DynCalendar>;
+    /// See [`CalDavSource::create_calendar`]
+    async fn create_calendar(&self, url: Url, name: String, supported_components: SupportedComponents, color: Option<Color>)
+        -> Result<DynCalendar, Box<dyn Error>>;
+    /// See [`CalDavSource::delete_calendar`]
+    async fn delete_calendar(&self, url: &Url) -> Result<(), Box<dyn Error>>;
+    /// See [`CalDavSource::invalidate_calendars`]
+    fn invalidate_calendars(&self);
+    /// See [`CalDavSource::persist`]
+    fn persist(&self) -> Result<(), Box<dyn Error>>;
+}
+
+/// A calendar of a [`DynCalDavSource`]
+pub type DynCalendar = Arc<RwLock<dyn BaseCalendar + Send + Sync>>;
+
+/// Implement [`DynCalDavSource`] for a type that implements [`CalDavSource`]
+///
+/// ```ignore
+/// kitchen_fridge::impl_dyn_caldav_source!(MySource, MyCalendar);
+/// ```
+#[macro_export]
+macro_rules! impl_dyn_caldav_source {
+    ($source:ty, $calendar:ty) => {
+        #[$crate::__private::async_trait]
+        impl $crate::traits::DynCalDavSource for $source {
+            async fn get_calendars(&self) -> Result<std::collections::HashMap<$crate::__private::Url, $crate::traits::DynCalendar>, Box<dyn std::error::Error>> {
+                let calendars = <Self as $crate::traits::CalDavSource<$calendar>>::get_calendars(self).await?;
+                Ok(calendars.into_iter()
+                    .map(|(url, cal)| (url, cal as $crate::traits::DynCalendar))
+                    .collect())
+            }
+
+            async fn get_calendar(&self, url: &$crate::__private::Url) -> Option<$crate::traits::DynCalendar> {
+                <Self as $crate::traits::CalDavSource<$calendar>>::get_calendar(self, url).await
+                    .map(|cal| cal as $crate::traits::DynCalendar)
+            }
+
+            async fn create_calendar(&self, url: $crate::__private::Url, name: String, supported_components: $crate::calendar::SupportedComponents, color: Option<$crate::__private::Color>)
+                -> Result<$crate::traits::DynCalendar, Box<dyn std::error::Error>>
+            {
+                let cal = <Self as $crate::traits::CalDavSource<$calendar>>::create_calendar(self, url, name, supported_components, color).await?;
+                Ok(cal)
+            }
+
+            async fn delete_calendar(&self, url: &$crate::__private::Url) -> Result<(), Box<dyn std::error::Error>> {
+                <Self as $crate::traits::CalDavSource<$calendar>>::delete_calendar(self, url).await
+            }
+
+            fn invalidate_calendars(&self) {
+                <Self as $crate::traits::CalDavSource<$calendar>>::invalidate_calendars(self)
+            }
+
+            fn persist(&self) -> Result<(), Box<dyn std::error::Error>> {
+                <Self as $crate::traits::CalDavSource<$calendar>>::persist(self)
+            }
+        }
+    };
+}
+
+#[cfg(feature = "cache")]
+impl_dyn_caldav_source!(crate::cache::Cache, crate::calendar::cached_calendar::CachedCalendar);
+#[cfg(feature = "client")]
+impl_dyn_caldav_source!(crate::client::Client, crate::calendar::remote_calendar::RemoteCalendar);
+#[cfg(feature = "google_tasks")]
+impl_dyn_caldav_source!(crate::google_tasks::GoogleTasksClient, crate::google_tasks::GoogleTaskList);
+
+
+/// An observer of the changes of the items of a calendar (e.g. to maintain an undo stack or an audit log)
+///
+/// See [`CachedCalendar::add_observer`](crate::calendar::cached_calendar::CachedCalendar::add_observer) and [`Cache::add_observer`](crate::cache::Cache::add_observer). \
+/// It is notified of the changes made by the app as well as the ones made by a sync.
+pub trait ItemObserver: Send + Sync {
+    /// Called after an item has been added, updated, marked for deletion or deleted.
+    ///
+    /// `old` is `None` in case the item has just been added, `new` is `None` in case the item has just been deleted. \
+    /// This is called while the calendar is locked, so this must not try to access the calendar
+    fn item_changed(&self, calendar_url: &Url, old: Option<&Item>, new: Option<&Item>);
+
+    /// Called once after several items have been changed at once (e.g. by [`CachedCalendar::set_completed_bulk`](crate::calendar::cached_calendar::CachedCalendar::set_completed_bulk)),
+    /// with the `(old, new)` pairs of every changed item.
+    ///
+    /// The default implementation calls [`Self::item_changed`] for each of them
+    fn items_changed(&self, calendar_url: &Url, changes: &[(Option<&Item>, Option<&Item>)]) {
+        for (old, new) in changes {
+            self.item_changed(calendar_url, *old, *new);
+        }
+    }
+}
+
+
 /// This trait contains functions that are common to all calendars
 ///
 /// Note that some concrete types (e.g. [`crate::calendar::cached_calendar::CachedCalendar`]) can also provide non-async versions of these functions
@@ -48,11 +215,29 @@ pub trait BaseCalendar {
     /// Returns the user-defined color of this calendar
     fn color(&self) -> Option<&Color>;
 
+    /// Returns who this calendar belongs to (see [`CalendarKind`](crate::calendar::CalendarKind))
+    fn kind(&self) -> crate::calendar::CalendarKind {
+        crate::calendar::CalendarKind::Owned
+    }
+
     /// Add an item into this calendar, and return its new sync status.
     /// For local calendars, the sync status is not modified.
     /// For remote calendars, the sync status is updated by the server
     async fn add_item(&mut self, item: Item) -> Result<SyncStatus, Box<dyn Error>>;
 
+    /// Add several items into this calendar (e.g. when importing many tasks), and return their new sync statuses, in the same order as `items`.
+    ///
+    /// Implementations can do this more efficiently than successive calls to [`Self::add_item`] (e.g. saving or notifying only once, or uploading several items at the same time).
+    /// The default implementation adds the items one after the other.
+    async fn add_items(&mut self, items: Vec<Item>) -> Vec<Result<SyncStatus, Box<dyn Error>>> {
+        let mut results = Vec::with_capacity(items.len());
+        for item in items {
+            // Errors are not `Send`, they must not be kept across `.await`s
+            results.push(self.add_item(item).await.map_err(|err| err.to_string()));
+        }
+        results.into_iter().map(|result| result.map_err(|err| err.into())).collect()
+    }
+
     /// Update an item that already exists in this calendar and returns its new `SyncStatus`
     /// This replaces a given item at a given URL
     async fn update_item(&mut self, item: Item) -> Result<SyncStatus, Box<dyn Error>>;
@@ -66,6 +251,16 @@ pub trait BaseCalendar {
     fn supports_events(&self) -> bool {
         self.supported_components().contains(crate::calendar::SupportedComponents::EVENT)
     }
+
+    /// Returns whether this calDAV calendar supports journal entries
+    fn supports_journal(&self) -> bool {
+        self.supported_components().contains(crate::calendar::SupportedComponents::JOURNAL)
+    }
+
+    /// Returns whether this calDAV calendar supports free/busy information
+    fn supports_freebusy(&self) -> bool {
+        self.supported_components().contains(crate::calendar::SupportedComponents::FREEBUSY)
+    }
 }
 
 
@@ -75,11 +270,49 @@ pub trait BaseCalendar {
 #[async_trait]
 pub trait DavCalendar : BaseCalendar {
     /// Create a new calendar
-    fn new(name: String, resource: Resource, supported_components: SupportedComponents, color: Option<Color>) -> Self;
+    fn new(name: String, resource: Resource, supported_components: SupportedComponents, color: Option<Color>) -> Self
+        where Self: Sized;
+
+    /// Forget any data that may have been memoized from the server (e.g. version tags), so that the next queries reflect the current state of the server.
+    ///
+    /// This is useful for long-running apps that keep a calendar alive, since remote changes may otherwise not be seen
+    fn refresh(&self) {}
+
+    /// A copy of this calendar, to make requests with, so that this calendar is not locked while they are in flight.
+    ///
+    /// Copies should share what this calendar memoizes from the server (e.g. version tags). The items that requests change through a copy are brought back into this calendar by [`DavCalendar::record_changes`]
+    fn detached(&self) -> Self
+        where Self: Sized;
+
+    /// Bring back the items at `urls` (or their deletion), once requests made with a [detached](DavCalendar::detached) copy of this calendar have changed them.
+    ///
+    /// This only needs a short write lock, after the requests are over. The default implementation does nothing, for calendars whose copies share everything with them
+    fn record_changes(&mut self, _detached: Self, _urls: &[Url]) -> Result<(), Box<dyn Error>>
+        where Self: Sized
+    {
+        Ok(())
+    }
 
     /// Get the URLs and the version tags of every item in this calendar
     async fn get_item_version_tags(&self) -> Result<HashMap<Url, VersionTag>, Box<dyn Error>>;
 
+    /// Get the URLs and the version tags of the items that overlap a given time range (`start` included, `end` excluded).
+    ///
+    /// This can be used to only sync a window of a huge calendar (e.g. the tasks that are due this month). \
+    /// What "overlapping" means for every kind of item is defined by [RFC 4791](https://datatracker.ietf.org/doc/html/rfc4791#section-9.9).
+    /// Implementations that are not able to filter items (which is the case of the default implementation) may return a superset of the matching items.
+    async fn get_item_version_tags_between(&self, _start: &DateTime<Utc>, _end: &DateTime<Utc>) -> Result<HashMap<Url, VersionTag>, Box<dyn Error>> {
+        self.get_item_version_tags().await
+    }
+
+    /// Get the URLs and the version tags of the items that have been modified since `since` (according to their `LAST-MODIFIED` property).
+    ///
+    /// This is used to only sync the recent activity of huge calendars (see [`Config::sync_window_days`](crate::config::Config::sync_window_days)). \
+    /// Implementations that are not able to filter items (which is the case of the default implementation) may return a superset of the matching items.
+    async fn get_item_version_tags_modified_since(&self, _since: &DateTime<Utc>) -> Result<HashMap<Url, VersionTag>, Box<dyn Error>> {
+        self.get_item_version_tags().await
+    }
+
     /// Returns a particular item
     async fn get_item_by_url(&self, url: &Url) -> Result<Option<Item>, Box<dyn Error>>;
 
@@ -87,6 +320,61 @@ pub trait DavCalendar : BaseCalendar {
     /// This is usually faster than calling multiple consecutive [`DavCalendar::get_item_by_url`], since it only issues one HTTP request.
     async fn get_items_by_url(&self, urls: &[Url]) -> Result<Vec<Option<Item>>, Box<dyn Error>>;
 
+    /// Same as [`DavCalendar::get_items_by_url`], but recurrent items only carry the overridden instances that overlap a given time range.
+    ///
+    /// This bounds the amount of data that is transferred for recurrent items that have many overridden instances (see [RFC 4791](https://datatracker.ietf.org/doc/html/rfc4791#section-9.6.6)). \
+    /// The returned items may be incomplete, so they are suitable for display, but they must not be pushed back to the server.
+    /// The default implementation does not limit anything.
+    async fn get_items_by_url_limited_to(&self, urls: &[Url], _start: &DateTime<Utc>, _end: &DateTime<Utc>) -> Result<Vec<Option<Item>>, Box<dyn Error>> {
+        self.get_items_by_url(urls).await
+    }
+
+    /// Same as [`BaseCalendar::add_items`], but sending up to `concurrency` requests at the same time.
+    ///
+    /// The default implementation ignores `concurrency`.
+    async fn add_items_concurrently(&mut self, items: Vec<Item>, _concurrency: usize) -> Vec<Result<SyncStatus, Box<dyn Error>>> {
+        self.add_items(items).await
+    }
+
+    /// Update several items, sending up to `concurrency` requests at the same time.
+    ///
+    /// The results are returned in the same order as `items`. The default implementation updates the items one after the other.
+    async fn update_items(&mut self, items: Vec<Item>, _concurrency: usize) -> Vec<Result<SyncStatus, Box<dyn Error>>> {
+        let mut results = Vec::with_capacity(items.len());
+        for item in items {
+            // Errors are not `Send`, they must not be kept across `.await`s
+            results.push(self.update_item(item).await.map_err(|err| err.to_string()));
+        }
+        results.into_iter().map(|result| result.map_err(|err| err.into())).collect()
+    }
+
+    /// Returns the instances of the items that occur within a given time range, recurrent items being expanded into their discrete occurrences.
+    ///
+    /// Remote calendars ask the server to expand recurrent items (see [RFC 4791](https://datatracker.ietf.org/doc/html/rfc4791#section-9.6.5)).
+    /// The default implementation does not expand anything, and returns every matching item as a single occurrence.
+    async fn get_occurrences_between(&self, start: &DateTime<Utc>, end: &DateTime<Utc>) -> Result<Vec<Occurrence>, Box<dyn Error>> {
+        let urls: Vec<Url> = self.get_item_version_tags_between(start, end).await?.into_keys().collect();
+        Ok(self.get_items_by_url(&urls).await?
+            .into_iter()
+            .flatten()
+            .map(|item| Occurrence::new(None, item))
+            .collect())
+    }
+
+    /// Returns the tasks whose summary contains a given text (case-insensitive).
+    ///
+    /// Remote calendars ask the server to perform the search, so that the whole calendar does not have to be downloaded.
+    /// The default implementation downloads every item and filters them locally.
+    async fn search(&self, query: &str) -> Result<Vec<Item>, Box<dyn Error>> {
+        let urls: Vec<Url> = self.get_item_version_tags().await?.into_keys().collect();
+        let query = query.to_lowercase();
+        Ok(self.get_items_by_url(&urls).await?
+            .into_iter()
+            .flatten()
+            .filter(|item| item.is_task() && item.name().to_lowercase().contains(&query))
+            .collect())
+    }
+
     /// Delete an item
     async fn delete_item(&mut self, item_url: &Url) -> Result<(), Box<dyn Error>>;
 
@@ -98,8 +386,13 @@ pub trait DavCalendar : BaseCalendar {
             .collect())
     }
 
-    // Note: the CalDAV protocol could also enable to do this:
-    // fn get_current_version(&self) -> CTag
+    /// Returns the current version of the whole calendar (e.g. the `getctag` or the `sync-token` of a CalDAV collection), that changes whenever any of its items changes.
+    ///
+    /// This is a cheap way to know whether anything has changed since it has last been fetched, without listing the items. \
+    /// `None` means this calendar is not able to tell, which is what the default implementation returns.
+    async fn get_current_version(&self) -> Result<Option<VersionTag>, Box<dyn Error>> {
+        Ok(None)
+    }
 }
 
 
@@ -111,7 +404,8 @@ pub trait DavCalendar : BaseCalendar {
 #[async_trait]
 pub trait CompleteCalendar : BaseCalendar {
     /// Create a new calendar
-    fn new(name: String, url: Url, supported_components: SupportedComponents, color: Option<Color>) -> Self;
+    fn new(name: String, url: Url, supported_components: SupportedComponents, color: Option<Color>) -> Self
+        where Self: Sized;
 
     /// Get the URLs of all current items in this calendar
     async fn get_item_urls(&self) -> Result<HashSet<Url>, Box<dyn Error>>;
@@ -122,6 +416,56 @@ pub trait CompleteCalendar : BaseCalendar {
     /// Returns all items that this calendar contains
     async fn get_items_mut(&mut self) -> Result<HashMap<Url, &mut Item>, Box<dyn Error>>;
 
+    /// Iterate over all items of this calendar, without collecting them first (unlike [`CompleteCalendar::get_items`])
+    fn iter_items(&self) -> Box<dyn Iterator<Item = &Item> + Send + '_>;
+
+    /// Iterate over the sync statuses of the items of this calendar, sorted by URL.
+    ///
+    /// A sync compares this index with the version tags of the remote calendar in a single pass. \
+    /// The version tag of a synced item is part of its sync status, so that this index is persisted along with the items, and no separate index file is needed. \
+    /// The default implementation sorts the items on every call, implementors that keep their items sorted should override it.
+    fn sync_index(&self) -> Box<dyn Iterator<Item = (&Url, &SyncStatus)> + Send + '_> {
+        let mut index: Vec<(&Url, &SyncStatus)> = self.iter_items()
+            .map(|item| (item.url(), item.sync_status()))
+            .collect();
+        index.sort_unstable_by_key(|(url, _)| *url);
+        Box::new(index.into_iter())
+    }
+
+    /// Iterate over the tasks of this calendar
+    fn iter_tasks(&self) -> Box<dyn Iterator<Item = &Task> + Send + '_> {
+        Box::new(self.iter_items().filter_map(|item| match item {
+            Item::Task(task) => Some(task),
+            _ => None,
+        }))
+    }
+
+    /// Returns a "page" of the items of this calendar that match a filter: the items are sorted according to `sort_keys` (see [`compare_with_keys`]), then at most `limit` of them are returned, starting from `offset`.
+    ///
+    /// This is useful to lazily populate long lists
+    fn get_items_page(&self, filter: &SearchFilter, sort_keys: &[SortKey], offset: usize, limit: usize) -> Vec<&Item> {
+        let mut items: Vec<&Item> = self.iter_items()
+            .filter(|item| filter.matches(item))
+            .collect();
+        let end = offset.saturating_add(limit).min(items.len());
+        if offset >= end {
+            return Vec::new();
+        }
+
+        // No need to sort the items that are after the requested page
+        let compare = |a: &&Item, b: &&Item| compare_with_keys(sort_keys, a, b);
+        if end < items.len() {
+            items.select_nth_unstable_by(end, compare);
+            items.truncate(end);
+        }
+        items.sort_unstable_by(compare);
+        items.drain(..offset);
+        items
+    }
+
+    /// Returns the items of this calendar that match a filter
+    async fn get_items_filtered(&self, filter: &SearchFilter) -> Result<HashMap<Url, &Item>, Box<dyn Error>>;
+
     /// Returns a particular item
     async fn get_item_by_url<'a>(&'a self, url: &Url) -> Option<&'a Item>;
 
@@ -135,4 +479,203 @@ pub trait CompleteCalendar : BaseCalendar {
 
     /// Immediately remove an item. See [`CompleteCalendar::mark_for_deletion`]
     async fn immediately_delete_item(&mut self, item_id: &Url) -> Result<(), Box<dyn Error>>;
+
+    /// Record who this calendar belongs to, as told by its remote counterpart during a sync.
+    ///
+    /// The default implementation ignores it, so that [`BaseCalendar::kind`] keeps its default value
+    fn set_kind(&mut self, _kind: crate::calendar::CalendarKind) {}
+
+    /// Returns the sync status of the calendar itself
+    fn sync_status(&self) -> CalendarSyncStatus;
+
+    /// Set the sync status of the calendar itself.
+    /// Setting it to [`CalendarSyncStatus::LocallyDeleted`] marks the calendar for deletion: the upcoming sync will delete it from the remote source, then from the local source
+    fn set_sync_status(&mut self, status: CalendarSyncStatus);
+
+    /// Returns the journal of the sync of this calendar that is in progress, or that has been interrupted (see [`SyncJournal`])
+    fn sync_journal(&self) -> Option<&SyncJournal> {
+        None
+    }
+
+    /// Record the progress of the current sync of this calendar (`None` once it is over).
+    ///
+    /// Calendars that support it persist the journal at once, along with their items, so that a sync that gets interrupted can be resumed. The default implementation ignores it
+    fn set_sync_journal(&mut self, _journal: Option<SyncJournal>) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// Returns the version of the remote calendar (see [`DavCalendar::get_current_version`]) as of the last sync that left both calendars identical, if known
+    fn last_remote_version(&self) -> Option<&VersionTag> {
+        None
+    }
+
+    /// Record the version of the remote calendar, once a sync has left both calendars identical (`None` in case it is not known).
+    ///
+    /// The next sync can be skipped as long as the remote calendar still has this version and nothing has changed locally. The default implementation ignores it, so that such calendars are always compared
+    fn set_last_remote_version(&mut self, _version: Option<VersionTag>) {}
+
+    /// Returns the date before which the changes of an item should not be pushed again, because the server has asked to wait (see [`RetryLaterError`](crate::config::RetryLaterError)) the last time they were pushed
+    fn retry_not_before(&self, _url: &Url) -> Option<DateTime<Utc>> {
+        None
+    }
+
+    /// Record the date before which the changes of an item should not be pushed again (`None` once they have been pushed).
+    ///
+    /// The default implementation ignores it, so that every sync pushes such items again
+    fn set_retry_not_before(&mut self, _url: &Url, _not_before: Option<DateTime<Utc>>) {}
+
+    /// Returns what is needed to undo the last sync that changed this calendar (see [`SyncRollback`])
+    fn rollback_point(&self) -> Option<&SyncRollback> {
+        None
+    }
+
+    /// Record the state of this calendar before a sync changes it (`None` once it has been rolled back).
+    ///
+    /// Calendars that support it keep it along with their items. The default implementation ignores it, so that syncs of such calendars cannot be undone
+    fn set_rollback_point(&mut self, _rollback: Option<SyncRollback>) {}
+
+    /// Returns the conflicts that the syncs of this calendar have resolved, oldest first (see [`ConflictRecord`])
+    fn conflict_log(&self) -> &[ConflictRecord] {
+        &[]
+    }
+
+    /// Record conflicts that a sync has resolved.
+    ///
+    /// Calendars that support it keep the most recent ones along with their items. The default implementation ignores them
+    fn record_conflicts(&mut self, _conflicts: Vec<ConflictRecord>) {}
+
+    /// Forget the deleted items that have been kept for longer than their retention period.
+    ///
+    /// Providers call this after the sync of every calendar. The default implementation does nothing, for calendars that do not keep deleted items
+    fn purge_trash(&mut self) {}
+
+    /// Write every item of this calendar into a single iCal (`.ics`) stream, e.g. for backups or to import them into other tools. \
+    /// The stream advertises the ProdID of `config`. Items that are marked for deletion are not exported.
+    async fn export_ics(&self, config: &Config, writer: &mut (dyn std::io::Write + Send)) -> Result<(), Box<dyn Error>> {
+        let mut items: Vec<&Item> = self.iter_items()
+            .filter(|item| matches!(item.sync_status(), SyncStatus::LocallyDeleted(_)) == false)
+            .collect();
+        items.sort_by(|a, b| a.url().cmp(b.url()));
+
+        crate::ical::write_calendar(writer, &config.prod_id(), self.name(), items)
+    }
+
+    /// Render the tasks of this calendar as a Markdown checklist (see [`crate::markdown`] to render tasks of several calendars, or a subset of them). \
+    /// Tasks that are marked for deletion are not exported.
+    async fn export_markdown(&self) -> Result<String, Box<dyn Error>> {
+        let name = self.name();
+        let tasks = self.iter_tasks()
+            .filter(|task| matches!(task.sync_status(), SyncStatus::LocallyDeleted(_)) == false)
+            .map(|task| (name, task));
+        Ok(crate::markdown::build_checklist(tasks, crate::markdown::Grouping::Calendar))
+    }
+}
+
+
+#[cfg(all(test, feature = "cache"))]
+mod tests {
+    use super::*;
+
+    use crate::cache::Cache;
+    #[cfg(feature = "client")]
+    use crate::client::Client;
+
+    // Calendars can be used as trait objects as well
+    #[allow(dead_code)]
+    fn assert_object_safe(_: &dyn DavCalendar, _: &dyn CompleteCalendar) {}
+
+    #[cfg(feature = "client")]
+    #[tokio::test]
+    async fn test_switch_sources_at_runtime() {
+        let sources: Vec<Box<dyn DynCalDavSource>> = vec![
+            Box::new(Cache::new_in_memory_remote()),
+            Box::new(Client::new("https://some.server/dav/", "user", "password").unwrap()),
+        ];
+
+        let source = &sources[0];
+        let cal_url = Url::parse("https://caldav.com/dyn").unwrap();
+        let created = source.create_calendar(cal_url.clone(), String::from("Dyn"), SupportedComponents::TODO, None).await.unwrap();
+        assert_eq!(created.read().unwrap().name(), "Dyn");
+
+        let calendars = source.get_calendars().await.unwrap();
+        assert_eq!(calendars.len(), 1);
+        assert!(calendars[&cal_url].read().unwrap().supports_todo());
+
+        source.delete_calendar(&cal_url).await.unwrap();
+        assert!(source.get_calendar(&cal_url).await.is_none());
+    }
+    #[tokio::test]
+    async fn test_with_calendar() {
+        let cache = Cache::new_in_memory_remote();
+        let cal_url = Url::parse("https://caldav.com/closures").unwrap();
+        CalDavSource::create_calendar(&cache, cal_url.clone(), String::from("Closures"), SupportedComponents::TODO, None).await.unwrap();
+
+        let task = crate::Task::new(String::from("Hidden lock"), false, &cal_url);
+        cache.with_calendar_mut(&cal_url, |cal| cal.add_item_sync(crate::Item::Task(task))).await.unwrap().unwrap();
+        let count = cache.with_calendar(&cal_url, |cal| cal.get_items_sync().unwrap().len()).await;
+        assert_eq!(count, Some(1));
+
+        let unknown_url = Url::parse("https://caldav.com/unknown").unwrap();
+        assert_eq!(cache.with_calendar(&unknown_url, |cal| cal.name().to_string()).await, None);
+    }
+
+    #[test]
+    fn test_iter_items() {
+        let cal_url = Url::parse("https://caldav.com/iter").unwrap();
+        let mut cal: crate::calendar::cached_calendar::CachedCalendar = CompleteCalendar::new(String::from("Iter"), cal_url.clone(), SupportedComponents::TODO, None);
+        cal.add_item_sync(Item::Task(crate::Task::new(String::from("First"), false, &cal_url))).unwrap();
+        cal.add_item_sync(Item::Task(crate::Task::new(String::from("Second"), true, &cal_url))).unwrap();
+
+        let cal: &dyn CompleteCalendar = &cal;
+        assert_eq!(cal.iter_items().count(), 2);
+        let completed: Vec<&str> = cal.iter_tasks()
+            .filter(|task| task.completed())
+            .map(|task| task.name())
+            .collect();
+        assert_eq!(completed, vec!["Second"]);
+    }
+
+    #[test]
+    fn test_items_page() {
+        let cal_url = Url::parse("https://caldav.com/pages").unwrap();
+        let mut cal: crate::calendar::cached_calendar::CachedCalendar = CompleteCalendar::new(String::from("Pages"), cal_url.clone(), SupportedComponents::TODO, None);
+        for i in 0..10 {
+            cal.add_item_sync(Item::Task(crate::Task::new(format!("Task {}", i), i % 3 == 0, &cal_url))).unwrap();
+        }
+
+        let names = |items: Vec<&Item>| items.iter().map(|item| item.name().to_string()).collect::<Vec<_>>();
+        let sort_keys = [SortKey::Alphabetical];
+        assert_eq!(names(cal.get_items_page(&SearchFilter::All, &sort_keys, 0, 3)), vec!["Task 0", "Task 1", "Task 2"]);
+        assert_eq!(names(cal.get_items_page(&SearchFilter::All, &sort_keys, 8, 3)), vec!["Task 8", "Task 9"]);
+        assert!(cal.get_items_page(&SearchFilter::All, &sort_keys, 10, 3).is_empty());
+        assert_eq!(names(cal.get_items_page(&SearchFilter::PendingTasks, &[SortKey::Alphabetical.reversed()], 1, 2)), vec!["Task 7", "Task 5"]);
+    }
+
+    #[tokio::test]
+    async fn test_export_ics() {
+        let cal_url = Url::parse("https://caldav.com/export").unwrap();
+        let mut cal: crate::calendar::cached_calendar::CachedCalendar = CompleteCalendar::new(String::from("Export"), cal_url.clone(), SupportedComponents::TODO, None);
+        let mut urls = Vec::new();
+        for name in ["First", "Second", "Deleted"] {
+            let mut item = Item::Task(crate::Task::new(String::from(name), false, &cal_url));
+            item.set_sync_status(SyncStatus::Synced(crate::item::VersionTag::from(String::from("some-tag"))));
+            urls.push(item.url().clone());
+            cal.add_item_sync(item).unwrap();
+        }
+        cal.mark_for_deletion_sync(&urls[2]).unwrap();
+
+        let mut exported = Vec::new();
+        let config = crate::config::Config { org_name: String::from("ABC Corporation"), ..crate::config::Config::default() };
+        cal.export_ics(&config, &mut exported).await.unwrap();
+        let exported = String::from_utf8(exported).unwrap();
+        assert_eq!(exported.matches("BEGIN:VCALENDAR").count(), 1);
+        assert!(exported.contains("X-WR-CALNAME:Export"));
+        assert!(exported.contains(&format!("PRODID:{}\r\n", config.prod_id())));
+        assert_eq!(exported.matches("BEGIN:VTODO").count(), 2);
+        assert!(!exported.contains("SUMMARY:Deleted"));
+
+        // Items are exported in a stable order, so that successive exports can be compared
+        let position = |name: &str| exported.find(&format!("SUMMARY:{}", name)).unwrap();
+        assert_eq!(position("First") < position("Second"), urls[0] < urls[1]);
+    }
 }
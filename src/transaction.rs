@@ -0,0 +1,197 @@
+//! Changes to several items (or calendars) of a [`Cache`] that are applied at once, see [`Cache::transaction`]
+//!
+//! This module requires the `cache` feature (enabled by default).
+#![cfg(feature = "cache")]
+
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Entry;
+use std::error::Error;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::sync::{Arc, RwLock, RwLockWriteGuard};
+
+use csscolorparser::Color;
+use url::Url;
+
+use crate::cache::{Cache, write_json_atomically};
+use crate::calendar::SupportedComponents;
+use crate::calendar::cached_calendar::CachedCalendar;
+use crate::traits::{BaseCalendar, CompleteCalendar};
+
+/// The file that lists the calendar files a transaction is replacing, while it is being committed
+const TRANSACTION_FILE: &str = "transaction.json";
+/// The extension of the new versions of the calendar files, until a transaction is committed
+const STAGED_EXTENSION: &str = "txn";
+
+/// A calendar of the cache that has been copied into a transaction, along with its revision at that time
+type CopiedCalendar = (Arc<RwLock<CachedCalendar>>, u64);
+
+/// Changes to the calendars of a [`Cache`], that are only applied once they are all done. See [`Cache::transaction`].
+///
+/// The calendars of a transaction are copies of the calendars of the cache, that can be changed with the usual methods of [`CachedCalendar`]
+/// (e.g. [`CachedCalendar::add_item_sync`], [`CachedCalendar::set_completed_bulk`], or setting their [sync status](crate::traits::CompleteCalendar::set_sync_status)
+/// to mark them for deletion). Observers are not notified until the transaction is committed. \
+/// Committing fails in case one of these calendars has been changed in the meantime (e.g. by a sync), so that these changes are not lost.
+pub struct Transaction<'c> {
+    cache: &'c Cache,
+    /// The edited copies of the calendars, that replace the calendars of the cache once committed
+    staged: HashMap<Url, CachedCalendar>,
+    /// The calendars of the cache that have been copied, along with their revisions at that time
+    copied_from: HashMap<Url, CopiedCalendar>,
+    /// The calendars that are created by this transaction
+    created: HashSet<Url>,
+}
+
+impl<'c> Transaction<'c> {
+    pub(crate) fn new(cache: &'c Cache) -> Self {
+        Self { cache, staged: HashMap::new(), copied_from: HashMap::new(), created: HashSet::new() }
+    }
+
+    /// Returns the copy of a calendar of the cache that this transaction changes
+    pub fn calendar(&mut self, url: &Url) -> Result<&mut CachedCalendar, Box<dyn Error>> {
+        match self.staged.entry(url.clone()) {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => {
+                let cal = self.cache.get_calendar_sync(url).ok_or_else(|| format!("There is no calendar {}", url))?;
+                let (copy, revision) = {
+                    let cal = cal.read().unwrap();
+                    (cal.detached_copy(), cal.revision())
+                };
+                self.copied_from.insert(url.clone(), (cal, revision));
+                Ok(entry.insert(copy))
+            },
+        }
+    }
+
+    /// Create a calendar, that is added to the cache when this transaction is committed
+    pub fn create_calendar(&mut self, url: Url, name: String, supported_components: SupportedComponents, color: Option<Color>) -> Result<&mut CachedCalendar, Box<dyn Error>> {
+        if self.staged.contains_key(&url) || self.cache.get_calendar_sync(&url).is_some() {
+            return Err(format!("Calendar {} exists already", url).into());
+        }
+        let mut cal: CachedCalendar = CompleteCalendar::new(name, url.clone(), supported_components, color);
+        self.cache.apply_calendar_settings(&mut cal);
+        self.created.insert(url.clone());
+        Ok(self.staged.entry(url).or_insert(cal))
+    }
+
+    /// Write the changed calendars to the backing folder of the cache (all of them or none), then replace the calendars of the cache
+    pub(crate) fn commit(self) -> Result<(), Box<dyn Error>> {
+        if self.staged.is_empty() {
+            return Ok(());
+        }
+        // In case they have to be added to the cache (either they have just been created, or they have been deleted while this transaction was running)
+        let adopted: HashMap<Url, Arc<RwLock<CachedCalendar>>> = self.staged.iter()
+            .map(|(url, cal)| (url.clone(), Arc::new(RwLock::new(self.cache.new_adopted_calendar(cal)))))
+            .collect();
+
+        // The calendars that have been copied are locked until their copies are applied (in a consistent order, and before the calendars of the cache, that observers may use)...
+        let mut copied: Vec<(&Url, &CopiedCalendar)> = self.copied_from.iter().collect();
+        copied.sort_by_key(|(url, _)| *url);
+        let mut existing_calendars: HashMap<Url, RwLockWriteGuard<CachedCalendar>> = HashMap::new();
+        for (url, (cal, revision)) in copied {
+            let guard = cal.write().unwrap();
+            // ...so that changes that other threads (e.g. a sync) have made in the meantime are not overwritten
+            if guard.revision() != *revision {
+                return Err(format!("Calendar {} has been changed while this transaction was running", url).into());
+            }
+            existing_calendars.insert(url.clone(), guard);
+        }
+
+        let mut new_calendars = Vec::new();
+        let mut applied_calendars = Vec::new();
+        {
+            let mut calendars = self.cache.lock_calendars();
+            // Another thread (e.g. a sync) may have created a calendar with the same URL since
+            if let Some(url) = self.created.iter().find(|url| calendars.contains_key(*url)) {
+                return Err(format!("Calendar {} has been created while this transaction was running", url).into());
+            }
+            if let Some((url, _)) = self.copied_from.iter().find(|(url, (cal, _))| calendars.get(*url).is_some_and(|current| Arc::ptr_eq(current, cal) == false)) {
+                return Err(format!("Calendar {} has been replaced while this transaction was running", url).into());
+            }
+            if self.cache.is_persistent() {
+                write_calendars(self.cache.backing_folder(), self.staged.values())?;
+            }
+            for (url, cal) in self.staged {
+                match (calendars.contains_key(&url), existing_calendars.remove(&url)) {
+                    (true, Some(existing)) => applied_calendars.push((existing, cal)),
+                    _ => {
+                        // Other threads cannot read it until its content has been applied
+                        let new_calendar = &adopted[&url];
+                        new_calendars.push((new_calendar.write().unwrap(), cal));
+                        calendars.insert(url, Arc::clone(new_calendar));
+                    },
+                }
+            }
+        }
+
+        // Observers are notified once the calendars of the cache are unlocked, so that they can use the cache
+        for (mut new_calendar, cal) in new_calendars {
+            new_calendar.apply_transaction(cal);
+        }
+        for (mut existing, cal) in applied_calendars {
+            existing.apply_transaction(cal);
+        }
+        Ok(())
+    }
+}
+
+/// Write calendars to a cache folder, so that either all of them or none of them are replaced, even if the process is interrupted
+fn write_calendars<'a>(folder: &Path, calendars: impl Iterator<Item = &'a CachedCalendar>) -> Result<(), Box<dyn Error>> {
+    std::fs::create_dir_all(folder)?;
+
+    // The new versions are written next to the current ones...
+    let mut file_names = Vec::new();
+    for cal in calendars {
+        let file_name = Cache::calendar_file_name(cal.url());
+        write_json_atomically(&folder.join(staged_file_name(&file_name)), cal)?;
+        file_names.push(file_name);
+    }
+
+    // ...and they replace the current ones once they are all on disk. From now on, an interrupted commit is completed by `recover`
+    let transaction_file = folder.join(TRANSACTION_FILE);
+    write_json_atomically(&transaction_file, &file_names)?;
+    rename_staged_files(folder, &file_names)?;
+    std::fs::remove_file(&transaction_file)?;
+    Ok(())
+}
+
+/// The name of the file the new version of a calendar file is written to, until it replaces it
+fn staged_file_name(file_name: &str) -> String {
+    format!("{}.{}", file_name, STAGED_EXTENSION)
+}
+
+/// Replace calendar files with their new versions
+fn rename_staged_files(folder: &Path, file_names: &[String]) -> Result<(), std::io::Error> {
+    for file_name in file_names {
+        let staged = folder.join(staged_file_name(file_name));
+        // It may have been renamed already, by a commit that has been interrupted
+        if staged.exists() {
+            std::fs::rename(&staged, folder.join(file_name))?;
+        }
+    }
+    Ok(())
+}
+
+/// Complete the transaction that was being committed to a cache folder, in case it has been interrupted, and discard the ones that had not started being committed
+pub(crate) fn recover(folder: &Path) -> Result<(), Box<dyn Error>> {
+    let transaction_file = folder.join(TRANSACTION_FILE);
+    if let Ok(file) = std::fs::File::open(&transaction_file) {
+        let file_names: Vec<String> = serde_json::from_reader(file)?;
+        log::info!(target: crate::logging::CACHE, "Completing an interrupted transaction of {} calendars in {:?}", file_names.len(), folder);
+        rename_staged_files(folder, &file_names)?;
+        std::fs::remove_file(&transaction_file)?;
+    }
+
+    let entries = match std::fs::read_dir(folder) {
+        Err(_) => return Ok(()), // Nothing has been written yet
+        Ok(entries) => entries,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension() == Some(OsStr::new(STAGED_EXTENSION)) {
+            log::info!(target: crate::logging::CACHE, "Discarding {:?}, from a transaction that has not been committed", path);
+            std::fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
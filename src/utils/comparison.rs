@@ -0,0 +1,196 @@
+//! Comparators to sort items in list views
+//!
+//! Every comparator gives a total order, and puts the items that lack the compared property (e.g. tasks without a due date) last.
+
+use std::cmp::Ordering;
+
+use chrono::{DateTime, Utc};
+
+use crate::Item;
+
+/// Compare items by name (case-insensitive)
+pub fn compare_items_alpha(a: &Item, b: &Item) -> Ordering {
+    a.name().to_lowercase().cmp(&b.name().to_lowercase())
+        .then_with(|| a.name().cmp(b.name()))
+}
+
+/// Compare tasks by due date, earliest first
+pub fn compare_items_by_due_date(a: &Item, b: &Item) -> Ordering {
+    none_last(due_date(a), due_date(b))
+}
+
+/// Compare tasks by priority, highest first
+pub fn compare_items_by_priority(a: &Item, b: &Item) -> Ordering {
+    none_last(priority(a), priority(b))
+}
+
+/// Compare items by creation date, oldest first
+pub fn compare_items_by_creation_date(a: &Item, b: &Item) -> Ordering {
+    none_last(a.creation_date(), b.creation_date())
+}
+
+/// Compare tasks by completion date, earliest first. Uncompleted tasks come last
+pub fn compare_items_by_completion_date(a: &Item, b: &Item) -> Ordering {
+    none_last(completion_date(a), completion_date(b))
+}
+
+/// Compare calendar orders (see [`CalendarProperties::order`](crate::calendar::CalendarProperties::order))
+pub fn compare_calendar_orders(a: Option<u32>, b: Option<u32>) -> Ordering {
+    none_last(a, b)
+}
+
+
+/// Something that can be sorted with a [`SortKey`]
+pub trait Sortable {
+    /// The item to sort
+    fn item(&self) -> &Item;
+    /// The order of the calendar this item belongs to, if known
+    fn calendar_order(&self) -> Option<u32> { None }
+}
+
+impl Sortable for Item {
+    fn item(&self) -> &Item { self }
+}
+
+impl<S: Sortable> Sortable for &S {
+    fn item(&self) -> &Item { (*self).item() }
+    fn calendar_order(&self) -> Option<u32> { (*self).calendar_order() }
+}
+
+/// An item, along with the order of its calendar
+impl<S: Sortable> Sortable for (S, Option<u32>) {
+    fn item(&self) -> &Item { self.0.item() }
+    fn calendar_order(&self) -> Option<u32> { self.1 }
+}
+
+/// A sorting criterion. Several keys can be combined with [`compare_with_keys`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SortKey {
+    /// See [`compare_items_alpha`]
+    Alphabetical,
+    /// See [`compare_items_by_due_date`]
+    DueDate,
+    /// See [`compare_items_by_priority`]
+    Priority,
+    /// See [`compare_items_by_creation_date`]
+    CreationDate,
+    /// See [`compare_items_by_completion_date`]
+    CompletionDate,
+    /// See [`compare_calendar_orders`]
+    CalendarOrder,
+    /// Reverse the order of another key
+    Reversed(Box<SortKey>),
+}
+
+impl SortKey {
+    /// Compare two items according to this key only
+    pub fn compare<S: Sortable>(&self, a: &S, b: &S) -> Ordering {
+        match self {
+            SortKey::Alphabetical => compare_items_alpha(a.item(), b.item()),
+            SortKey::DueDate => compare_items_by_due_date(a.item(), b.item()),
+            SortKey::Priority => compare_items_by_priority(a.item(), b.item()),
+            SortKey::CreationDate => compare_items_by_creation_date(a.item(), b.item()),
+            SortKey::CompletionDate => compare_items_by_completion_date(a.item(), b.item()),
+            SortKey::CalendarOrder => compare_calendar_orders(a.calendar_order(), b.calendar_order()),
+            SortKey::Reversed(key) => key.compare(a, b).reverse(),
+        }
+    }
+
+    /// Returns the reversed version of this key
+    pub fn reversed(self) -> Self {
+        SortKey::Reversed(Box::new(self))
+    }
+}
+
+/// Compare two items with several keys, the first ones having precedence.
+///
+/// Items that are equal for every key are sorted by URL, so that the order is always the same
+///
+/// ```
+/// # use kitchen_fridge::Item;
+/// use kitchen_fridge::utils::comparison::{compare_with_keys, SortKey};
+///
+/// fn sort(items: &mut Vec<&Item>) {
+///     let keys = [SortKey::DueDate, SortKey::Priority, SortKey::Alphabetical];
+///     items.sort_by(|a, b| compare_with_keys(&keys, a, b));
+/// }
+/// ```
+pub fn compare_with_keys<S: Sortable>(keys: &[SortKey], a: &S, b: &S) -> Ordering {
+    keys.iter()
+        .map(|key| key.compare(a, b))
+        .find(|ordering| *ordering != Ordering::Equal)
+        .unwrap_or_else(|| a.item().url().cmp(b.item().url()))
+}
+
+
+fn due_date(item: &Item) -> Option<DateTime<Utc>> {
+    match item {
+        Item::Task(task) => task.due(),
+        _ => None,
+    }
+}
+
+fn priority(item: &Item) -> Option<u8> {
+    match item {
+        Item::Task(task) => task.priority(),
+        _ => None,
+    }
+}
+
+fn completion_date(item: &Item) -> Option<&DateTime<Utc>> {
+    match item {
+        Item::Task(task) => task.completion_status().completion_date(),
+        _ => None,
+    }
+}
+
+/// Compare two optional values, `None` being greater than any value
+fn none_last<T: Ord>(a: Option<T>, b: Option<T>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use url::Url;
+
+    use crate::test_utils::TaskBuilder;
+
+    fn task(name: &str) -> TaskBuilder {
+        let cal_url: Url = "https://some.calendar/".parse().unwrap();
+        TaskBuilder::new(name, &cal_url)
+    }
+
+    fn names<S: Sortable>(items: &[S]) -> Vec<&str> {
+        items.iter().map(|s| s.item().name()).collect()
+    }
+
+    #[test]
+    fn test_sort_keys() {
+        let milk = task("buy milk").property("DUE", "20210322").property("PRIORITY", "5").item();
+        let eggs = task("Buy eggs").property("DUE", "20210322").property("PRIORITY", "1").item();
+        let concert = task("Attend a concert").property("PRIORITY", "0").item();
+        let taxes = task("Pay taxes").property("DUE", "20210315").item();
+
+        let mut items = vec![&milk, &eggs, &concert, &taxes];
+        items.sort_by(|a, b| compare_with_keys(&[SortKey::Alphabetical], a, b));
+        assert_eq!(names(&items), vec!["Attend a concert", "Buy eggs", "buy milk", "Pay taxes"]);
+
+        items.sort_by(|a, b| compare_with_keys(&[SortKey::DueDate, SortKey::Alphabetical], a, b));
+        assert_eq!(names(&items), vec!["Pay taxes", "Buy eggs", "buy milk", "Attend a concert"]);
+
+        items.sort_by(|a, b| compare_with_keys(&[SortKey::Priority, SortKey::Alphabetical.reversed()], a, b));
+        assert_eq!(names(&items), vec!["Buy eggs", "buy milk", "Pay taxes", "Attend a concert"]);
+
+        let mut listed = vec![(&milk, Some(2)), (&eggs, None), (&concert, Some(1))];
+        listed.sort_by(|a, b| compare_with_keys(&[SortKey::CalendarOrder], a, b));
+        assert_eq!(names(&listed), vec!["Attend a concert", "buy milk", "Buy eggs"]);
+    }
+}
@@ -0,0 +1,56 @@
+//! Interning of strings that many items share (e.g. their PRODID)
+//!
+//! Large calendars hold thousands of items that have been written by the same few apps. Interning makes them share a single copy of these strings.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Deserializer};
+
+/// Strings that are no longer used by any item are dropped once the table grows past this size
+const PRUNE_THRESHOLD: usize = 64;
+
+static TABLE: Lazy<Mutex<HashSet<Arc<str>>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+/// Returns a shared copy of `s`
+pub(crate) fn intern(s: &str) -> Arc<str> {
+    let mut table = TABLE.lock().unwrap();
+    if let Some(interned) = table.get(s) {
+        return interned.clone();
+    }
+
+    if table.len() >= PRUNE_THRESHOLD {
+        table.retain(|interned| Arc::strong_count(interned) > 1);
+    }
+    let interned: Arc<str> = Arc::from(s);
+    table.insert(interned.clone());
+    interned
+}
+
+/// Deserialize a string, and intern it. This is meant for `#[serde(deserialize_with)]`
+pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<Arc<str>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    Ok(intern(&s))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern() {
+        let first = intern("-//Interned test//EN");
+        let second = intern(&String::from("-//Interned test//EN"));
+        assert!(Arc::ptr_eq(&first, &second));
+        assert!(Arc::ptr_eq(&first, &intern("-//Other interned test//EN")) == false);
+
+        let deserializer = serde::de::value::StrDeserializer::<serde::de::value::Error>::new("-//Interned test//EN");
+        let deserialized = deserialize(deserializer).unwrap();
+        assert!(Arc::ptr_eq(&first, &deserialized));
+    }
+}
@@ -1,8 +1,20 @@
 //! Some utility functions
 
+#[cfg(feature = "client")]
+mod multistatus;
+#[cfg(feature = "client")]
+pub(crate) use multistatus::MultistatusSplitter;
+pub mod comparison;
+pub mod time_zone;
+pub(crate) mod intern;
+
 use std::collections::{HashMap, HashSet};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, RwLock};
 use std::hash::Hash;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::Thread;
 use std::io::{stdin, stdout, Read, Write};
 
 use minidom::Element;
@@ -49,6 +61,61 @@ pub fn find_elem<S: AsRef<str>>(root: &Element, searched_name: S) -> Option<&Ele
     None
 }
 
+/// Escape a text, so that it can be inserted into an XML document
+pub fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+
+/// Decode the body of an HTTP response into a string.
+///
+/// The encoding is taken from the byte order mark if any, then from the `charset` of the `Content-Type` header, then from the XML declaration.
+/// UTF-8 is assumed otherwise.
+pub fn decode_text(bytes: &[u8], content_type: Option<&str>) -> Result<String, Box<dyn std::error::Error>> {
+    let (encoding, bom_length) = detect_encoding(bytes, content_type);
+    encoding.decode_without_bom_handling_and_without_replacement(&bytes[bom_length..])
+        .map(|text| text.into_owned())
+        .ok_or_else(|| format!("Response is not valid {} text", encoding.name()).into())
+}
+
+/// The encoding of the body of an HTTP response (see [`decode_text`]), and the length of its byte order mark.
+///
+/// `bytes` may only be the start of the body, as long as it contains the XML declaration
+pub(crate) fn detect_encoding(bytes: &[u8], content_type: Option<&str>) -> (&'static encoding_rs::Encoding, usize) {
+    match encoding_rs::Encoding::for_bom(bytes) {
+        Some(found) => found,
+        None => {
+            let declared = content_type.and_then(charset_from_content_type)
+                .or_else(|| charset_from_xml_declaration(bytes))
+                .and_then(|label| encoding_rs::Encoding::for_label(label.as_bytes()));
+            (declared.unwrap_or(encoding_rs::UTF_8), 0)
+        },
+    }
+}
+
+fn charset_from_content_type(content_type: &str) -> Option<String> {
+    content_type.split(';')
+        .skip(1)
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+}
+
+fn charset_from_xml_declaration(bytes: &[u8]) -> Option<String> {
+    let start = &bytes[..bytes.len().min(200)];
+    let start = String::from_utf8_lossy(start);
+    let declaration = &start[start.find("<?xml")?..];
+    let declaration = &declaration[..declaration.find("?>")?];
+    let after = &declaration[declaration.find("encoding")? + "encoding".len()..];
+    let after = after.trim_start().strip_prefix('=')?.trim_start();
+    let quote = after.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let value = &after[1..];
+    Some(value[..value.find(quote)?].to_string())
+}
 
 pub fn print_xml(element: &Element) {
     let mut writer = std::io::stdout();
@@ -62,31 +129,28 @@ pub fn print_xml(element: &Element) {
 }
 
 /// A debug utility that pretty-prints calendars
-pub async fn print_calendar_list<C>(cals: &HashMap<Url, Arc<Mutex<C>>>)
+pub async fn print_calendar_list<C>(cals: &HashMap<Url, Arc<RwLock<C>>>)
 where
     C: CompleteCalendar,
 {
     for (url, cal) in cals {
-        println!("CAL {} ({})", cal.lock().unwrap().name(), url);
-        match cal.lock().unwrap().get_items().await {
-            Err(_err) => continue,
-            Ok(map) => {
-                for (_, item) in map {
-                    print_task(item);
-                }
-            },
+        let cal = cal.read().unwrap();
+        println!("CAL {} ({})", cal.name(), url);
+        for item in cal.iter_items() {
+            print_task(item);
         }
     }
 }
 
 /// A debug utility that pretty-prints calendars
-pub async fn print_dav_calendar_list<C>(cals: &HashMap<Url, Arc<Mutex<C>>>)
+pub async fn print_dav_calendar_list<C>(cals: &HashMap<Url, Arc<RwLock<C>>>)
 where
     C: DavCalendar,
 {
     for (url, cal) in cals {
-        println!("CAL {} ({})", cal.lock().unwrap().name(), url);
-        match cal.lock().unwrap().get_item_version_tags().await {
+        let cal = cal.read().unwrap().detached();
+        println!("CAL {} ({})", cal.name(), url);
+        match cal.get_item_version_tags().await {
             Err(_err) => continue,
             Ok(map) => {
                 for (url, version_tag) in map {
@@ -152,6 +216,108 @@ pub fn pause() {
 
 /// Generate a random URL with a given prefix
 pub fn random_url(parent_calendar: &Url) -> Url {
-    let random = uuid::Uuid::new_v4().to_hyphenated().to_string();
+    let random = crate::deterministic::new_uuid().to_hyphenated().to_string();
     parent_calendar.join(&random).unwrap(/* this cannot panic since we've just created a string that is a valid URL */)
 }
+
+/// Run futures concurrently, within the current task, and return their outputs in the same order.
+///
+/// Unlike spawning them, this works for futures that borrow data (e.g. calendars that belong to the caller)
+pub(crate) async fn join_all<F: Future>(futures: Vec<F>) -> Vec<F::Output> {
+    let mut futures: Vec<Pin<Box<F>>> = futures.into_iter().map(Box::pin).collect();
+    let mut outputs: Vec<Option<F::Output>> = futures.iter().map(|_| None).collect();
+    std::future::poll_fn(|cx| {
+        let mut pending = false;
+        for (future, output) in futures.iter_mut().zip(outputs.iter_mut()) {
+            if output.is_some() {
+                continue;
+            }
+            match future.as_mut().poll(cx) {
+                Poll::Ready(value) => *output = Some(value),
+                Poll::Pending => pending = true,
+            }
+        }
+        if pending { Poll::Pending } else { Poll::Ready(()) }
+    }).await;
+    outputs.into_iter().flatten().collect()
+}
+
+/// A future that borrows a calendar (or something else), as returned by the async methods of the calendar traits
+pub(crate) type BorrowingFuture<'a, R> = Pin<Box<dyn Future<Output = R> + Send + 'a>>;
+
+/// Run a single operation of a local calendar, that is read-locked for the duration of this operation only.
+///
+/// Local calendars keep their items in memory, so their async methods (that return references to these items) do not actually wait for anything.
+/// The operation is run to completion here, on the current thread, so that the lock is never held across an `.await`. \
+/// Requests to remote calendars must not go through here: they are made with detached copies of them instead (see [`DavCalendar::detached`]). \
+/// `arg` is given to `op` as well, since `op` cannot borrow anything that would outlive the lock.
+pub(crate) fn read_locked<C, A, R>(calendar: &RwLock<C>, arg: &A, op: impl for<'a> FnOnce(&'a C, &'a A) -> BorrowingFuture<'a, R>) -> R
+where
+    A: ?Sized,
+{
+    let calendar = calendar.read().unwrap();
+    run_to_completion(op(&calendar, arg))
+}
+
+/// Same as [`read_locked`], for operations that need a write lock
+pub(crate) fn write_locked<C, A, R>(calendar: &RwLock<C>, arg: &A, op: impl for<'a> FnOnce(&'a mut C, &'a A) -> BorrowingFuture<'a, R>) -> R
+where
+    A: ?Sized,
+{
+    let mut calendar = calendar.write().unwrap();
+    run_to_completion(op(&mut calendar, arg))
+}
+
+/// Poll a future until it is ready, blocking the current thread whenever it is pending
+fn run_to_completion<F: Future>(future: F) -> F::Output {
+    struct ThreadWaker(Thread);
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut context = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        match future.as_mut().poll(&mut context) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_text() {
+        // "Café" in ISO-8859-1
+        let latin1 = b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><d:displayname>Caf\xe9</d:displayname>";
+        assert_eq!(decode_text(latin1, None).unwrap(), "<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><d:displayname>Café</d:displayname>");
+        assert_eq!(decode_text(b"Caf\xe9", Some("text/calendar; charset=\"iso-8859-1\"")).unwrap(), "Café");
+
+        // The BOM has precedence, and is stripped
+        assert_eq!(decode_text(b"\xef\xbb\xbfCaf\xc3\xa9", Some("text/calendar; charset=iso-8859-1")).unwrap(), "Café");
+        assert_eq!(decode_text(b"\xff\xfeC\x00a\x00f\x00\xe9\x00", None).unwrap(), "Café");
+
+        assert_eq!(decode_text(b"Caf\xc3\xa9", Some("application/xml")).unwrap(), "Café");
+        assert!(decode_text(b"Caf\xe9", None).is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_join_all() {
+        let started = tokio::time::Instant::now();
+        let futures = (1..=3u64).rev()
+            .map(|i| async move {
+                tokio::time::sleep(std::time::Duration::from_millis(100 * i)).await;
+                i
+            })
+            .collect();
+        assert_eq!(join_all(futures).await, vec![3, 2, 1]);
+        // The futures run concurrently: the longest one sets the total duration
+        assert_eq!(started.elapsed(), std::time::Duration::from_millis(300));
+    }
+}
@@ -0,0 +1,326 @@
+//! Incremental parsing of (possibly huge) XML replies, such as `multistatus` replies to REPORT requests
+
+use std::error::Error;
+
+use encoding_rs::Encoding;
+use minidom::Element;
+
+/// Splits an XML document that is received by chunks into the direct children of its root element.
+///
+/// This makes it possible to process the `<response>` elements of a `multistatus` reply one by one, without holding the whole reply (and its whole DOM) in memory. \
+/// The document is only scanned for the boundaries of its markup (tags, comments, CDATA sections and processing instructions). Every child is then parsed by `minidom`, along with the start tag of the root element, so that the namespaces declared there (with a prefix, or as the default namespace) still apply.
+///
+/// Its limits are the ones of the documents CalDAV servers actually send:
+/// * the encoding must be ASCII-compatible (e.g. UTF-8 or ISO-8859-1)
+/// * a `DOCTYPE` must not have an internal subset (i.e. it must not declare entities)
+/// * the document must be well-formed: the scan does not validate it, only the children are (when they are parsed)
+/// * comments are dropped, since `minidom` does not support them
+pub(crate) struct MultistatusSplitter {
+    /// The encoding of the document, that the children are decoded with
+    encoding: &'static Encoding,
+    /// The bytes that have been received but not processed yet
+    buffer: Vec<u8>,
+    /// Where to resume scanning `buffer`
+    scan_pos: usize,
+    /// The start tag of the root element (that contains the namespace declarations), and its qualified name
+    root: Option<(Vec<u8>, String)>,
+    /// How deep `scan_pos` is in the document (0 being outside of the root element)
+    depth: usize,
+    /// Where the child of the root element that is currently being received starts in `buffer`
+    child_start: Option<usize>,
+}
+
+impl MultistatusSplitter {
+    pub fn new(encoding: &'static Encoding) -> Self {
+        Self {
+            encoding,
+            buffer: Vec::new(),
+            scan_pos: 0,
+            root: None,
+            depth: 0,
+            child_start: None,
+        }
+    }
+
+    /// Feed a chunk of the document, and get the (complete) children of the root element that it made available
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<Vec<Element>, Box<dyn Error>> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut children = Vec::new();
+        while let Some((tag_start, tag_end)) = self.next_markup() {
+            let tag = &self.buffer[tag_start..tag_end];
+            self.scan_pos = tag_end;
+
+            match MarkupKind::of(tag) {
+                MarkupKind::Other => continue,
+                MarkupKind::SelfClosing => {
+                    if self.depth == 0 {
+                        // An empty root element (e.g. a multistatus for an empty calendar)
+                        self.root = Some((tag.to_vec(), tag_name(tag)));
+                        continue;
+                    }
+                    if self.depth == 1 {
+                        children.push(self.parse_child(tag_start, tag_end)?);
+                    }
+                },
+                MarkupKind::Start => {
+                    if self.depth == 0 {
+                        let name = tag_name(tag);
+                        self.root = Some((tag.to_vec(), name));
+                    } else if self.depth == 1 {
+                        self.child_start = Some(tag_start);
+                    }
+                    self.depth += 1;
+                },
+                MarkupKind::End => {
+                    self.depth = self.depth.checked_sub(1).ok_or("Unbalanced XML document")?;
+                    if self.depth == 1 {
+                        let child_start = self.child_start.take().ok_or("Unbalanced XML document")?;
+                        children.push(self.parse_child(child_start, tag_end)?);
+                    }
+                },
+            }
+        }
+
+        self.discard_processed_bytes();
+        Ok(children)
+    }
+
+    /// Must be called once the whole document has been fed
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        match self.root.is_some() && self.depth == 0 {
+            true => Ok(()),
+            false => Err("Truncated XML document".into()),
+        }
+    }
+
+    /// Find the next markup (tag, comment, etc.) after `scan_pos`, if it has been fully received
+    fn next_markup(&self) -> Option<(usize, usize)> {
+        let start = self.scan_pos + self.buffer[self.scan_pos..].iter().position(|b| *b == b'<')?;
+        let rest = &self.buffer[start..];
+
+        for (opening, closing) in [(&b"<!--"[..], &b"-->"[..]), (b"<![CDATA[", b"]]>"), (b"<?", b"?>")] {
+            if rest.starts_with(opening) {
+                return find(&rest[opening.len()..], closing)
+                    .map(|pos| (start, start + opening.len() + pos + closing.len()));
+            }
+            if opening.starts_with(rest) {
+                // We do not know yet what kind of markup this is
+                return None;
+            }
+        }
+
+        // A tag, whose attribute values may contain '>'
+        let mut quote = None;
+        for (i, b) in rest.iter().enumerate().skip(1) {
+            match (quote, *b) {
+                (None, b'"') | (None, b'\'') => quote = Some(*b),
+                (Some(q), c) if q == c => quote = None,
+                (None, b'>') => return Some((start, start + i + 1)),
+                _ => (),
+            }
+        }
+        None
+    }
+
+    /// Parse a child of the root element, as a standalone document that has the same root element (so that namespace declarations are kept)
+    fn parse_child(&self, start: usize, end: usize) -> Result<Element, Box<dyn Error>> {
+        let (root_tag, root_name) = self.root.as_ref().ok_or("Missing root element")?;
+
+        let mut document = root_tag.clone();
+        // minidom does not support comments
+        document.extend(without_comments(&self.buffer[start..end]));
+        document.extend_from_slice(format!("</{}>", root_name).as_bytes());
+
+        let (document, _) = self.encoding.decode_without_bom_handling(&document);
+        let root: Element = document.parse()?;
+        let child = root.children().next().ok_or("Missing child element")?;
+        Ok(child.clone())
+    }
+
+    /// Forget about the bytes that will not be needed anymore
+    fn discard_processed_bytes(&mut self) {
+        let keep_from = self.child_start.unwrap_or(self.scan_pos);
+        self.buffer.drain(..keep_from);
+        self.scan_pos -= keep_from;
+        if let Some(start) = self.child_start.as_mut() {
+            *start -= keep_from;
+        }
+    }
+}
+
+enum MarkupKind {
+    Start,
+    End,
+    SelfClosing,
+    /// Comments, CDATA sections, processing instructions, etc.
+    Other,
+}
+
+impl MarkupKind {
+    fn of(tag: &[u8]) -> Self {
+        if tag.starts_with(b"<!") || tag.starts_with(b"<?") {
+            MarkupKind::Other
+        } else if tag.starts_with(b"</") {
+            MarkupKind::End
+        } else if tag.ends_with(b"/>") {
+            MarkupKind::SelfClosing
+        } else {
+            MarkupKind::Start
+        }
+    }
+}
+
+/// The qualified name of a start tag
+fn tag_name(tag: &[u8]) -> String {
+    let name: Vec<u8> = tag[1..].iter()
+        .take_while(|b| b.is_ascii_whitespace() == false && **b != b'>' && **b != b'/')
+        .cloned()
+        .collect();
+    String::from_utf8_lossy(&name).to_string()
+}
+
+/// A copy of a (well-formed) XML fragment, without its comments
+fn without_comments(fragment: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(fragment.len());
+    let mut rest = fragment;
+    while let Some(start) = rest.iter().position(|b| *b == b'<') {
+        result.extend_from_slice(&rest[..start]);
+        rest = &rest[start..];
+
+        // A '<' in a well-formed fragment is either markup, or inside a CDATA section
+        let (opening, closing, keep) = if rest.starts_with(b"<!--") {
+            (&b"<!--"[..], &b"-->"[..], false)
+        } else if rest.starts_with(b"<![CDATA[") {
+            (&b"<![CDATA["[..], &b"]]>"[..], true)
+        } else {
+            result.push(b'<');
+            rest = &rest[1..];
+            continue;
+        };
+        let end = find(&rest[opening.len()..], closing)
+            .map(|pos| opening.len() + pos + closing.len())
+            .unwrap_or(rest.len());
+        if keep {
+            result.extend_from_slice(&rest[..end]);
+        }
+        rest = &rest[end..];
+    }
+    result.extend_from_slice(rest);
+    result
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MULTISTATUS: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:multistatus xmlns:d="DAV:" xmlns:cal="urn:ietf:params:xml:ns:caldav">
+  <!-- a comment with a </d:response> in it -->
+  <d:response>
+    <d:href>/calendars/user/tasks/1.ics</d:href>
+    <d:propstat><d:prop><d:getetag>"tag-1"</d:getetag></d:prop></d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>/calendars/user/tasks/2.ics</d:href>
+    <d:propstat><d:prop>
+      <d:getetag>"tag->2"</d:getetag>
+      <cal:calendar-data><![CDATA[BEGIN:VCALENDAR
+SUMMARY:</d:response> is not a tag here
+END:VCALENDAR]]></cal:calendar-data>
+    </d:prop></d:propstat>
+  </d:response>
+  <d:response/>
+</d:multistatus>
+"#;
+
+    #[test]
+    fn test_split_by_chunks() {
+        for chunk_size in [1, 7, 64, MULTISTATUS.len()] {
+            let mut splitter = MultistatusSplitter::new(encoding_rs::UTF_8);
+            let mut children = Vec::new();
+            for chunk in MULTISTATUS.as_bytes().chunks(chunk_size) {
+                children.extend(splitter.feed(chunk).unwrap());
+            }
+            splitter.finish().unwrap();
+
+            assert_eq!(children.len(), 3);
+            assert!(children.iter().all(|child| child.name() == "response" && child.ns() == "DAV:"));
+            assert_eq!(crate::utils::find_elem(&children[0], "href").unwrap().text(), "/calendars/user/tasks/1.ics");
+            assert_eq!(crate::utils::find_elem(&children[1], "getetag").unwrap().text(), "\"tag->2\"");
+            assert!(crate::utils::find_elem(&children[1], "calendar-data").unwrap().text().contains("</d:response> is not a tag here"));
+        }
+    }
+
+    #[test]
+    fn test_namespaces() {
+        // The default namespace can be declared on the root element...
+        let mut splitter = MultistatusSplitter::new(encoding_rs::UTF_8);
+        let children = splitter.feed(br#"<multistatus xmlns="DAV:"><response><href>/1.ics</href></response><response><href>/2.ics</href></response></multistatus>"#).unwrap();
+        splitter.finish().unwrap();
+        assert_eq!(children.len(), 2);
+        assert!(children.iter().all(|child| child.name() == "response" && child.ns() == "DAV:"));
+
+        // ...or on the children themselves, and prefixes can differ from one child to another
+        let mut splitter = MultistatusSplitter::new(encoding_rs::UTF_8);
+        let children = splitter.feed(br#"<D:multistatus xmlns:D="DAV:">
+  <response xmlns="DAV:"><href>/1.ics</href></response>
+  <A:response xmlns:A="DAV:"><A:href>/2.ics</A:href></A:response>
+  <D:response><D:href>/3.ics</D:href></D:response>
+</D:multistatus>"#).unwrap();
+        splitter.finish().unwrap();
+        assert_eq!(children.len(), 3);
+        assert!(children.iter().all(|child| child.name() == "response" && child.ns() == "DAV:"));
+        assert_eq!(crate::utils::find_elem(&children[1], "href").unwrap().text(), "/2.ics");
+    }
+
+    #[test]
+    fn test_comments_and_cdata() {
+        let document = r#"<?xml version="1.0"?>
+<!DOCTYPE multistatus>
+<d:multistatus xmlns:d="DAV:"><!-- <d:response> --><![CDATA[<d:response>]]><d:response><d:href><![CDATA[/a<b>.ics]]></d:href><!-- </d:response> --></d:response></d:multistatus>"#;
+        for chunk_size in [1, 3, document.len()] {
+            let mut splitter = MultistatusSplitter::new(encoding_rs::UTF_8);
+            let mut children = Vec::new();
+            for chunk in document.as_bytes().chunks(chunk_size) {
+                children.extend(splitter.feed(chunk).unwrap());
+            }
+            splitter.finish().unwrap();
+            assert_eq!(children.len(), 1);
+            assert_eq!(crate::utils::find_elem(&children[0], "href").unwrap().text(), "/a<b>.ics");
+        }
+    }
+
+    #[test]
+    fn test_truncated_document() {
+        let mut splitter = MultistatusSplitter::new(encoding_rs::UTF_8);
+        let children = splitter.feed(&MULTISTATUS.as_bytes()[..400]).unwrap();
+        assert_eq!(children.len(), 1);
+        assert!(splitter.finish().is_err());
+    }
+
+    #[test]
+    fn test_empty_root() {
+        let mut splitter = MultistatusSplitter::new(encoding_rs::UTF_8);
+        let children = splitter.feed(br#"<?xml version="1.0" encoding="utf-8"?>
+<d:multistatus xmlns:d="DAV:"/>
+"#).unwrap();
+        assert!(children.is_empty());
+        splitter.finish().unwrap();
+    }
+
+    #[test]
+    fn test_latin1() {
+        let text = MULTISTATUS.replace("tasks/1.ics", "tâches/1.ics");
+        let (document, _, _) = encoding_rs::WINDOWS_1252.encode(&text);
+        let mut splitter = MultistatusSplitter::new(encoding_rs::WINDOWS_1252);
+        let children = splitter.feed(&document).unwrap();
+        splitter.finish().unwrap();
+        assert_eq!(crate::utils::find_elem(&children[0], "href").unwrap().text(), "/calendars/user/tâches/1.ics");
+    }
+}
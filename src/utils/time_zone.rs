@@ -0,0 +1,234 @@
+//! Dates of iCal properties (e.g. `DUE;TZID=Europe/Paris:20210322T090000`), and conversions between time zones
+//!
+//! Most of this crate handles dates as UTC instants. This module keeps track of the time zone a date is expressed in,
+//! so that date math (e.g. "the same time, one day later") does not shift by one hour across daylight saving time changes.
+
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Offset, TimeZone, Utc};
+use chrono_tz::Tz;
+use ical::property::Property;
+
+use crate::ical::param;
+
+/// The value of a date property, as it is stored in an iCal file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IcalDateTime {
+    /// A whole day (e.g. `DUE;VALUE=DATE:20210322`)
+    Date(NaiveDate),
+    /// An instant (e.g. `DUE:20210322T090000Z`)
+    Utc(DateTime<Utc>),
+    /// A time in a given time zone (e.g. `DUE;TZID=Europe/Paris:20210322T090000`)
+    Zoned(DateTime<Tz>),
+    /// A "floating" time, that does not depend on any time zone (e.g. `DUE:20210322T090000`).
+    /// This is also used for times in a time zone this crate does not know about
+    Floating(NaiveDateTime),
+}
+
+impl IcalDateTime {
+    /// Parse the value of a date property. `None` in case it is invalid
+    pub fn from_property(prop: &Property) -> Option<Self> {
+        let value = prop.value.as_deref()?.trim();
+
+        if param(prop, "VALUE") == Some("DATE") || value.len() == 8 {
+            return NaiveDate::parse_from_str(value, "%Y%m%d").ok().map(IcalDateTime::Date);
+        }
+        if let Some(utc) = value.strip_suffix('Z') {
+            return NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").ok()
+                .map(|naive| IcalDateTime::Utc(Utc.from_utc_datetime(&naive)));
+        }
+
+        let local = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()?;
+        match param(prop, "TZID") {
+            None => Some(IcalDateTime::Floating(local)),
+            Some(tzid) => match parse_tzid(tzid) {
+                Some(tz) => Some(IcalDateTime::Zoned(from_local(&local, &tz))),
+                None => {
+                    log::warn!("Unknown time zone {}, {} is considered as a floating time", tzid, value);
+                    Some(IcalDateTime::Floating(local))
+                },
+            },
+        }
+    }
+
+    /// Create a property with this value
+    pub fn to_property(&self, name: &str) -> Property {
+        let mut prop = Property::new();
+        prop.name = name.to_string();
+        let (params, value) = match self {
+            IcalDateTime::Date(date) => (Some(vec![(String::from("VALUE"), vec![String::from("DATE")])]), date.format("%Y%m%d").to_string()),
+            IcalDateTime::Utc(utc) => (None, utc.format("%Y%m%dT%H%M%SZ").to_string()),
+            IcalDateTime::Zoned(zoned) => (Some(vec![(String::from("TZID"), vec![zoned.timezone().name().to_string()])]), zoned.format("%Y%m%dT%H%M%S").to_string()),
+            IcalDateTime::Floating(local) => (None, local.format("%Y%m%dT%H%M%S").to_string()),
+        };
+        prop.params = params;
+        prop.value = Some(value);
+        prop
+    }
+
+    /// The instant this date stands for, in case floating times and whole days are considered in UTC (see [`IcalDateTime::to_utc_in`])
+    pub fn to_utc(&self) -> DateTime<Utc> {
+        match self {
+            IcalDateTime::Date(date) => Utc.from_utc_datetime(&date.and_hms(0, 0, 0)),
+            IcalDateTime::Utc(utc) => *utc,
+            IcalDateTime::Zoned(zoned) => zoned.with_timezone(&Utc),
+            IcalDateTime::Floating(local) => Utc.from_utc_datetime(local),
+        }
+    }
+
+    /// The instant this date stands for, for a user in a given time zone.
+    ///
+    /// Floating times and whole days do not depend on any time zone: e.g. a task due at 9:00 is due at 9:00 wherever its user is. This is where they are resolved.
+    pub fn to_utc_in(&self, local_zone: &Tz) -> DateTime<Utc> {
+        match self {
+            IcalDateTime::Date(_) | IcalDateTime::Floating(_) => from_local(&self.naive_local(), local_zone).with_timezone(&Utc),
+            _ => self.to_utc(),
+        }
+    }
+
+    /// The date and time on the wall clock, as it is stored (i.e. in its own time zone for [`IcalDateTime::Zoned`] dates, in UTC for [`IcalDateTime::Utc`] dates)
+    pub fn naive_local(&self) -> NaiveDateTime {
+        match self {
+            IcalDateTime::Date(date) => date.and_hms(0, 0, 0),
+            IcalDateTime::Utc(utc) => utc.naive_utc(),
+            IcalDateTime::Zoned(zoned) => zoned.naive_local(),
+            IcalDateTime::Floating(local) => *local,
+        }
+    }
+
+    /// Whether this date does not depend on any time zone (i.e. it is a floating time, or a whole day)
+    pub fn is_floating(&self) -> bool {
+        matches!(self, IcalDateTime::Date(_) | IcalDateTime::Floating(_))
+    }
+
+    /// The time zone of this date, if it is a [`IcalDateTime::Zoned`] one
+    pub fn time_zone(&self) -> Option<Tz> {
+        match self {
+            IcalDateTime::Zoned(zoned) => Some(zoned.timezone()),
+            _ => None,
+        }
+    }
+
+    /// Express an instant the same way as this date (e.g. in the same time zone).
+    ///
+    /// This is useful to write properties that RFC5545 requires to have the same value type as another one (e.g. a `RECURRENCE-ID` and its `DTSTART`)
+    pub fn same_kind(&self, instant: &DateTime<Utc>) -> IcalDateTime {
+        match self {
+            IcalDateTime::Date(_) => IcalDateTime::Date(instant.naive_utc().date()),
+            IcalDateTime::Utc(_) => IcalDateTime::Utc(*instant),
+            IcalDateTime::Zoned(zoned) => IcalDateTime::Zoned(instant.with_timezone(&zoned.timezone())),
+            IcalDateTime::Floating(_) => IcalDateTime::Floating(instant.naive_utc()),
+        }
+    }
+
+    /// Move this date by a duration on the wall clock (e.g. "one day later, at the same time"), rather than by an absolute duration.
+    ///
+    /// Both are the same, unless a daylight saving time change happens in between.
+    pub fn add_wall_clock(&self, duration: Duration) -> IcalDateTime {
+        match self {
+            IcalDateTime::Date(date) => IcalDateTime::Date((date.and_hms(0, 0, 0) + duration).date()),
+            IcalDateTime::Utc(utc) => IcalDateTime::Utc(*utc + duration),
+            IcalDateTime::Zoned(zoned) => IcalDateTime::Zoned(add_wall_clock(zoned, duration)),
+            IcalDateTime::Floating(local) => IcalDateTime::Floating(*local + duration),
+        }
+    }
+}
+
+/// Find a time zone from a `TZID` parameter.
+///
+/// Besides IANA names (e.g. `Europe/Paris`), some clients use a prefix (e.g. `/mozilla.org/20050126_1/Europe/Paris`), that is ignored
+pub fn parse_tzid(tzid: &str) -> Option<Tz> {
+    let tzid = tzid.trim().trim_matches('"');
+    if let Ok(tz) = tzid.parse() {
+        return Some(tz);
+    }
+
+    // Try the last segments of the TZID, e.g. `America/Argentina/Buenos_Aires`, then `Argentina/Buenos_Aires`, then `Buenos_Aires`
+    let segments: Vec<&str> = tzid.split('/').filter(|segment| segment.is_empty() == false).collect();
+    (1..segments.len())
+        .find_map(|start| segments[start..].join("/").parse().ok())
+}
+
+/// The instant a local time stands for, in a given time zone.
+///
+/// As required by RFC5545, a local time that happens twice (when clocks go back) is the first one,
+/// and a local time that does not exist (when clocks go forward) is interpreted with the UTC offset before the change.
+pub fn from_local(local: &NaiveDateTime, tz: &Tz) -> DateTime<Tz> {
+    if let Some(zoned) = tz.from_local_datetime(local).earliest() {
+        return zoned;
+    }
+    let offset_before = tz.offset_from_utc_datetime(&(*local - Duration::days(1))).fix();
+    let utc = *local - Duration::seconds(offset_before.local_minus_utc() as i64);
+    Utc.from_utc_datetime(&utc).with_timezone(tz)
+}
+
+/// The local time of an instant, in a given time zone
+pub fn to_local(instant: &DateTime<Utc>, tz: &Tz) -> DateTime<Tz> {
+    instant.with_timezone(tz)
+}
+
+/// Move a zoned time by a duration on the wall clock (see [`IcalDateTime::add_wall_clock`])
+pub fn add_wall_clock(zoned: &DateTime<Tz>, duration: Duration) -> DateTime<Tz> {
+    from_local(&(zoned.naive_local() + duration), &zoned.timezone())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn property(params: Option<(&str, &str)>, value: &str) -> Property {
+        let mut prop = Property::new();
+        prop.name = String::from("DUE");
+        prop.params = params.map(|(key, value)| vec![(key.to_string(), vec![value.to_string()])]);
+        prop.value = Some(value.to_string());
+        prop
+    }
+
+    #[test]
+    fn test_parsing() {
+        let paris: Tz = "Europe/Paris".parse().unwrap();
+
+        let zoned = IcalDateTime::from_property(&property(Some(("TZID", "Europe/Paris")), "20210322T090000")).unwrap();
+        assert_eq!(zoned, IcalDateTime::Zoned(paris.ymd(2021, 3, 22).and_hms(9, 0, 0)));
+        assert_eq!(zoned.to_utc(), Utc.ymd(2021, 3, 22).and_hms(8, 0, 0));
+        assert_eq!(zoned.to_property("DUE").value.as_deref(), Some("20210322T090000"));
+
+        let prefixed = IcalDateTime::from_property(&property(Some(("TZID", "/mozilla.org/20050126_1/Europe/Paris")), "20210322T090000")).unwrap();
+        assert_eq!(prefixed.time_zone(), Some(paris));
+
+        let floating = IcalDateTime::from_property(&property(None, "20210322T090000")).unwrap();
+        assert!(floating.is_floating());
+        assert_eq!(floating.to_utc(), Utc.ymd(2021, 3, 22).and_hms(9, 0, 0));
+        assert_eq!(floating.to_utc_in(&paris), Utc.ymd(2021, 3, 22).and_hms(8, 0, 0));
+        assert_eq!(floating.to_utc_in(&"America/New_York".parse().unwrap()), Utc.ymd(2021, 3, 22).and_hms(13, 0, 0));
+        assert_eq!(floating.to_property("DUE").value.as_deref(), Some("20210322T090000"));
+        assert_eq!(zoned.to_utc_in(&"America/New_York".parse().unwrap()), zoned.to_utc());
+
+        let unknown = IcalDateTime::from_property(&property(Some(("TZID", "Somewhere/Nowhere")), "20210322T090000")).unwrap();
+        assert_eq!(unknown.to_utc(), Utc.ymd(2021, 3, 22).and_hms(9, 0, 0));
+
+        assert_eq!(IcalDateTime::from_property(&property(None, "20210322T090000Z")).unwrap().to_utc(), Utc.ymd(2021, 3, 22).and_hms(9, 0, 0));
+        assert_eq!(IcalDateTime::from_property(&property(Some(("VALUE", "DATE")), "20210322")).unwrap(), IcalDateTime::Date(NaiveDate::from_ymd(2021, 3, 22)));
+        assert_eq!(IcalDateTime::from_property(&property(None, "not a date")), None);
+    }
+
+    #[test]
+    fn test_daylight_saving_time() {
+        let paris: Tz = "Europe/Paris".parse().unwrap();
+
+        // Clocks go forward from 02:00 to 03:00 on 2021-03-28: 02:30 does not exist, and is interpreted with the offset before the change (UTC+1)
+        let in_the_gap = from_local(&NaiveDate::from_ymd(2021, 3, 28).and_hms(2, 30, 0), &paris);
+        assert_eq!(in_the_gap.with_timezone(&Utc), Utc.ymd(2021, 3, 28).and_hms(1, 30, 0));
+        // Clocks go back from 03:00 to 02:00 on 2021-10-31: 02:30 happens twice, the first one is used (UTC+2)
+        let twice = from_local(&NaiveDate::from_ymd(2021, 10, 31).and_hms(2, 30, 0), &paris);
+        assert_eq!(twice.with_timezone(&Utc), Utc.ymd(2021, 10, 31).and_hms(0, 30, 0));
+
+        // One day later is still at 9:00, even though there are only 23 hours in between
+        let due = IcalDateTime::Zoned(paris.ymd(2021, 3, 27).and_hms(9, 0, 0));
+        let next_day = due.add_wall_clock(Duration::days(1));
+        assert_eq!(next_day, IcalDateTime::Zoned(paris.ymd(2021, 3, 28).and_hms(9, 0, 0)));
+        assert_eq!(next_day.to_utc() - due.to_utc(), Duration::hours(23));
+
+        assert_eq!(due.same_kind(&Utc.ymd(2021, 4, 1).and_hms(7, 0, 0)), IcalDateTime::Zoned(paris.ymd(2021, 4, 1).and_hms(9, 0, 0)));
+        assert_eq!(to_local(&Utc.ymd(2021, 4, 1).and_hms(7, 0, 0), &paris).naive_local(), NaiveDate::from_ymd(2021, 4, 1).and_hms(9, 0, 0));
+    }
+}
@@ -0,0 +1,144 @@
+//! The deterministic mode changes settings that are global to the process.
+//! This is why these tests live in their own test binary, so that they do not interfere with the other tests.
+
+use std::sync::Mutex;
+
+use chrono::{TimeZone, Utc};
+use url::Url;
+
+use kitchen_fridge::deterministic;
+use kitchen_fridge::item::{SyncStatus, VersionTag};
+use kitchen_fridge::Task;
+
+/// Tests of this binary run in parallel. Only one of them must change the global settings at a time
+static GLOBAL_SETTINGS: Mutex<()> = Mutex::new(());
+
+#[test]
+fn test_deterministic_runs() {
+    let _settings = GLOBAL_SETTINGS.lock().unwrap();
+    let cal_url = Url::parse("https://my.server.com/calendars/john/tasks/").unwrap();
+    let start = Utc.ymd(2021, 3, 21).and_hms(9, 0, 0);
+
+    let run = || {
+        deterministic::enable(start);
+        // Correlation identifiers do not consume deterministic identifiers
+        let _ = kitchen_fridge::correlation::new_request_id();
+        let mut task = Task::new(String::from("Buy milk"), false, &cal_url);
+        task.set_name(String::from("Buy oat milk"));
+        let version_tag = VersionTag::random();
+        let sync_status = SyncStatus::random_synced();
+        (task, version_tag, sync_status)
+    };
+
+    let (first_task, first_tag, first_status) = run();
+    let (second_task, second_tag, second_status) = run();
+    deterministic::disable();
+
+    assert_eq!(first_task.url().as_str(), "https://my.server.com/calendars/john/tasks/00000000-0000-0000-0000-000000000001");
+    assert_eq!(first_task.uid(), "00000000-0000-0000-0000-000000000002");
+    assert_eq!(first_task.creation_date(), Some(&start));
+    assert_eq!(first_task.last_modified(), &Utc.ymd(2021, 3, 21).and_hms(9, 0, 1));
+    assert_eq!(first_tag.as_str(), "00000000-0000-0000-0000-000000000003");
+
+    assert_eq!(first_task.url(), second_task.url());
+    assert_eq!(first_task.uid(), second_task.uid());
+    assert_eq!(first_task.creation_date(), second_task.creation_date());
+    assert_eq!(first_task.last_modified(), second_task.last_modified());
+    assert_eq!(first_tag, second_tag);
+    assert_eq!(first_status, second_status);
+
+    // Back to random identifiers
+    assert_ne!(Task::new(String::from("Buy milk"), false, &cal_url).uid(), first_task.uid());
+}
+
+#[cfg(feature = "cache")]
+#[test]
+fn test_deterministic_due_dates() {
+    use kitchen_fridge::calendar::cached_calendar::CachedCalendar;
+    use kitchen_fridge::calendar::SupportedComponents;
+    use kitchen_fridge::traits::CompleteCalendar;
+
+    let _settings = GLOBAL_SETTINGS.lock().unwrap();
+    let cal_url = Url::parse("https://my.server.com/calendars/john/tasks/").unwrap();
+    let mut cal = CachedCalendar::new(String::from("Tasks"), cal_url.clone(), SupportedComponents::TODO, None);
+    for (uid, due) in [("yesterday", "20210320T120000Z"), ("next-week", "20210324T120000Z")] {
+        let ical = format!("BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//Test//Test//EN\r\nBEGIN:VTODO\r\nUID:{}\r\nDTSTAMP:20210301T000000Z\r\nSUMMARY:{}\r\nDUE:{}\r\nEND:VTODO\r\nEND:VCALENDAR\r\n", uid, uid, due);
+        let item = kitchen_fridge::ical::parse(&ical, cal_url.join(uid).unwrap(), SyncStatus::NotSynced).unwrap();
+        cal.add_item_sync(item).unwrap();
+    }
+    let names = |tasks: Vec<&Task>| tasks.iter().map(|task| task.name().to_string()).collect::<Vec<_>>();
+
+    // Sunday, March 21st 2021
+    deterministic::enable(Utc.ymd(2021, 3, 21).and_hms(9, 0, 0));
+    let overdue = names(cal.get_overdue_tasks());
+    let this_week = names(cal.get_tasks_due_this_week());
+    deterministic::disable();
+
+    assert_eq!(overdue, vec!["yesterday"]);
+    assert_eq!(this_week, vec!["yesterday"]);
+    // Both are in the past according to the system clock
+    assert_eq!(names(cal.get_overdue_tasks()), vec!["yesterday", "next-week"]);
+}
+
+#[cfg(feature = "cache")]
+#[test]
+fn test_cache_due_date_queries() {
+    use chrono::{DateTime, Duration, Local};
+    use kitchen_fridge::blocking::block_on;
+    use kitchen_fridge::cache::Cache;
+    use kitchen_fridge::calendar::SupportedComponents;
+    use kitchen_fridge::task::CompletionStatus;
+    use kitchen_fridge::traits::CalDavSource;
+
+    let _settings = GLOBAL_SETTINGS.lock().unwrap();
+    // Wednesday, March 24th 2021, 9:00 in local time
+    let now = Local.ymd(2021, 3, 24).and_hms(9, 0, 0).with_timezone(&Utc);
+    let today_noon = Local.ymd(2021, 3, 24).and_hms(12, 0, 0).with_timezone(&Utc);
+    let ical_date = |date: DateTime<Utc>| date.format("%Y%m%dT%H%M%SZ").to_string();
+
+    let cache = Cache::new_in_memory_remote();
+    let cal_url = Url::parse("https://my.server.com/calendars/john/due-dates/").unwrap();
+    let cal = block_on(cache.create_calendar(cal_url.clone(), String::from("Due dates"), SupportedComponents::TODO, None)).unwrap();
+    let tasks = [
+        ("late", Some(now - Duration::days(3)), None),
+        ("today", Some(today_noon), None),
+        ("next-month", Some(now + Duration::days(31)), None),
+        ("done-late", Some(now - Duration::days(3)), Some(now - Duration::days(1))),
+        ("done-long-ago", None, Some(now - Duration::days(30))),
+        ("whenever", None, None),
+    ];
+    for (uid, due, completed) in tasks {
+        let due = due.map(|date| format!("DUE:{}\r\n", ical_date(date))).unwrap_or_default();
+        let completed = completed.map(|date| format!("STATUS:COMPLETED\r\nCOMPLETED:{}\r\n", ical_date(date))).unwrap_or_default();
+        let ical = format!("BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//Test//Test//EN\r\nBEGIN:VTODO\r\nUID:{}\r\nDTSTAMP:20210301T000000Z\r\nSUMMARY:{}\r\n{}{}END:VTODO\r\nEND:VCALENDAR\r\n", uid, uid, due, completed);
+        let item = kitchen_fridge::ical::parse(&ical, cal_url.join(uid).unwrap(), SyncStatus::NotSynced).unwrap();
+        cal.write().unwrap().add_item_sync(item).unwrap();
+    }
+    let names = |tasks: Vec<(Url, Task)>| tasks.iter().map(|(_, task)| task.name().to_string()).collect::<Vec<_>>();
+
+    deterministic::enable(now);
+    let overdue = names(cache.get_overdue_tasks());
+    let due_today = names(cache.get_tasks_due_today());
+    let this_week = names(cache.get_tasks_due_this_week());
+    deterministic::disable();
+
+    assert_eq!(overdue, vec!["late"]);
+    assert_eq!(due_today, vec!["today"]);
+    assert_eq!(this_week, vec!["today"]);
+    assert_eq!(names(cache.get_tasks_completed_since(now - Duration::days(7))), vec!["done-late"]);
+    assert_eq!(names(cache.get_tasks_completed_since(now - Duration::days(60))), vec!["done-late", "done-long-ago"]);
+
+    // The index follows the changes of the calendar
+    cal.write().unwrap().iter_items_mut_sync()
+        .filter(|item| item.name() == "late")
+        .for_each(|mut item| item.unwrap_task_mut().set_completion_status(CompletionStatus::Completed(Some(now))));
+    cal.write().unwrap().immediately_delete_item_sync(&cal_url.join("today").unwrap()).unwrap();
+
+    deterministic::enable(now);
+    let overdue = names(cache.get_overdue_tasks());
+    let due_today = names(cache.get_tasks_due_today());
+    deterministic::disable();
+
+    assert!(overdue.is_empty());
+    assert!(due_today.is_empty());
+}
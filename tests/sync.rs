@@ -1,23 +1,23 @@
-mod scenarii;
-
-#[cfg(feature = "local_calendar_mocks_remote_calendars")]
+#[cfg(feature = "testing")]
 use std::sync::{Arc, Mutex};
 
-#[cfg(feature = "local_calendar_mocks_remote_calendars")]
+#[cfg(feature = "testing")]
 use kitchen_fridge::mock_behaviour::MockBehaviour;
+#[cfg(feature = "testing")]
+use kitchen_fridge::testing::scenarii;
 
 
 
 /// A test that simulates a regular synchronisation between a local cache and a server.
 /// Note that this uses a second cache to "mock" a server.
 struct TestFlavour {
-    #[cfg(feature = "local_calendar_mocks_remote_calendars")]
-    scenarii: Vec<scenarii::ItemScenario>,
-    #[cfg(feature = "local_calendar_mocks_remote_calendars")]
+    #[cfg(feature = "testing")]
+    scenarii: Vec<kitchen_fridge::testing::ItemScenario>,
+    #[cfg(feature = "testing")]
     mock_behaviour: Arc<Mutex<MockBehaviour>>,
 }
 
-#[cfg(not(feature = "local_calendar_mocks_remote_calendars"))]
+#[cfg(not(feature = "testing"))]
 impl TestFlavour {
     pub fn normal() -> Self { Self{} }
     pub fn first_sync_to_local() -> Self { Self{} }
@@ -41,7 +41,7 @@ impl TestFlavour {
     }
 }
 
-#[cfg(feature = "local_calendar_mocks_remote_calendars")]
+#[cfg(feature = "testing")]
 impl TestFlavour {
     pub fn normal() -> Self {
         Self {
@@ -201,37 +201,7 @@ impl TestFlavour {
 
 
     pub async fn run(&self, max_attempts: u32) {
-        self.mock_behaviour.lock().unwrap().suspend();
-
-        let mut provider = scenarii::populate_test_provider_before_sync(&self.scenarii, Arc::clone(&self.mock_behaviour)).await;
-        print_provider(&provider, "before sync").await;
-
-        self.mock_behaviour.lock().unwrap().resume();
-        for attempt in 0..max_attempts {
-            println!("\nSyncing...\n");
-            if provider.sync().await == true {
-                println!("Sync complete after {} attempts (multiple attempts are due to forced errors in mocked behaviour)", attempt+1);
-                break
-            }
-        }
-        self.mock_behaviour.lock().unwrap().suspend();
-
-        print_provider(&provider, "after sync").await;
-
-        // Check the contents of both sources are the same after sync
-        assert!(provider.remote().has_same_observable_content_as(provider.local()).await.unwrap());
-
-        // But also explicitely check that every item is expected
-        let expected_provider = scenarii::populate_test_provider_after_sync(&self.scenarii, Arc::clone(&self.mock_behaviour)).await;
-
-        assert!(provider.local() .has_same_observable_content_as(expected_provider.local() ).await.unwrap());
-        assert!(provider.remote().has_same_observable_content_as(expected_provider.remote()).await.unwrap());
-
-        // Perform a second sync, even if no change has happened, just to check
-        println!("Syncing again");
-        provider.sync().await;
-        assert!(provider.local() .has_same_observable_content_as(expected_provider.local() ).await.unwrap());
-        assert!(provider.remote().has_same_observable_content_as(expected_provider.remote()).await.unwrap());
+        kitchen_fridge::testing::run_scenarii(&self.scenarii, Arc::clone(&self.mock_behaviour), max_attempts).await;
     }
 }
 
@@ -337,22 +307,3 @@ async fn test_errors_in_regular_sync11() {
 async fn test_errors_in_regular_sync12() {
     run_flavour(TestFlavour::normal_with_errors12(), 100).await;
 }
-
-#[cfg(feature = "integration_tests")]
-use kitchen_fridge::{traits::CalDavSource,
-               provider::Provider,
-               cache::Cache,
-               calendar::cached_calendar::CachedCalendar,
-};
-
-/// Print the contents of the provider. This is usually used for debugging
-#[allow(dead_code)]
-#[cfg(feature = "integration_tests")]
-async fn print_provider(provider: &Provider<Cache, CachedCalendar, Cache, CachedCalendar>, title: &str) {
-    let cals_server = provider.remote().get_calendars().await.unwrap();
-    println!("----Server, {}-------", title);
-    kitchen_fridge::utils::print_calendar_list(&cals_server).await;
-    let cals_local = provider.local().get_calendars().await.unwrap();
-    println!("-----Local, {}-------", title);
-    kitchen_fridge::utils::print_calendar_list(&cals_local).await;
-}